@@ -1,6 +1,7 @@
 use std::sync::{OnceLock, RwLock};
 
-use clap::{Args as ClapArgs, Parser, Subcommand};
+use clap::builder::ArgPredicate;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
 
 /// Your army commander
 #[derive(Parser, Debug, PartialEq, Eq)]
@@ -25,6 +26,20 @@ pub struct Args {
 pub enum Commands {
     /// Project management commands
     Project(ProjectArgs),
+    /// Module introspection commands
+    Modules(ModulesArgs),
+    /// Diagnose connectivity and prerequisites of a host
+    Doctor(DoctorArgs),
+    /// Gather facts from hosts into an inventory
+    Facts(FactsArgs),
+    /// Encrypt, decrypt and edit vault secrets
+    Vault(VaultArgs),
+    /// Run a task file on hosts of the project inventory
+    Run(RunArgs),
+    /// Show the settings a host will actually use and where each comes from
+    ExplainHost(ExplainHostArgs),
+    /// Fetch shared modules and roles from a git repository or tarball
+    Get(GetArgs),
 }
 
 #[derive(ClapArgs, Debug, PartialEq, Eq)]
@@ -58,11 +73,202 @@ pub struct NewArgs {
     pub dir: Option<String>,
 }
 
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct ModulesArgs {
+    #[command(subcommand)]
+    pub command: ModulesCommands,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum ModulesCommands {
+    /// List core modules and project plugin modules
+    List(ListModulesArgs),
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct ListModulesArgs {
+    /// Only show modules whose name or description contains this text
+    #[arg(short, long)]
+    pub filter: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Project directory whose `modules/` plugins are listed
+    #[arg(short, long, default_value = ".")]
+    pub project: String,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct DoctorArgs {
+    /// Host address to diagnose
+    pub host: String,
+
+    /// SSH port
+    #[arg(short, long, default_value_t = 22)]
+    pub port: u16,
+
+    /// User to authenticate as (defaults to komandan defaults, then $USER)
+    #[arg(short, long)]
+    pub user: Option<String>,
+
+    /// Private key file to authenticate with
+    #[arg(short = 'k', long)]
+    pub private_key_file: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct FactsArgs {
+    /// Addresses of the hosts to gather facts from
+    #[arg(required_unless_present = "all")]
+    pub hosts: Vec<String>,
+
+    /// Gather facts from every host in the project's hosts file
+    #[arg(short, long, conflicts_with = "hosts")]
+    pub all: bool,
+
+    /// Project directory whose `komandan.json` names the hosts file
+    #[arg(short, long, default_value = ".")]
+    pub project: String,
+
+    /// Inventory format
+    #[arg(long, value_enum, default_value_t = InventoryFormat::Json)]
+    pub format: InventoryFormat,
+
+    /// Write the inventory to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct RunArgs {
+    /// Lua file returning a task or a list of tasks
+    pub task_file: String,
+
+    /// Hosts to run on: comma-separated names, addresses, tags, `~regex`
+    /// or `all`
+    #[arg(long)]
+    pub hosts: String,
+
+    /// Project directory whose `komandan.json` names the hosts file
+    #[arg(short, long, default_value = ".")]
+    pub project: String,
+
+    #[clap(flatten)]
+    pub flags: Flags,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct ExplainHostArgs {
+    /// Name or address of a host in the project inventory
+    pub name: String,
+
+    /// Lua file returning the task to resolve task-level settings from
+    #[arg(short, long)]
+    pub task: Option<String>,
+
+    /// Project directory whose `komandan.json` names the hosts file
+    #[arg(short, long, default_value = ".")]
+    pub project: String,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct GetArgs {
+    /// Git URL or tarball URL/path to fetch; without one, installs every
+    /// package of `komandan.lock` at its locked version
+    pub source: Option<String>,
+
+    /// Package name (defaults to the last segment of the source)
+    #[arg(short, long)]
+    pub name: Option<String>,
+
+    /// Branch, tag or commit to check out for git sources
+    #[arg(long = "ref", value_name = "REF")]
+    pub git_ref: Option<String>,
+
+    /// Project directory to install into
+    #[arg(short, long, default_value = ".")]
+    pub project: String,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct VaultArgs {
+    #[command(subcommand)]
+    pub command: VaultCommands,
+
+    /// File holding the vault password (defaults to
+    /// `$KOMANDAN_VAULT_PASSWORD_FILE`, then `$KOMANDAN_VAULT_PASSWORD`)
+    #[arg(long, global = true)]
+    pub vault_password_file: Option<String>,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum VaultCommands {
+    /// Encrypt a file in place, or print one encrypted value for a hosts file
+    Encrypt(VaultEncryptArgs),
+    /// Decrypt a file in place, or print its content
+    Decrypt(VaultDecryptArgs),
+    /// Edit an encrypted file in `$VISUAL` or `$EDITOR`, creating it if missing
+    Edit(VaultEditArgs),
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct VaultEncryptArgs {
+    /// File to encrypt in place
+    #[arg(required_unless_present = "string")]
+    pub file: Option<String>,
+
+    /// Encrypt this value and print it instead
+    #[arg(long, conflicts_with = "file")]
+    pub string: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct VaultDecryptArgs {
+    /// Encrypted file
+    pub file: String,
+
+    /// Print the decrypted content instead of rewriting the file
+    #[arg(long)]
+    pub stdout: bool,
+}
+
+#[derive(ClapArgs, Debug, PartialEq, Eq)]
+pub struct VaultEditArgs {
+    /// Encrypted file
+    pub file: String,
+}
+
+/// Output format of `komandan facts`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InventoryFormat {
+    /// One JSON object per host
+    #[default]
+    Json,
+    /// One CSV row per host
+    Csv,
+}
+
+/// Output format for listing commands.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable table
+    #[default]
+    Text,
+    /// Machine-readable JSON
+    Json,
+}
+
 #[derive(ClapArgs, Clone, Debug, Default, PartialEq, Eq)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Flags {
     /// Dry run mode
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        default_value_if("drift", ArgPredicate::IsPresent, "true")
+    )]
     pub dry_run: bool,
 
     /// Don't print report
@@ -84,6 +290,57 @@ pub struct Flags {
     /// Print version information
     #[arg(short = 'V', long)]
     pub version: bool,
+
+    /// Only run tasks tagged with one of these tags (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Skip tasks tagged with any of these tags (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pub skip_tags: Vec<String>,
+
+    /// Maximum number of hosts or tasks run at once by parallel runners
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub forks: Option<u16>,
+
+    /// Print how long every task spent connecting, transferring files,
+    /// running commands and in Lua
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Clear the fact cache when it is configured, gathering facts anew
+    #[arg(long)]
+    pub flush_facts: bool,
+
+    /// File holding the password of vault-encrypted values (defaults to
+    /// `$KOMANDAN_VAULT_PASSWORD_FILE`, then `$KOMANDAN_VAULT_PASSWORD`)
+    #[arg(long)]
+    pub vault_password_file: Option<String>,
+
+    /// Ask before running every task on every host: yes, no, or continue
+    /// without asking again
+    #[arg(long)]
+    pub step: bool,
+
+    /// Skip the tasks before the task with this name, e.g. to resume a run
+    /// that failed halfway
+    #[arg(long, value_name = "NAME")]
+    pub start_at_task: Option<String>,
+
+    /// Record the commands run by tasks, e.g. in the REPL, and write them to
+    /// this file as a draft script of `cmd` tasks
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// Record the signature and result of every task to this file, to
+    /// compare later runs against with `--drift`
+    #[arg(long, value_name = "FILE")]
+    pub state_file: Option<String>,
+
+    /// Dry run and report which hosts have drifted from the state last
+    /// applied to `--state-file`
+    #[arg(long, requires = "state_file")]
+    pub drift: bool,
 }
 
 /// Updatable global resolved-config store.
@@ -144,6 +401,22 @@ pub fn init_global_config(config: ResolvedConfig) -> Result<(), String> {
     Ok(())
 }
 
+/// Replace the global flags, keeping the resolved `project_dir`.
+///
+/// For embedding komandan as a library: configure dry-run, verbosity, tags
+/// and the like without building an [`Args`] from argv.
+///
+/// # Errors
+///
+/// Returns an error if the global config `RwLock` is poisoned.
+pub fn set_global_flags(flags: Flags) -> Result<(), String> {
+    config_cell()
+        .write()
+        .map_err(|e| format!("global config lock poisoned: {e}"))?
+        .flags = flags;
+    Ok(())
+}
+
 /// Returns a snapshot of the resolved global config.
 ///
 /// Because the store is now updatable, callers receive a clone of the current
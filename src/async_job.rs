@@ -0,0 +1,390 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+
+use crate::connection::{Connection, create_connection};
+use crate::local::escape_shell_value;
+use crate::output;
+use crate::util::duration_param;
+
+/// Directory, relative to the login user's home, holding one directory per job.
+const JOBS_DIR: &str = ".komandan/async";
+
+/// Exit code `timeout` reports when the job ran past its `async` limit.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Extra time granted past the `async` limit before polling gives up on a
+/// job that never reported an exit code.
+const POLL_GRACE: Duration = Duration::from_secs(30);
+
+/// The `async` / `poll` settings of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsyncSpec {
+    /// Longest the background command may run before it is killed.
+    pub limit: Duration,
+    /// Interval at which `komando` polls until the job finished; `None`
+    /// returns the job id immediately.
+    pub poll: Option<Duration>,
+}
+
+impl AsyncSpec {
+    /// Read `async` and `poll` from a task table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either is not a valid duration, `async` is zero,
+    /// or `poll` is given without `async`.
+    pub fn from_task(task: &Table) -> mlua::Result<Option<Self>> {
+        let limit = duration_param(task.get::<Value>("async")?, "async")?;
+        let poll = duration_param(task.get::<Value>("poll")?, "poll")?;
+        match (limit, poll) {
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err(RuntimeError(
+                "Task poll requires async to be set".to_string(),
+            )),
+            (Some(limit), _) if limit.is_zero() => Err(RuntimeError(
+                "Task async must be a positive duration".to_string(),
+            )),
+            (Some(limit), poll) => Ok(Some(Self {
+                limit,
+                poll: poll.filter(|poll| !poll.is_zero()),
+            })),
+        }
+    }
+}
+
+/// State of a background job as reported by the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub job_id: String,
+    /// Exit code of the command, `None` while it is still running.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl JobStatus {
+    /// Whether the command hit its `async` limit and was killed.
+    #[must_use]
+    pub const fn timed_out(&self) -> bool {
+        matches!(self.exit_code, Some(TIMEOUT_EXIT_CODE))
+    }
+
+    fn into_table(self, lua: &Lua) -> mlua::Result<Table> {
+        let table = lua.create_table()?;
+        table.set("finished", self.exit_code.is_some())?;
+        table.set("timed_out", self.timed_out())?;
+        table.set("exit_code", self.exit_code)?;
+        table.set("stdout", self.stdout)?;
+        table.set("stderr", self.stderr)?;
+        table.set("job_id", self.job_id)?;
+        Ok(table)
+    }
+}
+
+/// Shell expression for a job's directory on the target.
+fn job_dir(job_id: &str) -> String {
+    format!("\"$HOME/{JOBS_DIR}/{job_id}\"")
+}
+
+fn validate_job_id(job_id: &str) -> mlua::Result<()> {
+    if job_id.is_empty()
+        || !job_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(RuntimeError(format!("invalid async job id '{job_id}'")));
+    }
+    Ok(())
+}
+
+/// Build the script that starts `command` in the background under `nohup`,
+/// recording its output and, once it exits, its exit code in `$1/rc`.
+fn start_script(job_id: &str, command: &str, limit: Duration) -> String {
+    let runner = format!(
+        "timeout {} sh -c {}; echo $? > \"$1/rc.tmp\" && mv \"$1/rc.tmp\" \"$1/rc\"",
+        limit.as_secs().max(1),
+        escape_shell_value(command)
+    );
+    let dir = job_dir(job_id);
+    format!(
+        "d={dir}; mkdir -p \"$d\" || exit 1; \
+         nohup sh -c {} komandan-async \"$d\" > \"$d/stdout\" 2> \"$d/stderr\" < /dev/null & \
+         echo $! > \"$d/pid\"",
+        escape_shell_value(&runner)
+    )
+}
+
+/// Start `command` as a background job on `connection` and return its id.
+///
+/// The job's files live under `~/.komandan/async/<job_id>` of the login
+/// user, so status checks work whatever elevation the command itself used.
+///
+/// # Errors
+///
+/// Returns an error if the job cannot be started.
+pub fn start_job(connection: &Connection, command: &str, limit: Duration) -> mlua::Result<String> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let job_id = format!("{}-{nanos}", std::process::id());
+    let command = connection.prepare_command(command);
+
+    let (_, stderr, exit_code) = connection
+        .cmdq(&start_script(&job_id, &command, limit))
+        .map_err(|e| RuntimeError(format!("failed to start async job: {e}")))?;
+    if exit_code != 0 {
+        return Err(RuntimeError(format!(
+            "failed to start async job (exit {exit_code}): {}",
+            stderr.trim()
+        )));
+    }
+    Ok(job_id)
+}
+
+/// Query the state of a job started with [`start_job`].
+///
+/// # Errors
+///
+/// Returns an error if the job id is unknown on the target or the status
+/// commands fail.
+pub fn job_status(connection: &Connection, job_id: &str) -> mlua::Result<JobStatus> {
+    validate_job_id(job_id)?;
+    let dir = job_dir(job_id);
+    let query = |script: String| {
+        connection
+            .cmdq(&script)
+            .map_err(|e| RuntimeError(format!("failed to query async job '{job_id}': {e}")))
+    };
+
+    let (rc, _, exit_code) = query(format!(
+        "d={dir}; [ -d \"$d\" ] || exit 2; cat \"$d/rc\" 2>/dev/null; true"
+    ))?;
+    if exit_code != 0 {
+        return Err(RuntimeError(format!("unknown async job '{job_id}'")));
+    }
+    let exit_code = rc.trim().parse::<i32>().ok();
+    let (stdout, _, _) = query(format!("cat {dir}/stdout 2>/dev/null; true"))?;
+    let (stderr, _, _) = query(format!("cat {dir}/stderr 2>/dev/null; true"))?;
+
+    Ok(JobStatus {
+        job_id: job_id.to_string(),
+        exit_code,
+        stdout,
+        stderr,
+    })
+}
+
+/// Remove a job's files from the target.
+///
+/// # Errors
+///
+/// Returns an error if the removal command cannot be run.
+pub fn remove_job(connection: &Connection, job_id: &str) -> mlua::Result<()> {
+    validate_job_id(job_id)?;
+    connection
+        .cmdq(&format!("rm -rf {}", job_dir(job_id)))
+        .map_err(|e| RuntimeError(format!("failed to remove async job '{job_id}': {e}")))?;
+    Ok(())
+}
+
+/// Run a task's module command as a background job.
+///
+/// Without `poll` the result carries the `job_id` and `finished = false`
+/// right after the job started. With `poll`, the job is checked at that
+/// interval until it exits, and the result holds its output and exit code.
+///
+/// # Errors
+///
+/// Returns an error if the module has no `cmd` parameter, the job cannot be
+/// started or queried, or it never reports completion.
+pub fn run_async_task(
+    lua: &Lua,
+    module: &Table,
+    connection: &Connection,
+    spec: AsyncSpec,
+    task_display: &str,
+    host_display: &str,
+) -> mlua::Result<Table> {
+    let command = module
+        .get::<Option<Table>>("params")?
+        .map(|params| params.get::<Option<String>>("cmd"))
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| {
+            RuntimeError(
+                "Task async needs a module with a 'cmd' parameter, such as the cmd module"
+                    .to_string(),
+            )
+        })?;
+
+    let result = lua.create_table()?;
+    result.set("stdout", "")?;
+    result.set("stderr", "")?;
+    result.set("exit_code", 0)?;
+    result.set("changed", true)?;
+
+    if crate::args::global_flags().dry_run {
+        output::emit(&format!(
+            "[[ Task '{task_display}' on host '{host_display}' would start an async job. Assuming 'changed' is true. ]]"
+        ));
+        return Ok(result);
+    }
+
+    let job_id = start_job(connection, &command, spec.limit)?;
+    output::emit(&format!(
+        ">> Started async job {job_id} for task '{task_display}' on host '{host_display}'"
+    ));
+    result.set("job_id", job_id.as_str())?;
+    result.set("finished", false)?;
+
+    let Some(poll) = spec.poll else {
+        return Ok(result);
+    };
+
+    let give_up = Instant::now() + spec.limit + POLL_GRACE;
+    loop {
+        thread::sleep(poll);
+        let status = job_status(connection, &job_id)?;
+        if let Some(exit_code) = status.exit_code {
+            remove_job(connection, &job_id)?;
+            if status.timed_out() {
+                output::emit(&format!(
+                    ">> Async job {job_id} on host '{host_display}' was killed after {}s",
+                    spec.limit.as_secs_f64()
+                ));
+            }
+            result.set("timed_out", status.timed_out())?;
+            result.set("stdout", status.stdout)?;
+            result.set("stderr", status.stderr)?;
+            result.set("exit_code", exit_code)?;
+            result.set("finished", true)?;
+            return Ok(result);
+        }
+        if Instant::now() >= give_up {
+            return Err(RuntimeError(format!(
+                "Async job {job_id} on host '{host_display}' did not report completion"
+            )));
+        }
+    }
+}
+
+/// Lua binding: `komandan.async_status(host, job_id, opts)` returns
+/// `{ job_id, finished, exit_code, timed_out, stdout, stderr }` for a job
+/// started by an `async` task. `opts.cleanup = true` removes the job's files
+/// once it finished.
+///
+/// # Errors
+///
+/// Returns an error if the host is invalid or the job is unknown.
+pub fn async_status(
+    lua: &Lua,
+    (host, job_id, opts): (Value, String, Option<Table>),
+) -> mlua::Result<Table> {
+    let connection = create_connection(lua, &host)?;
+    let status = job_status(&connection, &job_id)?;
+    let cleanup = opts
+        .map(|opts| opts.get::<Option<bool>>("cleanup"))
+        .transpose()?
+        .flatten()
+        .unwrap_or(false);
+    if cleanup && status.exit_code.is_some() {
+        remove_job(&connection, &job_id)?;
+    }
+    status.into_table(lua)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+    use mlua::chunk;
+
+    #[test]
+    fn test_async_spec_from_task() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        assert_eq!(AsyncSpec::from_task(&task)?, None);
+
+        task.set("async", "1h")?;
+        task.set("poll", 10)?;
+        assert_eq!(
+            AsyncSpec::from_task(&task)?,
+            Some(AsyncSpec {
+                limit: Duration::from_secs(3600),
+                poll: Some(Duration::from_secs(10)),
+            })
+        );
+
+        task.set("poll", 0)?;
+        assert_eq!(
+            AsyncSpec::from_task(&task)?.and_then(|spec| spec.poll),
+            None
+        );
+
+        task.set("async", 0)?;
+        assert!(AsyncSpec::from_task(&task).is_err());
+
+        task.set("async", mlua::Nil)?;
+        task.set("poll", 5)?;
+        assert!(AsyncSpec::from_task(&task).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_job_id() {
+        assert!(validate_job_id("123-456").is_ok());
+        assert!(validate_job_id("../etc").is_err());
+        assert!(validate_job_id("").is_err());
+    }
+
+    #[test]
+    fn test_async_task_with_poll() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = lua
+            .load(chunk! {
+                return komandan.local({
+                    name = "Slow command",
+                    komandan.modules.cmd({ cmd = "sleep 1; echo finished" }),
+                    async = 30,
+                    poll = "200ms",
+                })
+            })
+            .eval::<Table>()?;
+        assert!(result.get::<bool>("finished")?);
+        assert_eq!(result.get::<String>("stdout")?.trim(), "finished");
+        assert_eq!(result.get::<i32>("exit_code")?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_task_fire_and_forget() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let status = lua
+            .load(chunk! {
+                local host = { address = "localhost" }
+                local started = komandan.komando({
+                    name = "Background command",
+                    komandan.modules.cmd({ cmd = "echo done" }),
+                    async = "1m",
+                }, host)
+                assert(started.finished == false)
+
+                local status
+                for _ = 1, 50 do
+                    status = komandan.async_status(host, started.job_id)
+                    if status.finished then
+                        break
+                    end
+                    os.execute("sleep 0.1")
+                end
+                komandan.async_status(host, started.job_id, { cleanup = true })
+                return status
+            })
+            .eval::<Table>()?;
+        assert!(status.get::<bool>("finished")?);
+        assert_eq!(status.get::<String>("stdout")?.trim(), "done");
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+
+use crate::komando::komando;
+use crate::output;
+use crate::report::{TaskStatus, insert_record};
+use crate::util::{host_display, task_display};
+use crate::validator::validate_host;
+
+/// Task options an `undo` module runs with, copied from the task it reverts.
+const UNDO_INHERITED_FIELDS: [&str; 7] = [
+    "elevate",
+    "elevation_method",
+    "as_user",
+    "env",
+    "env_unset",
+    "env_inherit",
+    "delegate_to",
+];
+
+/// Run `tasks` on `host` in order as one unit.
+///
+/// A task may declare an `undo` module that reverts it. When a task fails,
+/// the `undo` modules of the earlier tasks that changed the host run in
+/// reverse order, each reverted task is reported as rolled back, and the
+/// call fails. On success, the result of every task is returned in order.
+///
+/// # Errors
+///
+/// Returns an error if the host or a task is invalid, or if a task failed.
+pub fn komando_block(lua: &Lua, (tasks, host): (Table, Value)) -> mlua::Result<Table> {
+    let host = block_host(lua, host)?;
+
+    let results = lua.create_table()?;
+    let mut applied = Vec::new();
+    for task in tasks.sequence_values::<Table>() {
+        let task = task?;
+        match komando(
+            lua,
+            (Value::Table(task.clone()), Value::Table(host.clone())),
+        ) {
+            Ok(result) => {
+                if result.get::<bool>("changed")? && task.contains_key("undo")? {
+                    applied.push(task);
+                }
+                results.push(result)?;
+            }
+            Err(e) => {
+                let failed = rollback(lua, &host, &applied);
+                return Err(RuntimeError(format!(
+                    "Task '{}' failed: {e}; rolled back {} of {} applied task(s)",
+                    task_display(&task),
+                    applied.len() - failed,
+                    applied.len()
+                )));
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Run `tasks` on `host` (the local machine when `nil`) in order, stopping
+/// at the first failure instead of raising it.
+///
+/// The tasks share the host's pooled connection, so the host is connected
+/// to once. The returned table holds the `results` of the tasks that
+/// succeeded, in order, and `ok`. After a failure, it also holds
+/// `failed_task` (the index of the failed task), its `error` and `skipped`,
+/// the number of tasks not run.
+///
+/// # Errors
+///
+/// Returns an error if the host is invalid.
+pub fn komando_sequence(lua: &Lua, (tasks, host): (Table, Value)) -> mlua::Result<Table> {
+    let host = block_host(lua, host)?;
+
+    let outcome = lua.create_table()?;
+    let results = lua.create_table()?;
+    outcome.set("results", &results)?;
+    let tasks = tasks
+        .sequence_values::<Value>()
+        .collect::<mlua::Result<Vec<_>>>()?;
+    for (index, task) in tasks.iter().enumerate() {
+        match komando(lua, (task.clone(), Value::Table(host.clone()))) {
+            Ok(result) => results.push(result)?,
+            Err(e) => {
+                outcome.set("ok", false)?;
+                outcome.set("failed_task", index + 1)?;
+                outcome.set("error", e.to_string())?;
+                outcome.set("skipped", tasks.len() - index - 1)?;
+                return Ok(outcome);
+            }
+        }
+    }
+    outcome.set("ok", true)?;
+    Ok(outcome)
+}
+
+/// `host` validated, or the local machine when it is `nil`.
+fn block_host(lua: &Lua, host: Value) -> mlua::Result<Table> {
+    if host.is_nil() {
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+        Ok(host)
+    } else {
+        validate_host(lua, host)
+    }
+}
+
+/// Run a task group on `spec.host` (the local machine when `nil`):
+/// `spec.tasks` in order, then `spec.rescue` if one of them failed, then
+/// `spec.always` whatever happened.
+///
+/// A failure handled by a successful `rescue` does not fail the call. The
+/// returned table holds `results`, `rescue_results` and `always_results`,
+/// plus `error` and `rescued = true` when a failure was rescued.
+///
+/// # Errors
+///
+/// Returns an error if `spec` is invalid, or if a task failed and was not
+/// rescued, or if an `always` task failed.
+pub fn block(lua: &Lua, spec: Table) -> mlua::Result<Table> {
+    let host = block_host(lua, spec.get::<Value>("host")?)?;
+    let tasks = spec
+        .get::<Option<Table>>("tasks")?
+        .ok_or_else(|| RuntimeError("block: 'tasks' must be a list of tasks".to_string()))?;
+    let rescue = spec.get::<Option<Table>>("rescue")?;
+    let always = spec.get::<Option<Table>>("always")?;
+
+    let outcome = lua.create_table()?;
+    let mut error = run_section(lua, &tasks, &host, &outcome, "results").err();
+    if let (Some(e), Some(rescue)) = (&error, &rescue) {
+        outcome.set("error", e.to_string())?;
+        error = match run_section(lua, rescue, &host, &outcome, "rescue_results") {
+            Ok(()) => {
+                outcome.set("rescued", true)?;
+                None
+            }
+            Err(rescue_error) => Some(RuntimeError(format!("{e}; rescue failed: {rescue_error}"))),
+        };
+    }
+    if let Some(always) = &always
+        && let Err(always_error) = run_section(lua, always, &host, &outcome, "always_results")
+    {
+        error = Some(match error {
+            Some(e) => RuntimeError(format!("{e}; always failed: {always_error}")),
+            None => always_error,
+        });
+    }
+
+    error.map_or(Ok(outcome), Err)
+}
+
+/// Run `tasks` on `host` in order, collecting their results in
+/// `outcome[key]`, and stop at the first failure.
+fn run_section(
+    lua: &Lua,
+    tasks: &Table,
+    host: &Table,
+    outcome: &Table,
+    key: &str,
+) -> mlua::Result<()> {
+    let results = lua.create_table()?;
+    outcome.set(key, &results)?;
+    for task in tasks.sequence_values::<Value>() {
+        results.push(komando(lua, (task?, Value::Table(host.clone())))?)?;
+    }
+    Ok(())
+}
+
+/// Run the `undo` module of every task in `applied`, last first, and record
+/// each task reverted as rolled back. Returns how many rollbacks failed.
+fn rollback(lua: &Lua, host: &Table, applied: &[Table]) -> usize {
+    let host_display = host_display(host);
+    let mut failed = 0;
+    for task in applied.iter().rev() {
+        let display = task_display(task);
+        output::emit(&format!(
+            ">> Rolling back task '{display}' on host '{host_display}'"
+        ));
+        let outcome = undo_task(lua, task, &display)
+            .and_then(|undo| komando(lua, (Value::Table(undo), Value::Table(host.clone()))));
+        match outcome {
+            Ok(_) => {
+                if !crate::args::global_flags().no_report {
+                    insert_record(display, host_display.clone(), TaskStatus::RolledBack);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                output::emit(&format!(
+                    ">> Rollback of task '{display}' on host '{host_display}' failed: {e}"
+                ));
+            }
+        }
+    }
+    failed
+}
+
+/// The task running `task`'s `undo` module.
+fn undo_task(lua: &Lua, task: &Table, display: &str) -> mlua::Result<Table> {
+    let undo = lua.create_table()?;
+    undo.set(1, task.get::<Value>("undo")?)?;
+    undo.set("name", format!("undo {display}"))?;
+    for field in UNDO_INHERITED_FIELDS {
+        undo.set(field, task.get::<Value>(field)?)?;
+    }
+    Ok(undo)
+}
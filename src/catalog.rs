@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::args::{ListModulesArgs, ModulesArgs, ModulesCommands, OutputFormat};
+use crate::modules::CORE_MODULES;
+
+/// Directory, relative to the project root, holding plugin modules.
+const PROJECT_MODULES_DIR: &str = "modules";
+
+/// Where a listed module comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleSource {
+    /// Built into the binary, exposed as `komandan.modules.<name>`.
+    Core,
+    /// A `modules/<name>.lua` file in the project, loaded via `require`.
+    Project,
+}
+
+/// One entry of `komandan modules list`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub source: ModuleSource,
+    pub description: String,
+}
+
+/// Handles the modules command
+///
+/// # Errors
+///
+/// Returns an error if the project plugin directory cannot be read or the
+/// listing cannot be serialized.
+pub fn handle_modules_command(args: &ModulesArgs) -> Result<()> {
+    match &args.command {
+        ModulesCommands::List(list_args) => list_command(list_args),
+    }
+}
+
+fn list_command(args: &ListModulesArgs) -> Result<()> {
+    let modules = filter_modules(
+        list_modules(Path::new(&args.project))?,
+        args.filter.as_deref(),
+    );
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&modules)?),
+        OutputFormat::Text => {
+            let width = modules
+                .iter()
+                .map(|module| module.name.len())
+                .max()
+                .unwrap_or(0)
+                .max("NAME".len());
+            println!("{:<width$}  {:<7}  DESCRIPTION", "NAME", "SOURCE");
+            for module in &modules {
+                let source = match module.source {
+                    ModuleSource::Core => "core",
+                    ModuleSource::Project => "project",
+                };
+                println!(
+                    "{:<width$}  {source:<7}  {}",
+                    module.name, module.description
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect core modules followed by the project's plugin modules.
+///
+/// Plugin modules are the `*.lua` files in `<project_dir>/modules/`; the
+/// first `--` comment line of each file is used as its description. A
+/// missing plugin directory is not an error.
+///
+/// # Errors
+///
+/// Returns an error if the plugin directory exists but cannot be read.
+pub fn list_modules(project_dir: &Path) -> Result<Vec<ModuleInfo>> {
+    let mut modules: Vec<ModuleInfo> = CORE_MODULES
+        .iter()
+        .map(|module| ModuleInfo {
+            name: module.name.to_string(),
+            source: ModuleSource::Core,
+            description: module.description.to_string(),
+        })
+        .collect();
+
+    let plugins_dir = project_dir.join(PROJECT_MODULES_DIR);
+    if !plugins_dir.is_dir() {
+        return Ok(modules);
+    }
+
+    let mut plugins = Vec::new();
+    for entry in fs::read_dir(&plugins_dir)
+        .with_context(|| format!("Failed to read directory: {}", plugins_dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("lua") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        plugins.push(ModuleInfo {
+            name: name.to_string(),
+            source: ModuleSource::Project,
+            description: leading_comment(&source).unwrap_or_else(|| "(no description)".to_string()),
+        });
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    modules.extend(plugins);
+
+    Ok(modules)
+}
+
+/// Keep modules whose name or description contains `filter`
+/// (case-insensitive). `None` keeps everything.
+#[must_use]
+pub fn filter_modules(modules: Vec<ModuleInfo>, filter: Option<&str>) -> Vec<ModuleInfo> {
+    let Some(filter) = filter.map(str::to_lowercase) else {
+        return modules;
+    };
+    modules
+        .into_iter()
+        .filter(|module| {
+            module.name.to_lowercase().contains(&filter)
+                || module.description.to_lowercase().contains(&filter)
+        })
+        .collect()
+}
+
+/// First `--` comment line of a Lua source, without the comment markers.
+fn leading_comment(source: &str) -> Option<String> {
+    source
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .and_then(|line| line.strip_prefix("--"))
+        .map(|comment| comment.trim_start_matches('-').trim().to_string())
+        .filter(|comment| !comment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_list_modules_core_only() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let modules = list_modules(temp_dir.path())?;
+        assert_eq!(modules.len(), CORE_MODULES.len());
+        assert!(
+            modules
+                .iter()
+                .all(|module| module.source == ModuleSource::Core)
+        );
+        assert!(modules.iter().any(|module| module.name == "cmd"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_modules_with_project_plugins() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let plugins = temp_dir.path().join("modules");
+        fs::create_dir(&plugins)?;
+        fs::write(
+            plugins.join("nginx_site.lua"),
+            "-- Manage nginx sites\nreturn {}\n",
+        )?;
+        fs::write(plugins.join("bare.lua"), "return {}\n")?;
+        fs::write(plugins.join("README.md"), "not a module")?;
+
+        let modules = list_modules(temp_dir.path())?;
+        let project: Vec<_> = modules
+            .iter()
+            .filter(|module| module.source == ModuleSource::Project)
+            .collect();
+        assert_eq!(project.len(), 2);
+        assert_eq!(project[0].name, "bare");
+        assert_eq!(project[0].description, "(no description)");
+        assert_eq!(project[1].name, "nginx_site");
+        assert_eq!(project[1].description, "Manage nginx sites");
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_modules() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let modules = list_modules(temp_dir.path())?;
+
+        let filtered = filter_modules(modules.clone(), Some("APT"));
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].name, "apt");
+
+        let filtered = filter_modules(modules.clone(), Some("packages"));
+        assert!(filtered.iter().any(|module| module.name == "dnf"));
+
+        assert_eq!(filter_modules(modules.clone(), None), modules);
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_info_json() -> Result<()> {
+        let info = ModuleInfo {
+            name: "cmd".to_string(),
+            source: ModuleSource::Core,
+            description: "Execute a shell command".to_string(),
+        };
+        let json = serde_json::to_value(&info)?;
+        assert_eq!(json["source"], "core");
+        assert_eq!(json["name"], "cmd");
+        Ok(())
+    }
+}
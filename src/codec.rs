@@ -0,0 +1,291 @@
+use base64::Engine as _;
+use base64::alphabet;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, String as LuaString, Table, Value};
+use serde::Deserialize;
+
+/// Decoders that accept input with or without `=` padding.
+const STANDARD_DECODER: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+const URL_SAFE_DECODER: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Parses every document of a YAML stream.
+fn yaml_documents(text: &str) -> mlua::Result<Vec<serde_norway::Value>> {
+    serde_norway::Deserializer::from_str(text)
+        .map(|document| {
+            serde_norway::Value::deserialize(document)
+                .map_err(|e| RuntimeError(format!("Invalid YAML: {e}")))
+        })
+        .collect()
+}
+
+fn yaml_encode(lua: &Lua, value: Value) -> mlua::Result<String> {
+    let value = lua.from_value::<serde_norway::Value>(value)?;
+    serde_norway::to_string(&value).map_err(|e| RuntimeError(format!("Cannot encode YAML: {e}")))
+}
+
+/// The `komandan.yaml` table:
+///
+/// - `decode(text)` returns the first document (`nil` for an empty stream)
+/// - `decode_all(text)` returns a list of every document, as in a
+///   multi-document Kubernetes manifest
+/// - `encode(value)` and `encode_all(list)`, the latter separating the
+///   documents with `---`
+///
+/// YAML `null` decodes to the same null value as JSON bodies in
+/// `komandan.http`.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn yaml_table(lua: &Lua) -> mlua::Result<Table> {
+    let yaml = lua.create_table()?;
+    yaml.set(
+        "decode",
+        lua.create_function(|lua, text: String| {
+            yaml_documents(&text)?
+                .first()
+                .map_or(Ok(Value::Nil), |document| lua.to_value(document))
+        })?,
+    )?;
+    yaml.set(
+        "decode_all",
+        lua.create_function(|lua, text: String| {
+            let documents = lua.create_table()?;
+            for document in yaml_documents(&text)? {
+                documents.push(lua.to_value(&document)?)?;
+            }
+            Ok(documents)
+        })?,
+    )?;
+    yaml.set(
+        "encode",
+        lua.create_function(|lua, value: Value| yaml_encode(lua, value))?,
+    )?;
+    yaml.set(
+        "encode_all",
+        lua.create_function(|lua, documents: Table| {
+            documents
+                .sequence_values::<Value>()
+                .map(|document| yaml_encode(lua, document?))
+                .collect::<mlua::Result<Vec<_>>>()
+                .map(|documents| documents.join("---\n"))
+        })?,
+    )?;
+    Ok(yaml)
+}
+
+/// Converts a TOML value to Lua. Dates and times become their TOML text,
+/// e.g. `"1979-05-27T07:32:00Z"`.
+fn toml_to_lua(lua: &Lua, value: toml::Value) -> mlua::Result<Value> {
+    Ok(match value {
+        toml::Value::String(value) => Value::String(lua.create_string(&value)?),
+        toml::Value::Integer(value) => Value::Integer(value),
+        toml::Value::Float(value) => Value::Number(value),
+        toml::Value::Boolean(value) => Value::Boolean(value),
+        toml::Value::Datetime(value) => Value::String(lua.create_string(value.to_string())?),
+        toml::Value::Array(values) => {
+            let table = lua.create_table_with_capacity(values.len(), 0)?;
+            for value in values {
+                table.push(toml_to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+        toml::Value::Table(values) => {
+            let table = lua.create_table_with_capacity(0, values.len())?;
+            for (key, value) in values {
+                table.set(key, toml_to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// The `komandan.toml` table: `decode(text)` returns the document as a
+/// table, and `encode(table)` returns TOML text with nested tables written
+/// as `[sections]`.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn toml_table(lua: &Lua) -> mlua::Result<Table> {
+    let toml = lua.create_table()?;
+    toml.set(
+        "decode",
+        lua.create_function(|lua, text: String| {
+            let document = text
+                .parse::<toml::Table>()
+                .map_err(|e| RuntimeError(format!("Invalid TOML: {e}")))?;
+            toml_to_lua(lua, toml::Value::Table(document))
+        })?,
+    )?;
+    toml.set(
+        "encode",
+        lua.create_function(|lua, value: Table| {
+            let document = lua.from_value::<toml::Table>(Value::Table(value))?;
+            toml::to_string(&document).map_err(|e| RuntimeError(format!("Cannot encode TOML: {e}")))
+        })?,
+    )?;
+    Ok(toml)
+}
+
+fn base64_decode(lua: &Lua, engine: &GeneralPurpose, text: &LuaString) -> mlua::Result<LuaString> {
+    let text = text
+        .as_bytes()
+        .iter()
+        .copied()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+    let data = engine
+        .decode(text)
+        .map_err(|e| RuntimeError(format!("Invalid base64: {e}")))?;
+    lua.create_string(data)
+}
+
+/// The `komandan.base64` table:
+///
+/// - `encode(data)` and `decode(text)` use the standard alphabet
+/// - `encode_url(data)` and `decode_url(text)` use the URL-safe alphabet
+///   (`-` and `_`), encoding without padding as JWTs and URLs expect
+///
+/// Decoding accepts input with or without padding and ignores whitespace,
+/// such as the line breaks of wrapped `base64` output. Data may be binary.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn base64_table(lua: &Lua) -> mlua::Result<Table> {
+    let base64 = lua.create_table()?;
+    base64.set(
+        "encode",
+        lua.create_function(|_, data: LuaString| Ok(STANDARD.encode(data.as_bytes())))?,
+    )?;
+    base64.set(
+        "decode",
+        lua.create_function(|lua, text: LuaString| base64_decode(lua, &STANDARD_DECODER, &text))?,
+    )?;
+    base64.set(
+        "encode_url",
+        lua.create_function(|_, data: LuaString| Ok(URL_SAFE_NO_PAD.encode(data.as_bytes())))?,
+    )?;
+    base64.set(
+        "decode_url",
+        lua.create_function(|lua, text: LuaString| base64_decode(lua, &URL_SAFE_DECODER, &text))?,
+    )?;
+    Ok(base64)
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::chunk;
+
+    #[test]
+    fn test_yaml_decode_all_and_encode() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let manifest = "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: web\n---\nkind: Service\nspec:\n  ports:\n    - port: 80\n    - port: 443\n";
+        let (count, kind, port, first) = lua
+            .load(chunk! {
+                local documents = komandan.yaml.decode_all($manifest)
+                return #documents, documents[2].kind, documents[2].spec.ports[2].port,
+                    komandan.yaml.decode($manifest).metadata.name
+            })
+            .eval::<(usize, String, i64, String)>()?;
+        assert_eq!(count, 2);
+        assert_eq!(kind, "Service");
+        assert_eq!(port, 443);
+        assert_eq!(first, "web");
+
+        let (encoded, round_trip) = lua
+            .load(chunk! {
+                local encoded = komandan.yaml.encode({ users = { "alice", "bob" } })
+                return encoded, komandan.yaml.decode(encoded).users[2]
+            })
+            .eval::<(String, String)>()?;
+        assert_eq!(encoded, "users:\n- alice\n- bob\n");
+        assert_eq!(round_trip, "bob");
+
+        let all = lua
+            .load(chunk! {
+                return komandan.yaml.encode_all({ { a = 1 }, { b = 2 } })
+            })
+            .eval::<String>()?;
+        assert_eq!(all, "a: 1\n---\nb: 2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml_decode_and_encode() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let config = "title = \"web\"\nreleased = 1979-05-27T07:32:00Z\n\n[server]\nports = [80, 443]\nratio = 0.5\n";
+        let (title, released, port, ratio) = lua
+            .load(chunk! {
+                local config = komandan.toml.decode($config)
+                return config.title, config.released, config.server.ports[2], config.server.ratio
+            })
+            .eval::<(String, String, i64, f64)>()?;
+        assert_eq!(title, "web");
+        assert_eq!(released, "1979-05-27T07:32:00Z");
+        assert_eq!(port, 443);
+        assert!((ratio - 0.5).abs() < f64::EPSILON);
+
+        let encoded = lua
+            .load(chunk! {
+                return komandan.toml.encode({ name = "api", server = { port = 8080 } })
+            })
+            .eval::<String>()?;
+        assert_eq!(encoded, "name = \"api\"\n\n[server]\nport = 8080\n");
+
+        let result = lua
+            .load(chunk! {
+                return komandan.toml.decode("key = ")
+            })
+            .exec();
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_decode_invalid() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let result = lua
+            .load(chunk! {
+                return komandan.yaml.decode("key: [unclosed")
+            })
+            .exec();
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_base64() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let (standard, url, decoded, wrapped, binary) = lua
+            .load(chunk! {
+                local data = "subjects?_d>"
+                return komandan.base64.encode(data), komandan.base64.encode_url(data),
+                    komandan.base64.decode_url(komandan.base64.encode_url(data)),
+                    komandan.base64.decode("aGVs\nbG8="),
+                    komandan.base64.decode(komandan.base64.encode(string.char(0, 255))) == string.char(0, 255)
+            })
+            .eval::<(String, String, String, String, bool)>()?;
+        assert_eq!(standard, "c3ViamVjdHM/X2Q+");
+        assert_eq!(url, "c3ViamVjdHM_X2Q-");
+        assert_eq!(decoded, "subjects?_d>");
+        assert_eq!(wrapped, "hello");
+        assert!(binary);
+
+        let result = lua
+            .load(chunk! {
+                return komandan.base64.decode("not base64!")
+            })
+            .exec();
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -2,8 +2,8 @@ use crate::connection::ConnectionError;
 use crate::defaults::Defaults;
 use crate::ssh::SSHAuthMethod;
 use crate::util::{host_display, task_display};
+use crate::vault::secret_field;
 use mlua::{Error::RuntimeError, Table};
-use secrecy::{ExposeSecret, SecretString};
 use std::env;
 use std::path::Path;
 
@@ -112,8 +112,7 @@ pub fn get_auth_config(
             }
             .to_runtime_error()
         })?
-        .as_ref()
-        .map(|s: &SecretString| s.expose_secret().to_string());
+        .clone();
 
     let default_password = defaults
         .password
@@ -125,81 +124,70 @@ pub fn get_auth_config(
             }
             .to_runtime_error()
         })?
-        .as_ref()
-        .map(|s: &SecretString| s.expose_secret().to_string());
+        .clone();
 
+    let private_key_pass = secret_field(host, "private_key_pass")?.or(default_private_key_pass);
     let ssh_auth_method = match host.get::<String>("private_key_file") {
-        Ok(private_key_file) => SSHAuthMethod::public_key(
-            private_key_file,
-            host.get::<String>("private_key_pass")
-                .ok()
-                .or(default_private_key_pass),
-        ),
+        Ok(private_key_file) => SSHAuthMethod::PublicKey {
+            private_key: private_key_file,
+            passphrase: private_key_pass,
+        },
         Err(_) => match default_private_key_file {
-            Some(ref private_key_file) => SSHAuthMethod::public_key(
-                private_key_file.clone(),
-                host.get::<String>("private_key_pass")
-                    .ok()
-                    .or(default_private_key_pass),
-            ),
-            None => match host.get::<String>("password") {
-                Ok(password) => SSHAuthMethod::password(password),
-                Err(_) => {
-                    if let Some(ref password) = default_password {
-                        SSHAuthMethod::password(password.clone())
+            Some(ref private_key_file) => SSHAuthMethod::PublicKey {
+                private_key: private_key_file.clone(),
+                passphrase: private_key_pass,
+            },
+            None => match secret_field(host, "password")?.or(default_password) {
+                Some(password) => SSHAuthMethod::Password(password),
+                None => {
+                    // Check if SSH key auto-discovery is enabled
+                    if !*defaults.ssh_auto_discover_keys.read().map_err(|_| {
+                        ConnectionError::Configuration {
+                            message: "Failed to read ssh_auto_discover_keys setting".to_string(),
+                            context: "defaults access".to_string(),
+                        }
+                        .to_runtime_error()
+                    })? {
+                        return Err(ConnectionError::Authentication {
+                                message: "No authentication method available and SSH key auto-discovery is disabled".to_string(),
+                                host: host_display,
+                                user,
+                                tried_keys: Vec::new(),
+                            }.to_runtime_error());
+                    }
+
+                    let home = if let Some(h) = home_override {
+                        h.to_string()
                     } else {
-                        // Check if SSH key auto-discovery is enabled
-                        if !*defaults.ssh_auto_discover_keys.read().map_err(|_| {
+                        env::var("HOME").map_err(|_| {
                             ConnectionError::Configuration {
-                                message: "Failed to read ssh_auto_discover_keys setting"
-                                    .to_string(),
-                                context: "defaults access".to_string(),
+                                message: "HOME environment variable not set".to_string(),
+                                context: "SSH key discovery".to_string(),
                             }
                             .to_runtime_error()
-                        })? {
+                        })?
+                    };
+                    let ed25519_path = format!("{home}/.ssh/id_ed25519");
+                    if Path::new(&ed25519_path).exists() {
+                        SSHAuthMethod::PublicKey {
+                            private_key: ed25519_path,
+                            passphrase: private_key_pass,
+                        }
+                    } else {
+                        let rsa_path = format!("{home}/.ssh/id_rsa");
+                        if Path::new(&rsa_path).exists() {
+                            SSHAuthMethod::PublicKey {
+                                private_key: rsa_path,
+                                passphrase: private_key_pass,
+                            }
+                        } else {
                             return Err(ConnectionError::Authentication {
-                                message: "No authentication method available and SSH key auto-discovery is disabled".to_string(),
+                                message: "No authentication method available".to_string(),
                                 host: host_display,
                                 user,
-                            }.to_runtime_error());
-                        }
-
-                        let home = if let Some(h) = home_override {
-                            h.to_string()
-                        } else {
-                            env::var("HOME").map_err(|_| {
-                                ConnectionError::Configuration {
-                                    message: "HOME environment variable not set".to_string(),
-                                    context: "SSH key discovery".to_string(),
-                                }
-                                .to_runtime_error()
-                            })?
-                        };
-                        let ed25519_path = format!("{home}/.ssh/id_ed25519");
-                        if Path::new(&ed25519_path).exists() {
-                            SSHAuthMethod::public_key(
-                                ed25519_path,
-                                host.get::<String>("private_key_pass")
-                                    .ok()
-                                    .or(default_private_key_pass),
-                            )
-                        } else {
-                            let rsa_path = format!("{home}/.ssh/id_rsa");
-                            if Path::new(&rsa_path).exists() {
-                                SSHAuthMethod::public_key(
-                                    rsa_path,
-                                    host.get::<String>("private_key_pass")
-                                        .ok()
-                                        .or(default_private_key_pass),
-                                )
-                            } else {
-                                return Err(ConnectionError::Authentication {
-                                    message: "No authentication method available".to_string(),
-                                    host: host_display,
-                                    user,
-                                }
-                                .to_runtime_error());
+                                tried_keys: vec![ed25519_path, rsa_path],
                             }
+                            .to_runtime_error());
                         }
                     }
                 }
@@ -1,74 +1,157 @@
+use std::collections::BTreeMap;
+
 use crate::connection::ConnectionError;
 use crate::defaults::Defaults;
 use crate::executor::CommandExecutor;
-use crate::local::LocalSession;
+use crate::local::{LocalSession, is_valid_env_var_name};
 use crate::ssh::SSHSession;
 use mlua::Table;
 
-/// Set up environment variables for SSH sessions
-///
-/// This function extracts environment variable setup logic from komando.rs
-/// and handles defaults, host-level, and task-level environment variables.
-///
-/// # Arguments
-/// * `ssh` - Mutable reference to SSH session
-/// * `host` - Host configuration table
-/// * `task` - Task configuration table
+/// A variable of a task's environment and the layer that decided it:
+/// `defaults`, `host` or `task`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVar {
+    /// The value, or `None` when the variable is unset by `env_unset`.
+    pub value: Option<String>,
+    pub source: &'static str,
+}
+
+/// Resolve the environment of `task` on `host`, per variable.
 ///
-/// # Returns
-/// * `mlua::Result<()>` - Success or error
+/// Layers apply in order: defaults, host `env`, host `env_unset`, task `env`,
+/// task `env_unset`; later layers win. A task with `env_inherit = false`
+/// skips the defaults and host layers and only gets its own variables.
 ///
 /// # Errors
 /// Returns an error if:
 /// - Default values cannot be read
 /// - Environment variable tables cannot be processed
-pub fn setup_environment_ssh(ssh: &mut SSHSession, host: &Table, task: &Table) -> mlua::Result<()> {
-    let defaults = Defaults::global();
+/// - An `env_unset` entry is not a valid variable name
+pub fn resolve_environment(
+    host: &Table,
+    task: Option<&Table>,
+) -> mlua::Result<BTreeMap<String, EnvVar>> {
+    let mut env = BTreeMap::new();
+    let inherit = task
+        .map(|task| task.get::<Option<bool>>("env_inherit"))
+        .transpose()?
+        .flatten()
+        .unwrap_or(true);
 
-    let Ok(default_env) = defaults.env.read() else {
-        return Err(ConnectionError::Configuration {
-            message: "Failed to read default environment variables".to_string(),
-            context: "defaults access".to_string(),
+    if inherit {
+        let Ok(default_env) = Defaults::global().env.read() else {
+            return Err(ConnectionError::Configuration {
+                message: "Failed to read default environment variables".to_string(),
+                context: "defaults access".to_string(),
+            }
+            .to_runtime_error());
+        };
+        for (key, value) in default_env.iter() {
+            env.insert(
+                key.clone(),
+                EnvVar {
+                    value: Some(value.clone()),
+                    source: "defaults",
+                },
+            );
         }
-        .to_runtime_error());
-    };
-
-    let env_host = host.get::<Option<Table>>("env")?;
-    let env_task = task.get::<Option<Table>>("env")?;
-
-    for (key, value) in default_env.iter() {
-        ssh.set_env(key, value);
+        apply_layer(&mut env, host, "host")?;
+    }
+    if let Some(task) = task {
+        apply_layer(&mut env, task, "task")?;
     }
+    Ok(env)
+}
 
-    if let Some(env_host) = env_host {
-        for pair in env_host.pairs::<String, String>() {
+/// Apply the `env` and then the `env_unset` of `table` to `env`.
+fn apply_layer(
+    env: &mut BTreeMap<String, EnvVar>,
+    table: &Table,
+    source: &'static str,
+) -> mlua::Result<()> {
+    if let Some(layer) = table.get::<Option<Table>>("env")? {
+        for pair in layer.pairs::<String, String>() {
             let (key, value) = pair.map_err(|e| {
                 ConnectionError::Configuration {
-                    message: format!("Invalid host environment variable: {e}"),
-                    context: "host environment variable processing".to_string(),
+                    message: format!("Invalid {source} environment variable: {e}"),
+                    context: format!("{source} environment variable processing"),
                 }
                 .to_runtime_error()
             })?;
-            ssh.set_env(&key, &value);
+            env.insert(
+                key,
+                EnvVar {
+                    value: Some(value),
+                    source,
+                },
+            );
         }
     }
 
-    if let Some(env_task) = env_task {
-        for pair in env_task.pairs::<String, String>() {
-            let (key, value) = pair.map_err(|e| {
+    if let Some(unset) = table.get::<Option<Table>>("env_unset")? {
+        for key in unset.sequence_values::<String>() {
+            let key = key.map_err(|e| {
                 ConnectionError::Configuration {
-                    message: format!("Invalid task environment variable: {e}"),
-                    context: "task environment variable processing".to_string(),
+                    message: format!("Invalid {source} env_unset entry: {e}"),
+                    context: format!("{source} environment variable processing"),
                 }
                 .to_runtime_error()
             })?;
-            ssh.set_env(&key, &value);
+            if !is_valid_env_var_name(&key) {
+                return Err(ConnectionError::Configuration {
+                    message: format!("Invalid environment variable name in env_unset: {key}"),
+                    context: format!("{source} environment variable processing"),
+                }
+                .to_runtime_error());
+            }
+            env.insert(
+                key,
+                EnvVar {
+                    value: None,
+                    source,
+                },
+            );
         }
     }
+    Ok(())
+}
 
+fn setup_environment(
+    session: &mut impl CommandExecutor,
+    host: &Table,
+    task: &Table,
+) -> mlua::Result<()> {
+    for (key, var) in resolve_environment(host, Some(task))? {
+        match var.value {
+            Some(value) => session.set_env(&key, &value),
+            None => session.unset_env(&key),
+        }
+    }
     Ok(())
 }
 
+/// Set up environment variables for SSH sessions
+///
+/// This function extracts environment variable setup logic from komando.rs
+/// and handles defaults, host-level, and task-level environment variables,
+/// as resolved by [`resolve_environment`].
+///
+/// # Arguments
+/// * `ssh` - Mutable reference to SSH session
+/// * `host` - Host configuration table
+/// * `task` - Task configuration table
+///
+/// # Returns
+/// * `mlua::Result<()>` - Success or error
+///
+/// # Errors
+/// Returns an error if:
+/// - Default values cannot be read
+/// - Environment variable tables cannot be processed
+pub fn setup_environment_ssh(ssh: &mut SSHSession, host: &Table, task: &Table) -> mlua::Result<()> {
+    setup_environment(ssh, host, task)
+}
+
 /// Set up environment variables for local sessions
 ///
 /// This function applies environment variables to local sessions using the same
@@ -86,48 +169,5 @@ pub fn setup_environment_local(
     host: &Table,
     task: &Table,
 ) -> mlua::Result<()> {
-    let defaults = Defaults::global();
-
-    let Ok(default_env) = defaults.env.read() else {
-        return Err(ConnectionError::Configuration {
-            message: "Failed to read default environment variables".to_string(),
-            context: "defaults access".to_string(),
-        }
-        .to_runtime_error());
-    };
-
-    let env_host = host.get::<Option<Table>>("env")?;
-    let env_task = task.get::<Option<Table>>("env")?;
-
-    for (key, value) in default_env.iter() {
-        local.set_env(key, value);
-    }
-
-    if let Some(env_host) = env_host {
-        for pair in env_host.pairs::<String, String>() {
-            let (key, value) = pair.map_err(|e| {
-                ConnectionError::Configuration {
-                    message: format!("Invalid host environment variable: {e}"),
-                    context: "host environment variable processing".to_string(),
-                }
-                .to_runtime_error()
-            })?;
-            local.set_env(&key, &value);
-        }
-    }
-
-    if let Some(env_task) = env_task {
-        for pair in env_task.pairs::<String, String>() {
-            let (key, value) = pair.map_err(|e| {
-                ConnectionError::Configuration {
-                    message: format!("Invalid task environment variable: {e}"),
-                    context: "task environment variable processing".to_string(),
-                }
-                .to_runtime_error()
-            })?;
-            local.set_env(&key, &value);
-        }
-    }
-
-    Ok(())
+    setup_environment(local, host, task)
 }
@@ -5,8 +5,10 @@ use mlua::Error::RuntimeError;
 /// The prior `troubleshooting` string fields, the `format_error` method, and
 /// the `get_*_troubleshooting` helpers were verbose boilerplate that cluttered
 /// every error message; they have been removed (see `REFACTOR_PLAN.md` §2.2).
-/// `Display` now renders only the structured fields. Detailed SSH debugging
-/// guidance belongs in documentation, not every error string.
+/// `Display` now renders only the structured fields. The authentication and
+/// host-key variants derive a short remediation hint from those fields (the
+/// exact `ssh-keyscan` command, the key files tried) rather than carrying
+/// free-form troubleshooting text.
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum ConnectionError {
     /// Host table failed validation (missing/invalid fields).
@@ -14,11 +16,16 @@ pub enum ConnectionError {
     HostValidation { message: String, host: String },
 
     /// SSH authentication failed.
-    #[error("SSH authentication failed: {message} for {user}@{host}")]
+    #[error(
+        "SSH authentication failed: {message} for {user}@{host}. {}",
+        auth_hint(.tried_keys)
+    )]
     Authentication {
         message: String,
         host: String,
         user: String,
+        /// Private key files offered to the server, in order.
+        tried_keys: Vec<String>,
     },
 
     /// SSH TCP/session connection failed.
@@ -30,8 +37,30 @@ pub enum ConnectionError {
     },
 
     /// SSH host-key verification failed.
-    #[error("SSH host key verification failed: {message} for host '{host}'")]
-    HostKeyVerification { message: String, host: String },
+    #[error(
+        "SSH host key verification failed: {message} for host '{host}'. If the host is trusted, add its key with: ssh-keyscan -p {port} {host} >> {known_hosts_file}"
+    )]
+    HostKeyVerification {
+        message: String,
+        host: String,
+        port: u16,
+        /// `known_hosts` file the key was checked against.
+        known_hosts_file: String,
+    },
+
+    /// Server host key does not match the fingerprint pinned on the host.
+    #[error(
+        "SSH host key fingerprint mismatch for host '{host}': server presented {actual}, expected {}. If the host was re-provisioned, update 'host_key_fingerprint' from: ssh-keyscan -p {port} {host} | ssh-keygen -lf -",
+        .expected.join(" or ")
+    )]
+    HostKeyFingerprint {
+        host: String,
+        port: u16,
+        /// Fingerprint of the key the server presented.
+        actual: String,
+        /// Fingerprints pinned with `host_key_fingerprint`.
+        expected: Vec<String>,
+    },
 
     /// Connection-factory configuration error.
     #[error("SSH configuration error: {message} in {context}")]
@@ -49,3 +78,15 @@ impl ConnectionError {
         RuntimeError(self.to_string())
     }
 }
+
+/// Render the remediation suffix for [`ConnectionError::Authentication`].
+fn auth_hint(tried_keys: &[String]) -> String {
+    let keys = if tried_keys.is_empty() {
+        "none".to_string()
+    } else {
+        tried_keys.join(", ")
+    };
+    format!(
+        "Key files tried: {keys}. Check the user name, that the public key is listed in the remote ~/.ssh/authorized_keys, or set 'private_key_file' / 'password' on the host."
+    )
+}
@@ -54,10 +54,10 @@ mod session;
 #[cfg(test)]
 mod tests;
 
-pub use auth::get_auth_config;
+pub use auth::{get_auth_config, get_user};
 pub use elevation::get_elevation_config;
 pub(crate) use env::setup_environment_local;
-pub use env::setup_environment_ssh;
+pub use env::{EnvVar, resolve_environment, setup_environment_ssh};
 pub use error::ConnectionError;
 pub use session::{create_configured_ssh_session, create_ssh_session};
 
@@ -69,6 +69,7 @@ use crate::util::host_display;
 use crate::validator::validate_host;
 use anyhow::Result;
 use mlua::{Lua, Table, Value};
+use std::time::Instant;
 
 /// Unified connection interface that can represent either SSH or local connections
 #[derive(Clone, Debug)]
@@ -103,6 +104,27 @@ impl Connection {
         }
     }
 
+    /// Execute a command with the connection's elevation applied, streaming
+    /// stdout line by line to `on_line`
+    ///
+    /// # Errors
+    /// Returns an error if the command cannot be started or its output cannot be read
+    pub fn cmd_stream(&self, command: &str, on_line: &mut dyn FnMut(&str)) -> Result<i32> {
+        match self {
+            Self::SSH(ssh) => ssh.cmd_stream(&ssh.prepare_command(command), on_line),
+            Self::Local(local) => local.cmd_stream(&local.prepare_command(command), on_line),
+        }
+    }
+
+    /// Wrap `command` with the connection's elevation method
+    #[must_use]
+    pub fn prepare_command(&self, command: &str) -> String {
+        match self {
+            Self::SSH(ssh) => ssh.prepare_command(command),
+            Self::Local(local) => local.prepare_command(command),
+        }
+    }
+
     /// Set an environment variable for the connection
     #[allow(dead_code)]
     pub fn set_env(&mut self, key: &str, value: &str) {
@@ -112,6 +134,33 @@ impl Connection {
         }
     }
 
+    /// Bound all subsequent operations on this connection by `deadline`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        match self {
+            Self::SSH(ssh) => ssh.set_deadline(deadline),
+            Self::Local(local) => local.set_deadline(deadline),
+        }
+    }
+
+    /// Abort the connection after a task timed out or failed, so a session
+    /// left broken is never handed to the next task.
+    ///
+    /// Disconnects SSH transports; local commands are already killed by the
+    /// deadline, so this is a no-op for them.
+    pub fn abort(&self, reason: &str) {
+        if let Self::SSH(ssh) = self {
+            ssh.abort(reason);
+        }
+    }
+
+    /// Return a pooled SSH session for reuse by the next task on the host.
+    /// Do not call after [`Connection::abort`].
+    pub fn release(self) {
+        if let Self::SSH(ssh) = self {
+            ssh.release();
+        }
+    }
+
     /// Get the connection type
     #[allow(dead_code)]
     #[must_use]
@@ -203,8 +252,7 @@ pub fn create_connection(lua: &Lua, host: &Value) -> mlua::Result<Connection> {
 ///
 /// # Returns
 /// * `mlua::Result<ConnectionType>` - The determined connection type
-#[allow(dead_code)]
-fn determine_connection_type(host: &Table) -> mlua::Result<ConnectionType> {
+pub(crate) fn determine_connection_type(host: &Table) -> mlua::Result<ConnectionType> {
     // Check if connection type is explicitly set
     if let Some(conn_type) = host
         .get::<String>("connection")
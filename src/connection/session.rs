@@ -8,7 +8,9 @@ use mlua::{Table, Value};
 /// Create and configure an SSH session with host key verification settings
 ///
 /// This function extracts SSH session creation and configuration logic from komando.rs
-/// and handles host key verification and known hosts configuration.
+/// and handles host key verification and known hosts configuration. A
+/// `host_key_fingerprint` on the host (one fingerprint or a list) pins the
+/// server key; it is checked even when `host_key_check` is off.
 ///
 /// # Arguments
 /// * `host` - Host configuration table
@@ -64,6 +66,24 @@ pub fn create_ssh_session(host: &Table) -> mlua::Result<SSHSession> {
         .to_runtime_error());
     };
 
+    ssh.host_key_fingerprints = match host.get::<Value>("host_key_fingerprint")? {
+        Value::Nil => Vec::new(),
+        Value::String(fingerprint) => vec![fingerprint.to_str()?.to_string()],
+        Value::Table(fingerprints) => fingerprints
+            .sequence_values::<String>()
+            .collect::<mlua::Result<Vec<_>>>()?,
+        other => {
+            return Err(ConnectionError::Configuration {
+                message: format!(
+                    "host_key_fingerprint must be a string or a list of strings, got {}",
+                    other.type_name()
+                ),
+                context: "SSH session configuration".to_string(),
+            }
+            .to_runtime_error());
+        }
+    };
+
     if host_key_check {
         // Read as Option<String> so a present-but-wrong-type value surfaces as
         // an error instead of silently falling back to the default.
@@ -106,6 +126,18 @@ pub fn get_port_from_host(host: &Table) -> mlua::Result<u16> {
     }
 }
 
+/// The `known_hosts` file a host-key hint points at: the session's, or the
+/// configured default (`~/.ssh/known_hosts` unless overridden) when host key
+/// checking is off.
+pub(super) fn hint_known_hosts_file(ssh: &SSHSession) -> String {
+    ssh.known_hosts_file.clone().unwrap_or_else(|| {
+        Defaults::global().known_hosts_file.read().map_or_else(
+            |_| "~/.ssh/known_hosts".to_string(),
+            |known_hosts_file| known_hosts_file.clone(),
+        )
+    })
+}
+
 /// Create a fully configured SSH session using existing komando.rs logic
 ///
 /// This function combines authentication, session creation, and configuration setup
@@ -156,12 +188,23 @@ pub fn create_configured_ssh_session(host_table: &Table, task: &Table) -> mlua::
         .to_runtime_error()
     })?;
 
-    // Connect using existing logic with error type classification
-    ssh.connect(&address, port, &user, auth_method)
+    let tried_keys = auth_method.key_files();
+
+    // `connect` raises structured `ConnectionError`s for host-key and auth
+    // failures; anything else is classified by message content.
+    ssh.connect_pooled(&address, port, &user, auth_method)
         .map_err(|e| {
+            let e = match e.downcast::<ConnectionError>() {
+                Ok(structured) => return structured.to_runtime_error(),
+                Err(e) => e,
+            };
             let error_msg = e.to_string();
 
-            // Classify the underlying error by its message content
+            // Host-key messages also mention "key", so test them first.
+            let is_host_key = error_msg.contains("host key")
+                || error_msg.contains("Host key")
+                || error_msg.contains("known_hosts")
+                || error_msg.contains("verification");
             let is_auth = error_msg.contains("authentication")
                 || error_msg.contains("Authentication")
                 || error_msg.contains("auth")
@@ -169,22 +212,21 @@ pub fn create_configured_ssh_session(host_table: &Table, task: &Table) -> mlua::
                 || error_msg.contains("password")
                 || error_msg.contains("key")
                 || error_msg.contains("Permission denied");
-            let is_host_key = error_msg.contains("host key")
-                || error_msg.contains("Host key")
-                || error_msg.contains("known_hosts")
-                || error_msg.contains("verification");
 
-            if is_auth {
-                ConnectionError::Authentication {
+            if is_host_key {
+                ConnectionError::HostKeyVerification {
                     message: error_msg,
-                    host: format!("{address}:{port}"),
-                    user: user.clone(),
+                    host: address.clone(),
+                    port,
+                    known_hosts_file: hint_known_hosts_file(&ssh),
                 }
                 .to_runtime_error()
-            } else if is_host_key {
-                ConnectionError::HostKeyVerification {
+            } else if is_auth {
+                ConnectionError::Authentication {
                     message: error_msg,
-                    host: address.clone(),
+                    host: format!("{address}:{port}"),
+                    user: user.clone(),
+                    tried_keys: tried_keys.clone(),
                 }
                 .to_runtime_error()
             } else {
@@ -1,9 +1,10 @@
 use super::auth::get_user;
-use super::session::get_port_from_host;
+use super::session::{get_port_from_host, hint_known_hosts_file};
 use super::*;
 use crate::create_lua;
 use crate::ssh::{Elevation, ElevationMethod, SSHAuthMethod};
 use mlua::Error::RuntimeError;
+use mlua::chunk;
 
 #[test]
 fn test_create_connection_local() -> mlua::Result<()> {
@@ -337,6 +338,33 @@ fn test_create_ssh_session() -> mlua::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_create_ssh_session_host_key_fingerprint() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let host = lua.create_table()?;
+    host.set("address", "web1")?;
+    assert!(create_ssh_session(&host)?.host_key_fingerprints.is_empty());
+
+    host.set("host_key_fingerprint", "SHA256:abc")?;
+    assert_eq!(
+        create_ssh_session(&host)?.host_key_fingerprints,
+        vec!["SHA256:abc"]
+    );
+
+    host.set(
+        "host_key_fingerprint",
+        lua.create_sequence_from(["SHA256:abc", "SHA256:def"])?,
+    )?;
+    host.set("host_key_check", false)?;
+    let ssh = create_ssh_session(&host)?;
+    assert_eq!(ssh.host_key_fingerprints, vec!["SHA256:abc", "SHA256:def"]);
+    assert!(ssh.known_hosts_file.is_none());
+
+    host.set("host_key_fingerprint", 42)?;
+    assert!(create_ssh_session(&host).is_err());
+    Ok(())
+}
+
 #[test]
 fn test_get_elevation_config() -> mlua::Result<()> {
     let lua = create_lua()?;
@@ -421,3 +449,91 @@ fn test_setup_environment_ssh_empty() -> mlua::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_resolve_environment_unset_and_inherit() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let (host, task) = lua
+        .load(chunk! {
+            local host = { address = "localhost", env = { HOST_VAR = "host" }, env_unset = { "DEBIAN_FRONTEND" } }
+            local task = { env = { TASK_VAR = "task", DEBIAN_FRONTEND = "readline" }, env_unset = { "HOST_VAR" } }
+            return host, task
+        })
+        .eval::<(Table, Table)>()?;
+
+    let env = resolve_environment(&host, None)?;
+    assert_eq!(env["HOST_VAR"].value.as_deref(), Some("host"));
+    assert_eq!(env["DEBIAN_FRONTEND"].value, None);
+    assert_eq!(env["DEBIAN_FRONTEND"].source, "host");
+
+    let env = resolve_environment(&host, Some(&task))?;
+    assert_eq!(env["HOST_VAR"].value, None);
+    assert_eq!(env["DEBIAN_FRONTEND"].value.as_deref(), Some("readline"));
+
+    task.set("env_inherit", false)?;
+    let env = resolve_environment(&host, Some(&task))?;
+    assert_eq!(
+        env.keys().map(String::as_str).collect::<Vec<_>>(),
+        ["DEBIAN_FRONTEND", "HOST_VAR", "TASK_VAR"]
+    );
+    assert!(env.values().all(|var| var.source == "task"));
+
+    task.set("env_unset", vec!["NOT A NAME"])?;
+    assert!(resolve_environment(&host, Some(&task)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_host_key_error_includes_keyscan_hint() {
+    let err = ConnectionError::HostKeyVerification {
+        message: "NotFound in known_hosts".to_string(),
+        host: "web1.example.com".to_string(),
+        port: 2222,
+        known_hosts_file: "/home/deploy/.ssh/known_hosts".to_string(),
+    };
+    let msg = err.to_string();
+    assert!(
+        msg.contains("ssh-keyscan -p 2222 web1.example.com >> /home/deploy/.ssh/known_hosts"),
+        "missing ssh-keyscan remediation: {msg}"
+    );
+}
+
+#[test]
+fn test_host_key_hint_names_a_known_hosts_file() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let host = lua.create_table()?;
+    host.set("address", "web1")?;
+    host.set("host_key_check", false)?;
+    let ssh = create_ssh_session(&host)?;
+    assert!(ssh.known_hosts_file.is_none());
+    assert!(!hint_known_hosts_file(&ssh).is_empty());
+
+    host.set("host_key_check", true)?;
+    host.set("known_hosts_file", "/etc/komandan/known_hosts")?;
+    let ssh = create_ssh_session(&host)?;
+    assert_eq!(hint_known_hosts_file(&ssh), "/etc/komandan/known_hosts");
+    Ok(())
+}
+
+#[test]
+fn test_auth_error_lists_tried_keys() {
+    let err = ConnectionError::Authentication {
+        message: "No authentication method available".to_string(),
+        host: "web1.example.com".to_string(),
+        user: "deploy".to_string(),
+        tried_keys: vec![
+            "/home/deploy/.ssh/id_ed25519".to_string(),
+            "/home/deploy/.ssh/id_rsa".to_string(),
+        ],
+    };
+    let msg = err.to_string();
+    assert!(msg.contains("/home/deploy/.ssh/id_ed25519, /home/deploy/.ssh/id_rsa"));
+
+    let err = ConnectionError::Authentication {
+        message: "denied".to_string(),
+        host: "web1.example.com".to_string(),
+        user: "deploy".to_string(),
+        tried_keys: Vec::new(),
+    };
+    assert!(err.to_string().contains("Key files tried: none"));
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use anyhow::{Context, Result, bail};
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+
+/// Variables loaded from `.env` files. Only `komandan.env` sees them; they
+/// are neither exported to the process nor passed to tasks.
+static DOTENV: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+
+fn dotenv() -> &'static RwLock<HashMap<String, String>> {
+    DOTENV.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Value of `name` on the control machine: the process environment first,
+/// then variables loaded from `.env` files.
+#[must_use]
+pub fn lookup(name: &str) -> Option<String> {
+    std::env::var(name).ok().or_else(|| {
+        dotenv()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    })
+}
+
+/// All variables visible through `komandan.env`.
+fn all() -> HashMap<String, String> {
+    let mut vars = dotenv()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    vars.extend(std::env::vars());
+    vars
+}
+
+/// Loads the `.env` file at `path`. With `override_loaded`, its values
+/// replace those of earlier files; otherwise the first file to set a
+/// variable wins. The process environment always takes precedence.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or has a malformed line.
+pub fn load_dotenv(path: &Path, override_loaded: bool) -> Result<usize> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let vars = parse_dotenv(&content).with_context(|| format!("Invalid {}", path.display()))?;
+    let count = vars.len();
+    let mut loaded = dotenv().write().unwrap_or_else(PoisonError::into_inner);
+    for (key, value) in vars {
+        if override_loaded {
+            loaded.insert(key, value);
+        } else {
+            loaded.entry(key).or_insert(value);
+        }
+    }
+    Ok(count)
+}
+
+/// Parses `KEY=value` lines. Blank lines and `#` comments are skipped, an
+/// `export ` prefix is allowed, single-quoted values are literal and
+/// double-quoted values understand `\n`, `\t`, `\"` and `\\`. Unquoted values
+/// end at ` #`.
+///
+/// # Errors
+///
+/// Returns an error naming the first malformed line.
+pub fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected KEY=value", index + 1);
+        };
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            bail!("line {}: invalid variable name '{key}'", index + 1);
+        }
+        let value = parse_value(value.trim())
+            .with_context(|| format!("line {}: unterminated quoted value", index + 1))?;
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        return quoted.split_once('\'').map(|(value, _)| value.to_string());
+    }
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(result),
+                '\\' => match chars.next()? {
+                    'n' => result.push('\n'),
+                    't' => result.push('\t'),
+                    'r' => result.push('\r'),
+                    other => result.push(other),
+                },
+                c => result.push(c),
+            }
+        }
+        return None;
+    }
+    let value = value.find(" #").map_or(value, |comment| &value[..comment]);
+    Some(value.trim_end().to_string())
+}
+
+/// Builds the `komandan.env` table:
+///
+/// - `get(name, default)` returns the variable, or `default` when unset
+/// - `get(name, { default = ..., required = true })`: with `required`, an
+///   unset or empty variable is an error naming it
+/// - `load(path, { override = false })` reads a `.env` file
+/// - `all()` returns every variable as a table
+/// - `list(prefix)` returns the variables whose names start with `prefix`
+///
+/// Variables can also be read as fields, e.g. `komandan.env.CI_COMMIT_SHA`.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn env_table(lua: &Lua) -> mlua::Result<Table> {
+    let env = lua.create_table()?;
+    env.set(
+        "get",
+        lua.create_function(|lua, (name, options): (String, Value)| {
+            let (default, required) = match options {
+                Value::Table(options) => (
+                    options.get::<Value>("default")?,
+                    options.get::<Option<bool>>("required")?.unwrap_or(false),
+                ),
+                default => (default, false),
+            };
+            match lookup(&name) {
+                Some(value) if !(required && value.is_empty()) => {
+                    lua.create_string(value).map(Value::String)
+                }
+                _ if required => Err(RuntimeError(format!(
+                    "Required environment variable '{name}' is not set"
+                ))),
+                _ => Ok(default),
+            }
+        })?,
+    )?;
+    env.set(
+        "load",
+        lua.create_function(|_, (path, options): (Option<String>, Option<Table>)| {
+            let path = path.unwrap_or_else(|| ".env".to_string());
+            let override_loaded = options
+                .map(|options| options.get::<Option<bool>>("override"))
+                .transpose()?
+                .flatten()
+                .unwrap_or(false);
+            load_dotenv(Path::new(&path), override_loaded)
+                .map_err(|e| RuntimeError(format!("{e:#}")))
+        })?,
+    )?;
+    env.set("all", lua.create_function(|_, ()| Ok(all()))?)?;
+    env.set(
+        "list",
+        lua.create_function(|_, prefix: Option<String>| {
+            let prefix = prefix.unwrap_or_default();
+            Ok(all()
+                .into_iter()
+                .filter(|(name, _)| name.starts_with(&prefix))
+                .collect::<HashMap<_, _>>())
+        })?,
+    )?;
+
+    let metatable = lua.create_table()?;
+    metatable.set(
+        "__index",
+        lua.create_function(|_, (_, name): (Table, String)| Ok(lookup(&name)))?,
+    )?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(
+            |_, (_, name, _): (Table, String, Value)| -> mlua::Result<()> {
+                Err(RuntimeError(format!(
+                    "komandan.env is read-only; cannot set '{name}'"
+                )))
+            },
+        )?,
+    )?;
+    env.set_metatable(Some(metatable))?;
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::chunk;
+
+    #[test]
+    fn test_parse_dotenv() -> Result<()> {
+        let vars = parse_dotenv(
+            "# deploy settings\n\
+             REGION=eu-west-1\n\
+             export TOKEN = 'a#b c'\n\
+             GREETING=\"hello\\nworld\"\n\
+             EMPTY=\n\
+             \n\
+             RELEASE=v1.2 # pinned\n",
+        )?;
+        assert_eq!(
+            vars,
+            vec![
+                ("REGION".to_string(), "eu-west-1".to_string()),
+                ("TOKEN".to_string(), "a#b c".to_string()),
+                ("GREETING".to_string(), "hello\nworld".to_string()),
+                ("EMPTY".to_string(), String::new()),
+                ("RELEASE".to_string(), "v1.2".to_string()),
+            ]
+        );
+        assert!(parse_dotenv("NO_EQUALS").is_err());
+        assert!(parse_dotenv("BAD KEY=1").is_err());
+        assert!(parse_dotenv("OPEN=\"never closed").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_table() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "KOMANDAN_TEST_DOTENV_ONLY=from-file\nPATH=shadowed\n",
+        )
+        .map_err(mlua::Error::external)?;
+        let path = path.to_string_lossy().to_string();
+
+        let lua = crate::create_lua()?;
+        let (only, path_var, missing, field) = lua
+            .load(chunk! {
+                komandan.env.load($path)
+                return komandan.env.get("KOMANDAN_TEST_DOTENV_ONLY"),
+                    komandan.env.get("PATH"),
+                    komandan.env.get("KOMANDAN_TEST_UNSET", "fallback"),
+                    komandan.env.KOMANDAN_TEST_DOTENV_ONLY
+            })
+            .eval::<(String, String, String, String)>()?;
+        assert_eq!(only, "from-file");
+        assert_ne!(path_var, "shadowed");
+        assert_eq!(missing, "fallback");
+        assert_eq!(field, "from-file");
+        assert!(std::env::var("KOMANDAN_TEST_DOTENV_ONLY").is_err());
+        assert!(lua.load("komandan.env.FOO = 'bar'").exec().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_get_options_and_list() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "KOMANDAN_TEST_LIST_REGION=eu-west-1\nKOMANDAN_TEST_LIST_ZONE=b\nKOMANDAN_TEST_EMPTY=\n",
+        )
+        .map_err(mlua::Error::external)?;
+        let path = path.to_string_lossy().to_string();
+
+        let lua = crate::create_lua()?;
+        let (region, fallback, listed) = lua
+            .load(chunk! {
+                komandan.env.load($path)
+                local listed = komandan.env.list("KOMANDAN_TEST_LIST_")
+                local count = 0
+                for _ in pairs(listed) do
+                    count = count + 1
+                end
+                return komandan.env.get("KOMANDAN_TEST_LIST_REGION", { required = true }),
+                    komandan.env.get("KOMANDAN_TEST_UNSET", { default = "fallback" }),
+                    count == 2 and listed.KOMANDAN_TEST_LIST_ZONE == "b"
+            })
+            .eval::<(String, String, bool)>()?;
+        assert_eq!(region, "eu-west-1");
+        assert_eq!(fallback, "fallback");
+        assert!(listed);
+
+        for name in ["KOMANDAN_TEST_UNSET", "KOMANDAN_TEST_EMPTY"] {
+            let error = lua
+                .load(chunk! {
+                    return komandan.env.get($name, { required = true })
+                })
+                .exec()
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+            assert!(error.contains(&format!(
+                "Required environment variable '{name}' is not set"
+            )));
+        }
+        Ok(())
+    }
+}
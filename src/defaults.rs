@@ -3,11 +3,19 @@ use mlua::{LuaSerdeExt, UserData};
 use secrecy::{ExposeSecret, SecretString};
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{Arc, OnceLock, RwLock},
+    time::Duration,
 };
 
+use crate::facts::FactCache;
+use crate::util::duration_param;
+
 static GLOBAL_DEFAULTS: OnceLock<Defaults> = OnceLock::new();
 
+/// Seconds cached facts stay fresh when `set_fact_cache` is given no TTL.
+const DEFAULT_FACT_CACHE_TTL: u64 = 24 * 60 * 60;
+
 #[derive(Clone)]
 pub struct Defaults {
     pub port: Arc<RwLock<u16>>,
@@ -24,6 +32,16 @@ pub struct Defaults {
     pub ssh_auto_discover_keys: Arc<RwLock<bool>>,
     pub env: Arc<RwLock<HashMap<String, String>>>,
     pub hosts: Arc<RwLock<Vec<serde_json::Value>>>,
+    /// Default parameters per module name, merged beneath task parameters.
+    pub module_params: Arc<RwLock<HashMap<String, serde_json::Map<String, serde_json::Value>>>>,
+    /// Variables visible to every task, beneath group, host and task vars.
+    pub vars: Arc<RwLock<serde_json::Map<String, serde_json::Value>>>,
+    /// Variables per host group (a host's `tags`), beneath host and task vars.
+    pub group_vars: Arc<RwLock<HashMap<String, serde_json::Map<String, serde_json::Value>>>>,
+    /// Worker threads for parallel runs; `None` uses one per CPU.
+    pub forks: Arc<RwLock<Option<usize>>>,
+    /// Where gathered facts are kept between runs; `None` disables caching.
+    pub fact_cache: Arc<RwLock<Option<FactCache>>>,
 }
 
 impl Defaults {
@@ -73,6 +91,16 @@ impl Defaults {
                 )
             });
 
+        let forks = std::env::var("KOMANDAN_FORKS").ok().and_then(|v| {
+            v.parse::<usize>()
+                .ok()
+                .filter(|forks| *forks > 0)
+                .or_else(|| {
+                    tracing::warn!("Invalid KOMANDAN_FORKS value, using one worker per CPU");
+                    None
+                })
+        });
+
         let key_check = std::env::var("KOMANDAN_SSH_HOST_KEY_CHECK")
             .ok()
             .and_then(|v| v.parse::<bool>().ok())
@@ -93,6 +121,11 @@ impl Defaults {
             ssh_auto_discover_keys: Arc::new(RwLock::new(false)),
             env,
             hosts: Arc::new(RwLock::new(Vec::new())),
+            module_params: Arc::new(RwLock::new(HashMap::new())),
+            vars: Arc::new(RwLock::new(serde_json::Map::new())),
+            group_vars: Arc::new(RwLock::new(HashMap::new())),
+            forks: Arc::new(RwLock::new(forks)),
+            fact_cache: Arc::new(RwLock::new(None)),
         })
     }
 
@@ -142,6 +175,11 @@ fn handle_lock_error<T>(lock_name: &str, is_write: bool) -> mlua::Result<T> {
     )))
 }
 
+/// `value` as a secret, decrypted first if it is vault-encrypted.
+fn reveal_secret(value: &str) -> mlua::Result<SecretString> {
+    crate::vault::reveal_secret(value).map_err(|e| mlua::Error::RuntimeError(format!("{e:#}")))
+}
+
 impl UserData for Defaults {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("get_port", |_, this, ()| -> mlua::Result<u16> {
@@ -210,11 +248,13 @@ impl UserData for Defaults {
         methods.add_method_mut(
             "set_private_key_pass",
             |_, this, new_private_key_pass: Option<String>| {
+                let new_private_key_pass = new_private_key_pass
+                    .map(|s| reveal_secret(&s))
+                    .transpose()?;
                 this.private_key_pass.write().map_or_else(
                     |_| handle_lock_error("private_key_pass", true),
                     |mut guard: std::sync::RwLockWriteGuard<Option<SecretString>>| {
-                        *guard =
-                            new_private_key_pass.map(|s| SecretString::new(s.into_boxed_str()));
+                        *guard = new_private_key_pass;
                         Ok(())
                     },
                 )
@@ -233,10 +273,11 @@ impl UserData for Defaults {
         });
 
         methods.add_method_mut("set_password", |_, this, new_password: Option<String>| {
+            let new_password = new_password.map(|s| reveal_secret(&s)).transpose()?;
             this.password.write().map_or_else(
                 |_| handle_lock_error("password", true),
                 |mut guard: std::sync::RwLockWriteGuard<Option<SecretString>>| {
-                    *guard = new_password.map(|s| SecretString::new(s.into_boxed_str()));
+                    *guard = new_password;
                     Ok(())
                 },
             )
@@ -399,6 +440,161 @@ impl UserData for Defaults {
             )
         });
 
+        methods.add_method("get_module_params", |lua, this, module: String| {
+            this.module_params.read().map_or_else(
+                |_| handle_lock_error("module_params", false),
+                |module_params| {
+                    module_params
+                        .get(&module)
+                        .map(|params| lua.to_value(params))
+                        .transpose()
+                },
+            )
+        });
+
+        methods.add_method_mut(
+            "set_module_params",
+            |lua, this, (module, params): (String, Option<mlua::Table>)| {
+                let params = params
+                    .map(|params| {
+                        lua.from_value::<serde_json::Map<_, _>>(mlua::Value::Table(params))
+                    })
+                    .transpose()?;
+                this.module_params.write().map_or_else(
+                    |_| handle_lock_error("module_params", true),
+                    |mut module_params| {
+                        match params {
+                            Some(params) => module_params.insert(module, params),
+                            None => module_params.remove(&module),
+                        };
+                        Ok(())
+                    },
+                )
+            },
+        );
+
+        methods.add_method("get_vars", |lua, this, ()| {
+            this.vars.read().map_or_else(
+                |_| handle_lock_error("vars", false),
+                |vars| lua.to_value(&*vars),
+            )
+        });
+
+        methods.add_method_mut("set_vars", |lua, this, new_vars: Option<mlua::Table>| {
+            let new_vars = new_vars
+                .map(|vars| lua.from_value::<serde_json::Map<_, _>>(mlua::Value::Table(vars)))
+                .transpose()?
+                .unwrap_or_default();
+            this.vars.write().map_or_else(
+                |_| handle_lock_error("vars", true),
+                |mut vars| {
+                    *vars = new_vars;
+                    Ok(())
+                },
+            )
+        });
+
+        methods.add_method("get_group_vars", |lua, this, group: String| {
+            this.group_vars.read().map_or_else(
+                |_| handle_lock_error("group_vars", false),
+                |group_vars| {
+                    group_vars
+                        .get(&group)
+                        .map(|vars| lua.to_value(vars))
+                        .transpose()
+                },
+            )
+        });
+
+        methods.add_method_mut(
+            "set_group_vars",
+            |lua, this, (group, vars): (String, Option<mlua::Table>)| {
+                let vars = vars
+                    .map(|vars| lua.from_value::<serde_json::Map<_, _>>(mlua::Value::Table(vars)))
+                    .transpose()?;
+                this.group_vars.write().map_or_else(
+                    |_| handle_lock_error("group_vars", true),
+                    |mut group_vars| {
+                        match vars {
+                            Some(vars) => group_vars.insert(group, vars),
+                            None => group_vars.remove(&group),
+                        };
+                        Ok(())
+                    },
+                )
+            },
+        );
+
+        methods.add_method("get_forks", |_, this, ()| {
+            this.forks
+                .read()
+                .map_or_else(|_| handle_lock_error("forks", false), |forks| Ok(*forks))
+        });
+
+        methods.add_method_mut("set_forks", |_, this, new_forks: Option<usize>| {
+            if new_forks == Some(0) {
+                return Err(mlua::Error::RuntimeError(
+                    "forks must be at least 1".to_string(),
+                ));
+            }
+            this.forks.write().map_or_else(
+                |_| handle_lock_error("forks", true),
+                |mut forks| {
+                    *forks = new_forks;
+                    Ok(())
+                },
+            )
+        });
+
+        methods.add_method("get_fact_cache", |lua, this, ()| {
+            this.fact_cache.read().map_or_else(
+                |_| handle_lock_error("fact_cache", false),
+                |fact_cache| {
+                    fact_cache
+                        .as_ref()
+                        .map(|cache| {
+                            let table = lua.create_table()?;
+                            table.set("path", cache.path.to_string_lossy())?;
+                            table.set("ttl", cache.ttl.as_secs_f64())?;
+                            Ok(table)
+                        })
+                        .transpose()
+                },
+            )
+        });
+
+        methods.add_method_mut(
+            "set_fact_cache",
+            |_, this, (path, ttl): (Option<String>, mlua::Value)| {
+                let new_fact_cache = path
+                    .map(|path| -> mlua::Result<FactCache> {
+                        Ok(FactCache {
+                            path: PathBuf::from(path),
+                            ttl: duration_param(ttl, "ttl")?
+                                .unwrap_or(Duration::from_secs(DEFAULT_FACT_CACHE_TTL)),
+                        })
+                    })
+                    .transpose()?;
+                if let Some(cache) = &new_fact_cache
+                    && crate::args::global_flags().flush_facts
+                {
+                    cache.flush_once().map_err(|e| {
+                        mlua::Error::RuntimeError(format!(
+                            "Failed to flush fact cache '{}': {e}",
+                            cache.path.display()
+                        ))
+                    })?;
+                }
+                this.fact_cache.write().map_or_else(
+                    |_| handle_lock_error("fact_cache", true),
+                    |mut fact_cache| {
+                        *fact_cache = new_fact_cache;
+                        Ok(())
+                    },
+                )
+            },
+        );
+
         methods.add_method("get_all_env", |lua, this, ()| {
             this.env.read().map_or_else(
                 |_| handle_lock_error("env", false),
@@ -631,6 +827,60 @@ mod tests {
         )
         .exec()?;
 
+        // Test module params
+        lua.load("defaults:set_module_params('test_module', { install_recommends = false })")
+            .exec()?;
+        lua.load("assert(defaults:get_module_params('test_module').install_recommends == false)")
+            .exec()?;
+        lua.load("defaults:set_module_params('test_module', nil)")
+            .exec()?;
+        lua.load("assert(defaults:get_module_params('test_module') == nil)")
+            .exec()?;
+
+        // Test vars
+        lua.load(
+            "
+            defaults:set_vars({ env = 'prod' })
+            assert(defaults:get_vars().env == 'prod')
+            defaults:set_vars(nil)
+            assert(next(defaults:get_vars()) == nil)
+            defaults:set_group_vars('web', { port = 8080 })
+            assert(defaults:get_group_vars('web').port == 8080)
+            defaults:set_group_vars('web', nil)
+            assert(defaults:get_group_vars('web') == nil)
+        ",
+        )
+        .exec()?;
+
+        // Test forks
+        lua.load("defaults:set_forks(8)").exec()?;
+        lua.load("assert(defaults:get_forks() == 8)").exec()?;
+        assert!(lua.load("defaults:set_forks(0)").exec().is_err());
+        lua.load("defaults:set_forks(nil)").exec()?;
+        lua.load("assert(defaults:get_forks() == nil)").exec()?;
+
+        // Test fact cache
+        lua.load("assert(defaults:get_fact_cache() == nil)")
+            .exec()?;
+        lua.load("defaults:set_fact_cache('/tmp/komandan-facts', '2h')")
+            .exec()?;
+        lua.load("assert(defaults:get_fact_cache().path == '/tmp/komandan-facts')")
+            .exec()?;
+        lua.load("assert(defaults:get_fact_cache().ttl == 7200)")
+            .exec()?;
+        lua.load("defaults:set_fact_cache('/tmp/komandan-facts')")
+            .exec()?;
+        lua.load("assert(defaults:get_fact_cache().ttl == 86400)")
+            .exec()?;
+        assert!(
+            lua.load("defaults:set_fact_cache('/tmp/komandan-facts', 'soon')")
+                .exec()
+                .is_err()
+        );
+        lua.load("defaults:set_fact_cache(nil)").exec()?;
+        lua.load("assert(defaults:get_fact_cache() == nil)")
+            .exec()?;
+
         // Test environment variables
         lua.load("assert(defaults:get_env('TEST_ENV') == '')")
             .exec()?;
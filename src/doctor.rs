@@ -0,0 +1,364 @@
+use anyhow::{Result, bail};
+use mlua::{Lua, Table, Value};
+use ssh2::Session;
+use std::fmt;
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::args::DoctorArgs;
+use crate::connection::{Connection, create_connection, determine_connection_type, get_user};
+use crate::create_lua;
+use crate::models::ConnectionType;
+
+/// How long each network probe may block.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Free space in the remote tmpdir below which a warning is raised, in KiB.
+const MIN_TMP_FREE_KB: u64 = 100 * 1024;
+
+/// Outcome of a single diagnostic check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass => write!(f, "PASS"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Fail => write!(f, "FAIL"),
+            Self::Skip => write!(f, "SKIP"),
+        }
+    }
+}
+
+/// One line of the `komandan doctor` diagnosis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Handles the doctor command
+///
+/// Prints one line per check and fails if any check failed, so the command
+/// can gate onboarding scripts.
+///
+/// # Errors
+///
+/// Returns an error if the Lua state cannot be created or any check failed.
+pub fn handle_doctor_command(args: &DoctorArgs) -> Result<()> {
+    let lua = create_lua()?;
+    let host = doctor_host(&lua, args)?;
+
+    println!("Diagnosing {}:{} ...", args.host, args.port);
+    let results = run_checks(&lua, &host, &args.host, args.port);
+    for result in &results {
+        println!("[{}] {:<12} {}", result.status, result.name, result.detail);
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{failed} check(s) failed for host '{}'", args.host);
+    }
+    println!("All checks passed.");
+    Ok(())
+}
+
+/// Build the Lua host table `create_connection` expects from CLI arguments.
+fn doctor_host(lua: &Lua, args: &DoctorArgs) -> mlua::Result<Table> {
+    let host = lua.create_table()?;
+    host.set("address", args.host.as_str())?;
+    host.set("port", args.port)?;
+    if let Some(user) = &args.user {
+        host.set("user", user.as_str())?;
+    }
+    if let Some(private_key_file) = &args.private_key_file {
+        host.set("private_key_file", private_key_file.as_str())?;
+    }
+    Ok(host)
+}
+
+/// Run every check against `host`, skipping checks whose prerequisite failed.
+///
+/// Network checks (DNS, TCP, banner, auth methods) are skipped for hosts
+/// reached through a local connection.
+pub fn run_checks(lua: &Lua, host: &Table, address: &str, port: u16) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+
+    let is_local = matches!(determine_connection_type(host), Ok(ConnectionType::Local));
+    if is_local {
+        results.push(CheckResult::new(
+            "network",
+            CheckStatus::Skip,
+            "local connection, no SSH involved",
+        ));
+    } else if !network_checks(lua, host, address, port, &mut results) {
+        return results;
+    }
+
+    let connection = match create_connection(lua, &Value::Table(host.clone())) {
+        Ok(connection) => {
+            results.push(CheckResult::new(
+                "login",
+                CheckStatus::Pass,
+                "session opened",
+            ));
+            connection
+        }
+        Err(e) => {
+            results.push(CheckResult::new("login", CheckStatus::Fail, e.to_string()));
+            return results;
+        }
+    };
+
+    results.push(remote_shell_check(&connection));
+    results.push(remote_python_check(&connection));
+    results.push(remote_sudo_check(&connection));
+    results.push(remote_tmp_space_check(&connection));
+    results
+}
+
+/// Run DNS, TCP, banner and auth-method checks, returning whether the SSH
+/// port is reachable.
+fn network_checks(
+    lua: &Lua,
+    host: &Table,
+    address: &str,
+    port: u16,
+    results: &mut Vec<CheckResult>,
+) -> bool {
+    let addrs: Vec<SocketAddr> = match (address, port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => {
+            results.push(CheckResult::new("dns", CheckStatus::Fail, e.to_string()));
+            return false;
+        }
+    };
+    let Some(addr) = addrs.first().copied() else {
+        results.push(CheckResult::new(
+            "dns",
+            CheckStatus::Fail,
+            "name resolved to no addresses",
+        ));
+        return false;
+    };
+    let resolved = addrs
+        .iter()
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    results.push(CheckResult::new("dns", CheckStatus::Pass, resolved));
+
+    let mut stream = match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+        Ok(stream) => stream,
+        Err(e) => {
+            results.push(CheckResult::new(
+                "tcp",
+                CheckStatus::Fail,
+                format!("cannot connect to {addr}: {e}"),
+            ));
+            return false;
+        }
+    };
+    results.push(CheckResult::new(
+        "tcp",
+        CheckStatus::Pass,
+        format!("{addr} accepts connections"),
+    ));
+
+    results.push(banner_check(&mut stream));
+    results.push(auth_methods_check(lua, host, addr));
+    true
+}
+
+fn banner_check(stream: &mut TcpStream) -> CheckResult {
+    if let Err(e) = stream.set_read_timeout(Some(PROBE_TIMEOUT)) {
+        return CheckResult::new("banner", CheckStatus::Fail, e.to_string());
+    }
+    let mut buffer = [0_u8; 256];
+    match stream.read(&mut buffer) {
+        Ok(read) => parse_banner(&buffer[..read]).map_or_else(
+            || {
+                CheckResult::new(
+                    "banner",
+                    CheckStatus::Fail,
+                    "server did not send an SSH identification string",
+                )
+            },
+            |banner| CheckResult::new("banner", CheckStatus::Pass, banner),
+        ),
+        Err(e) => CheckResult::new("banner", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// Extract the `SSH-2.0-...` identification line from the first bytes sent
+/// by the server.
+fn parse_banner(bytes: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("SSH-"))
+        .map(ToString::to_string)
+}
+
+fn auth_methods_check(lua: &Lua, host: &Table, addr: SocketAddr) -> CheckResult {
+    let user = match lua.create_table().and_then(|task| get_user(host, &task)) {
+        Ok(user) => user,
+        Err(e) => return CheckResult::new("auth", CheckStatus::Fail, e.to_string()),
+    };
+
+    let methods = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)
+        .map_err(|e| e.to_string())
+        .and_then(|tcp| {
+            let mut session = Session::new().map_err(|e| e.to_string())?;
+            session.set_tcp_stream(tcp);
+            session.handshake().map_err(|e| e.to_string())?;
+            session
+                .auth_methods(&user)
+                .map(ToString::to_string)
+                .map_err(|e| e.to_string())
+        });
+
+    match methods {
+        Ok(methods) => CheckResult::new(
+            "auth",
+            CheckStatus::Pass,
+            format!("server offers {methods} for user '{user}'"),
+        ),
+        Err(e) => CheckResult::new("auth", CheckStatus::Fail, e),
+    }
+}
+
+fn remote_shell_check(connection: &Connection) -> CheckResult {
+    match connection.cmdq("command -v sh") {
+        Ok((stdout, _, 0)) => CheckResult::new("sh", CheckStatus::Pass, stdout.trim()),
+        Ok((_, stderr, code)) => CheckResult::new(
+            "sh",
+            CheckStatus::Fail,
+            format!("sh not found (exit {code}): {}", stderr.trim()),
+        ),
+        Err(e) => CheckResult::new("sh", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+fn remote_python_check(connection: &Connection) -> CheckResult {
+    match connection.cmdq("command -v python3 || command -v python") {
+        Ok((stdout, _, 0)) => CheckResult::new("python", CheckStatus::Pass, stdout.trim()),
+        Ok(_) => CheckResult::new(
+            "python",
+            CheckStatus::Warn,
+            "no python interpreter found; modules relying on it will fail",
+        ),
+        Err(e) => CheckResult::new("python", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+fn remote_sudo_check(connection: &Connection) -> CheckResult {
+    match connection.cmdq("command -v sudo >/dev/null || exit 127; sudo -n true") {
+        Ok((_, _, 0)) => CheckResult::new("sudo", CheckStatus::Pass, "passwordless sudo available"),
+        Ok((_, _, 127)) => CheckResult::new(
+            "sudo",
+            CheckStatus::Warn,
+            "sudo not installed; elevated tasks need another elevation_method",
+        ),
+        Ok(_) => CheckResult::new(
+            "sudo",
+            CheckStatus::Warn,
+            "sudo requires a password; elevated tasks will prompt or fail",
+        ),
+        Err(e) => CheckResult::new("sudo", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+fn remote_tmp_space_check(connection: &Connection) -> CheckResult {
+    match connection.cmdq("df -Pk \"${TMPDIR:-/tmp}\"") {
+        Ok((stdout, _, 0)) => match parse_df_available_kb(&stdout) {
+            Some(available) if available < MIN_TMP_FREE_KB => CheckResult::new(
+                "tmp space",
+                CheckStatus::Warn,
+                format!("only {} MiB free in tmpdir", available / 1024),
+            ),
+            Some(available) => CheckResult::new(
+                "tmp space",
+                CheckStatus::Pass,
+                format!("{} MiB free in tmpdir", available / 1024),
+            ),
+            None => CheckResult::new("tmp space", CheckStatus::Warn, "could not parse df output"),
+        },
+        Ok((_, stderr, code)) => CheckResult::new(
+            "tmp space",
+            CheckStatus::Warn,
+            format!("df failed (exit {code}): {}", stderr.trim()),
+        ),
+        Err(e) => CheckResult::new("tmp space", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// Read the "Available" column (KiB) from POSIX `df -Pk` output.
+fn parse_df_available_kb(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_banner() {
+        assert_eq!(
+            parse_banner(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n"),
+            Some("SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13".to_string())
+        );
+        assert_eq!(parse_banner(b"HTTP/1.1 400 Bad Request\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_df_available_kb() {
+        let output = "Filesystem     1024-blocks    Used Available Capacity Mounted on\n\
+                      /dev/sda1         41152736 8453120  30586080      22% /\n";
+        assert_eq!(parse_df_available_kb(output), Some(30_586_080));
+        assert_eq!(parse_df_available_kb("garbage"), None);
+    }
+
+    #[test]
+    fn test_run_checks_localhost() -> anyhow::Result<()> {
+        let lua = create_lua()?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+
+        let results = run_checks(&lua, &host, "localhost", 22);
+        assert_eq!(results[0].status, CheckStatus::Skip);
+        assert!(
+            results
+                .iter()
+                .any(|result| result.name == "sh" && result.status == CheckStatus::Pass)
+        );
+        Ok(())
+    }
+}
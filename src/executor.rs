@@ -1,4 +1,5 @@
 use std::path::Path;
+use std::time::Instant;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -28,12 +29,32 @@ pub trait CommandExecutor {
     /// Returns an error if the command execution fails or if there are issues reading the output.
     fn cmdq(&self, command: &str) -> Result<(String, String, i32)>;
 
+    /// Execute a command quietly, passing each stdout line to `on_line` as it
+    /// arrives instead of buffering the whole output
+    ///
+    /// Returns the command's exit code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be started or its output cannot be read.
+    fn cmd_stream(&self, command: &str, on_line: &mut dyn FnMut(&str)) -> Result<i32>;
+
     /// Prepare a command with elevation if needed
     fn prepare_command(&self, command: &str) -> String;
 
     /// Set an environment variable for command execution
     fn set_env(&mut self, key: &str, value: &str);
 
+    /// Unset an environment variable for command execution, including one
+    /// the login shell would otherwise provide
+    fn unset_env(&mut self, key: &str);
+
+    /// Bound every subsequent operation by a wall-clock deadline
+    ///
+    /// Operations started after the deadline fail immediately; operations in
+    /// flight are cut off when it passes. `None` removes the bound.
+    fn set_deadline(&mut self, deadline: Option<Instant>);
+
     /// Get an environment variable from the remote/local system
     ///
     /// # Errors
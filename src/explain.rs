@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{Context, Result};
+use mlua::{Lua, LuaSerdeExt, Table, Value};
+
+use crate::connection::resolve_environment;
+use crate::defaults::Defaults;
+use crate::run::task_list;
+use crate::util::host_display;
+
+/// One resolved setting of `komandan explain-host`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    pub name: String,
+    pub value: String,
+    /// Where the value came from, e.g. `task`, `host`, `group 'web'`,
+    /// `env KOMANDAN_SSH_USER` or `built-in default`.
+    pub source: String,
+}
+
+impl Setting {
+    fn new(name: impl Into<String>, value: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            source: source.into(),
+        }
+    }
+}
+
+/// Label a value taken from the defaults, which `komandan explain-host` only
+/// fills from `env_var` or the built-in value.
+fn defaults_source(env_var: &str) -> String {
+    if std::env::var_os(env_var).is_some() {
+        format!("env {env_var}")
+    } else {
+        "built-in default".to_string()
+    }
+}
+
+/// Shown in place of a secret.
+const MASKED: &str = "********";
+
+fn lock_error(name: &str) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("Failed to acquire read lock on {name}"))
+}
+
+/// The settings Komandan resolves when running `task` on `host`, in the
+/// order the connection code applies them, each with the layer it came
+/// from. Layers take precedence as task > host > group > defaults, and the
+/// defaults come from `KOMANDAN_*` environment variables or built-in values.
+///
+/// Secrets are shown masked.
+///
+/// # Errors
+///
+/// Returns an error if a setting has the wrong type or the defaults cannot
+/// be read.
+pub fn explain_host(lua: &Lua, host: &Table, task: Option<&Table>) -> mlua::Result<Vec<Setting>> {
+    let defaults = Defaults::global();
+    let mut settings = Vec::new();
+
+    let address = host.get::<String>("address")?;
+    settings.push(Setting::new("address", address.clone(), "host"));
+    settings.push(match host.get::<Option<String>>("connection")? {
+        Some(connection) => Setting::new("connection", connection, "host"),
+        None if matches!(address.as_str(), "localhost" | "127.0.0.1" | "::1") => {
+            Setting::new("connection", "local", "address")
+        }
+        None => Setting::new("connection", "ssh", "address"),
+    });
+
+    settings.push(match host.get::<Option<u16>>("port")? {
+        Some(port) => Setting::new("port", port.to_string(), "host"),
+        None => {
+            let port = *defaults.port.read().map_err(|_| lock_error("port"))?;
+            Setting::new(
+                "port",
+                port.to_string(),
+                defaults_source("KOMANDAN_SSH_PORT"),
+            )
+        }
+    });
+
+    let default_user = defaults
+        .user
+        .read()
+        .map_err(|_| lock_error("user"))?
+        .clone();
+    settings.push(match (host.get::<Option<String>>("user")?, default_user) {
+        (Some(user), _) => Setting::new("user", user, "host"),
+        (None, Some(user)) => Setting::new("user", user, defaults_source("KOMANDAN_SSH_USER")),
+        (None, None) => std::env::var("USER").map_or_else(
+            |_| Setting::new("user", "(none)", "unset"),
+            |user| Setting::new("user", user, "env USER"),
+        ),
+    });
+
+    settings.extend(auth_settings(host)?);
+
+    let default_key_check = *defaults
+        .key_check
+        .read()
+        .map_err(|_| lock_error("key_check"))?;
+    settings.push(host.get::<Option<bool>>("host_key_check")?.map_or_else(
+        || {
+            Setting::new(
+                "host_key_check",
+                default_key_check.to_string(),
+                defaults_source("KOMANDAN_SSH_HOST_KEY_CHECK"),
+            )
+        },
+        |check| Setting::new("host_key_check", check.to_string(), "host"),
+    ));
+    let default_known_hosts = defaults
+        .known_hosts_file
+        .read()
+        .map_err(|_| lock_error("known_hosts_file"))?
+        .clone();
+    settings.push(host.get::<Option<String>>("known_hosts_file")?.map_or_else(
+        || {
+            Setting::new(
+                "known_hosts_file",
+                default_known_hosts,
+                defaults_source("KOMANDAN_SSH_KNOWN_HOSTS_FILE"),
+            )
+        },
+        |file| Setting::new("known_hosts_file", file, "host"),
+    ));
+
+    let task_field = |name: &str| -> mlua::Result<Value> {
+        task.map_or(Ok(Value::Nil), |task| task.get::<Value>(name))
+    };
+    let default_elevate = *defaults.elevate.read().map_err(|_| lock_error("elevate"))?;
+    settings.push(layered(
+        "elevate",
+        &[
+            ("task", task_field("elevate")?),
+            ("host", host.get("elevate")?),
+        ],
+        default_elevate.to_string(),
+    )?);
+    let default_method = defaults
+        .elevation_method
+        .read()
+        .map_err(|_| lock_error("elevation_method"))?
+        .clone();
+    settings.push(layered(
+        "elevation_method",
+        &[
+            ("task", task_field("elevation_method")?),
+            ("host", host.get("elevation_method")?),
+        ],
+        default_method,
+    )?);
+    let default_as_user = defaults
+        .as_user
+        .read()
+        .map_err(|_| lock_error("as_user"))?
+        .clone();
+    settings.push(layered(
+        "as_user",
+        &[
+            ("task", task_field("as_user")?),
+            ("host", host.get("as_user")?),
+        ],
+        default_as_user.unwrap_or_else(|| "(none)".to_string()),
+    )?);
+
+    settings.extend(env_settings(host, task)?);
+    settings.extend(var_settings(lua, host, task)?);
+    Ok(settings)
+}
+
+/// The first of `layers` that is set, else `default` from the defaults.
+fn layered(name: &str, layers: &[(&str, Value)], default: String) -> mlua::Result<Setting> {
+    for (source, value) in layers {
+        match value {
+            Value::Nil => {}
+            Value::Boolean(value) => return Ok(Setting::new(name, value.to_string(), *source)),
+            Value::String(value) => {
+                return Ok(Setting::new(name, value.to_str()?.to_string(), *source));
+            }
+            other => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "{source} '{name}' must be a boolean or string, got {}",
+                    other.type_name()
+                )));
+            }
+        }
+    }
+    Ok(Setting::new(name, default, "defaults"))
+}
+
+/// Authentication, in the order `get_auth_config` tries it: private key of
+/// the host, then of the defaults, then password of the host, then of the
+/// defaults, then key discovery.
+fn auth_settings(host: &Table) -> mlua::Result<Vec<Setting>> {
+    let defaults = Defaults::global();
+    let default_key_file = defaults
+        .private_key_file
+        .read()
+        .map_err(|_| lock_error("private_key_file"))?
+        .clone();
+    let has_default_key_pass = defaults
+        .private_key_pass
+        .read()
+        .map_err(|_| lock_error("private_key_pass"))?
+        .is_some();
+    let has_default_password = defaults
+        .password
+        .read()
+        .map_err(|_| lock_error("password"))?
+        .is_some();
+
+    let key_file = host
+        .get::<Option<String>>("private_key_file")?
+        .map(|file| Setting::new("private_key_file", file, "host"))
+        .or_else(|| {
+            default_key_file.map(|file| {
+                Setting::new(
+                    "private_key_file",
+                    file,
+                    defaults_source("KOMANDAN_SSH_PRIVATE_KEY_FILE"),
+                )
+            })
+        });
+    let Some(key_file) = key_file else {
+        let password = if host.get::<Option<String>>("password")?.is_some() {
+            Setting::new("auth", "password", "host")
+        } else if has_default_password {
+            Setting::new("auth", "password", defaults_source("KOMANDAN_SSH_PASSWORD"))
+        } else if *defaults
+            .ssh_auto_discover_keys
+            .read()
+            .map_err(|_| lock_error("ssh_auto_discover_keys"))?
+        {
+            Setting::new(
+                "auth",
+                "~/.ssh/id_ed25519 or ~/.ssh/id_rsa",
+                "key discovery",
+            )
+        } else {
+            Setting::new("auth", "(none)", "unset")
+        };
+        return Ok(vec![password]);
+    };
+
+    let key_pass = if host.get::<Option<String>>("private_key_pass")?.is_some() {
+        Setting::new("private_key_pass", MASKED, "host")
+    } else if has_default_key_pass {
+        Setting::new(
+            "private_key_pass",
+            MASKED,
+            defaults_source("KOMANDAN_SSH_PRIVATE_KEY_PASS"),
+        )
+    } else {
+        Setting::new("private_key_pass", "(none)", "unset")
+    };
+    Ok(vec![
+        Setting::new("auth", "private key", key_file.source.clone()),
+        key_file,
+        key_pass,
+    ])
+}
+
+/// Environment variables set or unset for commands, per variable:
+/// task > host > defaults.
+fn env_settings(host: &Table, task: Option<&Table>) -> mlua::Result<Vec<Setting>> {
+    Ok(resolve_environment(host, task)?
+        .into_iter()
+        .map(|(key, var)| {
+            Setting::new(
+                format!("env.{key}"),
+                var.value.unwrap_or_else(|| "(unset)".to_string()),
+                var.source,
+            )
+        })
+        .collect())
+}
+
+/// Variables, per key, labelled with the layer of `resolve_vars` that set it.
+fn var_settings(lua: &Lua, host: &Table, task: Option<&Table>) -> mlua::Result<Vec<Setting>> {
+    let defaults = Defaults::global();
+    let mut vars: BTreeMap<String, (serde_json::Value, String)> = BTreeMap::new();
+    let mut merge = |layer: &serde_json::Value, source: &str| {
+        if let Some(layer) = layer.as_object() {
+            for (key, value) in layer {
+                vars.insert(key.clone(), (value.clone(), source.to_string()));
+            }
+        }
+    };
+
+    let default_vars = defaults
+        .vars
+        .read()
+        .map_err(|_| lock_error("vars"))?
+        .clone();
+    merge(&serde_json::Value::Object(default_vars), "defaults");
+    let group_vars = defaults
+        .group_vars
+        .read()
+        .map_err(|_| lock_error("group_vars"))?
+        .clone();
+    for group in host.get::<Option<Vec<String>>>("tags")?.unwrap_or_default() {
+        if let Some(layer) = group_vars.get(&group) {
+            merge(
+                &serde_json::Value::Object(layer.clone()),
+                &format!("group '{group}'"),
+            );
+        }
+    }
+    merge(&lua.from_value(host.get::<Value>("vars")?)?, "host");
+    if let Some(task) = task {
+        merge(&lua.from_value(task.get::<Value>("vars")?)?, "task");
+    }
+
+    Ok(vars
+        .into_iter()
+        .map(|(key, (value, source))| {
+            Setting::new(format!("vars.{key}"), value.to_string(), source)
+        })
+        .collect())
+}
+
+/// Print the effective settings of the host of `inventory` named `name`, or
+/// with that address, as the first task of `task_file` would see them.
+///
+/// # Errors
+///
+/// Returns an error if no host matches, the task file cannot be loaded or a
+/// setting is invalid.
+pub fn explain_inventory_host(
+    lua: &Lua,
+    inventory: &[serde_json::Value],
+    name: &str,
+    task_file: Option<&str>,
+) -> Result<()> {
+    let host = inventory
+        .iter()
+        .find(|host| {
+            ["name", "address"]
+                .iter()
+                .any(|field| host.get(field).and_then(serde_json::Value::as_str) == Some(name))
+        })
+        .with_context(|| format!("No host named '{name}' in the inventory"))?;
+    let host = match lua.to_value(host)? {
+        Value::Table(host) => host,
+        _ => anyhow::bail!("Host '{name}' is not a table"),
+    };
+
+    let task = match task_file {
+        Some(task_file) => {
+            let script = fs::read_to_string(task_file)
+                .with_context(|| format!("Failed to read the task file ({task_file})"))?;
+            let loaded = lua.load(&script).set_name(task_file).eval::<Table>()?;
+            task_list(loaded)?
+                .into_iter()
+                .next()
+                .and_then(|task| task.as_table().cloned())
+        }
+        None => None,
+    };
+
+    let settings = explain_host(lua, &host, task.as_ref())?;
+    print_settings(&host_display(&host), &settings);
+    Ok(())
+}
+
+/// Print `settings` of `host_display` as aligned columns.
+fn print_settings(host_display: &str, settings: &[Setting]) {
+    let width = settings
+        .iter()
+        .map(|setting| setting.name.len())
+        .max()
+        .unwrap_or_default();
+    let value_width = settings
+        .iter()
+        .map(|setting| setting.value.len())
+        .max()
+        .unwrap_or_default();
+    println!("Effective settings for {host_display}:");
+    for setting in settings {
+        println!(
+            "  {:<width$}  {:<value_width$}  [{}]",
+            setting.name, setting.value, setting.source
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::chunk;
+
+    fn find<'a>(settings: &'a [Setting], name: &str) -> Option<&'a Setting> {
+        settings.iter().find(|setting| setting.name == name)
+    }
+
+    #[test]
+    fn test_explain_host_sources() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let (host, task) = lua
+            .load(chunk! {
+                komandan.defaults:set_group_vars("test_explain_web", { tier = "web", port = 8080 })
+                local host = {
+                    name = "web1",
+                    address = "10.0.0.1",
+                    port = 2222,
+                    user = "deploy",
+                    private_key_file = "/keys/deploy",
+                    private_key_pass = "s3cret",
+                    elevate = true,
+                    tags = { "test_explain_web" },
+                    env = { APP_ENV = "prod" },
+                    env_unset = { "DEBIAN_FRONTEND" },
+                    vars = { port = 9090 },
+                }
+                local task = { elevation_method = "su", env = { APP_ENV = "staging" } }
+                return host, task
+            })
+            .eval::<(Table, Table)>()?;
+        let settings = explain_host(&lua, &host, Some(&task))?;
+
+        let value_source = |name: &str| {
+            find(&settings, name).map(|setting| (setting.value.as_str(), setting.source.as_str()))
+        };
+        assert_eq!(value_source("connection"), Some(("ssh", "address")));
+        assert_eq!(value_source("port"), Some(("2222", "host")));
+        assert_eq!(value_source("user"), Some(("deploy", "host")));
+        assert_eq!(value_source("auth"), Some(("private key", "host")));
+        assert_eq!(value_source("private_key_pass"), Some(("********", "host")));
+        assert_eq!(value_source("elevate"), Some(("true", "host")));
+        assert_eq!(value_source("elevation_method"), Some(("su", "task")));
+        assert_eq!(value_source("env.APP_ENV"), Some(("staging", "task")));
+        assert_eq!(value_source("env.LC_ALL"), Some(("C", "defaults")));
+        assert_eq!(
+            value_source("env.DEBIAN_FRONTEND"),
+            Some(("(unset)", "host"))
+        );
+        assert_eq!(value_source("vars.port"), Some(("9090", "host")));
+        assert_eq!(
+            value_source("vars.tier"),
+            Some(("\"web\"", "group 'test_explain_web'"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_host_password_and_localhost() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let host = lua
+            .load(chunk! { return { address = "localhost", password = "hunter2" } })
+            .eval::<Table>()?;
+        let settings = explain_host(&lua, &host, None)?;
+        let auth = find(&settings, "auth");
+        assert_eq!(
+            auth.map(|setting| (setting.value.as_str(), setting.source.as_str())),
+            Some(("password", "host"))
+        );
+        assert!(settings.iter().all(|setting| setting.value != "hunter2"));
+        assert_eq!(
+            find(&settings, "connection").map(|setting| setting.value.as_str()),
+            Some("local")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,558 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, SerializeOptions, Table, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::connection::{Connection, create_connection};
+use crate::defaults::Defaults;
+use crate::local::escape_shell_value;
+use crate::util::host_display;
+use crate::validator::validate_host;
+
+/// Prints one `key=value` line per fact; `ip_address`, `disk`, `interface`
+/// and `interface_address` may repeat.
+const FACTS_SCRIPT: &str = r#"[ -f /etc/os-release ] && . /etc/os-release
+if [ "$(uname -s)" = Darwin ]; then
+    ID=macos
+    VERSION_ID=$(sw_vers -productVersion 2>/dev/null)
+    PRETTY_NAME="macOS $VERSION_ID"
+fi
+echo "hostname=$(hostname 2>/dev/null || cat /etc/hostname 2>/dev/null)"
+echo "system=$(uname -s)"
+echo "kernel=$(uname -r)"
+echo "architecture=$(uname -m)"
+echo "distribution=$ID"
+echo "distribution_like=$ID_LIKE"
+echo "distribution_version=$VERSION_ID"
+echo "distribution_codename=$VERSION_CODENAME"
+echo "pretty_name=$PRETTY_NAME"
+echo "cpu_model=$(grep -m1 '^model name' /proc/cpuinfo 2>/dev/null | cut -d: -f2 | sed 's/^ *//')"
+echo "cpu_count=$(getconf _NPROCESSORS_ONLN 2>/dev/null || nproc 2>/dev/null)"
+if [ -r /proc/meminfo ]; then
+    echo "memory_kb=$(awk '/^MemTotal:/{print $2}' /proc/meminfo)"
+else
+    echo "memory_kb=$(( $(sysctl -n hw.memsize 2>/dev/null || echo 0) / 1024 ))"
+fi
+df -Pk / 2>/dev/null | awk 'NR == 2 {print "disk_total_kb=" $2; print "disk_available_kb=" $4}'
+if command -v ip >/dev/null 2>&1; then
+    ip -o addr show scope global 2>/dev/null | awk '{split($4, a, "/"); print "ip_address=" a[1]}'
+else
+    for address in $(hostname -I 2>/dev/null); do echo "ip_address=$address"; done
+fi
+lsblk -dbnro NAME,SIZE,TYPE 2>/dev/null | awk '$3 == "disk" {print "disk=" $1 " " $2}'
+if command -v ip >/dev/null 2>&1; then
+    ip -o link show 2>/dev/null | awk '{name = $2; sub(/:$/, "", name); sub(/@.*/, "", name); mac = ""; for (i = 3; i < NF; i++) if ($i == "link/ether") mac = $(i + 1); print "interface=" name " " mac}'
+    ip -o addr show 2>/dev/null | awk '{print "interface_address=" $2 " " $4}'
+fi
+virtualization=$(systemd-detect-virt 2>/dev/null)
+if [ -z "$virtualization" ]; then
+    if [ -f /.dockerenv ]; then
+        virtualization=docker
+    elif grep -q '^flags.* hypervisor' /proc/cpuinfo 2>/dev/null; then
+        virtualization=vm
+    elif [ -r /proc/cpuinfo ]; then
+        virtualization=none
+    fi
+fi
+echo "virtualization=$virtualization"
+if [ -d /run/systemd/system ]; then
+    echo init_system=systemd
+elif [ -d /run/openrc ] || command -v openrc >/dev/null 2>&1; then
+    echo init_system=openrc
+elif [ "$(uname -s)" = Darwin ]; then
+    echo init_system=launchd
+else
+    echo "init_system=$(cat /proc/1/comm 2>/dev/null)"
+fi
+exit 0"#;
+
+/// What `komandan.facts(host)` knows about a host. Unknown facts are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Facts {
+    pub hostname: Option<String>,
+    /// Kernel name as reported by `uname -s`, e.g. `Linux` or `Darwin`.
+    pub system: Option<String>,
+    pub kernel: Option<String>,
+    pub architecture: Option<String>,
+    /// Distribution family: `debian`, `redhat`, `suse`, `arch`, `alpine`,
+    /// `gentoo` or `darwin`, else the lowercase `system`.
+    pub os_family: Option<String>,
+    /// `ID` from `/etc/os-release`, e.g. `ubuntu`.
+    pub distribution: Option<String>,
+    pub distribution_version: Option<String>,
+    pub distribution_codename: Option<String>,
+    pub pretty_name: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpu_count: Option<u32>,
+    pub memory_mb: Option<u64>,
+    /// Size of the root filesystem.
+    pub disk_total_mb: Option<u64>,
+    /// Space left on the root filesystem for unprivileged users.
+    pub disk_available_mb: Option<u64>,
+    /// Global-scope addresses, IPv4 and IPv6.
+    pub ip_addresses: Vec<String>,
+    /// `systemd`, `openrc`, `launchd`, or the name of PID 1.
+    pub init_system: Option<String>,
+    /// As reported by `systemd-detect-virt`, e.g. `kvm` or `docker`; `vm`
+    /// for a hypervisor it could not name, `none` on bare metal.
+    pub virtualization: Option<String>,
+    /// Whole disks, without their partitions.
+    #[serde(default)]
+    pub disks: Vec<Disk>,
+    /// Network interfaces, including the loopback interface.
+    #[serde(default)]
+    pub interfaces: Vec<Interface>,
+}
+
+/// A disk of [`Facts::disks`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Disk {
+    /// Kernel name, e.g. `sda` or `nvme0n1`.
+    pub name: String,
+    pub size_mb: u64,
+}
+
+/// A network interface of [`Facts::interfaces`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interface {
+    pub name: String,
+    pub mac_address: Option<String>,
+    /// Addresses with their prefix length, e.g. `10.0.0.5/24`.
+    pub addresses: Vec<String>,
+}
+
+/// Command printing the facts of the host it runs on, for [`parse_facts`].
+/// A single `sh -c` invocation, so it works under sudo.
+#[must_use]
+pub fn facts_command() -> String {
+    format!("sh -c {}", escape_shell_value(FACTS_SCRIPT))
+}
+
+/// Parse the output of [`facts_command`].
+#[must_use]
+pub fn parse_facts(output: &str) -> Facts {
+    let mut facts = Facts::default();
+    let mut distribution_like = None;
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let text = Some(value.to_string());
+        match key {
+            "hostname" => facts.hostname = text,
+            "system" => facts.system = text,
+            "kernel" => facts.kernel = text,
+            "architecture" => facts.architecture = text,
+            "distribution" => facts.distribution = text,
+            "distribution_like" => distribution_like = text,
+            "distribution_version" => facts.distribution_version = text,
+            "distribution_codename" => facts.distribution_codename = text,
+            "pretty_name" => facts.pretty_name = text,
+            "cpu_model" => facts.cpu_model = text,
+            "cpu_count" => facts.cpu_count = value.parse().ok(),
+            // Zero when no source of the memory size was found.
+            "memory_kb" => facts.memory_mb = kb_to_mb(value).filter(|mb| *mb > 0),
+            "disk_total_kb" => facts.disk_total_mb = kb_to_mb(value),
+            "disk_available_kb" => facts.disk_available_mb = kb_to_mb(value),
+            "ip_address" => facts.ip_addresses.push(value.to_string()),
+            "init_system" => facts.init_system = text,
+            "virtualization" => facts.virtualization = text,
+            "disk" => {
+                if let Some((name, size)) = value.split_once(' ') {
+                    facts.disks.push(Disk {
+                        name: name.to_string(),
+                        size_mb: size.parse::<u64>().map_or(0, |bytes| bytes >> 20),
+                    });
+                }
+            }
+            "interface" => {
+                let (name, mac_address) = value.split_once(' ').unwrap_or((value, ""));
+                facts.interfaces.push(Interface {
+                    name: name.to_string(),
+                    mac_address: Some(mac_address.trim().to_string()).filter(|mac| !mac.is_empty()),
+                    addresses: Vec::new(),
+                });
+            }
+            "interface_address" => {
+                let interface = value.split_once(' ').and_then(|(name, address)| {
+                    let interface = facts.interfaces.iter_mut().find(|i| i.name == name)?;
+                    Some((interface, address))
+                });
+                if let Some((interface, address)) = interface {
+                    interface.addresses.push(address.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    facts.os_family = os_family(
+        facts.distribution.as_deref(),
+        distribution_like.as_deref(),
+        facts.system.as_deref(),
+    );
+    facts
+}
+
+/// Whole mebibytes in a kibibyte count.
+fn kb_to_mb(value: &str) -> Option<u64> {
+    value.parse::<u64>().ok().map(|kb| kb / 1024)
+}
+
+/// Family of a distribution from its os-release `ID` and `ID_LIKE`.
+fn os_family(id: Option<&str>, like: Option<&str>, system: Option<&str>) -> Option<String> {
+    let family = id
+        .into_iter()
+        .chain(like.into_iter().flat_map(str::split_whitespace))
+        .find_map(|id| match id {
+            "debian" | "ubuntu" | "raspbian" => Some("debian"),
+            "rhel" | "fedora" | "centos" | "rocky" | "almalinux" | "ol" | "amzn" => Some("redhat"),
+            "suse" | "opensuse" | "sles" => Some("suse"),
+            _ if id.starts_with("opensuse") => Some("suse"),
+            "arch" | "manjaro" => Some("arch"),
+            "alpine" => Some("alpine"),
+            "gentoo" => Some("gentoo"),
+            "macos" => Some("darwin"),
+            _ => None,
+        });
+    family
+        .map(ToString::to_string)
+        .or_else(|| system.map(str::to_lowercase))
+}
+
+/// Directory keeping gathered facts between runs, one `<host>.facts.json`
+/// file per host, set with `komandan.defaults:set_fact_cache(path, ttl)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FactCache {
+    pub path: PathBuf,
+    /// How long cached facts are used before the host is probed again.
+    pub ttl: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFacts {
+    /// Unix time in seconds.
+    gathered_at: u64,
+    facts: Facts,
+}
+
+/// Suffix of the cache files, so flushing leaves other files in the cache
+/// directory alone.
+const CACHE_FILE_SUFFIX: &str = ".facts.json";
+
+/// Whether [`FactCache::flush_once`] already ran in this process.
+static FLUSHED: AtomicBool = AtomicBool::new(false);
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl FactCache {
+    /// The configured cache, if any.
+    #[must_use]
+    pub fn global() -> Option<Self> {
+        Defaults::global()
+            .fact_cache
+            .read()
+            .ok()
+            .and_then(|cache| cache.clone())
+    }
+
+    /// Cache file of the host `address`, reached on `port` if given.
+    fn file(&self, address: &str, port: Option<u16>) -> PathBuf {
+        let mut name = address
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        if let Some(port) = port {
+            name.push_str(&format!("_{port}"));
+        }
+        self.path.join(format!("{name}{CACHE_FILE_SUFFIX}"))
+    }
+
+    /// Cached facts of a host, unless missing, unreadable or older than the TTL.
+    #[must_use]
+    pub fn load(&self, address: &str, port: Option<u16>) -> Option<Facts> {
+        let content = fs::read_to_string(self.file(address, port)).ok()?;
+        let cached = serde_json::from_str::<CachedFacts>(&content).ok()?;
+        let age = unix_now().saturating_sub(cached.gathered_at);
+        (age < self.ttl.as_secs()).then_some(cached.facts)
+    }
+
+    /// Save the facts of a host, replacing any cached ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory or file cannot be written.
+    pub fn store(&self, address: &str, port: Option<u16>, facts: &Facts) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.path)?;
+        let file = self.file(address, port);
+        let partial = file.with_extension("json.tmp");
+        let cached = CachedFacts {
+            gathered_at: unix_now(),
+            facts: facts.clone(),
+        };
+        fs::write(&partial, serde_json::to_vec_pretty(&cached)?)?;
+        fs::rename(&partial, &file)?;
+        Ok(())
+    }
+
+    /// Remove every cached host, returning how many were removed. Only the
+    /// cache files are removed, not the other files of the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read or a file
+    /// cannot be removed.
+    pub fn flush(&self) -> anyhow::Result<usize> {
+        if !self.path.is_dir() {
+            return Ok(0);
+        }
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .and_then(std::ffi::OsStr::to_str)
+                .is_some_and(|name| name.ends_with(CACHE_FILE_SUFFIX))
+            {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// [`Self::flush`] the first time it is called in this process, so
+    /// `--flush-facts` does not throw away facts gathered earlier in the run
+    /// when the cache is set again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be flushed.
+    pub fn flush_once(&self) -> anyhow::Result<usize> {
+        if FLUSHED.swap(true, Ordering::SeqCst) {
+            return Ok(0);
+        }
+        self.flush()
+    }
+}
+
+/// Facts of the host table `host` from the configured [`FactCache`], if fresh.
+#[must_use]
+pub fn cached_facts(host: &Table) -> Option<Facts> {
+    let address = host.get::<String>("address").ok()?;
+    let port = host.get::<Option<u16>>("port").ok().flatten();
+    FactCache::global()?.load(&address, port)
+}
+
+/// Save `facts` of the host table `host` in the configured [`FactCache`], if
+/// any. Failures are only logged, as the facts were gathered all the same.
+pub fn cache_facts(host: &Table, facts: &Facts) {
+    let Some(cache) = FactCache::global() else {
+        return;
+    };
+    let address = host.get::<String>("address").unwrap_or_default();
+    let port = host.get::<Option<u16>>("port").ok().flatten();
+    if let Err(e) = cache.store(&address, port, facts) {
+        tracing::warn!("Failed to cache facts of '{address}': {e}");
+    }
+}
+
+/// Run the facts probe on `connection`.
+///
+/// # Errors
+///
+/// Returns an error if the probe cannot be run or exits non-zero.
+pub fn gather(connection: &Connection) -> anyhow::Result<Facts> {
+    let (stdout, stderr, exit_code) = connection.cmdq(&facts_command())?;
+    if exit_code != 0 {
+        anyhow::bail!("facts probe exited with {exit_code}: {stderr}");
+    }
+    Ok(parse_facts(&stdout))
+}
+
+/// Convert `facts` to a Lua table; unknown facts are left out.
+///
+/// # Errors
+///
+/// Returns an error if the table cannot be created.
+pub fn facts_to_lua(lua: &Lua, facts: &Facts) -> mlua::Result<Value> {
+    lua.to_value_with(facts, SerializeOptions::new().serialize_none_to_null(false))
+}
+
+/// Lua binding: `komandan.facts(host)` connects to `host` (the local machine
+/// when `nil`) and returns its facts, see [`Facts`]. Facts in the
+/// [`FactCache`] are used instead while fresh.
+///
+/// # Errors
+///
+/// Returns an error if the host is invalid, the connection fails, or the
+/// probe cannot be run.
+pub fn lua_facts(lua: &Lua, host: Value) -> mlua::Result<Value> {
+    let host_table = if host.is_nil() {
+        let table = lua.create_table()?;
+        table.set("address", "localhost")?;
+        table
+    } else {
+        validate_host(lua, host)?
+    };
+    facts_to_lua(lua, &host_facts(lua, &host_table)?)
+}
+
+/// Facts of the host table `host`, taken from the [`FactCache`] while fresh.
+///
+/// # Errors
+///
+/// Returns an error if the connection fails or the probe cannot be run.
+pub fn host_facts(lua: &Lua, host: &Table) -> mlua::Result<Facts> {
+    if let Some(facts) = cached_facts(host) {
+        return Ok(facts);
+    }
+    let display = host_display(host);
+    let connection = create_connection(lua, &Value::Table(host.clone()))?;
+    let facts = gather(&connection)
+        .map_err(|e| RuntimeError(format!("Failed to gather facts from '{display}': {e}")))?;
+    connection.release();
+    cache_facts(host, &facts);
+    Ok(facts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_facts() {
+        let facts = parse_facts(
+            "hostname=web1\nsystem=Linux\nkernel=6.1.0-18-amd64\narchitecture=x86_64\n\
+             distribution=ubuntu\ndistribution_like=debian\ndistribution_version=24.04\n\
+             distribution_codename=noble\npretty_name=Ubuntu 24.04 LTS\ncpu_model=\n\
+             cpu_count=4\nmemory_kb=8167932\ndisk_total_kb=41152736\ndisk_available_kb=0\n\
+             ip_address=10.0.0.5\nip_address=fd00::5\n\
+             init_system=systemd\nvirtualization=kvm\n\
+             disk=vda 42949672960\ndisk=vdb 10737418240\n\
+             interface=lo\ninterface=eth0 52:54:00:12:34:56\n\
+             interface_address=lo 127.0.0.1/8\ninterface_address=eth0 10.0.0.5/24\n\
+             interface_address=eth0 fd00::5/64",
+        );
+        assert_eq!(facts.hostname.as_deref(), Some("web1"));
+        assert_eq!(facts.os_family.as_deref(), Some("debian"));
+        assert_eq!(facts.distribution_version.as_deref(), Some("24.04"));
+        assert_eq!(facts.cpu_model, None);
+        assert_eq!(facts.cpu_count, Some(4));
+        assert_eq!(facts.memory_mb, Some(7976));
+        assert_eq!(facts.disk_total_mb, Some(40188));
+        assert_eq!(facts.disk_available_mb, Some(0));
+        assert_eq!(facts.ip_addresses, vec!["10.0.0.5", "fd00::5"]);
+        assert_eq!(facts.init_system.as_deref(), Some("systemd"));
+        assert_eq!(facts.virtualization.as_deref(), Some("kvm"));
+        assert_eq!(
+            facts.disks,
+            [
+                Disk {
+                    name: "vda".to_string(),
+                    size_mb: 40960
+                },
+                Disk {
+                    name: "vdb".to_string(),
+                    size_mb: 10240
+                },
+            ]
+        );
+        assert_eq!(
+            facts.interfaces,
+            [
+                Interface {
+                    name: "lo".to_string(),
+                    mac_address: None,
+                    addresses: vec!["127.0.0.1/8".to_string()],
+                },
+                Interface {
+                    name: "eth0".to_string(),
+                    mac_address: Some("52:54:00:12:34:56".to_string()),
+                    addresses: vec!["10.0.0.5/24".to_string(), "fd00::5/64".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_os_family() {
+        assert_eq!(
+            os_family(Some("rocky"), Some("rhel centos fedora"), Some("Linux")).as_deref(),
+            Some("redhat")
+        );
+        assert_eq!(
+            os_family(Some("opensuse-leap"), Some("suse opensuse"), Some("Linux")).as_deref(),
+            Some("suse")
+        );
+        assert_eq!(
+            os_family(Some("pop"), Some("ubuntu debian"), Some("Linux")).as_deref(),
+            Some("debian")
+        );
+        assert_eq!(
+            os_family(None, None, Some("FreeBSD")).as_deref(),
+            Some("freebsd")
+        );
+    }
+
+    #[test]
+    fn test_fact_cache() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = FactCache {
+            path: dir.path().join("facts"),
+            ttl: Duration::from_secs(60),
+        };
+        let facts = parse_facts("hostname=web1\nsystem=Linux");
+        assert_eq!(cache.load("10.0.0.5", Some(2222)), None);
+
+        cache.store("10.0.0.5", Some(2222), &facts)?;
+        assert!(dir.path().join("facts/10.0.0.5_2222.facts.json").exists());
+        assert_eq!(cache.load("10.0.0.5", Some(2222)), Some(facts.clone()));
+        assert_eq!(cache.load("10.0.0.5", None), None);
+
+        let expired = FactCache {
+            ttl: Duration::ZERO,
+            ..cache.clone()
+        };
+        assert_eq!(expired.load("10.0.0.5", Some(2222)), None);
+
+        cache.store("fd00::5", None, &facts)?;
+        assert!(dir.path().join("facts/fd00__5.facts.json").exists());
+        fs::write(dir.path().join("facts/komandan.json"), "{}")?;
+        assert_eq!(cache.flush()?, 2);
+        assert!(dir.path().join("facts/komandan.json").exists());
+        assert_eq!(cache.load("fd00::5", None), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_facts_localhost() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let facts = lua_facts(&lua, Value::Nil)?;
+        let facts = facts
+            .as_table()
+            .ok_or_else(|| RuntimeError("facts is not a table".to_string()))?;
+        assert!(facts.get::<Option<String>>("system")?.is_some());
+        assert!(facts.get::<Option<String>>("kernel")?.is_some());
+        assert!(facts.get::<Table>("ip_addresses").is_ok());
+        Ok(())
+    }
+}
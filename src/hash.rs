@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use md5::Md5;
+use mlua::{Error::RuntimeError, Lua, String as LuaString, Table};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::modules::to_hex;
+
+/// Algorithms of `komandan.hash`, as named in Lua.
+const ALGORITHMS: [&str; 4] = ["md5", "sha1", "sha256", "sha512"];
+
+fn digest_file<D: Digest + io::Write>(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = D::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Lowercase hex digest of `data` with `algorithm`.
+fn hash_data(algorithm: &str, data: &[u8]) -> mlua::Result<String> {
+    Ok(match algorithm {
+        "md5" => to_hex(&Md5::digest(data)),
+        "sha1" => to_hex(&Sha1::digest(data)),
+        "sha256" => to_hex(&Sha256::digest(data)),
+        "sha512" => to_hex(&Sha512::digest(data)),
+        _ => return Err(unknown_algorithm(algorithm)),
+    })
+}
+
+/// Lowercase hex digest of the file at `path` with `algorithm`, read in
+/// chunks.
+fn hash_file(algorithm: &str, path: &Path) -> mlua::Result<String> {
+    match algorithm {
+        "md5" => digest_file::<Md5>(path),
+        "sha1" => digest_file::<Sha1>(path),
+        "sha256" => digest_file::<Sha256>(path),
+        "sha512" => digest_file::<Sha512>(path),
+        _ => return Err(unknown_algorithm(algorithm)),
+    }
+    .map_err(|e| RuntimeError(format!("{}: {e}", path.display())))
+}
+
+fn unknown_algorithm(algorithm: &str) -> mlua::Error {
+    RuntimeError(format!(
+        "Unknown hash algorithm: '{algorithm}'. Use one of: {}",
+        ALGORITHMS.join(", ")
+    ))
+}
+
+/// The `komandan.hash` table of lowercase hex digests computed on the
+/// control machine:
+///
+/// - `md5(data)`, `sha1(data)`, `sha256(data)` and `sha512(data)` hash a
+///   string
+/// - `file(path, algorithm)` hashes a local file (`algorithm` defaults to
+///   `"sha256"`)
+///
+/// `md5` and `sha1` are only meant for matching checksums published by
+/// others, not for anything security-sensitive.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn hash_table(lua: &Lua) -> mlua::Result<Table> {
+    let hash = lua.create_table()?;
+    for algorithm in ALGORITHMS {
+        hash.set(
+            algorithm,
+            lua.create_function(move |_, data: LuaString| hash_data(algorithm, &data.as_bytes()))?,
+        )?;
+    }
+    hash.set(
+        "file",
+        lua.create_function(|_, (path, algorithm): (String, Option<String>)| {
+            hash_file(algorithm.as_deref().unwrap_or("sha256"), Path::new(&path))
+        })?,
+    )?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::chunk;
+
+    use super::*;
+
+    #[test]
+    fn test_hash_data() -> mlua::Result<()> {
+        assert_eq!(hash_data("md5", b"")?, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hash_data("sha1", b"")?,
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            hash_data("sha256", b"abc")?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(hash_data("sha512", b"abc")?.starts_with("ddaf35a193617aba"));
+        assert!(hash_data("crc32", b"abc").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_file() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = dir.path().join("artifact.tar.gz");
+        std::fs::write(&path, "komandan").map_err(mlua::Error::external)?;
+        let path = path.to_string_lossy().to_string();
+
+        let lua = crate::create_lua()?;
+        let (sha256, md5, same) = lua
+            .load(chunk! {
+                return komandan.hash.file($path), komandan.hash.file($path, "md5"),
+                    komandan.hash.file($path, "sha1") == komandan.hash.sha1("komandan")
+            })
+            .eval::<(String, String, bool)>()?;
+        assert_eq!(sha256, hash_data("sha256", b"komandan")?);
+        assert_eq!(md5, hash_data("md5", b"komandan")?);
+        assert!(same);
+
+        let missing = dir.path().join("missing").to_string_lossy().to_string();
+        let result = lua
+            .load(chunk! {
+                return komandan.hash.file($missing)
+            })
+            .exec();
+        assert!(result.is_err());
+        Ok(())
+    }
+}
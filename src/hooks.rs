@@ -0,0 +1,452 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mlua::{Error::RuntimeError, Function, Lua, Table};
+
+use crate::report::TaskStatus;
+
+/// Lua registry key of the hooks registered through `komandan.hooks`.
+const LUA_HOOKS_KEY: &str = "komandan_hooks";
+
+/// Hook kinds, as named in `komandan.hooks.on_<kind>`.
+const HOOK_KINDS: [&str; 3] = ["task_start", "task_complete", "run_complete"];
+
+/// A task starting or finished on a host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskEvent {
+    /// Display name of the task.
+    pub task: String,
+    /// Display name of the host the task runs on.
+    pub host: String,
+    /// `ok`, `changed`, `failed`, `skipped`, `unreachable` or `rolled_back`
+    /// once the task finished; `None` when it starts.
+    pub status: Option<&'static str>,
+    /// Time the task took, once it finished.
+    pub duration: Option<Duration>,
+    /// Why the task failed.
+    pub error: Option<String>,
+}
+
+impl TaskEvent {
+    pub(crate) const fn new(task: String, host: String) -> Self {
+        Self {
+            task,
+            host,
+            status: None,
+            duration: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn finished(
+        &self,
+        status: &TaskStatus,
+        duration: Duration,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            status: Some(status_name(status)),
+            duration: Some(duration),
+            error,
+            ..self.clone()
+        }
+    }
+
+    fn to_lua(&self, lua: &Lua) -> mlua::Result<Table> {
+        let event = lua.create_table()?;
+        event.set("task", self.task.as_str())?;
+        event.set("host", self.host.as_str())?;
+        event.set("status", self.status)?;
+        event.set("duration", self.duration.map(|d| d.as_secs_f64()))?;
+        event.set("error", self.error.as_deref())?;
+        Ok(event)
+    }
+}
+
+/// Totals of a finished run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    /// Finished tasks per status, e.g. `{"changed": 3, "ok": 5}`.
+    pub counts: BTreeMap<&'static str, usize>,
+    /// Time since the first task started.
+    pub duration: Duration,
+    /// Why the run failed.
+    pub error: Option<String>,
+}
+
+impl RunSummary {
+    fn to_lua(&self, lua: &Lua) -> mlua::Result<Table> {
+        let summary = lua.create_table()?;
+        for (status, count) in &self.counts {
+            summary.set(*status, *count)?;
+        }
+        summary.set("duration", self.duration.as_secs_f64())?;
+        summary.set("error", self.error.as_deref())?;
+        Ok(summary)
+    }
+}
+
+/// Callbacks run at points of a Komandan run, e.g. for notifications,
+/// timing collectors or audit trails. Every method does nothing by default.
+///
+/// Hooks are called from the thread running the task, so with parallel
+/// runs from several threads at once.
+pub trait LifecycleHook: Send + Sync {
+    /// Called when a task starts on a host, before it connects.
+    fn on_task_start(&self, _event: &TaskEvent) {}
+    /// Called when a task finished on a host, whatever its status.
+    fn on_task_complete(&self, _event: &TaskEvent) {}
+    /// Called once the main script finished, with the run's totals.
+    fn on_run_complete(&self, _summary: &RunSummary) {}
+}
+
+static HOOKS: OnceLock<RwLock<Vec<Arc<dyn LifecycleHook>>>> = OnceLock::new();
+
+fn hooks() -> &'static RwLock<Vec<Arc<dyn LifecycleHook>>> {
+    HOOKS.get_or_init(|| RwLock::new(Vec::new()))
+}
+
+/// Register `hook` for the rest of the process.
+pub fn register_hook(hook: Arc<dyn LifecycleHook>) {
+    hooks()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(hook);
+}
+
+/// Remove `hook`, as passed to [`register_hook`]; other hooks stay.
+pub fn unregister_hook(hook: &Arc<dyn LifecycleHook>) {
+    hooks()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|registered| !Arc::ptr_eq(registered, hook));
+}
+
+fn rust_hooks() -> Vec<Arc<dyn LifecycleHook>> {
+    hooks()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Start time and status counts of the tasks run from one main Lua state,
+/// kept in its app data.
+#[derive(Default)]
+struct RunState {
+    started: Option<Instant>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+/// How often the main Lua state checks whether a parallel run finished
+/// while waiting for the events of its workers.
+const WORKER_EVENTS_POLL: Duration = Duration::from_millis(10);
+
+/// Sends the task events of a parallel worker's Lua state to the main Lua
+/// state that started the run. Set in the worker's app data while it runs
+/// a task of that run.
+#[derive(Clone)]
+pub(crate) struct WorkerEvents(Sender<(&'static str, TaskEvent)>);
+
+impl WorkerEvents {
+    /// Run `f` on the worker Lua state `inner`, forwarding the events of
+    /// the tasks it runs.
+    pub(crate) fn forward<R>(&self, inner: &Lua, f: impl FnOnce() -> R) -> R {
+        let previous = inner.set_app_data(self.clone());
+        let result = f();
+        match previous {
+            Some(previous) => inner.set_app_data(previous),
+            None => inner.remove_app_data::<Self>(),
+        };
+        result
+    }
+}
+
+/// Run the parallel work `run` on a helper thread, recording the events
+/// its workers forward in `lua` and passing them to its Lua hooks while
+/// the work is still going.
+///
+/// When `lua` is itself a worker, the events go on to its own main state.
+pub(crate) fn with_worker_events<R: Send>(
+    lua: &Lua,
+    run: impl FnOnce(&WorkerEvents) -> R + Send,
+) -> R {
+    let forwarded = lua
+        .app_data_ref::<WorkerEvents>()
+        .map(|events| WorkerEvents::clone(&events));
+    if let Some(events) = forwarded {
+        return run(&events);
+    }
+    let (sender, receiver) = mpsc::channel();
+    let events = WorkerEvents(sender);
+    thread::scope(|scope| {
+        let handle = scope.spawn(|| run(&events));
+        while !handle.is_finished() {
+            if let Ok((kind, event)) = receiver.recv_timeout(WORKER_EVENTS_POLL) {
+                receive(lua, kind, &event);
+            }
+        }
+        for (kind, event) in receiver.try_iter() {
+            receive(lua, kind, &event);
+        }
+        handle.join().unwrap_or_else(std::panic::resume_unwind)
+    })
+}
+
+const fn status_name(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::OK => "ok",
+        TaskStatus::Changed => "changed",
+        TaskStatus::Failed => "failed",
+        TaskStatus::Skipped => "skipped",
+        TaskStatus::Unreachable => "unreachable",
+        TaskStatus::RolledBack => "rolled_back",
+    }
+}
+
+/// The Lua hooks of `kind` registered in `lua`, if any hook was.
+fn lua_hooks(lua: &Lua, kind: &str) -> Option<Vec<Function>> {
+    let registered = lua
+        .named_registry_value::<Option<Table>>(LUA_HOOKS_KEY)
+        .ok()??;
+    let functions = registered.get::<Table>(kind).ok()?;
+    functions.sequence_values::<Function>().collect().ok()
+}
+
+fn call_lua_hooks(lua: &Lua, kind: &str, make_arg: impl Fn() -> mlua::Result<Table>) {
+    let Some(functions) = lua_hooks(lua, kind) else {
+        return;
+    };
+    for function in functions {
+        if let Err(e) = make_arg().and_then(|arg| function.call::<()>(arg)) {
+            tracing::warn!("on_{kind} hook failed: {e}");
+        }
+    }
+}
+
+/// Update the run state of `lua`, creating it on first use.
+fn update_run_state(lua: &Lua, update: impl FnOnce(&mut RunState)) {
+    if lua.app_data_ref::<RunState>().is_none() {
+        lua.set_app_data(RunState::default());
+    }
+    if let Some(mut state) = lua.app_data_mut::<RunState>() {
+        update(&mut state);
+    }
+}
+
+/// Forward `event` to the main Lua state when `lua` is running a task for
+/// a parallel run, or receive it in `lua` otherwise.
+fn deliver(lua: &Lua, kind: &'static str, event: &TaskEvent) {
+    if let Some(events) = lua.app_data_ref::<WorkerEvents>() {
+        let _ = events.0.send((kind, event.clone()));
+        return;
+    }
+    receive(lua, kind, event);
+}
+
+/// Record `event` in the run state of the main Lua state `lua` and call
+/// its Lua hooks.
+fn receive(lua: &Lua, kind: &'static str, event: &TaskEvent) {
+    update_run_state(lua, |state| {
+        state.started.get_or_insert_with(Instant::now);
+        if let Some(status) = event.status {
+            *state.counts.entry(status).or_default() += 1;
+        }
+    });
+    call_lua_hooks(lua, kind, || event.to_lua(lua));
+}
+
+pub(crate) fn task_start(lua: &Lua, event: &TaskEvent) {
+    for hook in rust_hooks() {
+        hook.on_task_start(event);
+    }
+    deliver(lua, "task_start", event);
+}
+
+pub(crate) fn task_complete(lua: &Lua, event: &TaskEvent) {
+    for hook in rust_hooks() {
+        hook.on_task_complete(event);
+    }
+    deliver(lua, "task_complete", event);
+}
+
+/// Call the run-complete hooks once the main script finished, with
+/// `error` when it failed.
+pub(crate) fn run_complete(lua: &Lua, error: Option<String>) {
+    let summary = {
+        let state = lua.remove_app_data::<RunState>().unwrap_or_default();
+        RunSummary {
+            counts: state.counts,
+            duration: state
+                .started
+                .map(|started| started.elapsed())
+                .unwrap_or_default(),
+            error,
+        }
+    };
+    for hook in rust_hooks() {
+        hook.on_run_complete(&summary);
+    }
+    call_lua_hooks(lua, "run_complete", || summary.to_lua(lua));
+}
+
+/// Register `function` as a Lua hook of `kind` in `lua`.
+fn register_lua_hook(lua: &Lua, kind: &str, function: Function) -> mlua::Result<()> {
+    let registered = match lua.named_registry_value::<Option<Table>>(LUA_HOOKS_KEY)? {
+        Some(registered) => registered,
+        None => {
+            let registered = lua.create_table()?;
+            for kind in HOOK_KINDS {
+                registered.set(kind, lua.create_table()?)?;
+            }
+            lua.set_named_registry_value(LUA_HOOKS_KEY, &registered)?;
+            registered
+        }
+    };
+    registered.get::<Table>(kind)?.push(function)?;
+    Ok(())
+}
+
+/// The `komandan.hooks` table: `on_task_start(fn)`, `on_task_complete(fn)`
+/// and `on_run_complete(fn)`.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn hooks_table(lua: &Lua) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    for kind in HOOK_KINDS {
+        table.set(
+            format!("on_{kind}"),
+            lua.create_function(move |lua, function: Option<Function>| {
+                let function = function
+                    .ok_or_else(|| RuntimeError(format!("on_{kind} expects a function")))?;
+                register_lua_hook(lua, kind, function)
+            })?,
+        )?;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::chunk;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl LifecycleHook for Recorder {
+        fn on_task_complete(&self, event: &TaskEvent) {
+            if event.task == "test_hooks_rust" {
+                self.0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(format!("{}:{}", event.host, event.status.unwrap_or("?")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rust_hook_sees_task_completion() -> mlua::Result<()> {
+        let recorder = Arc::new(Recorder::default());
+        let hook: Arc<dyn LifecycleHook> = recorder.clone();
+        register_hook(Arc::clone(&hook));
+        let lua = crate::create_lua()?;
+        let result = lua
+            .load(chunk! {
+                komandan.komando({ name = "test_hooks_rust", komandan.modules.cmd({ cmd = "true" }) })
+            })
+            .exec();
+        unregister_hook(&hook);
+        result?;
+        let seen = recorder
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        assert_eq!(seen, ["localhost:changed"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_hooks() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let events = lua
+            .load(chunk! {
+                local events = {}
+                komandan.hooks.on_task_start(function(event)
+                    if event.task == "test_hooks_lua" then
+                        table.insert(events, "start " .. event.host)
+                    end
+                end)
+                komandan.hooks.on_task_complete(function(event)
+                    if event.task == "test_hooks_lua" then
+                        table.insert(events, event.status .. " " .. tostring(event.error ~= nil))
+                    end
+                end)
+                komandan.hooks.on_task_complete(function() error("broken hook") end)
+                pcall(komandan.komando, { name = "test_hooks_lua", komandan.modules.cmd({ cmd = "false" }) })
+                return events
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(events, ["start localhost", "failed true"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_hooks_see_parallel_tasks_while_running() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let marker = dir.path().join("started").display().to_string();
+        let lua = crate::create_lua()?;
+        let (results, completed) = lua
+            .load(chunk! {
+                local completed = 0
+                komandan.hooks.on_task_start(function(event)
+                    if event.task == "first" then
+                        io.open($marker, "w"):close()
+                    end
+                end)
+                komandan.hooks.on_task_complete(function() completed = completed + 1 end)
+                local results = komandan.komando_parallel_tasks({
+                    { name = "first", komandan.modules.cmd({ cmd = "sleep 1" }) },
+                    {
+                        name = "second",
+                        komandan.modules.cmd({
+                            cmd = "for i in $(seq 50); do [ -f '" .. $marker .. "' ] && exit 0; sleep 0.1; done; exit 1",
+                        }),
+                    },
+                }, { address = "localhost" }, { forks = 2 })
+                return results, completed
+            })
+            .eval::<(Table, usize)>()?;
+        assert_eq!(results.len()?, 2);
+        assert_eq!(completed, 2);
+
+        let other = crate::create_lua()?;
+        other
+            .load(chunk! { komandan.komando({ komandan.modules.cmd({ cmd = "true" }) }) })
+            .exec()?;
+        let changed = |lua: &Lua| {
+            lua.app_data_ref::<RunState>()
+                .and_then(|state| state.counts.get("changed").copied())
+        };
+        assert_eq!(changed(&lua), Some(2));
+        assert_eq!(changed(&other), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_requires_function() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        assert!(
+            lua.load(chunk! { komandan.hooks.on_run_complete(42) })
+                .exec()
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, Table, Value};
+
+use crate::util::{HttpReply, HttpRequest, http_request};
+
+/// Seconds a request may take when `timeout` is not given.
+const DEFAULT_TIMEOUT: u64 = 30;
+
+/// Builds the request from the `options` of a `komandan.http` call:
+/// `headers`, `body` (a string, or a table sent as JSON), `auth`
+/// (`{ username = ..., password = ... }` for basic auth or `{ token = ... }`
+/// for a bearer token), `timeout` in seconds, `proxy`, `follow_redirects`
+/// and `validate_certs`. A non-nil `body` argument takes the place of the
+/// `body` option.
+fn build_request(
+    lua: &Lua,
+    method: &str,
+    url: String,
+    options: Option<&Table>,
+    body: Value,
+) -> mlua::Result<HttpRequest> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(RuntimeError(format!(
+            "Invalid url: '{url}'. Use an http:// or https:// URL"
+        )));
+    }
+    let method = method.to_uppercase();
+    if method.is_empty() || !method.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(RuntimeError(format!("Invalid method: '{method}'")));
+    }
+    let mut request = HttpRequest {
+        method,
+        url,
+        headers: Vec::new(),
+        body: None,
+        timeout: Some(Duration::from_secs(DEFAULT_TIMEOUT)),
+        proxy: None,
+        follow_redirects: true,
+        validate_certs: true,
+    };
+    let options = match options {
+        Some(options) => options.clone(),
+        None => lua.create_table()?,
+    };
+
+    let headers = options
+        .get::<Option<BTreeMap<String, String>>>("headers")?
+        .unwrap_or_default();
+    let has_header = |name: &str| headers.keys().any(|key| key.eq_ignore_ascii_case(name));
+    let mut extra_headers = Vec::new();
+    let body = if body.is_nil() {
+        options.get::<Value>("body")?
+    } else {
+        body
+    };
+    request.body = match body {
+        Value::Nil => None,
+        Value::String(body) => Some(body.as_bytes().to_vec()),
+        body @ Value::Table(_) => {
+            if !has_header("content-type") {
+                extra_headers.push(("Content-Type".to_string(), "application/json".to_string()));
+            }
+            let body = lua.from_value::<serde_json::Value>(body)?;
+            Some(body.to_string().into_bytes())
+        }
+        _ => {
+            return Err(RuntimeError(
+                "'body' option must be a string or a table".to_string(),
+            ));
+        }
+    };
+    if let Some(auth) = options.get::<Option<Table>>("auth")? {
+        let authorization = match (
+            auth.get::<Option<String>>("username")?,
+            auth.get::<Option<String>>("token")?,
+        ) {
+            (Some(username), None) => {
+                let password = auth.get::<Option<String>>("password")?.unwrap_or_default();
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{username}:{password}"))
+                )
+            }
+            (None, Some(token)) => format!("Bearer {token}"),
+            _ => {
+                return Err(RuntimeError(
+                    "'auth' option needs either 'username' (and 'password') or 'token'".to_string(),
+                ));
+            }
+        };
+        if !has_header("authorization") {
+            extra_headers.push(("Authorization".to_string(), authorization));
+        }
+    }
+    request.headers = headers.into_iter().chain(extra_headers).collect();
+    if request
+        .headers
+        .iter()
+        .any(|(name, value)| name.contains(['\r', '\n', ':']) || value.contains(['\r', '\n']))
+    {
+        return Err(RuntimeError(
+            "header names and values must be single lines".to_string(),
+        ));
+    }
+    if let Some(timeout) = options.get::<Option<f64>>("timeout")? {
+        if !timeout.is_finite() || timeout <= 0.0 {
+            return Err(RuntimeError(
+                "'timeout' option must be a positive number of seconds".to_string(),
+            ));
+        }
+        request.timeout = Some(Duration::from_secs_f64(timeout));
+    }
+    request.proxy = options.get::<Option<String>>("proxy")?;
+    request.follow_redirects = options
+        .get::<Option<bool>>("follow_redirects")?
+        .unwrap_or(true);
+    request.validate_certs = options
+        .get::<Option<bool>>("validate_certs")?
+        .unwrap_or(true);
+    Ok(request)
+}
+
+/// The Lua table returned for `reply`: `status`, `ok` (a 2xx status),
+/// `headers` (lower-cased names; repeated headers joined with `", "`),
+/// `body`, and `json` when the body is JSON.
+fn reply_table(lua: &Lua, reply: HttpReply) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("status", reply.status)?;
+    table.set("ok", (200..300).contains(&reply.status))?;
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in reply.headers {
+        headers
+            .entry(name.to_lowercase())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            })
+            .or_insert_with(|| value.clone());
+    }
+    table.set("headers", headers)?;
+    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&reply.body) {
+        table.set("json", lua.to_value(&json)?)?;
+    }
+    table.set("body", lua.create_string(&reply.body)?)?;
+    Ok(table)
+}
+
+fn send(lua: &Lua, request: &HttpRequest) -> mlua::Result<Table> {
+    let reply = http_request(request).map_err(RuntimeError)?;
+    reply_table(lua, reply)
+}
+
+/// Builds the `komandan.http` table for calling APIs from the controller:
+///
+/// - `get(url, options)` and `delete(url, options)`
+/// - `post(url, body, options)` and `put(url, body, options)`
+/// - `request({ method = "PATCH", url = ..., ... })` with the options as
+///   fields
+///
+/// Options are `headers`, `body`, `auth`, `timeout` (seconds, default 30),
+/// `proxy`, `follow_redirects` and `validate_certs`. Responses of any status
+/// are returned as `{ status, ok, headers, body, json }`; only failing to
+/// get a response raises an error.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn http_table(lua: &Lua) -> mlua::Result<Table> {
+    let http = lua.create_table()?;
+    for method in ["get", "delete"] {
+        http.set(
+            method,
+            lua.create_function(move |lua, (url, options): (String, Option<Table>)| {
+                send(
+                    lua,
+                    &build_request(lua, method, url, options.as_ref(), Value::Nil)?,
+                )
+            })?,
+        )?;
+    }
+    for method in ["post", "put"] {
+        http.set(
+            method,
+            lua.create_function(
+                move |lua, (url, body, options): (String, Value, Option<Table>)| {
+                    send(
+                        lua,
+                        &build_request(lua, method, url, options.as_ref(), body)?,
+                    )
+                },
+            )?,
+        )?;
+    }
+    http.set(
+        "request",
+        lua.create_function(|lua, options: Table| {
+            let method = options
+                .get::<Option<String>>("method")?
+                .unwrap_or_else(|| "GET".to_string());
+            let url = options
+                .get::<Option<String>>("url")?
+                .ok_or_else(|| RuntimeError("'url' option is required".to_string()))?;
+            send(
+                lua,
+                &build_request(lua, &method, url, Some(&options), Value::Nil)?,
+            )
+        })?,
+    )?;
+    Ok(http)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use mlua::chunk;
+
+    #[test]
+    fn test_build_request() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let options = lua
+            .load(chunk! {
+                return {
+                    headers = { ["X-Trace"] = "abc" }, body = { text = "deployed" },
+                    auth = { username = "ops", password = "secret" }, timeout = 5, follow_redirects = false,
+                }
+            })
+            .eval::<Table>()?;
+        let request = build_request(
+            &lua,
+            "post",
+            "https://hooks.example.com/deploy".to_string(),
+            Some(&options),
+            Value::Nil,
+        )?;
+        assert_eq!(request.method, "POST");
+        assert_eq!(
+            request.headers,
+            [
+                ("X-Trace".to_string(), "abc".to_string()),
+                ("Content-Type".to_string(), "application/json".to_string()),
+                (
+                    "Authorization".to_string(),
+                    "Basic b3BzOnNlY3JldA==".to_string()
+                ),
+            ]
+        );
+        assert_eq!(request.body, Some(b"{\"text\":\"deployed\"}".to_vec()));
+        assert_eq!(request.timeout, Some(Duration::from_secs(5)));
+        assert!(!request.follow_redirects);
+
+        assert!(
+            build_request(
+                &lua,
+                "GET",
+                "ftp://example.com".to_string(),
+                None,
+                Value::Nil
+            )
+            .is_err()
+        );
+        let options = lua.create_table()?;
+        options.set("auth", lua.create_table()?)?;
+        assert!(
+            build_request(
+                &lua,
+                "GET",
+                "http://example.com".to_string(),
+                Some(&options),
+                Value::Nil
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_post_to_local_server() -> mlua::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(mlua::Error::external)?;
+        let port = listener.local_addr().map_err(mlua::Error::external)?.port();
+        let server = std::thread::spawn(move || -> std::io::Result<String> {
+            let (mut stream, _) = listener.accept()?;
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            while !String::from_utf8_lossy(&request).contains("\"ok\"") {
+                let read = stream.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let body = "{\"id\":42}";
+            write!(
+                stream,
+                "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )?;
+            Ok(String::from_utf8_lossy(&request).to_string())
+        });
+
+        let lua = crate::create_lua()?;
+        let url = format!("http://127.0.0.1:{port}/items");
+        let (status, ok, id, cookies) = lua
+            .load(chunk! {
+                local response = komandan.http.post($url, { ok = true }, { auth = { token = "t0ken" } })
+                return response.status, response.ok, response.json.id, response.headers["set-cookie"]
+            })
+            .eval::<(u16, bool, i64, String)>()?;
+        let request = server
+            .join()
+            .map_err(|_| RuntimeError("server thread panicked".to_string()))?
+            .map_err(mlua::Error::external)?;
+        assert_eq!(status, 201);
+        assert!(ok);
+        assert_eq!(id, 42);
+        assert_eq!(cookies, "a=1, b=2");
+        assert!(request.starts_with("POST /items HTTP/1.1\r\n"));
+        assert!(
+            request
+                .to_lowercase()
+                .contains("authorization: bearer t0ken\r\n")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use mlua::{AnyUserData, Error::RuntimeError, Lua, Table};
+
+use crate::executor::CommandExecutor;
+use crate::local::escape_shell_value;
+
+/// Interpreters probed by `session:interpreter()` when no candidates are
+/// given, in order of preference.
+pub const DEFAULT_INTERPRETERS: &[&str] = &["python3", "perl", "sh", "busybox"];
+
+/// An interpreter found on the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interpreter {
+    /// Candidate name that matched, e.g. `python3` or `busybox`.
+    pub name: String,
+    /// Absolute path reported by `command -v`.
+    pub path: String,
+}
+
+impl Interpreter {
+    /// Command prefix that starts the interpreter; busybox needs its applet
+    /// name.
+    #[must_use]
+    pub fn command(&self) -> String {
+        let path = escape_shell_value(&self.path);
+        if self.name == "busybox" {
+            format!("{path} sh")
+        } else {
+            path
+        }
+    }
+
+    /// Command running `script` as an inline program of this interpreter.
+    #[must_use]
+    pub fn script_command(&self, script: &str) -> String {
+        let flag = match self.name.as_str() {
+            "perl" => "-e",
+            _ => "-c",
+        };
+        format!("{} {flag} {}", self.command(), escape_shell_value(script))
+    }
+}
+
+/// Find the first of `candidates` available on the target.
+///
+/// All candidates are probed with a single command, so detection costs one
+/// round trip regardless of how many interpreters are listed.
+///
+/// # Errors
+///
+/// Returns an error if a candidate is not a plain command name or the probe
+/// cannot be run.
+pub fn detect_interpreter<S: CommandExecutor>(
+    session: &S,
+    candidates: &[String],
+) -> mlua::Result<Option<Interpreter>> {
+    if let Some(invalid) = candidates.iter().find(|name| {
+        name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    }) {
+        return Err(RuntimeError(format!(
+            "invalid interpreter name '{invalid}': use letters, digits, '_', '-' or '.'"
+        )));
+    }
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    let script = format!(
+        "for c in {}; do p=$(command -v \"$c\" 2>/dev/null) && {{ echo \"$c $p\"; exit 0; }}; done; exit 1",
+        candidates.join(" ")
+    );
+    let (stdout, _, _) = session
+        .cmdq(&script)
+        .map_err(|e| RuntimeError(format!("failed to detect interpreter: {e}")))?;
+    Ok(parse_detect_output(&stdout))
+}
+
+/// Parse the `<name> <path>` line printed by the detection probe.
+fn parse_detect_output(output: &str) -> Option<Interpreter> {
+    let (name, path) = output.lines().next()?.trim().split_once(' ')?;
+    let path = path.trim();
+    if name.is_empty() || !path.starts_with('/') {
+        return None;
+    }
+    Some(Interpreter {
+        name: name.to_string(),
+        path: path.to_string(),
+    })
+}
+
+/// Lua method `session:interpreter(candidates)`: return
+/// `{ name, path, command }` for the first available interpreter in
+/// `candidates` (default [`DEFAULT_INTERPRETERS`]), or `nil` if none is
+/// installed.
+///
+/// # Errors
+///
+/// Returns an error if a candidate name is invalid or the probe fails.
+pub fn lua_interpreter<S>(
+    lua: &Lua,
+    (this, candidates): (AnyUserData, Option<Vec<String>>),
+) -> mlua::Result<Option<Table>>
+where
+    S: CommandExecutor + 'static,
+{
+    let candidates = candidates.unwrap_or_else(|| {
+        DEFAULT_INTERPRETERS
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    });
+    let session = this.borrow::<S>()?;
+    detect_interpreter(&*session, &candidates)?
+        .map(|interpreter| -> mlua::Result<Table> {
+            let table = lua.create_table()?;
+            table.set("command", interpreter.command())?;
+            table.set("name", interpreter.name)?;
+            table.set("path", interpreter.path)?;
+            Ok(table)
+        })
+        .transpose()
+}
+
+/// Lua method `session:run_script(interpreter, script)`: run `script` with an
+/// interpreter returned by `session:interpreter()`, tracking the output like
+/// `session:cmd()`.
+///
+/// # Errors
+///
+/// Returns an error if `interpreter` lacks `name`/`path` or the command
+/// cannot be run.
+pub fn lua_run_script<S>(
+    lua: &Lua,
+    (this, interpreter, script): (AnyUserData, Table, String),
+) -> mlua::Result<Table>
+where
+    S: CommandExecutor + 'static,
+{
+    let interpreter = Interpreter {
+        name: interpreter.get("name")?,
+        path: interpreter.get("path")?,
+    };
+    let mut session = this.borrow_mut::<S>()?;
+    let command = session.prepare_command(&interpreter.script_command(&script));
+    let (stdout, stderr, exit_code) = session.cmd(&command)?;
+
+    let table = lua.create_table()?;
+    table.set("stdout", stdout)?;
+    table.set("stderr", stderr)?;
+    table.set("exit_code", exit_code)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+    use crate::local::LocalSession;
+    use mlua::chunk;
+
+    #[test]
+    fn test_parse_detect_output() {
+        assert_eq!(
+            parse_detect_output("python3 /usr/bin/python3\n"),
+            Some(Interpreter {
+                name: "python3".to_string(),
+                path: "/usr/bin/python3".to_string(),
+            })
+        );
+        assert_eq!(parse_detect_output(""), None);
+        assert_eq!(parse_detect_output("sh sh"), None);
+    }
+
+    #[test]
+    fn test_script_command() {
+        let busybox = Interpreter {
+            name: "busybox".to_string(),
+            path: "/bin/busybox".to_string(),
+        };
+        assert_eq!(
+            busybox.script_command("echo hi"),
+            "'/bin/busybox' sh -c 'echo hi'"
+        );
+
+        let perl = Interpreter {
+            name: "perl".to_string(),
+            path: "/usr/bin/perl".to_string(),
+        };
+        assert_eq!(
+            perl.script_command("print 1"),
+            "'/usr/bin/perl' -e 'print 1'"
+        );
+    }
+
+    #[test]
+    fn test_detect_interpreter_prefers_first_available() -> mlua::Result<()> {
+        let session = LocalSession::new();
+        let candidates = vec!["komandan-no-such-interpreter".to_string(), "sh".to_string()];
+        let interpreter = detect_interpreter(&session, &candidates)?;
+        assert_eq!(interpreter.map(|i| i.name), Some("sh".to_string()));
+
+        let missing = vec!["komandan-no-such-interpreter".to_string()];
+        assert_eq!(detect_interpreter(&session, &missing)?, None);
+        assert!(detect_interpreter(&session, &["sh; rm".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_script_from_lua() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let session = LocalSession::new();
+        let output = lua
+            .load(chunk! {
+                local ssh = $session
+                local sh = ssh:interpreter({ "sh" })
+                return ssh:run_script(sh, "echo \"from $0\"").stdout
+            })
+            .eval::<String>()?;
+        assert!(output.starts_with("from"));
+        Ok(())
+    }
+}
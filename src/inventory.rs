@@ -0,0 +1,260 @@
+use anyhow::{Context, Result, bail};
+use mlua::{Lua, LuaSerdeExt, Table};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::args::{FactsArgs, InventoryFormat};
+use crate::create_lua;
+use crate::facts::{Facts, host_facts};
+use crate::models::KomandanConfig;
+use crate::util::host_display;
+use crate::validator::validate_host;
+
+/// Columns of the CSV inventory, in order.
+const CSV_COLUMNS: [&str; 20] = [
+    "host",
+    "address",
+    "hostname",
+    "os_family",
+    "distribution",
+    "distribution_version",
+    "distribution_codename",
+    "pretty_name",
+    "system",
+    "kernel",
+    "architecture",
+    "cpu_model",
+    "cpu_count",
+    "memory_mb",
+    "disk_total_mb",
+    "disk_available_mb",
+    "ip_addresses",
+    "init_system",
+    "virtualization",
+    "error",
+];
+
+/// Facts of one host in the inventory; `error` is set instead when they
+/// could not be gathered.
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryRecord {
+    pub host: String,
+    pub address: String,
+    #[serde(flatten)]
+    pub facts: Facts,
+    pub error: Option<String>,
+}
+
+/// Handles the facts command
+///
+/// Gathers facts from every host in parallel and writes one inventory. Hosts
+/// that cannot be reached are kept in it with their error.
+///
+/// # Errors
+///
+/// Returns an error if the hosts cannot be loaded, the inventory cannot be
+/// written, or facts could not be gathered from some host.
+pub fn handle_facts_command(args: &FactsArgs) -> Result<()> {
+    let hosts = if args.all {
+        project_hosts(Path::new(&args.project))?
+    } else {
+        args.hosts
+            .iter()
+            .map(|address| serde_json::json!({ "address": address }))
+            .collect()
+    };
+
+    let records = hosts.par_iter().map(gather_record).collect::<Vec<_>>();
+
+    let inventory = match args.format {
+        InventoryFormat::Json => serde_json::to_string_pretty(&records)? + "\n",
+        InventoryFormat::Csv => to_csv(&records)?,
+    };
+    match &args.output {
+        Some(output) => fs::write(output, inventory)
+            .with_context(|| format!("Failed to write inventory to {output}"))?,
+        None => print!("{inventory}"),
+    }
+
+    let mut failed = 0;
+    for record in &records {
+        if let Some(error) = &record.error {
+            eprintln!("Failed to gather facts from '{}': {error}", record.host);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "could not gather facts from {failed} of {} host(s)",
+            records.len()
+        );
+    }
+    Ok(())
+}
+
+/// Hosts listed in the hosts file named by the project's `komandan.json`.
+///
+/// # Errors
+///
+/// Returns an error if the config is missing or names no hosts file, or the
+/// hosts file cannot be evaluated.
+fn project_hosts(project_dir: &Path) -> Result<Vec<serde_json::Value>> {
+    let config_path = project_dir.join("komandan.json");
+    let config: KomandanConfig = serde_json::from_str(
+        &fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read {}", config_path.display()))?,
+    )
+    .with_context(|| format!("Failed to parse {}", config_path.display()))?;
+    let Some(hosts_file) = config.defaults.hosts else {
+        bail!(
+            "{} does not name a hosts file in defaults.hosts",
+            config_path.display()
+        );
+    };
+
+    let hosts_path = project_dir.join(hosts_file);
+    let lua = Lua::new();
+    let hosts = lua
+        .load(&crate::vault::reveal(
+            &fs::read_to_string(&hosts_path)
+                .with_context(|| format!("Failed to read hosts file {}", hosts_path.display()))?,
+        )?)
+        .set_name(hosts_path.to_string_lossy())
+        .eval::<Table>()?;
+    let mut result = Vec::new();
+    for pair in hosts.pairs::<mlua::Value, mlua::Value>() {
+        let (_, host) = pair?;
+        result.push(lua.from_value(host)?);
+    }
+    Ok(result)
+}
+
+/// Gather the facts of `host` in a Lua state of its own.
+fn gather_record(host: &serde_json::Value) -> InventoryRecord {
+    let address = host
+        .get("address")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let mut record = InventoryRecord {
+        host: address.clone(),
+        address,
+        facts: Facts::default(),
+        error: None,
+    };
+    let gathered = create_lua().and_then(|lua| {
+        let host = validate_host(&lua, lua.to_value(host)?)?;
+        record.host = host_display(&host);
+        host_facts(&lua, &host)
+    });
+    match gathered {
+        Ok(facts) => record.facts = facts,
+        Err(e) => record.error = Some(e.to_string()),
+    }
+    record
+}
+
+/// Render `records` as CSV with a header row; lists are space-separated.
+///
+/// # Errors
+///
+/// Returns an error if a record cannot be serialized.
+pub fn to_csv(records: &[InventoryRecord]) -> Result<String> {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push('\n');
+    for record in records {
+        let value = serde_json::to_value(record)?;
+        let row = CSV_COLUMNS
+            .iter()
+            .map(|column| csv_field(value.get(column)))
+            .collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+fn csv_field(value: Option<&serde_json::Value>) -> String {
+    let text = match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map_or_else(|| item.to_string(), ToString::to_string)
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        Some(other) => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_csv() -> Result<()> {
+        let records = vec![
+            InventoryRecord {
+                host: "web1 (10.0.0.5)".to_string(),
+                address: "10.0.0.5".to_string(),
+                facts: Facts {
+                    cpu_model: Some("Xeon \"Gold\", 2GHz".to_string()),
+                    cpu_count: Some(4),
+                    ip_addresses: vec!["10.0.0.5".to_string(), "fd00::5".to_string()],
+                    ..Facts::default()
+                },
+                error: None,
+            },
+            InventoryRecord {
+                host: "db1".to_string(),
+                address: "db1".to_string(),
+                facts: Facts::default(),
+                error: Some("connection refused".to_string()),
+            },
+        ];
+        let csv = to_csv(&records)?;
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], CSV_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "web1 (10.0.0.5),10.0.0.5,,,,,,,,,,\"Xeon \"\"Gold\"\", 2GHz\",4,,,,10.0.0.5 fd00::5,,,"
+        );
+        assert_eq!(lines[2], "db1,db1,,,,,,,,,,,,,,,,,,connection refused");
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_hosts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("komandan.json"),
+            r#"{ "name": "test", "version": "0.1.0", "main": "main.lua", "defaults": { "hosts": "hosts.lua" } }"#,
+        )?;
+        fs::write(
+            dir.path().join("hosts.lua"),
+            r#"return { { address = "10.0.0.5", name = "web1" }, db = { address = "10.0.0.6" } }"#,
+        )?;
+        let hosts = project_hosts(dir.path())?;
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0]["name"], "web1");
+        assert_eq!(hosts[1]["address"], "10.0.0.6");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gather_record_localhost() {
+        let record = gather_record(&serde_json::json!({ "address": "localhost" }));
+        assert_eq!(record.error, None);
+        assert!(record.facts.system.is_some());
+    }
+}
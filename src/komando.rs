@@ -1,17 +1,32 @@
 use std::cell::OnceCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-use mlua::{Error::RuntimeError, FromLua, Integer, Lua, Table, Value};
-use mlua::{IntoLua, LuaSerdeExt, chunk};
+use mlua::{DeserializeOptions, IntoLua, LuaSerdeExt, chunk};
+use mlua::{Error::RuntimeError, FromLua, Function, Integer, Lua, Table, Value};
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
+use crate::async_job::{AsyncSpec, run_async_task};
 use crate::connection::{Connection, create_connection};
 use crate::create_lua;
 use crate::defaults::Defaults;
+use crate::hooks::{self, TaskEvent};
+use crate::local::escape_shell_value;
 use crate::models::{Host, KomandoResult, Task};
+use crate::output::{self, OutputMode};
+use crate::profile::{self, Phase, TaskProfile};
+use crate::recorder;
 use crate::report::{TaskStatus, insert_record};
-use crate::util::{host_display, task_display};
+use crate::start_at;
+use crate::step;
+use crate::task_graph;
+use crate::util::{duration_param, host_display, task_display};
 use crate::validator::{validate_host, validate_task};
+use crate::vars::resolve_vars;
 
 /// Execute a task on a host using the centralized connection factory
 ///
@@ -48,26 +63,137 @@ pub fn komando(lua: &Lua, (task, host): (Value, Value)) -> mlua::Result<Table> {
         )
     };
 
+    let event = TaskEvent::new(task_display(&task), host_display(&host));
+    hooks::task_start(lua, &event);
+    let started = Instant::now();
+    match run_task(lua, &task, &host) {
+        Ok((result, status)) => {
+            crate::state::record_task(lua, &task, &host, &status)?;
+            hooks::task_complete(lua, &event.finished(&status, started.elapsed(), None));
+            Ok(result)
+        }
+        Err(e) => {
+            crate::state::record_task(lua, &task, &host, &TaskStatus::Failed)?;
+            let error = Some(e.to_string());
+            let event = event.finished(&TaskStatus::Failed, started.elapsed(), error);
+            hooks::task_complete(lua, &event);
+            Err(e)
+        }
+    }
+}
+
+/// Run a validated `task` on `host` and return its result and status.
+///
+/// # Errors
+///
+/// Returns an error if the task cannot be run or fails.
+fn run_task(lua: &Lua, task: &Table, host: &Table) -> mlua::Result<(Table, TaskStatus)> {
+    let (task, host) = (task.clone(), host.clone());
     let module = task.get::<Table>(1)?;
+    module.set("vars", resolve_vars(lua, &host, Some(&task))?)?;
 
-    let host_display = host_display(&host);
+    let target = delegate_target(lua, &task)?;
+    let host_display = match &target {
+        Some(target) => format!(
+            "{} (delegated to {})",
+            host_display(&host),
+            host_display(target)
+        ),
+        None => host_display(&host),
+    };
     let task_display = task_display(&task);
+    output::set_prefix(&crate::util::host_display(&host));
+
+    if let Some(reason) = skip_reason(&task, &host, &task_display, &host_display)? {
+        return skip_task(lua, task_display, host_display, reason)
+            .map(|result| (result, TaskStatus::Skipped));
+    }
+    recorder::record_task(&task, &host, &module)?;
+
+    let _profile = TaskProfile::begin(&task_display, &host_display);
+    let timeout = task_timeout(&task)?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
 
     // Use centralized connection creation
-    let connection = create_connection(lua, &Value::Table(host))?;
+    let connection_host = target.unwrap_or_else(|| host.clone());
+    let mut connection = profile::time(Phase::Connect, "", || {
+        create_connection(lua, &Value::Table(connection_host))
+    })?;
+    connection.set_deadline(deadline);
+
+    match guard_reason(&task, &connection) {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            connection.release();
+            return skip_task(lua, task_display, host_display, &reason)
+                .map(|result| (result, TaskStatus::Skipped));
+        }
+        Err(e) => {
+            connection.abort("task failed");
+            return Err(e);
+        }
+    }
 
-    let result = match connection {
-        Connection::Local(local) => execute_task(
+    let run = TaskRun {
+        module: &module,
+        host: &host,
+        task_display: &task_display,
+        host_display: &host_display,
+    };
+    let outcome = match (AsyncSpec::from_task(&task)?, connection.clone()) {
+        (Some(spec), _) => run_async_task(
             lua,
             &module,
-            local,
+            &connection,
+            spec,
             &task_display,
             &host_display,
-            " (local)",
-        )?,
-        Connection::SSH(ssh) => execute_task(lua, &module, ssh, &task_display, &host_display, "")?,
+        ),
+        (None, Connection::Local(local)) => execute_task(lua, &run, local, " (local)"),
+        (None, Connection::SSH(ssh)) => execute_task(lua, &run, ssh, ""),
+    };
+
+    let result = match (outcome, timeout, deadline) {
+        (Ok(result), _, _) => result,
+        (Err(e), Some(timeout), Some(deadline)) if Instant::now() >= deadline => {
+            connection.abort("task timeout");
+            let message = format!(
+                "Task '{task_display}' on host '{host_display}' timed out after {}s: {e}",
+                timeout.as_secs_f64()
+            );
+            output::emit(&format!(">> {message}"));
+            if !crate::args::global_flags().no_report {
+                insert_record(task_display, host_display, TaskStatus::Failed);
+            }
+            return Err(RuntimeError(message));
+        }
+        (Err(e), _, _) => {
+            // The error may have come from the transport, e.g. a libssh2
+            // timeout or a dropped socket, so the session is not pooled.
+            connection.abort("task failed");
+            return Err(e);
+        }
     };
+    connection.release();
+
+    let task_status = task_status(&task, &result)?;
+    if task_status == TaskStatus::Changed {
+        crate::report::mark_changed();
+    }
+    if !crate::args::global_flags().no_report {
+        insert_record(task_display, host_display, task_status.clone());
+    }
+
+    Ok((result, task_status))
+}
 
+/// Status of a finished task, after applying `changed_when` / `failed_when`.
+///
+/// # Errors
+///
+/// Returns an error if the task failed and `ignore_exit_code` is not set,
+/// or if the overrides cannot be evaluated.
+fn task_status(task: &Table, result: &Table) -> mlua::Result<TaskStatus> {
     let defaults = Defaults::global();
     let default_ignore_exit_code = match defaults.ignore_exit_code.read() {
         Ok(ignore_exit_code) => *ignore_exit_code,
@@ -78,43 +204,238 @@ pub fn komando(lua: &Lua, (task, host): (Value, Value)) -> mlua::Result<Table> {
         .get::<bool>("ignore_exit_code")
         .unwrap_or(default_ignore_exit_code);
 
-    let exit_code = result.get::<Integer>("exit_code")?;
+    let failed = apply_result_overrides(task, result)?;
 
-    if exit_code != 0 && !ignore_exit_code {
+    if failed && !ignore_exit_code {
         return Err(RuntimeError("Failed to run task.".to_string()));
     }
 
-    let task_status = if exit_code != 0 {
+    Ok(if failed {
         TaskStatus::Failed
     } else if result.get::<bool>("changed")? {
         TaskStatus::Changed
     } else {
         TaskStatus::OK
-    };
+    })
+}
 
+/// Why the task's `creates` / `unless` guard says its work is already done,
+/// checked on the host the task runs on.
+///
+/// # Errors
+///
+/// Returns an error if a guard command cannot be run.
+fn guard_reason(task: &Table, connection: &Connection) -> mlua::Result<Option<String>> {
+    if let Some(path) = task.get::<Option<String>>("creates")? {
+        let command = format!("test -e {}", escape_shell_value(&path));
+        let (_, _, exit_code) = connection.cmdq(&connection.prepare_command(&command))?;
+        if exit_code == 0 {
+            return Ok(Some(format!("creates: '{path}' exists")));
+        }
+    }
+    if let Some(unless) = task.get::<Option<String>>("unless")? {
+        let command = format!("sh -c {}", escape_shell_value(&unless));
+        let (_, _, exit_code) = connection.cmdq(&connection.prepare_command(&command))?;
+        if exit_code == 0 {
+            return Ok(Some(format!("unless: '{unless}' succeeded")));
+        }
+    }
+    Ok(None)
+}
+
+/// Report a task that is not run and return its result.
+///
+/// # Errors
+///
+/// Returns an error if the result table cannot be created.
+fn skip_task(
+    lua: &Lua,
+    task_display: String,
+    host_display: String,
+    reason: &str,
+) -> mlua::Result<Table> {
+    output::emit(&format!(
+        ">> Skipping task '{task_display}' on host '{host_display}' ({reason})"
+    ));
     if !crate::args::global_flags().no_report {
-        insert_record(task_display, host_display, task_status);
+        insert_record(task_display, host_display, TaskStatus::Skipped);
     }
+    skipped_result(lua)
+}
 
+/// Result of a task that was not run.
+///
+/// # Errors
+///
+/// Returns an error if the table cannot be created.
+fn skipped_result(lua: &Lua) -> mlua::Result<Table> {
+    let result = lua.create_table()?;
+    result.set("stdout", "")?;
+    result.set("stderr", "")?;
+    result.set("exit_code", 0)?;
+    result.set("changed", false)?;
+    result.set("skipped", true)?;
     Ok(result)
 }
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+/// Resolve the task's optional `delegate_to` into the host table the task
+/// should connect to instead of the loop host.
+///
+/// `"localhost"` forces a local connection; any other string is used as an
+/// address; a table is validated like any host.
+///
+/// # Errors
+///
+/// Returns an error if `delegate_to` is neither a string nor a valid host.
+fn delegate_target(lua: &Lua, task: &Table) -> mlua::Result<Option<Table>> {
+    match task.get::<Value>("delegate_to")? {
+        Value::Nil => Ok(None),
+        Value::String(address) if address.to_str()? == "localhost" => local_host(lua).map(Some),
+        Value::String(address) => {
+            let host = lua.create_table()?;
+            host.set("address", address)?;
+            Ok(Some(host))
+        }
+        delegate @ Value::Table(_) => validate_host(lua, delegate).map(Some),
+        _ => Err(RuntimeError(
+            "Task delegate_to must be a host table or an address.".to_string(),
+        )),
+    }
+}
+
+/// Run `task` on the control machine through a `LocalSession`, whatever host
+/// the surrounding script is working on (e.g. build an artifact locally, then
+/// upload it). The run is reported under "localhost".
+///
+/// # Errors
+///
+/// Returns the same errors as [`komando`].
+pub fn komando_local(lua: &Lua, task: Value) -> mlua::Result<Table> {
+    komando(lua, (task, Value::Table(local_host(lua)?)))
+}
+
+/// Host table that always resolves to a local connection.
+fn local_host(lua: &Lua) -> mlua::Result<Table> {
+    let host = lua.create_table()?;
+    host.set("address", "localhost")?;
+    host.set("connection", "local")?;
+    Ok(host)
+}
+
+/// Why the task is skipped before connecting to the host: it comes before
+/// the `--start-at-task` task, is filtered out by `--tags` / `--skip-tags`,
+/// or is declined under `--step`.
+///
+/// # Errors
+///
+/// Returns an error if the task's tags are invalid or the `--step` answer
+/// cannot be read.
+fn skip_reason(
+    task: &Table,
+    host: &Table,
+    task_display: &str,
+    host_display: &str,
+) -> mlua::Result<Option<&'static str>> {
+    let flags = crate::args::global_flags();
+    if let Some(start_at) = &flags.start_at_task
+        && start_at::before_start_task(start_at, task_display, &crate::util::host_display(host))
+    {
+        return Ok(Some("before --start-at-task"));
+    }
+    let task_tags = task.get::<Option<Vec<String>>>("tags")?.unwrap_or_default();
+    if !tags_selected(&task_tags, &flags.tags, &flags.skip_tags) {
+        return Ok(Some("tags"));
+    }
+    if flags.step && !step::confirm_step(task_display, host_display)? {
+        return Ok(Some("declined in step mode"));
+    }
+    Ok(None)
+}
+
+/// Decide whether a task with `task_tags` runs under the `--tags` /
+/// `--skip-tags` filter.
+///
+/// A task carrying any skipped tag never runs. Otherwise, an empty `--tags`
+/// list selects every task, and a non-empty one selects tasks sharing at
+/// least one tag with it.
+fn tags_selected(task_tags: &[String], tags: &[String], skip_tags: &[String]) -> bool {
+    if task_tags.iter().any(|tag| skip_tags.contains(tag)) {
+        return false;
+    }
+    tags.is_empty() || task_tags.iter().any(|tag| tags.contains(tag))
+}
+
+/// Read the task's optional `timeout` (seconds, or a duration string such
+/// as `"5m"`) as a `Duration`.
+///
+/// # Errors
+///
+/// Returns an error if `timeout` is present but not a positive duration.
+fn task_timeout(task: &Table) -> mlua::Result<Option<Duration>> {
+    let Some(timeout) = duration_param(task.get::<Value>("timeout")?, "timeout")? else {
+        return Ok(None);
+    };
+    if timeout.is_zero() {
+        return Err(RuntimeError(
+            "Task timeout must be a positive duration".to_string(),
+        ));
+    }
+    Ok(Some(timeout))
+}
+
+/// Apply the task's optional `changed_when` / `failed_when` predicates to a
+/// module result and return whether the task counts as failed.
+///
+/// Each predicate is called with the result table. `changed_when` replaces
+/// the module's own `changed` flag; `failed_when` replaces the default
+/// "non-zero exit code" failure rule. Without `failed_when`, the exit code
+/// decides.
+///
+/// # Errors
+///
+/// Returns an error if a predicate is present but not a function, raises a
+/// Lua error, or if `exit_code` is missing from the result.
+fn apply_result_overrides(task: &Table, result: &Table) -> mlua::Result<bool> {
+    if let Some(changed_when) = task.get::<Option<Function>>("changed_when")? {
+        let changed = changed_when.call::<bool>(result)?;
+        result.set("changed", changed)?;
+    }
+
+    match task.get::<Option<Function>>("failed_when")? {
+        Some(failed_when) => failed_when.call::<bool>(result),
+        None => Ok(result.get::<Integer>("exit_code")? != 0),
+    }
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 enum ParallelHashMapKey {
-    Number(u32),
+    Integer(i64),
     Text(String),
 }
 
-pub fn komando_parallel_tasks(lua: &Lua, (tasks, host): (Value, Value)) -> mlua::Result<Table> {
+/// Run every task in `tasks` on `host` in parallel.
+///
+/// `opts.forks` caps how many tasks run at once (see [`resolve_forks`]) and
+/// `opts.output` selects how their output is printed (see [`RunSettings`]).
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid or any task fails.
+pub fn komando_parallel_tasks(
+    lua: &Lua,
+    (tasks, host, opts): (Value, Value, Option<Table>),
+) -> mlua::Result<Table> {
     let host = Host::from_lua(host, lua)?;
     let tasks_table = tasks
         .as_table()
         .ok_or_else(|| RuntimeError("Tasks must be a table".to_string()))?;
     let items = collect_keyed_values::<Task>(lua, tasks_table)?;
+    let settings = RunSettings::from_lua_opts(opts.as_ref())?;
     parallel_komando(
         lua,
         items,
-        |inner, task| {
+        settings,
+        &|inner: &Lua, task: &Task| -> mlua::Result<(Value, Value)> {
             let host_v = host.clone().into_lua(inner)?;
             let task_v = task.clone().into_lua(inner)?;
             Ok((task_v, host_v))
@@ -123,36 +444,739 @@ pub fn komando_parallel_tasks(lua: &Lua, (tasks, host): (Value, Value)) -> mlua:
     )
 }
 
-pub fn komando_parallel_hosts(lua: &Lua, (task, hosts): (Value, Value)) -> mlua::Result<Table> {
+/// Run `tasks` on `host`, starting each task once every task named in its
+/// `depends_on` succeeded and running independent tasks in parallel.
+///
+/// A task is referred to by its `name`, or by its key when `tasks` is keyed
+/// by strings. Tasks run in waves: each wave holds every task whose
+/// dependencies finished in earlier waves. When a task fails, the tasks
+/// depending on it, directly or not, are skipped while unrelated tasks still
+/// run; the call then fails. On success, the result of every task is
+/// returned under its key in `tasks`.
+///
+/// Accepts the `forks` and `output` options of `komando_parallel_tasks`.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, a dependency is unknown or
+/// cyclic, or any task failed; the error lists every failed task.
+pub fn komando_graph(
+    lua: &Lua,
+    (host, tasks, opts): (Value, Value, Option<Table>),
+) -> mlua::Result<Table> {
+    let host = Host::from_lua(host, lua)?;
+    let tasks_table = tasks
+        .as_table()
+        .ok_or_else(|| RuntimeError("Tasks must be a table".to_string()))?;
+    let mut items = collect_keyed_values::<Task>(lua, tasks_table)?;
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let ids = items
+        .iter()
+        .map(|(key, task)| match (task.name(), key) {
+            (Some(name), _) => Some(name.to_string()),
+            (None, ParallelHashMapKey::Text(key)) => Some(key.clone()),
+            (None, ParallelHashMapKey::Integer(_)) => None,
+        })
+        .collect::<Vec<_>>();
+    let nodes = ids
+        .iter()
+        .zip(&items)
+        .map(|(id, (_, task))| (id.clone(), task.depends_on().to_vec()))
+        .collect::<Vec<_>>();
+    let graph = task_graph::plan(&nodes).map_err(RuntimeError)?;
+    let settings = RunSettings::from_lua_opts(opts.as_ref())?;
+    let build_args = |inner: &Lua, task: &Task| -> mlua::Result<(Value, Value)> {
+        let host_v = host.clone().into_lua(inner)?;
+        let task_v = task.clone().into_lua(inner)?;
+        Ok((task_v, host_v))
+    };
+
+    let mut outcomes: HashMap<usize, ItemOutcome> = HashMap::new();
+    for wave in &graph.waves {
+        let mut runnable = Vec::with_capacity(wave.len());
+        for &index in wave {
+            let blocked = graph.dependencies[index]
+                .iter()
+                .any(|dependency| !matches!(outcomes.get(dependency), Some(ItemOutcome::Done(_))));
+            if blocked {
+                let label = ids[index]
+                    .clone()
+                    .unwrap_or_else(|| items[index].0.to_string());
+                output::emit(&format!(
+                    ">> Task '{label}' skipped: a task it depends on did not succeed"
+                ));
+                outcomes.insert(index, ItemOutcome::Cancelled);
+            } else {
+                let position = i64::try_from(index)
+                    .map_err(|_| RuntimeError("Too many tasks in graph".to_string()))?;
+                runnable.push((
+                    ParallelHashMapKey::Integer(position),
+                    items[index].1.clone(),
+                ));
+            }
+        }
+        let budget = FailureBudget::new(runnable.len(), None);
+        for (key, outcome) in run_parallel(lua, runnable, settings, &build_args, &budget) {
+            if let ParallelHashMapKey::Integer(position) = key {
+                outcomes.insert(usize::try_from(position).unwrap_or(usize::MAX), outcome);
+            }
+        }
+    }
+
+    let failures = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (key, _))| match outcomes.get(&index) {
+            Some(ItemOutcome::Failed(error)) => Some(format!("{key}: {error}")),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        return Err(RuntimeError(format!(
+            "Task graph failed: {} task(s) failed. Failures: {}",
+            failures.len(),
+            failures.join("; ")
+        )));
+    }
+
+    let keyed = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, (key, _))| {
+            let outcome = outcomes.remove(&index).unwrap_or(ItemOutcome::Cancelled);
+            (key, outcome)
+        })
+        .collect();
+    outcomes_table(lua, keyed)
+}
+
+/// Run `task` on every host in `hosts` in parallel.
+///
+/// Each host gets its own entry in the returned table: the `komando` result
+/// on success, or `{ failed = true, error = "...", exit_code = -1 }` when the
+/// task errored on that host.
+///
+/// Options (`opts`, optional):
+/// - `forks`: maximum number of hosts run at once (see [`resolve_forks`]).
+/// - `output`: `"prefix"` (default) prints each line as it comes, prefixed
+///   with `[host]`; `"block"` prints each host's lines together once its
+///   task finished.
+/// - `serial`: process hosts in ordered batches of that size (a count, or a
+///   percentage string such as `"25%"`); a batch starts only after the
+///   previous one finished within the failure threshold. Hosts are ordered by
+///   their key in `hosts`.
+/// - `fail_fast`: cancel the remaining hosts on the first failure.
+/// - `max_fail_percentage`: cancel the remaining hosts once more than this
+///   percentage of all hosts failed. Serial runs default to `0` (any failure
+///   stops the rollout).
+///
+/// A task with `run_once = true` runs only on the first host, and its result
+/// is returned for every host of the group.
+///
+/// `task` may also be a list of tasks, run on every host in order with the
+/// `strategy` option (see [`Strategy`]); each host's entry is then the list
+/// of its task results, up to the first failure. `serial` only applies to a
+/// single task.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, or if the failure threshold
+/// was crossed; the error lists every failed host.
+pub fn komando_parallel_hosts(
+    lua: &Lua,
+    (task, hosts, opts): (Value, Value, Option<Table>),
+) -> mlua::Result<Table> {
+    if let Some(tasks) = task_list(&task)? {
+        return parallel_hosts_task_list(lua, &tasks, &hosts, opts);
+    }
     let task = Task::from_lua(task, lua)?;
     let hosts_table = hosts
         .as_table()
         .ok_or_else(|| RuntimeError("Hosts must be a table".to_string()))?;
-    let items = collect_keyed_values::<Host>(lua, hosts_table)?;
-    parallel_komando(
-        lua,
-        items,
-        |inner, host| {
-            let task_v = task.clone().into_lua(inner)?;
-            let host_v = host.clone().into_lua(inner)?;
-            Ok((task_v, host_v))
-        },
-        "Failed to execute parallel hosts",
-    )
+    let mut items = collect_keyed_values::<Host>(lua, hosts_table)?;
+    let build_args = |inner: &Lua, host: &Host| -> mlua::Result<(Value, Value)> {
+        let task_v = task.clone().into_lua(inner)?;
+        let host_v = host.clone().into_lua(inner)?;
+        Ok((task_v, host_v))
+    };
+
+    let opts = opts.map_or_else(|| lua.create_table(), Ok)?;
+    let serial = opts.get::<Value>("serial")?;
+    let batch_size = if serial.is_nil() {
+        items.len().max(1)
+    } else {
+        serial_batch_size(&serial, items.len())?
+    };
+    let max_fail_percentage = max_fail_percentage(&opts, !serial.is_nil())?;
+    let settings = RunSettings::from_lua_opts(Some(&opts))?;
+    let total = items.len();
+
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut unreachable = Vec::new();
+    if let Some(timeout) = alive_probe_timeout(&opts)? {
+        let (alive, dead) = split_alive_hosts(items, timeout, settings.forks)?;
+        items = alive;
+        for (key, host, error) in dead {
+            report_unreachable(lua, &task, host, &error)?;
+            unreachable.push((key, ItemOutcome::Unreachable(error)));
+        }
+    }
+
+    if task.run_once() && !items.is_empty() {
+        let keys = items.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        items.truncate(1);
+        let budget = FailureBudget::new(1, None);
+        let outcome = run_parallel(lua, items, settings, &build_args, &budget)
+            .pop()
+            .map_or(ItemOutcome::Cancelled, |(_, outcome)| outcome);
+        if let ItemOutcome::Failed(error) = &outcome
+            && max_fail_percentage.is_some()
+        {
+            return Err(RuntimeError(format!(
+                "Parallel run aborted: run_once task failed: {error}"
+            )));
+        }
+        let mut outcomes = unreachable;
+        outcomes.extend(keys.into_iter().map(|key| (key, outcome.clone())));
+        return outcomes_table(lua, outcomes);
+    }
+    let batch_count = items.len().div_ceil(batch_size);
+    let budget = FailureBudget::new(total, max_fail_percentage);
+    for _ in &unreachable {
+        budget.record_failure();
+    }
+
+    let mut outcomes = Vec::with_capacity(total);
+    outcomes.append(&mut unreachable);
+    for (index, batch) in items.chunks(batch_size).enumerate() {
+        if budget.is_cancelled() {
+            outcomes.extend(
+                batch
+                    .iter()
+                    .map(|(key, _)| (key.clone(), ItemOutcome::Cancelled)),
+            );
+            continue;
+        }
+        if !serial.is_nil() {
+            output::emit(&format!(
+                ">> Batch {}/{batch_count} ({} host(s))",
+                index + 1,
+                batch.len()
+            ));
+        }
+        outcomes.extend(run_parallel(
+            lua,
+            batch.to_vec(),
+            settings,
+            &build_args,
+            &budget,
+        ));
+    }
+
+    if budget.is_cancelled() {
+        let failures = outcomes
+            .iter()
+            .filter_map(|(key, outcome)| match outcome {
+                ItemOutcome::Failed(error) | ItemOutcome::Unreachable(error) => {
+                    Some(format!("{key}: {error}"))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        return Err(RuntimeError(format!(
+            "Parallel run aborted: {} of {} host(s) failed, remaining hosts were cancelled. Failures: {}",
+            failures.len(),
+            total,
+            failures.join("; ")
+        )));
+    }
+
+    outcomes_table(lua, outcomes)
+}
+
+/// How a list of tasks runs on a group of hosts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    /// Every host finishes a task before any host starts the next one, like
+    /// a loop of `komando_parallel_hosts` calls. The default.
+    Linear,
+    /// Every host runs through the tasks at its own pace.
+    Free,
+}
+
+impl Strategy {
+    /// Read the `strategy` option: `"linear"` (default) or `"free"`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the option names another strategy.
+    fn from_lua_opts(opts: &Table) -> mlua::Result<Self> {
+        match opts.get::<Option<String>>("strategy")?.as_deref() {
+            None | Some("linear") => Ok(Self::Linear),
+            Some("free") => Ok(Self::Free),
+            Some(other) => Err(RuntimeError(format!(
+                "strategy must be \"linear\" or \"free\", got \"{other}\""
+            ))),
+        }
+    }
+}
+
+/// `value` as a list of tasks, or `None` when it is a single task: a task's
+/// first element is its module, a list's first element is a task.
+///
+/// # Errors
+///
+/// Returns an error if the first element cannot be read.
+pub(crate) fn task_list(value: &Value) -> mlua::Result<Option<Table>> {
+    let Some(table) = value.as_table() else {
+        return Ok(None);
+    };
+    let Value::Table(first) = table.get::<Value>(1)? else {
+        return Ok(None);
+    };
+    Ok(matches!(first.get::<Value>(1)?, Value::Table(_)).then(|| table.clone()))
+}
+
+/// Run the list `tasks` on every host in `hosts` with the `strategy` of
+/// `opts`. A host stops at its first failed task.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, or if the failure threshold
+/// was crossed; the error lists every failed host.
+fn parallel_hosts_task_list(
+    lua: &Lua,
+    tasks: &Table,
+    hosts: &Value,
+    opts: Option<Table>,
+) -> mlua::Result<Table> {
+    let tasks = tasks
+        .sequence_values::<Value>()
+        .map(|task| Task::from_lua(task?, lua))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let hosts_table = hosts
+        .as_table()
+        .ok_or_else(|| RuntimeError("Hosts must be a table".to_string()))?;
+    let mut items = collect_keyed_values::<Host>(lua, hosts_table)?;
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let opts = opts.map_or_else(|| lua.create_table(), Ok)?;
+    if !opts.get::<Value>("serial")?.is_nil() {
+        return Err(RuntimeError(
+            "serial only applies to a single task, not to a list of tasks".to_string(),
+        ));
+    }
+    let strategy = Strategy::from_lua_opts(&opts)?;
+    if strategy == Strategy::Free && tasks.iter().any(Task::run_once) {
+        return Err(RuntimeError(
+            "run_once tasks need the linear strategy".to_string(),
+        ));
+    }
+    let settings = RunSettings::from_lua_opts(Some(&opts))?;
+    let budget = FailureBudget::new(items.len(), max_fail_percentage(&opts, false)?);
+
+    let mut outcomes = BTreeMap::new();
+    if let Some(timeout) = alive_probe_timeout(&opts)? {
+        let (alive, dead) = split_alive_hosts(items, timeout, settings.forks)?;
+        items = alive;
+        for (key, host, error) in dead {
+            for task in &tasks {
+                report_unreachable(lua, task, host.clone(), &error)?;
+            }
+            budget.record_failure();
+            outcomes.insert(key, vec![ItemOutcome::Unreachable(error)]);
+        }
+    }
+
+    match strategy {
+        Strategy::Linear => run_linear(lua, &tasks, items, settings, &budget, &mut outcomes),
+        Strategy::Free => outcomes.extend(run_free(lua, &tasks, items, settings, &budget)),
+    }
+
+    if budget.is_cancelled() {
+        let failures = outcomes
+            .iter()
+            .filter_map(|(key, outcomes)| match outcomes.last() {
+                Some(ItemOutcome::Failed(error) | ItemOutcome::Unreachable(error)) => {
+                    Some(format!("{key}: {error}"))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        return Err(RuntimeError(format!(
+            "Parallel run aborted: {} of {} host(s) failed, remaining hosts were cancelled. Failures: {}",
+            failures.len(),
+            budget.total,
+            failures.join("; ")
+        )));
+    }
+
+    let results_table = lua.create_table()?;
+    for (key, outcomes) in outcomes {
+        let results = lua.create_table()?;
+        for outcome in outcomes {
+            results.push(outcome_value(lua, outcome)?)?;
+        }
+        results_table.set(key_value(lua, key)?, results)?;
+    }
+    Ok(results_table)
+}
+
+/// Run `tasks` in lockstep: each task on every host still running, waiting
+/// for all of them before the next task. Hosts drop out at their first
+/// failure. A `run_once` task runs on the first host still running, and its
+/// outcome counts for every host.
+fn run_linear(
+    lua: &Lua,
+    tasks: &[Task],
+    mut items: Vec<(ParallelHashMapKey, Host)>,
+    settings: RunSettings,
+    budget: &FailureBudget,
+    outcomes: &mut BTreeMap<ParallelHashMapKey, Vec<ItemOutcome>>,
+) {
+    for (key, _) in &items {
+        outcomes.insert(key.clone(), Vec::new());
+    }
+    for task in tasks {
+        if items.is_empty() {
+            break;
+        }
+        let build_args = |inner: &Lua, host: &Host| -> mlua::Result<(Value, Value)> {
+            Ok((task.clone().into_lua(inner)?, host.clone().into_lua(inner)?))
+        };
+        let batch = if task.run_once() {
+            items[..1].to_vec()
+        } else {
+            items.clone()
+        };
+        let mut results = run_parallel(lua, batch, settings, &build_args, budget);
+        if task.run_once() {
+            let outcome = results
+                .pop()
+                .map_or(ItemOutcome::Cancelled, |(_, outcome)| outcome);
+            results = items
+                .iter()
+                .map(|(key, _)| (key.clone(), outcome.clone()))
+                .collect();
+        }
+        let mut running = Vec::with_capacity(items.len());
+        for ((key, outcome), item) in results.into_iter().zip(items) {
+            let done = matches!(outcome, ItemOutcome::Done(_));
+            outcomes.entry(key).or_default().push(outcome);
+            if done {
+                running.push(item);
+            }
+        }
+        items = running;
+    }
+}
+
+/// Run `tasks` on every host at the host's own pace: each host runs the
+/// whole list on one worker, stopping at its first failure.
+fn run_free(
+    lua: &Lua,
+    tasks: &[Task],
+    items: Vec<(ParallelHashMapKey, Host)>,
+    settings: RunSettings,
+    budget: &FailureBudget,
+) -> Vec<(ParallelHashMapKey, Vec<ItemOutcome>)> {
+    hooks::with_worker_events(lua, |events| {
+        let run = || {
+            items
+                .into_par_iter()
+                .map(|(key, host)| {
+                    let mut outcomes = Vec::with_capacity(tasks.len());
+                    for task in tasks {
+                        if budget.is_cancelled() {
+                            outcomes.push(ItemOutcome::Cancelled);
+                            break;
+                        }
+                        let result = output::captured(settings.output, || {
+                            with_worker_lua(|inner| {
+                                events.forward(inner, || {
+                                    let task_v = task.clone().into_lua(inner)?;
+                                    let host_v = host.clone().into_lua(inner)?;
+                                    let result = komando(inner, (task_v, host_v))?;
+                                    result_from_lua(inner, result)
+                                })
+                            })
+                        });
+                        match result {
+                            Ok(result) => outcomes.push(ItemOutcome::Done(result)),
+                            Err(e) => {
+                                budget.record_failure();
+                                outcomes.push(ItemOutcome::Failed(e.to_string()));
+                                break;
+                            }
+                        }
+                    }
+                    (key, outcomes)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        in_fork_pool(settings.forks, run)
+    })
+}
+
+/// Options shared by every parallel runner.
+#[derive(Debug, Clone, Copy)]
+struct RunSettings {
+    /// Worker thread limit, `None` for one per CPU.
+    forks: Option<usize>,
+    /// How each worker's output is printed.
+    output: OutputMode,
+}
+
+impl RunSettings {
+    /// Read `forks` and `output` from the options table of a parallel call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either option is invalid.
+    fn from_lua_opts(opts: Option<&Table>) -> mlua::Result<Self> {
+        Ok(Self {
+            forks: resolve_forks(opts)?,
+            output: OutputMode::from_lua_opts(opts)?,
+        })
+    }
+}
+
+/// Resolve how many worker threads a parallel run may use.
+///
+/// The `forks` option of the call wins, then the `--forks` CLI flag, then
+/// `komandan.defaults:set_forks()` (or `KOMANDAN_FORKS`). `None` means one
+/// worker per CPU.
+///
+/// # Errors
+///
+/// Returns an error if `opts.forks` is not a positive integer or the defaults
+/// lock is poisoned.
+fn resolve_forks(opts: Option<&Table>) -> mlua::Result<Option<usize>> {
+    if let Some(forks) = opts
+        .map(|opts| opts.get::<Option<usize>>("forks"))
+        .transpose()?
+        .flatten()
+    {
+        if forks == 0 {
+            return Err(RuntimeError("forks must be at least 1".to_string()));
+        }
+        return Ok(Some(forks));
+    }
+    if let Some(forks) = crate::args::global_flags().forks {
+        return Ok(Some(usize::from(forks)));
+    }
+    Defaults::global()
+        .forks
+        .read()
+        .map(|forks| *forks)
+        .map_err(|_| RuntimeError("Failed to acquire read lock".to_string()))
+}
+
+/// Rayon pool with `forks` threads, shared by every run using that size so
+/// the pooled worker VMs (`WORKER_LUA`) survive across calls.
+///
+/// # Errors
+///
+/// Returns an error if the pool cannot be built.
+fn fork_pool(forks: usize) -> mlua::Result<Arc<ThreadPool>> {
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+    let mut pools = POOLS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Some(pool) = pools.get(&forks) {
+        return Ok(Arc::clone(pool));
+    }
+    let pool = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(forks)
+            .thread_name(|index| format!("komandan-fork-{index}"))
+            .build()
+            .map_err(|e| RuntimeError(format!("Failed to start {forks} worker threads: {e}")))?,
+    );
+    pools.insert(forks, Arc::clone(&pool));
+    Ok(pool)
+}
+
+/// Connect timeout of the `check_host_alive` probe when `alive_timeout` is
+/// not given.
+const DEFAULT_ALIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Read the `check_host_alive` / `alive_timeout` options as the probe's
+/// connect timeout; `None` disables the probe.
+///
+/// # Errors
+///
+/// Returns an error if `alive_timeout` is invalid, zero, or given without
+/// `check_host_alive`.
+fn alive_probe_timeout(opts: &Table) -> mlua::Result<Option<Duration>> {
+    let enabled = opts
+        .get::<Option<bool>>("check_host_alive")?
+        .unwrap_or(false);
+    let timeout = duration_param(opts.get::<Value>("alive_timeout")?, "alive_timeout")?;
+    match (enabled, timeout) {
+        (false, None) => Ok(None),
+        (false, Some(_)) => Err(RuntimeError(
+            "alive_timeout requires check_host_alive = true".to_string(),
+        )),
+        (true, Some(timeout)) if timeout.is_zero() => Err(RuntimeError(
+            "alive_timeout must be greater than zero".to_string(),
+        )),
+        (true, timeout) => Ok(Some(timeout.unwrap_or(DEFAULT_ALIVE_TIMEOUT))),
+    }
+}
+
+/// Open and drop a TCP connection to `address:port`, trying every resolved
+/// address before giving up.
+///
+/// # Errors
+///
+/// Returns why the host is unreachable.
+fn probe_tcp(address: &str, port: u16, timeout: Duration) -> Result<(), String> {
+    let addrs = (address, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {address}: {e}"))?;
+    let mut error = format!("{address} did not resolve to any address");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => error = format!("{address}:{port} is unreachable: {e}"),
+        }
+    }
+    Err(error)
+}
+
+/// Hosts that failed the `check_host_alive` probe, with the reason.
+type UnreachableHosts = Vec<(ParallelHashMapKey, Host, String)>;
+
+/// Probe the SSH port of every host concurrently and split `items` into the
+/// hosts that accepted a connection and the unreachable ones. Local hosts
+/// are never probed.
+///
+/// # Errors
+///
+/// Returns an error if the defaults lock is poisoned.
+fn split_alive_hosts(
+    items: Vec<(ParallelHashMapKey, Host)>,
+    timeout: Duration,
+    forks: Option<usize>,
+) -> mlua::Result<(Vec<(ParallelHashMapKey, Host)>, UnreachableHosts)> {
+    let default_port = Defaults::global()
+        .port
+        .read()
+        .map(|port| *port)
+        .map_err(|_| RuntimeError("Failed to acquire read lock".to_string()))?;
+    let probed = in_fork_pool(forks, || {
+        items
+            .into_par_iter()
+            .map(|(key, host)| {
+                let status = host.ssh_endpoint().map_or(Ok(()), |(address, port)| {
+                    probe_tcp(address, port.unwrap_or(default_port), timeout)
+                });
+                (key, host, status)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut alive = Vec::with_capacity(probed.len());
+    let mut unreachable = Vec::new();
+    for (key, host, status) in probed {
+        match status {
+            Ok(()) => alive.push((key, host)),
+            Err(error) => unreachable.push((key, host, error)),
+        }
+    }
+    Ok((alive, unreachable))
+}
+
+/// Print and record a host skipped by the `check_host_alive` probe.
+///
+/// # Errors
+///
+/// Returns an error if the task or host cannot be converted to Lua.
+fn report_unreachable(lua: &Lua, task: &Task, host: Host, error: &str) -> mlua::Result<()> {
+    let task_display = task_display(&Table::from_lua(task.clone().into_lua(lua)?, lua)?);
+    let host_display = host_display(&Table::from_lua(host.into_lua(lua)?, lua)?);
+    output::emit(&format!(
+        ">> Host '{host_display}' is unreachable, skipping task '{task_display}': {error}"
+    ));
+    if !crate::args::global_flags().no_report {
+        insert_record(task_display, host_display, TaskStatus::Unreachable);
+    }
+    Ok(())
+}
+
+/// Read the `fail_fast` / `max_fail_percentage` options as a tolerated
+/// failure percentage; `None` tolerates every failure.
+///
+/// # Errors
+///
+/// Returns an error if `max_fail_percentage` is outside `0..=100`.
+fn max_fail_percentage(opts: &Table, serial: bool) -> mlua::Result<Option<f64>> {
+    if opts.get::<Option<bool>>("fail_fast")?.unwrap_or(false) {
+        return Ok(Some(0.0));
+    }
+    match opts.get::<Option<f64>>("max_fail_percentage")? {
+        Some(percentage) if (0.0..=100.0).contains(&percentage) => Ok(Some(percentage)),
+        Some(percentage) => Err(RuntimeError(format!(
+            "max_fail_percentage must be between 0 and 100, got {percentage}"
+        ))),
+        None => Ok(serial.then_some(0.0)),
+    }
+}
+
+/// Resolve a `serial` option into a batch size for `total` hosts.
+///
+/// Accepts a positive integer, or a string holding either an integer or a
+/// percentage (`"30%"`, rounded up, at least one host).
+///
+/// # Errors
+///
+/// Returns an error if `serial` is not a positive count or a percentage in
+/// `(0, 100]`.
+fn serial_batch_size(serial: &Value, total: usize) -> mlua::Result<usize> {
+    let invalid = || {
+        RuntimeError(format!(
+            "serial must be a positive number of hosts or a percentage like \"25%\", got {serial:?}"
+        ))
+    };
+    let size = match serial {
+        Value::Integer(count) => usize::try_from(*count).map_err(|_| invalid())?,
+        Value::String(text) => {
+            let text = text.to_str()?;
+            let text = text.trim();
+            if let Some(percent) = text.strip_suffix('%') {
+                let percent = percent.trim().parse::<usize>().map_err(|_| invalid())?;
+                if percent > 100 {
+                    return Err(invalid());
+                }
+                (total * percent)
+                    .div_ceil(100)
+                    .max(usize::from(percent > 0))
+            } else {
+                text.parse::<usize>().map_err(|_| invalid())?
+            }
+        }
+        _ => return Err(invalid()),
+    };
+    if size == 0 {
+        return Err(invalid());
+    }
+    Ok(size)
 }
 
 /// Walk a Lua table of `(key, value)` pairs into a `Vec` keyed by
 /// `ParallelHashMapKey`, parsing each value into `T` via `FromLua`.
 ///
-/// Number keys become `Number(u32)`; all other keys are stringified to `Text`.
-/// Later duplicates overwrite earlier ones (matching the prior
-/// `HashMap::insert` last-write-wins semantics).
+/// Integer keys become `Integer` and string keys `Text`, so the results
+/// table is keyed exactly like the input table. The pairs come back in no
+/// particular order; callers that need one sort by key.
 ///
 /// # Errors
 ///
-/// Returns `mlua::Error::RuntimeError` when a number key cannot be read as an
-/// `i64`, when an integer key is negative or otherwise out of `u32` range, or
-/// when `T::from_lua` fails for any value.
+/// Returns `mlua::Error::RuntimeError` when a key is neither an integer nor a
+/// string, or when `T::from_lua` fails for any value.
 fn collect_keyed_values<T: FromLua>(
     lua: &Lua,
     table: &Table,
@@ -161,15 +1185,15 @@ fn collect_keyed_values<T: FromLua>(
     for pair in table.pairs::<Value, Value>() {
         let (key, value): (Value, Value) = pair?;
         let parsed = T::from_lua(value, lua)?;
-        let phk = if key.is_number() {
-            let key_int = key
-                .as_integer()
-                .ok_or_else(|| RuntimeError("Key must be an integer".to_string()))?;
-            let key_u32 = u32::try_from(key_int)
-                .map_err(|_| RuntimeError("Key out of range for u32".to_string()))?;
-            ParallelHashMapKey::Number(key_u32)
-        } else {
-            ParallelHashMapKey::Text(key.to_string()?)
+        let phk = match key {
+            Value::Integer(n) => ParallelHashMapKey::Integer(n),
+            Value::String(s) => ParallelHashMapKey::Text(s.to_str()?.to_string()),
+            other => {
+                return Err(RuntimeError(format!(
+                    "Keys must be integers or strings, got {}",
+                    other.type_name()
+                )));
+            }
         };
         map.insert(phk, parsed);
     }
@@ -194,11 +1218,15 @@ thread_local! {
     ///
     /// - The `komandan` global table is read-only after `setup_komandan_table`.
     /// - Built-in modules mutate only `self`/locals, never `_G`.
-    /// - User Lua never executes inside a worker VM — only `komando`'s
-    ///   internal `execute_task` chunk does.
+    /// - The only user Lua run inside a worker VM is module methods and the
+    ///   task's `changed_when` / `failed_when` predicates, all loaded from
+    ///   bytecode without upvalues (predicates that capture locals are
+    ///   rejected up front). They see the worker's globals, not the caller's.
     ///
-    /// A future module that writes `_G` would violate this and corrupt pooled
-    /// VMs; review new modules against this invariant.
+    /// User code may read globals and mutate its own module and result
+    /// tables, but anything it writes to `_G` survives into later tasks on
+    /// the same worker, in no particular order. A module that writes `_G`
+    /// would corrupt pooled VMs; review new modules against this invariant.
     static WORKER_LUA: OnceCell<Lua> = const { OnceCell::new() };
 }
 
@@ -233,69 +1261,262 @@ fn with_worker_lua<R>(f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R
         .flatten()
 }
 
+/// Converts a worker's result table for the trip back to the caller's VM,
+/// keeping every field. Values that have no data form, such as functions,
+/// are dropped.
+///
+/// # Errors
+///
+/// Returns an error if the table lacks the standard result fields.
+fn result_from_lua(lua: &Lua, result: Table) -> mlua::Result<KomandoResult> {
+    lua.from_value_with(
+        Value::Table(result),
+        DeserializeOptions::new().deny_unsupported_types(false),
+    )
+}
+
+/// Result of one item of a parallel run.
+#[derive(Clone)]
+enum ItemOutcome {
+    Done(KomandoResult),
+    Failed(String),
+    /// Skipped because the `check_host_alive` probe could not connect.
+    Unreachable(String),
+    /// Not started because the failure threshold was crossed first.
+    Cancelled,
+}
+
+/// Shared failure counter that cancels the rest of a parallel run once more
+/// than `max_fail_percentage` of `total` items failed.
+struct FailureBudget {
+    total: usize,
+    max_fail_percentage: Option<f64>,
+    failed: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl FailureBudget {
+    const fn new(total: usize, max_fail_percentage: Option<f64>) -> Self {
+        Self {
+            total,
+            max_fail_percentage,
+            failed: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    fn record_failure(&self) {
+        let failed = self.failed.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(percentage) = self.max_fail_percentage {
+            #[allow(clippy::cast_precision_loss)]
+            let exceeded = failed as f64 * 100.0 > percentage * self.total as f64;
+            if exceeded {
+                self.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl std::fmt::Display for ParallelHashMapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
 /// Run `komando` in parallel over `items`, collecting the per-item results into
 /// a Lua table keyed by the original `ParallelHashMapKey`.
 ///
-/// Each item is processed on the calling rayon worker thread's pooled Lua VM
-/// (see `WORKER_LUA`), which is built once per worker and reused across tasks
-/// — see `REFACTOR_PLAN.md` §1.2. `build_args` is invoked per item to convert
-/// the item plus the fixed operand — host for tasks-mode, task for hosts-mode
-/// — into the `(task, host)` pair `komando` expects, expressed in the inner
-/// VM's value space.
+/// Used by `komando_parallel_tasks`: the first failing item cancels the items
+/// not yet started.
 ///
 /// # Errors
 ///
-/// Returns `mlua::Error::RuntimeError` carrying `error_msg` if any per-item
-/// step fails: inner VM construction, argument conversion, `komando` execution,
-/// or `KomandoResult` parsing. The final result-table build may surface its
-/// own `mlua::Error` variants (e.g. string allocation failures).
+/// Returns `mlua::Error::RuntimeError` carrying `error_msg` if any item failed.
+/// The final result-table build may surface its own `mlua::Error` variants
+/// (e.g. string allocation failures).
 fn parallel_komando<T, F>(
     lua: &Lua,
     items: Vec<(ParallelHashMapKey, T)>,
-    build_args: F,
+    settings: RunSettings,
+    build_args: &F,
     error_msg: &str,
 ) -> mlua::Result<Table>
 where
     T: Clone + Send + Sync,
     F: Fn(&Lua, &T) -> mlua::Result<(Value, Value)> + Send + Sync,
 {
-    let results: Option<Vec<(ParallelHashMapKey, KomandoResult)>> = items
-        .into_par_iter()
-        .map(|(key, item)| {
-            let result: mlua::Result<(ParallelHashMapKey, KomandoResult)> =
-                with_worker_lua(|inner| {
-                    let (task_v, host_v) = build_args(inner, &item)?;
-                    let result = komando(inner, (task_v, host_v))?;
-                    let parsed = inner.from_value::<KomandoResult>(Value::Table(result))?;
-                    Ok((key, parsed))
-                });
-            result.ok()
-        })
-        .collect();
+    let budget = FailureBudget::new(items.len(), Some(0.0));
+    let outcomes = run_parallel(lua, items, settings, build_args, &budget);
+    if budget.is_cancelled() {
+        return Err(RuntimeError(error_msg.to_string()));
+    }
+    outcomes_table(lua, outcomes)
+}
+
+/// Run `komando` for every item on the rayon pool, recording failures in
+/// `budget` and skipping items that start after it was exhausted.
+///
+/// Each item is processed on the calling rayon worker thread's pooled Lua VM
+/// (see `WORKER_LUA`), which is built once per worker and reused across tasks
+/// — see `REFACTOR_PLAN.md` §1.2. `build_args` is invoked per item to convert
+/// the item plus the fixed operand — host for tasks-mode, task for hosts-mode
+/// — into the `(task, host)` pair `komando` expects, expressed in the inner
+/// VM's value space. Any per-item step failing (inner VM construction,
+/// argument conversion, `komando` execution, or `KomandoResult` parsing)
+/// yields `ItemOutcome::Failed`. The workers' task events reach the hooks of
+/// `lua` while the run goes on (see [`hooks::with_worker_events`]).
+fn run_parallel<T, F>(
+    lua: &Lua,
+    items: Vec<(ParallelHashMapKey, T)>,
+    settings: RunSettings,
+    build_args: &F,
+    budget: &FailureBudget,
+) -> Vec<(ParallelHashMapKey, ItemOutcome)>
+where
+    T: Clone + Send + Sync,
+    F: Fn(&Lua, &T) -> mlua::Result<(Value, Value)> + Send + Sync,
+{
+    hooks::with_worker_events(lua, |events| {
+        let run = || {
+            items
+                .into_par_iter()
+                .map(|(key, item)| {
+                    if budget.is_cancelled() {
+                        return (key, ItemOutcome::Cancelled);
+                    }
+                    let result = output::captured(settings.output, || {
+                        with_worker_lua(|inner| {
+                            events.forward(inner, || {
+                                let (task_v, host_v) = build_args(inner, &item)?;
+                                let result = komando(inner, (task_v, host_v))?;
+                                result_from_lua(inner, result)
+                            })
+                        })
+                    });
+                    match result {
+                        Ok(result) => (key, ItemOutcome::Done(result)),
+                        Err(e) => {
+                            budget.record_failure();
+                            (key, ItemOutcome::Failed(e.to_string()))
+                        }
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
 
-    let results = results.ok_or_else(|| RuntimeError(error_msg.to_string()))?;
+        in_fork_pool(settings.forks, run)
+    })
+}
 
+/// Run `f` on the shared pool sized `forks`, or on rayon's global pool when
+/// no limit is set or the pool cannot be started.
+fn in_fork_pool<R: Send>(forks: Option<usize>, f: impl FnOnce() -> R + Send) -> R {
+    match forks.map(fork_pool) {
+        Some(Ok(pool)) => pool.install(f),
+        Some(Err(e)) => {
+            tracing::warn!("{e}; falling back to the default worker pool");
+            f()
+        }
+        None => f(),
+    }
+}
+
+/// Build the Lua result table of a parallel run, keyed like the input table.
+///
+/// # Errors
+///
+/// Returns an error if a Lua value cannot be created.
+fn outcomes_table(
+    lua: &Lua,
+    outcomes: Vec<(ParallelHashMapKey, ItemOutcome)>,
+) -> mlua::Result<Table> {
     let results_table = lua.create_table()?;
-    for (key, result) in results {
-        let key_v: Value = match key {
-            ParallelHashMapKey::Number(n) => Value::Number(f64::from(n)),
-            ParallelHashMapKey::Text(s) => Value::String(lua.create_string(&s)?),
-        };
-        results_table.set(key_v, lua.to_value(&result)?)?;
+    for (key, outcome) in outcomes {
+        results_table.set(key_value(lua, key)?, outcome_value(lua, outcome)?)?;
     }
     Ok(results_table)
 }
 
-/// Run a single task's Lua-side execution flow against `module` on a connected
-/// session.
+/// `key` as the Lua value it was read from.
+///
+/// # Errors
+///
+/// Returns an error if the string cannot be created.
+fn key_value(lua: &Lua, key: ParallelHashMapKey) -> mlua::Result<Value> {
+    Ok(match key {
+        ParallelHashMapKey::Integer(n) => Value::Integer(n),
+        ParallelHashMapKey::Text(s) => Value::String(lua.create_string(&s)?),
+    })
+}
+
+/// The result table of one item of a parallel run.
+///
+/// # Errors
+///
+/// Returns an error if a Lua value cannot be created.
+fn outcome_value(lua: &Lua, outcome: ItemOutcome) -> mlua::Result<Value> {
+    Ok(match outcome {
+        ItemOutcome::Done(result) => lua.to_value(&result)?,
+        ItemOutcome::Failed(error) => {
+            let table = lua.create_table()?;
+            table.set("stdout", "")?;
+            table.set("stderr", error.as_str())?;
+            table.set("exit_code", -1)?;
+            table.set("changed", false)?;
+            table.set("failed", true)?;
+            table.set("error", error)?;
+            Value::Table(table)
+        }
+        ItemOutcome::Unreachable(error) => {
+            let table = lua.create_table()?;
+            table.set("stdout", "")?;
+            table.set("stderr", error.as_str())?;
+            table.set("exit_code", -1)?;
+            table.set("changed", false)?;
+            table.set("failed", true)?;
+            table.set("unreachable", true)?;
+            table.set("error", error)?;
+            Value::Table(table)
+        }
+        ItemOutcome::Cancelled => {
+            let table = lua.create_table()?;
+            table.set("stdout", "")?;
+            table.set("stderr", "")?;
+            table.set("exit_code", -1)?;
+            table.set("changed", false)?;
+            table.set("cancelled", true)?;
+            Value::Table(table)
+        }
+    })
+}
+
+/// What `execute_task` runs and how it is labelled.
+struct TaskRun<'a> {
+    module: &'a Table,
+    /// The loop host, exposed to the module as `self.host` even when the
+    /// task is delegated to another machine.
+    host: &'a Table,
+    task_display: &'a str,
+    host_display: &'a str,
+}
+
+/// Run a single task's Lua-side execution flow against `run.module` on a
+/// connected session.
 ///
 /// Unified over SSH and local transports: the session is exposed to Lua as
 /// `$module.ssh` regardless of transport (the field name is an internal
-/// Komandan convention referenced by the README, not a user-facing knob).
-/// `connection_label` is appended to the initial "Running task ... on host
-/// ..." status line so local runs are distinguishable in stdout — pass `""`
-/// for SSH and `" (local)"` for local execution; all other status lines are
-/// transport-agnostic by design.
+/// Komandan convention referenced by the README, not a user-facing knob), and
+/// the loop host as `$module.host`. `connection_label` is appended to the
+/// initial "Running task ... on host ..." status line so local runs are
+/// distinguishable in stdout — pass `""` for SSH and `" (local)"` for local
+/// execution; all other status lines are transport-agnostic by design.
 ///
 /// # Errors
 ///
@@ -304,20 +1525,29 @@ where
 /// invocations, result extraction, or status printing.
 fn execute_task<S>(
     lua: &Lua,
-    module: &Table,
+    run: &TaskRun<'_>,
     session: S,
-    task_display: &str,
-    host_display: &str,
     connection_label: &str,
 ) -> mlua::Result<Table>
 where
     S: IntoLua + Clone,
 {
+    let TaskRun {
+        module,
+        host,
+        task_display,
+        host_display,
+    } = *run;
     let dry_run = crate::args::global_flags().dry_run;
 
     lua.load(chunk! {
         print(">> Running task '" .. $task_display .. "' on host '" .. $host_display .. "'" .. $connection_label .. " ...")
         $module.ssh = $session
+        $module.host = $host
+        $module.selected_implementation = nil
+        $module.selected_interpreter = nil
+        $module.extra_result = {}
+        $module.gathered_facts = nil
 
         if $dry_run then
             if $module.dry_run ~= nil then
@@ -331,6 +1561,11 @@ where
         end
 
         local result = $module.ssh:get_session_result()
+        for key, value in pairs($module.extra_result) do
+            if result[key] == nil then
+                result[key] = value
+            end
+        end
         komandan.dprint(result.stdout)
         if result.exit_code ~= 0 then
             print(">> Task '" .. $task_display .. "' on host '" .. $host_display .."' failed with exit code " .. result.exit_code .. ": " .. result.stderr)
@@ -363,6 +1598,165 @@ mod tests {
     };
     use crate::ssh::{Elevation, ElevationMethod, SSHAuthMethod, SSHSession};
 
+    #[test]
+    fn test_serial_batch_size() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let text = |s: &str| lua.create_string(s).map(Value::String);
+
+        assert_eq!(serial_batch_size(&Value::Integer(2), 5)?, 2);
+        assert_eq!(serial_batch_size(&text("3")?, 5)?, 3);
+        assert_eq!(serial_batch_size(&text("25%")?, 10)?, 3);
+        assert_eq!(serial_batch_size(&text("100%")?, 4)?, 4);
+        assert_eq!(serial_batch_size(&text("10%")?, 3)?, 1);
+        assert!(serial_batch_size(&Value::Integer(0), 5).is_err());
+        assert!(serial_batch_size(&text("0%")?, 5).is_err());
+        assert!(serial_batch_size(&text("150%")?, 5).is_err());
+        assert!(serial_batch_size(&text("half")?, 5).is_err());
+        assert!(serial_batch_size(&Value::Boolean(true), 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_results_keep_key_types() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local host = { address = "localhost", connection = "local" }
+                local tasks = {
+                    komandan.modules.cmd({ cmd = "echo first" }),
+                    [7] = komandan.modules.cmd({ cmd = "echo seventh" }),
+                    web = komandan.modules.cmd({ cmd = "echo web" }),
+                }
+                for key, task in pairs(tasks) do
+                    tasks[key] = { task }
+                end
+                return komandan.komando_parallel_tasks(tasks, host)
+            })
+            .eval::<Table>()?;
+        for (key, stdout) in [
+            (Value::Integer(1), "first"),
+            (Value::Integer(7), "seventh"),
+            (Value::String(lua.create_string("web")?), "web"),
+        ] {
+            let result = results.raw_get::<Table>(key)?;
+            assert_eq!(result.get::<String>("stdout")?.trim(), stdout);
+        }
+        assert!(results.raw_get::<Value>("1")?.is_nil());
+
+        let invalid = lua
+            .load(chunk! {
+                local host = { address = "localhost", connection = "local" }
+                return komandan.komando_parallel_tasks({ [true] = { komandan.modules.cmd({ cmd = "true" }) } }, host)
+            })
+            .exec();
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_budget() {
+        let budget = FailureBudget::new(10, Some(20.0));
+        budget.record_failure();
+        budget.record_failure();
+        assert!(!budget.is_cancelled());
+        budget.record_failure();
+        assert!(budget.is_cancelled());
+
+        let unlimited = FailureBudget::new(2, None);
+        unlimited.record_failure();
+        unlimited.record_failure();
+        assert!(!unlimited.is_cancelled());
+
+        let fail_fast = FailureBudget::new(5, Some(0.0));
+        fail_fast.record_failure();
+        assert!(fail_fast.is_cancelled());
+    }
+
+    #[test]
+    fn test_max_fail_percentage() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        assert_eq!(max_fail_percentage(&opts, false)?, None);
+        assert_eq!(max_fail_percentage(&opts, true)?, Some(0.0));
+
+        opts.set("max_fail_percentage", 30)?;
+        assert_eq!(max_fail_percentage(&opts, true)?, Some(30.0));
+
+        opts.set("fail_fast", true)?;
+        assert_eq!(max_fail_percentage(&opts, false)?, Some(0.0));
+
+        opts.set("fail_fast", false)?;
+        opts.set("max_fail_percentage", 120)?;
+        assert!(max_fail_percentage(&opts, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_forks_from_opts() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        opts.set("forks", 3)?;
+        assert_eq!(resolve_forks(Some(&opts))?, Some(3));
+
+        opts.set("forks", 0)?;
+        assert!(resolve_forks(Some(&opts)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_alive_probe_timeout() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        assert_eq!(alive_probe_timeout(&opts)?, None);
+
+        opts.set("alive_timeout", 1)?;
+        assert!(alive_probe_timeout(&opts).is_err());
+
+        opts.set("check_host_alive", true)?;
+        assert_eq!(alive_probe_timeout(&opts)?, Some(Duration::from_secs(1)));
+        opts.set("alive_timeout", Value::Nil)?;
+        assert_eq!(alive_probe_timeout(&opts)?, Some(DEFAULT_ALIVE_TIMEOUT));
+        opts.set("alive_timeout", 0)?;
+        assert!(alive_probe_timeout(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_tcp() -> mlua::Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(mlua::Error::external)?;
+        let port = listener.local_addr().map_err(mlua::Error::external)?.port();
+        assert!(probe_tcp("127.0.0.1", port, Duration::from_secs(1)).is_ok());
+        drop(listener);
+        assert!(probe_tcp("127.0.0.1", port, Duration::from_secs(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork_pool_is_cached() -> mlua::Result<()> {
+        let pool = fork_pool(2)?;
+        assert_eq!(pool.current_num_threads(), 2);
+        assert!(Arc::ptr_eq(&pool, &fork_pool(2)?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags_selected() {
+        let tags = |list: &[&str]| list.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let task_tags = tags(&["deploy", "web"]);
+
+        assert!(tags_selected(&task_tags, &[], &[]));
+        assert!(tags_selected(&task_tags, &tags(&["web"]), &[]));
+        assert!(!tags_selected(&task_tags, &tags(&["db"]), &[]));
+        assert!(!tags_selected(&task_tags, &[], &tags(&["deploy"])));
+        assert!(!tags_selected(
+            &task_tags,
+            &tags(&["web"]),
+            &tags(&["deploy"])
+        ));
+        assert!(tags_selected(&[], &[], &tags(&["deploy"])));
+        assert!(!tags_selected(&[], &tags(&["web"]), &[]));
+    }
+
     #[test]
     fn test_get_auth_config() -> Result<()> {
         let lua = create_lua()?;
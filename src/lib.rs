@@ -1,35 +1,68 @@
 #![feature(once_cell_try)]
 
 pub mod args;
+mod async_job;
+mod block;
+pub mod catalog;
 mod checks;
+mod codec;
 pub mod connection;
+pub mod control_env;
 pub mod defaults;
+pub mod doctor;
 pub mod executor;
+pub mod explain;
+pub mod facts;
+mod hash;
+pub mod hooks;
+mod http;
+mod interpreter;
+pub mod inventory;
 mod komando;
 mod local;
+mod local_fs;
+mod lock;
 pub mod models;
 mod modules;
+mod output;
 pub mod parallel_executor;
+mod profile;
 pub mod project;
+mod prompt;
+pub mod recorder;
+pub mod registry;
 mod repl_config;
 mod report;
+pub mod run;
+mod session_pool;
 pub mod ssh;
+mod start_at;
+pub mod state;
+mod step;
+mod task_graph;
 mod util;
 mod validator;
+mod vars;
+pub mod vault;
 
 use anyhow::Result;
 use args::Args;
 use checks::collect_check_functions;
 use defaults::Defaults;
-use komando::{komando, komando_parallel_hosts, komando_parallel_tasks};
+use komando::{
+    komando, komando_graph, komando_local, komando_parallel_hosts, komando_parallel_tasks,
+};
 use mlua::{Lua, MultiValue, chunk};
 use modules::{base_module, collect_core_modules};
 use parallel_executor::{create_global_executor_interface, parallel_executor_constructor};
+use profile::generate_profile;
 use report::generate_report;
 use rustyline::DefaultEditor;
 use std::{env, fs, path::Path};
 use util::{
-    dprint, filter_hosts, host_info, parse_hosts_json_file, parse_hosts_json_url, regex_is_match,
+    dprint, filter_hosts, host_info, lua_now, lua_parse_duration, lua_parse_size, lua_sleep,
+    lua_stopwatch, monotonic, parse_hosts_json_file, parse_hosts_json_url, regex_is_match, retry,
+    tail, uuid_v4, uuid_v7,
 };
 
 /// Cached `LuaJIT` version string, populated once on first `Lua` construction.
@@ -175,9 +208,24 @@ pub fn setup_komandan_table(lua: &Lua) -> mlua::Result<()> {
     komandan.set("modules", collect_core_modules(lua)?)?;
     komandan.set("check", collect_check_functions(lua)?)?;
     komandan.set("parallel_executor", parallel_executor_constructor(lua)?)?;
+    komandan.set("env", control_env::env_table(lua)?)?;
+    komandan.set("vault", vault::vault_table(lua)?)?;
+    komandan.set("record", recorder::record_table(lua)?)?;
+    komandan.set("hooks", hooks::hooks_table(lua)?)?;
+    komandan.set("http", http::http_table(lua)?)?;
+    komandan.set("yaml", codec::yaml_table(lua)?)?;
+    komandan.set("toml", codec::toml_table(lua)?)?;
+    komandan.set("base64", codec::base64_table(lua)?)?;
+    komandan.set("hash", hash::hash_table(lua)?)?;
+    komandan.set("fs", local_fs::fs_table(lua)?)?;
 
     let entries = [
         ("komando", lua.create_function(komando)?),
+        (
+            "async_status",
+            lua.create_function(async_job::async_status)?,
+        ),
+        ("local", lua.create_function(komando_local)?),
         (
             "komando_parallel_tasks",
             lua.create_function(komando_parallel_tasks)?,
@@ -186,6 +234,13 @@ pub fn setup_komandan_table(lua: &Lua) -> mlua::Result<()> {
             "komando_parallel_hosts",
             lua.create_function(komando_parallel_hosts)?,
         ),
+        ("komando_graph", lua.create_function(komando_graph)?),
+        ("komando_block", lua.create_function(block::komando_block)?),
+        (
+            "komando_sequence",
+            lua.create_function(block::komando_sequence)?,
+        ),
+        ("block", lua.create_function(block::block)?),
         ("regex_is_match", lua.create_function(regex_is_match)?),
         ("filter_hosts", lua.create_function(filter_hosts)?),
         (
@@ -198,12 +253,51 @@ pub fn setup_komandan_table(lua: &Lua) -> mlua::Result<()> {
         ),
         ("dprint", lua.create_function(dprint)?),
         ("host_info", lua.create_function(host_info)?),
+        ("facts", lua.create_function(facts::lua_facts)?),
+        ("tail", lua.create_function(tail)?),
+        (
+            "vars",
+            lua.create_function(|lua, (host, task): (mlua::Table, Option<mlua::Table>)| {
+                vars::resolve_vars(lua, &host, task.as_ref())
+            })?,
+        ),
+        (
+            "prompt_vars",
+            lua.create_function(|lua, specs: mlua::Table| prompt::prompt_vars(lua, &specs))?,
+        ),
+        (
+            "prompt",
+            lua.create_function(|lua, (message, options): (String, Option<mlua::Table>)| {
+                prompt::prompt(lua, message, false, options.as_ref())
+            })?,
+        ),
+        (
+            "prompt_secret",
+            lua.create_function(|lua, (message, options): (String, Option<mlua::Table>)| {
+                prompt::prompt(lua, message, true, options.as_ref())
+            })?,
+        ),
+        ("parse_duration", lua.create_function(lua_parse_duration)?),
+        ("parse_size", lua.create_function(lua_parse_size)?),
+        ("retry", lua.create_function(retry)?),
+        ("sleep", lua.create_function(lua_sleep)?),
+        ("now", lua.create_function(lua_now)?),
+        ("monotonic", lua.create_function(|_, ()| Ok(monotonic()))?),
+        ("stopwatch", lua.create_function(lua_stopwatch)?),
+        ("uuid", lua.create_function(|_, ()| Ok(uuid_v4()))?),
+        ("uuid_v7", lua.create_function(|_, ()| Ok(uuid_v7()))?),
+        (
+            "reset_connections",
+            lua.create_function(|_, ()| Ok(session_pool::clear()))?,
+        ),
     ];
     for (name, func) in &entries {
         komandan.set(*name, func.clone())?;
     }
 
     lua.globals().set("komandan", &komandan)?;
+    lua.globals()
+        .set("print", lua.create_function(output::lua_print)?)?;
 
     let k_table = lua.create_table()?;
     k_table.set("defaults", komandan.get::<mlua::Value>("defaults")?)?;
@@ -212,6 +306,16 @@ pub fn setup_komandan_table(lua: &Lua) -> mlua::Result<()> {
     }
     k_table.set("mods", komandan.get::<mlua::Value>("modules")?)?;
     k_table.set("check", komandan.get::<mlua::Value>("check")?)?;
+    k_table.set("env", komandan.get::<mlua::Value>("env")?)?;
+    k_table.set("vault", komandan.get::<mlua::Value>("vault")?)?;
+    k_table.set("record", komandan.get::<mlua::Value>("record")?)?;
+    k_table.set("hooks", komandan.get::<mlua::Value>("hooks")?)?;
+    k_table.set("http", komandan.get::<mlua::Value>("http")?)?;
+    k_table.set("yaml", komandan.get::<mlua::Value>("yaml")?)?;
+    k_table.set("toml", komandan.get::<mlua::Value>("toml")?)?;
+    k_table.set("base64", komandan.get::<mlua::Value>("base64")?)?;
+    k_table.set("hash", komandan.get::<mlua::Value>("hash")?)?;
+    k_table.set("fs", komandan.get::<mlua::Value>("fs")?)?;
     k_table.set("parallel_executor", create_global_executor_interface(lua)?)?;
     lua.globals().set("k", k_table)?;
 
@@ -233,11 +337,11 @@ pub fn run_main_file(lua: &Lua, main_file: &String) -> Result<()> {
         }
     };
 
-    lua.load(&script).set_name(main_file).exec()?;
+    let result = lua.load(&script).set_name(main_file).exec();
+    hooks::run_complete(lua, result.as_ref().err().map(ToString::to_string));
+    result?;
 
-    if !crate::args::global_flags().no_report {
-        generate_report();
-    }
+    print_summaries(&crate::args::global_flags());
 
     Ok(())
 }
@@ -266,15 +370,28 @@ pub fn run_main_file_with_args(lua: &Lua, args: &Args, main_file: &String) -> Re
         }
     };
 
-    lua.load(&script).set_name(main_file).exec()?;
+    let result = lua.load(&script).set_name(main_file).exec();
+    hooks::run_complete(lua, result.as_ref().err().map(ToString::to_string));
+    result?;
 
-    if !args.flags.no_report {
-        generate_report();
-    }
+    print_summaries(&args.flags);
 
     Ok(())
 }
 
+/// Prints the report and, with `--profile`, the timing breakdown of a run.
+fn print_summaries(flags: &args::Flags) {
+    if !flags.no_report {
+        generate_report();
+    }
+    if flags.profile {
+        generate_profile();
+    }
+    if let Some(start_at) = &flags.start_at_task {
+        start_at::warn_if_not_reached(start_at);
+    }
+}
+
 /// Starts the REPL (Read-Eval-Print Loop).
 ///
 /// # Errors
@@ -324,6 +441,17 @@ pub fn repl(lua: &Lua) -> Result<()> {
     }
 }
 
+/// The exit code of a run that succeeded: `2` when a dry run found changes
+/// to make, so CI can gate on configuration drift, otherwise `0`.
+#[must_use]
+pub fn exit_code() -> u8 {
+    if args::global_flags().dry_run && report::changes_made() {
+        2
+    } else {
+        0
+    }
+}
+
 pub fn print_version() {
     let version = env!("CARGO_PKG_VERSION");
     let authors = env!("CARGO_PKG_AUTHORS");
@@ -360,6 +488,17 @@ mod tests {
                     verbose: true,
                     unsafe_lua: false,
                     version: false,
+                    tags: Vec::new(),
+                    skip_tags: Vec::new(),
+                    forks: None,
+                    profile: false,
+                    flush_facts: false,
+                    vault_password_file: None,
+                    step: false,
+                    start_at_task: None,
+                    record: None,
+                    state_file: None,
+                    drift: false,
                 },
             }
         );
@@ -374,12 +513,16 @@ mod tests {
         assert!(komandan_table.contains_key("defaults")?);
         assert!(komandan_table.contains_key("KomandanModule")?);
         assert!(komandan_table.contains_key("komando")?);
+        assert!(komandan_table.contains_key("local")?);
         assert!(komandan_table.contains_key("regex_is_match")?);
         assert!(komandan_table.contains_key("filter_hosts")?);
         assert!(komandan_table.contains_key("parse_hosts_json_file")?);
         assert!(komandan_table.contains_key("parse_hosts_json_url")?);
         assert!(komandan_table.contains_key("dprint")?);
         assert!(komandan_table.contains_key("host_info")?);
+        assert!(komandan_table.contains_key("tail")?);
+        assert!(komandan_table.contains_key("parse_duration")?);
+        assert!(komandan_table.contains_key("parse_size")?);
 
         let modules_table = komandan_table.get::<Table>("modules")?;
         assert!(modules_table.contains_key("apt")?);
@@ -1,28 +1,32 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt::Write as FmtWrite,
     fs,
-    io::{self, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     os::unix::fs::PermissionsExt,
     path::Path,
-    process::{Command, Stdio},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use anyhow::{Error, Result};
 use mlua::{Error::RuntimeError, UserData, Value};
 
 use crate::executor::{CommandExecutor, SessionResult};
+use crate::profile::{self, Phase};
 use crate::ssh::{Elevation, ElevationMethod};
 
 use std::sync::LazyLock;
 
 use regex::Regex;
 
-fn escape_shell_value(value: &str) -> String {
+pub(crate) fn escape_shell_value(value: &str) -> String {
     format!("'{}'", value.replace('\'', "'\\''"))
 }
 
-fn is_valid_env_var_name(name: &str) -> bool {
+pub(crate) fn is_valid_env_var_name(name: &str) -> bool {
     static RE: LazyLock<Regex> = LazyLock::new(|| {
         Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap_or_else(|e| {
             panic!("Failed to compile regex: {e}");
@@ -34,17 +38,21 @@ fn is_valid_env_var_name(name: &str) -> bool {
 #[derive(Clone, Debug)]
 pub struct LocalSession {
     env: HashMap<String, String>,
+    /// Variables removed from the environment before each command.
+    env_unset: BTreeSet<String>,
     pub elevation: Elevation,
     stdout: Option<String>,
     stderr: Option<String>,
     exit_code: Option<i32>,
     changed: Option<bool>,
+    deadline: Option<Instant>,
 }
 
 impl LocalSession {
     pub fn new() -> Self {
         Self {
             env: HashMap::new(),
+            env_unset: BTreeSet::new(),
             elevation: Elevation {
                 method: ElevationMethod::None,
                 as_user: None,
@@ -53,12 +61,29 @@ impl LocalSession {
             stderr: Some(String::new()),
             exit_code: Some(0),
             changed: Some(false),
+            deadline: None,
         }
     }
 
-    fn execute_command(&self, command: &str) -> Result<(String, String, i32)> {
+    fn check_deadline(&self) -> Result<()> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::msg("task timeout exceeded"));
+        }
+        Ok(())
+    }
+
+    /// Prefix `command` with `unset` and `export` lines for the session
+    /// environment.
+    fn with_env(&self, command: &str) -> String {
         let mut full_command = String::new();
 
+        for key in &self.env_unset {
+            let _ = writeln!(full_command, "unset {key}");
+        }
+
         // Set environment variables
         for (key, value) in &self.env {
             if writeln!(full_command, "export {}={}", key, escape_shell_value(value)).is_err() {
@@ -68,14 +93,28 @@ impl LocalSession {
         }
 
         full_command.push_str(command);
+        full_command
+    }
+
+    fn execute_command(&self, command: &str) -> Result<(String, String, i32)> {
+        profile::time(Phase::Command, command, || self.run_shell(command))
+    }
+
+    fn run_shell(&self, command: &str) -> Result<(String, String, i32)> {
+        let full_command = self.with_env(command);
 
         // Execute via shell
-        let output = Command::new("sh")
+        self.check_deadline()?;
+        let mut command = Command::new("sh");
+        command
             .arg("-c")
             .arg(&full_command)
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()?;
+            .stderr(Stdio::piped());
+        let output = match self.deadline {
+            Some(deadline) => output_with_deadline(command.spawn()?, deadline)?,
+            None => command.output()?,
+        };
 
         let stdout = String::from_utf8_lossy(&output.stdout)
             .trim_end_matches('\n')
@@ -106,6 +145,29 @@ impl CommandExecutor for LocalSession {
         self.execute_command(command)
     }
 
+    fn cmd_stream(&self, command: &str, on_line: &mut dyn FnMut(&str)) -> Result<i32> {
+        profile::time(Phase::Command, command, || {
+            self.check_deadline()?;
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(self.with_env(command))
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+
+            if let Some(deadline) = self.deadline {
+                return stream_with_deadline(child, deadline, on_line);
+            }
+            if let Some(stdout) = child.stdout.take() {
+                for line in BufReader::new(stdout).lines() {
+                    on_line(&line?);
+                }
+            }
+
+            Ok(child.wait()?.code().unwrap_or(-1))
+        })
+    }
+
     fn prepare_command(&self, command: &str) -> String {
         match self.elevation.method {
             ElevationMethod::Su => {
@@ -127,12 +189,22 @@ impl CommandExecutor for LocalSession {
     }
 
     fn set_env(&mut self, key: &str, value: &str) {
+        self.env_unset.remove(key);
         *self
             .env
             .entry(key.to_string())
             .or_insert_with(|| value.to_string()) = value.to_string();
     }
 
+    fn unset_env(&mut self, key: &str) {
+        self.env.remove(key);
+        self.env_unset.insert(key.to_string());
+    }
+
+    fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
     fn get_remote_env(&self, var: &str) -> Result<String> {
         if !is_valid_env_var_name(var) {
             return Err(Error::msg(format!(
@@ -156,38 +228,47 @@ impl CommandExecutor for LocalSession {
     }
 
     fn upload(&self, local_path: &Path, remote_path: &Path) -> Result<()> {
-        // For local execution, upload is just a copy operation
-        if local_path.is_dir() {
-            copy_dir_all(local_path, remote_path)?;
-        } else {
-            if let Some(parent) = remote_path.parent() {
-                fs::create_dir_all(parent)?;
+        profile::time(Phase::Transfer, "", || {
+            self.check_deadline()?;
+            // For local execution, upload is just a copy operation
+            if local_path.is_dir() {
+                copy_dir_all(local_path, remote_path)?;
+            } else {
+                if let Some(parent) = remote_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(local_path, remote_path)?;
             }
-            fs::copy(local_path, remote_path)?;
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     fn download(&self, remote_path: &Path, local_path: &Path) -> Result<()> {
-        // For local execution, download is just a copy operation
-        if remote_path.is_dir() {
-            copy_dir_all(remote_path, local_path)?;
-        } else {
-            if let Some(parent) = local_path.parent() {
-                fs::create_dir_all(parent)?;
+        profile::time(Phase::Transfer, "", || {
+            self.check_deadline()?;
+            // For local execution, download is just a copy operation
+            if remote_path.is_dir() {
+                copy_dir_all(remote_path, local_path)?;
+            } else {
+                if let Some(parent) = local_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(remote_path, local_path)?;
             }
-            fs::copy(remote_path, local_path)?;
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     fn write_remote_file(&self, remote_path: &Path, content: &[u8]) -> Result<()> {
-        if let Some(parent) = remote_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        let mut file = fs::File::create(remote_path)?;
-        file.write_all(content)?;
-        Ok(())
+        profile::time(Phase::Transfer, "", || {
+            self.check_deadline()?;
+            if let Some(parent) = remote_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::File::create(remote_path)?;
+            file.write_all(content)?;
+            Ok(())
+        })
     }
 
     fn chmod(&self, remote_path: &Path, mode: &str) -> Result<()> {
@@ -216,6 +297,101 @@ impl CommandExecutor for LocalSession {
     }
 }
 
+/// Wait for `child` like `Child::wait_with_output`, killing it once
+/// `deadline` passes.
+///
+/// Pipes are drained on helper threads so a chatty child cannot block on a
+/// full pipe while we poll. On timeout the readers are detached rather than
+/// joined: a backgrounded grandchild may still hold the pipes open.
+fn output_with_deadline(mut child: Child, deadline: Instant) -> Result<Output> {
+    let stdout = child.stdout.take().map(spawn_reader);
+    let stderr = child.stderr.take().map(spawn_reader);
+
+    let status = wait_with_deadline(&mut child, deadline)?;
+    Ok(Output {
+        status,
+        stdout: join_reader(stdout),
+        stderr: join_reader(stderr),
+    })
+}
+
+/// Feed the stdout lines of `child` to `on_line` and return its exit code,
+/// killing it once `deadline` passes.
+///
+/// Lines are read on a helper thread and handed over through a channel, so
+/// neither a silent child nor one that never stops printing can hold the
+/// caller past the deadline.
+fn stream_with_deadline(
+    mut child: Child,
+    deadline: Instant,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<i32> {
+    let (sender, lines) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            kill(&mut child);
+            return Err(Error::msg("task timeout exceeded"));
+        }
+        match lines.recv_timeout(remaining) {
+            Ok(Ok(line)) => on_line(&line),
+            Ok(Err(e)) => {
+                kill(&mut child);
+                return Err(e.into());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(wait_with_deadline(&mut child, deadline)?
+        .code()
+        .unwrap_or(-1))
+}
+
+/// Wait for `child` to exit, killing it once `deadline` passes.
+fn wait_with_deadline(child: &mut Child, deadline: Instant) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            kill(child);
+            return Err(Error::msg("task timeout exceeded"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn spawn_reader<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn join_reader(handle: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
 fn copy_dir_all(src: &Path, dst: &Path) -> io::Result<()> {
     fs::create_dir_all(dst)?;
     for entry in fs::read_dir(src)? {
@@ -295,6 +471,10 @@ impl UserData for LocalSession {
             Ok(())
         });
 
+        methods.add_function("with_lock", crate::lock::with_lock::<Self>);
+        methods.add_function("interpreter", crate::interpreter::lua_interpreter::<Self>);
+        methods.add_function("run_script", crate::interpreter::lua_run_script::<Self>);
+
         methods.add_method_mut(
             "write_remote_file",
             |_, this, (remote_path, content): (String, String)| {
@@ -377,6 +557,21 @@ mod tests {
         assert_eq!(session.env.get("TEST_KEY"), Some(&"TEST_VALUE".to_string()));
     }
 
+    #[test]
+    fn test_unset_env() -> Result<()> {
+        let mut session = LocalSession::new();
+        session.set_env("HOME", "/tmp");
+        session.unset_env("HOME");
+        assert!(session.env.is_empty());
+        let (stdout, _, _) = session.cmdq("echo \"[${HOME-}]\"")?;
+        assert_eq!(stdout, "[]");
+
+        session.set_env("HOME", "/tmp");
+        let (stdout, _, _) = session.cmdq("echo \"[${HOME-}]\"")?;
+        assert_eq!(stdout, "[/tmp]");
+        Ok(())
+    }
+
     #[test]
     fn test_prepare_command() {
         let mut session = LocalSession::new();
@@ -418,4 +613,60 @@ mod tests {
         assert_eq!(exit_code, 0);
         Ok(())
     }
+
+    #[test]
+    fn test_cmd_stream() -> anyhow::Result<()> {
+        let session = LocalSession::new();
+        let mut lines = Vec::new();
+        let exit_code = session.cmd_stream("printf 'a\\nb\\n'; exit 2", &mut |line| {
+            lines.push(line.to_string());
+        })?;
+        assert_eq!(lines, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(exit_code, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmd_deadline() -> anyhow::Result<()> {
+        let mut session = LocalSession::new();
+        session.set_deadline(Some(Instant::now() + Duration::from_secs(5)));
+        let (stdout, _stderr, exit_code) = session.cmd("echo within")?;
+        assert_eq!(stdout, "within");
+        assert_eq!(exit_code, 0);
+
+        session.set_deadline(Some(Instant::now() + Duration::from_millis(100)));
+        let started = Instant::now();
+        let result = session.cmd("sleep 5");
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert!(session.cmd("echo late").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmd_stream_deadline() -> anyhow::Result<()> {
+        let mut session = LocalSession::new();
+        session.set_deadline(Some(Instant::now() + Duration::from_secs(5)));
+        let mut lines = Vec::new();
+        let exit_code = session.cmd_stream("printf 'a\\nb\\n'", &mut |line| {
+            lines.push(line.to_string());
+        })?;
+        assert_eq!(lines, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(exit_code, 0);
+
+        let started = Instant::now();
+        session.set_deadline(Some(started + Duration::from_millis(200)));
+        let mut count = 0;
+        let result = session.cmd_stream("while true; do echo tick; done", &mut |_| count += 1);
+        assert!(result.is_err());
+        assert!(count > 0);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        session.set_deadline(Some(Instant::now() + Duration::from_millis(100)));
+        let started = Instant::now();
+        assert!(session.cmd_stream("sleep 5", &mut |_| {}).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
 }
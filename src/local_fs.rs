@@ -0,0 +1,377 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use mlua::{Error::RuntimeError, Lua, String as LuaString, Table};
+use rand::{RngExt, distr::Alphanumeric};
+use regex::Regex;
+
+fn random_name() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect()
+}
+
+fn io_error(path: &str, e: &io::Error) -> mlua::Error {
+    RuntimeError(format!("{path}: {e}"))
+}
+
+/// Replaces `path` with `content` through a temporary file in the same
+/// directory, so readers never see a partly written file. An existing
+/// file keeps its permissions.
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(format!(".komandan-{}", random_name()));
+    let tmp_path = PathBuf::from(tmp_name);
+    let result = fs::File::create_new(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|()| match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(&tmp_path, metadata.permissions()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Creates a file or directory named `<prefix><random><suffix>` in the
+/// system temp directory, readable only by the current user.
+fn create_temp(prefix: &str, suffix: &str, directory: bool) -> io::Result<PathBuf> {
+    loop {
+        let path = std::env::temp_dir().join(format!("{prefix}{}{suffix}", random_name()));
+        let created = if directory {
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(&path)
+        } else {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&path).map(drop)
+        };
+        match created {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Regex for one `/`-free glob component: `*` matches any characters, `?`
+/// one character and `[abc]`, `[a-z]` or `[!abc]` a set of characters.
+fn component_regex(component: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut chars = component.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' if chars.clone().any(|c| c == ']') => {
+                pattern.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    pattern.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' || c == '&' || c == '~' {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+fn join(shown: &str, name: &str) -> String {
+    if shown.is_empty() {
+        name.to_string()
+    } else if shown.ends_with('/') {
+        format!("{shown}{name}")
+    } else {
+        format!("{shown}/{name}")
+    }
+}
+
+/// Matches `components` below the directory `shown` (the current
+/// directory when empty), adding every match to `matches`.
+fn walk(
+    shown: &str,
+    components: &[&str],
+    matches: &mut BTreeSet<String>,
+) -> Result<(), regex::Error> {
+    let Some((component, rest)) = components.split_first() else {
+        matches.insert(shown.to_string());
+        return Ok(());
+    };
+    let dir = if shown.is_empty() { "." } else { shown };
+    if !has_wildcard(component) {
+        let path = join(shown, component);
+        if fs::symlink_metadata(&path).is_ok() {
+            walk(&path, rest, matches)?;
+        }
+        return Ok(());
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut names = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            Some((entry.file_name().into_string().ok()?, is_dir))
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    if *component == "**" {
+        walk(shown, rest, matches)?;
+        for (name, is_dir) in names {
+            if is_dir && !name.starts_with('.') {
+                walk(&join(shown, &name), components, matches)?;
+            }
+        }
+        return Ok(());
+    }
+    let regex = component_regex(component)?;
+    for (name, _) in names {
+        if (!name.starts_with('.') || component.starts_with('.')) && regex.is_match(&name) {
+            walk(&join(shown, &name), rest, matches)?;
+        }
+    }
+    Ok(())
+}
+
+/// Paths matching `pattern`, sorted. Components may use `*`, `?` and
+/// `[...]`, and a `**` component matches any number of directories. As in
+/// a shell, wildcards do not match names starting with `.` unless the
+/// pattern component does.
+///
+/// # Errors
+///
+/// Returns an error if a `[...]` set is invalid.
+pub fn glob(pattern: &str) -> Result<Vec<String>, regex::Error> {
+    let root = if pattern.starts_with('/') { "/" } else { "" };
+    let components = pattern
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>();
+    if components.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut matches = BTreeSet::new();
+    walk(root, &components, &mut matches)?;
+    Ok(matches.into_iter().collect())
+}
+
+/// Builds the `komandan.fs` table for files on the control machine:
+///
+/// - `read(path)` returns the content
+/// - `write(path, content)` replaces the file atomically, keeping the
+///   permissions of an existing file; `append(path, content)` adds to it
+/// - `exists(path)` and `is_dir(path)`
+/// - `mkdir_p(path)` creates a directory and its parents
+/// - `copy(src, dst)` copies a file and returns the number of bytes
+/// - `glob(pattern)` returns the sorted paths matching e.g. `"dist/**/*.tar.gz"`
+/// - `tempfile({ prefix, suffix })` and `tempdir({ prefix })` create a file
+///   or directory in the system temp directory, private to the current
+///   user, and return its path; they are not removed automatically
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn fs_table(lua: &Lua) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set(
+        "read",
+        lua.create_function(|lua, path: String| {
+            let content = fs::read(&path).map_err(|e| io_error(&path, &e))?;
+            lua.create_string(content)
+        })?,
+    )?;
+    table.set(
+        "write",
+        lua.create_function(|_, (path, content): (String, LuaString)| {
+            write_atomic(Path::new(&path), &content.as_bytes()).map_err(|e| io_error(&path, &e))
+        })?,
+    )?;
+    table.set(
+        "append",
+        lua.create_function(|_, (path, content): (String, LuaString)| {
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(&content.as_bytes()))
+                .map_err(|e| io_error(&path, &e))
+        })?,
+    )?;
+    table.set(
+        "exists",
+        lua.create_function(|_, path: String| Ok(Path::new(&path).exists()))?,
+    )?;
+    table.set(
+        "is_dir",
+        lua.create_function(|_, path: String| Ok(Path::new(&path).is_dir()))?,
+    )?;
+    table.set(
+        "mkdir_p",
+        lua.create_function(|_, path: String| {
+            fs::create_dir_all(&path).map_err(|e| io_error(&path, &e))
+        })?,
+    )?;
+    table.set(
+        "copy",
+        lua.create_function(|_, (src, dst): (String, String)| {
+            fs::copy(&src, &dst).map_err(|e| RuntimeError(format!("{src} -> {dst}: {e}")))
+        })?,
+    )?;
+    table.set(
+        "glob",
+        lua.create_function(|_, pattern: String| {
+            glob(&pattern).map_err(|e| RuntimeError(format!("Invalid pattern '{pattern}': {e}")))
+        })?,
+    )?;
+    for (name, directory) in [("tempfile", false), ("tempdir", true)] {
+        table.set(
+            name,
+            lua.create_function(move |_, options: Option<Table>| {
+                let (prefix, suffix) = match options {
+                    Some(options) => (
+                        options.get::<Option<String>>("prefix")?,
+                        options.get::<Option<String>>("suffix")?,
+                    ),
+                    None => (None, None),
+                };
+                let prefix = prefix.unwrap_or_else(|| "komandan-".to_string());
+                let suffix = suffix.unwrap_or_default();
+                if prefix.contains('/') || suffix.contains('/') {
+                    return Err(RuntimeError(format!(
+                        "{name}: 'prefix' and 'suffix' must not contain '/'"
+                    )));
+                }
+                create_temp(&prefix, &suffix, directory)
+                    .map(|path| path.to_string_lossy().to_string())
+                    .map_err(|e| RuntimeError(format!("{name}: {e}")))
+            })?,
+        )?;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::chunk;
+
+    use super::*;
+
+    #[test]
+    fn test_glob() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().to_string_lossy().to_string();
+        for file in [
+            "dist/app-1.tar.gz",
+            "dist/app-2.tar.gz",
+            "dist/nested/deep/lib.tar.gz",
+            "dist/.hidden.tar.gz",
+            "dist/notes.txt",
+        ] {
+            let path = dir.path().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, "")?;
+        }
+
+        let matches = |pattern: &str| -> io::Result<Vec<String>> {
+            let found = glob(&format!("{root}/{pattern}")).map_err(io::Error::other)?;
+            Ok(found
+                .into_iter()
+                .map(|path| path[root.len() + 1..].to_string())
+                .collect())
+        };
+        assert_eq!(
+            matches("dist/*.tar.gz")?,
+            ["dist/app-1.tar.gz", "dist/app-2.tar.gz"]
+        );
+        assert_eq!(matches("dist/app-[!1].tar.gz")?, ["dist/app-2.tar.gz"]);
+        assert_eq!(matches("dist/app-?.tar.gz")?.len(), 2);
+        assert_eq!(
+            matches("dist/**/*.tar.gz")?,
+            [
+                "dist/app-1.tar.gz",
+                "dist/app-2.tar.gz",
+                "dist/nested/deep/lib.tar.gz"
+            ]
+        );
+        assert_eq!(matches("dist/.*.tar.gz")?, ["dist/.hidden.tar.gz"]);
+        assert_eq!(matches("dist/notes.txt")?, ["dist/notes.txt"]);
+        assert!(matches("missing/*")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_table() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let (content, exists, copied, temp_is_dir, temp_file) = lua
+            .load(chunk! {
+                local dir = komandan.fs.tempdir({ prefix = "komandan-test-" })
+                komandan.fs.mkdir_p(dir .. "/a/b")
+                local path = dir .. "/a/b/release.txt"
+                komandan.fs.write(path, "v1\n")
+                komandan.fs.write(path, "v2\n")
+                komandan.fs.append(path, "done\n")
+                local copied = komandan.fs.copy(path, dir .. "/copy.txt")
+                local temp_file = komandan.fs.tempfile({ suffix = ".json" })
+                local result = {
+                    komandan.fs.read(dir .. "/copy.txt"),
+                    komandan.fs.exists(path) and not komandan.fs.exists(dir .. "/missing"),
+                    copied,
+                    komandan.fs.is_dir(dir),
+                    temp_file,
+                }
+                os.remove(temp_file)
+                os.remove(dir .. "/copy.txt")
+                os.remove(path)
+                os.remove(dir .. "/a/b")
+                os.remove(dir .. "/a")
+                os.remove(dir)
+                return unpack(result)
+            })
+            .eval::<(String, bool, u64, bool, String)>()?;
+        assert_eq!(content, "v2\ndone\n");
+        assert!(exists);
+        assert_eq!(copied, 8);
+        assert!(temp_is_dir);
+        assert!(temp_file.ends_with(".json"));
+
+        let result = lua
+            .load(chunk! {
+                return komandan.fs.read("/nonexistent/komandan")
+            })
+            .exec();
+        assert!(result.is_err());
+        Ok(())
+    }
+}
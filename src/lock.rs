@@ -0,0 +1,255 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use mlua::{AnyUserData, Error::RuntimeError, Function, Lua, MultiValue, Table, Value};
+
+use crate::executor::CommandExecutor;
+use crate::local::escape_shell_value;
+use crate::util::duration_param;
+
+/// Directory on the target holding one `<name>.lock` directory per held lock.
+const LOCK_DIR: &str = "/tmp/komandan-locks";
+
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(3600);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Exit codes of the acquire script.
+const LOCK_ACQUIRED: i32 = 0;
+const LOCK_BUSY: i32 = 1;
+const LOCK_STALE_REMOVED: i32 = 3;
+
+/// How long to wait for a lock and when a held lock counts as abandoned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockOptions {
+    pub timeout: Duration,
+    pub stale_after: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_LOCK_TIMEOUT,
+            stale_after: DEFAULT_STALE_AFTER,
+        }
+    }
+}
+
+impl LockOptions {
+    /// Read `{ timeout = ..., stale_after = ... }`, both durations.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either option is not a valid duration.
+    pub fn from_lua_opts(opts: Option<&Table>) -> mlua::Result<Self> {
+        let mut options = Self::default();
+        if let Some(opts) = opts {
+            if let Some(timeout) = duration_param(opts.get::<Value>("timeout")?, "timeout")? {
+                options.timeout = timeout;
+            }
+            if let Some(stale_after) =
+                duration_param(opts.get::<Value>("stale_after")?, "stale_after")?
+            {
+                options.stale_after = stale_after;
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// A lock held on the target; release it with [`release_lock`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteLock {
+    path: String,
+    token: String,
+}
+
+/// Acquire the named lock on the session's target.
+///
+/// The lock is a directory created with `mkdir`, which is atomic on every
+/// POSIX filesystem, holding an `owner` file with a token unique to this
+/// holder. A lock whose directory is older than `stale_after` is assumed to
+/// belong to a crashed run and is removed.
+///
+/// # Errors
+///
+/// Returns an error if the name is invalid, the lock is still held after
+/// `timeout`, or the lock commands cannot be run.
+pub fn acquire_lock<S: CommandExecutor>(
+    session: &S,
+    name: &str,
+    options: &LockOptions,
+) -> mlua::Result<RemoteLock> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(RuntimeError(format!(
+            "invalid lock name '{name}': use letters, digits, '_', '-' or '.'"
+        )));
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let lock = RemoteLock {
+        path: format!("{LOCK_DIR}/{name}.lock"),
+        token: format!("{}-{nanos}", std::process::id()),
+    };
+    let path = escape_shell_value(&lock.path);
+    let script = format!(
+        "mkdir -p {LOCK_DIR} 2>/dev/null && chmod 1777 {LOCK_DIR} 2>/dev/null; \
+         if mkdir {path} 2>/dev/null; then echo {token} > {path}/owner; exit {LOCK_ACQUIRED}; fi; \
+         now=$(date +%s); \
+         mtime=$(stat -c %Y {path} 2>/dev/null || stat -f %m {path} 2>/dev/null || echo \"$now\"); \
+         if [ $((now - mtime)) -gt {stale} ]; then rm -rf {path}; exit {LOCK_STALE_REMOVED}; fi; \
+         cat {path}/owner 2>/dev/null; exit {LOCK_BUSY}",
+        token = escape_shell_value(&lock.token),
+        stale = options.stale_after.as_secs(),
+    );
+
+    let started = Instant::now();
+    loop {
+        let (stdout, stderr, exit_code) = session
+            .cmdq(&script)
+            .map_err(|e| RuntimeError(format!("failed to acquire lock '{name}': {e}")))?;
+        match exit_code {
+            LOCK_ACQUIRED => return Ok(lock),
+            LOCK_STALE_REMOVED => {
+                tracing::warn!("Removed stale lock '{name}' at {}", lock.path);
+            }
+            LOCK_BUSY if started.elapsed() < options.timeout => {
+                thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            LOCK_BUSY => {
+                return Err(RuntimeError(format!(
+                    "timed out after {}s waiting for lock '{name}' held by '{}'",
+                    options.timeout.as_secs_f64(),
+                    stdout.trim()
+                )));
+            }
+            code => {
+                return Err(RuntimeError(format!(
+                    "failed to acquire lock '{name}' (exit {code}): {}",
+                    stderr.trim()
+                )));
+            }
+        }
+    }
+}
+
+/// Release a lock taken with [`acquire_lock`], unless another holder took it
+/// over after it was considered stale.
+///
+/// # Errors
+///
+/// Returns an error if the release command cannot be run.
+pub fn release_lock<S: CommandExecutor>(session: &S, lock: &RemoteLock) -> mlua::Result<()> {
+    let path = escape_shell_value(&lock.path);
+    let token = escape_shell_value(&lock.token);
+    session
+        .cmdq(&format!(
+            "[ \"$(cat {path}/owner 2>/dev/null)\" = {token} ] && rm -rf {path}; true"
+        ))
+        .map_err(|e| RuntimeError(format!("failed to release lock at {}: {e}", lock.path)))?;
+    Ok(())
+}
+
+/// Lua method `session:with_lock(name, fn, opts)`: run `fn` while holding
+/// the named lock on the target, releasing it even if `fn` raises.
+///
+/// The session is cloned for the lock commands so `fn` can keep calling
+/// methods on the same session userdata.
+///
+/// # Errors
+///
+/// Returns an error if the lock cannot be acquired or released, or the error
+/// raised by `fn`.
+pub fn with_lock<S>(
+    _: &Lua,
+    (this, name, f, opts): (AnyUserData, String, Function, Option<Table>),
+) -> mlua::Result<MultiValue>
+where
+    S: CommandExecutor + Clone + 'static,
+{
+    let options = LockOptions::from_lua_opts(opts.as_ref())?;
+    let session = this.borrow::<S>()?.clone();
+
+    let lock = acquire_lock(&session, &name, &options)?;
+    let result = f.call::<MultiValue>(());
+    release_lock(&session, &lock)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+    use crate::local::LocalSession;
+    use mlua::chunk;
+
+    fn unique_name(prefix: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        format!("{prefix}-{}-{nanos}", std::process::id())
+    }
+
+    #[test]
+    fn test_lock_is_exclusive_until_released() -> mlua::Result<()> {
+        let session = LocalSession::new();
+        let name = unique_name("exclusive");
+        let options = LockOptions {
+            timeout: Duration::from_millis(200),
+            stale_after: DEFAULT_STALE_AFTER,
+        };
+
+        let lock = acquire_lock(&session, &name, &options)?;
+        let error = acquire_lock(&session, &name, &options)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("timed out"));
+
+        release_lock(&session, &lock)?;
+        let lock = acquire_lock(&session, &name, &options)?;
+        release_lock(&session, &lock)
+    }
+
+    #[test]
+    fn test_stale_lock_is_taken_over() -> mlua::Result<()> {
+        let session = LocalSession::new();
+        let name = unique_name("stale");
+        let path = format!("{LOCK_DIR}/{name}.lock");
+        session
+            .cmdq(&format!("mkdir -p {path} && touch -d '2 hours ago' {path}"))
+            .map_err(mlua::Error::external)?;
+
+        let lock = acquire_lock(&session, &name, &LockOptions::default())?;
+        release_lock(&session, &lock)
+    }
+
+    #[test]
+    fn test_invalid_lock_name() {
+        let session = LocalSession::new();
+        assert!(acquire_lock(&session, "../etc", &LockOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_with_lock_from_lua() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let session = LocalSession::new();
+        let name = unique_name("lua");
+        let value = lua
+            .load(chunk! {
+                local ssh = $session
+                return ssh:with_lock($name, function()
+                    return ssh:cmdq("echo locked").stdout
+                end, { timeout = "2s" })
+            })
+            .eval::<String>()?;
+        assert_eq!(value.trim(), "locked");
+        Ok(())
+    }
+}
@@ -1,19 +1,22 @@
 use anyhow::Context;
 use clap::Parser;
 use komandan::{
-    args::{Args, Commands},
-    create_lua_with_args,
+    args::{Args, Commands, ExplainHostArgs, Flags, RunArgs},
+    catalog, control_env, create_lua_with_args,
     defaults::Defaults,
+    doctor, exit_code, explain, inventory,
     models::KomandanConfig,
-    print_version, project, repl, run_main_file_with_args,
+    print_version, project, recorder, registry, repl, run, run_main_file_with_args, state, vault,
 };
 use mlua::{Lua, LuaSerdeExt};
 use std::fs;
 use std::path::Path;
+use std::process::ExitCode;
 
-fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<ExitCode> {
     let args = Args::parse();
-    run_app(&args)
+    run_app(&args)?;
+    Ok(ExitCode::from(exit_code()))
 }
 
 fn run_app(args: &Args) -> anyhow::Result<()> {
@@ -30,11 +33,65 @@ fn run_app(args: &Args) -> anyhow::Result<()> {
     if let Some(command) = &args.command {
         return match command {
             Commands::Project(project_args) => project::handle_project_command(project_args),
+            Commands::Modules(modules_args) => catalog::handle_modules_command(modules_args),
+            Commands::Doctor(doctor_args) => doctor::handle_doctor_command(doctor_args),
+            Commands::Facts(facts_args) => inventory::handle_facts_command(facts_args),
+            Commands::Vault(vault_args) => vault::handle_vault_command(vault_args),
+            Commands::Run(run_args) => run_task_command(run_args),
+            Commands::ExplainHost(explain_args) => explain_host_command(explain_args),
+            Commands::Get(get_args) => registry::handle_get_command(get_args),
         };
     }
 
     let lua = create_lua_with_args(args)?;
+    run_and_record(&args.flags, || {
+        run_and_track(&args.flags, || run_scripts(args, &lua))
+    })
+}
+
+/// Runs `run`, recording the commands it runs to `--record` when set.
+///
+/// # Errors
+///
+/// Returns an error if `run` fails or the recording cannot be written.
+fn run_and_record(flags: &Flags, run: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let Some(record) = &flags.record else {
+        return run();
+    };
+    recorder::start();
+    let result = run();
+    recorder::save(record)?;
+    result
+}
 
+/// Runs `run`, then, with `--state-file`, saves the state applied or, with
+/// `--drift`, reports the hosts that drifted from it.
+///
+/// # Errors
+///
+/// Returns an error if `run` fails or the state file cannot be used.
+fn run_and_track(flags: &Flags, run: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let result = run();
+    match &flags.state_file {
+        Some(path) if flags.drift => {
+            result?;
+            state::print_drift(path)
+        }
+        Some(path) if !flags.dry_run => {
+            state::save(path)?;
+            result
+        }
+        _ => result,
+    }
+}
+
+/// Runs the `-e` chunk, then the main file or project directory, then the
+/// REPL when no script was given or `--interactive` is set.
+///
+/// # Errors
+///
+/// Returns an error if a script fails or the REPL cannot start.
+fn run_scripts(args: &Args, lua: &Lua) -> anyhow::Result<()> {
     if let Some(chunk_src) = args.chunk.clone() {
         lua.load(&chunk_src).eval::<()>()?;
     }
@@ -47,17 +104,17 @@ fn run_app(args: &Args) -> anyhow::Result<()> {
         Some(main_file) => {
             let path = Path::new(main_file);
             if path.is_dir() {
-                run_project_dir(path, args, &lua)?;
+                run_project_dir(path, args, lua)?;
             } else {
-                run_main_file_with_args(&lua, args, main_file)?;
+                run_main_file_with_args(lua, args, main_file)?;
             }
         }
-        None if args.chunk.is_none() => repl(&lua)?,
+        None if args.chunk.is_none() => repl(lua)?,
         _ => {}
     }
 
     if args.flags.interactive && (args.main_file.is_some() || args.chunk.is_some()) {
-        repl(&lua)?;
+        repl(lua)?;
     }
 
     Ok(())
@@ -91,7 +148,7 @@ fn load_hosts_defaults(path: &Path, config: &KomandanConfig, lua: &Lua) -> anyho
         return Ok(());
     }
 
-    let hosts_content = fs::read_to_string(&hosts_path)?;
+    let hosts_content = vault::reveal(&fs::read_to_string(&hosts_path)?)?;
     let hosts_table: mlua::Table = lua.load(&hosts_content).eval()?;
 
     let mut hosts_vec = Vec::new();
@@ -112,20 +169,51 @@ fn load_hosts_defaults(path: &Path, config: &KomandanConfig, lua: &Lua) -> anyho
     Ok(())
 }
 
-/// Runs a Komandan project directory: reads its `komandan.json`, loads host
-/// defaults, then executes the configured main script.
+/// Loads the `module_defaults` section of `komandan.json` into the global
+/// `Defaults`, replacing the parameters configured for each listed module.
 ///
-/// # Arguments
+/// # Errors
 ///
-/// * `path` - Project directory containing `komandan.json`
-/// * `args` - Parsed CLI args
-/// * `lua` - Lua context
+/// Returns an error if the defaults lock is poisoned.
+fn load_module_defaults(config: &KomandanConfig) -> anyhow::Result<()> {
+    if config.module_defaults.is_empty() {
+        return Ok(());
+    }
+    Defaults::global()
+        .module_params
+        .write()
+        .map(|mut module_params| module_params.extend(config.module_defaults.clone()))
+        .map_err(|e| anyhow::anyhow!("Failed to set module defaults: {e}"))
+}
+
+/// Loads the `vars` and `group_vars` sections of `komandan.json` into the
+/// global `Defaults`.
 ///
 /// # Errors
 ///
-/// Returns an error if `komandan.json` is missing, unreadable, invalid, or if
-/// main-script execution fails.
-fn run_project_dir(path: &Path, args: &Args, lua: &Lua) -> anyhow::Result<()> {
+/// Returns an error if the defaults lock is poisoned.
+fn load_vars(config: &KomandanConfig) -> anyhow::Result<()> {
+    let defaults = Defaults::global();
+    defaults
+        .vars
+        .write()
+        .map(|mut vars| vars.extend(config.vars.clone()))
+        .map_err(|e| anyhow::anyhow!("Failed to set vars: {e}"))?;
+    defaults
+        .group_vars
+        .write()
+        .map(|mut group_vars| group_vars.extend(config.group_vars.clone()))
+        .map_err(|e| anyhow::anyhow!("Failed to set group vars: {e}"))
+}
+
+/// Reads a project's `komandan.json` and loads its host defaults, module
+/// defaults, variables and `.env` file.
+///
+/// # Errors
+///
+/// Returns an error if `komandan.json` is missing, unreadable or invalid, or
+/// if the project files cannot be loaded.
+fn load_project(path: &Path, lua: &Lua) -> anyhow::Result<KomandanConfig> {
     let config_path = path.join("komandan.json");
     anyhow::ensure!(
         config_path.exists(),
@@ -142,6 +230,30 @@ fn run_project_dir(path: &Path, args: &Args, lua: &Lua) -> anyhow::Result<()> {
     })?;
 
     load_hosts_defaults(path, &config, lua)?;
+    load_module_defaults(&config)?;
+    load_vars(&config)?;
+    let dotenv_path = path.join(".env");
+    if dotenv_path.exists() {
+        control_env::load_dotenv(&dotenv_path, false)?;
+    }
+    Ok(config)
+}
+
+/// Runs a Komandan project directory: loads the project (see
+/// [`load_project`]), then executes the configured main script.
+///
+/// # Arguments
+///
+/// * `path` - Project directory containing `komandan.json`
+/// * `args` - Parsed CLI args
+/// * `lua` - Lua context
+///
+/// # Errors
+///
+/// Returns an error if the project cannot be loaded or if main-script
+/// execution fails.
+fn run_project_dir(path: &Path, args: &Args, lua: &Lua) -> anyhow::Result<()> {
+    let config = load_project(path, lua)?;
 
     let main_script = path
         .join(config.main)
@@ -152,6 +264,59 @@ fn run_project_dir(path: &Path, args: &Args, lua: &Lua) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Runs a task file on the project hosts selected by `--hosts`.
+///
+/// # Errors
+///
+/// Returns an error if the project cannot be loaded, no host matches, or a
+/// task fails.
+fn run_task_command(run_args: &RunArgs) -> anyhow::Result<()> {
+    let args = Args {
+        main_file: None,
+        chunk: None,
+        flags: run_args.flags.clone(),
+        command: None,
+    };
+    let lua = create_lua_with_args(&args)?;
+    load_project(Path::new(&run_args.project), &lua)?;
+    let inventory = Defaults::global()
+        .hosts
+        .read()
+        .map_err(|e| anyhow::anyhow!("Failed to read hosts defaults: {e}"))?
+        .clone();
+    let hosts = run::select_hosts(&inventory, &run_args.hosts)?;
+
+    if args.flags.dry_run {
+        println!("[[[ Running in dry-run mode ]]]");
+    }
+    run_and_record(&args.flags, || {
+        run_and_track(&args.flags, || {
+            run::run_task_file(&lua, &args.flags, &run_args.task_file, &hosts)
+        })
+    })
+}
+
+/// Prints the effective settings of a project host.
+///
+/// # Errors
+///
+/// Returns an error if the project cannot be loaded or the host is unknown.
+fn explain_host_command(explain_args: &ExplainHostArgs) -> anyhow::Result<()> {
+    let lua = komandan::create_lua()?;
+    load_project(Path::new(&explain_args.project), &lua)?;
+    let inventory = Defaults::global()
+        .hosts
+        .read()
+        .map_err(|e| anyhow::anyhow!("Failed to read hosts defaults: {e}"))?
+        .clone();
+    explain::explain_inventory_host(
+        &lua,
+        &inventory,
+        &explain_args.name,
+        explain_args.task.as_deref(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +336,17 @@ mod tests {
                 verbose: false,
                 unsafe_lua: false,
                 version: false,
+                tags: Vec::new(),
+                skip_tags: Vec::new(),
+                forks: None,
+                profile: false,
+                flush_facts: false,
+                vault_password_file: None,
+                step: false,
+                start_at_task: None,
+                record: None,
+                state_file: None,
+                drift: false,
             },
             command: None,
         }
@@ -210,6 +386,30 @@ mod tests {
         assert!(run_app(&args).is_ok());
     }
 
+    #[test]
+    fn test_drift_args() {
+        assert!(Args::try_parse_from(["komandan", "--drift"]).is_err());
+        let args = Args::parse_from(["komandan", "--drift", "--state-file", "state.json"]);
+        assert!(args.flags.dry_run);
+        assert!(!Args::parse_from(["komandan"]).flags.dry_run);
+    }
+
+    #[test]
+    fn test_run_app_state_file() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let state_file = temp_dir.path().join("state.json");
+        let mut args = default_args();
+        args.chunk = Some("print('hello')".to_string());
+        args.flags.state_file = Some(state_file.to_string_lossy().to_string());
+        run_app(&args)?;
+        assert!(state_file.exists());
+
+        args.flags.dry_run = true;
+        args.flags.drift = true;
+        run_app(&args)?;
+        Ok(())
+    }
+
     #[test]
     fn test_run_app_subcommand() -> anyhow::Result<()> {
         let mut args = default_args();
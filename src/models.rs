@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
-use mlua::{Error, FromLua, IntoLua, Lua, LuaSerdeExt, UserData, Value};
+use mlua::{Error, FromLua, Function, IntoLua, Lua, LuaSerdeExt, Table, UserData, Value};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
 use crate::ssh::ElevationMethod;
+use crate::util::duration_param;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConnectionType {
@@ -43,6 +44,7 @@ pub struct Host {
     port: Option<u16>,
     user: Option<String>,
     key_check: Option<bool>,
+    host_key_fingerprint: Option<Vec<String>>,
     private_key_file: Option<String>,
     private_key_pass: Option<SecretString>,
     password: Option<SecretString>,
@@ -50,11 +52,28 @@ pub struct Host {
     elevation_method: Option<ElevationMethod>,
     as_user: Option<String>,
     env: Option<HashMap<String, String>>,
+    env_unset: Option<Vec<String>>,
     connection: Option<ConnectionType>,
+    /// Groups the host is in; `group_vars` are looked up by these names.
+    tags: Option<Vec<String>>,
+    vars: Option<serde_json::Value>,
+}
+
+impl Host {
+    /// Address and optional port the host is reached on over SSH, or `None`
+    /// when it runs on the local connection.
+    #[must_use]
+    pub fn ssh_endpoint(&self) -> Option<(&str, Option<u16>)> {
+        let local = self.connection.as_ref().map_or_else(
+            || matches!(self.address.as_str(), "localhost" | "127.0.0.1" | "::1"),
+            |connection| *connection == ConnectionType::Local,
+        );
+        (!local).then_some((self.address.as_str(), self.port))
+    }
 }
 
 impl FromLua for Host {
-    fn from_lua(lua_value: Value, _: &Lua) -> mlua::Result<Self> {
+    fn from_lua(lua_value: Value, lua: &Lua) -> mlua::Result<Self> {
         let table = lua_value
             .as_table()
             .ok_or_else(|| Error::external("Value is not a table"))?;
@@ -64,13 +83,14 @@ impl FromLua for Host {
             port: table.get("port")?,
             user: table.get("user")?,
             key_check: table.get("host_key_check")?,
+            host_key_fingerprint: match table.get::<Value>("host_key_fingerprint")? {
+                Value::Nil => None,
+                Value::String(fingerprint) => Some(vec![fingerprint.to_str()?.to_string()]),
+                fingerprints => Some(Vec::<String>::from_lua(fingerprints, lua)?),
+            },
             private_key_file: table.get("private_key_file")?,
-            private_key_pass: table
-                .get::<Option<String>>("private_key_pass")?
-                .map(|s| SecretString::new(s.into_boxed_str())),
-            password: table
-                .get::<Option<String>>("password")?
-                .map(|s| SecretString::new(s.into_boxed_str())),
+            private_key_pass: crate::vault::secret_field(table, "private_key_pass")?,
+            password: crate::vault::secret_field(table, "password")?,
             elevate: table.get("elevate")?,
             elevation_method: table
                 .get::<Option<String>>("elevation_method")?
@@ -78,10 +98,13 @@ impl FromLua for Host {
                 .transpose()?,
             as_user: table.get("as_user")?,
             env: table.get("env")?,
+            env_unset: table.get("env_unset")?,
             connection: table
                 .get::<Option<String>>("connection")?
                 .map(|s| s.parse().map_err(Error::external))
                 .transpose()?,
+            tags: table.get("tags")?,
+            vars: vars_from_lua(lua, table)?,
         })
     }
 }
@@ -102,6 +125,9 @@ impl IntoLua for Host {
         if let Some(key_check) = self.key_check {
             table.set("host_key_check", key_check)?;
         }
+        if let Some(host_key_fingerprint) = self.host_key_fingerprint {
+            table.set("host_key_fingerprint", host_key_fingerprint)?;
+        }
         if let Some(private_key_file) = self.private_key_file {
             table.set("private_key_file", private_key_file)?;
         }
@@ -126,13 +152,38 @@ impl IntoLua for Host {
         if let Some(env) = self.env {
             table.set("env", env)?;
         }
+        if let Some(env_unset) = self.env_unset {
+            table.set("env_unset", env_unset)?;
+        }
         if let Some(connection) = self.connection {
             table.set("connection", connection.as_str())?;
         }
+        if let Some(tags) = self.tags {
+            table.set("tags", tags)?;
+        }
+        if let Some(vars) = self.vars {
+            table.set("vars", lua.to_value(&vars)?)?;
+        }
         Ok(Value::Table(table))
     }
 }
 
+/// The `vars` table of a host or task, kept as JSON to cross Lua states.
+fn vars_from_lua(lua: &Lua, table: &Table) -> mlua::Result<Option<serde_json::Value>> {
+    table
+        .get::<Option<Table>>("vars")?
+        .map(|vars| lua.from_value(Value::Table(vars)))
+        .transpose()
+}
+
+/// Where a task runs instead of the loop host: an address (including
+/// `"localhost"`) or a full host table.
+#[derive(Clone, Debug)]
+enum DelegateTo {
+    Address(String),
+    Host(Host),
+}
+
 #[derive(Clone, Debug)]
 pub struct Task {
     name: Option<String>,
@@ -142,6 +193,31 @@ pub struct Task {
     elevation_method: Option<ElevationMethod>,
     as_user: Option<String>,
     env: Option<HashMap<String, String>>,
+    env_unset: Option<Vec<String>>,
+    /// `false` to leave out the default and host environment.
+    env_inherit: Option<bool>,
+    tags: Option<Vec<String>>,
+    /// Names of the tasks `komando_graph` must finish first.
+    depends_on: Option<Vec<String>>,
+    run_once: Option<bool>,
+    delegate_to: Option<DelegateTo>,
+    /// Wall-clock bound for the whole module run, in seconds.
+    timeout: Option<f64>,
+    /// Run limit of a background (`async`) task, in seconds.
+    async_limit: Option<f64>,
+    /// Poll interval of a background task, in seconds.
+    poll: Option<f64>,
+    /// Path whose existence means the task's work is already done.
+    creates: Option<String>,
+    /// Command whose success means the task's work is already done.
+    unless: Option<String>,
+    vars: Option<serde_json::Value>,
+    /// Module reverting the task when a later task in its block fails.
+    undo: Option<Module>,
+    /// Bytecode of the optional `changed_when(result)` predicate.
+    changed_when: Option<Vec<u8>>,
+    /// Bytecode of the optional `failed_when(result)` predicate.
+    failed_when: Option<Vec<u8>>,
 }
 
 impl FromLua for Task {
@@ -160,10 +236,77 @@ impl FromLua for Task {
                 .transpose()?,
             as_user: table.get("as_user")?,
             env: table.get("env")?,
+            env_unset: table.get("env_unset")?,
+            env_inherit: table.get("env_inherit")?,
+            tags: table.get("tags")?,
+            depends_on: table.get("depends_on")?,
+            run_once: table.get("run_once")?,
+            delegate_to: match table.get::<Value>("delegate_to")? {
+                Value::Nil => None,
+                Value::String(address) => Some(DelegateTo::Address(address.to_str()?.to_string())),
+                host => Some(DelegateTo::Host(Host::from_lua(host, lua)?)),
+            },
+            timeout: duration_param(table.get::<Value>("timeout")?, "timeout")?
+                .map(|timeout| timeout.as_secs_f64()),
+            async_limit: duration_param(table.get::<Value>("async")?, "async")?
+                .map(|limit| limit.as_secs_f64()),
+            poll: duration_param(table.get::<Value>("poll")?, "poll")?
+                .map(|poll| poll.as_secs_f64()),
+            creates: table.get("creates")?,
+            unless: table.get("unless")?,
+            vars: vars_from_lua(lua, table)?,
+            undo: table
+                .get::<Option<Value>>("undo")?
+                .map(|undo| Module::from_lua(undo, lua))
+                .transpose()?,
+            changed_when: table
+                .get::<Option<Function>>("changed_when")?
+                .map(|f| predicate_bytecode(&f, "changed_when"))
+                .transpose()?,
+            failed_when: table
+                .get::<Option<Function>>("failed_when")?
+                .map(|f| predicate_bytecode(&f, "failed_when"))
+                .transpose()?,
         })
     }
 }
 
+/// Bytecode of a `changed_when` / `failed_when` predicate for a worker VM.
+///
+/// The dump drops upvalues, so a predicate capturing a local would fail or
+/// misjudge the result in a parallel run; such predicates are rejected.
+fn predicate_bytecode(predicate: &Function, field: &str) -> mlua::Result<Vec<u8>> {
+    let upvalues = predicate.info().num_upvalues;
+    if upvalues > 0 {
+        return Err(Error::RuntimeError(format!(
+            "Task {field} cannot use local variables from the enclosing scope in \
+             parallel runs (found {upvalues} upvalue(s)); read what it needs from \
+             the result table instead."
+        )));
+    }
+    Ok(predicate.dump(true))
+}
+
+impl Task {
+    /// Whether the task runs on a single host when given a host group.
+    #[must_use]
+    pub const fn run_once(&self) -> bool {
+        matches!(self.run_once, Some(true))
+    }
+
+    /// The task's `name`, if set.
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Names of the tasks this one depends on.
+    #[must_use]
+    pub fn depends_on(&self) -> &[String] {
+        self.depends_on.as_deref().unwrap_or_default()
+    }
+}
+
 impl IntoLua for Task {
     fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
         let table = lua.create_table()?;
@@ -186,10 +329,60 @@ impl IntoLua for Task {
         if let Some(env) = self.env {
             table.set("env", env)?;
         }
+        if let Some(env_unset) = self.env_unset {
+            table.set("env_unset", env_unset)?;
+        }
+        if let Some(env_inherit) = self.env_inherit {
+            table.set("env_inherit", env_inherit)?;
+        }
+        if let Some(tags) = self.tags {
+            table.set("tags", tags)?;
+        }
+        if let Some(depends_on) = self.depends_on {
+            table.set("depends_on", depends_on)?;
+        }
+        if let Some(run_once) = self.run_once {
+            table.set("run_once", run_once)?;
+        }
+        match self.delegate_to {
+            Some(DelegateTo::Address(address)) => table.set("delegate_to", address)?,
+            Some(DelegateTo::Host(host)) => table.set("delegate_to", host.into_lua(lua)?)?,
+            None => {}
+        }
+        if let Some(timeout) = self.timeout {
+            table.set("timeout", timeout)?;
+        }
+        if let Some(async_limit) = self.async_limit {
+            table.set("async", async_limit)?;
+        }
+        if let Some(poll) = self.poll {
+            table.set("poll", poll)?;
+        }
+        if let Some(creates) = self.creates {
+            table.set("creates", creates)?;
+        }
+        if let Some(unless) = self.unless {
+            table.set("unless", unless)?;
+        }
+        if let Some(vars) = self.vars {
+            table.set("vars", lua.to_value(&vars)?)?;
+        }
+        if let Some(undo) = self.undo {
+            table.set("undo", undo.into_lua(lua)?)?;
+        }
+        if let Some(changed_when) = self.changed_when {
+            table.set("changed_when", lua.load(changed_when).into_function()?)?;
+        }
+        if let Some(failed_when) = self.failed_when {
+            table.set("failed_when", lua.load(failed_when).into_function()?)?;
+        }
         Ok(Value::Table(table))
     }
 }
 
+/// How many `__index` parents `Module::from_lua` follows for inherited methods.
+const MAX_INHERITANCE_DEPTH: usize = 8;
+
 #[derive(Clone, Debug)]
 pub struct Module {
     functions: HashMap<String, Vec<u8>>,
@@ -205,18 +398,45 @@ impl FromLua for Module {
         let mut others: HashMap<String, serde_json::Value> = HashMap::new();
         for pair in table.pairs::<Value, Value>() {
             let (key, value) = pair?;
-            if value.is_function() {
-                functions.insert(
-                    key.to_string()?,
-                    value
-                        .as_function()
-                        .ok_or_else(|| Error::external("Value is not a function"))?
-                        .dump(true),
-                );
+            if let Value::Function(function) = &value {
+                // Rust functions have no bytecode; the receiving state
+                // resolves them through its own `KomandanModule`.
+                let bytecode = function.dump(true);
+                if !bytecode.is_empty() {
+                    functions.insert(key.to_string()?, bytecode);
+                }
             } else {
                 others.insert(key.to_string()?, lua.from_value(value)?);
             }
         }
+
+        // Methods inherited from a parent such as `KomandanModule` live in the
+        // `__index` chain; flatten them so the module still works after the
+        // round trip to another Lua state. The depth bound guards against
+        // classes whose metatable chain loops back on itself.
+        let mut parent = table
+            .metatable()
+            .map(|mt| mt.get::<Value>("__index"))
+            .transpose()?;
+        for _ in 0..MAX_INHERITANCE_DEPTH {
+            let Some(Value::Table(class)) = parent else {
+                break;
+            };
+            for pair in class.pairs::<Value, Value>() {
+                if let (Value::String(key), Value::Function(function)) = pair? {
+                    let bytecode = function.dump(true);
+                    if !bytecode.is_empty() {
+                        functions
+                            .entry(key.to_str()?.to_string())
+                            .or_insert(bytecode);
+                    }
+                }
+            }
+            parent = class
+                .metatable()
+                .map(|mt| mt.get::<Value>("__index"))
+                .transpose()?;
+        }
         Ok(Self { functions, others })
     }
 }
@@ -230,16 +450,28 @@ impl IntoLua for Module {
         for (key, value) in &self.others {
             table.set(key.as_str(), lua.to_value(value)?)?;
         }
+        if let Ok(komandan) = lua.globals().get::<Table>("komandan")
+            && let Ok(class) = komandan.get::<Table>("KomandanModule")
+        {
+            let metatable = lua.create_table()?;
+            metatable.set("__index", class)?;
+            table.set_metatable(Some(metatable))?;
+        }
         Ok(Value::Table(table))
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// A task result carried from a parallel worker's Lua VM back to the
+/// caller's. `extra` holds every other field of the result table, such as
+/// `skipped` or values a module added with `set_result`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct KomandoResult {
     stdout: String,
     stderr: String,
     exit_code: i32,
     changed: bool,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl UserData for KomandoResult {}
@@ -251,6 +483,16 @@ pub struct KomandanConfig {
     pub main: String,
     #[serde(default)]
     pub defaults: DefaultsConfig,
+    /// Default parameters per module name, applied beneath the parameters a
+    /// task passes to that module.
+    #[serde(default)]
+    pub module_defaults: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// Variables visible to every task.
+    #[serde(default)]
+    pub vars: serde_json::Map<String, serde_json::Value>,
+    /// Variables per host group, i.e. per host tag.
+    #[serde(default)]
+    pub group_vars: HashMap<String, serde_json::Map<String, serde_json::Value>>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -324,6 +566,7 @@ mod tests {
             port: Some(22),
             user: Some("user".to_string()),
             key_check: None,
+            host_key_fingerprint: None,
             private_key_file: Some("/path/to/key".to_string()),
             private_key_pass: Some(SecretString::new("pass".to_string().into_boxed_str())),
             password: Some(SecretString::new("password".to_string().into_boxed_str())),
@@ -331,7 +574,10 @@ mod tests {
             elevation_method: Some(ElevationMethod::Sudo),
             as_user: Some("root".to_string()),
             env: Some(env.clone()),
+            env_unset: Some(vec!["DEBIAN_FRONTEND".to_string()]),
             connection: None,
+            tags: None,
+            vars: None,
         };
 
         let table = host
@@ -350,6 +596,7 @@ mod tests {
         assert_eq!(table.get::<String>("elevation_method")?, "sudo");
         assert_eq!(table.get::<String>("as_user")?, "root");
         assert_eq!(table.get::<HashMap<String, String>>("env")?, env);
+        assert_eq!(table.get::<Vec<String>>("env_unset")?, ["DEBIAN_FRONTEND"]);
         Ok(())
     }
 
@@ -361,6 +608,7 @@ mod tests {
             port: None,
             user: None,
             key_check: None,
+            host_key_fingerprint: None,
             private_key_file: None,
             private_key_pass: Some(SecretString::new(
                 "super_secret_passphrase".to_string().into_boxed_str(),
@@ -372,7 +620,10 @@ mod tests {
             elevation_method: None,
             as_user: None,
             env: None,
+            env_unset: None,
             connection: None,
+            tags: None,
+            vars: None,
         };
         let debug = format!("{host:?}");
         assert!(
@@ -494,6 +745,8 @@ mod tests {
         let mut env = HashMap::new();
         env.insert("key".to_string(), "value".to_string());
         table.set("env", env.clone())?;
+        table.set("env_unset", vec!["DEBIAN_FRONTEND"])?;
+        table.set("env_inherit", false)?;
 
         let task = Task::from_lua(Value::Table(table), &lua)?;
         assert_eq!(task.name, Some("test".to_string()));
@@ -502,6 +755,108 @@ mod tests {
         assert_eq!(task.elevation_method, Some(ElevationMethod::Sudo));
         assert_eq!(task.as_user, Some("root".to_string()));
         assert_eq!(task.env, Some(env));
+        assert_eq!(task.env_unset, Some(vec!["DEBIAN_FRONTEND".to_string()]));
+        assert_eq!(task.env_inherit, Some(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_result_predicates_round_trip() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let table = lua.create_table()?;
+        let module_table = lua.create_table()?;
+        module_table.set("command", "echo hi")?;
+        table.set(1, module_table)?;
+        table.set(
+            "changed_when",
+            lua.load("return function(r) return r.stdout == 'hi' end")
+                .eval::<Function>()?,
+        )?;
+        table.set(
+            "failed_when",
+            lua.load("return function(r) return r.exit_code > 1 end")
+                .eval::<Function>()?,
+        )?;
+
+        let task = Task::from_lua(Value::Table(table), &lua)?;
+        assert!(task.changed_when.is_some());
+        assert!(task.failed_when.is_some());
+
+        let round_tripped = task
+            .into_lua(&lua)?
+            .as_table()
+            .ok_or_else(|| Error::external("round-tripped value is not a table"))?
+            .clone();
+        let result = lua.create_table()?;
+        result.set("stdout", "hi")?;
+        result.set("exit_code", 1)?;
+        let changed_when: Function = round_tripped.get("changed_when")?;
+        let failed_when: Function = round_tripped.get("failed_when")?;
+        assert!(changed_when.call::<bool>(&result)?);
+        assert!(!failed_when.call::<bool>(&result)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_predicate_with_upvalue_errors() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let table = lua.create_table()?;
+        let module_table = lua.create_table()?;
+        module_table.set("command", "echo hi")?;
+        table.set(1, module_table)?;
+        table.set(
+            "changed_when",
+            lua.load("local marker = 'hi' return function(r) return r.stdout == marker end")
+                .eval::<Function>()?,
+        )?;
+
+        let result = Task::from_lua(Value::Table(table), &lua);
+        assert!(result.is_err_and(|e| e.to_string().contains("changed_when")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_komandan_config_module_defaults() -> serde_json::Result<()> {
+        let config: KomandanConfig = serde_json::from_str(
+            r#"{
+                "name": "web",
+                "version": "0.1.0",
+                "main": "main.lua",
+                "module_defaults": {
+                    "apt": { "install_recommends": false },
+                    "file": { "owner": "www-data", "group": "www-data" }
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            config.module_defaults["apt"]["install_recommends"],
+            serde_json::Value::Bool(false)
+        );
+        assert_eq!(config.module_defaults["file"].len(), 2);
+
+        let minimal: KomandanConfig =
+            serde_json::from_str(r#"{ "name": "web", "version": "0.1.0", "main": "main.lua" }"#)?;
+        assert!(minimal.module_defaults.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_komandan_config_vars() -> serde_json::Result<()> {
+        let config: KomandanConfig = serde_json::from_str(
+            r#"{
+                "name": "web",
+                "version": "0.1.0",
+                "main": "main.lua",
+                "vars": { "env": "prod" },
+                "group_vars": { "web": { "port": 8080 } }
+            }"#,
+        )?;
+        assert_eq!(config.vars["env"], "prod");
+        assert_eq!(config.group_vars["web"]["port"], 8080);
+
+        let minimal: KomandanConfig =
+            serde_json::from_str(r#"{ "name": "web", "version": "0.1.0", "main": "main.lua" }"#)?;
+        assert!(minimal.vars.is_empty() && minimal.group_vars.is_empty());
         Ok(())
     }
 
@@ -529,6 +884,68 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_module_from_lua_keeps_inherited_methods() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let module = lua
+            .load(
+                r#"
+                local Base = {}
+                Base.run = function(self) return "base run" end
+                Base.cleanup = function(self) return "base cleanup" end
+                local module = setmetatable({ name = "child" }, { __index = Base })
+                module.cleanup = function(self) return "own cleanup" end
+                return module
+                "#,
+            )
+            .eval::<Value>()?;
+
+        let restored = Module::from_lua(module, &lua)?.into_lua(&lua)?;
+        let restored = restored
+            .as_table()
+            .ok_or_else(|| Error::external("module is not a table"))?;
+        assert_eq!(
+            restored.get::<Function>("run")?.call::<String>(())?,
+            "base run"
+        );
+        assert_eq!(
+            restored.get::<Function>("cleanup")?.call::<String>(())?,
+            "own cleanup"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_round_trip_resolves_rust_helpers() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let params = lua.create_table()?;
+        params.set("src", "Cargo.toml")?;
+        params.set("dst", "/tmp/Cargo.toml")?;
+        let module = lua
+            .globals()
+            .get::<Table>("komandan")?
+            .get::<Table>("modules")?
+            .get::<Function>("upload")?
+            .call::<Value>(params)?;
+
+        let worker = crate::create_lua()?;
+        let restored = Module::from_lua(module, &lua)?.into_lua(&worker)?;
+        let restored = restored
+            .as_table()
+            .ok_or_else(|| Error::external("module is not a table"))?;
+        let checksum = restored
+            .get::<Function>("local_sha256")?
+            .call::<Option<String>>("Cargo.toml")?;
+        assert_eq!(checksum.map(|sum| sum.len()), Some(64));
+        assert!(
+            restored
+                .get::<Function>("sha256_command")?
+                .call::<String>("/tmp/x")?
+                .starts_with("sh -c ")
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_module_round_trip_nested_mixed() -> mlua::Result<()> {
         let lua = Lua::new();
@@ -0,0 +1,314 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Days before expiry a certificate is renewed when `renew_before_days` is
+/// not given.
+const DEFAULT_RENEW_BEFORE_DAYS: u32 = 30;
+
+/// The challenge options of `certbot certonly` for `challenge`.
+fn challenge_options(params: &Table, challenge: &str) -> mlua::Result<String> {
+    match challenge {
+        "webroot" => {
+            let webroot = params
+                .get::<Option<String>>("webroot_path")?
+                .ok_or_else(|| {
+                    RuntimeError(
+                        "'webroot_path' parameter is required for the webroot challenge"
+                            .to_string(),
+                    )
+                })?;
+            Ok(format!("--webroot -w {}", escape_shell_value(&webroot)))
+        }
+        "standalone" => Ok("--standalone".to_string()),
+        "dns" => {
+            let plugin = params.get::<Option<String>>("dns_plugin")?.ok_or_else(|| {
+                RuntimeError("'dns_plugin' parameter is required for the dns challenge".to_string())
+            })?;
+            if plugin.is_empty()
+                || !plugin
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+                return Err(RuntimeError(format!("Invalid dns_plugin: '{plugin}'")));
+            }
+            let mut options = format!("--dns-{plugin}");
+            if let Some(credentials) = params.get::<Option<String>>("dns_credentials")? {
+                let _ = write!(
+                    options,
+                    " --dns-{plugin}-credentials {}",
+                    escape_shell_value(&credentials)
+                );
+            }
+            if let Some(seconds) = params.get::<Option<u32>>("dns_propagation_seconds")? {
+                let _ = write!(options, " --dns-{plugin}-propagation-seconds {seconds}");
+            }
+            Ok(options)
+        }
+        _ => Err(RuntimeError(format!(
+            "Invalid challenge: {challenge}. Valid challenges are: webroot, standalone and dns."
+        ))),
+    }
+}
+
+/// Obtain and renew a certificate for `domains` (a name or a list; the
+/// first one names the certificate unless `cert_name` is given) with
+/// `certbot` on the host. `challenge` is `webroot` (default, files under
+/// `webroot_path`), `standalone`, or `dns` with the certbot DNS plugin
+/// `dns_plugin` (e.g. `"cloudflare"`), its `dns_credentials` file and
+/// `dns_propagation_seconds`. A certificate is requested when there is
+/// none, when its names differ from `domains`, or when it expires within
+/// `renew_before_days` (default 30). `email` registers the ACME account,
+/// `server` selects another ACME directory (`staging = true` for the Let's
+/// Encrypt staging one) and `key_type` is `rsa` or `ecdsa`. The task only
+/// reports a change when a certificate was issued; `reload_command` (e.g.
+/// `"systemctl reload nginx"`) then runs on the host. Sets `cert_path`,
+/// `key_path`, `chain_path` and `not_after` in the task result.
+pub fn acme_certificate(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let domains = super::name_list(&params, "domains", ".-*")?;
+    let Some(first) = domains.first() else {
+        return Err(RuntimeError("'domains' parameter is required".to_string()));
+    };
+    let cert_name = params
+        .get::<Option<String>>("cert_name")?
+        .unwrap_or_else(|| first.trim_start_matches("*.").to_string());
+    if cert_name.is_empty()
+        || cert_name.starts_with('.')
+        || !cert_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c))
+    {
+        return Err(RuntimeError(format!("Invalid cert_name: '{cert_name}'")));
+    }
+    let challenge = params
+        .get::<Option<String>>("challenge")?
+        .unwrap_or_else(|| "webroot".to_string());
+    let renew_before_days = params
+        .get::<Option<u32>>("renew_before_days")?
+        .unwrap_or(DEFAULT_RENEW_BEFORE_DAYS);
+
+    let mut command = format!(
+        "certbot certonly --non-interactive --agree-tos --force-renewal --cert-name {cert_name} {}",
+        challenge_options(&params, &challenge)?
+    );
+    for domain in &domains {
+        let _ = write!(command, " -d {}", escape_shell_value(domain));
+    }
+    match params.get::<Option<String>>("email")? {
+        Some(email) => {
+            let _ = write!(command, " --email {}", escape_shell_value(&email));
+        }
+        None => command.push_str(" --register-unsafely-without-email"),
+    }
+    match (
+        params.get::<Option<String>>("server")?,
+        params.get::<Option<bool>>("staging")?.unwrap_or(false),
+    ) {
+        (Some(_), true) => {
+            return Err(RuntimeError(
+                "'server' and 'staging' parameters are mutually exclusive".to_string(),
+            ));
+        }
+        (Some(server), false) => {
+            let _ = write!(command, " --server {}", escape_shell_value(&server));
+        }
+        (None, true) => command.push_str(" --staging"),
+        (None, false) => {}
+    }
+    if let Some(key_type) = params.get::<Option<String>>("key_type")? {
+        if key_type != "rsa" && key_type != "ecdsa" {
+            return Err(RuntimeError(
+                "'key_type' parameter must be 'rsa' or 'ecdsa'".to_string(),
+            ));
+        }
+        let _ = write!(command, " --key-type {key_type}");
+    }
+
+    let live = format!("/etc/letsencrypt/live/{cert_name}");
+    let cert_path = format!("{live}/cert.pem");
+    let seconds = u64::from(renew_before_days) * 86400;
+    let names_command =
+        format!("openssl x509 -noout -text -in {cert_path} | grep -o 'DNS:[^,[:space:]]*'");
+    let checkend_command = format!("openssl x509 -noout -checkend {seconds} -in {cert_path}");
+    let enddate_command = format!("openssl x509 -noout -enddate -in {cert_path}");
+    let mut wanted_names = domains.clone();
+    wanted_names.sort();
+    wanted_names.dedup();
+    let reload_command = params.get::<Option<String>>("reload_command")?;
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "acme_certificate" })
+
+            module.params = $params
+            module.wanted_names = $wanted_names
+            module.live = $live
+            module.exists_command = "[ -f " .. $cert_path .. " ]"
+            module.names_command = $names_command
+            module.checkend_command = $checkend_command
+            module.enddate_command = $enddate_command
+            module.certonly_command = $command
+            module.reload_command = $reload_command
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("acme_certificate: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Why a certificate has to be requested, nil when the current
+            -- one is fine.
+            module.reason = function(self)
+                self.ssh:requires({ "certbot", "openssl" })
+                if self.ssh:cmdq(self.exists_command).exit_code ~= 0 then
+                    return "missing"
+                end
+                local names = {}
+                for name in string.gmatch(self:sh(self.names_command), "DNS:(%S+)") do
+                    table.insert(names, name)
+                end
+                table.sort(names)
+                if table.concat(names, " ") ~= table.concat(self.wanted_names, " ") then
+                    return "names differ"
+                end
+                if self.ssh:cmdq(self.checkend_command).exit_code ~= 0 then
+                    return "expiring"
+                end
+                return nil
+            end
+
+            module.set_paths = function(self)
+                self:set_result("cert_path", self.live .. "/cert.pem")
+                self:set_result("key_path", self.live .. "/privkey.pem")
+                self:set_result("chain_path", self.live .. "/fullchain.pem")
+            end
+
+            module.dry_run = function(self)
+                self:set_paths()
+                local reason = self:reason()
+                self:set_result("reason", reason)
+                self.ssh:set_changed(reason ~= nil)
+            end
+
+            module.run = function(self)
+                self:set_paths()
+                local reason = self:reason()
+                self:set_result("reason", reason)
+                if reason ~= nil then
+                    self:sh(self.certonly_command)
+                    if self.reload_command ~= nil then
+                        self:sh(self.reload_command)
+                    end
+                end
+                local not_after = string.match(self:sh(self.enddate_command), "notAfter=(.*)")
+                self:set_result("not_after", not_after)
+                self.ssh:set_changed(reason ~= nil)
+            end
+
+            return module
+        })
+        .set_name("acme_certificate")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_acme_certificate_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(acme_certificate(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("domains", "example.com")?;
+        assert!(acme_certificate(&lua, params).is_err_and(|e| {
+            e.to_string()
+                .contains("'webroot_path' parameter is required")
+        }));
+
+        let params = lua.create_table()?;
+        params.set("domains", "example.com")?;
+        params.set("challenge", "dns")?;
+        params.set("dns_plugin", "cloudflare; id")?;
+        assert!(acme_certificate(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("domains", "example.com")?;
+        params.set("challenge", "standalone")?;
+        params.set("staging", true)?;
+        params.set("server", "https://acme.example.com/directory")?;
+        assert!(acme_certificate(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_acme_certificate_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.acme_certificate({
+                    domains = { "*.example.com", "example.com" }, email = "ops@example.com",
+                    challenge = "dns", dns_plugin = "cloudflare", dns_credentials = "/root/.cloudflare.ini",
+                    dns_propagation_seconds = 60, renew_before_days = 20, staging = true,
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("certonly_command")?,
+            "certbot certonly --non-interactive --agree-tos --force-renewal --cert-name example.com --dns-cloudflare --dns-cloudflare-credentials '/root/.cloudflare.ini' --dns-cloudflare-propagation-seconds 60 -d '*.example.com' -d 'example.com' --email 'ops@example.com' --staging"
+        );
+        assert_eq!(
+            module.get::<String>("checkend_command")?,
+            "openssl x509 -noout -checkend 1728000 -in /etc/letsencrypt/live/example.com/cert.pem"
+        );
+        assert_eq!(
+            module.get::<Vec<String>>("wanted_names")?,
+            ["*.example.com", "example.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_acme_certificate_reason() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let reasons = lua
+            .load(chunk! {
+                local function reason(exists, names, valid)
+                    local module = komandan.modules.acme_certificate({ domains = { "example.com", "www.example.com" }, challenge = "standalone" })
+                    module.ssh = {
+                        requires = function() end,
+                        cmdq = function(_, command)
+                            if command == module.exists_command then
+                                return { exit_code = exists and 0 or 1, stdout = "", stderr = "" }
+                            elseif command == module.names_command then
+                                return { exit_code = 0, stdout = names, stderr = "" }
+                            end
+                            return { exit_code = valid and 0 or 1, stdout = "", stderr = "" }
+                        end,
+                    }
+                    return module:reason() or "none"
+                end
+                return {
+                    reason(false, "", true),
+                    reason(true, "DNS:www.example.com\nDNS:example.com", true),
+                    reason(true, "DNS:example.com", true),
+                    reason(true, "DNS:example.com\nDNS:www.example.com", false),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(reasons, ["missing", "none", "names differ", "expiring"]);
+        Ok(())
+    }
+}
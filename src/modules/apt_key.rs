@@ -0,0 +1,333 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Heredoc delimiter of an inline `content` key.
+const KEY_EOF: &str = "KOMANDAN_APT_KEY_EOF";
+
+/// Prints the fingerprints of the keys in the file `$1`, one per line.
+const FINGERPRINTS: &str = "gpg --batch --show-keys --with-colons \"$1\" 2>/dev/null | awk -F: '$1 == \"fpr\" { print $10 }'";
+
+/// A key ID or fingerprint in the form `gpg --with-colons` prints it:
+/// uppercase hex without spaces or `0x` prefix.
+fn normalize_key_id(id: &str) -> mlua::Result<String> {
+    let normalized = id.replace(' ', "").to_uppercase();
+    let normalized = normalized.strip_prefix("0X").unwrap_or(&normalized);
+    if !matches!(normalized.len(), 8 | 16 | 40 | 64)
+        || !normalized.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(RuntimeError(format!(
+            "Invalid key ID or fingerprint: '{id}'"
+        )));
+    }
+    Ok(normalized.to_string())
+}
+
+/// The shell line writing the key to `"$TMP/key"`, and the heredoc body to
+/// append after the command for inline `content`.
+fn fetch_source(params: &Table) -> mlua::Result<(String, String)> {
+    let url = params.get::<Option<String>>("url")?;
+    let keyserver = params.get::<Option<String>>("keyserver")?;
+    let content = params.get::<Option<String>>("content")?;
+    match (url, keyserver, content) {
+        (Some(url), None, None) => Ok((
+            format!("curl -fsSL -o \"$TMP/key\" {}", escape_shell_value(&url)),
+            String::new(),
+        )),
+        (None, Some(keyserver), None) => {
+            let id = params
+                .get::<Option<String>>("id")?
+                .ok_or_else(|| RuntimeError("'keyserver' needs the key 'id'".to_string()))?;
+            let id = normalize_key_id(&id)?;
+            Ok((
+                format!(
+                    "gpg --batch --homedir \"$TMP\" --keyserver {} --recv-keys {id} && gpg --batch --homedir \"$TMP\" --export {id} > \"$TMP/key\"",
+                    escape_shell_value(&keyserver)
+                ),
+                String::new(),
+            ))
+        }
+        (None, None, Some(content)) => Ok((
+            format!("cat > \"$TMP/key\" <<'{KEY_EOF}'"),
+            format!("\n{}\n{KEY_EOF}", content.trim_end_matches('\n')),
+        )),
+        _ => Err(RuntimeError(
+            "exactly one of 'url', 'keyserver' or 'content' parameters is required".to_string(),
+        )),
+    }
+}
+
+/// Install an APT repository signing key the way `signed-by` expects it: as
+/// a dearmored keyring file `<keyring_dir>/<name>.gpg` (default directory
+/// `/etc/apt/keyrings`), or at `path`. The key comes from `url`, from
+/// `keyserver` with the key `id`, or from the inline ASCII-armored
+/// `content`. With `fingerprint` (or `id`), the key is only fetched when the
+/// keyring lacks that fingerprint, and a fetched key with another
+/// fingerprint is rejected; otherwise the fetched key's fingerprints are
+/// compared with the keyring's. `state = "absent"` removes the keyring.
+/// Sets `fingerprints` (of the installed keyring) in the task result.
+pub fn apt_key(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let path = match (
+        params.get::<Option<String>>("path")?,
+        params.get::<Option<String>>("name")?,
+    ) {
+        (Some(path), _) if path.starts_with('/') => path,
+        (Some(path), _) => {
+            return Err(RuntimeError(format!(
+                "Invalid path: '{path}'. The keyring path must be absolute"
+            )));
+        }
+        (None, Some(name)) => {
+            if name.is_empty()
+                || name.starts_with('.')
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+            {
+                return Err(RuntimeError(format!("Invalid keyring name: '{name}'")));
+            }
+            let keyring_dir = params
+                .get::<Option<String>>("keyring_dir")?
+                .unwrap_or_else(|| "/etc/apt/keyrings".to_string());
+            format!("{}/{name}.gpg", keyring_dir.trim_end_matches('/'))
+        }
+        (None, None) => {
+            return Err(RuntimeError(
+                "'name' or 'path' parameter is required".to_string(),
+            ));
+        }
+    };
+    params.set("path", path.as_str())?;
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+
+    let quoted_path = escape_shell_value(&path);
+    let current_command =
+        format!("fpr() {{ {FINGERPRINTS}; }}; [ ! -e {quoted_path} ] || fpr {quoted_path}");
+    let remove_command = format!("rm -f {quoted_path}");
+    let (expected, fetch_command, install_command) = if state == "present" {
+        let expected = match (
+            params.get::<Option<String>>("fingerprint")?,
+            params.get::<Option<String>>("id")?,
+        ) {
+            (Some(fingerprint), _) => Some(normalize_key_id(&fingerprint)?),
+            (None, Some(id)) => Some(normalize_key_id(&id)?),
+            (None, None) => None,
+        };
+        let (source, heredoc) = fetch_source(&params)?;
+        let prelude = format!(
+            "fpr() {{ {FINGERPRINTS}; }}; TMP=$(mktemp -d) && trap 'rm -rf \"$TMP\"' EXIT && {source} && if grep -q 'BEGIN PGP' \"$TMP/key\"; then gpg --batch --dearmor < \"$TMP/key\" > \"$TMP/key.gpg\"; else cp \"$TMP/key\" \"$TMP/key.gpg\"; fi"
+        );
+        let check = expected.as_deref().map_or_else(String::new, |expected| {
+            format!(
+                " && {{ fpr \"$TMP/key.gpg\" | grep -q '{expected}$' || {{ echo 'fetched key does not match fingerprint {expected}' >&2; false; }}; }}"
+            )
+        });
+        let fetch_command = format!("{prelude} && fpr \"$TMP/key.gpg\"{heredoc}");
+        let install_command = format!(
+            "{prelude}{check} && install -D -m 0644 \"$TMP/key.gpg\" {quoted_path}{heredoc}"
+        );
+        (expected, Some(fetch_command), Some(install_command))
+    } else {
+        (None, None, None)
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "apt_key" })
+
+            module.params = $params
+            module.expected = $expected
+            module.current_command = $current_command
+            module.fetch_command = $fetch_command
+            module.install_command = $install_command
+            module.remove_command = $remove_command
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("apt_key: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            module.fingerprints = function(self, command)
+                local fingerprints = {}
+                for fingerprint in string.gmatch(self:sh(command), "%x+") do
+                    table.insert(fingerprints, fingerprint)
+                end
+                table.sort(fingerprints)
+                return fingerprints
+            end
+
+            -- Whether the keyring differs from the wanted state.
+            module.needs_change = function(self, current)
+                if self.params.state == "absent" then
+                    return #current > 0
+                end
+                if self.expected ~= nil then
+                    for _, fingerprint in ipairs(current) do
+                        if string.sub(fingerprint, -#self.expected) == self.expected then
+                            return false
+                        end
+                    end
+                    return true
+                end
+                local fetched = self:fingerprints(self.fetch_command)
+                return table.concat(fetched, ",") ~= table.concat(current, ",")
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("gpg")
+                local current = self:fingerprints(self.current_command)
+                self:set_result("fingerprints", current)
+                self.ssh:set_changed(self:needs_change(current))
+            end
+
+            module.run = function(self)
+                self.ssh:requires("gpg")
+                local current = self:fingerprints(self.current_command)
+                if not self:needs_change(current) then
+                    self:set_result("fingerprints", current)
+                    return
+                end
+                if self.params.state == "absent" then
+                    self:sh(self.remove_command)
+                    self:set_result("fingerprints", {})
+                else
+                    self:sh(self.install_command)
+                    self:set_result("fingerprints", self:fingerprints(self.current_command))
+                end
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("apt_key")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_key_id() -> mlua::Result<()> {
+        assert_eq!(
+            normalize_key_id("9DC8 5822 9FC7 DD38 854A  E2D8 8D81 803C 0EBF CD88")?,
+            "9DC858229FC7DD38854AE2D88D81803C0EBFCD88"
+        );
+        assert_eq!(normalize_key_id("0x0ebfcd88")?, "0EBFCD88");
+        assert!(normalize_key_id("not-a-key").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apt_key_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(apt_key(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "docker")?;
+        let result = apt_key(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("exactly one of")));
+
+        let params = lua.create_table()?;
+        params.set("name", "../docker")?;
+        params.set("url", "https://download.docker.com/linux/ubuntu/gpg")?;
+        assert!(apt_key(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "docker")?;
+        params.set("keyserver", "hkps://keyserver.ubuntu.com")?;
+        assert!(apt_key(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "docker")?;
+        params.set("state", "absent")?;
+        assert!(apt_key(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apt_key_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.apt_key({
+                    name = "docker",
+                    url = "https://download.docker.com/linux/ubuntu/gpg",
+                    fingerprint = "9DC8 5822 9FC7 DD38 854A  E2D8 8D81 803C 0EBF CD88",
+                })
+            })
+            .eval::<Table>()?;
+        let params = module.get::<Table>("params")?;
+        assert_eq!(
+            params.get::<String>("path")?,
+            "/etc/apt/keyrings/docker.gpg"
+        );
+        let install = module.get::<String>("install_command")?;
+        assert!(
+            install.contains(
+                "curl -fsSL -o \"$TMP/key\" 'https://download.docker.com/linux/ubuntu/gpg'"
+            )
+        );
+        assert!(install.contains("grep -q '9DC858229FC7DD38854AE2D88D81803C0EBFCD88$'"));
+        assert!(
+            install.ends_with("install -D -m 0644 \"$TMP/key.gpg\" '/etc/apt/keyrings/docker.gpg'")
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.apt_key({
+                    path = "/usr/share/keyrings/app.gpg",
+                    content = "-----BEGIN PGP PUBLIC KEY BLOCK-----\nabc\n-----END PGP PUBLIC KEY BLOCK-----\n",
+                })
+            })
+            .eval::<Table>()?;
+        assert!(module.get::<String>("fetch_command")?.ends_with(
+            "\n-----BEGIN PGP PUBLIC KEY BLOCK-----\nabc\n-----END PGP PUBLIC KEY BLOCK-----\nKOMANDAN_APT_KEY_EOF"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_apt_key_needs_change() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local function needs_change(params, current)
+                    params.name = "app"
+                    local module = komandan.modules.apt_key(params)
+                    module.fingerprints = function() return { "AAAA1111BBBB2222" } end
+                    return module:needs_change(current)
+                end
+                local url = "https://example.com/key.asc"
+                return {
+                    needs_change({ url = url, id = "BBBB2222" }, { "CCCC0000AAAA1111BBBB2222" }),
+                    needs_change({ url = url, id = "BBBB2222" }, {}),
+                    needs_change({ url = url }, { "AAAA1111BBBB2222" }),
+                    needs_change({ url = url }, { "DDDD" }),
+                    needs_change({ state = "absent" }, {}),
+                    needs_change({ state = "absent" }, { "DDDD" }),
+                }
+            })
+            .eval::<Vec<bool>>()?;
+        assert_eq!(results, [false, true, false, true, false, true]);
+        Ok(())
+    }
+}
@@ -1,6 +1,65 @@
-use mlua::{Table, chunk};
+use mlua::{LuaSerdeExt, Table, chunk};
 
+use super::checksum::{lua_local_sha256, remote_sha256_command};
+use super::json_file::lua_edit_json;
+use super::template::render_template;
+use crate::facts::{cache_facts, cached_facts, facts_command, facts_to_lua, parse_facts};
+
+/// Lua base class every module table derives from.
+///
+/// Modules may list alternative implementations in `implementations`, most
+/// preferred first, each as `{ interpreter = "python3", run = fn }`. The
+/// default `run` uses the first one whose interpreter exists on the target,
+/// calling `fn(self, interpreter)` with the table returned by
+/// `ssh:interpreter()`. A module's own `dry_run` can reuse the choice through
+/// `self:select_implementation()`; it is cached for one task run only, since
+/// the same module table may next run against a different host.
+///
+/// For comparing files, `KomandanModule.local_sha256(path)` checksums a path
+/// on the control machine and `self:remote_sha256(path)` one on the target.
+/// Both return a hex string for a file, a table of checksums keyed by
+/// relative path for a directory, or `nil` if the path does not exist;
+/// `KomandanModule.checksums_differ(wanted, actual)` compares the two.
+///
+/// `self:facts()` returns the facts of the target (see `komandan.facts`),
+/// probed once per task run unless the fact cache holds them.
+///
+/// `self.vars` holds the task's layered variables (see `komandan.vars`) and
+/// `KomandanModule.render_template(source, vars)` renders a minijinja
+/// template, returning the output and its SHA-256.
+/// `KomandanModule.edit_json(content, params)` applies the edits of a
+/// `json_file` task to a JSON document, returning `nil` when it is unchanged.
+/// `KomandanModule.decode_json(text)` parses JSON into a Lua value, `nil`
+/// when `text` is not JSON.
+///
+/// `self:set_result(key, value)` adds a field to the task's result table,
+/// e.g. a detected state; the standard fields cannot be overridden.
 pub fn base_module(lua: &mlua::Lua) -> mlua::Result<Table> {
+    let local_sha256 = lua.create_function(lua_local_sha256)?;
+    let sha256_command = lua.create_function(|_, path: String| Ok(remote_sha256_command(&path)))?;
+    let parse_facts = lua.create_function(|lua, (output, host): (String, Option<Table>)| {
+        let facts = parse_facts(&output);
+        if let Some(host) = &host {
+            cache_facts(host, &facts);
+        }
+        facts_to_lua(lua, &facts)
+    })?;
+    let cached_facts = lua.create_function(|lua, host: Option<Table>| {
+        host.as_ref()
+            .and_then(cached_facts)
+            .map_or(Ok(mlua::Value::Nil), |facts| facts_to_lua(lua, &facts))
+    })?;
+    let render_template = lua.create_function(|_, (source, vars): (String, mlua::Value)| {
+        render_template(&source, &vars)
+    })?;
+    let edit_json = lua.create_function(|lua, (content, params): (String, Table)| {
+        lua_edit_json(lua, &content, &params)
+    })?;
+    let decode_json = lua.create_function(|lua, text: String| {
+        serde_json::from_str::<serde_json::Value>(&text)
+            .map_or(Ok(mlua::Value::Nil), |value| lua.to_value(&value))
+    })?;
+    let facts_command = facts_command();
     lua.load(chunk! {
             local KomandanModule = {}
 
@@ -10,12 +69,109 @@ pub fn base_module(lua: &mlua::Lua) -> mlua::Result<Table> {
         return o
     end
 
+    KomandanModule.select_implementation = function(self)
+        if self.selected_implementation ~= nil then
+            return self.selected_implementation, self.selected_interpreter
+        end
+
+        local names = {}
+        for _, implementation in ipairs(self.implementations) do
+            table.insert(names, implementation.interpreter)
+        end
+        local interpreter = self.ssh:interpreter(names)
+        if interpreter == nil then
+            error(self.name .. ": none of the supported interpreters is available: " .. table.concat(names, ", "))
+        end
+        for _, implementation in ipairs(self.implementations) do
+            if implementation.interpreter == interpreter.name then
+                self.selected_implementation = implementation
+                self.selected_interpreter = interpreter
+                return implementation, interpreter
+            end
+        end
+    end
+
     KomandanModule.run = function(self)
+        if self.implementations ~= nil then
+            local implementation, interpreter = self:select_implementation()
+            implementation.run(self, interpreter)
+        end
     end
 
     KomandanModule.cleanup = function(self)
     end
 
+    KomandanModule.set_result = function(self, key, value)
+        self.extra_result = self.extra_result or {}
+        self.extra_result[key] = value
+    end
+
+    KomandanModule.facts = function(self)
+        if self.gathered_facts == nil then
+            local facts = self.cached_facts(self.host)
+            if facts == nil then
+                local result = self.ssh:cmdq(self.facts_command)
+                if result.exit_code ~= 0 then
+                    error(self.name .. ": failed to gather facts: " .. result.stderr)
+                end
+                facts = self.parse_facts(result.stdout, self.host)
+            end
+            self.gathered_facts = facts
+        end
+        return self.gathered_facts
+    end
+
+    KomandanModule.facts_command = $facts_command
+    KomandanModule.parse_facts = $parse_facts
+    KomandanModule.cached_facts = $cached_facts
+
+    KomandanModule.render_template = $render_template
+    KomandanModule.edit_json = $edit_json
+    KomandanModule.decode_json = $decode_json
+
+    KomandanModule.local_sha256 = $local_sha256
+    KomandanModule.sha256_command = $sha256_command
+
+    KomandanModule.remote_sha256 = function(self, path)
+        local result = self.ssh:cmdq(self.sha256_command(path))
+        if result.exit_code ~= 0 then
+            error("failed to checksum " .. path .. ": " .. result.stderr)
+        end
+        local lines = {}
+        for line in string.gmatch(result.stdout, "[^
+]+") do
+            table.insert(lines, line)
+        end
+        if lines[1] == "file" then
+            return string.match(lines[2] or "", "^(%x+)")
+        elseif lines[1] == "directory" then
+            local sums = {}
+            for i = 2, #lines do
+                local sum, name = string.match(lines[i], "^(%x+)%s+%./(.+)$")
+                if sum ~= nil then
+                    sums[name] = sum
+                end
+            end
+            return sums
+        end
+        return nil
+    end
+
+    KomandanModule.checksums_differ = function(wanted, actual)
+        if type(wanted) ~= "table" then
+            return wanted ~= actual
+        end
+        if type(actual) ~= "table" then
+            return true
+        end
+        for name, sum in pairs(wanted) do
+            if actual[name] ~= sum then
+                return true
+            end
+        end
+        return false
+    end
+
     return KomandanModule
         })
     .eval::<Table>()
@@ -0,0 +1,226 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+/// `brew` with the usual Homebrew prefixes on `PATH`, since non-login SSH
+/// shells on macOS rarely have them, and without the implicit auto-update.
+const BREW: &str = "PATH=\"/opt/homebrew/bin:/usr/local/bin:/home/linuxbrew/.linuxbrew/bin:$PATH\" HOMEBREW_NO_AUTO_UPDATE=1 HOMEBREW_NO_ENV_HINTS=1 brew";
+
+/// Manage Homebrew formulae, or casks with `cask = true`, on macOS (and
+/// Linuxbrew) hosts. `name` is a package or a list of packages; `state` is
+/// `present` (default), `absent` or `latest`. `tap` adds one or more taps
+/// (e.g. `"homebrew/cask-fonts"`) first. `update_homebrew = true` runs
+/// `brew update` before anything else, and `upgrade_all = true` upgrades
+/// every outdated package. Only packages not yet in `brew list` (or, with
+/// `latest`, in `brew outdated`) are installed or upgraded. Sets
+/// `installed`, `upgraded` and `removed` (lists of names) in the task result.
+pub fn brew(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let packages = super::name_list(&params, "name", "@._+-/")?;
+    let taps = super::name_list(&params, "tap", "._-/")?;
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let update_homebrew = params
+        .get::<Option<bool>>("update_homebrew")?
+        .unwrap_or(false);
+    let upgrade_all = params.get::<Option<bool>>("upgrade_all")?.unwrap_or(false);
+    if packages.is_empty() && taps.is_empty() && !update_homebrew && !upgrade_all {
+        return Err(RuntimeError(
+            "'name', 'tap', 'update_homebrew' or 'upgrade_all' parameter is required".to_string(),
+        ));
+    }
+    params.set("update_homebrew", update_homebrew)?;
+    params.set("upgrade_all", upgrade_all)?;
+    let kind = if params.get::<Option<bool>>("cask")?.unwrap_or(false) {
+        "--cask"
+    } else {
+        "--formula"
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "brew" })
+
+            module.params = $params
+            module.packages = $packages
+            module.taps = $taps
+            module.kind = $kind
+            module.brew_command = $BREW
+
+            module.brew = function(self, args)
+                local result = self.ssh:cmdq(self.brew_command .. " " .. args)
+                if result.exit_code ~= 0 then
+                    error("brew: brew " .. args .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The lines brew prints for args, as a set.
+            module.brew_set = function(self, args)
+                local lines = {}
+                for line in string.gmatch(self:brew(args), "[^\n]+") do
+                    lines[string.lower(line)] = true
+                end
+                return lines
+            end
+
+            -- What a run would do: taps to add and packages to install,
+            -- upgrade or remove. brew lists packages of other taps by
+            -- their short name.
+            module.plan = function(self)
+                local plan = { taps = {}, install = {}, upgrade = {}, remove = {}, upgrade_all = false }
+                if #self.taps > 0 then
+                    local current = self:brew_set("tap")
+                    for _, tap in ipairs(self.taps) do
+                        if not current[string.lower(tap)] then
+                            table.insert(plan.taps, tap)
+                        end
+                    end
+                end
+                local outdated = {}
+                if self.params.state == "latest" or self.params.upgrade_all then
+                    outdated = self:brew_set("outdated " .. self.kind .. " --quiet")
+                    plan.upgrade_all = self.params.upgrade_all and next(outdated) ~= nil
+                end
+                if #self.packages > 0 then
+                    local installed = self:brew_set("list " .. self.kind .. " -1")
+                    for _, package in ipairs(self.packages) do
+                        local short = string.lower(string.match(package, "([^/]+)$"))
+                        local present = installed[short] or installed[string.lower(package)]
+                        if self.params.state == "absent" then
+                            if present then
+                                table.insert(plan.remove, package)
+                            end
+                        elseif not present then
+                            table.insert(plan.install, package)
+                        elseif outdated[short] or outdated[string.lower(package)] then
+                            table.insert(plan.upgrade, package)
+                        end
+                    end
+                end
+                self:set_result("installed", plan.install)
+                self:set_result("upgraded", plan.upgrade)
+                self:set_result("removed", plan.remove)
+                return plan
+            end
+
+            module.changes = function(plan)
+                return #plan.taps > 0 or #plan.install > 0 or #plan.upgrade > 0
+                    or #plan.remove > 0 or plan.upgrade_all
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self.changes(self:plan()))
+            end
+
+            module.run = function(self)
+                if self.params.update_homebrew then
+                    self:brew("update")
+                end
+                local plan = self:plan()
+                for _, tap in ipairs(plan.taps) do
+                    self:brew("tap " .. tap)
+                end
+                if #plan.install > 0 then
+                    self:brew("install " .. self.kind .. " " .. table.concat(plan.install, " "))
+                end
+                if plan.upgrade_all then
+                    self:brew("upgrade " .. self.kind)
+                elseif #plan.upgrade > 0 then
+                    self:brew("upgrade " .. self.kind .. " " .. table.concat(plan.upgrade, " "))
+                end
+                if #plan.remove > 0 then
+                    self:brew("uninstall " .. self.kind .. " " .. table.concat(plan.remove, " "))
+                end
+                self.ssh:set_changed(self.changes(plan))
+            end
+
+            return module
+        })
+        .set_name("brew")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_brew_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = brew(&lua, lua.create_table()?);
+        assert!(result.is_err_and(|e| e.to_string().contains("'name', 'tap'")));
+
+        let params = lua.create_table()?;
+        params.set("name", "wget; rm -rf /")?;
+        assert!(brew(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "--force")?;
+        assert!(brew(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "wget")?;
+        params.set("state", "installed")?;
+        assert!(brew(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("update_homebrew", true)?;
+        assert!(brew(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_brew_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local outputs = {
+                    ["tap"] = "homebrew/core\nhomebrew/cask-fonts\n",
+                    ["list --formula -1"] = "wget\npython@3.12\nterraform\n",
+                    ["outdated --formula --quiet"] = "python@3.12\n",
+                }
+                local function plan(params)
+                    local module = komandan.modules.brew(params)
+                    module.brew = function(_, args) return outputs[args] or "" end
+                    module.set_result = function() end
+                    local plan = module:plan()
+                    return {
+                        table.concat(plan.taps, ","),
+                        table.concat(plan.install, ","),
+                        table.concat(plan.upgrade, ","),
+                        table.concat(plan.remove, ","),
+                        tostring(plan.upgrade_all),
+                    }
+                end
+                return {
+                    plan({ name = { "wget", "jq" }, tap = { "homebrew/cask-fonts", "hashicorp/tap" } }),
+                    plan({ name = { "python@3.12", "hashicorp/tap/terraform" }, state = "latest" }),
+                    plan({ name = { "wget", "jq" }, state = "absent" }),
+                    plan({ upgrade_all = true }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["hashicorp/tap", "jq", "", "", "false"],
+                ["", "", "python@3.12", "", "false"],
+                ["", "", "", "wget", "false"],
+                ["", "", "", "", "true"],
+            ]
+        );
+        Ok(())
+    }
+}
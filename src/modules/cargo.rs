@@ -0,0 +1,278 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// `cargo` with the default install directory on `PATH`, since rustup only
+/// adds it for login shells.
+const CARGO: &str = "PATH=\"${CARGO_HOME:-$HOME/.cargo}/bin:$PATH\" cargo";
+
+/// Install Rust binaries with `cargo install`. `name` is a crate or a list
+/// of crates and `version` pins a single `name`. `locked = true` builds
+/// with the crate's `Cargo.lock`, `features` is a list of features to
+/// enable, and `root` installs below another directory than `~/.cargo`.
+/// `state` is `present` (default), `absent` or `latest` (compared with
+/// `cargo search`). A crate counts as installed when `cargo install --list`
+/// records it and all of its binaries are still in the `bin` directory; a
+/// crate with missing binaries is reinstalled. Sets `installed` and
+/// `removed` (lists of crates) in the task result.
+pub fn cargo(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let crates = super::name_list(&params, "name", "_-")?;
+    if crates.is_empty() {
+        return Err(RuntimeError("'name' parameter is required".to_string()));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let version = params.get::<Option<String>>("version")?;
+    if let Some(version) = &version {
+        if crates.len() != 1 || state != "present" {
+            return Err(RuntimeError(
+                "'version' parameter needs a single 'name' and state 'present'".to_string(),
+            ));
+        }
+        if version.is_empty()
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".+-".contains(c))
+        {
+            return Err(RuntimeError(format!("Invalid version: '{version}'")));
+        }
+    }
+    let features = super::name_list(&params, "features", "_-/")?;
+
+    let root = params.get::<Option<String>>("root")?;
+    let root_option = root.as_deref().map_or_else(String::new, |root| {
+        format!(" --root {}", escape_shell_value(root))
+    });
+    let bin_dir = root.as_deref().map_or_else(
+        || "\"${CARGO_HOME:-$HOME/.cargo}/bin\"".to_string(),
+        |root| format!("{}/bin", escape_shell_value(root)),
+    );
+    let mut install_command = format!("{CARGO} install{root_option}");
+    if params.get::<Option<bool>>("locked")?.unwrap_or(false) {
+        install_command.push_str(" --locked");
+    }
+    if !features.is_empty() {
+        let _ = write!(install_command, " --features {}", features.join(","));
+    }
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "cargo" })
+
+            module.params = $params
+            module.crates = $crates
+            module.version = $version
+            module.list_command = $CARGO .. " install --list" .. $root_option
+            module.bin_command = "ls -1 " .. $bin_dir
+            module.search_command = $CARGO .. " search --limit 1"
+            module.install_command = $install_command
+            module.uninstall_command = $CARGO .. " uninstall" .. $root_option
+
+            module.cargo = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("cargo: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Installed crates from cargo install --list, which prints
+            -- "name v1.2.3:" followed by its indented binaries.
+            module.parse_list = function(output)
+                local installed, current = {}, nil
+                for line in string.gmatch(output, "[^\n]+") do
+                    local name, version = string.match(line, "^(%S+) v([^%s:]+)")
+                    if name ~= nil then
+                        current = { version = version, binaries = {} }
+                        installed[name] = current
+                    elseif current ~= nil then
+                        local binary = string.match(line, "^%s+(%S+)$")
+                        if binary ~= nil then
+                            table.insert(current.binaries, binary)
+                        end
+                    end
+                end
+                return installed
+            end
+
+            -- The newest version of name on crates.io.
+            module.latest_version = function(self, name)
+                local output = self:cargo(self.search_command .. " " .. name)
+                local found, version = string.match(output, "^(%S+) = \"([^\"]+)\"")
+                if found ~= name then
+                    error("cargo: crate " .. name .. " not found in the registry")
+                end
+                return version
+            end
+
+            -- Crates to install, whether to force each install, and crates
+            -- to remove.
+            module.plan = function(self)
+                local install, force, remove = {}, {}, {}
+                local installed = self.parse_list(self:cargo(self.list_command))
+                local binaries = {}
+                local listing = self.ssh:cmdq(self.bin_command)
+                for binary in string.gmatch(listing.stdout, "[^\n]+") do
+                    binaries[binary] = true
+                end
+                for _, name in ipairs(self.crates) do
+                    local current = installed[name]
+                    if self.params.state == "absent" then
+                        if current ~= nil then
+                            table.insert(remove, name)
+                        end
+                    elseif current == nil then
+                        table.insert(install, name)
+                    else
+                        local complete = true
+                        for _, binary in ipairs(current.binaries) do
+                            complete = complete and binaries[binary] == true
+                        end
+                        local wanted = self.version
+                        if self.params.state == "latest" then
+                            wanted = self:latest_version(name)
+                        end
+                        if not complete or (wanted ~= nil and current.version ~= wanted) then
+                            table.insert(install, name)
+                            force[name] = not complete
+                        end
+                    end
+                end
+                return install, force, remove
+            end
+
+            module.dry_run = function(self)
+                local install, _, remove = self:plan()
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(#install > 0 or #remove > 0)
+            end
+
+            module.run = function(self)
+                local install, force, remove = self:plan()
+                for _, name in ipairs(install) do
+                    local command = self.install_command
+                    if self.version ~= nil then
+                        command = command .. " --version " .. self.version
+                    end
+                    if force[name] then
+                        command = command .. " --force"
+                    end
+                    self:cargo(command .. " " .. name)
+                end
+                if #remove > 0 then
+                    self:cargo(self.uninstall_command .. " " .. table.concat(remove, " "))
+                end
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(#install > 0 or #remove > 0)
+            end
+
+            return module
+        })
+        .set_name("cargo")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_cargo_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(cargo(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", lua.create_sequence_from(["ripgrep", "fd-find"])?)?;
+        params.set("version", "14.1.0")?;
+        assert!(cargo(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "ripgrep")?;
+        params.set("features", "pcre2; reboot")?;
+        assert!(cargo(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cargo_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.cargo({
+                    name = "ripgrep", locked = true, features = { "pcre2" }, root = "/opt/tools",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("install_command")?,
+            format!("{CARGO} install --root '/opt/tools' --locked --features pcre2")
+        );
+        assert_eq!(
+            module.get::<String>("bin_command")?,
+            "ls -1 '/opt/tools'/bin"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cargo_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local list = "ripgrep v14.0.3:\n    rg\nfd-find v9.0.0:\n    fd\n"
+                local function plan(params, binaries)
+                    local module = komandan.modules.cargo(params)
+                    module.cargo = function(self, command)
+                        if command == self.list_command then
+                            return list
+                        end
+                        return "ripgrep = \"14.1.0\"    # line-oriented search tool\n"
+                    end
+                    module.ssh = { cmdq = function() return { stdout = binaries, exit_code = 0 } end }
+                    local install, force, remove = module:plan()
+                    return {
+                        table.concat(install, ","),
+                        tostring(force.ripgrep == true),
+                        table.concat(remove, ","),
+                    }
+                end
+                return {
+                    plan({ name = { "ripgrep", "bat" } }, "rg\nfd\n"),
+                    plan({ name = "ripgrep" }, "fd\n"),
+                    plan({ name = "ripgrep", state = "latest" }, "rg\nfd\n"),
+                    plan({ name = "ripgrep", version = "14.0.3" }, "rg\nfd\n"),
+                    plan({ name = { "fd-find", "bat" }, state = "absent" }, "rg\nfd\n"),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["bat", "false", ""],
+                ["ripgrep", "true", ""],
+                ["ripgrep", "false", ""],
+                ["", "false", ""],
+                ["", "false", "fd-find"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mlua::{IntoLua, Lua, Value};
+use sha2::{Digest, Sha256};
+
+use crate::local::escape_shell_value;
+
+/// Prints `file` and the checksum of `$1`, `directory` and a `<sum>  ./<path>`
+/// line per regular file below `$1`, or `absent`.
+const REMOTE_SHA256_SCRIPT: &str = r#"checksum() { if command -v sha256sum >/dev/null 2>&1; then sha256sum "$1"; else shasum -a 256 "$1"; fi; }
+if [ -d "$1" ]; then echo directory; cd "$1" && find . -type f | while IFS= read -r f; do checksum "$f"; done
+elif [ -f "$1" ]; then echo file; checksum "$1"
+else echo absent; fi"#;
+
+/// Lowercase hex SHA-256 of `data`.
+#[must_use]
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+pub(crate) fn to_hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// SHA-256 of the file at `path`, read in chunks.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// SHA-256 of every file below `dir`, keyed by its `/`-separated path
+/// relative to `dir`. Symlinks are followed, like `upload` does.
+///
+/// # Errors
+///
+/// Returns an error if a directory or file cannot be read.
+pub fn tree_sha256(dir: &Path) -> io::Result<BTreeMap<String, String>> {
+    fn walk(dir: &Path, prefix: &str, sums: &mut BTreeMap<String, String>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            if path.is_dir() {
+                walk(&path, &format!("{name}/"), sums)?;
+            } else {
+                sums.insert(name, file_sha256(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    let mut sums = BTreeMap::new();
+    walk(dir, "", &mut sums)?;
+    Ok(sums)
+}
+
+/// Lua function `local_sha256(path)` for files on the control machine: the
+/// checksum of a file, a table of checksums (see [`tree_sha256`]) for a
+/// directory, or `nil` if `path` does not exist.
+///
+/// # Errors
+///
+/// Returns an error if `path` exists but cannot be read.
+pub fn lua_local_sha256(lua: &Lua, path: String) -> mlua::Result<Value> {
+    let path = PathBuf::from(path);
+    if path.is_dir() {
+        tree_sha256(&path)
+            .map_err(|e| mlua::Error::external(format!("{}: {e}", path.display())))?
+            .into_lua(lua)
+    } else if path.exists() {
+        file_sha256(&path)
+            .map_err(|e| mlua::Error::external(format!("{}: {e}", path.display())))?
+            .into_lua(lua)
+    } else {
+        Ok(Value::Nil)
+    }
+}
+
+/// Command whose output `KomandanModule:remote_sha256()` parses. A single
+/// `sh -c` invocation, so it works under sudo.
+#[must_use]
+pub fn remote_sha256_command(path: &str) -> String {
+    format!(
+        "sh -c {} komandan-sha256 {}",
+        escape_shell_value(REMOTE_SHA256_SCRIPT),
+        escape_shell_value(path)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::CommandExecutor;
+    use crate::local::LocalSession;
+
+    #[test]
+    fn test_sha256_hex() -> io::Result<()> {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data");
+        fs::write(&path, "komandan")?;
+        assert_eq!(file_sha256(&path)?, sha256_hex(b"komandan"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_sha256_matches_remote_listing() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("conf.d"))?;
+        fs::write(dir.path().join("app.conf"), "a")?;
+        fs::write(dir.path().join("conf.d/extra.conf"), "b")?;
+
+        let local = tree_sha256(dir.path())?;
+        assert_eq!(
+            local.keys().collect::<Vec<_>>(),
+            vec!["app.conf", "conf.d/extra.conf"]
+        );
+
+        let session = LocalSession::new();
+        let path = dir.path().to_string_lossy();
+        let (stdout, _, exit_code) = session.cmdq(&remote_sha256_command(&path))?;
+        assert_eq!(exit_code, 0);
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some("directory"));
+        for line in lines {
+            let (sum, name) = line
+                .split_once("  ./")
+                .ok_or_else(|| anyhow::anyhow!("unexpected line {line}"))?;
+            assert_eq!(local.get(name).map(String::as_str), Some(sum));
+        }
+        Ok(())
+    }
+}
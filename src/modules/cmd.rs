@@ -1,6 +1,42 @@
-use mlua::{ExternalResult, Lua, Table, chunk};
+use std::sync::Once;
 
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+/// Warns once per run that `cmd` still reports every run as changed.
+static IMPLICIT_CHANGED_WARNING: Once = Once::new();
+
+/// Run the shell command `cmd` on the host. Whether the run counts as a
+/// change is set by `changed_if_stdout` / `changed_if_stderr`, Lua patterns
+/// matched against the command's output, or else by `changed_by_default`
+/// (default `true`, which is deprecated and warns once per run). A dry run
+/// does not run the command; it reports a change unless no hint is set and
+/// `changed_by_default = false`.
 pub fn cmd(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    match params.get::<Value>("changed_by_default")? {
+        Value::Nil => {
+            let has_hints = !params.get::<Value>("changed_if_stdout")?.is_nil()
+                || !params.get::<Value>("changed_if_stderr")?.is_nil();
+            if !has_hints {
+                IMPLICIT_CHANGED_WARNING.call_once(|| {
+                    tracing::warn!(
+                        "The cmd module reports every run as changed. This implicit default is deprecated: set changed_by_default = false (per task or in module_defaults) to report read-only commands as OK."
+                    );
+                });
+            }
+        }
+        Value::Boolean(_) => {}
+        _ => {
+            return Err(RuntimeError(
+                "changed_by_default must be a boolean".to_string(),
+            ));
+        }
+    }
+    for hint in ["changed_if_stdout", "changed_if_stderr"] {
+        if !matches!(params.get::<Value>(hint)?, Value::Nil | Value::String(_)) {
+            return Err(RuntimeError(format!("{hint} must be a Lua pattern string")));
+        }
+    }
+
     let base_module = super::base_module(lua)?;
     let module = lua
         .load(chunk! {
@@ -8,9 +44,23 @@ pub fn cmd(lua: &Lua, params: Table) -> mlua::Result<Table> {
 
             module.params = $params
 
+            module.dry_run = function(self)
+                local has_hints = self.params.changed_if_stdout ~= nil or self.params.changed_if_stderr ~= nil
+                self.ssh:set_changed(has_hints or self.params.changed_by_default ~= false)
+            end
+
             module.run = function(self)
-                self.ssh:cmd(self.params.cmd)
-                self.ssh:set_changed(true)
+                local result = self.ssh:cmd(self.params.cmd)
+
+                local stdout_hint = self.params.changed_if_stdout
+                local stderr_hint = self.params.changed_if_stderr
+                if stdout_hint ~= nil or stderr_hint ~= nil then
+                    local changed = (stdout_hint ~= nil and string.find(result.stdout, stdout_hint) ~= nil)
+                        or (stderr_hint ~= nil and string.find(result.stderr, stderr_hint) ~= nil)
+                    self.ssh:set_changed(changed)
+                else
+                    self.ssh:set_changed(self.params.changed_by_default ~= false)
+                end
             end
 
             return module
@@ -21,3 +71,67 @@ pub fn cmd(lua: &Lua, params: Table) -> mlua::Result<Table> {
 
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+
+    fn run_cmd(lua: &Lua, params: Table) -> mlua::Result<Table> {
+        let task = lua.create_table()?;
+        task.set(1, cmd(lua, params)?)?;
+        task.set("name", "cmd test")?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+        crate::komando::komando(lua, (Value::Table(task), Value::Table(host)))
+    }
+
+    #[test]
+    fn test_cmd_changed_by_default() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("cmd", "true")?;
+        assert!(run_cmd(&lua, params)?.get::<bool>("changed")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmd_unchanged_when_opted_out() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("cmd", "uname")?;
+        params.set("changed_by_default", false)?;
+        assert!(!run_cmd(&lua, params)?.get::<bool>("changed")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmd_changed_if_stdout_hint() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("cmd", "echo 'Setting up nginx'")?;
+        params.set("changed_if_stdout", "Setting up")?;
+        assert!(run_cmd(&lua, params)?.get::<bool>("changed")?);
+
+        let params = lua.create_table()?;
+        params.set("cmd", "echo 'nginx is already the newest version'")?;
+        params.set("changed_if_stdout", "Setting up")?;
+        assert!(!run_cmd(&lua, params)?.get::<bool>("changed")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmd_invalid_hint_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("cmd", "true")?;
+        params.set("changed_by_default", "no")?;
+        assert!(cmd(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("cmd", "true")?;
+        params.set("changed_if_stderr", 1)?;
+        assert!(cmd(&lua, params).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// The octal `mode` as `stat -c %a` prints it, e.g. `"0644"` as `"644"`.
+fn normalize_mode(mode: &str) -> mlua::Result<String> {
+    if mode.is_empty() || mode.len() > 4 || !mode.chars().all(|c| ('0'..='7').contains(&c)) {
+        return Err(RuntimeError(format!(
+            "Invalid mode: '{mode}'. Use an octal mode such as '0644'"
+        )));
+    }
+    let digits = mode.trim_start_matches('0');
+    Ok(if digits.is_empty() { "0" } else { digits }.to_string())
+}
+
+/// Copy a file to `dst` on the host from one of: the inline `content`, the
+/// file `src` on the control machine, or the file `src` on the host itself
+/// with `remote_src = true`. The file is written next to `dst` and moved
+/// into place, keeping the mode and owner of a file it replaces. Changes
+/// are detected by SHA-256, so an identical file is left alone. `mode`
+/// (octal), `owner` and `group` are applied to `dst`; `backup = true`
+/// saves the replaced file as `<dst>.<timestamp>.bak` and sets its path
+/// as `backup_file` in the task result. Sets `checksum` (SHA-256 of the
+/// copied content) in the task result.
+pub fn copy(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let dst = params
+        .get::<Option<String>>("dst")?
+        .ok_or_else(|| RuntimeError("'dst' parameter is required".to_string()))?;
+    if dst.is_empty() || dst.ends_with('/') {
+        return Err(RuntimeError(format!(
+            "Invalid dst: '{dst}'. Use the path of the file to write"
+        )));
+    }
+    let remote_src = params.get::<Option<bool>>("remote_src")?.unwrap_or(false);
+    params.set("remote_src", remote_src)?;
+    let content = params.get::<Option<mlua::String>>("content")?;
+    let src = params.get::<Option<String>>("src")?;
+    let content_sha256 = match (&src, &content) {
+        (Some(_), Some(_)) => {
+            return Err(RuntimeError(
+                "'src' and 'content' parameters are mutually exclusive".to_string(),
+            ));
+        }
+        (None, None) => {
+            return Err(RuntimeError(
+                "'src' or 'content' parameter is required".to_string(),
+            ));
+        }
+        (None, Some(_)) if remote_src => {
+            return Err(RuntimeError(
+                "'remote_src' parameter requires 'src'".to_string(),
+            ));
+        }
+        (None, Some(content)) => Some(super::checksum::sha256_hex(&content.as_bytes())),
+        (Some(src), None) if !remote_src && !std::path::Path::new(src).is_file() => {
+            return Err(RuntimeError(format!("src is not a file: {src}")));
+        }
+        (Some(_), None) => None,
+    };
+    if let Some(mode) = params.get::<Option<String>>("mode")? {
+        params.set("mode", normalize_mode(&mode)?)?;
+    }
+    let backup = params.get::<Option<bool>>("backup")?.unwrap_or(false);
+    params.set("backup", backup)?;
+    let attributes = lua.create_table()?;
+    for (name, program) in [("mode", "chmod"), ("owner", "chown"), ("group", "chgrp")] {
+        if let Some(value) = params.get::<Option<String>>(name)? {
+            let attribute = lua.create_table()?;
+            attribute.set(
+                "command",
+                format!(
+                    "{program} {} {}",
+                    escape_shell_value(&value),
+                    escape_shell_value(&dst)
+                ),
+            )?;
+            attribute.set("value", value)?;
+            attributes.set(name, attribute)?;
+        }
+    }
+
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+    let tmp_path = format!("{dst}.komandan-{random_file_name}");
+    let quoted_dst = escape_shell_value(&dst);
+    let quoted_tmp = escape_shell_value(&tmp_path);
+    let install_command = format!(
+        "{{ [ ! -e {quoted_dst} ] || {{ chmod --reference={quoted_dst} {quoted_tmp}; chown --reference={quoted_dst} {quoted_tmp} 2>/dev/null; true; }}; }} && mv -f {quoted_tmp} {quoted_dst}"
+    );
+    let remote_copy_command = src
+        .as_deref()
+        .filter(|_| remote_src)
+        .map(|src| format!("cp {} {quoted_tmp}", escape_shell_value(src)));
+    let backup_command = format!(
+        "backup={quoted_dst}.$(date +%Y%m%d%H%M%S).bak && cp -p {quoted_dst} \"$backup\" && echo \"$backup\""
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "copy" })
+
+            module.params = $params
+            module.content_sha256 = $content_sha256
+            module.attributes = $attributes
+            module.tmp_path = $tmp_path
+            module.stat_command = "stat -c '%a %U %G' " .. $quoted_dst
+            module.install_command = $install_command
+            module.remote_copy_command = $remote_copy_command
+            module.backup_command = $backup_command
+            module.remove_tmp_command = "rm -f " .. $quoted_tmp
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("copy: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The checksum of the content to copy.
+            module.wanted_checksum = function(self)
+                if self.content_sha256 ~= nil then
+                    return self.content_sha256
+                end
+                local sum
+                if self.params.remote_src then
+                    sum = self:remote_sha256(self.params.src)
+                else
+                    sum = self.local_sha256(self.params.src)
+                end
+                if type(sum) ~= "string" then
+                    error("copy: src is not a file: " .. self.params.src)
+                end
+                return sum
+            end
+
+            -- The checksum of dst, nil when it does not exist.
+            module.current_checksum = function(self)
+                local sum = self:remote_sha256(self.params.dst)
+                if type(sum) == "table" then
+                    error("copy: dst is a directory: " .. self.params.dst)
+                end
+                return sum
+            end
+
+            -- The chmod, chown and chgrp commands for the attributes dst
+            -- lacks; all of them when dst does not exist yet.
+            module.attribute_commands = function(self)
+                local current = {}
+                local result = self.ssh:cmdq(self.stat_command)
+                if result.exit_code == 0 then
+                    current.mode, current.owner, current.group = string.match(result.stdout, "^(%d+) (%S+) (%S+)")
+                end
+                local commands = {}
+                for _, name in ipairs({ "mode", "owner", "group" }) do
+                    local attribute = self.attributes[name]
+                    if attribute ~= nil and attribute.value ~= current[name] then
+                        table.insert(commands, attribute.command)
+                    end
+                end
+                return commands
+            end
+
+            module.dry_run = function(self)
+                local wanted = self:wanted_checksum()
+                self:set_result("checksum", wanted)
+                local changed = wanted ~= self:current_checksum()
+                self.ssh:set_changed(changed or #self:attribute_commands() > 0)
+            end
+
+            module.run = function(self)
+                local wanted = self:wanted_checksum()
+                self:set_result("checksum", wanted)
+                local current = self:current_checksum()
+                local changed = wanted ~= current
+                if changed then
+                    if current ~= nil and self.params.backup then
+                        self:set_result("backup_file", self:sh(self.backup_command))
+                    end
+                    if self.params.content ~= nil then
+                        self.ssh:write_remote_file(self.tmp_path, self.params.content)
+                    elseif self.params.remote_src then
+                        self:sh(self.remote_copy_command)
+                    else
+                        self.ssh:upload(self.params.src, self.tmp_path)
+                    end
+                    self:sh(self.install_command)
+                end
+                for _, command in ipairs(self:attribute_commands()) do
+                    self:sh(command)
+                    changed = true
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.cleanup = function(self)
+                self.ssh:cmdq(self.remove_tmp_command)
+            end
+
+            return module
+        })
+        .set_name("copy")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_mode() -> mlua::Result<()> {
+        assert_eq!(normalize_mode("0644")?, "644");
+        assert_eq!(normalize_mode("4755")?, "4755");
+        assert_eq!(normalize_mode("000")?, "0");
+        for invalid in ["", "u+x", "0888", "12345"] {
+            assert!(normalize_mode(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(copy(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("dst", "/tmp/copy.txt")?;
+        assert!(copy(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("dst", "/tmp/copy.txt")?;
+        params.set("src", "/nonexistent/file.txt")?;
+        assert!(copy(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("dst", "/tmp/copy.txt")?;
+        params.set("src", "/nonexistent/file.txt")?;
+        params.set("remote_src", true)?;
+        assert!(copy(&lua, params).is_ok());
+
+        let params = lua.create_table()?;
+        params.set("dst", "/tmp/copy.txt")?;
+        params.set("content", "hello")?;
+        params.set("remote_src", true)?;
+        assert!(copy(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_content_and_remote_src() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let dst = dir.path().join("app.conf").to_string_lossy().to_string();
+        let copied = dir.path().join("copied.conf").to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = |params: Table| -> mlua::Result<Table> {
+            let task = lua.create_table()?;
+            task.set(1, copy(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))
+        };
+        let content = |text: &str| -> mlua::Result<Table> {
+            let params = lua.create_table()?;
+            params.set("dst", dst.as_str())?;
+            params.set("content", text)?;
+            params.set("mode", "0600")?;
+            params.set("backup", true)?;
+            Ok(params)
+        };
+
+        assert!(run(content("listen 80\n")?)?.get::<bool>("changed")?);
+        assert!(!run(content("listen 80\n")?)?.get::<bool>("changed")?);
+        let result = run(content("listen 8080\n")?)?;
+        assert!(result.get::<bool>("changed")?);
+        let backup_file = result.get::<String>("backup_file")?;
+        assert_eq!(
+            std::fs::read_to_string(&backup_file).map_err(mlua::Error::external)?,
+            "listen 80\n"
+        );
+
+        let params = lua.create_table()?;
+        params.set("src", dst.as_str())?;
+        params.set("dst", copied.as_str())?;
+        params.set("remote_src", true)?;
+        let result = run(params)?;
+        assert!(result.get::<bool>("changed")?);
+        assert_eq!(
+            result.get::<String>("checksum")?,
+            super::super::checksum::sha256_hex(b"listen 8080\n")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&copied).map_err(mlua::Error::external)?,
+            "listen 8080\n"
+        );
+        Ok(())
+    }
+}
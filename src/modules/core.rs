@@ -1,31 +1,342 @@
-use mlua::Table;
+use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, Table, Value};
+
+use crate::defaults::Defaults;
 
 use super::{
-    apt, cmd, dnf, download, file, get_url, group, lineinfile, postgresql_user, script,
-    systemd_service, template, upload, user,
+    acme_certificate, apt, apt_key, brew, cargo, cmd, copy, cron, debconf, dnf, docker_compose,
+    download, fetch, file, filesystem, flatpak, gem, get_url, git, git_config, group, journald,
+    json_file, k8s, known_hosts, lineinfile, lvm_lv, lvm_vg, mongodb_user, npm, openrc_service,
+    openssl, package, patch, pip, postgresql_user, reboot_required, redis_config, script,
+    seboolean, sefcontext, setup, ssh_config, sshd_config, swap, systemd_service, systemd_timer,
+    template, timesync, upload, uri, user, wireguard, xml,
 };
 
-pub fn collect_core_modules(lua: &mlua::Lua) -> mlua::Result<Table> {
+/// Signature shared by every built-in module constructor.
+type ModuleConstructor = fn(&Lua, Table) -> mlua::Result<Table>;
+
+/// A built-in module as exposed under `komandan.modules`.
+pub struct CoreModule {
+    /// Key in the `komandan.modules` table.
+    pub name: &'static str,
+    /// One-line summary shown by `komandan modules list`.
+    pub description: &'static str,
+    constructor: ModuleConstructor,
+}
+
+/// Registry of built-in modules, in the order they are registered.
+pub const CORE_MODULES: &[CoreModule] = &[
+    CoreModule {
+        name: "acme_certificate",
+        description: "Obtain and renew certificates with certbot",
+        constructor: acme_certificate::acme_certificate,
+    },
+    CoreModule {
+        name: "apt",
+        description: "Manage packages on Debian/Ubuntu systems using apt",
+        constructor: apt::apt,
+    },
+    CoreModule {
+        name: "apt_key",
+        description: "Install APT repository signing keys as keyring files",
+        constructor: apt_key::apt_key,
+    },
+    CoreModule {
+        name: "brew",
+        description: "Manage Homebrew formulae, casks and taps",
+        constructor: brew::brew,
+    },
+    CoreModule {
+        name: "cargo",
+        description: "Install Rust binaries with cargo install",
+        constructor: cargo::cargo,
+    },
+    CoreModule {
+        name: "cmd",
+        description: "Execute a shell command",
+        constructor: cmd::cmd,
+    },
+    CoreModule {
+        name: "copy",
+        description: "Copy inline content or a file to the host",
+        constructor: copy::copy,
+    },
+    CoreModule {
+        name: "cron",
+        description: "Manage tagged crontab and /etc/cron.d entries",
+        constructor: cron::cron,
+    },
+    CoreModule {
+        name: "debconf",
+        description: "Preseed a debconf answer",
+        constructor: debconf::debconf,
+    },
+    CoreModule {
+        name: "dnf",
+        description: "Manage packages on Fedora/RHEL systems using dnf",
+        constructor: dnf::dnf,
+    },
+    CoreModule {
+        name: "docker_compose",
+        description: "Deploy a Docker Compose project",
+        constructor: docker_compose::docker_compose,
+    },
+    CoreModule {
+        name: "download",
+        description: "Download a file or directory from the host",
+        constructor: download::download,
+    },
+    CoreModule {
+        name: "fetch",
+        description: "Read a file from the host or download it to the control machine",
+        constructor: fetch::fetch,
+    },
+    CoreModule {
+        name: "file",
+        description: "Manage files, directories, links and their permissions",
+        constructor: file::file,
+    },
+    CoreModule {
+        name: "filesystem",
+        description: "Create a filesystem on a device unless one exists",
+        constructor: filesystem::filesystem,
+    },
+    CoreModule {
+        name: "flatpak",
+        description: "Manage Flatpak applications and remotes",
+        constructor: flatpak::flatpak,
+    },
+    CoreModule {
+        name: "gem",
+        description: "Manage Ruby gems",
+        constructor: gem::gem,
+    },
+    CoreModule {
+        name: "get_url",
+        description: "Download a file from a URL on the host",
+        constructor: get_url::get_url,
+    },
+    CoreModule {
+        name: "git",
+        description: "Clone or update a git repository checkout",
+        constructor: git::git,
+    },
+    CoreModule {
+        name: "git_config",
+        description: "Set or unset git configuration keys",
+        constructor: git_config::git_config,
+    },
+    CoreModule {
+        name: "group",
+        description: "Manage system groups",
+        constructor: group::group,
+    },
+    CoreModule {
+        name: "journald",
+        description: "Query the journal or a log file for matching lines",
+        constructor: journald::journald,
+    },
+    CoreModule {
+        name: "json_file",
+        description: "Edit a JSON file by JSON pointer or merge patch",
+        constructor: json_file::json_file,
+    },
+    CoreModule {
+        name: "k8s",
+        description: "Apply Kubernetes manifests with kubectl",
+        constructor: k8s::k8s,
+    },
+    CoreModule {
+        name: "known_hosts",
+        description: "Manage host entries in a known_hosts file",
+        constructor: known_hosts::known_hosts,
+    },
+    CoreModule {
+        name: "lineinfile",
+        description: "Insert or replace a line in a file",
+        constructor: lineinfile::lineinfile,
+    },
+    CoreModule {
+        name: "lvm_lv",
+        description: "Create, resize and remove LVM logical volumes",
+        constructor: lvm_lv::lvm_lv,
+    },
+    CoreModule {
+        name: "lvm_vg",
+        description: "Manage LVM volume groups and their physical volumes",
+        constructor: lvm_vg::lvm_vg,
+    },
+    CoreModule {
+        name: "mongodb_user",
+        description: "Manage MongoDB users and their roles",
+        constructor: mongodb_user::mongodb_user,
+    },
+    CoreModule {
+        name: "npm",
+        description: "Manage Node.js packages with npm, globally or per project",
+        constructor: npm::npm,
+    },
+    CoreModule {
+        name: "openrc_service",
+        description: "Manage OpenRC services on Alpine and Gentoo",
+        constructor: openrc_service::openrc_service,
+    },
+    CoreModule {
+        name: "openssl",
+        description: "Generate private keys, CSRs and self-signed certificates",
+        constructor: openssl::openssl,
+    },
+    CoreModule {
+        name: "package",
+        description: "Manage packages with the package manager the host has",
+        constructor: package::package,
+    },
+    CoreModule {
+        name: "patch",
+        description: "Update all packages within a maintenance window",
+        constructor: patch::patch,
+    },
+    CoreModule {
+        name: "pip",
+        description: "Manage Python packages with pip, optionally in a virtualenv",
+        constructor: pip::pip,
+    },
+    CoreModule {
+        name: "postgresql_user",
+        description: "Manage PostgreSQL roles",
+        constructor: postgresql_user::postgresql_user,
+    },
+    CoreModule {
+        name: "reboot_required",
+        description: "Detect whether the host needs a reboot",
+        constructor: reboot_required::reboot_required,
+    },
+    CoreModule {
+        name: "redis_config",
+        description: "Set Redis config values and persist them to redis.conf",
+        constructor: redis_config::redis_config,
+    },
+    CoreModule {
+        name: "script",
+        description: "Run a local script file or inline script on the host",
+        constructor: script::script,
+    },
+    CoreModule {
+        name: "seboolean",
+        description: "Set SELinux booleans, persistently by default",
+        constructor: seboolean::seboolean,
+    },
+    CoreModule {
+        name: "sefcontext",
+        description: "Manage SELinux file-context rules and relabel their paths",
+        constructor: sefcontext::sefcontext,
+    },
+    CoreModule {
+        name: "setup",
+        description: "Gather the facts of the host",
+        constructor: setup::setup,
+    },
+    CoreModule {
+        name: "ssh_config",
+        description: "Manage Include-based blocks of a user's SSH client config",
+        constructor: ssh_config::ssh_config,
+    },
+    CoreModule {
+        name: "sshd_config",
+        description: "Set SSH server options, validated with sshd -t",
+        constructor: sshd_config::sshd_config,
+    },
+    CoreModule {
+        name: "swap",
+        description: "Provision a swap file or partition",
+        constructor: swap::swap,
+    },
+    CoreModule {
+        name: "systemd_service",
+        description: "Manage systemd services",
+        constructor: systemd_service::systemd_service,
+    },
+    CoreModule {
+        name: "systemd_timer",
+        description: "Schedule a command with a systemd service and timer pair",
+        constructor: systemd_timer::systemd_timer,
+    },
+    CoreModule {
+        name: "template",
+        description: "Render a Jinja template to a file on the host",
+        constructor: template::template,
+    },
+    CoreModule {
+        name: "timesync",
+        description: "Configure chrony or systemd-timesyncd time synchronization",
+        constructor: timesync::timesync,
+    },
+    CoreModule {
+        name: "upload",
+        description: "Upload a file or directory to the host",
+        constructor: upload::upload,
+    },
+    CoreModule {
+        name: "uri",
+        description: "Send an HTTP request from the host",
+        constructor: uri::uri,
+    },
+    CoreModule {
+        name: "user",
+        description: "Manage system users",
+        constructor: user::user,
+    },
+    CoreModule {
+        name: "wireguard",
+        description: "Manage WireGuard interfaces with wg-quick",
+        constructor: wireguard::wireguard,
+    },
+    CoreModule {
+        name: "xml",
+        description: "Edit an XML file with XPath selectors",
+        constructor: xml::xml,
+    },
+];
+
+pub fn collect_core_modules(lua: &Lua) -> mlua::Result<Table> {
     let modules = lua.create_table()?;
-    modules.set("apt", lua.create_function(apt::apt)?)?;
-    modules.set("cmd", lua.create_function(cmd::cmd)?)?;
-    modules.set("dnf", lua.create_function(dnf::dnf)?)?;
-    modules.set("download", lua.create_function(download::download)?)?;
-    modules.set("file", lua.create_function(file::file)?)?;
-    modules.set("get_url", lua.create_function(get_url::get_url)?)?;
-    modules.set("group", lua.create_function(group::group)?)?;
-    modules.set("lineinfile", lua.create_function(lineinfile::lineinfile)?)?;
-    modules.set(
-        "postgresql_user",
-        lua.create_function(postgresql_user::postgresql_user)?,
-    )?;
-    modules.set("script", lua.create_function(script::script)?)?;
-    modules.set(
-        "systemd_service",
-        lua.create_function(systemd_service::systemd_service)?,
-    )?;
-    modules.set("template", lua.create_function(template::template)?)?;
-    modules.set("upload", lua.create_function(upload::upload)?)?;
-    modules.set("user", lua.create_function(user::user)?)?;
+    for module in CORE_MODULES {
+        let name = module.name;
+        let constructor = module.constructor;
+        modules.set(
+            name,
+            lua.create_function(move |lua, params: Option<Table>| {
+                // Modules without required parameters, such as `setup`, may
+                // be called with no argument.
+                let params = params.map_or_else(|| lua.create_table(), Ok)?;
+                apply_module_defaults(lua, name, &params)?;
+                constructor(lua, params)
+            })?,
+        )?;
+    }
     Ok(modules)
 }
+
+/// Fill the parameters `params` leaves unset with the defaults configured
+/// for `module` (`module_defaults` in `komandan.json` or
+/// `komandan.defaults:set_module_params()`).
+///
+/// # Errors
+///
+/// Returns an error if the defaults lock is poisoned or a value cannot be
+/// converted to Lua.
+pub fn apply_module_defaults(lua: &Lua, module: &str, params: &Table) -> mlua::Result<()> {
+    let defaults = Defaults::global()
+        .module_params
+        .read()
+        .map(|module_params| module_params.get(module).cloned())
+        .map_err(|_| RuntimeError("Failed to acquire read lock on module_params".to_string()))?;
+    let Some(defaults) = defaults else {
+        return Ok(());
+    };
+    for (key, value) in &defaults {
+        if params.get::<Value>(key.as_str())?.is_nil() {
+            params.set(key.as_str(), lua.to_value(value)?)?;
+        }
+    }
+    Ok(())
+}
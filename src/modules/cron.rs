@@ -0,0 +1,336 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Heredoc delimiter of the written crontab.
+const CRON_EOF: &str = "KOMANDAN_CRON_EOF";
+
+/// Schedules accepted by `special_time`, written as `@<name>`.
+const SPECIAL_TIMES: &[&str] = &[
+    "reboot", "yearly", "annually", "monthly", "weekly", "daily", "hourly",
+];
+
+/// The schedule of the entry: `@<special_time>`, or the five time fields
+/// with `*` for those not given.
+fn schedule(params: &Table) -> mlua::Result<String> {
+    let fields = ["minute", "hour", "day", "month", "weekday"];
+    if let Some(special) = params.get::<Option<String>>("special_time")? {
+        if let Some(name) = fields
+            .into_iter()
+            .find(|name| params.contains_key(*name).unwrap_or(false))
+        {
+            return Err(RuntimeError(format!(
+                "'special_time' and '{name}' parameters are mutually exclusive"
+            )));
+        }
+        let special = special.trim_start_matches('@');
+        if !SPECIAL_TIMES.contains(&special) {
+            return Err(RuntimeError(format!(
+                "Invalid special_time: {special}. Valid values are: {}.",
+                SPECIAL_TIMES.join(", ")
+            )));
+        }
+        return Ok(format!("@{special}"));
+    }
+
+    let mut values = Vec::with_capacity(fields.len());
+    for name in fields {
+        let value = params
+            .get::<Option<String>>(name)?
+            .unwrap_or_else(|| "*".to_string());
+        if value.is_empty()
+            || !value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "*/,-".contains(c))
+        {
+            return Err(RuntimeError(format!("Invalid {name}: '{value}'")));
+        }
+        values.push(value);
+    }
+    Ok(values.join(" "))
+}
+
+/// Manage the crontab entry `name`. The entry is tagged with a
+/// `# komandan: <name>` line, so re-runs update it in place and the rest of
+/// the crontab is left alone. `job` is the command, scheduled by `minute`,
+/// `hour`, `day`, `month` and `weekday` (each defaults to `*`) or by
+/// `special_time` (e.g. `"reboot"` or `"daily"`). The entry goes into the
+/// crontab of `user` (default: the connecting user), or with `cron_file`
+/// into `/etc/cron.d/<cron_file>`, run as `user` (default `root`); a
+/// `cron_file` left without entries is removed. `state = "absent"` removes
+/// the entry.
+pub fn cron(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if name.trim().is_empty() || name.contains('\n') {
+        return Err(RuntimeError(format!("Invalid entry name: '{name}'")));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let user = params.get::<Option<String>>("user")?;
+    let cron_file = params.get::<Option<String>>("cron_file")?;
+    if let Some(cron_file) = &cron_file
+        && (cron_file.is_empty()
+            || !cron_file
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c)))
+    {
+        // cron skips files in /etc/cron.d whose names contain a dot.
+        return Err(RuntimeError(format!(
+            "Invalid cron_file: '{cron_file}'. Use letters, digits, '_' and '-'"
+        )));
+    }
+
+    let entry = if state == "present" {
+        let job = params.get::<Option<String>>("job")?.ok_or_else(|| {
+            RuntimeError("'job' parameter is required unless state is 'absent'".to_string())
+        })?;
+        if job.trim().is_empty() || job.contains('\n') {
+            return Err(RuntimeError(
+                "'job' parameter must be a single line".to_string(),
+            ));
+        }
+        let schedule = schedule(&params)?;
+        Some(if cron_file.is_some() {
+            let user = user.as_deref().unwrap_or("root");
+            if user.is_empty()
+                || user.starts_with('-')
+                || !user
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+            {
+                return Err(RuntimeError(format!("Invalid user: '{user}'")));
+            }
+            format!("{schedule} {user} {}", job.trim())
+        } else {
+            format!("{schedule} {}", job.trim())
+        })
+    } else {
+        None
+    };
+
+    let (read_command, write_command, remove_command) = if let Some(cron_file) = &cron_file {
+        let path = format!("/etc/cron.d/{cron_file}");
+        (
+            format!("cat {path}"),
+            format!("touch {path} && chmod 644 {path} && cat > {path} <<'{CRON_EOF}'\n"),
+            Some(format!("rm -f {path}")),
+        )
+    } else {
+        let user_option = user.as_deref().map_or_else(String::new, |user| {
+            format!(" -u {}", escape_shell_value(user))
+        });
+        (
+            format!("crontab{user_option} -l"),
+            format!("crontab{user_option} - <<'{CRON_EOF}'\n"),
+            None,
+        )
+    };
+    let program = if cron_file.is_some() {
+        "cron"
+    } else {
+        "crontab"
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "cron" })
+
+            module.params = $params
+            module.marker = "# komandan: " .. $name
+            module.entry = $entry
+            module.program = $program
+            module.read_command = $read_command
+            module.write_command = $write_command
+            module.remove_command = $remove_command
+            module.cron_eof = $CRON_EOF
+
+            -- The current crontab; crontab -l fails when there is none yet.
+            module.current = function(self)
+                if self.remove_command == nil then
+                    self.ssh:requires("crontab")
+                end
+                local result = self.ssh:cmdq(self.read_command)
+                if result.exit_code ~= 0 then
+                    return ""
+                end
+                return result.stdout
+            end
+
+            -- The crontab with the tagged entry replaced in place, appended
+            -- or removed, keeping every other line as it is.
+            module.updated = function(self, current)
+                local lines = {}
+                local skip, found = false, false
+                if current ~= "" then
+                    for line in string.gmatch(current .. "\n", "([^\n]*)\n") do
+                        if skip then
+                            skip = false
+                        elseif line == self.marker then
+                            skip = true
+                            if self.entry ~= nil and not found then
+                                table.insert(lines, self.marker)
+                                table.insert(lines, self.entry)
+                            end
+                            found = true
+                        else
+                            table.insert(lines, line)
+                        end
+                    end
+                end
+                if self.entry ~= nil and not found then
+                    table.insert(lines, self.marker)
+                    table.insert(lines, self.entry)
+                end
+                return table.concat(lines, "\n")
+            end
+
+            module.dry_run = function(self)
+                local current = self:current()
+                self.ssh:set_changed(self:updated(current) ~= current)
+            end
+
+            module.run = function(self)
+                local current = self:current()
+                local updated = self:updated(current)
+                if updated == current then
+                    self.ssh:set_changed(false)
+                    return
+                end
+                local command
+                if updated == "" and self.remove_command ~= nil then
+                    command = self.remove_command
+                elseif updated == "" then
+                    command = self.write_command .. self.cron_eof
+                else
+                    command = self.write_command .. updated .. "\n" .. self.cron_eof
+                end
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("cron: failed to update the " .. self.program .. " entries: " .. result.stderr)
+                end
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("cron")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_cron_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(cron(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        let result = cron(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'job' parameter is required")));
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        params.set("job", "/usr/local/bin/backup")?;
+        params.set("hour", "3; reboot")?;
+        assert!(cron(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        params.set("job", "/usr/local/bin/backup")?;
+        params.set("special_time", "reboot")?;
+        params.set("minute", 5)?;
+        assert!(cron(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        params.set("state", "absent")?;
+        params.set("cron_file", "backup.conf")?;
+        assert!(cron(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cron_entries() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.cron({
+                    name = "backup", job = "/usr/local/bin/backup", minute = 30, hour = 3, weekday = "1-5", user = "deploy",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("entry")?,
+            "30 3 * * 1-5 /usr/local/bin/backup"
+        );
+        assert_eq!(
+            module.get::<String>("read_command")?,
+            "crontab -u 'deploy' -l"
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.cron({
+                    name = "warmup", job = "/opt/app/warmup", special_time = "@reboot", cron_file = "app",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("entry")?,
+            "@reboot root /opt/app/warmup"
+        );
+        assert!(
+            module
+                .get::<String>("write_command")?
+                .starts_with("touch /etc/cron.d/app && chmod 644 /etc/cron.d/app")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cron_updated() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local crontab = "MAILTO=ops\n\n# komandan: backup\n0 2 * * * /usr/local/bin/backup\n@daily /usr/bin/true"
+                local present = komandan.modules.cron({ name = "backup", job = "/usr/local/bin/backup", hour = 3, minute = 0 })
+                local absent = komandan.modules.cron({ name = "backup", state = "absent" })
+                return {
+                    present:updated(crontab),
+                    present:updated(""),
+                    absent:updated(crontab),
+                    absent:updated("# komandan: backup\n0 2 * * * /usr/local/bin/backup"),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            results,
+            [
+                "MAILTO=ops\n\n# komandan: backup\n0 3 * * * /usr/local/bin/backup\n@daily /usr/bin/true",
+                "# komandan: backup\n0 3 * * * /usr/local/bin/backup",
+                "MAILTO=ops\n\n@daily /usr/bin/true",
+                "",
+            ]
+        );
+        Ok(())
+    }
+}
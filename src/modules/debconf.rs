@@ -0,0 +1,233 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Types `debconf-set-selections` accepts.
+const VTYPES: [&str; 10] = [
+    "boolean",
+    "error",
+    "multiselect",
+    "note",
+    "password",
+    "seen",
+    "select",
+    "string",
+    "text",
+    "title",
+];
+
+/// `value` as debconf stores it: booleans as `true`/`false`, multiselect
+/// choices separated by `", "`.
+fn debconf_value(value: &Value, vtype: &str) -> mlua::Result<String> {
+    let value = match value {
+        Value::Boolean(value) => value.to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => value.to_str()?.to_string(),
+        Value::Table(choices) if vtype == "multiselect" => choices
+            .sequence_values::<String>()
+            .collect::<mlua::Result<Vec<_>>>()?
+            .join(", "),
+        _ => {
+            return Err(RuntimeError(
+                "'value' parameter must be a string, number or boolean".to_string(),
+            ));
+        }
+    };
+    if value.contains('\n') {
+        return Err(RuntimeError(
+            "'value' parameter must be a single line".to_string(),
+        ));
+    }
+    Ok(match vtype {
+        "boolean" => match value.to_lowercase().as_str() {
+            "true" | "yes" | "1" => "true".to_string(),
+            "false" | "no" | "0" => "false".to_string(),
+            _ => {
+                return Err(RuntimeError(format!(
+                    "Invalid boolean value: '{value}'. Use true or false"
+                )));
+            }
+        },
+        "multiselect" => value
+            .split(',')
+            .map(str::trim)
+            .filter(|choice| !choice.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => value,
+    })
+}
+
+/// Preseed the debconf answer to `question` of the package `name`, so the
+/// package installs non-interactively, e.g. `{ name = "postfix", question =
+/// "postfix/main_mailer_type", vtype = "select", value = "Internet Site" }`.
+/// Run it before the `apt` task installing the package. `vtype` is the
+/// debconf type (`string`, `boolean`, `select`, `multiselect`, `password`,
+/// ...); a `multiselect` value may be a list. The current answer is read
+/// with `debconf-show`, which does not reveal passwords, so a `password`
+/// is always set and reported as changed. `unseen = true` keeps the
+/// question unseen, so a later interactive configuration still asks it.
+pub fn debconf(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let mut required = Vec::with_capacity(3);
+    for name in ["name", "question", "vtype"] {
+        let value = params
+            .get::<Option<String>>(name)?
+            .ok_or_else(|| RuntimeError(format!("'{name}' parameter is required")))?;
+        if value.is_empty() || value.chars().any(char::is_whitespace) {
+            return Err(RuntimeError(format!("Invalid {name}: '{value}'")));
+        }
+        required.push(value);
+    }
+    let (name, question, vtype) = (&required[0], &required[1], &required[2]);
+    if !VTYPES.contains(&vtype.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid vtype: {vtype}. Valid types are: {}.",
+            VTYPES.join(", ")
+        )));
+    }
+    let value = match params.get::<Value>("value")? {
+        Value::Nil => {
+            return Err(RuntimeError("'value' parameter is required".to_string()));
+        }
+        value => debconf_value(&value, vtype)?,
+    };
+    let unseen = if params.get::<Option<bool>>("unseen")?.unwrap_or(false) {
+        " -u"
+    } else {
+        ""
+    };
+    let selection = format!("{name} {question} {vtype} {value}");
+    let set_command = format!(
+        "printf '%s\\n' {} | debconf-set-selections{unseen}",
+        escape_shell_value(&selection)
+    );
+    let show_command = format!("debconf-show {}", escape_shell_value(name));
+    let is_password = vtype == "password";
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "debconf" })
+
+            module.params = $params
+            module.question = $question
+            module.value = $value
+            module.is_password = $is_password
+            module.show_command = $show_command
+            module.set_command = $set_command
+
+            -- The current answer to the question, nil when it has none.
+            module.current = function(self)
+                self.ssh:requires({ "debconf-show", "debconf-set-selections" })
+                local result = self.ssh:cmdq(self.show_command)
+                if result.exit_code ~= 0 then
+                    error("debconf: debconf-show failed: " .. result.stderr)
+                end
+                return self:parse_show(result.stdout)
+            end
+
+            module.parse_show = function(self, output)
+                for line in string.gmatch(output, "[^\n]+") do
+                    local question, value = string.match(line, "^[%s%*]*([^:%s]+):%s?(.*)$")
+                    if question == self.question then
+                        return value
+                    end
+                end
+                return nil
+            end
+
+            module.is_changed = function(self)
+                return self.is_password or self:current() ~= self.value
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:is_changed())
+            end
+
+            module.run = function(self)
+                local changed = self:is_changed()
+                if changed then
+                    local result = self.ssh:cmdq(self.set_command)
+                    if result.exit_code ~= 0 then
+                        error("debconf: debconf-set-selections failed: " .. result.stderr)
+                    end
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            return module
+        })
+        .set_name("debconf")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_debconf_value() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert_eq!(debconf_value(&Value::Boolean(true), "boolean")?, "true");
+        assert_eq!(
+            debconf_value(&Value::String(lua.create_string("No")?), "boolean")?,
+            "false"
+        );
+        let choices = lua.create_sequence_from(["en_US.UTF-8 UTF-8", "de_DE.UTF-8 UTF-8"])?;
+        assert_eq!(
+            debconf_value(&Value::Table(choices), "multiselect")?,
+            "en_US.UTF-8 UTF-8, de_DE.UTF-8 UTF-8"
+        );
+        assert!(debconf_value(&Value::String(lua.create_string("maybe")?), "boolean").is_err());
+        assert!(debconf_value(&Value::String(lua.create_string("a\nb")?), "string").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debconf_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(debconf(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "postfix")?;
+        params.set("question", "postfix/main_mailer_type")?;
+        params.set("vtype", "choice")?;
+        params.set("value", "Internet Site")?;
+        assert!(debconf(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "postfix")?;
+        params.set("question", "postfix/main_mailer_type")?;
+        params.set("vtype", "select")?;
+        assert!(debconf(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debconf_commands_and_current() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (set_command, current, missing) = lua
+            .load(chunk! {
+                local module = komandan.modules.debconf({
+                    name = "postfix", question = "postfix/main_mailer_type", vtype = "select", value = "Internet Site",
+                })
+                local output = "  postfix/mailname: mail.example.com\n* postfix/main_mailer_type: Internet Site\n"
+                return module.set_command, module:parse_show(output), module:parse_show("")
+            })
+            .eval::<(String, String, Option<String>)>()?;
+        assert_eq!(
+            set_command,
+            "printf '%s\\n' 'postfix postfix/main_mailer_type select Internet Site' | debconf-set-selections"
+        );
+        assert_eq!(current, "Internet Site");
+        assert_eq!(missing, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// Directory holding one compose project per subdirectory, unless
+/// `project_dir` is given.
+const PROJECTS_DIR: &str = "/opt/komandan/compose";
+
+/// Whether `name` is usable as a compose project or service name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+/// The compose file: the local file `src`, or the inline `content`.
+fn compose_source(params: &Table) -> mlua::Result<String> {
+    match (
+        params.get::<Option<String>>("src")?,
+        params.get::<Option<String>>("content")?,
+    ) {
+        (Some(src), None) => std::fs::read_to_string(&src)
+            .map_err(|e| RuntimeError(format!("Failed to read compose file {src}: {e}"))),
+        (None, Some(content)) => Ok(content),
+        (Some(_), Some(_)) => Err(RuntimeError(
+            "'src' and 'content' parameters are mutually exclusive".to_string(),
+        )),
+        (None, None) => Err(RuntimeError(
+            "'src' or 'content' parameter is required unless state is 'absent'".to_string(),
+        )),
+    }
+}
+
+/// The replica counts of `scale`, a table of service names to counts.
+fn scale_overrides(params: &Table) -> mlua::Result<BTreeMap<String, u32>> {
+    let mut scale = BTreeMap::new();
+    if let Some(table) = params.get::<Option<Table>>("scale")? {
+        for pair in table.pairs::<String, u32>() {
+            let (service, count) = pair?;
+            if !is_valid_name(&service) {
+                return Err(RuntimeError(format!("Invalid service name: '{service}'")));
+            }
+            scale.insert(service, count);
+        }
+    }
+    Ok(scale)
+}
+
+/// Deploy the Docker Compose project `project` with `docker compose up -d`.
+/// The compose file is the local file `src` or the inline `content`,
+/// rendered as a template when `vars` is given, and is kept on the host as
+/// `compose.yaml` in `project_dir` (default
+/// `/opt/komandan/compose/<project>`). `scale` overrides the replicas of
+/// services, e.g. `{ worker = 3 }`. The task is changed when the compose
+/// file differs, or when the containers of a service do not match its
+/// config hash (`docker compose config --hash`) or replica count.
+/// `state = "absent"` runs `docker compose down` and removes the file.
+pub fn docker_compose(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let project = params
+        .get::<Option<String>>("project")?
+        .ok_or_else(|| RuntimeError("'project' parameter is required".to_string()))?;
+    if !is_valid_name(&project) || project.chars().any(|c| c.is_ascii_uppercase() || c == '.') {
+        return Err(RuntimeError(format!(
+            "Invalid project name: '{project}'. Use lowercase letters, digits, '_' and '-'"
+        )));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let vars = params.get::<Value>("vars")?;
+    if !vars.is_nil() && !vars.is_table() {
+        return Err(RuntimeError("'vars' parameter must be a table".to_string()));
+    }
+    let source = if state == "present" {
+        Some(compose_source(&params)?)
+    } else {
+        None
+    };
+    let source_sha256 = source
+        .as_deref()
+        .map(|source| super::checksum::sha256_hex(source.as_bytes()));
+    let scale = scale_overrides(&params)?;
+
+    let dir = params
+        .get::<Option<String>>("project_dir")?
+        .unwrap_or_else(|| format!("{PROJECTS_DIR}/{project}"));
+    let file = format!("{}/compose.yaml", dir.trim_end_matches('/'));
+    params.set("file", file.as_str())?;
+    let file = escape_shell_value(&file);
+    let compose = format!("docker compose -p {project} -f {file}");
+    let mkdir_command = format!("mkdir -p {}", escape_shell_value(&dir));
+    let mut up_command = format!("{compose} up -d --remove-orphans");
+    for (service, count) in &scale {
+        let _ = write!(up_command, " --scale {service}={count}");
+    }
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "docker_compose" })
+
+            module.params = $params
+            module.source = $source
+            module.source_sha256 = $source_sha256
+            module.scale = $scale
+            module.random_file_name = $random_file_name
+            module.file = $file
+            module.mkdir_command = $mkdir_command
+            module.config_hash_command = $compose .. " config --hash " .. "'*'"
+            module.ps_command = "docker ps -a --filter label=com.docker.compose.project=" .. $project
+                .. " --filter label=com.docker.compose.oneoff=False --format "
+                .. "'{{.Label \"com.docker.compose.service\"}} {{.Label \"com.docker.compose.config-hash\"}}'"
+            module.up_command = $up_command
+            module.down_command = "docker compose -p " .. $project .. " down --remove-orphans"
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("docker_compose: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The compose file content and its SHA-256, rendered with the
+            -- task variables and vars when vars is given.
+            module.render = function(self)
+                if self.params.vars == nil then
+                    return self.source, self.source_sha256
+                end
+                local vars = {}
+                for key, value in pairs(self.vars or {}) do
+                    vars[key] = value
+                end
+                for key, value in pairs(self.params.vars) do
+                    vars[key] = value
+                end
+                return self.render_template(self.source, vars)
+            end
+
+            -- Whether the containers differ from the compose file: a
+            -- service whose containers carry another config hash, or whose
+            -- container count is not its scale (default 1).
+            module.drift = function(self, hashes, containers)
+                local wanted = {}
+                for service, hash in string.gmatch(hashes, "(%S+)%s+(%x+)") do
+                    wanted[service] = hash
+                end
+                local counts = {}
+                for service, hash in string.gmatch(containers, "(%S+)%s+(%S+)") do
+                    if wanted[service] ~= hash then
+                        return true
+                    end
+                    counts[service] = (counts[service] or 0) + 1
+                end
+                for service in pairs(wanted) do
+                    if (counts[service] or 0) ~= (self.scale[service] or 1) then
+                        return true
+                    end
+                end
+                return false
+            end
+
+            module.containers = function(self)
+                return self:sh(self.ps_command)
+            end
+
+            module.absent = function(self, apply)
+                local containers = self:containers() ~= ""
+                local file = self:remote_sha256(self.params.file) ~= nil
+                if apply and containers then
+                    self:sh(self.down_command)
+                end
+                if apply and file then
+                    self:sh("rm -f " .. self.file)
+                end
+                self.ssh:set_changed(containers or file)
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("docker")
+                if self.params.state == "absent" then
+                    self:absent(false)
+                    return
+                end
+                local _, sha256 = self:render()
+                if self:remote_sha256(self.params.file) ~= sha256 then
+                    self.ssh:set_changed(true)
+                    return
+                end
+                self.ssh:set_changed(self:drift(self:sh(self.config_hash_command), self:containers()))
+            end
+
+            module.run = function(self)
+                self.ssh:requires("docker")
+                if self.params.state == "absent" then
+                    self:absent(true)
+                    return
+                end
+                local content, sha256 = self:render()
+                local file_changed = self:remote_sha256(self.params.file) ~= sha256
+                if file_changed then
+                    self:sh(self.mkdir_command)
+                    local tmpfile = self.ssh:get_tmpdir() .. "/." .. self.random_file_name
+                    self.ssh:write_remote_file(tmpfile, content)
+                    self:sh("mv " .. tmpfile .. " " .. self.file)
+                end
+                local drift = self:drift(self:sh(self.config_hash_command), self:containers())
+                if file_changed or drift then
+                    self:sh(self.up_command)
+                end
+                self.ssh:set_changed(file_changed or drift)
+            end
+
+            return module
+        })
+        .set_name("docker_compose")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_docker_compose_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(docker_compose(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("project", "web")?;
+        let result = docker_compose(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'src' or 'content'")));
+
+        let params = lua.create_table()?;
+        params.set("project", "Web App")?;
+        params.set("state", "absent")?;
+        assert!(docker_compose(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("project", "web")?;
+        params.set("content", "services: {}\n")?;
+        let scale = lua.create_table()?;
+        scale.set("worker;id", 2)?;
+        params.set("scale", scale)?;
+        assert!(docker_compose(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_docker_compose_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.docker_compose({
+                    project = "web", content = "services:\n  app:\n    image: nginx\n", scale = { worker = 3, app = 2 },
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("up_command")?,
+            "docker compose -p web -f '/opt/komandan/compose/web/compose.yaml' up -d --remove-orphans --scale app=2 --scale worker=3"
+        );
+        assert_eq!(
+            module.get::<String>("config_hash_command")?,
+            "docker compose -p web -f '/opt/komandan/compose/web/compose.yaml' config --hash '*'"
+        );
+        assert_eq!(
+            module.get::<String>("source_sha256")?,
+            super::super::checksum::sha256_hex(b"services:\n  app:\n    image: nginx\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_docker_compose_drift() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local module = komandan.modules.docker_compose({ project = "web", content = "services: {}", scale = { worker = 2 } })
+                local hashes = "app aaaa\nworker bbbb"
+                return {
+                    module:drift(hashes, "app aaaa\nworker bbbb\nworker bbbb"),
+                    module:drift(hashes, "app aaaa\nworker bbbb"),
+                    module:drift(hashes, "app cccc\nworker bbbb\nworker bbbb"),
+                    module:drift(hashes, ""),
+                }
+            })
+            .eval::<Vec<bool>>()?;
+        assert_eq!(results, [false, true, true, true]);
+        Ok(())
+    }
+}
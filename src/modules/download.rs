@@ -8,9 +8,23 @@ pub fn download(lua: &Lua, params: Table) -> mlua::Result<Table> {
 
             module.params = $params
 
+            module.is_changed = function(self)
+                local wanted = self:remote_sha256(self.params.src)
+                if wanted == nil then
+                    error("src does not exist: " .. self.params.src)
+                end
+                return self.checksums_differ(wanted, self.local_sha256(self.params.dst))
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:is_changed())
+            end
+
             module.run = function(self)
-                self.ssh:download(self.params.src, self.params.dst)
-                self.ssh:set_changed(true)
+                if self:is_changed() then
+                    self.ssh:download(self.params.src, self.params.dst)
+                    self.ssh:set_changed(true)
+                end
             end
 
             return module
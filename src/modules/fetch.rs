@@ -0,0 +1,206 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Read the file `src` from the host. Without `dest`, its contents are set
+/// as `content` in the task result (`base64 = true` returns them base64
+/// encoded, for binary files) and the task never reports a change. With
+/// `dest`, the file is downloaded to `<dest>/<host>/<src>` on the control
+/// machine, where `<host>` is the name or address of the host, so files
+/// fetched from several hosts do not overwrite each other; `flat = true`
+/// downloads it to `dest` itself. A local copy with the same SHA-256 is left
+/// alone. Sets `dest` (the local path) in the task result.
+pub fn fetch(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let src = params
+        .get::<Option<String>>("src")?
+        .ok_or_else(|| RuntimeError("'src' parameter is required".to_string()))?;
+    if !src.starts_with('/') {
+        return Err(RuntimeError(format!(
+            "Invalid src: '{src}'. Use an absolute path on the host"
+        )));
+    }
+    let dest = params.get::<Option<String>>("dest")?;
+    let base64 = params.get::<Option<bool>>("base64")?.unwrap_or(false);
+    params.set("base64", base64)?;
+    let flat = params.get::<Option<bool>>("flat")?.unwrap_or(false);
+    params.set("flat", flat)?;
+    if dest.is_none() && flat {
+        return Err(RuntimeError("'flat' parameter requires 'dest'".to_string()));
+    }
+    if dest.is_some() && base64 {
+        return Err(RuntimeError(
+            "'base64' parameter only applies without 'dest'".to_string(),
+        ));
+    }
+
+    let quoted = escape_shell_value(&src);
+    // cmdq strips trailing newlines, so a dot is printed after the file and
+    // removed again.
+    let read_command = if base64 {
+        format!("base64 < {quoted} | tr -d '\\n'")
+    } else {
+        format!("cat {quoted} && printf .")
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "fetch" })
+
+            module.params = $params
+            module.read_command = $read_command
+
+            -- The local path of the fetched file.
+            module.local_path = function(self)
+                if self.params.flat then
+                    return self.params.dest
+                end
+                local host = self.host or {}
+                local name = string.gsub(tostring(host.name or host.address or "localhost"), "[^%w%.%-_:]", "_")
+                return self.params.dest .. "/" .. name .. self.params.src
+            end
+
+            -- Whether the local copy differs from the file on the host.
+            module.is_changed = function(self)
+                local remote = self:remote_sha256(self.params.src)
+                if remote == nil then
+                    error("fetch: src does not exist: " .. self.params.src)
+                end
+                if type(remote) == "table" then
+                    error("fetch: src is a directory: " .. self.params.src)
+                end
+                return self.checksums_differ(remote, self.local_sha256(self:local_path()))
+            end
+
+            module.read = function(self)
+                local result = self.ssh:cmdq(self.read_command)
+                if result.exit_code ~= 0 then
+                    error("fetch: failed to read " .. self.params.src .. ": " .. result.stderr)
+                end
+                if self.params.base64 then
+                    return result.stdout
+                end
+                return string.sub(result.stdout, 1, -2)
+            end
+
+            module.dry_run = function(self)
+                if self.params.dest == nil then
+                    self:set_result("content", self:read())
+                    self.ssh:set_changed(false)
+                    return
+                end
+                self:set_result("dest", self:local_path())
+                self.ssh:set_changed(self:is_changed())
+            end
+
+            module.run = function(self)
+                if self.params.dest == nil then
+                    self:set_result("content", self:read())
+                    self.ssh:set_changed(false)
+                    return
+                end
+                local path = self:local_path()
+                self:set_result("dest", path)
+                local changed = self:is_changed()
+                if changed then
+                    self.ssh:download(self.params.src, path)
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            return module
+        })
+        .set_name("fetch")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_fetch_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(fetch(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("src", "etc/hosts")?;
+        assert!(fetch(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("src", "/etc/hosts")?;
+        params.set("flat", true)?;
+        assert!(fetch(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("src", "/etc/hosts")?;
+        params.set("dest", "fetched")?;
+        params.set("base64", true)?;
+        assert!(fetch(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_local_path() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let paths = lua
+            .load(chunk! {
+                local module = komandan.modules.fetch({ src = "/etc/nginx/nginx.conf", dest = "fetched" })
+                module.host = { name = "web 1", address = "10.0.0.5" }
+                local flat = komandan.modules.fetch({ src = "/etc/nginx/nginx.conf", dest = "nginx.conf", flat = true })
+                return { module:local_path(), flat:local_path() }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(paths, ["fetched/web_1/etc/nginx/nginx.conf", "nginx.conf"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_content_and_dest() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let src = dir.path().join("motd");
+        std::fs::write(&src, "welcome\n\n").map_err(mlua::Error::external)?;
+        let src = src.to_string_lossy().to_string();
+        let dest = dir.path().join("fetched").to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = |params: Table| -> mlua::Result<Table> {
+            params.set("src", src.as_str())?;
+            let task = lua.create_table()?;
+            task.set(1, fetch(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))
+        };
+
+        let result = run(lua.create_table()?)?;
+        assert!(!result.get::<bool>("changed")?);
+        assert_eq!(result.get::<String>("content")?, "welcome\n\n");
+
+        let params = lua.create_table()?;
+        params.set("base64", true)?;
+        assert_eq!(run(params)?.get::<String>("content")?, "d2VsY29tZQoK");
+
+        let params = lua.create_table()?;
+        params.set("dest", dest.as_str())?;
+        let result = run(params)?;
+        assert!(result.get::<bool>("changed")?);
+        let path = result.get::<String>("dest")?;
+        assert_eq!(path, format!("{dest}/localhost{src}"));
+        assert_eq!(
+            std::fs::read_to_string(&path).map_err(mlua::Error::external)?,
+            "welcome\n\n"
+        );
+
+        let params = lua.create_table()?;
+        params.set("dest", dest.as_str())?;
+        assert!(!run(params)?.get::<bool>("changed")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// The `mkfs` command for `fstype`, with `force` overwriting an existing
+/// filesystem and the filesystem `label`.
+fn mkfs_command(fstype: &str, force: bool, label: Option<&str>) -> mlua::Result<String> {
+    let force_option = match fstype {
+        "ext2" | "ext3" | "ext4" => "-F",
+        "xfs" | "btrfs" => "-f",
+        _ => {
+            return Err(RuntimeError(format!(
+                "Invalid fstype: {fstype}. Valid types are: ext2, ext3, ext4, xfs and btrfs."
+            )));
+        }
+    };
+    let mut command = format!("mkfs.{fstype}");
+    if fstype.starts_with("ext") {
+        command.push_str(" -q");
+    }
+    if force {
+        command.push(' ');
+        command.push_str(force_option);
+    }
+    if let Some(label) = label {
+        command.push_str(" -L ");
+        command.push_str(&escape_shell_value(label));
+    }
+    Ok(command)
+}
+
+/// Create a filesystem of `fstype` (`ext2`, `ext3`, `ext4`, `xfs` or
+/// `btrfs`) on the device `dev`, with the optional `label`. The current
+/// filesystem is read with `blkid`: a device that already has `fstype` is
+/// left alone, and one with another filesystem or a partition table is
+/// only reformatted with `force = true`, so data is never wiped by accident. Sets `previous` (the
+/// filesystem found, `nil` for none) in the task result.
+pub fn filesystem(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let dev = params
+        .get::<Option<String>>("dev")?
+        .ok_or_else(|| RuntimeError("'dev' parameter is required".to_string()))?;
+    if !dev.starts_with('/') {
+        return Err(RuntimeError(format!(
+            "Invalid device: '{dev}'. Use an absolute path such as /dev/sdb1"
+        )));
+    }
+    let fstype = params
+        .get::<Option<String>>("fstype")?
+        .ok_or_else(|| RuntimeError("'fstype' parameter is required".to_string()))?;
+    let force = params.get::<Option<bool>>("force")?.unwrap_or(false);
+    params.set("force", force)?;
+    let label = params.get::<Option<String>>("label")?;
+    let mkfs = mkfs_command(&fstype, force, label.as_deref())?;
+
+    let dev = escape_shell_value(&dev);
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "filesystem" })
+
+            module.params = $params
+            module.exists_command = "[ -e " .. $dev .. " ]"
+            module.blkid_command = "blkid -p -o export " .. $dev
+            module.mkfs_command = $mkfs .. " " .. $dev
+            module.mkfs_program = "mkfs." .. $fstype
+
+            -- The current filesystem type, or a partition table as
+            -- "gpt partition table"; nil for none. blkid exits with 2 when
+            -- the device holds nothing it recognizes.
+            module.current = function(self)
+                self.ssh:requires({ "blkid", self.mkfs_program })
+                if self.ssh:cmdq(self.exists_command).exit_code ~= 0 then
+                    error("filesystem: device " .. self.params.dev .. " does not exist")
+                end
+                local result = self.ssh:cmdq(self.blkid_command)
+                if result.exit_code == 2 then
+                    return nil
+                end
+                if result.exit_code ~= 0 then
+                    error("filesystem: blkid failed: " .. result.stderr)
+                end
+                return self.parse_blkid(result.stdout)
+            end
+
+            module.parse_blkid = function(output)
+                local fstype = string.match("\n" .. output, "\nTYPE=(%S+)")
+                if fstype ~= nil then
+                    return fstype
+                end
+                local pttype = string.match("\n" .. output, "\nPTTYPE=(%S+)")
+                if pttype ~= nil then
+                    return pttype .. " partition table"
+                end
+                return nil
+            end
+
+            -- Whether to run mkfs on a device holding current.
+            module.needs_mkfs = function(self, current)
+                if current == nil or current == "" then
+                    return true
+                end
+                if current == self.params.fstype then
+                    return false
+                end
+                if not self.params.force then
+                    error("filesystem: " .. self.params.dev .. " already holds " .. current .. ", set force = true to reformat it")
+                end
+                return true
+            end
+
+            module.dry_run = function(self)
+                local current = self:current()
+                self:set_result("previous", current)
+                self.ssh:set_changed(self:needs_mkfs(current))
+            end
+
+            module.run = function(self)
+                local current = self:current()
+                self:set_result("previous", current)
+                local changed = self:needs_mkfs(current)
+                if changed then
+                    local result = self.ssh:cmd(self.mkfs_command)
+                    if result.exit_code ~= 0 then
+                        error("filesystem: " .. self.mkfs_program .. " failed: " .. result.stderr)
+                    end
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            return module
+        })
+        .set_name("filesystem")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_mkfs_command() -> mlua::Result<()> {
+        assert_eq!(mkfs_command("ext4", false, None)?, "mkfs.ext4 -q");
+        assert_eq!(
+            mkfs_command("xfs", true, Some("data"))?,
+            "mkfs.xfs -f -L 'data'"
+        );
+        assert_eq!(mkfs_command("btrfs", true, None)?, "mkfs.btrfs -f");
+        assert!(mkfs_command("ntfs", false, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filesystem_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(filesystem(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("dev", "sdb1")?;
+        params.set("fstype", "ext4")?;
+        assert!(filesystem(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("dev", "/dev/sdb1")?;
+        params.set("fstype", "zfs")?;
+        assert!(filesystem(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filesystem_needs_mkfs() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (empty, same, forced) = lua
+            .load(chunk! {
+                local module = komandan.modules.filesystem({ dev = "/dev/sdb1", fstype = "ext4" })
+                local forced = komandan.modules.filesystem({ dev = "/dev/sdb1", fstype = "ext4", force = true })
+                return module:needs_mkfs(nil), module:needs_mkfs("ext4"), forced:needs_mkfs("xfs")
+            })
+            .eval::<(bool, bool, bool)>()?;
+        assert!(empty);
+        assert!(!same);
+        assert!(forced);
+
+        let result = lua
+            .load(chunk! {
+                local module = komandan.modules.filesystem({ dev = "/dev/sdb1", fstype = "ext4" })
+                module:needs_mkfs("xfs")
+            })
+            .exec();
+        assert!(result.is_err_and(|e| e.to_string().contains("already holds xfs")));
+
+        let parsed = lua
+            .load(chunk! {
+                local module = komandan.modules.filesystem({ dev = "/dev/sdb", fstype = "ext4" })
+                return {
+                    module.parse_blkid("DEVNAME=/dev/sdb1\nUUID=1234\nTYPE=ext4\nUSAGE=filesystem"),
+                    module.parse_blkid("DEVNAME=/dev/sdb\nPTUUID=abcd\nPTTYPE=gpt"),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(parsed, ["ext4", "gpt partition table"]);
+        Ok(())
+    }
+}
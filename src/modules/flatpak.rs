@@ -0,0 +1,256 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Manage Flatpak applications. `name` is an application ID (e.g.
+/// `org.mozilla.firefox`) or a list of them, installed from `remote`
+/// (default `flathub`); `state` is `present` (default), `absent` or
+/// `latest`. With `remote_url`, the remote is added first when missing,
+/// e.g. `https://dl.flathub.org/repo/flathub.flatpakrepo`. `scope` is
+/// `system` (default) or `user`. `update = true` updates every installed
+/// application and runtime that has an update. Installed applications come
+/// from `flatpak list` and pending updates from `flatpak remote-ls
+/// --updates`. Sets `installed`, `updated` and `removed` (lists of IDs) in
+/// the task result.
+pub fn flatpak(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let apps = super::name_list(&params, "name", "._-")?;
+    let remote = params
+        .get::<Option<String>>("remote")?
+        .unwrap_or_else(|| "flathub".to_string());
+    if remote.is_empty()
+        || remote.starts_with('-')
+        || !remote
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    {
+        return Err(RuntimeError(format!("Invalid remote name: '{remote}'")));
+    }
+    params.set("remote", remote.as_str())?;
+    let remote_url = params.get::<Option<String>>("remote_url")?;
+    let update = params.get::<Option<bool>>("update")?.unwrap_or(false);
+    params.set("update", update)?;
+    if apps.is_empty() && remote_url.is_none() && !update {
+        return Err(RuntimeError(
+            "'name', 'remote_url' or 'update' parameter is required".to_string(),
+        ));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let scope = match params.get::<Option<String>>("scope")?.as_deref() {
+        None | Some("system") => "--system",
+        Some("user") => "--user",
+        Some(other) => {
+            return Err(RuntimeError(format!(
+                "Invalid scope: {other}. Valid scopes are: system and user."
+            )));
+        }
+    };
+
+    let remote_add_command = remote_url.as_deref().map(|url| {
+        format!(
+            "flatpak remote-add {scope} --if-not-exists {remote} {}",
+            escape_shell_value(url)
+        )
+    });
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "flatpak" })
+
+            module.params = $params
+            module.apps = $apps
+            module.remotes_command = "flatpak remotes " .. $scope .. " --columns=name"
+            module.remote_add_command = $remote_add_command
+            module.list_command = "flatpak list " .. $scope .. " --app --columns=application"
+            module.updates_command = "flatpak remote-ls " .. $scope .. " --updates --columns=application"
+            module.install_command = "flatpak install " .. $scope .. " --noninteractive -y " .. $remote
+            module.update_command = "flatpak update " .. $scope .. " --noninteractive -y"
+            module.uninstall_command = "flatpak uninstall " .. $scope .. " --noninteractive -y"
+
+            module.flatpak = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("flatpak: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            module.lines = function(self, command)
+                local lines = {}
+                for line in string.gmatch(self:flatpak(command), "[^\n]+") do
+                    lines[string.match(line, "^%s*(.-)%s*$")] = true
+                end
+                return lines
+            end
+
+            -- What a run would do: add the remote, and the IDs to install,
+            -- update and remove; update_all when any update is pending.
+            module.plan = function(self)
+                self.ssh:requires("flatpak")
+                local plan = { remote = false, install = {}, update = {}, remove = {}, update_all = false }
+                if self.remote_add_command ~= nil then
+                    plan.remote = not self:lines(self.remotes_command)[self.params.remote]
+                end
+                local updates = {}
+                if self.params.update or self.params.state == "latest" then
+                    updates = self:lines(self.updates_command)
+                    plan.update_all = self.params.update and next(updates) ~= nil
+                end
+                if #self.apps > 0 then
+                    local installed = self:lines(self.list_command)
+                    for _, app in ipairs(self.apps) do
+                        if self.params.state == "absent" then
+                            if installed[app] then
+                                table.insert(plan.remove, app)
+                            end
+                        elseif not installed[app] then
+                            table.insert(plan.install, app)
+                        elseif updates[app] then
+                            table.insert(plan.update, app)
+                        end
+                    end
+                end
+                self:set_result("installed", plan.install)
+                self:set_result("updated", plan.update)
+                self:set_result("removed", plan.remove)
+                return plan
+            end
+
+            module.changes = function(plan)
+                return plan.remote or #plan.install > 0 or #plan.update > 0
+                    or #plan.remove > 0 or plan.update_all
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self.changes(self:plan()))
+            end
+
+            module.run = function(self)
+                local plan = self:plan()
+                if plan.remote then
+                    self:flatpak(self.remote_add_command)
+                end
+                if #plan.install > 0 then
+                    self:flatpak(self.install_command .. " " .. table.concat(plan.install, " "))
+                end
+                if plan.update_all then
+                    self:flatpak(self.update_command)
+                elseif #plan.update > 0 then
+                    self:flatpak(self.update_command .. " " .. table.concat(plan.update, " "))
+                end
+                if #plan.remove > 0 then
+                    self:flatpak(self.uninstall_command .. " " .. table.concat(plan.remove, " "))
+                end
+                self.ssh:set_changed(self.changes(plan))
+            end
+
+            return module
+        })
+        .set_name("flatpak")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_flatpak_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(flatpak(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "org.mozilla.firefox")?;
+        params.set("scope", "global")?;
+        assert!(flatpak(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "org.mozilla.firefox")?;
+        params.set("remote", "flathub; reboot")?;
+        assert!(flatpak(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatpak_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.flatpak({
+                    name = "org.mozilla.firefox",
+                    scope = "user",
+                    remote_url = "https://dl.flathub.org/repo/flathub.flatpakrepo",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("remote_add_command")?,
+            "flatpak remote-add --user --if-not-exists flathub 'https://dl.flathub.org/repo/flathub.flatpakrepo'"
+        );
+        assert_eq!(
+            module.get::<String>("install_command")?,
+            "flatpak install --user --noninteractive -y flathub"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatpak_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local function plan(params)
+                    local module = komandan.modules.flatpak(params)
+                    module.ssh = { requires = function() end }
+                    module.set_result = function() end
+                    module.flatpak = function(self, command)
+                        if command == self.remotes_command then
+                            return "fedora\n"
+                        elseif command == self.updates_command then
+                            return "org.gimp.GIMP\n"
+                        end
+                        return "org.mozilla.firefox\norg.gimp.GIMP\n"
+                    end
+                    local plan = module:plan()
+                    return {
+                        tostring(plan.remote),
+                        table.concat(plan.install, ","),
+                        table.concat(plan.update, ","),
+                        table.concat(plan.remove, ","),
+                        tostring(plan.update_all),
+                    }
+                end
+                return {
+                    plan({ name = { "org.mozilla.firefox", "org.videolan.VLC" }, remote_url = "https://dl.flathub.org/repo/flathub.flatpakrepo" }),
+                    plan({ name = { "org.gimp.GIMP", "org.mozilla.firefox" }, state = "latest" }),
+                    plan({ name = { "org.gimp.GIMP", "org.videolan.VLC" }, state = "absent" }),
+                    plan({ update = true }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["true", "org.videolan.VLC", "", "", "false"],
+                ["false", "", "org.gimp.GIMP", "", "false"],
+                ["false", "", "", "org.gimp.GIMP", "false"],
+                ["false", "", "", "", "true"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Manage Ruby gems. `name` is a gem or a list of gems and `version` pins a
+/// single `name` (with `state = "absent"`, only that version is removed).
+/// `state` is `present` (default), `absent` or `latest`. `user_install =
+/// true` installs into the connecting user's gem directory, and
+/// `executable` is the `gem` command to use, e.g. one of an rbenv Ruby.
+/// Installed versions come from `gem list --local` and outdated gems from
+/// `gem outdated`. Sets `installed` and `removed` (lists of gems) in the
+/// task result.
+pub fn gem(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let gems = super::name_list(&params, "name", "._-")?;
+    if gems.is_empty() {
+        return Err(RuntimeError("'name' parameter is required".to_string()));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let version = params.get::<Option<String>>("version")?;
+    if let Some(version) = &version {
+        if gems.len() != 1 || state == "latest" {
+            return Err(RuntimeError(
+                "'version' parameter needs a single 'name' and cannot be used with state 'latest'"
+                    .to_string(),
+            ));
+        }
+        if version.is_empty()
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".-".contains(c))
+        {
+            return Err(RuntimeError(format!("Invalid version: '{version}'")));
+        }
+    }
+
+    let gem = params.get::<Option<String>>("executable")?.map_or_else(
+        || "gem".to_string(),
+        |executable| escape_shell_value(&executable),
+    );
+    let user_install = if params.get::<Option<bool>>("user_install")?.unwrap_or(false) {
+        " --user-install"
+    } else {
+        ""
+    };
+    let version_option = version
+        .as_deref()
+        .map_or_else(String::new, |version| format!(" --version {version}"));
+    let install_command = format!("{gem} install --no-document{user_install}{version_option}");
+    let uninstall_command = if version.is_some() {
+        format!("{gem} uninstall --executables{user_install}{version_option}")
+    } else {
+        format!("{gem} uninstall --executables --all{user_install}")
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "gem" })
+
+            module.params = $params
+            module.gems = $gems
+            module.version = $version
+            module.list_command = $gem .. " list --local"
+            module.outdated_command = $gem .. " outdated"
+            module.install_command = $install_command
+            module.uninstall_command = $uninstall_command
+
+            module.gem = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("gem: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Installed versions by gem, from lines such as
+            -- "rake (13.1.0, default: 13.0.6)".
+            module.parse_list = function(output)
+                local installed = {}
+                for name, versions in string.gmatch(output, "([^%s(]+) %(([^)]*)%)") do
+                    installed[name] = {}
+                    for version in string.gmatch(versions, "[^,%s]+") do
+                        if version ~= "default:" then
+                            installed[name][version] = true
+                        end
+                    end
+                end
+                return installed
+            end
+
+            -- Gems to install and gems to remove.
+            module.plan = function(self)
+                local install, remove = {}, {}
+                local installed = self.parse_list(self:gem(self.list_command))
+                local outdated = {}
+                if self.params.state == "latest" then
+                    for name in string.gmatch(self:gem(self.outdated_command), "([^%s(]+) %(") do
+                        outdated[name] = true
+                    end
+                end
+                for _, name in ipairs(self.gems) do
+                    local versions = installed[name]
+                    local present = versions ~= nil and (self.version == nil or versions[self.version] == true)
+                    if self.params.state == "absent" then
+                        if present then
+                            table.insert(remove, name)
+                        end
+                    elseif not present or outdated[name] then
+                        table.insert(install, name)
+                    end
+                end
+                return install, remove
+            end
+
+            module.dry_run = function(self)
+                local install, remove = self:plan()
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(#install > 0 or #remove > 0)
+            end
+
+            module.run = function(self)
+                local install, remove = self:plan()
+                if #install > 0 then
+                    self:gem(self.install_command .. " " .. table.concat(install, " "))
+                end
+                if #remove > 0 then
+                    self:gem(self.uninstall_command .. " " .. table.concat(remove, " "))
+                end
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(#install > 0 or #remove > 0)
+            end
+
+            return module
+        })
+        .set_name("gem")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_gem_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(gem(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "bundler")?;
+        params.set("version", "2.5.0")?;
+        params.set("state", "latest")?;
+        assert!(gem(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "bundler`id`")?;
+        assert!(gem(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gem_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.gem({
+                    name = "bundler", version = "2.5.6", user_install = true,
+                    executable = "/home/deploy/.rbenv/shims/gem",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("install_command")?,
+            "'/home/deploy/.rbenv/shims/gem' install --no-document --user-install --version 2.5.6"
+        );
+        assert_eq!(
+            module.get::<String>("uninstall_command")?,
+            "'/home/deploy/.rbenv/shims/gem' uninstall --executables --user-install --version 2.5.6"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_gem_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local outputs = {
+                    list = "bundler (2.5.6, default: 2.4.19)\nrake (13.1.0)\nnokogiri (1.16.2 x86_64-linux)\n",
+                    outdated = "rake (13.1.0 < 13.2.1)\n",
+                }
+                local function plan(params)
+                    local module = komandan.modules.gem(params)
+                    module.gem = function(self, command)
+                        return command == self.outdated_command and outputs.outdated or outputs.list
+                    end
+                    local install, remove = module:plan()
+                    return { table.concat(install, ","), table.concat(remove, ",") }
+                end
+                return {
+                    plan({ name = { "bundler", "rake", "rails" } }),
+                    plan({ name = "bundler", version = "2.4.19" }),
+                    plan({ name = "bundler", version = "2.6.0" }),
+                    plan({ name = { "rake", "bundler" }, state = "latest" }),
+                    plan({ name = { "nokogiri", "rails" }, state = "absent" }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["rails", ""],
+                ["", ""],
+                ["bundler", ""],
+                ["rake", ""],
+                ["", "nokogiri"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// The `git` invocation: without prompts, and with `key_file` as the only
+/// SSH identity when given.
+fn git_program(key_file: Option<&str>) -> String {
+    key_file.map_or_else(
+        || "GIT_TERMINAL_PROMPT=0 git".to_string(),
+        |key_file| {
+            let ssh = format!(
+                "ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new",
+                escape_shell_value(key_file)
+            );
+            format!(
+                "GIT_TERMINAL_PROMPT=0 GIT_SSH_COMMAND={} git",
+                escape_shell_value(&ssh)
+            )
+        },
+    )
+}
+
+/// Deploy the repository `repo` to `dest`: clone it when missing, otherwise
+/// fetch and check out `version` (a branch, tag or commit; default: the
+/// remote `HEAD`) as a detached `HEAD`. `depth` makes shallow clones and
+/// fetches, `submodules = false` skips `git submodule update --init
+/// --recursive`, `clean = true` removes untracked files, and `force = true`
+/// discards local changes to tracked files (otherwise the task fails on
+/// them). `key_file` is a deploy key on the host used for SSH remotes. The
+/// task is changed when `HEAD` moves or `clean` removed files, and sets
+/// `before` (`nil` on the first clone) and `after` commits in the result.
+pub fn git(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let repo = params
+        .get::<Option<String>>("repo")?
+        .ok_or_else(|| RuntimeError("'repo' parameter is required".to_string()))?;
+    if repo.is_empty() || repo.starts_with('-') {
+        return Err(RuntimeError(format!("Invalid repository: '{repo}'")));
+    }
+    let dest = params
+        .get::<Option<String>>("dest")?
+        .ok_or_else(|| RuntimeError("'dest' parameter is required".to_string()))?;
+    let version = params.get::<Option<String>>("version")?;
+    if let Some(version) = &version
+        && (version.is_empty()
+            || version.starts_with('-')
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c)))
+    {
+        return Err(RuntimeError(format!("Invalid version: '{version}'")));
+    }
+    let depth = params
+        .get::<Option<u32>>("depth")?
+        .map_or_else(String::new, |depth| format!(" --depth {depth}"));
+    for (name, default) in [("force", false), ("clean", false), ("submodules", true)] {
+        let value = params.get::<Option<bool>>(name)?.unwrap_or(default);
+        params.set(name, value)?;
+    }
+
+    let program = git_program(params.get::<Option<String>>("key_file")?.as_deref());
+    let in_dest = format!("{program} -C {}", escape_shell_value(&dest));
+    let repo = escape_shell_value(&repo);
+    let reference = version.as_deref().unwrap_or("HEAD");
+    let force = if params.get::<bool>("force")? {
+        " --force"
+    } else {
+        ""
+    };
+
+    let head_command = format!(
+        "[ -d {}/.git ] && {in_dest} rev-parse HEAD",
+        escape_shell_value(&dest)
+    );
+    let status_command = format!("{in_dest} status --porcelain --untracked-files=no");
+    let clone_command = format!(
+        "{program} clone --no-checkout{depth} {repo} {}",
+        escape_shell_value(&dest)
+    );
+    let fetch_command = format!(
+        "{in_dest} remote set-url origin {repo} && {in_dest} fetch --force{depth} origin {reference}"
+    );
+    let checkout_command = format!("{in_dest} checkout --detach{force} FETCH_HEAD");
+    let submodule_command = format!("{in_dest} submodule update --init --recursive{force}{depth}");
+    let clean_command = format!("{in_dest} clean -ffd");
+    let clean_check_command = format!("{in_dest} clean -nd");
+    let ls_remote_command = format!(
+        "{program} ls-remote {repo} {reference} {}",
+        escape_shell_value(&format!("{reference}^{{}}"))
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "git" })
+
+            module.params = $params
+            module.version = $version
+            module.head_command = $head_command
+            module.status_command = $status_command
+            module.clone_command = $clone_command
+            module.fetch_command = $fetch_command
+            module.checkout_command = $checkout_command
+            module.submodule_command = $submodule_command
+            module.clean_command = $clean_command
+            module.clean_check_command = $clean_check_command
+            module.ls_remote_command = $ls_remote_command
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("git: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The checked out commit, nil when dest is not a repository yet.
+            module.head = function(self)
+                local result = self.ssh:cmdq(self.head_command)
+                if result.exit_code ~= 0 then
+                    return nil
+                end
+                return result.stdout
+            end
+
+            -- The commit version points to on the remote; a commit hash is
+            -- taken as is, and annotated tags are peeled to their commit.
+            module.remote_commit = function(self)
+                if self.version ~= nil and string.match(self.version, "^%x+$") and #self.version >= 7 then
+                    return self.version
+                end
+                local commit
+                for sha, ref in string.gmatch(self:sh(self.ls_remote_command), "(%x+)%s+([^\n]+)") do
+                    if commit == nil or string.sub(ref, -3) == "^{}" then
+                        commit = sha
+                    end
+                end
+                return commit
+            end
+
+            module.moves = function(before, target)
+                return before == nil or target == nil or string.sub(before, 1, #target) ~= target
+            end
+
+            module.check_local_changes = function(self)
+                if not self.params.force and self:sh(self.status_command) ~= "" then
+                    error("git: " .. self.params.dest .. " has local changes, set force = true to discard them")
+                end
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("git")
+                local before = self:head()
+                local changed = true
+                if before ~= nil then
+                    self:check_local_changes()
+                    changed = self.moves(before, self:remote_commit())
+                        or (self.params.clean and self:sh(self.clean_check_command) ~= "")
+                end
+                self:set_result("before", before)
+                self.ssh:set_changed(changed)
+            end
+
+            module.run = function(self)
+                self.ssh:requires("git")
+                local before = self:head()
+                if before == nil then
+                    self:sh(self.clone_command)
+                else
+                    self:check_local_changes()
+                end
+                self:sh(self.fetch_command)
+                self:sh(self.checkout_command)
+                if self.params.submodules then
+                    self:sh(self.submodule_command)
+                end
+                local cleaned = self.params.clean and self:sh(self.clean_command) ~= ""
+                local after = self:head()
+                self:set_result("before", before)
+                self:set_result("after", after)
+                self.ssh:set_changed(before ~= after or cleaned)
+            end
+
+            return module
+        })
+        .set_name("git")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_git_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(git(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("repo", "https://github.com/hahnavi/komandan.git")?;
+        assert!(git(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("repo", "https://github.com/hahnavi/komandan.git")?;
+        params.set("dest", "/srv/komandan")?;
+        params.set("version", "--upload-pack=id")?;
+        assert!(git(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.git({
+                    repo = "git@github.com:hahnavi/komandan.git", dest = "/srv/app", version = "v1.2.0",
+                    depth = 1, force = true, key_file = "/etc/deploy/id_ed25519",
+                })
+            })
+            .eval::<Table>()?;
+        let program = "GIT_TERMINAL_PROMPT=0 GIT_SSH_COMMAND='ssh -i '\\''/etc/deploy/id_ed25519'\\'' -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new' git";
+        assert_eq!(
+            module.get::<String>("clone_command")?,
+            format!(
+                "{program} clone --no-checkout --depth 1 'git@github.com:hahnavi/komandan.git' '/srv/app'"
+            )
+        );
+        assert!(
+            module
+                .get::<String>("fetch_command")?
+                .ends_with("fetch --force --depth 1 origin v1.2.0")
+        );
+        assert_eq!(
+            module.get::<String>("checkout_command")?,
+            format!("{program} -C '/srv/app' checkout --detach --force FETCH_HEAD")
+        );
+        assert!(
+            module
+                .get::<String>("ls_remote_command")?
+                .ends_with("ls-remote 'git@github.com:hahnavi/komandan.git' v1.2.0 'v1.2.0^{}'")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_remote_commit() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (tag, pinned, moves, stays) = lua
+            .load(chunk! {
+                local module = komandan.modules.git({ repo = "https://example.com/app.git", dest = "/srv/app", version = "v1.0" })
+                module.sh = function()
+                    return "1111111111111111111111111111111111111111\trefs/tags/v1.0\n2222222222222222222222222222222222222222\trefs/tags/v1.0^{}"
+                end
+                local pinned = komandan.modules.git({ repo = "https://example.com/app.git", dest = "/srv/app", version = "abc1234" })
+                return module:remote_commit(), pinned:remote_commit(),
+                    module.moves("abc9999", "abc1234"), module.moves("abc1234ffff", "abc1234")
+            })
+            .eval::<(String, String, bool, bool)>()?;
+        assert_eq!(tag, "2222222222222222222222222222222222222222");
+        assert_eq!(pinned, "abc1234");
+        assert!(moves);
+        assert!(!stays);
+        Ok(())
+    }
+}
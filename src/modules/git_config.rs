@@ -0,0 +1,214 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// The `git config` invocation for `scope`: `system`, `global`, or `local`
+/// in the repository `repo`.
+fn git_config_prefix(scope: &str, repo: Option<&str>) -> mlua::Result<String> {
+    match (scope, repo) {
+        ("system" | "global", _) => Ok(format!("git config --{scope}")),
+        ("local", Some(repo)) => Ok(format!(
+            "git -C {} config --local",
+            escape_shell_value(repo)
+        )),
+        ("local", None) => Err(RuntimeError(
+            "'repo' parameter is required with scope 'local'".to_string(),
+        )),
+        _ => Err(RuntimeError(format!(
+            "Invalid scope: {scope}. Valid scopes are: system, global and local."
+        ))),
+    }
+}
+
+/// Set the git configuration key `name` to `value` with `git config`.
+/// `scope` is `global` (the default; the config of the connecting user, or
+/// of `as_user`), `system`, or `local` with `repo` the repository path.
+/// `state = "absent"` unsets every value of the key instead. A key holding
+/// several values is replaced by the single `value`. Sets `previous` (the
+/// values before the change, `nil` when unset) in the task result.
+pub fn git_config(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if !name.contains('.') || name.starts_with('.') || name.ends_with('.') {
+        return Err(RuntimeError(format!(
+            "Invalid key: '{name}'. Keys look like 'section.name'"
+        )));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let value = params.get::<Option<String>>("value")?;
+    if state == "present" && value.is_none() {
+        return Err(RuntimeError(
+            "'value' parameter is required unless state is 'absent'".to_string(),
+        ));
+    }
+    let scope = params
+        .get::<Option<String>>("scope")?
+        .unwrap_or_else(|| "global".to_string());
+    params.set("scope", scope.as_str())?;
+    let prefix = git_config_prefix(&scope, params.get::<Option<String>>("repo")?.as_deref())?;
+
+    let key = escape_shell_value(&name);
+    let get_command = format!("{prefix} --get-all {key}");
+    let set_command = value
+        .as_deref()
+        .map(|value| format!("{prefix} --replace-all {key} {}", escape_shell_value(value)));
+    let unset_command = format!("{prefix} --unset-all {key}");
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "git_config" })
+
+            module.params = $params
+            module.get_command = $get_command
+            module.set_command = $set_command
+            module.unset_command = $unset_command
+
+            module.git = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("git_config: git config failed: " .. result.stderr)
+                end
+            end
+
+            -- The current values of the key, one per line, or nil when unset.
+            module.current = function(self)
+                self.ssh:requires("git")
+                local result = self.ssh:cmdq(self.get_command)
+                if result.exit_code == 1 then
+                    return nil
+                end
+                if result.exit_code ~= 0 then
+                    error("git_config: git config failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            module.needs_change = function(self, current)
+                if self.params.state == "absent" then
+                    return current ~= nil
+                end
+                return current ~= self.params.value
+            end
+
+            module.dry_run = function(self)
+                local current = self:current()
+                self:set_result("previous", current)
+                self.ssh:set_changed(self:needs_change(current))
+            end
+
+            module.run = function(self)
+                local current = self:current()
+                self:set_result("previous", current)
+                if not self:needs_change(current) then
+                    return
+                end
+                if self.params.state == "absent" then
+                    self:git(self.unset_command)
+                else
+                    self:git(self.set_command)
+                end
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("git_config")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_git_config_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(git_config(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "user.email")?;
+        let result = git_config(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'value' parameter is required")));
+
+        let params = lua.create_table()?;
+        params.set("name", "useremail")?;
+        params.set("value", "ops@example.com")?;
+        assert!(git_config(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "user.email")?;
+        params.set("value", "ops@example.com")?;
+        params.set("scope", "local")?;
+        let result = git_config(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'repo' parameter is required")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.git_config({
+                    name = "user.name",
+                    value = "Ops Team",
+                    scope = "local",
+                    repo = "/srv/app",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("get_command")?,
+            "git -C '/srv/app' config --local --get-all 'user.name'"
+        );
+        assert_eq!(
+            module.get::<String>("set_command")?,
+            "git -C '/srv/app' config --local --replace-all 'user.name' 'Ops Team'"
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.git_config({ name = "core.editor", state = "absent" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("unset_command")?,
+            "git config --global --unset-all 'core.editor'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_needs_change() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (unchanged, changed, multiple, absent) = lua
+            .load(chunk! {
+                local module = komandan.modules.git_config({ name = "pull.rebase", value = "true" })
+                local remove = komandan.modules.git_config({ name = "pull.rebase", state = "absent" })
+                return module:needs_change("true"), module:needs_change(nil),
+                    module:needs_change("true\nfalse"), remove:needs_change(nil)
+            })
+            .eval::<(bool, bool, bool, bool)>()?;
+        assert!(!unchanged);
+        assert!(changed);
+        assert!(multiple);
+        assert!(!absent);
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Lines returned in the result when `max_lines` is not set.
+const DEFAULT_MAX_LINES: u32 = 100;
+
+/// The command printing the matching lines: `journalctl` with the `unit`,
+/// `priority`, `since`, `until` and `pattern` filters, or `grep` (`cat`
+/// without a `pattern`) on the log file `path`.
+fn query_command(params: &Table) -> mlua::Result<String> {
+    let pattern = params.get::<Option<String>>("pattern")?;
+    if let Some(path) = params.get::<Option<String>>("path")? {
+        if let Some(name) = ["unit", "priority", "since", "until"]
+            .into_iter()
+            .find(|name| params.contains_key(*name).unwrap_or(false))
+        {
+            return Err(RuntimeError(format!(
+                "'{name}' parameter only applies to the journal, not to 'path'"
+            )));
+        }
+        let path = escape_shell_value(&path);
+        return Ok(pattern.map_or_else(
+            || format!("cat -- {path}"),
+            |pattern| format!("grep -E -- {} {path}", escape_shell_value(&pattern)),
+        ));
+    }
+
+    let mut command = String::from("journalctl --no-pager --quiet --output=cat");
+    for (name, option) in [
+        ("unit", "--unit"),
+        ("priority", "--priority"),
+        ("since", "--since"),
+        ("until", "--until"),
+        ("pattern", "--grep"),
+    ] {
+        if let Some(value) = params.get::<Option<String>>(name)? {
+            let _ = write!(command, " {option}={}", escape_shell_value(&value));
+        }
+    }
+    Ok(command)
+}
+
+/// Query the journal with `journalctl`, filtered by `unit`, `priority`
+/// (e.g. `err` or `warning..err`), `since`/`until` (e.g. `"-1h"`,
+/// `"2024-05-01 10:00"`) and the regular expression `pattern`; or search
+/// the log file `path` for `pattern`. Never changes the host. Sets `count`
+/// (number of matching lines) and `lines` (the last `max_lines`, default
+/// 100, of them) in the task result. With `max_count`, the task fails when
+/// more lines match, e.g. `max_count = 0` to check that there are no errors.
+pub fn journald(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let command = query_command(&params)?;
+    let max_lines = params
+        .get::<Option<u32>>("max_lines")?
+        .unwrap_or(DEFAULT_MAX_LINES);
+    params.set("max_lines", max_lines)?;
+    if let Some(max_count) = params.get::<Option<u32>>("max_count")? {
+        params.set("max_count", max_count)?;
+    }
+    let program = if params.contains_key("path")? {
+        "grep"
+    } else {
+        "journalctl"
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "journald" })
+
+            module.params = $params
+            module.command = $command
+            module.program = $program
+
+            -- The matching lines; grep and journalctl exit with 1 when
+            -- nothing matches.
+            module.query = function(self)
+                self.ssh:requires(self.program)
+                local result = self.ssh:cmdq(self.command)
+                if result.exit_code == 1 and result.stdout == "" then
+                    return {}
+                end
+                if result.exit_code ~= 0 then
+                    error("journald: " .. self.program .. " failed: " .. result.stderr)
+                end
+                local lines = {}
+                for line in string.gmatch(result.stdout, "[^\n]+") do
+                    table.insert(lines, line)
+                end
+                return lines
+            end
+
+            -- Set count and the last max_lines lines, and fail when more
+            -- than max_count lines match.
+            module.report = function(self, lines)
+                local last = {}
+                for i = math.max(#lines - self.params.max_lines + 1, 1), #lines do
+                    table.insert(last, lines[i])
+                end
+                self:set_result("count", #lines)
+                self:set_result("lines", last)
+                self.ssh:set_changed(false)
+                if self.params.max_count ~= nil and #lines > self.params.max_count then
+                    error("journald: " .. #lines .. " matching lines, expected at most " .. self.params.max_count)
+                end
+            end
+
+            module.run = function(self)
+                self:report(self:query())
+            end
+
+            module.dry_run = module.run
+
+            return module
+        })
+        .set_name("journald")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_journald_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.journald({ unit = "nginx", priority = "err", since = "-1h", pattern = "upstream" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("command")?,
+            "journalctl --no-pager --quiet --output=cat --unit='nginx' --priority='err' --since='-1h' --grep='upstream'"
+        );
+        assert_eq!(module.get::<String>("program")?, "journalctl");
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.journald({ path = "/var/log/app.log", pattern = "ERROR|FATAL" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("command")?,
+            "grep -E -- 'ERROR|FATAL' '/var/log/app.log'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_journald_rejects_journal_filters_with_path() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("path", "/var/log/app.log")?;
+        params.set("unit", "app")?;
+        let result = journald(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'unit' parameter")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_journald_report() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = lua
+            .load(chunk! {
+                local module = komandan.modules.journald({ path = "/var/log/app.log", max_count = 2 })
+                module.set_result = function() end
+                module.ssh = { set_changed = function() end }
+                module:report({ "a", "b", "c" })
+            })
+            .exec();
+        assert!(result.is_err_and(|e| {
+            e.to_string()
+                .contains("3 matching lines, expected at most 2")
+        }));
+
+        let (count, lines) = lua
+            .load(chunk! {
+                local module = komandan.modules.journald({ unit = "app", max_lines = 2 })
+                local results = {}
+                module.set_result = function(_, key, value) results[key] = value end
+                module.ssh = { set_changed = function() end }
+                module:report({ "a", "b", "c" })
+                return results.count, results.lines
+            })
+            .eval::<(i64, Vec<String>)>()?;
+        assert_eq!(count, 3);
+        assert_eq!(lines, ["b", "c"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, LuaSerdeExt, Table, Value, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+use serde::Serialize;
+use serde_json::{Map, ser::PrettyFormatter};
+
+use crate::local::escape_shell_value;
+
+/// The unescaped reference tokens of the JSON pointer `pointer` (RFC 6901).
+fn pointer_tokens(pointer: &str) -> mlua::Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer.strip_prefix('/').ok_or_else(|| {
+        RuntimeError(format!(
+            "Invalid JSON pointer: '{pointer}'. It must start with '/'"
+        ))
+    })?;
+    Ok(rest
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// The member `token` of `target`, created as an empty object when an
+/// object lacks it.
+fn child_mut<'a>(
+    target: &'a mut serde_json::Value,
+    token: &str,
+) -> mlua::Result<&'a mut serde_json::Value> {
+    if target.is_null() {
+        *target = serde_json::Value::Object(Map::new());
+    }
+    match target {
+        serde_json::Value::Object(map) => Ok(map
+            .entry(token)
+            .or_insert_with(|| serde_json::Value::Object(Map::new()))),
+        serde_json::Value::Array(items) => token
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index))
+            .ok_or_else(|| RuntimeError(format!("No array element '{token}'"))),
+        _ => Err(RuntimeError(format!(
+            "Cannot descend into '{token}': not an object or array"
+        ))),
+    }
+}
+
+/// Set the value at `tokens` in `document`, creating missing objects on the
+/// way. In an array, `-` appends.
+fn set_pointer(
+    document: &mut serde_json::Value,
+    tokens: &[String],
+    value: serde_json::Value,
+) -> mlua::Result<()> {
+    let Some((last, parents)) = tokens.split_last() else {
+        *document = value;
+        return Ok(());
+    };
+    let mut target = document;
+    for token in parents {
+        target = child_mut(target, token)?;
+    }
+    if target.is_null() {
+        *target = serde_json::Value::Object(Map::new());
+    }
+    match target {
+        serde_json::Value::Object(map) => {
+            map.insert(last.clone(), value);
+        }
+        serde_json::Value::Array(items) if last == "-" => items.push(value),
+        serde_json::Value::Array(items) => {
+            let index = last
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index <= items.len())
+                .ok_or_else(|| RuntimeError(format!("No array element '{last}'")))?;
+            if let Some(item) = items.get_mut(index) {
+                *item = value;
+            } else {
+                items.push(value);
+            }
+        }
+        _ => {
+            return Err(RuntimeError(format!(
+                "Cannot set '{last}': not an object or array"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Remove the value at `tokens` from `document`; a missing value is fine.
+fn remove_pointer(document: &mut serde_json::Value, tokens: &[String]) {
+    let Some((last, parents)) = tokens.split_last() else {
+        *document = serde_json::Value::Null;
+        return;
+    };
+    let mut target = document;
+    for token in parents {
+        let child = match target {
+            serde_json::Value::Object(map) => map.get_mut(token),
+            serde_json::Value::Array(items) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index)),
+            _ => None,
+        };
+        let Some(child) = child else {
+            return;
+        };
+        target = child;
+    }
+    match target {
+        serde_json::Value::Object(map) => {
+            map.remove(last);
+        }
+        serde_json::Value::Array(items) => {
+            if let Some(index) = last.parse::<usize>().ok().filter(|i| *i < items.len()) {
+                items.remove(index);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Apply the JSON merge patch `patch` (RFC 7386) to `target`: objects are
+/// merged member by member, `null` removes a member, anything else
+/// replaces.
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Map::new());
+    }
+    if let serde_json::Value::Object(map) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(&key);
+            } else {
+                merge_patch(map.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
+}
+
+/// The indentation of the first indented line of `content`, two spaces when
+/// there is none.
+fn detect_indent(content: &str) -> &str {
+    content
+        .lines()
+        .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+        .find(|indent| !indent.is_empty())
+        .unwrap_or("  ")
+}
+
+/// Apply `merge`, then set `value` at `pointer` (or remove it with `state =
+/// "absent"`) in the JSON document `content`. Returns the new document,
+/// pretty-printed with the indentation of `content`, or `None` when the
+/// edits leave the document as it was. An empty `content` is an empty
+/// object.
+fn edit_json(
+    content: &str,
+    pointer: Option<&str>,
+    value: Option<serde_json::Value>,
+    merge: Option<serde_json::Value>,
+    absent: bool,
+) -> mlua::Result<Option<String>> {
+    let original = if content.trim().is_empty() {
+        serde_json::Value::Object(Map::new())
+    } else {
+        serde_json::from_str(content).map_err(|e| RuntimeError(format!("Invalid JSON: {e}")))?
+    };
+    let mut document = original.clone();
+    if let Some(patch) = merge {
+        merge_patch(&mut document, patch);
+    }
+    if let Some(pointer) = pointer {
+        let tokens = pointer_tokens(pointer)?;
+        if absent {
+            remove_pointer(&mut document, &tokens);
+        } else {
+            set_pointer(&mut document, &tokens, value.unwrap_or_default())?;
+        }
+    }
+    if document == original {
+        return Ok(None);
+    }
+
+    let mut output = Vec::new();
+    let formatter = PrettyFormatter::with_indent(detect_indent(content).as_bytes());
+    document
+        .serialize(&mut serde_json::Serializer::with_formatter(
+            &mut output,
+            formatter,
+        ))
+        .map_err(|e| RuntimeError(format!("Failed to write JSON: {e}")))?;
+    output.push(b'\n');
+    let output = String::from_utf8(output).into_lua_err()?;
+    serde_json::from_str::<serde_json::Value>(&output)
+        .map_err(|e| RuntimeError(format!("Edited JSON does not parse: {e}")))?;
+    Ok(Some(output))
+}
+
+/// Lua function `edit_json(content, params)` of `KomandanModule`: applies
+/// the `pointer`, `value`, `merge` and `state` of a `json_file` task to
+/// `content` (see [`edit_json`]).
+///
+/// # Errors
+///
+/// Returns an error if `content` is not JSON or the edit does not apply.
+pub fn lua_edit_json(lua: &Lua, content: &str, params: &Table) -> mlua::Result<Option<String>> {
+    let json = |name: &str| -> mlua::Result<Option<serde_json::Value>> {
+        match params.get::<Value>(name)? {
+            Value::Nil => Ok(None),
+            value => lua.from_value(value).map(Some),
+        }
+    };
+    edit_json(
+        content,
+        params.get::<Option<String>>("pointer")?.as_deref(),
+        json("value")?,
+        json("merge")?,
+        params.get::<Option<String>>("state")?.as_deref() == Some("absent"),
+    )
+}
+
+/// Edit the JSON file `path` on the host: set `value` at the JSON pointer
+/// `pointer` (e.g. `"/server/port"`, created with any missing objects on
+/// the way; `-` appends to an array), remove it with `state = "absent"`,
+/// or apply the JSON merge patch `merge` (a table, applied before
+/// `pointer`). The file is only rewritten when its parsed content changes,
+/// pretty-printed with its own indentation and with object keys sorted;
+/// the result is parsed again before it replaces the file. A missing file
+/// is an error unless `create = true`, which starts from `{}`.
+pub fn json_file(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let path = params
+        .get::<Option<String>>("path")?
+        .ok_or_else(|| RuntimeError("'path' parameter is required".to_string()))?;
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let pointer = params.get::<Option<String>>("pointer")?;
+    if let Some(pointer) = &pointer {
+        pointer_tokens(pointer)?;
+    }
+    let merge = params.get::<Value>("merge")?;
+    if !merge.is_nil() && !merge.is_table() {
+        return Err(RuntimeError(
+            "'merge' parameter must be a table".to_string(),
+        ));
+    }
+    match (&pointer, state.as_str()) {
+        (None, "absent") => {
+            return Err(RuntimeError(
+                "'pointer' parameter is required when state is 'absent'".to_string(),
+            ));
+        }
+        (None, _) if merge.is_nil() => {
+            return Err(RuntimeError(
+                "'pointer' or 'merge' parameter is required".to_string(),
+            ));
+        }
+        (Some(_), "present") if params.get::<Value>("value")?.is_nil() => {
+            return Err(RuntimeError(
+                "'value' parameter is required with 'pointer'".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    let create = params.get::<Option<bool>>("create")?.unwrap_or(false);
+    params.set("create", create)?;
+
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+    let tmp_path = format!("{path}.komandan-{random_file_name}");
+    let quoted = escape_shell_value(&path);
+    let quoted_tmp = escape_shell_value(&tmp_path);
+    // cmdq strips trailing newlines, so a dot is printed after the file and
+    // removed again.
+    let read_command = format!("[ ! -e {quoted} ] || {{ cat {quoted} && printf .; }}");
+    let install_command = format!(
+        "{{ [ ! -e {quoted} ] || {{ chmod --reference={quoted} {quoted_tmp}; chown --reference={quoted} {quoted_tmp} 2>/dev/null; true; }}; }} && mv -f {quoted_tmp} {quoted}"
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "json_file" })
+
+            module.params = $params
+            module.tmp_path = $tmp_path
+            module.read_command = $read_command
+            module.install_command = $install_command
+            module.remove_tmp_command = "rm -f " .. $quoted_tmp
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("json_file: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The edited document, nil when the file stays as it is.
+            module.edited = function(self)
+                local content = self:sh(self.read_command)
+                if content == "" then
+                    if self.params.state == "absent" then
+                        return nil
+                    end
+                    if not self.params.create then
+                        error("json_file: " .. self.params.path .. " does not exist, set create = true to create it")
+                    end
+                else
+                    content = string.sub(content, 1, -2)
+                end
+                local ok, edited = pcall(self.edit_json, content, self.params)
+                if not ok then
+                    error("json_file: " .. self.params.path .. ": " .. tostring(edited))
+                end
+                return edited
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:edited() ~= nil)
+            end
+
+            module.run = function(self)
+                local edited = self:edited()
+                if edited ~= nil then
+                    self.ssh:write_remote_file(self.tmp_path, edited)
+                    self:sh(self.install_command)
+                end
+                self.ssh:set_changed(edited ~= nil)
+            end
+
+            module.cleanup = function(self)
+                self.ssh:cmdq(self.remove_tmp_command)
+            end
+
+            return module
+        })
+        .set_name("json_file")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_edit_json() -> mlua::Result<()> {
+        let content =
+            "{\n    \"server\": {\n        \"port\": 80\n    },\n    \"tags\": [\"a\"]\n}\n";
+        assert_eq!(
+            edit_json(content, Some("/server/port"), Some(json!(80)), None, false)?,
+            None
+        );
+        assert_eq!(
+            edit_json(
+                content,
+                Some("/server/port"),
+                Some(json!(8080)),
+                None,
+                false
+            )?
+            .as_deref(),
+            Some(
+                "{\n    \"server\": {\n        \"port\": 8080\n    },\n    \"tags\": [\n        \"a\"\n    ]\n}\n"
+            )
+        );
+        assert_eq!(
+            edit_json("", Some("/log/level"), Some(json!("debug")), None, false)?.as_deref(),
+            Some("{\n  \"log\": {\n    \"level\": \"debug\"\n  }\n}\n")
+        );
+        assert_eq!(
+            edit_json(
+                "{\"tags\": [\"a\"]}",
+                Some("/tags/-"),
+                Some(json!("b")),
+                None,
+                false
+            )?
+            .as_deref(),
+            Some("{\n  \"tags\": [\n    \"a\",\n    \"b\"\n  ]\n}\n")
+        );
+        assert_eq!(
+            edit_json(content, Some("/server"), None, None, true)?.as_deref(),
+            Some("{\n    \"tags\": [\n        \"a\"\n    ]\n}\n")
+        );
+        assert_eq!(
+            edit_json(content, Some("/missing/key"), None, None, true)?,
+            None
+        );
+        assert_eq!(
+            edit_json(
+                "{\"a\": 1, \"b\": {\"c\": 2}}",
+                None,
+                None,
+                Some(json!({ "a": null, "b": { "d": 3 } })),
+                false
+            )?
+            .as_deref(),
+            Some("{\n  \"b\": {\n    \"c\": 2,\n    \"d\": 3\n  }\n}\n")
+        );
+        assert!(edit_json("{", Some("/a"), Some(json!(1)), None, false).is_err());
+        assert!(edit_json("[1]", Some("/5"), Some(json!(1)), None, false).is_err());
+        assert!(pointer_tokens("server/port").is_err());
+        assert_eq!(pointer_tokens("/a~1b/c~0d")?, ["a/b", "c~d"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_file_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(json_file(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/app.json")?;
+        assert!(json_file(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/app.json")?;
+        params.set("pointer", "/port")?;
+        assert!(json_file(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/app.json")?;
+        params.set("pointer", "port")?;
+        params.set("value", 8080)?;
+        assert!(json_file(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/app.json")?;
+        params.set("pointer", "/port")?;
+        params.set("state", "absent")?;
+        assert!(json_file(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_file_edits_file() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = dir.path().join("app.json");
+        std::fs::write(&path, "{\n  \"port\": 80\n}\n").map_err(mlua::Error::external)?;
+        let path = path.to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = || -> mlua::Result<bool> {
+            let params = lua.create_table()?;
+            params.set("path", path.as_str())?;
+            params.set("pointer", "/port")?;
+            params.set("value", 8080)?;
+            let task = lua.create_table()?;
+            task.set(1, json_file(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))?
+                .get::<bool>("changed")
+        };
+        assert!(run()?);
+        assert!(!run()?);
+        assert_eq!(
+            std::fs::read_to_string(&path).map_err(mlua::Error::external)?,
+            "{\n  \"port\": 8080\n}\n"
+        );
+        Ok(())
+    }
+}
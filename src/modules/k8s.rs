@@ -0,0 +1,272 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// Rollout timeout when `wait_timeout` is not given.
+const DEFAULT_WAIT_TIMEOUT: &str = "300s";
+
+/// The manifest: the local file `src`, or the inline `content`.
+fn manifest_source(params: &Table) -> mlua::Result<String> {
+    match (
+        params.get::<Option<String>>("src")?,
+        params.get::<Option<String>>("content")?,
+    ) {
+        (Some(src), None) => std::fs::read_to_string(&src)
+            .map_err(|e| RuntimeError(format!("Failed to read manifest {src}: {e}"))),
+        (None, Some(content)) => Ok(content),
+        (Some(_), Some(_)) => Err(RuntimeError(
+            "'src' and 'content' parameters are mutually exclusive".to_string(),
+        )),
+        (None, None) => Err(RuntimeError(
+            "'src' or 'content' parameter is required".to_string(),
+        )),
+    }
+}
+
+/// The `kubectl` invocation with the `kubeconfig`, `context` and
+/// `namespace` options.
+fn kubectl_program(params: &Table) -> mlua::Result<String> {
+    let mut kubectl = String::from("kubectl");
+    for (name, option) in [
+        ("kubeconfig", "--kubeconfig"),
+        ("context", "--context"),
+        ("namespace", "--namespace"),
+    ] {
+        if let Some(value) = params.get::<Option<String>>(name)? {
+            let _ = write!(kubectl, " {option}={}", escape_shell_value(&value));
+        }
+    }
+    Ok(kubectl)
+}
+
+/// Apply a Kubernetes manifest with `kubectl` on the host; run the task
+/// with `delegate_to = "localhost"` to apply from the control machine.
+/// The manifest is the local file `src` or the inline `content`, rendered
+/// as a template when `vars` is given. `kubeconfig`, `context` and
+/// `namespace` select the cluster. `prune` is a label selector: objects
+/// with those labels that are no longer in the manifest are deleted.
+/// `wait = true` waits for the rollout of the applied deployments,
+/// stateful sets and daemon sets (`wait_timeout`, default `"300s"`).
+/// Changes are detected with `kubectl diff`, which also makes the dry run;
+/// the diff is set as `diff` in the task result. `state = "absent"`
+/// deletes the objects of the manifest.
+pub fn k8s(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let source = manifest_source(&params)?;
+    let vars = params.get::<Value>("vars")?;
+    if !vars.is_nil() && !vars.is_table() {
+        return Err(RuntimeError("'vars' parameter must be a table".to_string()));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let wait = params.get::<Option<bool>>("wait")?.unwrap_or(false);
+    params.set("wait", wait)?;
+    let wait_timeout = params
+        .get::<Option<String>>("wait_timeout")?
+        .unwrap_or_else(|| DEFAULT_WAIT_TIMEOUT.to_string());
+    if wait_timeout.is_empty() || !wait_timeout.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(RuntimeError(format!(
+            "Invalid wait_timeout: '{wait_timeout}'. Use a duration such as '300s' or '5m'"
+        )));
+    }
+    let prune = params
+        .get::<Option<String>>("prune")?
+        .map_or_else(String::new, |selector| {
+            format!(" --prune --selector={}", escape_shell_value(&selector))
+        });
+
+    let kubectl = kubectl_program(&params)?;
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "k8s" })
+
+            module.params = $params
+            module.source = $source
+            module.kubectl = $kubectl
+            module.prune = $prune
+            module.wait_timeout = $wait_timeout
+            module.random_file_name = $random_file_name
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("k8s: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Write the rendered manifest to a temporary file on the host.
+            module.write_manifest = function(self)
+                local content = self.source
+                if self.params.vars ~= nil then
+                    local vars = {}
+                    for key, value in pairs(self.vars or {}) do
+                        vars[key] = value
+                    end
+                    for key, value in pairs(self.params.vars) do
+                        vars[key] = value
+                    end
+                    content = self.render_template(self.source, vars)
+                end
+                self.manifest = self.ssh:get_tmpdir() .. "/." .. self.random_file_name .. ".yaml"
+                self.ssh:write_remote_file(self.manifest, content)
+            end
+
+            -- Whether the cluster differs from the manifest; kubectl diff
+            -- exits with 1 when there are differences and above 1 on errors.
+            module.diff = function(self)
+                local result = self.ssh:cmdq(self.kubectl .. " diff -f " .. self.manifest .. self.prune)
+                if result.exit_code > 1 then
+                    error("k8s: kubectl diff failed: " .. result.stderr)
+                end
+                self:set_result("diff", result.stdout)
+                return result.exit_code == 1
+            end
+
+            -- The objects of the manifest that exist in the cluster.
+            module.existing = function(self)
+                return self:sh(self.kubectl .. " get -f " .. self.manifest .. " -o name --ignore-not-found")
+            end
+
+            -- The applied objects kubectl rollout status can wait for.
+            module.rollouts = function(output)
+                local names = {}
+                for name in string.gmatch(output, "[^\n]+") do
+                    local kind = string.match(name, "^(%a+)%.apps/")
+                    if kind == "deployment" or kind == "statefulset" or kind == "daemonset" then
+                        table.insert(names, name)
+                    end
+                end
+                return names
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("kubectl")
+                self:write_manifest()
+                if self.params.state == "absent" then
+                    self.ssh:set_changed(self:existing() ~= "")
+                    return
+                end
+                self.ssh:set_changed(self:diff())
+            end
+
+            module.run = function(self)
+                self.ssh:requires("kubectl")
+                self:write_manifest()
+                if self.params.state == "absent" then
+                    local existing = self:existing() ~= ""
+                    if existing then
+                        self:sh(self.kubectl .. " delete -f " .. self.manifest .. " --ignore-not-found --wait=" .. tostring(self.params.wait))
+                    end
+                    self.ssh:set_changed(existing)
+                    return
+                end
+                local changed = self:diff()
+                if changed then
+                    local applied = self:sh(self.kubectl .. " apply -f " .. self.manifest .. self.prune .. " -o name")
+                    if self.params.wait then
+                        for _, name in ipairs(self.rollouts(applied)) do
+                            self:sh(self.kubectl .. " rollout status " .. name .. " --timeout=" .. self.wait_timeout)
+                        end
+                    end
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.cleanup = function(self)
+                if self.manifest ~= nil then
+                    self.ssh:cmdq("rm -f " .. self.manifest)
+                end
+            end
+
+            return module
+        })
+        .set_name("k8s")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_k8s_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(k8s(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("content", "apiVersion: v1\nkind: Namespace\n")?;
+        params.set("wait_timeout", "5m; id")?;
+        assert!(k8s(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("src", "/nonexistent/manifest.yaml")?;
+        assert!(k8s(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_k8s_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.k8s({
+                    content = "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: web\n",
+                    kubeconfig = "/etc/rancher/k3s/k3s.yaml", namespace = "web", prune = "app=web",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("kubectl")?,
+            "kubectl --kubeconfig='/etc/rancher/k3s/k3s.yaml' --namespace='web'"
+        );
+        assert_eq!(
+            module.get::<String>("prune")?,
+            " --prune --selector='app=web'"
+        );
+        assert_eq!(module.get::<String>("wait_timeout")?, "300s");
+        Ok(())
+    }
+
+    #[test]
+    fn test_k8s_rollouts() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let names = lua
+            .load(chunk! {
+                local module = komandan.modules.k8s({ content = "" })
+                return module.rollouts("namespace/web\ndeployment.apps/api\nservice/api\nstatefulset.apps/db\ndaemonset.apps/agent")
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            names,
+            [
+                "deployment.apps/api",
+                "statefulset.apps/db",
+                "daemonset.apps/agent"
+            ]
+        );
+        Ok(())
+    }
+}
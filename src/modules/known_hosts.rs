@@ -0,0 +1,288 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// How `known_hosts` names `host` on `port`: plain for port 22,
+/// `[host]:port` otherwise.
+fn host_pattern(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+/// Manage the entries of host `name` in a `known_hosts` file, so hosts can
+/// be trusted before the first connection, e.g. an internal git server.
+/// The keys are scanned with `ssh-keyscan` (limited to `key_types`, e.g.
+/// `"ed25519,rsa"`) unless `key` gives one as `"ssh-ed25519 AAAA..."`. The
+/// file is `path`, or `~/.ssh/known_hosts` of `user` (default: the
+/// connecting user). `port` defaults to 22, and `hash = true` writes hashed
+/// host names. Existing entries are found with `ssh-keygen -F`, so hashed
+/// entries count too: a scanned host is left alone once it has any entry,
+/// a given `key` replaces entries with other keys. `state = "absent"`
+/// removes every entry of the host.
+pub fn known_hosts(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if name.is_empty()
+        || name.starts_with('-')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-:_".contains(c))
+    {
+        return Err(RuntimeError(format!("Invalid host name: '{name}'")));
+    }
+    let port = params.get::<Option<u16>>("port")?.unwrap_or(22);
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let key = params.get::<Option<String>>("key")?;
+    if let Some(key) = &key
+        && (key.split_whitespace().count() < 2 || key.contains(['\n', '\'']))
+    {
+        return Err(RuntimeError(
+            "'key' parameter must be a single \"type base64\" key".to_string(),
+        ));
+    }
+    let key_types = super::name_list(&params, "key_types", "-,@.")?;
+    let hash = params.get::<Option<bool>>("hash")?.unwrap_or(false);
+
+    let pattern = escape_shell_value(&host_pattern(&name, port));
+    let user = params.get::<Option<String>>("user")?;
+    let file = match (params.get::<Option<String>>("path")?, &user) {
+        (Some(path), _) => format!("KH={}", escape_shell_value(&path)),
+        (None, Some(user)) => format!(
+            "KH=\"$(getent passwd {} | cut -d: -f6)/.ssh/known_hosts\"",
+            escape_shell_value(user)
+        ),
+        (None, None) => "KH=\"$HOME/.ssh/known_hosts\"".to_string(),
+    };
+    let chown = user.as_deref().map_or_else(String::new, |user| {
+        format!(" && chown {}: \"$KH\"", escape_shell_value(user))
+    });
+    let hash_option = if hash { " -H" } else { "" };
+    let new_entries = key.as_deref().map_or_else(
+        || {
+            let types = if key_types.is_empty() {
+                String::new()
+            } else {
+                format!(" -t {}", key_types.join(","))
+            };
+            format!(
+                "ssh-keyscan{hash_option} -p {port}{types} {} 2>/dev/null",
+                escape_shell_value(&name)
+            )
+        },
+        |key| {
+            let line = escape_shell_value(&format!("{} {}", host_pattern(&name, port), key.trim()));
+            if hash {
+                format!(
+                    "TMP=$(mktemp) && printf '%s\\n' {line} > \"$TMP\" && ssh-keygen -H -f \"$TMP\" >/dev/null 2>&1 && cat \"$TMP\"; rm -f \"$TMP\" \"$TMP.old\""
+                )
+            } else {
+                format!("printf '%s\\n' {line}")
+            }
+        },
+    );
+
+    let find_command = format!("{file} && [ -f \"$KH\" ] && ssh-keygen -F {pattern} -f \"$KH\"");
+    let remove_command =
+        format!("{file} && ssh-keygen -R {pattern} -f \"$KH\" >/dev/null && rm -f \"$KH.old\"");
+    let add_command = format!(
+        "{file} && mkdir -p \"$(dirname \"$KH\")\" && ENTRIES=$({new_entries}) && [ -n \"$ENTRIES\" ] && printf '%s\\n' \"$ENTRIES\" >> \"$KH\" && chmod 644 \"$KH\"{chown}"
+    );
+    let wanted_key = key.map(|key| key.split_whitespace().take(2).collect::<Vec<_>>().join(" "));
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "known_hosts" })
+
+            module.params = $params
+            module.wanted_key = $wanted_key
+            module.find_command = $find_command
+            module.remove_command = $remove_command
+            module.add_command = $add_command
+
+            module.sh = function(self, command, what)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("known_hosts: failed to " .. what .. " " .. self.params.name .. ": " .. result.stderr)
+                end
+            end
+
+            -- The keys of the current entries as "type base64", skipping
+            -- the comment lines ssh-keygen -F prints.
+            module.current_keys = function(self)
+                local keys = {}
+                local result = self.ssh:cmdq(self.find_command)
+                if result.exit_code ~= 0 then
+                    return keys
+                end
+                for line in string.gmatch(result.stdout, "[^\n]+") do
+                    local key = string.match(line, "^[^#%s]%S*%s+(%S+%s+%S+)")
+                    if key ~= nil then
+                        table.insert(keys, key)
+                    end
+                end
+                return keys
+            end
+
+            -- Whether to remove the current entries, and whether to add new.
+            module.changes = function(self, keys)
+                if self.params.state == "absent" then
+                    return #keys > 0, false
+                end
+                if #keys == 0 then
+                    return false, true
+                end
+                if self.wanted_key == nil then
+                    return false, false
+                end
+                for _, key in ipairs(keys) do
+                    if key ~= self.wanted_key then
+                        return true, true
+                    end
+                end
+                return false, false
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("ssh-keygen")
+                local remove, add = self:changes(self:current_keys())
+                self.ssh:set_changed(remove or add)
+            end
+
+            module.run = function(self)
+                self.ssh:requires("ssh-keygen")
+                local remove, add = self:changes(self:current_keys())
+                if remove then
+                    self:sh(self.remove_command, "remove the entries of")
+                end
+                if add then
+                    self:sh(self.add_command, "add the keys of")
+                end
+                self.ssh:set_changed(remove or add)
+            end
+
+            return module
+        })
+        .set_name("known_hosts")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_host_pattern() {
+        assert_eq!(host_pattern("git.example.com", 22), "git.example.com");
+        assert_eq!(
+            host_pattern("git.example.com", 2222),
+            "[git.example.com]:2222"
+        );
+    }
+
+    #[test]
+    fn test_known_hosts_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(known_hosts(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "-oProxyCommand=id")?;
+        assert!(known_hosts(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "git.example.com")?;
+        params.set("key", "AAAAC3NzaC1lZDI1NTE5")?;
+        assert!(known_hosts(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_hosts_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.known_hosts({
+                    name = "git.example.com", port = 2222, key_types = "ed25519", hash = true, user = "deploy",
+                })
+            })
+            .eval::<Table>()?;
+        let add = module.get::<String>("add_command")?;
+        assert!(add.starts_with("KH=\"$(getent passwd 'deploy' | cut -d: -f6)/.ssh/known_hosts\""));
+        assert!(add.contains(
+            "ENTRIES=$(ssh-keyscan -H -p 2222 -t ed25519 'git.example.com' 2>/dev/null)"
+        ));
+        assert!(add.ends_with("chown 'deploy': \"$KH\""));
+        assert!(
+            module
+                .get::<String>("find_command")?
+                .ends_with("ssh-keygen -F '[git.example.com]:2222' -f \"$KH\"")
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.known_hosts({
+                    name = "git.example.com", key = "ssh-ed25519 AAAAC3Nza git@example", path = "/etc/ssh/ssh_known_hosts",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(module.get::<String>("wanted_key")?, "ssh-ed25519 AAAAC3Nza");
+        assert!(module.get::<String>("add_command")?.contains(
+            "ENTRIES=$(printf '%s\\n' 'git.example.com ssh-ed25519 AAAAC3Nza git@example')"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_hosts_changes() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local function changes(params, keys)
+                    params.name = "git.example.com"
+                    local module = komandan.modules.known_hosts(params)
+                    local remove, add = module:changes(keys)
+                    return { remove, add }
+                end
+                local key = "ssh-ed25519 AAAA"
+                return {
+                    changes({}, {}),
+                    changes({}, { "ssh-rsa BBBB" }),
+                    changes({ key = key }, { key }),
+                    changes({ key = key }, { key, "ssh-rsa BBBB" }),
+                    changes({ state = "absent" }, { key }),
+                    changes({ state = "absent" }, {}),
+                }
+            })
+            .eval::<Vec<Vec<bool>>>()?;
+        assert_eq!(
+            results,
+            [
+                [false, true],
+                [false, false],
+                [false, false],
+                [true, true],
+                [true, false],
+                [false, false],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use super::lvm_vg::is_valid_lvm_name;
+
+/// A logical volume size: absolute bytes, or a percentage of `VG`, `FREE`
+/// or `PVS`.
+#[derive(Debug, PartialEq, Eq)]
+enum LvSize {
+    Bytes(u64),
+    Percent(u32, &'static str),
+}
+
+impl LvSize {
+    /// Parse sizes such as `"10G"`, `"512M"`, `"100%FREE"` or `"50%VG"`;
+    /// a number without unit is in MiB, as with `lvcreate -L`.
+    fn parse(size: &str) -> mlua::Result<Self> {
+        let invalid = || {
+            RuntimeError(format!(
+                "Invalid size: '{size}'. Use e.g. '10G', '512M', '100%FREE' or '50%VG'"
+            ))
+        };
+        if let Some((percent, of)) = size.split_once('%') {
+            let percent = percent.parse::<u32>().map_err(|_| invalid())?;
+            let of = match of {
+                "VG" => "VG",
+                "FREE" => "FREE",
+                "PVS" => "PVS",
+                _ => return Err(invalid()),
+            };
+            if percent == 0 || percent > 100 {
+                return Err(invalid());
+            }
+            return Ok(Self::Percent(percent, of));
+        }
+        let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let shift = match &size[digits.len()..] {
+            "k" | "K" => 10,
+            "" | "m" | "M" => 20,
+            "g" | "G" => 30,
+            "t" | "T" => 40,
+            _ => return Err(invalid()),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .filter(|number| *number > 0)
+            .and_then(|number| number.checked_mul(1 << shift))
+            .map(Self::Bytes)
+            .ok_or_else(invalid)
+    }
+
+    /// The `lvcreate`/`lvextend` option giving this size.
+    fn option(&self) -> String {
+        match self {
+            Self::Bytes(bytes) => format!("-L {bytes}b"),
+            Self::Percent(percent, of) => format!("-l {percent}%{of}"),
+        }
+    }
+}
+
+/// Manage the LVM logical volume `lv` in the volume group `vg`. `size` is
+/// absolute (`"10G"`, `"512M"`; MiB without unit) or a percentage such as
+/// `"100%FREE"` or `"50%VG"`. A missing volume is created with `lvcreate`;
+/// an existing one smaller than `size` is grown with `lvextend`, and one
+/// larger is only shrunk with `shrink = true`. `%FREE` sizes apply when
+/// creating only. `resizefs = true` resizes the filesystem on the volume
+/// along with it. `state = "absent"` removes the volume with `lvremove`.
+pub fn lvm_lv(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let mut names = Vec::with_capacity(2);
+    for (name, what) in [("vg", "volume group"), ("lv", "logical volume")] {
+        let value = params
+            .get::<Option<String>>(name)?
+            .ok_or_else(|| RuntimeError(format!("'{name}' parameter is required")))?;
+        if !is_valid_lvm_name(&value) {
+            return Err(RuntimeError(format!("Invalid {what} name: '{value}'")));
+        }
+        names.push(value);
+    }
+    let volume = names.join("/");
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let size = match params.get::<Option<String>>("size")? {
+        Some(size) => Some(LvSize::parse(&size)?),
+        None if state == "present" => {
+            return Err(RuntimeError(
+                "'size' parameter is required unless state is 'absent'".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let size_option = size.as_ref().map(LvSize::option);
+    let (size_bytes, size_percent, percent_of) = match size {
+        Some(LvSize::Bytes(bytes)) => (Some(bytes), None, None),
+        Some(LvSize::Percent(percent, of)) => (None, Some(percent), Some(of)),
+        None => (None, None, None),
+    };
+    let shrink = params.get::<Option<bool>>("shrink")?.unwrap_or(false);
+    params.set("shrink", shrink)?;
+    let resizefs = if params.get::<Option<bool>>("resizefs")?.unwrap_or(false) {
+        " -r"
+    } else {
+        ""
+    };
+    let create_command = size_option
+        .as_deref()
+        .map(|option| format!("lvcreate -y -n {} {option} {}", names[1], names[0]));
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "lvm_lv" })
+
+            module.params = $params
+            module.volume = $volume
+            module.size_option = $size_option
+            module.size_bytes = $size_bytes
+            module.size_percent = $size_percent
+            module.percent_of = $percent_of
+            module.lvs_command = "lvs --noheadings --units b --nosuffix -o lv_size,vg_extent_size,vg_size " .. $volume
+            module.create_command = $create_command
+            module.extend_command = "lvextend" .. $resizefs .. " " .. ($size_option or "") .. " " .. $volume
+            module.reduce_command = "lvreduce -y" .. $resizefs .. " " .. ($size_option or "") .. " " .. $volume
+            module.remove_command = "lvremove -y " .. $volume
+
+            -- The volume size, extent size and volume group size in bytes;
+            -- nil when the volume does not exist.
+            module.current = function(self)
+                self.ssh:requires("lvs")
+                local result = self.ssh:cmdq(self.lvs_command)
+                if result.exit_code ~= 0 then
+                    return nil
+                end
+                local size, extent, vg_size = string.match(result.stdout, "(%d+)%s+(%d+)%s+(%d+)")
+                return { size = tonumber(size), extent = tonumber(extent), vg_size = tonumber(vg_size) }
+            end
+
+            -- The wanted size in bytes of an existing volume, nil when it
+            -- is not resized (%FREE sizes).
+            module.wanted_size = function(self, current)
+                if self.size_bytes ~= nil then
+                    return self.size_bytes
+                end
+                if self.percent_of == "FREE" then
+                    return nil
+                end
+                local extents = math.floor(current.vg_size / current.extent * self.size_percent / 100)
+                return extents * current.extent
+            end
+
+            -- The command to run, nil when the volume is as wanted.
+            module.plan = function(self, current)
+                if self.params.state == "absent" then
+                    return current ~= nil and self.remove_command or nil
+                end
+                if current == nil then
+                    return self.create_command
+                end
+                local wanted = self:wanted_size(current)
+                if wanted == nil then
+                    return nil
+                end
+                if current.size < wanted then
+                    return self.extend_command
+                end
+                if current.size - wanted >= current.extent then
+                    if not self.params.shrink then
+                        error("lvm_lv: " .. self.volume .. " is larger than the wanted size, set shrink = true to reduce it")
+                    end
+                    return self.reduce_command
+                end
+                return nil
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:plan(self:current()) ~= nil)
+            end
+
+            module.run = function(self)
+                local command = self:plan(self:current())
+                if command ~= nil then
+                    local result = self.ssh:cmd(command)
+                    if result.exit_code ~= 0 then
+                        error("lvm_lv: " .. command .. " failed: " .. result.stderr)
+                    end
+                end
+                self.ssh:set_changed(command ~= nil)
+            end
+
+            return module
+        })
+        .set_name("lvm_lv")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_lv_size_parse() -> mlua::Result<()> {
+        assert_eq!(LvSize::parse("10G")?, LvSize::Bytes(10 << 30));
+        assert_eq!(LvSize::parse("512")?, LvSize::Bytes(512 << 20));
+        assert_eq!(LvSize::parse("100%FREE")?, LvSize::Percent(100, "FREE"));
+        assert_eq!(LvSize::parse("50%VG")?.option(), "-l 50%VG");
+        assert_eq!(
+            LvSize::parse("2t")?.option(),
+            format!("-L {}b", 2_u64 << 40)
+        );
+        for invalid in ["", "G", "10X", "0M", "150%VG", "50%ORIGIN", "-5G"] {
+            assert!(LvSize::parse(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lvm_lv_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(lvm_lv(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("vg", "data")?;
+        params.set("lv", "www")?;
+        assert!(lvm_lv(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("vg", "data")?;
+        params.set("lv", "www;id")?;
+        params.set("size", "10G")?;
+        assert!(lvm_lv(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lvm_lv_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local gib = 1024 * 1024 * 1024
+                local extent = 4 * 1024 * 1024
+                local function plan(params, current)
+                    params.vg = "data"
+                    params.lv = "www"
+                    local module = komandan.modules.lvm_lv(params)
+                    return module:plan(current) or ""
+                end
+                return {
+                    plan({ size = "10G" }, nil),
+                    plan({ size = "10G" }, { size = 10 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ size = "20G", resizefs = true }, { size = 10 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ size = "5G", shrink = true }, { size = 10 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ size = "50%VG" }, { size = 50 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ size = "100%FREE" }, { size = 10 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ state = "absent" }, { size = 10 * gib, extent = extent, vg_size = 100 * gib }),
+                    plan({ state = "absent" }, nil),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            plans,
+            [
+                "lvcreate -y -n www -L 10737418240b data",
+                "",
+                "lvextend -r -L 21474836480b data/www",
+                "lvreduce -y -L 5368709120b data/www",
+                "",
+                "",
+                "lvremove -y data/www",
+                "",
+            ]
+        );
+
+        let result = lua
+            .load(chunk! {
+                local module = komandan.modules.lvm_lv({ vg = "data", lv = "www", size = "5G" })
+                module:plan({ size = 10 * 1024 * 1024 * 1024, extent = 4194304, vg_size = 0 })
+            })
+            .exec();
+        assert!(result.is_err_and(|e| e.to_string().contains("set shrink = true")));
+        Ok(())
+    }
+}
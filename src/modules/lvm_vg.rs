@@ -0,0 +1,230 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+/// Whether `name` is usable as an LVM volume group or logical volume name.
+pub(super) fn is_valid_lvm_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-+".contains(c))
+}
+
+/// Manage the LVM volume group `vg` on the physical volumes `pvs` (a device
+/// or a list of devices, e.g. `{ "/dev/sdb", "/dev/sdc" }`). Devices that
+/// are not physical volumes yet are initialized with `pvcreate`; a missing
+/// group is created with `vgcreate`, and an existing one is extended with
+/// the devices it lacks. Physical volumes are never removed from a group.
+/// `state = "absent"` removes the group with `vgremove`, which fails while
+/// it still holds logical volumes unless `force = true`. Sets `created`
+/// (the devices initialized as physical volumes) in the task result.
+pub fn lvm_vg(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let vg = params
+        .get::<Option<String>>("vg")?
+        .ok_or_else(|| RuntimeError("'vg' parameter is required".to_string()))?;
+    if !is_valid_lvm_name(&vg) {
+        return Err(RuntimeError(format!("Invalid volume group name: '{vg}'")));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let pvs = super::name_list(&params, "pvs", "/._-:")?;
+    if let Some(pv) = pvs.iter().find(|pv| !pv.starts_with('/')) {
+        return Err(RuntimeError(format!(
+            "Invalid physical volume: '{pv}'. Use an absolute device path"
+        )));
+    }
+    if state == "present" && pvs.is_empty() {
+        return Err(RuntimeError(
+            "'pvs' parameter is required unless state is 'absent'".to_string(),
+        ));
+    }
+    let force = if params.get::<Option<bool>>("force")?.unwrap_or(false) {
+        " -f"
+    } else {
+        ""
+    };
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "lvm_vg" })
+
+            module.params = $params
+            module.vg = $vg
+            module.pvs = $pvs
+            module.pvs_command = "pvs --noheadings --separator '|' -o pv_name,vg_name"
+            module.vgs_command = "vgs --noheadings -o vg_name"
+            module.remove_command = "vgremove -y" .. $force .. " " .. $vg
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("lvm_vg: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The volume group of every physical volume ("" for none), and
+            -- whether the volume group exists.
+            module.current = function(self)
+                self.ssh:requires({ "pvs", "vgs" })
+                local pvs = {}
+                for pv, vg in string.gmatch(self:sh(self.pvs_command), "%s*([^|\n]+)|([^\n]*)") do
+                    pvs[pv] = string.match(vg, "^%s*(.-)%s*$")
+                end
+                local exists = false
+                for vg in string.gmatch(self:sh(self.vgs_command), "%S+") do
+                    exists = exists or vg == self.vg
+                end
+                return pvs, exists
+            end
+
+            -- Devices to pvcreate, whether to vgcreate or vgremove, and
+            -- devices to add with vgextend.
+            module.plan = function(self, pvs, exists)
+                local plan = { pvcreate = {}, extend = {}, create = false, remove = false }
+                if self.params.state == "absent" then
+                    plan.remove = exists
+                    return plan
+                end
+                for _, pv in ipairs(self.pvs) do
+                    local vg = pvs[pv]
+                    if vg == nil then
+                        table.insert(plan.pvcreate, pv)
+                    elseif vg ~= "" and vg ~= self.vg then
+                        error("lvm_vg: " .. pv .. " already belongs to volume group " .. vg)
+                    end
+                    if exists and vg ~= self.vg then
+                        table.insert(plan.extend, pv)
+                    end
+                end
+                plan.create = not exists
+                return plan
+            end
+
+            module.changes = function(plan)
+                return #plan.pvcreate > 0 or #plan.extend > 0 or plan.create or plan.remove
+            end
+
+            module.dry_run = function(self)
+                local plan = self:plan(self:current())
+                self:set_result("created", plan.pvcreate)
+                self.ssh:set_changed(self.changes(plan))
+            end
+
+            module.run = function(self)
+                local plan = self:plan(self:current())
+                if plan.remove then
+                    self:sh(self.remove_command)
+                end
+                if #plan.pvcreate > 0 then
+                    self:sh("pvcreate -y " .. table.concat(plan.pvcreate, " "))
+                end
+                if plan.create then
+                    self:sh("vgcreate -y " .. self.vg .. " " .. table.concat(self.pvs, " "))
+                elseif #plan.extend > 0 then
+                    self:sh("vgextend -y " .. self.vg .. " " .. table.concat(plan.extend, " "))
+                end
+                self:set_result("created", plan.pvcreate)
+                self.ssh:set_changed(self.changes(plan))
+            end
+
+            return module
+        })
+        .set_name("lvm_vg")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_is_valid_lvm_name() {
+        assert!(is_valid_lvm_name("vg_data"));
+        assert!(is_valid_lvm_name("lv-root.1"));
+        assert!(!is_valid_lvm_name("-vg"));
+        assert!(!is_valid_lvm_name("vg data"));
+    }
+
+    #[test]
+    fn test_lvm_vg_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(lvm_vg(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("vg", "data")?;
+        assert!(lvm_vg(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("vg", "data")?;
+        params.set("pvs", "sdb")?;
+        assert!(lvm_vg(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("vg", "data")?;
+        params.set("state", "absent")?;
+        assert!(lvm_vg(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lvm_vg_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local function plan(params, pvs, exists)
+                    params.vg = "data"
+                    local module = komandan.modules.lvm_vg(params)
+                    local plan = module:plan(pvs, exists)
+                    return {
+                        table.concat(plan.pvcreate, ","),
+                        table.concat(plan.extend, ","),
+                        tostring(plan.create),
+                        tostring(plan.remove),
+                    }
+                end
+                local devices = { "/dev/sdb", "/dev/sdc" }
+                return {
+                    plan({ pvs = devices }, { ["/dev/sda2"] = "system" }, false),
+                    plan({ pvs = devices }, { ["/dev/sdb"] = "data", ["/dev/sdc"] = "" }, true),
+                    plan({ pvs = devices }, { ["/dev/sdb"] = "data", ["/dev/sdc"] = "data" }, true),
+                    plan({ state = "absent" }, {}, true),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["/dev/sdb,/dev/sdc", "", "true", "false"],
+                ["", "/dev/sdc", "false", "false"],
+                ["", "", "false", "false"],
+                ["", "", "false", "true"],
+            ]
+        );
+
+        let result = lua
+            .load(chunk! {
+                local module = komandan.modules.lvm_vg({ vg = "data", pvs = "/dev/sdb" })
+                module:plan({ ["/dev/sdb"] = "other" }, true)
+            })
+            .exec();
+        assert!(result.is_err_and(|e| {
+            e.to_string()
+                .contains("already belongs to volume group other")
+        }));
+        Ok(())
+    }
+}
@@ -1,19 +1,92 @@
+mod acme_certificate;
 mod apt;
+mod apt_key;
 mod base;
+mod brew;
+mod cargo;
+mod checksum;
 mod cmd;
+mod copy;
 mod core;
+mod cron;
+mod debconf;
 mod dnf;
+mod docker_compose;
 mod download;
+mod fetch;
 mod file;
+mod filesystem;
+mod flatpak;
+mod gem;
 mod get_url;
+mod git;
+mod git_config;
 mod group;
+mod journald;
+mod json_file;
+mod k8s;
+mod known_hosts;
 mod lineinfile;
+mod lvm_lv;
+mod lvm_vg;
+mod mongodb_user;
+mod npm;
+mod openrc_service;
+mod openssl;
+mod package;
+mod patch;
+mod pip;
 mod postgresql_user;
+mod reboot_required;
+mod redis_config;
 mod script;
+mod seboolean;
+mod sefcontext;
+mod setup;
+mod ssh_config;
+mod sshd_config;
+mod swap;
 mod systemd_service;
+mod systemd_timer;
 mod template;
+mod timesync;
 mod upload;
+mod uri;
 mod user;
+mod wireguard;
+mod xml;
 
 pub use base::*;
+pub(crate) use checksum::to_hex;
 pub use core::*;
+
+/// The `key` parameter as a list of names: a single string or a list of
+/// strings. Names must be non-empty, must not start with `-`, and use only
+/// ASCII letters, digits and the characters in `extra`, so they can be
+/// passed to a shell unquoted.
+fn name_list(params: &mlua::Table, key: &str, extra: &str) -> mlua::Result<Vec<String>> {
+    let names = match params.get::<mlua::Value>(key)? {
+        mlua::Value::Nil => Vec::new(),
+        mlua::Value::String(name) => vec![name.to_str()?.to_string()],
+        mlua::Value::Table(names) => names
+            .sequence_values::<String>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "'{key}' parameter must be a string or a list of strings"
+            )));
+        }
+    };
+    if let Some(name) = names.iter().find(|name| {
+        name.is_empty()
+            || name.starts_with('-')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+    }) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "Invalid name in '{key}': '{name}'"
+        )));
+    }
+    Ok(names)
+}
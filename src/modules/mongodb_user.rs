@@ -0,0 +1,416 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+use serde_json::json;
+
+use crate::local::escape_shell_value;
+
+/// A role granted to a MongoDB user, as `role` on the database `db`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Role {
+    role: String,
+    db: String,
+}
+
+impl Role {
+    /// The role as printed by the existence check, e.g. `readWrite@app`.
+    fn key(&self) -> String {
+        format!("{}@{}", self.role, self.db)
+    }
+}
+
+/// Parse `roles`, a list of role names granted on `database` or of
+/// `{ role = "...", db = "..." }` tables. `None` when roles are not managed.
+fn parse_roles(roles: Value, database: &str) -> mlua::Result<Option<Vec<Role>>> {
+    let roles = match roles {
+        Value::Nil => return Ok(None),
+        Value::Table(roles) => roles,
+        _ => return Err(RuntimeError("'roles' must be a list".to_string())),
+    };
+    let mut parsed = Vec::new();
+    for role in roles.sequence_values::<Value>() {
+        parsed.push(match role? {
+            Value::String(role) => Role {
+                role: role.to_str()?.to_string(),
+                db: database.to_string(),
+            },
+            Value::Table(role) => Role {
+                role: role
+                    .get::<Option<String>>("role")?
+                    .ok_or_else(|| RuntimeError("every role needs a 'role' name".to_string()))?,
+                db: role
+                    .get::<Option<String>>("db")?
+                    .unwrap_or_else(|| database.to_string()),
+            },
+            _ => {
+                return Err(RuntimeError(
+                    "'roles' entries must be role names or { role, db } tables".to_string(),
+                ));
+            }
+        });
+    }
+    parsed.sort();
+    parsed.dedup();
+    Ok(Some(parsed))
+}
+
+fn roles_json(roles: &[Role]) -> serde_json::Value {
+    roles
+        .iter()
+        .map(|role| json!({ "role": role.role, "db": role.db }))
+        .collect()
+}
+
+/// `mongosh --quiet` with the `login_host` and `login_port` in `params`.
+fn mongosh_connect(params: &Table) -> mlua::Result<String> {
+    let mut command = String::from("mongosh --quiet");
+    if let Some(host) = params.get::<Option<String>>("login_host")? {
+        let _ = write!(command, " --host {}", escape_shell_value(&host));
+    }
+    if let Some(port) = params.get::<Option<u16>>("login_port")? {
+        let _ = write!(command, " --port {port}");
+    }
+    Ok(command)
+}
+
+/// The `mongosh` invocation, with the login options in `params`, that
+/// evaluates the script appended to it.
+fn mongosh_prefix(params: &Table) -> mlua::Result<String> {
+    let mut command = mongosh_connect(params)?;
+    if let Some(user) = params.get::<Option<String>>("login_user")? {
+        let _ = write!(command, " --username {}", escape_shell_value(&user));
+        if let Some(password) = params.get::<Option<String>>("login_password")? {
+            let _ = write!(command, " --password {}", escape_shell_value(&password));
+        }
+        let auth_db = params
+            .get::<Option<String>>("login_database")?
+            .unwrap_or_else(|| "admin".to_string());
+        let _ = write!(
+            command,
+            " --authenticationDatabase {}",
+            escape_shell_value(&auth_db)
+        );
+    }
+    command.push_str(" --eval ");
+    Ok(command)
+}
+
+/// Manage a MongoDB user and its roles with `mongosh`. `state = "present"`
+/// (the default, or `action = "create"`) creates the user `name` in
+/// `database` (default `admin`) when missing and, when `roles` is given,
+/// replaces its roles if they differ; `state = "absent"` (or `action =
+/// "drop"`) removes it. `roles` lists role names on `database` or `{ role,
+/// db }` tables. The `password` is only set when the user is created,
+/// unless `update_password = "always"`: then logging in as the user is
+/// tried, and the password is updated when that fails. `login_host`,
+/// `login_port`, `login_user`, `login_password` and `login_database` set
+/// how `mongosh` connects.
+pub fn mongodb_user(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    let action = match (
+        params.get::<Option<String>>("action")?,
+        params.get::<Option<String>>("state")?,
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(RuntimeError(
+                "'action' and 'state' parameters are mutually exclusive".to_string(),
+            ));
+        }
+        (Some(action), None) => action,
+        (None, Some(state)) => match state.as_str() {
+            "present" => "create".to_string(),
+            "absent" => "drop".to_string(),
+            _ => {
+                return Err(RuntimeError(
+                    "'state' parameter must be 'present' or 'absent'".to_string(),
+                ));
+            }
+        },
+        (None, None) => "create".to_string(),
+    };
+    if action != "create" && action != "drop" {
+        return Err(RuntimeError(format!(
+            "Invalid action: {action}. Valid actions are: create and drop."
+        )));
+    }
+    params.set("action", action.as_str())?;
+    let database = params
+        .get::<Option<String>>("database")?
+        .unwrap_or_else(|| "admin".to_string());
+    let roles = parse_roles(params.get::<Value>("roles")?, &database)?;
+    let password = params.get::<Option<String>>("password")?;
+    let update_password = params
+        .get::<Option<String>>("update_password")?
+        .unwrap_or_else(|| "on_create".to_string());
+    if update_password != "on_create" && update_password != "always" {
+        return Err(RuntimeError(
+            "'update_password' parameter must be 'on_create' or 'always'".to_string(),
+        ));
+    }
+    if update_password == "always" && password.is_none() {
+        return Err(RuntimeError(
+            "'password' parameter is required when update_password is 'always'".to_string(),
+        ));
+    }
+
+    let target = format!("const target = db.getSiblingDB({});\n", json!(database));
+    let check = format!(
+        "{target}const user = target.getUser({});\nif (user === null) {{ print('absent'); }} else {{ print('present'); user.roles.forEach(r => print(r.role + '@' + r.db)); }}",
+        json!(name)
+    );
+    let mut spec = json!({
+        "user": name,
+        "roles": roles.as_deref().map_or_else(|| json!([]), roles_json),
+    });
+    if let Some(password) = &password {
+        spec["pwd"] = json!(password);
+    }
+    let create = format!("{target}target.createUser({spec});");
+    let update = roles.as_deref().map(|roles| {
+        format!(
+            "{target}target.updateUser({}, {{ roles: {} }});",
+            json!(name),
+            roles_json(roles)
+        )
+    });
+    let drop = format!("{target}target.dropUser({});", json!(name));
+    let password_update = format!(
+        "{target}target.updateUser({}, {{ pwd: {} }});",
+        json!(name),
+        json!(password)
+    );
+
+    let prefix = mongosh_prefix(&params)?;
+    let command = |script: &str| format!("{prefix}{}", escape_shell_value(script));
+    let check_command = command(&check);
+    let create_command = command(&create);
+    let update_command = update.as_deref().map(command);
+    let drop_command = command(&drop);
+    let password_check_command = match &password {
+        Some(password) if update_password == "always" => Some(format!(
+            "{} --username {} --password {} --authenticationDatabase {} --eval 'db.runCommand({{ ping: 1 }}).ok'",
+            mongosh_connect(&params)?,
+            escape_shell_value(&name),
+            escape_shell_value(password),
+            escape_shell_value(&database)
+        )),
+        _ => None,
+    };
+    let password_update_command = password_check_command
+        .as_ref()
+        .map(|_| command(&password_update));
+    let wanted_roles = roles.map(|roles| {
+        let mut keys = roles.iter().map(Role::key).collect::<Vec<_>>();
+        keys.sort();
+        keys
+    });
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "mongodb_user" })
+
+            module.params = $params
+            module.check_command = $check_command
+            module.create_command = $create_command
+            module.update_command = $update_command
+            module.drop_command = $drop_command
+            module.password_check_command = $password_check_command
+            module.password_update_command = $password_update_command
+            module.wanted_roles = $wanted_roles
+
+            module.mongosh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("mongodb_user: mongosh failed: " .. result.stderr)
+                end
+                return result
+            end
+
+            -- Whether the user exists, and whether its roles are the wanted
+            -- ones (always true when roles are not managed).
+            module.state = function(self)
+                self.ssh:requires("mongosh")
+                local lines = {}
+                for line in self:mongosh(self.check_command).stdout:gmatch("[^\n]+") do
+                    table.insert(lines, line)
+                end
+                if lines[1] ~= "present" then
+                    return false, false
+                end
+                if self.wanted_roles == nil then
+                    return true, true
+                end
+                local current = {}
+                for i = 2, #lines do
+                    table.insert(current, lines[i])
+                end
+                table.sort(current)
+                return true, table.concat(current, "\n") == table.concat(self.wanted_roles, "\n")
+            end
+
+            -- Whether the password needs updating: only with
+            -- update_password = "always", when logging in as the user fails.
+            module.password_outdated = function(self)
+                if self.password_check_command == nil then
+                    return false
+                end
+                return self.ssh:cmdq(self.password_check_command).exit_code ~= 0
+            end
+
+            module.dry_run = function(self)
+                local exists, roles_match = self:state()
+                if self.params.action == "create" then
+                    self.ssh:set_changed(not exists or not roles_match or self:password_outdated())
+                else
+                    self.ssh:set_changed(exists)
+                end
+            end
+
+            module.run = function(self)
+                local exists, roles_match = self:state()
+                if self.params.action == "create" then
+                    if not exists then
+                        self:mongosh(self.create_command)
+                        self.ssh:set_changed(true)
+                    else
+                        if not roles_match then
+                            self:mongosh(self.update_command)
+                            self.ssh:set_changed(true)
+                        end
+                        if self:password_outdated() then
+                            self:mongosh(self.password_update_command)
+                            self.ssh:set_changed(true)
+                        end
+                    end
+                elseif exists then
+                    self:mongosh(self.drop_command)
+                    self.ssh:set_changed(true)
+                end
+            end
+
+            return module
+        })
+        .set_name("mongodb_user")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_mongodb_user_requires_name_parameter() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = mongodb_user(&lua, lua.create_table()?);
+        assert!(result.is_err_and(|e| e.to_string().contains("'name' parameter is required")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mongodb_user_validates_action_parameter() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("name", "app")?;
+        params.set("action", "invalid_action")?;
+        let result = mongodb_user(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("Invalid action")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mongodb_user_state_and_update_password() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.mongodb_user({
+                    name = "app", password = "secret", database = "app", state = "present",
+                    update_password = "always", login_port = 27018,
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<Table>("params")?.get::<String>("action")?,
+            "create"
+        );
+        assert_eq!(
+            module.get::<String>("password_check_command")?,
+            "mongosh --quiet --port 27018 --username 'app' --password 'secret' --authenticationDatabase 'app' --eval 'db.runCommand({ ping: 1 }).ok'"
+        );
+        assert!(
+            module
+                .get::<String>("password_update_command")?
+                .contains(r#"updateUser("app", { pwd: "secret" })"#)
+        );
+
+        let params = lua.create_table()?;
+        params.set("name", "app")?;
+        params.set("state", "absent")?;
+        params.set("action", "drop")?;
+        assert!(mongodb_user(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "app")?;
+        params.set("update_password", "always")?;
+        assert!(mongodb_user(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_roles() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let roles = lua
+            .load(
+                chunk! { return { "readWrite", { role = "read", db = "reporting" }, "readWrite" } },
+            )
+            .eval::<Value>()?;
+        let roles = parse_roles(roles, "app")?.unwrap_or_default();
+        assert_eq!(
+            roles.iter().map(Role::key).collect::<Vec<_>>(),
+            ["read@reporting", "readWrite@app"]
+        );
+        assert!(parse_roles(Value::Nil, "app")?.is_none());
+        assert!(parse_roles(Value::Boolean(true), "app").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mongodb_user_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.mongodb_user({
+                    name = "app",
+                    password = "it's secret",
+                    database = "app",
+                    roles = { "readWrite" },
+                    login_user = "root",
+                    login_password = "hunter2",
+                })
+            })
+            .eval::<Table>()?;
+        let create = module.get::<String>("create_command")?;
+        assert!(create.starts_with(
+            "mongosh --quiet --username 'root' --password 'hunter2' --authenticationDatabase 'admin' --eval '"
+        ));
+        assert!(create.contains("createUser"));
+        assert!(create.contains(r#""pwd":"it'\''s secret""#));
+        assert!(create.contains(r#""role":"readWrite""#));
+        let update = module.get::<String>("update_command")?;
+        assert!(update.contains(r#"updateUser("app", { roles: [{"#));
+        assert!(update.contains(r#""db":"app""#));
+        assert_eq!(
+            module.get::<Vec<String>>("wanted_roles")?,
+            ["readWrite@app"]
+        );
+        Ok(())
+    }
+}
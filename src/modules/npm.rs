@@ -0,0 +1,280 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Manage Node.js packages with npm, globally or, with `path`, in that
+/// project directory. `name` is a package or a list of packages (scoped
+/// names such as `@vue/cli` allowed) and `version` pins a single `name` to
+/// an exact version. `state` is `present` (default), `absent` or `latest`.
+/// `ci = true` runs `npm ci` in `path` when `node_modules` does not match
+/// `package-lock.json`. `registry` sets the registry URL. Installed
+/// versions come from `npm ls --depth=0 --json` and outdated ones from
+/// `npm outdated --json`, so only packages that need it are touched. Sets
+/// `installed` and `removed` (lists of packages) in the task result.
+pub fn npm(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let packages = super::name_list(&params, "name", "@/._-~")?;
+    let path = params.get::<Option<String>>("path")?;
+    let ci = params.get::<Option<bool>>("ci")?.unwrap_or(false);
+    params.set("ci", ci)?;
+    if packages.is_empty() && !ci {
+        return Err(RuntimeError(
+            "'name' parameter is required unless ci is true".to_string(),
+        ));
+    }
+    if ci && path.is_none() {
+        return Err(RuntimeError("'ci' needs the project 'path'".to_string()));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let version = params.get::<Option<String>>("version")?;
+    if let Some(version) = &version {
+        if packages.len() != 1 || state != "present" {
+            return Err(RuntimeError(
+                "'version' parameter needs a single 'name' and state 'present'".to_string(),
+            ));
+        }
+        if version.is_empty()
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".+-".contains(c))
+        {
+            return Err(RuntimeError(format!("Invalid version: '{version}'")));
+        }
+    }
+
+    let (prefix, global) = path.as_deref().map_or_else(
+        || (String::new(), " -g"),
+        |path| (format!("cd {} && ", escape_shell_value(path)), ""),
+    );
+    let registry = params
+        .get::<Option<String>>("registry")?
+        .map_or_else(String::new, |registry| {
+            format!(" --registry {}", escape_shell_value(&registry))
+        });
+    let ls_command = format!("{prefix}npm ls --depth=0 --json{global}");
+    let outdated_command = format!("{prefix}npm outdated --json{global}{registry}");
+    let install_command = format!("{prefix}npm install{global}{registry}");
+    let uninstall_command = format!("{prefix}npm uninstall{global}");
+    let ci_check = format!("{prefix}cmp -s package-lock.json node_modules/.package-lock.json");
+    let ci_command = format!("{prefix}npm ci{registry}");
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "npm" })
+
+            module.params = $params
+            module.packages = $packages
+            module.version = $version
+            module.ls_command = $ls_command
+            module.outdated_command = $outdated_command
+            module.install_command = $install_command
+            module.uninstall_command = $uninstall_command
+            module.ci_check = $ci_check
+            module.ci_command = $ci_command
+
+            module.npm = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("npm: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The JSON output of npm ls or npm outdated; both exit with 1
+            -- when they find problems or outdated packages.
+            module.json = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code > 1 or (result.exit_code == 1 and result.stdout == "") then
+                    error("npm: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The object of key name in json, as text. Both outputs nest
+            -- no deeper at depth 0.
+            module.entry = function(json, name)
+                local key = string.gsub(name, "%p", "%%%0")
+                return string.match(json, "\"" .. key .. "\":%s*(%b{})")
+            end
+
+            -- Packages to install and packages to remove.
+            module.plan = function(self)
+                local install, remove = {}, {}
+                if #self.packages == 0 then
+                    return install, remove
+                end
+                local installed = self:json(self.ls_command)
+                local outdated = "{}"
+                if self.params.state == "latest" then
+                    outdated = self:json(self.outdated_command)
+                end
+                for _, name in ipairs(self.packages) do
+                    local entry = self.entry(installed, name)
+                    local current = entry and string.match(entry, "\"version\":%s*\"([^\"]+)\"")
+                    if self.params.state == "absent" then
+                        if current ~= nil then
+                            table.insert(remove, name)
+                        end
+                    elseif current == nil then
+                        table.insert(install, self.version and name .. "@" .. self.version or name)
+                    elseif self.version ~= nil and current ~= self.version then
+                        table.insert(install, name .. "@" .. self.version)
+                    elseif self.entry(outdated, name) ~= nil then
+                        table.insert(install, name .. "@latest")
+                    end
+                end
+                return install, remove
+            end
+
+            module.ci_pending = function(self)
+                return self.params.ci and self.ssh:cmdq(self.ci_check).exit_code ~= 0
+            end
+
+            module.quoted = function(names)
+                local quoted = {}
+                for _, name in ipairs(names) do
+                    table.insert(quoted, "'" .. name .. "'")
+                end
+                return table.concat(quoted, " ")
+            end
+
+            module.dry_run = function(self)
+                local ci = self:ci_pending()
+                local install, remove = self:plan()
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(ci or #install > 0 or #remove > 0)
+            end
+
+            module.run = function(self)
+                local ci = self:ci_pending()
+                if ci then
+                    self:npm(self.ci_command)
+                end
+                local install, remove = self:plan()
+                if #install > 0 then
+                    self:npm(self.install_command .. " " .. self.quoted(install))
+                end
+                if #remove > 0 then
+                    self:npm(self.uninstall_command .. " " .. self.quoted(remove))
+                end
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(ci or #install > 0 or #remove > 0)
+            end
+
+            return module
+        })
+        .set_name("npm")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_npm_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(npm(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("ci", true)?;
+        let result = npm(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'ci' needs the project 'path'")));
+
+        let params = lua.create_table()?;
+        params.set("name", "pm2")?;
+        params.set("version", "^5.0.0")?;
+        assert!(npm(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "pm2 && reboot")?;
+        assert!(npm(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_npm_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.npm({ name = "pm2" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("ls_command")?,
+            "npm ls --depth=0 --json -g"
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.npm({ path = "/srv/app", ci = true, registry = "https://npm.example.com" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("ci_command")?,
+            "cd '/srv/app' && npm ci --registry 'https://npm.example.com'"
+        );
+        assert_eq!(
+            module.get::<String>("ls_command")?,
+            "cd '/srv/app' && npm ls --depth=0 --json"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_npm_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local ls = [[{
+                  "name": "app",
+                  "dependencies": {
+                    "@vue/cli": { "version": "5.0.8", "overridden": false },
+                    "pm2": { "version": "5.3.0", "overridden": false }
+                  }
+                }]]
+                local outdated = [[{ "pm2": { "current": "5.3.0", "wanted": "5.3.0", "latest": "5.4.2" } }]]
+                local function plan(params)
+                    local module = komandan.modules.npm(params)
+                    module.json = function(self, command)
+                        return command == self.outdated_command and outdated or ls
+                    end
+                    local install, remove = module:plan()
+                    return { table.concat(install, ","), table.concat(remove, ",") }
+                end
+                return {
+                    plan({ name = { "pm2", "@vue/cli", "yarn" } }),
+                    plan({ name = "pm2", version = "5.4.2" }),
+                    plan({ name = { "pm2", "@vue/cli" }, state = "latest" }),
+                    plan({ name = { "@vue/cli", "yarn" }, state = "absent" }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["yarn", ""],
+                ["pm2@5.4.2", ""],
+                ["pm2@latest", ""],
+                ["", "@vue/cli"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+/// Whether `name` is usable as an OpenRC service or runlevel name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-@".contains(c))
+}
+
+/// Manage an OpenRC service, as on Alpine and Gentoo hosts. `state` is
+/// `started`, `stopped`, `restarted` or `reloaded` and runs `rc-service`
+/// when needed; `enabled = true` or `false` adds the service to or deletes
+/// it from `runlevel` (default `default`) with `rc-update`. At least one of
+/// `state` and `enabled` is required. `restarted` and `reloaded` always
+/// change the host.
+pub fn openrc_service(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if !is_valid_name(&name) {
+        return Err(RuntimeError(format!("Invalid service name: '{name}'")));
+    }
+    let state = params.get::<Option<String>>("state")?;
+    if let Some(state) = &state
+        && !["started", "stopped", "restarted", "reloaded"].contains(&state.as_str())
+    {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: started, stopped, restarted and reloaded."
+        )));
+    }
+    let enabled = params.get::<Option<bool>>("enabled")?;
+    if state.is_none() && enabled.is_none() {
+        return Err(RuntimeError(
+            "'state' or 'enabled' parameter is required".to_string(),
+        ));
+    }
+    let runlevel = params
+        .get::<Option<String>>("runlevel")?
+        .unwrap_or_else(|| "default".to_string());
+    if !is_valid_name(&runlevel) {
+        return Err(RuntimeError(format!("Invalid runlevel: '{runlevel}'")));
+    }
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "openrc_service" })
+
+            module.params = $params
+            module.state = $state
+            module.enabled = $enabled
+            module.status_command = "rc-service " .. $name .. " status"
+            module.enabled_command = "[ -e /etc/runlevels/" .. $runlevel .. "/" .. $name .. " ]"
+            module.service_command = "rc-service " .. $name .. " "
+            module.add_command = "rc-update add " .. $name .. " " .. $runlevel
+            module.del_command = "rc-update del " .. $name .. " " .. $runlevel
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmd(command)
+                if result.exit_code ~= 0 then
+                    error("openrc_service: " .. command .. " failed: " .. result.stderr)
+                end
+            end
+
+            -- The rc-service action to run (nil for none) and the rc-update
+            -- command to run (nil for none), given the current status.
+            module.plan = function(self, started, enabled)
+                local action
+                if self.state == "started" and not started then
+                    action = "start"
+                elseif self.state == "stopped" and started then
+                    action = "stop"
+                elseif self.state == "restarted" then
+                    action = "restart"
+                elseif self.state == "reloaded" then
+                    action = "reload"
+                end
+                local update
+                if self.enabled == true and not enabled then
+                    update = self.add_command
+                elseif self.enabled == false and enabled then
+                    update = self.del_command
+                end
+                return action, update
+            end
+
+            module.current = function(self)
+                self.ssh:requires({ "rc-service", "rc-update" })
+                local started = self.ssh:cmdq(self.status_command).exit_code == 0
+                local enabled = self.ssh:cmdq(self.enabled_command).exit_code == 0
+                return started, enabled
+            end
+
+            module.dry_run = function(self)
+                local action, update = self:plan(self:current())
+                self.ssh:set_changed(action ~= nil or update ~= nil)
+            end
+
+            module.run = function(self)
+                local action, update = self:plan(self:current())
+                if update ~= nil then
+                    self:sh(update)
+                end
+                if action ~= nil then
+                    self:sh(self.service_command .. action)
+                end
+                self.ssh:set_changed(action ~= nil or update ~= nil)
+            end
+
+            return module
+        })
+        .set_name("openrc_service")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_openrc_service_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(openrc_service(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "nginx")?;
+        let result = openrc_service(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'state' or 'enabled'")));
+
+        let params = lua.create_table()?;
+        params.set("name", "nginx")?;
+        params.set("state", "running")?;
+        assert!(openrc_service(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "nginx")?;
+        params.set("enabled", true)?;
+        params.set("runlevel", "../boot")?;
+        assert!(openrc_service(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_openrc_service_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local function plan(params, started, enabled)
+                    params.name = "nginx"
+                    local module = komandan.modules.openrc_service(params)
+                    local action, update = module:plan(started, enabled)
+                    return { action or "", update or "" }
+                end
+                return {
+                    plan({ state = "started", enabled = true }, false, false),
+                    plan({ state = "started", enabled = true }, true, true),
+                    plan({ state = "stopped", enabled = false, runlevel = "boot" }, true, true),
+                    plan({ state = "restarted" }, true, true),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["start", "rc-update add nginx default"],
+                ["", ""],
+                ["stop", "rc-update del nginx boot"],
+                ["restart", ""],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,491 @@
+use std::fmt::Write as _;
+use std::net::IpAddr;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// Days a certificate is valid when `valid_days` is not given.
+const DEFAULT_VALID_DAYS: u32 = 365;
+
+/// Subject fields accepted in a `subject` table, in the order they are
+/// written to the subject.
+const SUBJECT_FIELDS: [&str; 7] = ["C", "ST", "L", "O", "OU", "CN", "emailAddress"];
+
+/// The `-subj` argument for `subject`: a string such as `"/CN=example.com"`
+/// or a table of `SUBJECT_FIELDS`. Without a subject the first name in
+/// `subject_alt_names` becomes the common name.
+fn subject(value: Value, alt_names: &[String]) -> mlua::Result<String> {
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('/', "\\/");
+    let subject = match value {
+        Value::Nil => alt_names
+            .first()
+            .map(|name| format!("/CN={}", escape(name.split_once(':').map_or(name.as_str(), |(_, n)| n))))
+            .ok_or_else(|| {
+                RuntimeError(
+                    "'subject' or 'subject_alt_names' parameter is required for a CSR or certificate"
+                        .to_string(),
+                )
+            })?,
+        Value::String(subject) => {
+            let subject = subject.to_str()?.to_string();
+            if !subject.starts_with('/') || !subject.contains('=') {
+                return Err(RuntimeError(format!(
+                    "Invalid subject: '{subject}'. Use the form '/CN=example.com/O=Example'"
+                )));
+            }
+            subject
+        }
+        Value::Table(fields) => {
+            for pair in fields.pairs::<String, Value>() {
+                let (field, _) = pair?;
+                if !SUBJECT_FIELDS.contains(&field.as_str()) {
+                    return Err(RuntimeError(format!(
+                        "Invalid subject field: {field}. Valid fields are: {}.",
+                        SUBJECT_FIELDS.join(", ")
+                    )));
+                }
+            }
+            let mut subject = String::new();
+            for field in SUBJECT_FIELDS {
+                if let Some(value) = fields.get::<Option<String>>(field)? {
+                    let _ = write!(subject, "/{field}={}", escape(&value));
+                }
+            }
+            if subject.is_empty() {
+                return Err(RuntimeError("'subject' parameter is empty".to_string()));
+            }
+            subject
+        }
+        _ => {
+            return Err(RuntimeError(
+                "'subject' parameter must be a string or a table".to_string(),
+            ));
+        }
+    };
+    if subject.contains('\n') {
+        return Err(RuntimeError(
+            "'subject' parameter must be a single line".to_string(),
+        ));
+    }
+    Ok(subject)
+}
+
+/// `subject_alt_names` with their type: entries without one become `IP:`
+/// for IP addresses and `DNS:` otherwise.
+fn alt_names(params: &Table) -> mlua::Result<Vec<String>> {
+    let names = match params.get::<Value>("subject_alt_names")? {
+        Value::Nil => Vec::new(),
+        Value::String(name) => vec![name.to_str()?.to_string()],
+        Value::Table(names) => names
+            .sequence_values::<String>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(
+                "'subject_alt_names' parameter must be a string or a list of strings".to_string(),
+            ));
+        }
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            if name.is_empty() || name.contains([',', '\n']) {
+                return Err(RuntimeError(format!(
+                    "Invalid name in 'subject_alt_names': '{name}'"
+                )));
+            }
+            Ok(match name.split_once(':') {
+                Some((kind, _)) if ["DNS", "IP", "email", "URI"].contains(&kind) => name,
+                _ if name.parse::<IpAddr>().is_ok() => format!("IP:{name}"),
+                _ => format!("DNS:{name}"),
+            })
+        })
+        .collect()
+}
+
+/// The `openssl genpkey` options for the key parameters, and the same
+/// parameters as the module compares them: the key type and the RSA size
+/// or EC curve.
+fn key_options(params: &Table) -> mlua::Result<(String, String, String)> {
+    let key_type = params
+        .get::<Option<String>>("key_type")?
+        .unwrap_or_else(|| "rsa".to_string());
+    let key_size = params.get::<Option<u32>>("key_size")?;
+    let curve = params.get::<Option<String>>("curve")?;
+    if key_type != "rsa" && key_size.is_some() {
+        return Err(RuntimeError(
+            "'key_size' parameter only applies to rsa keys".to_string(),
+        ));
+    }
+    if key_type != "ec" && curve.is_some() {
+        return Err(RuntimeError(
+            "'curve' parameter only applies to ec keys".to_string(),
+        ));
+    }
+    match key_type.as_str() {
+        "rsa" => {
+            let size = key_size.unwrap_or(2048);
+            if !(1024..=16384).contains(&size) {
+                return Err(RuntimeError(format!(
+                    "Invalid key_size: {size}. Use 1024 to 16384 bits"
+                )));
+            }
+            Ok((
+                format!("-algorithm RSA -pkeyopt rsa_keygen_bits:{size}"),
+                key_type,
+                size.to_string(),
+            ))
+        }
+        "ec" => {
+            let curve = curve.unwrap_or_else(|| "prime256v1".to_string());
+            if curve.is_empty() || !curve.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(RuntimeError(format!("Invalid curve: '{curve}'")));
+            }
+            Ok((
+                format!("-algorithm EC -pkeyopt ec_paramgen_curve:{curve}"),
+                key_type,
+                curve,
+            ))
+        }
+        "ed25519" => Ok(("-algorithm ED25519".to_string(), key_type, String::new())),
+        _ => Err(RuntimeError(format!(
+            "Invalid key_type: {key_type}. Valid types are: rsa, ec and ed25519."
+        ))),
+    }
+}
+
+/// Command printing what identifies the CSR or certificate at `path`: its
+/// subject, public key and alternative names, and for a certificate the
+/// number of days it is valid.
+fn signature_command(kind: &str, path: &str) -> String {
+    let mut command = format!(
+        "openssl {kind} -in {path} -noout -subject -pubkey && {{ openssl {kind} -in {path} -noout -text | grep -A1 'Subject Alternative Name' || true; }}"
+    );
+    if kind == "x509" {
+        let _ = write!(
+            command,
+            " && echo days=$(( ($(date -d \"$(openssl x509 -in {path} -noout -enddate | cut -d= -f2)\" +%s) - $(date -d \"$(openssl x509 -in {path} -noout -startdate | cut -d= -f2)\" +%s)) / 86400 ))"
+        );
+    }
+    command
+}
+
+/// Generate the private key `private_key` on the host and, from it, the
+/// CSR `csr` and the self-signed certificate `certificate` when those paths
+/// are given, e.g. for internal TLS. `key_type` is `rsa` (default, with
+/// `key_size`, default 2048), `ec` (with the OpenSSL `curve` name, default
+/// `prime256v1`) or `ed25519`. The CSR and certificate carry `subject` (a
+/// string such as `"/CN=db.internal/O=Example"` or a table of `C`, `ST`,
+/// `L`, `O`, `OU`, `CN` and `emailAddress`; the first alternative name by
+/// default) and `subject_alt_names` (names, IP addresses or typed entries
+/// such as `"URI:spiffe://example"`); the certificate is valid for
+/// `valid_days` (default 365). Files are only written when missing, when
+/// they no longer match these parameters, or when the certificate has
+/// expired; a key with another type or size is replaced, which also
+/// replaces the CSR and certificate. Keys are created with mode 600.
+pub fn openssl(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let private_key = params
+        .get::<Option<String>>("private_key")?
+        .ok_or_else(|| RuntimeError("'private_key' parameter is required".to_string()))?;
+    let (genpkey_options, key_type, key_param) = key_options(&params)?;
+    let csr = params.get::<Option<String>>("csr")?;
+    let certificate = params.get::<Option<String>>("certificate")?;
+    let valid_days = params
+        .get::<Option<u32>>("valid_days")?
+        .unwrap_or(DEFAULT_VALID_DAYS);
+    if valid_days == 0 {
+        return Err(RuntimeError(
+            "'valid_days' parameter must be at least 1".to_string(),
+        ));
+    }
+
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+    let tmp = |path: &str| escape_shell_value(&format!("{path}.komandan-{random_file_name}"));
+    let quoted_key = escape_shell_value(&private_key);
+    let key_tmp = tmp(&private_key);
+    let key_command = format!(
+        "(umask 077 && openssl genpkey {genpkey_options} -out {key_tmp}) && mv -f {key_tmp} {quoted_key}"
+    );
+    let key_text_command =
+        format!("openssl pkey -in {quoted_key} -noout -text | sed -n '1p;/ASN1 OID/p'");
+    let mut tmp_paths = vec![key_tmp];
+
+    let outputs = lua.create_table()?;
+    if csr.is_some() || certificate.is_some() {
+        let alt_names = alt_names(&params)?;
+        let mut request_options = format!(
+            "-new -key {quoted_key} -subj {}",
+            escape_shell_value(&subject(params.get::<Value>("subject")?, &alt_names)?)
+        );
+        if !alt_names.is_empty() {
+            let _ = write!(
+                request_options,
+                " -addext {}",
+                escape_shell_value(&format!("subjectAltName={}", alt_names.join(",")))
+            );
+        }
+        for (kind, path) in [("req", &csr), ("x509", &certificate)] {
+            let Some(path) = path else {
+                continue;
+            };
+            let quoted_path = escape_shell_value(path);
+            let path_tmp = tmp(path);
+            let x509_options = if kind == "x509" {
+                format!(" -x509 -days {valid_days}")
+            } else {
+                String::new()
+            };
+            let output = lua.create_table()?;
+            output.set("path", path.as_str())?;
+            output.set("exists_command", format!("[ -f {quoted_path} ]"))?;
+            output.set(
+                "generate_command",
+                format!("openssl req{x509_options} {request_options} -out {path_tmp}"),
+            )?;
+            output.set("signature_command", signature_command(kind, &quoted_path))?;
+            output.set(
+                "candidate_signature_command",
+                signature_command(kind, &path_tmp),
+            )?;
+            if kind == "x509" {
+                output.set(
+                    "expired_command",
+                    format!("openssl x509 -noout -checkend 0 -in {quoted_path}"),
+                )?;
+            }
+            output.set(
+                "install_command",
+                format!("chmod 644 {path_tmp} && mv -f {path_tmp} {quoted_path}"),
+            )?;
+            output.set("remove_command", format!("rm -f {path_tmp}"))?;
+            outputs.push(output)?;
+            tmp_paths.push(path_tmp);
+        }
+    }
+    let remove_tmp_command = format!("rm -f {}", tmp_paths.join(" "));
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "openssl" })
+
+            module.params = $params
+            module.key_type = $key_type
+            module.key_param = $key_param
+            module.key_exists_command = "[ -f " .. $quoted_key .. " ]"
+            module.key_text_command = $key_text_command
+            module.key_command = $key_command
+            module.outputs = $outputs
+            module.remove_tmp_command = $remove_tmp_command
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("openssl: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Whether the openssl pkey -text output describes a key of the
+            -- requested type and size or curve.
+            module.key_text_matches = function(self, text)
+                local ed25519 = string.find(text, "ED25519", 1, true) ~= nil
+                local curve = string.match(text, "ASN1 OID: (%S+)")
+                if self.key_type == "ed25519" then
+                    return ed25519
+                elseif self.key_type == "ec" then
+                    return curve == self.key_param
+                end
+                return not ed25519 and curve == nil and string.match(text, "%((%d+) bit") == self.key_param
+            end
+
+            module.key_matches = function(self)
+                if self.ssh:cmdq(self.key_exists_command).exit_code ~= 0 then
+                    return false
+                end
+                return self:key_text_matches(self:sh(self.key_text_command))
+            end
+
+            -- Build the candidate file of an output and tell whether it has
+            -- to replace the current one.
+            module.output_differs = function(self, output)
+                self:sh(output.generate_command)
+                if self.ssh:cmdq(output.exists_command).exit_code ~= 0 then
+                    return true
+                end
+                if output.expired_command ~= nil and self.ssh:cmdq(output.expired_command).exit_code ~= 0 then
+                    return true
+                end
+                local current = self.ssh:cmdq(output.signature_command)
+                return current.exit_code ~= 0 or current.stdout ~= self:sh(output.candidate_signature_command)
+            end
+
+            module.dry_run = function(self)
+                self.ssh:requires("openssl")
+                if not self:key_matches() then
+                    self.ssh:set_changed(true)
+                    return
+                end
+                local changed = false
+                for _, output in ipairs(self.outputs) do
+                    changed = self:output_differs(output) or changed
+                    self.ssh:cmdq(output.remove_command)
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.run = function(self)
+                self.ssh:requires("openssl")
+                local changed = not self:key_matches()
+                if changed then
+                    self:sh(self.key_command)
+                end
+                for _, output in ipairs(self.outputs) do
+                    if self:output_differs(output) or changed then
+                        self:sh(output.install_command)
+                        changed = true
+                    else
+                        self.ssh:cmdq(output.remove_command)
+                    end
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.cleanup = function(self)
+                self.ssh:cmdq(self.remove_tmp_command)
+            end
+
+            return module
+        })
+        .set_name("openssl")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_openssl_subject_and_alt_names() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set(
+            "subject_alt_names",
+            lua.create_sequence_from(["db.internal", "10.0.0.5", "URI:spiffe://example/db"])?,
+        )?;
+        let names = alt_names(&params)?;
+        assert_eq!(
+            names,
+            ["DNS:db.internal", "IP:10.0.0.5", "URI:spiffe://example/db"]
+        );
+        assert_eq!(subject(Value::Nil, &names)?, "/CN=db.internal");
+
+        let fields = lua.create_table()?;
+        fields.set("CN", "db.internal")?;
+        fields.set("O", "Example/Ops")?;
+        assert_eq!(
+            subject(Value::Table(fields), &names)?,
+            "/O=Example\\/Ops/CN=db.internal"
+        );
+        let fields = lua.create_table()?;
+        fields.set("Common", "db.internal")?;
+        assert!(subject(Value::Table(fields), &names).is_err());
+        assert!(subject(Value::Nil, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_openssl_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(openssl(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("private_key", "/etc/ssl/private/db.key")?;
+        params.set("key_type", "ec")?;
+        params.set("key_size", 4096)?;
+        assert!(openssl(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("private_key", "/etc/ssl/private/db.key")?;
+        params.set("certificate", "/etc/ssl/certs/db.crt")?;
+        assert!(
+            openssl(&lua, params)
+                .is_err_and(|e| e.to_string().contains("'subject' or 'subject_alt_names'"))
+        );
+
+        let (key_text, ec_text, rsa_text) = lua
+            .load(chunk! {
+                local rsa = komandan.modules.openssl({ private_key = "/tmp/rsa.key", key_size = 4096 })
+                local ec = komandan.modules.openssl({ private_key = "/tmp/ec.key", key_type = "ec" })
+                return rsa:key_text_matches("Private-Key: (4096 bit, 2 primes)\n"),
+                    ec:key_text_matches("Private-Key: (256 bit)\nASN1 OID: prime256v1\n"),
+                    rsa:key_text_matches("Private-Key: (256 bit)\nASN1 OID: prime256v1\n")
+            })
+            .eval::<(bool, bool, bool)>()?;
+        assert!(key_text);
+        assert!(ec_text);
+        assert!(!rsa_text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_openssl_generates_files() -> mlua::Result<()> {
+        if std::process::Command::new("openssl")
+            .arg("version")
+            .output()
+            .is_err()
+        {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = |key_type: &str, alt_name: &str| -> mlua::Result<Table> {
+            let params = lua.create_table()?;
+            params.set("private_key", path("server.key"))?;
+            params.set("key_type", key_type)?;
+            params.set("csr", path("server.csr"))?;
+            params.set("certificate", path("server.crt"))?;
+            params.set("subject_alt_names", alt_name)?;
+            let task = lua.create_table()?;
+            task.set(1, openssl(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (Value::Table(task), Value::Table(host)))
+        };
+
+        assert!(run("ec", "db.internal")?.get::<bool>("changed")?);
+        let key = std::fs::read(path("server.key")).map_err(mlua::Error::external)?;
+        assert!(!run("ec", "db.internal")?.get::<bool>("changed")?);
+
+        assert!(run("ec", "db.example.com")?.get::<bool>("changed")?);
+        assert_eq!(
+            std::fs::read(path("server.key")).map_err(mlua::Error::external)?,
+            key
+        );
+        assert!(run("ed25519", "db.example.com")?.get::<bool>("changed")?);
+        assert_ne!(
+            std::fs::read(path("server.key")).map_err(mlua::Error::external)?,
+            key
+        );
+        assert_eq!(
+            std::fs::read_dir(dir.path())
+                .map_err(mlua::Error::external)?
+                .count(),
+            3
+        );
+        Ok(())
+    }
+}
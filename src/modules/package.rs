@@ -0,0 +1,321 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Commands `package` runs for one package manager, detected in this order.
+struct PackageManager {
+    name: &'static str,
+    /// Built-in module that handles `present` and `absent` for this manager.
+    module: Option<&'static str>,
+    refresh: &'static str,
+    /// Prints the name of every installed package, one per line.
+    installed: &'static str,
+    /// Prints the name of every package with a pending update, one per line.
+    upgradable: &'static str,
+    install: &'static str,
+    upgrade: &'static str,
+    remove: &'static str,
+}
+
+const PACKAGE_MANAGERS: &[PackageManager] = &[
+    PackageManager {
+        name: "apt-get",
+        module: Some("apt"),
+        refresh: "apt-get update -qq",
+        installed: "dpkg-query -W -f='${binary:Package} ${db:Status-Status}\\n' | awk '$2 == \"installed\" {sub(/:.*/, \"\", $1); print $1}'",
+        upgradable: "apt-get -s upgrade | awk '/^Inst /{print $2}'",
+        install: "env DEBIAN_FRONTEND=noninteractive apt-get install -y",
+        upgrade: "env DEBIAN_FRONTEND=noninteractive apt-get install -y --only-upgrade",
+        remove: "env DEBIAN_FRONTEND=noninteractive apt-get remove -y",
+    },
+    PackageManager {
+        name: "dnf",
+        module: Some("dnf"),
+        refresh: "dnf -q makecache",
+        installed: "rpm -qa --qf '%{NAME}\\n'",
+        upgradable: "dnf -q check-update | awk 'NF == 3 && $1 ~ /\\./ {sub(/\\.[^.]*$/, \"\", $1); print $1}'",
+        install: "dnf install -y",
+        upgrade: "dnf upgrade -y",
+        remove: "dnf remove -y",
+    },
+    PackageManager {
+        name: "zypper",
+        module: None,
+        refresh: "zypper -n -q refresh",
+        installed: "rpm -qa --qf '%{NAME}\\n'",
+        upgradable: "zypper -n -q list-updates | awk -F'|' '/^v /{gsub(/ /, \"\", $3); print $3}'",
+        install: "zypper -n install",
+        upgrade: "zypper -n update",
+        remove: "zypper -n remove",
+    },
+    PackageManager {
+        name: "pacman",
+        module: None,
+        refresh: "pacman -Sy --noconfirm",
+        installed: "pacman -Qq",
+        upgradable: "pacman -Qu | awk '{print $1}'",
+        install: "pacman -S --noconfirm --needed",
+        upgrade: "pacman -S --noconfirm",
+        remove: "pacman -R --noconfirm",
+    },
+    PackageManager {
+        name: "apk",
+        module: None,
+        refresh: "apk update -q",
+        installed: "apk info -q",
+        upgradable: "apk -u list | awk '{print $1}' | sed -E 's/-[0-9][^-]*-r[0-9]+$//'",
+        install: "apk add",
+        upgrade: "apk add --upgrade",
+        remove: "apk del",
+    },
+];
+
+/// Lua list of the package managers, with the listing commands wrapped in
+/// a single `sh -c` so pipelines keep working under sudo.
+fn package_managers_table(lua: &Lua) -> mlua::Result<Table> {
+    let wrap = |script: &str| format!("sh -c {}", escape_shell_value(script));
+    let managers = lua.create_table()?;
+    for manager in PACKAGE_MANAGERS {
+        let entry = lua.create_table()?;
+        entry.set("name", manager.name)?;
+        entry.set("module", manager.module)?;
+        entry.set("refresh", wrap(manager.refresh))?;
+        entry.set("installed", wrap(manager.installed))?;
+        entry.set("upgradable", wrap(manager.upgradable))?;
+        entry.set("install", manager.install)?;
+        entry.set("upgrade", manager.upgrade)?;
+        entry.set("remove", manager.remove)?;
+        managers.push(entry)?;
+    }
+    Ok(managers)
+}
+
+/// Manage packages with whichever of apt-get, dnf, zypper, pacman or apk
+/// the host has, so one task works across distributions. `name` is a
+/// package or a list of packages and `state` is `present` (default),
+/// `absent` or `latest`; `update_cache = true` refreshes the package index
+/// first. On apt and dnf hosts, `present` and `absent` are handed to the
+/// `apt` and `dnf` modules. Sets `package_manager` in the task result, and
+/// `installed`, `upgraded` and `removed` (lists of packages) when the module
+/// handles the packages itself.
+pub fn package(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let packages = super::name_list(&params, "name", "._+-:@")?;
+    if packages.is_empty() {
+        return Err(RuntimeError("'name' parameter is required".to_string()));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let update_cache = params.get::<Option<bool>>("update_cache")?.unwrap_or(false);
+    params.set("update_cache", update_cache)?;
+    let managers = package_managers_table(lua)?;
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "package" })
+
+            module.params = $params
+            module.packages = $packages
+            module.managers = $managers
+
+            module.detect_manager = function(self)
+                for _, manager in ipairs(self.managers) do
+                    if self.ssh:cmdq("command -v " .. manager.name).exit_code == 0 then
+                        return manager
+                    end
+                end
+                error("package: no supported package manager found (apt-get, dnf, zypper, pacman, apk)")
+            end
+
+            -- The module of the package manager handling this task, if any.
+            module.delegate = function(self, manager)
+                if manager.module == nil or self.params.state == "latest" then
+                    return nil
+                end
+                local delegate = komandan.modules[manager.module]({
+                    package = self.packages,
+                    action = self.params.state == "present" and "install" or "remove",
+                    update_cache = self.params.update_cache,
+                })
+                delegate.ssh = self.ssh
+                delegate.host = self.host
+                self.extra_result = self.extra_result or {}
+                delegate.extra_result = self.extra_result
+                return delegate
+            end
+
+            module.lines = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("package: " .. command .. " failed: " .. result.stderr)
+                end
+                local lines = {}
+                for line in string.gmatch(result.stdout, "[^\n]+") do
+                    lines[line] = true
+                end
+                return lines
+            end
+
+            -- Packages to install, upgrade and remove.
+            module.plan = function(self, manager)
+                local install, upgrade, remove = {}, {}, {}
+                local installed = self:lines(manager.installed)
+                local upgradable = {}
+                if self.params.state == "latest" then
+                    upgradable = self:lines(manager.upgradable)
+                end
+                for _, name in ipairs(self.packages) do
+                    if self.params.state == "absent" then
+                        if installed[name] then
+                            table.insert(remove, name)
+                        end
+                    elseif not installed[name] then
+                        table.insert(install, name)
+                    elseif upgradable[name] then
+                        table.insert(upgrade, name)
+                    end
+                end
+                self:set_result("installed", install)
+                self:set_result("upgraded", upgrade)
+                self:set_result("removed", remove)
+                return install, upgrade, remove
+            end
+
+            module.refresh = function(self, manager)
+                if self.params.update_cache then
+                    local result = self.ssh:cmdq(manager.refresh)
+                    if result.exit_code ~= 0 then
+                        error("package: failed to refresh the package index: " .. result.stderr)
+                    end
+                end
+            end
+
+            module.dry_run = function(self)
+                local manager = self:detect_manager()
+                self:set_result("package_manager", manager.name)
+                local delegate = self:delegate(manager)
+                if delegate ~= nil then
+                    delegate:dry_run()
+                    return
+                end
+                self:refresh(manager)
+                local install, upgrade, remove = self:plan(manager)
+                self.ssh:set_changed(#install > 0 or #upgrade > 0 or #remove > 0)
+            end
+
+            module.run = function(self)
+                local manager = self:detect_manager()
+                self:set_result("package_manager", manager.name)
+                local delegate = self:delegate(manager)
+                if delegate ~= nil then
+                    delegate:run()
+                    return
+                end
+                self:refresh(manager)
+                local install, upgrade, remove = self:plan(manager)
+                for _, step in ipairs({ { manager.install, install }, { manager.upgrade, upgrade }, { manager.remove, remove } }) do
+                    local command, names = step[1], step[2]
+                    if #names > 0 then
+                        local result = self.ssh:cmd(command .. " " .. table.concat(names, " "))
+                        if result.exit_code ~= 0 then
+                            error("package: " .. manager.name .. " failed: " .. result.stderr)
+                        end
+                    end
+                end
+                self.ssh:set_changed(#install > 0 or #upgrade > 0 or #remove > 0)
+            end
+
+            return module
+        })
+        .set_name("package")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_package_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(package(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "nginx")?;
+        params.set("state", "installed")?;
+        assert!(package(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "nginx;reboot")?;
+        assert!(package(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_delegates_to_apt() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (manager, action, packages, latest) = lua
+            .load(chunk! {
+                local module = komandan.modules.package({ name = { "nginx", "curl" }, state = "absent" })
+                module.ssh = {
+                    cmdq = function(_, command)
+                        return { exit_code = command == "command -v apt-get" and 0 or 1, stdout = "", stderr = "" }
+                    end,
+                }
+                local apt = module.managers[1]
+                local delegate = module:delegate(apt)
+                module.params.state = "latest"
+                return apt.name, delegate.params.action, delegate.params.package, module:delegate(apt) == nil
+            })
+            .eval::<(String, String, Vec<String>, bool)>()?;
+        assert_eq!(manager, "apt-get");
+        assert_eq!(action, "remove");
+        assert_eq!(packages, ["nginx", "curl"]);
+        assert!(latest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local function plan(params)
+                    local module = komandan.modules.package(params)
+                    module.set_result = function() end
+                    module.ssh = {
+                        cmdq = function(_, command)
+                            local stdout = command == "installed" and "nginx\ncurl\n" or "curl\n"
+                            return { exit_code = 0, stdout = stdout, stderr = "" }
+                        end,
+                    }
+                    local install, upgrade, remove = module:plan({ installed = "installed", upgradable = "upgradable" })
+                    return { table.concat(install, ","), table.concat(upgrade, ","), table.concat(remove, ",") }
+                end
+                return {
+                    plan({ name = { "nginx", "htop" } }),
+                    plan({ name = { "nginx", "curl" }, state = "latest" }),
+                    plan({ name = { "curl", "htop" }, state = "absent" }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [["htop", "", ""], ["", "curl", ""], ["", "", "curl"]]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+use crate::util::duration_param;
+
+/// Commands `patch` runs for one package manager, tried in this order.
+struct PackageManager {
+    name: &'static str,
+    /// Refreshes the package index before listing updates, if needed.
+    refresh: Option<&'static str>,
+    /// Prints the name of every package an upgrade would update, one per line.
+    list: &'static str,
+    upgrade: &'static str,
+}
+
+const PACKAGE_MANAGERS: &[PackageManager] = &[
+    PackageManager {
+        name: "apt-get",
+        refresh: Some("apt-get update -qq"),
+        list: "apt-get -s dist-upgrade | awk '/^Inst /{print $2}'",
+        upgrade: "DEBIAN_FRONTEND=noninteractive apt-get -y -o Dpkg::Options::=--force-confdef -o Dpkg::Options::=--force-confold dist-upgrade",
+    },
+    PackageManager {
+        name: "dnf",
+        refresh: None,
+        list: "dnf -q check-update | awk 'NF == 3 && $1 ~ /\\./ {print $1}'",
+        upgrade: "dnf -y upgrade",
+    },
+    PackageManager {
+        name: "yum",
+        refresh: None,
+        list: "yum -q check-update | awk 'NF == 3 && $1 ~ /\\./ {print $1}'",
+        upgrade: "yum -y update",
+    },
+    PackageManager {
+        name: "zypper",
+        refresh: Some("zypper -n -q refresh"),
+        list: "zypper -n -q list-updates | awk -F'|' '/^v /{gsub(/ /, \"\", $3); print $3}'",
+        upgrade: "zypper -n update",
+    },
+    PackageManager {
+        name: "apk",
+        refresh: Some("apk update -q"),
+        list: "apk -u list | awk '{print $1}'",
+        upgrade: "apk upgrade",
+    },
+    PackageManager {
+        name: "pacman",
+        refresh: Some("pacman -Sy --noconfirm"),
+        list: "pacman -Qu | awk '{print $1}'",
+        upgrade: "pacman -Su --noconfirm",
+    },
+];
+
+/// Lua list of `{ name, refresh, list, upgrade }` with every command wrapped
+/// in a single `sh -c`, so pipelines keep working under sudo.
+fn package_managers_table(lua: &Lua) -> mlua::Result<Table> {
+    let wrap = |script: &str| format!("sh -c {}", escape_shell_value(script));
+    let managers = lua.create_table()?;
+    for manager in PACKAGE_MANAGERS {
+        let entry = lua.create_table()?;
+        entry.set("name", manager.name)?;
+        entry.set("refresh", manager.refresh.map(wrap))?;
+        entry.set("list", wrap(manager.list))?;
+        entry.set("upgrade", wrap(manager.upgrade))?;
+        managers.push(entry)?;
+    }
+    Ok(managers)
+}
+
+/// Parse a maintenance window `"HH:MM-HH:MM"` into its start and end as
+/// minutes after midnight. The window may wrap past midnight, e.g.
+/// `"22:00-04:00"`.
+///
+/// # Errors
+///
+/// Returns a message if `text` is not a valid window or starts and ends at
+/// the same time.
+pub fn parse_window(text: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid maintenance window '{text}': expected HH:MM-HH:MM");
+    let minutes = |time: &str| -> Result<u32, String> {
+        let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+        let hours = hours.parse::<u32>().map_err(|_| invalid())?;
+        let minutes = minutes.parse::<u32>().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(hours * 60 + minutes)
+    };
+    let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+    let (start, end) = (minutes(start)?, minutes(end)?);
+    if start == end {
+        return Err(format!("maintenance window '{text}' is empty"));
+    }
+    Ok((start, end))
+}
+
+/// Update every package on the host through whichever of apt-get, dnf, yum,
+/// zypper, apk or pacman it has, optionally only inside a maintenance window
+/// and within a time budget, then check whether a reboot is required and
+/// reboot when `reboot = true`. Sets `package_manager`, `updated_packages`,
+/// `reboot_required`, `reboot_reasons` and `rebooted` in the task result.
+pub fn patch(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let window = params
+        .get::<Option<String>>("window")?
+        .map(|window| parse_window(&window))
+        .transpose()
+        .map_err(RuntimeError)?;
+    let (window_start, window_end) = window.unzip();
+    let time_budget = duration_param(params.get::<Value>("time_budget")?, "time_budget")?
+        .map(|budget| budget.as_secs().max(1));
+    let managers = package_managers_table(lua)?;
+    let module = lua
+        .load(chunk! {
+            params.reboot = params.reboot or false
+
+            local module = $base_module:new({ name = "patch" })
+
+            module.params = $params
+            module.managers = $managers
+            module.window_start = $window_start
+            module.window_end = $window_end
+            module.time_budget = $time_budget
+
+            module.detect_manager = function(self)
+                for _, manager in ipairs(self.managers) do
+                    if self.ssh:cmdq("command -v " .. manager.name).exit_code == 0 then
+                        return manager
+                    end
+                end
+                error("patch: no supported package manager found (apt-get, dnf, yum, zypper, apk, pacman)")
+            end
+
+            -- Seconds left in the maintenance window by the host clock, 0
+            -- outside of it, or nil without a window.
+            module.window_remaining = function(self)
+                if self.window_start == nil then
+                    return nil
+                end
+                local now = self.ssh:cmdq("date +%H:%M:%S").stdout
+                local hours, minutes, seconds = now:match("^(%d+):(%d+):(%d+)")
+                if hours == nil then
+                    error("patch: cannot read the time on the host: " .. now)
+                end
+                local now_seconds = tonumber(hours) * 3600 + tonumber(minutes) * 60 + tonumber(seconds)
+                local length = ((self.window_end - self.window_start) % 1440) * 60
+                local elapsed = (now_seconds - self.window_start * 60) % 86400
+                if elapsed >= length then
+                    return 0
+                end
+                return length - elapsed
+            end
+
+            -- Whole seconds the upgrade may take, 0 outside the window, or nil
+            -- when unbounded.
+            module.budget = function(self)
+                local remaining = self:window_remaining()
+                if remaining == nil then
+                    return self.time_budget
+                end
+                if self.time_budget ~= nil and self.time_budget < remaining then
+                    return self.time_budget
+                end
+                return remaining
+            end
+
+            module.pending_packages = function(self, manager)
+                local result = self.ssh:cmdq(manager.list)
+                if result.exit_code ~= 0 then
+                    error("patch: failed to list pending updates: " .. result.stderr)
+                end
+                local packages = {}
+                for line in result.stdout:gmatch("[^\n]+") do
+                    table.insert(packages, line)
+                end
+                return packages
+            end
+
+            module.skip = function(self)
+                self:set_result("updated_packages", {})
+                self:set_result("rebooted", false)
+                self:set_result("skipped", "outside the maintenance window " .. self.params.window)
+                self.ssh:set_changed(false)
+            end
+
+            module.check_reboot = function(self)
+                local check = komandan.modules.reboot_required({})
+                check.ssh = self.ssh
+                check.extra_result = {}
+                check:run()
+                self:set_result("reboot_required", check.extra_result.reboot_required)
+                self:set_result("reboot_reasons", check.extra_result.reboot_reasons)
+                return check.extra_result.reboot_required
+            end
+
+            module.dry_run = function(self)
+                local manager = self:detect_manager()
+                self:set_result("package_manager", manager.name)
+                local budget = self:budget()
+                if budget ~= nil and budget <= 0 then
+                    self:skip()
+                    return
+                end
+                local packages = self:pending_packages(manager)
+                self:set_result("updated_packages", packages)
+                self:set_result("rebooted", false)
+                self:check_reboot()
+                self.ssh:set_changed(#packages > 0)
+            end
+
+            module.run = function(self)
+                local manager = self:detect_manager()
+                self:set_result("package_manager", manager.name)
+                local budget = self:budget()
+                if budget ~= nil and budget <= 0 then
+                    self:skip()
+                    return
+                end
+
+                if manager.refresh ~= nil and self.ssh:cmd(manager.refresh).exit_code ~= 0 then
+                    return
+                end
+                local packages = self:pending_packages(manager)
+                self:set_result("updated_packages", packages)
+                self:set_result("rebooted", false)
+
+                if #packages > 0 then
+                    local command = manager.upgrade
+                    if budget ~= nil then
+                        command = "timeout " .. math.floor(budget) .. " " .. command
+                    end
+                    local result = self.ssh:cmd(command)
+                    if result.exit_code == 124 and budget ~= nil then
+                        error("patch: upgrade did not finish within " .. math.floor(budget) .. "s")
+                    end
+                    if result.exit_code ~= 0 then
+                        return
+                    end
+                    self.ssh:set_changed(true)
+                end
+
+                if not self:check_reboot() or not self.params.reboot then
+                    return
+                end
+                local remaining = self:window_remaining()
+                if remaining ~= nil and remaining <= 0 then
+                    self:set_result("skipped", "reboot: outside the maintenance window " .. self.params.window)
+                    return
+                end
+                self.ssh:cmd("sh -c '(sleep 2; systemctl reboot || shutdown -r now || reboot) >/dev/null 2>&1 &'")
+                self:set_result("rebooted", true)
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("patch")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("02:00-04:30"), Ok((120, 270)));
+        assert_eq!(parse_window("22:00 - 04:00"), Ok((1320, 240)));
+        assert!(parse_window("02:00").is_err());
+        assert!(parse_window("24:00-01:00").is_err());
+        assert!(parse_window("02:60-03:00").is_err());
+        assert!(parse_window("03:00-03:00").is_err());
+    }
+
+    #[test]
+    fn test_patch_invalid_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("window", "tonight")?;
+        assert!(patch(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("time_budget", "soon")?;
+        assert!(patch(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_budget_within_window() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let budget_at = |now: &'static str, time_budget: Option<&str>| -> mlua::Result<f64> {
+            let params = lua.create_table()?;
+            params.set("window", "23:00-01:00")?;
+            params.set("time_budget", time_budget)?;
+            let module = patch(&lua, params)?;
+
+            let ssh = lua.create_table()?;
+            ssh.set(
+                "cmdq",
+                lua.create_function(move |lua, (_self, _cmd): (Table, String)| {
+                    let result = lua.create_table()?;
+                    result.set("exit_code", 0)?;
+                    result.set("stdout", now)?;
+                    Ok(result)
+                })?,
+            )?;
+            module.set("ssh", ssh)?;
+            module.get::<mlua::Function>("budget")?.call(module)
+        };
+
+        assert!((budget_at("23:30:00", None)? - 5400.0).abs() < f64::EPSILON);
+        assert!((budget_at("00:59:30", None)? - 30.0).abs() < f64::EPSILON);
+        assert!((budget_at("23:30:00", Some("10m"))? - 600.0).abs() < f64::EPSILON);
+        assert!(budget_at("12:00:00", Some("10m"))?.abs() < f64::EPSILON);
+        Ok(())
+    }
+}
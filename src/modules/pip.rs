@@ -0,0 +1,314 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Manage Python packages with pip. `name` is a package or a list of
+/// packages (extras such as `requests[socks]` allowed) and `version` pins a
+/// single `name`; `requirements` installs a requirements file on the host.
+/// `state` is `present` (default), `absent` or `latest`. With `virtualenv`,
+/// the packages go into that virtual environment, created first with
+/// `virtualenv_command` (default `python3 -m venv`) when missing; otherwise
+/// `executable` (default `python3 -m pip`) is used. `extra_index_url` adds
+/// one or more package indexes. Installed versions come from
+/// `pip list --format=freeze`, so only missing, mismatching or (with
+/// `latest`) outdated packages are touched. Sets `installed` and `removed`
+/// (lists of requirement specifiers) in the task result.
+pub fn pip(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let packages = super::name_list(&params, "name", "._-[],")?;
+    let requirements = params.get::<Option<String>>("requirements")?;
+    if packages.is_empty() && requirements.is_none() {
+        return Err(RuntimeError(
+            "'name' or 'requirements' parameter is required".to_string(),
+        ));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if !["present", "absent", "latest"].contains(&state.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid state: {state}. Valid states are: present, absent and latest."
+        )));
+    }
+    params.set("state", state.as_str())?;
+    let version = params.get::<Option<String>>("version")?;
+    if let Some(version) = &version {
+        if packages.len() != 1 || state != "present" {
+            return Err(RuntimeError(
+                "'version' parameter needs a single 'name' and state 'present'".to_string(),
+            ));
+        }
+        if version.is_empty()
+            || !version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".*+!-".contains(c))
+        {
+            return Err(RuntimeError(format!("Invalid version: '{version}'")));
+        }
+    }
+    if requirements.is_some() && state == "absent" {
+        return Err(RuntimeError(
+            "'requirements' parameter cannot be used with state 'absent'".to_string(),
+        ));
+    }
+
+    let virtualenv = params.get::<Option<String>>("virtualenv")?;
+    let pip = match &virtualenv {
+        Some(virtualenv) => format!("{}/bin/pip", escape_shell_value(virtualenv)),
+        None => params
+            .get::<Option<String>>("executable")?
+            .unwrap_or_else(|| "python3 -m pip".to_string()),
+    };
+    let pip = format!("PIP_DISABLE_PIP_VERSION_CHECK=1 {pip}");
+    let mut index_options = String::new();
+    for url in super::name_list(&params, "extra_index_url", ":/._-~%?&=+@")? {
+        let _ = write!(
+            index_options,
+            " --extra-index-url {}",
+            escape_shell_value(&url)
+        );
+    }
+    let virtualenv_command = params
+        .get::<Option<String>>("virtualenv_command")?
+        .unwrap_or_else(|| "python3 -m venv".to_string());
+    let virtualenv_check = virtualenv
+        .as_deref()
+        .map(|virtualenv| format!("[ -x {}/bin/pip ]", escape_shell_value(virtualenv)));
+    let virtualenv_create = virtualenv
+        .as_deref()
+        .map(|virtualenv| format!("{virtualenv_command} {}", escape_shell_value(virtualenv)));
+    let wanted = match (&version, packages.as_slice()) {
+        (Some(version), [name]) => vec![format!("{name}=={version}")],
+        _ => packages,
+    };
+    let requirements_option = requirements
+        .as_deref()
+        .map(|requirements| format!(" -r {}", escape_shell_value(requirements)));
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "pip" })
+
+            module.params = $params
+            module.wanted = $wanted
+            module.list_command = $pip .. " list --format=freeze"
+            module.outdated_command = $pip .. " list --outdated --format=freeze" .. $index_options
+            module.install_command = $pip .. " install" .. $index_options
+            module.uninstall_command = $pip .. " uninstall -y"
+            module.requirements_option = $requirements_option
+            module.virtualenv_check = $virtualenv_check
+            module.virtualenv_create = $virtualenv_create
+
+            module.pip = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("pip: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- Package names as pip compares them: lowercase, runs of
+            -- "-", "_" and "." as one "-", extras dropped.
+            module.normalize = function(name)
+                name = string.gsub(string.lower(name), "%[.*%]", "")
+                return (string.gsub(name, "[-_.]+", "-"))
+            end
+
+            -- Installed (or outdated) package versions by normalized name.
+            module.versions = function(self, command)
+                local versions = {}
+                for name, version in string.gmatch(self:pip(command), "([^\n=]+)==([^\n]+)") do
+                    versions[self.normalize(name)] = version
+                end
+                return versions
+            end
+
+            -- Specifiers to install and names to remove.
+            module.plan = function(self)
+                local install, remove = {}, {}
+                local installed = self:versions(self.list_command)
+                local outdated = {}
+                if self.params.state == "latest" then
+                    outdated = self:versions(self.outdated_command)
+                end
+                for _, spec in ipairs(self.wanted) do
+                    local name, version = string.match(spec, "^([^=]+)==(.+)$")
+                    name = self.normalize(name or spec)
+                    local current = installed[name]
+                    if self.params.state == "absent" then
+                        if current ~= nil then
+                            table.insert(remove, spec)
+                        end
+                    elseif current == nil or (version ~= nil and current ~= version) or outdated[name] ~= nil then
+                        table.insert(install, spec)
+                    end
+                end
+                return install, remove
+            end
+
+            -- Whether installing the requirements file would change
+            -- anything; needs pip 22.2 or newer for --dry-run.
+            module.requirements_pending = function(self)
+                if self.requirements_option == nil then
+                    return false
+                end
+                local upgrade = self.params.state == "latest" and " --upgrade" or ""
+                local output = self:pip(self.install_command .. " --dry-run" .. upgrade .. self.requirements_option)
+                return string.find(output, "Would install", 1, true) ~= nil
+            end
+
+            module.virtualenv_missing = function(self)
+                return self.virtualenv_check ~= nil and self.ssh:cmdq(self.virtualenv_check).exit_code ~= 0
+            end
+
+            module.dry_run = function(self)
+                if self:virtualenv_missing() then
+                    self.ssh:set_changed(self.params.state ~= "absent")
+                    return
+                end
+                local install, remove = self:plan()
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(#install > 0 or #remove > 0 or self:requirements_pending())
+            end
+
+            module.run = function(self)
+                local changed = false
+                if self:virtualenv_missing() then
+                    if self.params.state == "absent" then
+                        self.ssh:set_changed(false)
+                        return
+                    end
+                    self:pip(self.virtualenv_create)
+                    changed = true
+                end
+                local install, remove = self:plan()
+                local upgrade = self.params.state == "latest" and " --upgrade" or ""
+                if #install > 0 then
+                    local specs = {}
+                    for _, spec in ipairs(install) do
+                        table.insert(specs, "'" .. spec .. "'")
+                    end
+                    self:pip(self.install_command .. upgrade .. " " .. table.concat(specs, " "))
+                    changed = true
+                end
+                if #remove > 0 then
+                    local names = {}
+                    for _, name in ipairs(remove) do
+                        table.insert(names, "'" .. name .. "'")
+                    end
+                    self:pip(self.uninstall_command .. " " .. table.concat(names, " "))
+                    changed = true
+                end
+                if self:requirements_pending() then
+                    self:pip(self.install_command .. upgrade .. self.requirements_option)
+                    changed = true
+                end
+                self:set_result("installed", install)
+                self:set_result("removed", remove)
+                self.ssh:set_changed(changed)
+            end
+
+            return module
+        })
+        .set_name("pip")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_pip_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = pip(&lua, lua.create_table()?);
+        assert!(result.is_err_and(|e| e.to_string().contains("'name' or 'requirements'")));
+
+        let params = lua.create_table()?;
+        params.set("name", lua.create_sequence_from(["flask", "gunicorn"])?)?;
+        params.set("version", "3.0.0")?;
+        assert!(pip(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "flask")?;
+        params.set("version", "3.0; reboot")?;
+        assert!(pip(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("requirements", "/srv/app/requirements.txt")?;
+        params.set("state", "absent")?;
+        assert!(pip(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pip_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.pip({
+                    name = "flask",
+                    version = "3.0.3",
+                    virtualenv = "/srv/app/venv",
+                    extra_index_url = "https://pypi.example.com/simple",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(module.get::<Vec<String>>("wanted")?, ["flask==3.0.3"]);
+        assert_eq!(
+            module.get::<String>("install_command")?,
+            "PIP_DISABLE_PIP_VERSION_CHECK=1 '/srv/app/venv'/bin/pip install --extra-index-url 'https://pypi.example.com/simple'"
+        );
+        assert_eq!(
+            module.get::<String>("virtualenv_create")?,
+            "python3 -m venv '/srv/app/venv'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pip_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local outputs = {
+                    list = "Flask==3.0.3\nzope.interface==6.4\nrequests==2.31.0\n",
+                    outdated = "requests==2.31.0\n",
+                }
+                local function plan(params)
+                    local module = komandan.modules.pip(params)
+                    module.pip = function(_, command)
+                        return string.find(command, "--outdated", 1, true) and outputs.outdated or outputs.list
+                    end
+                    local install, remove = module:plan()
+                    return { table.concat(install, ","), table.concat(remove, ",") }
+                end
+                return {
+                    plan({ name = { "flask", "zope_interface", "gunicorn" } }),
+                    plan({ name = "flask", version = "3.1.0" }),
+                    plan({ name = { "requests[socks]", "flask" }, state = "latest" }),
+                    plan({ name = { "Flask", "gunicorn" }, state = "absent" }),
+                }
+            })
+            .eval::<Vec<Vec<String>>>()?;
+        assert_eq!(
+            plans,
+            [
+                ["gunicorn", ""],
+                ["flask==3.1.0", ""],
+                ["requests[socks]", ""],
+                ["", "Flask"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use mlua::{ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Prints a `reason=` line for every sign that the host needs a reboot:
+/// Debian's `/var/run/reboot-required` flag (with the packages listed in
+/// `reboot-required.pkgs`), `needs-restarting -r` exiting with 1 on RHEL
+/// and Fedora, and a running kernel that is not the newest one installed.
+const REBOOT_REQUIRED_SCRIPT: &str = r#"if [ -f /var/run/reboot-required ]; then
+    pkgs=$(tr '\n' ' ' < /var/run/reboot-required.pkgs 2>/dev/null)
+    echo "reason=/var/run/reboot-required exists${pkgs:+ (packages: ${pkgs% })}"
+fi
+if command -v needs-restarting >/dev/null 2>&1; then
+    needs-restarting -r >/dev/null 2>&1
+    [ $? -eq 1 ] && echo "reason=needs-restarting -r reports a reboot is required"
+fi
+running=$(uname -r)
+if [ -d /lib/modules ]; then
+    latest=$(ls -1 /lib/modules | sort -V | tail -n 1)
+    if [ -n "$latest" ] && [ "$latest" != "$running" ]; then
+        echo "reason=running kernel $running, newest installed kernel $latest"
+    fi
+fi
+exit 0"#;
+
+/// Detect whether the host needs a reboot, e.g. after patching. Never
+/// changes the host; sets `reboot_required` (boolean) and `reboot_reasons`
+/// (list of strings) in the task result. Takes no parameters.
+pub fn reboot_required(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let command = format!("sh -c {}", escape_shell_value(REBOOT_REQUIRED_SCRIPT));
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "reboot_required" })
+
+            module.params = $params
+            module.command = $command
+
+            module.run = function(self)
+                local result = self.ssh:cmdq(self.command)
+                if result.exit_code ~= 0 then
+                    error("Failed to check whether a reboot is required: " .. result.stderr)
+                end
+
+                local reasons = {}
+                for line in result.stdout:gmatch("[^\n]+") do
+                    local reason = line:match("^reason=(.*)$")
+                    if reason ~= nil then
+                        table.insert(reasons, reason)
+                    end
+                end
+
+                self:set_result("reboot_required", #reasons > 0)
+                self:set_result("reboot_reasons", reasons)
+                self.ssh:set_changed(false)
+            end
+
+            module.dry_run = module.run
+
+            return module
+        })
+        .set_name("reboot_required")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_reboot_required_without_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(reboot_required(&lua, lua.create_table()?).is_ok());
+
+        let name = lua
+            .load(chunk! {
+                return komandan.modules.reboot_required().name
+            })
+            .eval::<String>()?;
+        assert_eq!(name, "reboot_required");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reboot_required_sets_result() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        task.set(1, reboot_required(&lua, lua.create_table()?)?)?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+
+        let result =
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))?;
+        assert!(!result.get::<bool>("changed")?);
+        let required = result.get::<bool>("reboot_required")?;
+        let reasons = result.get::<Table>("reboot_reasons")?;
+        assert_eq!(required, reasons.raw_len() > 0);
+        Ok(())
+    }
+}
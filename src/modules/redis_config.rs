@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Heredoc delimiter of the rewritten config file.
+const CONFIG_EOF: &str = "KOMANDAN_REDIS_CONF_EOF";
+
+/// A config value as Redis reads it; booleans become `yes`/`no`.
+fn config_value(name: &str, value: Value) -> mlua::Result<String> {
+    match value {
+        Value::String(value) => Ok(value.to_str()?.to_string()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(if value { "yes" } else { "no" }.to_string()),
+        _ => Err(RuntimeError(format!(
+            "value of '{name}' must be a string, number or boolean"
+        ))),
+    }
+}
+
+/// The settings to apply: `config`, plus `name` = `value` when given.
+fn parse_config(params: &Table) -> mlua::Result<BTreeMap<String, String>> {
+    let mut config = BTreeMap::new();
+    if let Some(table) = params.get::<Option<Table>>("config")? {
+        for pair in table.pairs::<String, Value>() {
+            let (name, value) = pair?;
+            let value = config_value(&name, value)?;
+            config.insert(name.to_lowercase(), value);
+        }
+    }
+    if let Some(name) = params.get::<Option<String>>("name")? {
+        let value = params.get::<Value>("value")?;
+        if value.is_nil() {
+            return Err(RuntimeError(
+                "'value' parameter is required with 'name'".to_string(),
+            ));
+        }
+        let value = config_value(&name, value)?;
+        config.insert(name.to_lowercase(), value);
+    }
+    if config.is_empty() {
+        return Err(RuntimeError(
+            "'name' and 'value' or 'config' parameter is required".to_string(),
+        ));
+    }
+    if let Some(name) = config
+        .keys()
+        .find(|name| name.is_empty() || name.contains(char::is_whitespace))
+    {
+        return Err(RuntimeError(format!("Invalid config name: '{name}'")));
+    }
+    Ok(config)
+}
+
+/// The `redis-cli` invocation with the login options in `params`.
+fn redis_cli(params: &Table) -> mlua::Result<String> {
+    let mut command = String::from("redis-cli");
+    if let Some(host) = params.get::<Option<String>>("login_host")? {
+        let _ = write!(command, " -h {}", escape_shell_value(&host));
+    }
+    if let Some(port) = params.get::<Option<u16>>("login_port")? {
+        let _ = write!(command, " -p {port}");
+    }
+    if let Some(user) = params.get::<Option<String>>("login_user")? {
+        let _ = write!(command, " --user {}", escape_shell_value(&user));
+    }
+    if let Some(password) = params.get::<Option<String>>("login_password")? {
+        let _ = write!(
+            command,
+            " -a {} --no-auth-warning",
+            escape_shell_value(&password)
+        );
+    }
+    Ok(command)
+}
+
+/// Set Redis config values, given as `name` and `value` or as a `config`
+/// table, at runtime with `CONFIG SET` and, unless `persist = false`, write
+/// them to `config_file` (default `/etc/redis/redis.conf`). Only the lines of changed settings are
+/// rewritten; the rest of the file, comments included, is kept. Sets
+/// `diff` (list of `-old`/`+new` lines) in the task result. `login_host`,
+/// `login_port`, `login_user` and `login_password` set how `redis-cli`
+/// connects.
+pub fn redis_config(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let config = parse_config(&params)?;
+    let persist = params.get::<Option<bool>>("persist")?.unwrap_or(true);
+    params.set("persist", persist)?;
+    let config_file = params
+        .get::<Option<String>>("config_file")?
+        .unwrap_or_else(|| "/etc/redis/redis.conf".to_string());
+    params.set("config_file", config_file.as_str())?;
+
+    let cli = redis_cli(&params)?;
+    let settings = lua.create_table()?;
+    for (name, value) in &config {
+        let setting = lua.create_table()?;
+        setting.set("name", name.as_str())?;
+        setting.set("value", value.as_str())?;
+        setting.set(
+            "get_command",
+            format!("{cli} CONFIG GET {}", escape_shell_value(name)),
+        )?;
+        setting.set(
+            "set_command",
+            format!(
+                "{cli} CONFIG SET {} {}",
+                escape_shell_value(name),
+                escape_shell_value(value)
+            ),
+        )?;
+        settings.push(setting)?;
+    }
+    let read_command = format!("cat {}", escape_shell_value(&config_file));
+    let write_command = format!(
+        "sh -c 'cat > \"$1\"' sh {} <<'{CONFIG_EOF}'\n",
+        escape_shell_value(&config_file)
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "redis_config" })
+
+            module.params = $params
+            module.settings = $settings
+            module.read_command = $read_command
+            module.write_command = $write_command
+            module.config_eof = $CONFIG_EOF
+
+            -- The value in bytes when it is a memory size such as "256mb",
+            -- otherwise lower-cased, so "256mb" matches "268435456" as
+            -- reported by CONFIG GET.
+            module.normalize = function(value)
+                value = string.lower(value)
+                local number, unit = string.match(value, "^(%d+)(%a+)$")
+                local units = {
+                    b = 1, k = 1000, kb = 1024, m = 1000 * 1000, mb = 1024 * 1024,
+                    g = 1000 * 1000 * 1000, gb = 1024 * 1024 * 1024,
+                }
+                if number ~= nil and units[unit] ~= nil then
+                    return string.format("%d", tonumber(number) * units[unit])
+                end
+                return value
+            end
+
+            module.redis_cli = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 or string.match(result.stdout, "^ERR") then
+                    error("redis_config: redis-cli failed: " .. result.stderr .. result.stdout)
+                end
+                return result.stdout
+            end
+
+            -- Settings whose running value differs from the wanted one.
+            module.runtime_changes = function(self)
+                self.ssh:requires("redis-cli")
+                local changes = {}
+                for _, setting in ipairs(self.settings) do
+                    local output = self:redis_cli(setting.get_command)
+                    local name, current = string.match(output, "^([^\n]*)\n?(.*)$")
+                    if name == nil or name == "" then
+                        error("redis_config: unknown config '" .. setting.name .. "'")
+                    end
+                    if self.normalize(current) ~= self.normalize(setting.value) then
+                        table.insert(changes, setting)
+                    end
+                end
+                return changes
+            end
+
+            -- The content with every setting on one directive line: the first
+            -- line of a directive is replaced when its value differs, later
+            -- duplicates are removed and missing directives are appended.
+            -- Returns the new content and the diff lines.
+            module.rewrite_config = function(self, content)
+                local lines = {}
+                local pos = 1
+                while pos <= #content do
+                    local newline = string.find(content, "\n", pos, true)
+                    if newline == nil then
+                        table.insert(lines, string.sub(content, pos))
+                        break
+                    end
+                    table.insert(lines, string.sub(content, pos, newline - 1))
+                    pos = newline + 1
+                end
+
+                local diff = {}
+                for _, setting in ipairs(self.settings) do
+                    local wanted = setting.value == "" and setting.name .. " \"\"" or setting.name .. " " .. setting.value
+                    local found = false
+                    local kept = {}
+                    for _, line in ipairs(lines) do
+                        local directive, value = string.match(line, "^%s*(%S+)%s*(.-)%s*$")
+                        if directive ~= nil and string.lower(directive) == setting.name then
+                            value = string.match(value, "^\"(.*)\"$") or value
+                            if found then
+                                table.insert(diff, "-" .. line)
+                            elseif self.normalize(value) ~= self.normalize(setting.value) then
+                                table.insert(diff, "-" .. line)
+                                table.insert(diff, "+" .. wanted)
+                                table.insert(kept, wanted)
+                            else
+                                table.insert(kept, line)
+                            end
+                            found = true
+                        else
+                            table.insert(kept, line)
+                        end
+                    end
+                    if not found then
+                        table.insert(diff, "+" .. wanted)
+                        table.insert(kept, wanted)
+                    end
+                    lines = kept
+                end
+                return table.concat(lines, "\n") .. "\n", diff
+            end
+
+            module.file_changes = function(self)
+                local result = self.ssh:cmdq(self.read_command)
+                if result.exit_code ~= 0 then
+                    error("redis_config: failed to read " .. self.params.config_file .. ": " .. result.stderr)
+                end
+                return self:rewrite_config(result.stdout)
+            end
+
+            module.dry_run = function(self)
+                local changed = #self:runtime_changes() > 0
+                if self.params.persist then
+                    local _, diff = self:file_changes()
+                    self:set_result("diff", diff)
+                    changed = changed or #diff > 0
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.run = function(self)
+                local changes = self:runtime_changes()
+                for _, setting in ipairs(changes) do
+                    self:redis_cli(setting.set_command)
+                end
+                local changed = #changes > 0
+                if self.params.persist then
+                    local content, diff = self:file_changes()
+                    if #diff > 0 then
+                        local result = self.ssh:cmdq(self.write_command .. content .. self.config_eof)
+                        if result.exit_code ~= 0 then
+                            error("redis_config: failed to write " .. self.params.config_file .. ": " .. result.stderr)
+                        end
+                        changed = true
+                    end
+                    self:set_result("diff", diff)
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            return module
+        })
+        .set_name("redis_config")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_redis_config_requires_config() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(redis_config(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "maxmemory")?;
+        assert!(redis_config(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_redis_config_settings() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.redis_config({
+                    name = "maxmemory",
+                    value = "256mb",
+                    config = { ["appendonly"] = true, ["MaxMemory-Policy"] = "allkeys-lru" },
+                    login_password = "hunter2",
+                })
+            })
+            .eval::<Table>()?;
+        let settings = module.get::<Vec<Table>>("settings")?;
+        let names = settings
+            .iter()
+            .map(|setting| setting.get::<String>("name"))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        assert_eq!(names, ["appendonly", "maxmemory", "maxmemory-policy"]);
+        assert_eq!(settings[0].get::<String>("value")?, "yes");
+        assert_eq!(
+            settings[1].get::<String>("set_command")?,
+            "redis-cli -a 'hunter2' --no-auth-warning CONFIG SET 'maxmemory' '256mb'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_redis_config_rewrite() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (content, diff) = lua
+            .load(chunk! {
+                local module = komandan.modules.redis_config({
+                    config = { maxmemory = "256mb", appendonly = "yes", ["maxmemory-policy"] = "allkeys-lru" },
+                })
+                return module:rewrite_config("# Redis\nmaxmemory 268435456\nappendonly no\n# appendonly yes\nappendonly no\n")
+            })
+            .eval::<(String, Vec<String>)>()?;
+        assert_eq!(
+            content,
+            "# Redis\nmaxmemory 268435456\nappendonly yes\n# appendonly yes\nmaxmemory-policy allkeys-lru\n"
+        );
+        assert_eq!(
+            diff,
+            [
+                "-appendonly no",
+                "+appendonly yes",
+                "-appendonly no",
+                "+maxmemory-policy allkeys-lru"
+            ]
+        );
+        Ok(())
+    }
+}
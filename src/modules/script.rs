@@ -1,6 +1,10 @@
 use mlua::{Lua, Table, chunk};
 use rand::{RngExt, distr::Alphanumeric};
 
+/// Run a script on the host, given inline as `script` or as the local file
+/// `from_file`, with `interpreter`, or else `sh` for short inline scripts
+/// and the script's own shebang otherwise. Always reported as changed; a
+/// dry run only checks that `from_file` exists.
 pub fn script(lua: &Lua, params: Table) -> mlua::Result<Table> {
     let random_file_name: String = rand::rng()
         .sample_iter(&Alphanumeric)
@@ -24,6 +28,13 @@ pub fn script(lua: &Lua, params: Table) -> mlua::Result<Table> {
             module.params = $params
             module.random_file_name = $random_file_name
 
+            module.dry_run = function(self)
+                if self.params.from_file ~= nil and self.local_sha256(self.params.from_file) == nil then
+                    error("from_file does not exist: " .. self.params.from_file)
+                end
+                self.ssh:set_changed(true)
+            end
+
             module.run = function(self)
                 local script_content = self.params.script
                 local use_inline = false
@@ -0,0 +1,201 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// The wanted value of the boolean: `true`/`false`, `on`/`off` or `1`/`0`.
+fn parse_state(value: Value) -> mlua::Result<bool> {
+    match value {
+        Value::Boolean(state) => Ok(state),
+        Value::Integer(1) => Ok(true),
+        Value::Integer(0) => Ok(false),
+        Value::String(state) => match state.to_str()?.to_lowercase().as_str() {
+            "on" | "true" | "1" => Ok(true),
+            "off" | "false" | "0" => Ok(false),
+            state => Err(RuntimeError(format!(
+                "Invalid state: {state}. Valid states are: on and off."
+            ))),
+        },
+        Value::Nil => Err(RuntimeError("'state' parameter is required".to_string())),
+        _ => Err(RuntimeError(
+            "'state' parameter must be a boolean or 'on'/'off'".to_string(),
+        )),
+    }
+}
+
+/// Set the SELinux boolean `name` to `state` (`true`/`false` or
+/// `"on"`/`"off"`) with `setsebool`. With `persistent = true` (the default)
+/// the value is also written to the policy, so it survives reboots, and a
+/// change is reported when either the running or the persistent value
+/// differs. Sets `previous` (`"on"`/`"off"`, the running value before the
+/// change) in the task result.
+pub fn seboolean(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(RuntimeError(format!("Invalid boolean name: '{name}'")));
+    }
+    let wanted = if parse_state(params.get::<Value>("state")?)? {
+        "on"
+    } else {
+        "off"
+    };
+    let persistent = params.get::<Option<bool>>("persistent")?.unwrap_or(true);
+    params.set("persistent", persistent)?;
+
+    let get_command = format!("getsebool {name}");
+    let policy_command = format!("semanage boolean -l -n | grep -E '^{name} '");
+    let set_command = format!(
+        "setsebool{} {name} {}",
+        if persistent { " -P" } else { "" },
+        escape_shell_value(wanted)
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "seboolean" })
+
+            module.params = $params
+            module.wanted = $wanted
+            module.get_command = $get_command
+            module.policy_command = $policy_command
+            module.set_command = $set_command
+
+            -- The running value, on or off.
+            module.current = function(self)
+                self.ssh:requires("getsebool")
+                local result = self.ssh:cmdq(self.get_command)
+                local value = string.match(result.stdout, "%-%->%s*(%a+)")
+                if result.exit_code ~= 0 or value == nil then
+                    error("seboolean: failed to read " .. self.params.name .. ": " .. result.stderr)
+                end
+                return value
+            end
+
+            -- The persistent value as listed by semanage, e.g. the second
+            -- value of "name (on   ,  off)  Description".
+            module.persistent_value = function(self)
+                self.ssh:requires("semanage")
+                local result = self.ssh:cmdq(self.policy_command)
+                local value = string.match(result.stdout, "%(%s*%a+%s*,%s*(%a+)%s*%)")
+                if result.exit_code ~= 0 or value == nil then
+                    error("seboolean: failed to read the policy value of " .. self.params.name .. ": " .. result.stderr)
+                end
+                return value
+            end
+
+            module.needs_change = function(self)
+                local current = self:current()
+                self:set_result("previous", current)
+                if current ~= self.wanted then
+                    return true
+                end
+                return self.params.persistent and self:persistent_value() ~= self.wanted
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:needs_change())
+            end
+
+            module.run = function(self)
+                if not self:needs_change() then
+                    return
+                end
+                local result = self.ssh:cmdq(self.set_command)
+                if result.exit_code ~= 0 then
+                    error("seboolean: setsebool failed: " .. result.stderr)
+                end
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("seboolean")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_seboolean_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(seboolean(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "httpd_can_network_connect")?;
+        let result = seboolean(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'state' parameter is required")));
+
+        let params = lua.create_table()?;
+        params.set("name", "httpd; reboot")?;
+        params.set("state", true)?;
+        assert!(seboolean(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "httpd_can_network_connect")?;
+        params.set("state", "maybe")?;
+        assert!(seboolean(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seboolean_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.seboolean({ name = "httpd_can_network_connect", state = "on" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(module.get::<String>("wanted")?, "on");
+        assert_eq!(
+            module.get::<String>("set_command")?,
+            "setsebool -P httpd_can_network_connect 'on'"
+        );
+
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.seboolean({ name = "virt_use_nfs", state = false, persistent = false })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("set_command")?,
+            "setsebool virt_use_nfs 'off'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_seboolean_needs_change() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local function needs_change(persistent, current, policy)
+                    local module = komandan.modules.seboolean({
+                        name = "httpd_can_network_connect", state = true, persistent = persistent,
+                    })
+                    module.set_result = function() end
+                    module.current = function() return current end
+                    module.persistent_value = function() return policy end
+                    return module:needs_change()
+                end
+                return {
+                    needs_change(true, "on", "on"),
+                    needs_change(true, "on", "off"),
+                    needs_change(false, "on", "off"),
+                    needs_change(false, "off", "off"),
+                }
+            })
+            .eval::<Vec<bool>>()?;
+        assert_eq!(results, [false, true, false, true]);
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// The `semanage fcontext -f` file types and how `semanage fcontext -l`
+/// lists them.
+const FILE_TYPES: [(&str, &str); 8] = [
+    ("a", "all files"),
+    ("f", "regular file"),
+    ("d", "directory"),
+    ("l", "symbolic link"),
+    ("c", "character device"),
+    ("b", "block device"),
+    ("s", "socket"),
+    ("p", "named pipe"),
+];
+
+/// The path to relabel for `target`: its leading part up to the first
+/// regular expression character, e.g. `/srv/www` for `/srv/www(/.*)?`.
+fn restorecon_path(target: &str) -> String {
+    let end = target
+        .find(['(', '[', '*', '?', '+', '|', '^', '$', '\\'])
+        .unwrap_or(target.len());
+    let path = target[..end].trim_end_matches('/');
+    if path.is_empty() {
+        "/".to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+/// Manage the SELinux file-context rule for `target`, a path regular
+/// expression such as `/srv/www(/.*)?`, with `semanage fcontext`. The rule
+/// labels the files matched with `setype`; `ftype` limits it to one file
+/// type (`a` all files, the default, or `f`, `d`, `l`, `c`, `b`, `s`, `p`).
+/// After a change, `restorecon -R` relabels the `restorecon` paths (default:
+/// the leading path of `target`) that exist; set `restorecon = false` to
+/// skip it. `state = "absent"` removes the rule.
+pub fn sefcontext(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let target = params
+        .get::<Option<String>>("target")?
+        .ok_or_else(|| RuntimeError("'target' parameter is required".to_string()))?;
+    if !target.starts_with('/') {
+        return Err(RuntimeError(format!(
+            "Invalid target: '{target}'. Targets are absolute path expressions"
+        )));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let setype = params.get::<Option<String>>("setype")?;
+    if state == "present" && setype.is_none() {
+        return Err(RuntimeError(
+            "'setype' parameter is required unless state is 'absent'".to_string(),
+        ));
+    }
+    let ftype = params
+        .get::<Option<String>>("ftype")?
+        .unwrap_or_else(|| "a".to_string());
+    let Some(&(_, ftype_name)) = FILE_TYPES.iter().find(|(flag, _)| *flag == ftype) else {
+        return Err(RuntimeError(format!(
+            "Invalid ftype: {ftype}. Valid file types are: a, f, d, l, c, b, s and p."
+        )));
+    };
+    params.set("ftype", ftype.as_str())?;
+
+    let restorecon = match params.get::<Value>("restorecon")? {
+        Value::Nil => vec![restorecon_path(&target)],
+        Value::Boolean(false) => Vec::new(),
+        Value::Table(paths) => paths
+            .sequence_values::<String>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(
+                "'restorecon' parameter must be a list of paths or false".to_string(),
+            ));
+        }
+    };
+
+    let quoted_target = escape_shell_value(&target);
+    let rule = |action: &str| {
+        let setype = setype.as_deref().map_or_else(String::new, |setype| {
+            format!(" -t {}", escape_shell_value(setype))
+        });
+        format!("semanage fcontext {action} -f {ftype}{setype} {quoted_target}")
+    };
+    let add_command = rule("-a");
+    let modify_command = rule("-m");
+    let delete_command = format!("semanage fcontext -d -f {ftype} {quoted_target}");
+    let restorecon_commands = restorecon
+        .iter()
+        .map(|path| {
+            let path = escape_shell_value(path);
+            format!("if [ -e {path} ]; then restorecon -R -- {path}; fi")
+        })
+        .collect::<Vec<_>>();
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "sefcontext" })
+
+            module.params = $params
+            module.ftype_name = $ftype_name
+            module.list_command = "semanage fcontext -l -C -n"
+            module.add_command = $add_command
+            module.modify_command = $modify_command
+            module.delete_command = $delete_command
+            module.restorecon_commands = $restorecon_commands
+
+            -- The type the local rule for the target and file type labels
+            -- files with, or nil when there is no such rule. Lines look like
+            -- "/srv/www(/.*)?   all files   system_u:object_r:httpd_sys_content_t:s0".
+            module.current_type = function(self, listing)
+                for line in string.gmatch(listing, "[^\n]+") do
+                    local target, ftype, context = string.match(line, "^(%S+)%s+(.-)%s+(%S+)%s*$")
+                    if target == self.params.target and ftype == self.ftype_name then
+                        return string.match(context, "^[^:]*:[^:]*:([^:]+)") or context
+                    end
+                end
+                return nil
+            end
+
+            module.semanage = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("sefcontext: semanage failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- The command bringing the rule to the wanted state, or nil.
+            module.change = function(self)
+                self.ssh:requires("semanage")
+                local current = self:current_type(self:semanage(self.list_command))
+                self:set_result("previous", current)
+                if self.params.state == "absent" then
+                    return current ~= nil and self.delete_command or nil
+                end
+                if current == nil then
+                    return self.add_command
+                end
+                if current ~= self.params.setype then
+                    return self.modify_command
+                end
+                return nil
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:change() ~= nil)
+            end
+
+            module.run = function(self)
+                local command = self:change()
+                if command == nil then
+                    return
+                end
+                self:semanage(command)
+                for _, restorecon in ipairs(self.restorecon_commands) do
+                    local result = self.ssh:cmdq(restorecon)
+                    if result.exit_code ~= 0 then
+                        error("sefcontext: restorecon failed: " .. result.stderr)
+                    end
+                end
+                self.ssh:set_changed(true)
+            end
+
+            return module
+        })
+        .set_name("sefcontext")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_sefcontext_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(sefcontext(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("target", "/srv/www(/.*)?")?;
+        let result = sefcontext(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'setype' parameter is required")));
+
+        let params = lua.create_table()?;
+        params.set("target", "/srv/www(/.*)?")?;
+        params.set("setype", "httpd_sys_content_t")?;
+        params.set("ftype", "x")?;
+        assert!(sefcontext(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restorecon_path() {
+        assert_eq!(restorecon_path("/srv/www(/.*)?"), "/srv/www");
+        assert_eq!(restorecon_path("/var/lib/app/data/.*"), "/var/lib/app/data");
+        assert_eq!(restorecon_path("/opt/app.conf"), "/opt/app.conf");
+        assert_eq!(restorecon_path("/(.*)"), "/");
+    }
+
+    #[test]
+    fn test_sefcontext_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.sefcontext({ target = "/srv/www(/.*)?", setype = "httpd_sys_content_t" })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("add_command")?,
+            "semanage fcontext -a -f a -t 'httpd_sys_content_t' '/srv/www(/.*)?'"
+        );
+        assert_eq!(
+            module.get::<Vec<String>>("restorecon_commands")?,
+            ["if [ -e '/srv/www' ]; then restorecon -R -- '/srv/www'; fi"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sefcontext_change() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let changes = lua
+            .load(chunk! {
+                local listing = "/srv/www(/.*)?   all files   system_u:object_r:httpd_sys_content_t:s0\n/srv/www(/.*)?   directory   system_u:object_r:var_t:s0"
+                local function change(params)
+                    local module = komandan.modules.sefcontext(params)
+                    module.ssh = { requires = function() end }
+                    module.set_result = function() end
+                    module.semanage = function() return listing end
+                    return module:change() or "none"
+                end
+                return {
+                    change({ target = "/srv/www(/.*)?", setype = "httpd_sys_content_t" }),
+                    change({ target = "/srv/www(/.*)?", setype = "httpd_sys_content_t", ftype = "d" }),
+                    change({ target = "/srv/app(/.*)?", setype = "httpd_sys_content_t" }),
+                    change({ target = "/srv/www(/.*)?", state = "absent" }),
+                    change({ target = "/srv/app(/.*)?", state = "absent" }),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            changes,
+            [
+                "none",
+                "semanage fcontext -m -f d -t 'httpd_sys_content_t' '/srv/www(/.*)?'",
+                "semanage fcontext -a -f a -t 'httpd_sys_content_t' '/srv/app(/.*)?'",
+                "semanage fcontext -d -f a '/srv/www(/.*)?'",
+                "none",
+            ]
+        );
+        Ok(())
+    }
+}
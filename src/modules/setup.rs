@@ -0,0 +1,75 @@
+use mlua::{ExternalResult, Lua, Table, chunk};
+
+/// Gather the facts of the host, as `komandan.facts` returns them (OS,
+/// memory, disks, network interfaces, virtualization), and set them as
+/// `facts` in the task result so a script can branch on the hardware or OS
+/// of the host. Facts are always gathered anew, and the fact cache is
+/// refreshed with them. Never changes the host. Takes no parameters, so
+/// `komandan.modules.setup()` needs no argument.
+pub fn setup(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "setup" })
+
+            module.params = $params
+
+            module.run = function(self)
+                local result = self.ssh:cmdq(self.facts_command)
+                if result.exit_code ~= 0 then
+                    error("setup: failed to gather facts: " .. result.stderr)
+                end
+                self.gathered_facts = self.parse_facts(result.stdout, self.host)
+                self:set_result("facts", self.gathered_facts)
+                self.ssh:set_changed(false)
+            end
+
+            module.dry_run = module.run
+
+            return module
+        })
+        .set_name("setup")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_setup_without_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(setup(&lua, lua.create_table()?).is_ok());
+
+        let name = lua
+            .load(chunk! {
+                return komandan.modules.setup().name
+            })
+            .eval::<String>()?;
+        assert_eq!(name, "setup");
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_sets_facts() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        task.set(1, setup(&lua, lua.create_table()?)?)?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+
+        let result =
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))?;
+        assert!(!result.get::<bool>("changed")?);
+        let facts = result.get::<Table>("facts")?;
+        assert!(facts.get::<Option<String>>("hostname")?.is_some());
+        assert!(facts.get::<Table>("interfaces").is_ok());
+        Ok(())
+    }
+}
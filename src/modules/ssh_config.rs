@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Heredoc delimiter of the written block file.
+const BLOCK_EOF: &str = "KOMANDAN_SSH_CONFIG_EOF";
+
+/// An option value as written to an SSH config; booleans become `yes`/`no`.
+fn option_value(name: &str, value: Value) -> mlua::Result<String> {
+    match value {
+        Value::String(value) => Ok(value.to_str()?.to_string()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(if value { "yes" } else { "no" }.to_string()),
+        _ => Err(RuntimeError(format!(
+            "option '{name}' must be a string, integer or boolean"
+        ))),
+    }
+}
+
+/// Render `hosts`, a list of `{ host = "pattern", options = { ... } }`
+/// tables, as `Host` sections with their options sorted by name.
+fn render_hosts(hosts: &Table) -> mlua::Result<String> {
+    let mut content = String::new();
+    for entry in hosts.sequence_values::<Table>() {
+        let entry = entry?;
+        let host = entry
+            .get::<Option<String>>("host")?
+            .ok_or_else(|| RuntimeError("every entry of 'hosts' needs a 'host'".to_string()))?;
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        let _ = writeln!(content, "Host {host}");
+        let mut options = BTreeMap::new();
+        if let Some(table) = entry.get::<Option<Table>>("options")? {
+            for pair in table.pairs::<String, Value>() {
+                let (name, value) = pair?;
+                let value = option_value(&name, value)?;
+                options.insert(name, value);
+            }
+        }
+        for (name, value) in options {
+            let _ = writeln!(content, "    {name} {value}");
+        }
+    }
+    Ok(content)
+}
+
+/// The content of the block: `content` as given, or rendered from `hosts`.
+fn block_content(params: &Table) -> mlua::Result<String> {
+    let mut content = match (
+        params.get::<Option<String>>("content")?,
+        params.get::<Option<Table>>("hosts")?,
+    ) {
+        (Some(content), None) => content,
+        (None, Some(hosts)) => render_hosts(&hosts)?,
+        (Some(_), Some(_)) => {
+            return Err(RuntimeError(
+                "'content' and 'hosts' parameters are mutually exclusive".to_string(),
+            ));
+        }
+        (None, None) => {
+            return Err(RuntimeError(
+                "'content' or 'hosts' parameter is required unless state is 'absent'".to_string(),
+            ));
+        }
+    };
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    Ok(format!("# Managed by komandan\n{content}"))
+}
+
+/// Manage the block `name` of a user's SSH client config. The block is kept
+/// in `~/.ssh/config.d/<name>.conf` and pulled in by an `Include` line at
+/// the top of `~/.ssh/config`, so the rest of the config is never touched.
+/// The block is `content` or is rendered from `hosts`, a list of
+/// `{ host = "pattern", options = { HostName = "...", ... } }` tables.
+/// `user` selects whose config (default: the connecting user); the files
+/// are then owned by that user. `state = "absent"` removes the block and
+/// its `Include` line.
+pub fn ssh_config(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        || name.starts_with('.')
+    {
+        return Err(RuntimeError(format!(
+            "Invalid block name: '{name}'. Use letters, digits, '.', '_' and '-'"
+        )));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let content = if state == "present" {
+        Some(block_content(&params)?)
+    } else {
+        None
+    };
+
+    let user = params.get::<Option<String>>("user")?;
+    let ssh_dir = user.as_deref().map_or_else(
+        || "SSH_DIR=\"$HOME/.ssh\"".to_string(),
+        |user| {
+            format!(
+                "SSH_DIR=\"$(getent passwd {} | cut -d: -f6)/.ssh\"",
+                escape_shell_value(user)
+            )
+        },
+    );
+    let chown = user.as_deref().map_or_else(String::new, |user| {
+        format!(
+            " && chown {}: \"$SSH_DIR\" \"$SSH_DIR/config.d\" \"$SSH_DIR/config\" \"$BLOCK\"",
+            escape_shell_value(user)
+        )
+    });
+    let include = escape_shell_value(&format!("Include config.d/{name}.conf"));
+    let prelude = format!(
+        "{ssh_dir} && [ \"$SSH_DIR\" != /.ssh ] && BLOCK=\"$SSH_DIR/config.d/{name}.conf\" && "
+    );
+
+    let read_command = format!("{prelude}cat \"$BLOCK\"");
+    let include_command = format!("{prelude}grep -qxF {include} \"$SSH_DIR/config\"");
+    let write_command = format!(
+        "{prelude}mkdir -p \"$SSH_DIR/config.d\" && touch \"$SSH_DIR/config\" \"$BLOCK\" && chmod 700 \"$SSH_DIR\" \"$SSH_DIR/config.d\" && chmod 600 \"$BLOCK\" \"$SSH_DIR/config\"{chown} && cat > \"$BLOCK\" <<'{BLOCK_EOF}'\n"
+    );
+    let add_include_command = format!(
+        "{prelude}{{ printf '%s\\n' {include}; cat \"$SSH_DIR/config\"; }} > \"$SSH_DIR/config.komandan\" && cat \"$SSH_DIR/config.komandan\" > \"$SSH_DIR/config\" && rm -f \"$SSH_DIR/config.komandan\""
+    );
+    let remove_command = format!(
+        "{prelude}rm -f \"$BLOCK\" && if [ -f \"$SSH_DIR/config\" ]; then {{ grep -vxF {include} \"$SSH_DIR/config\" || true; }} > \"$SSH_DIR/config.komandan\" && cat \"$SSH_DIR/config.komandan\" > \"$SSH_DIR/config\" && rm -f \"$SSH_DIR/config.komandan\"; fi"
+    );
+    // `cat` output comes back without its trailing newlines.
+    let wanted_output = content
+        .as_deref()
+        .map(|content| content.trim_end_matches('\n').to_string());
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "ssh_config" })
+
+            module.params = $params
+            module.content = $content
+            module.wanted_output = $wanted_output
+            module.read_command = $read_command
+            module.include_command = $include_command
+            module.write_command = $write_command
+            module.add_include_command = $add_include_command
+            module.remove_command = $remove_command
+            module.block_eof = $BLOCK_EOF
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("ssh_config: failed to update the SSH config: " .. result.stderr)
+                end
+            end
+
+            -- The current block content, nil when missing, and whether the
+            -- config includes the block.
+            module.state = function(self)
+                local block = self.ssh:cmdq(self.read_command)
+                local included = self.ssh:cmdq(self.include_command).exit_code == 0
+                if block.exit_code ~= 0 then
+                    return nil, included
+                end
+                return block.stdout, included
+            end
+
+            module.changes = function(self)
+                local block, included = self:state()
+                if self.params.state == "absent" then
+                    return block ~= nil or included, false
+                end
+                return block ~= self.wanted_output, not included
+            end
+
+            module.dry_run = function(self)
+                local write, include = self:changes()
+                self.ssh:set_changed(write or include)
+            end
+
+            module.run = function(self)
+                local write, include = self:changes()
+                if self.params.state == "absent" then
+                    if write then
+                        self:sh(self.remove_command)
+                        self.ssh:set_changed(true)
+                    end
+                    return
+                end
+                if write then
+                    self:sh(self.write_command .. self.content .. self.block_eof)
+                end
+                if include then
+                    self:sh(self.add_include_command)
+                end
+                self.ssh:set_changed(write or include)
+            end
+
+            return module
+        })
+        .set_name("ssh_config")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_ssh_config_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(ssh_config(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "../evil")?;
+        params.set("content", "Host *\n")?;
+        assert!(ssh_config(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "bastion")?;
+        let result = ssh_config(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'content' or 'hosts'")));
+
+        let params = lua.create_table()?;
+        params.set("name", "bastion")?;
+        params.set("state", "absent")?;
+        assert!(ssh_config(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_config_renders_hosts() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.ssh_config({
+                    name = "bastion",
+                    hosts = {
+                        { host = "bastion", options = { User = "ops", HostName = "203.0.113.10", Port = 2222 } },
+                        { host = "10.0.*", options = { ProxyJump = "bastion", ForwardAgent = false } },
+                    },
+                    user = "deploy",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("content")?,
+            "# Managed by komandan\nHost bastion\n    HostName 203.0.113.10\n    Port 2222\n    User ops\n\nHost 10.0.*\n    ForwardAgent no\n    ProxyJump bastion\n"
+        );
+        let write = module.get::<String>("write_command")?;
+        assert!(write.starts_with("SSH_DIR=\"$(getent passwd 'deploy' | cut -d: -f6)/.ssh\""));
+        assert!(write.contains("chown 'deploy':"));
+        assert!(
+            module
+                .get::<String>("include_command")?
+                .contains("grep -qxF 'Include config.d/bastion.conf'")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_config_changes() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local function changes(params, block, included)
+                    local module = komandan.modules.ssh_config(params)
+                    module.state = function() return block, included end
+                    local write, include = module:changes()
+                    return { write, include }
+                end
+                local present = { name = "work", content = "Host work\n    User me\n" }
+                local content = "# Managed by komandan\nHost work\n    User me"
+                return {
+                    changes(present, content, true),
+                    changes(present, nil, false),
+                    changes(present, content, false),
+                    changes({ name = "work", state = "absent" }, nil, false),
+                    changes({ name = "work", state = "absent" }, nil, true),
+                }
+            })
+            .eval::<Vec<Vec<bool>>>()?;
+        assert_eq!(
+            results,
+            [
+                [false, false],
+                [true, true],
+                [false, true],
+                [false, false],
+                [true, false]
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// The values of an option as written to `sshd_config`: booleans become
+/// `yes`/`no`, and a list gives one line per value (e.g. `Port` or
+/// `HostKey`).
+fn option_values(name: &str, value: Value) -> mlua::Result<Vec<String>> {
+    let values = match value {
+        Value::String(value) => vec![value.to_str()?.to_string()],
+        Value::Integer(value) => vec![value.to_string()],
+        Value::Boolean(value) => vec![if value { "yes" } else { "no" }.to_string()],
+        Value::Table(values) => values
+            .sequence_values::<Value>()
+            .map(|value| {
+                let value = value?;
+                if value.is_table() {
+                    return Err(RuntimeError(format!(
+                        "option '{name}' must not contain nested lists"
+                    )));
+                }
+                option_values(name, value).map(|mut values| values.remove(0))
+            })
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(format!(
+                "option '{name}' must be a string, integer, boolean or list"
+            )));
+        }
+    };
+    if values.is_empty() {
+        return Err(RuntimeError(format!("option '{name}' has no value")));
+    }
+    if values
+        .iter()
+        .any(|value| value.trim().is_empty() || value.contains('\n'))
+    {
+        return Err(RuntimeError(format!(
+            "option '{name}' must have non-empty single-line values"
+        )));
+    }
+    Ok(values)
+}
+
+/// Set global options of the SSH server config `path` (default
+/// `/etc/ssh/sshd_config`), given as an `options` table such as
+/// `{ PermitRootLogin = "prohibit-password", PasswordAuthentication = false,
+/// Port = 2222 }`; a list value gives one line per value. sshd uses the
+/// first value it reads for an option, so the managed options are moved to
+/// the top of the file, ahead of `Include` lines and drop-ins that would
+/// otherwise override them; other global lines of those options are
+/// removed, while `Match` blocks are left alone. The new config is checked
+/// with `sshd -t` before it replaces the file, and a config that fails the
+/// check is refused. With `reload = true` the `service` (default `sshd`)
+/// is reloaded after a change. Sets `diff` (list of `-old`/`+new` lines) in
+/// the task result.
+pub fn sshd_config(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let table = params
+        .get::<Option<Table>>("options")?
+        .ok_or_else(|| RuntimeError("'options' parameter is required".to_string()))?;
+    let mut options = BTreeMap::new();
+    for pair in table.pairs::<String, Value>() {
+        let (name, value) = pair?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(RuntimeError(format!("Invalid option name: '{name}'")));
+        }
+        if name.eq_ignore_ascii_case("match") || name.eq_ignore_ascii_case("include") {
+            return Err(RuntimeError(format!(
+                "option '{name}' cannot be managed by sshd_config"
+            )));
+        }
+        let values = option_values(&name, value)?;
+        options.insert(name, values);
+    }
+    if options.is_empty() {
+        return Err(RuntimeError("'options' parameter is empty".to_string()));
+    }
+    let path = params
+        .get::<Option<String>>("path")?
+        .unwrap_or_else(|| "/etc/ssh/sshd_config".to_string());
+    params.set("path", path.as_str())?;
+    let service = params
+        .get::<Option<String>>("service")?
+        .unwrap_or_else(|| "sshd".to_string());
+    let reload_command = if params.get::<Option<bool>>("reload")?.unwrap_or(false) {
+        Some(format!("systemctl reload {}", escape_shell_value(&service)))
+    } else {
+        None
+    };
+
+    let lines = lua.create_table()?;
+    for (name, values) in &options {
+        for value in values {
+            lines.push(format!("{name} {value}"))?;
+        }
+    }
+    let managed = options
+        .keys()
+        .map(|name| name.to_lowercase())
+        .collect::<Vec<_>>();
+
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+    let tmp_path = format!("{path}.komandan-{random_file_name}");
+    let quoted_path = escape_shell_value(&path);
+    let quoted_tmp = escape_shell_value(&tmp_path);
+    let read_command = format!("cat {quoted_path} && printf .");
+    let validate_command =
+        format!("\"$(command -v sshd || echo /usr/sbin/sshd)\" -t -f {quoted_tmp}");
+    let install_command = format!(
+        "chmod --reference={quoted_path} {quoted_tmp} && {{ chown --reference={quoted_path} {quoted_tmp} 2>/dev/null; true; }} && mv -f {quoted_tmp} {quoted_path}"
+    );
+    let remove_tmp_command = format!("rm -f {quoted_tmp}");
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "sshd_config" })
+
+            module.params = $params
+            module.lines = $lines
+            module.managed = $managed
+            module.tmp_path = $tmp_path
+            module.read_command = $read_command
+            module.validate_command = $validate_command
+            module.install_command = $install_command
+            module.remove_tmp_command = $remove_tmp_command
+            module.reload_command = $reload_command
+
+            -- The content with the managed options on top and their other
+            -- global lines removed. Returns the new content and the diff
+            -- lines.
+            module.rewrite_config = function(self, content)
+                local managed = {}
+                for _, name in ipairs(self.managed) do
+                    managed[name] = true
+                end
+                local lines = {}
+                for _, line in ipairs(self.lines) do
+                    table.insert(lines, line)
+                end
+                local wanted = {}
+                for _, line in ipairs(self.lines) do
+                    wanted[line] = true
+                end
+
+                local removed = {}
+                local diff = {}
+                local in_match = false
+                for line in string.gmatch(content, "([^\n]*)\n") do
+                    local keyword = string.match(line, "^%s*(%w+)")
+                    if keyword ~= nil and string.lower(keyword) == "match" then
+                        in_match = true
+                    end
+                    if not in_match and keyword ~= nil and managed[string.lower(keyword)] then
+                        removed[line] = true
+                        if not wanted[line] then
+                            table.insert(diff, "-" .. line)
+                        end
+                    else
+                        table.insert(lines, line)
+                    end
+                end
+                for _, line in ipairs(self.lines) do
+                    if not removed[line] then
+                        table.insert(diff, "+" .. line)
+                    end
+                end
+                return table.concat(lines, "\n") .. "\n", diff
+            end
+
+            module.changes = function(self)
+                local result = self.ssh:cmdq(self.read_command)
+                if result.exit_code ~= 0 then
+                    error("sshd_config: failed to read " .. self.params.path .. ": " .. result.stderr)
+                end
+                local content = string.sub(result.stdout, 1, -2)
+                if content ~= "" and string.sub(content, -1) ~= "\n" then
+                    content = content .. "\n"
+                end
+                local new_content, diff = self:rewrite_config(content)
+                return new_content ~= content, new_content, diff
+            end
+
+            module.sh = function(self, command, message)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("sshd_config: " .. message .. ": " .. result.stderr .. result.stdout)
+                end
+            end
+
+            module.dry_run = function(self)
+                local changed, _, diff = self:changes()
+                self:set_result("diff", diff)
+                self.ssh:set_changed(changed)
+            end
+
+            module.run = function(self)
+                local changed, content, diff = self:changes()
+                self:set_result("diff", diff)
+                if changed then
+                    self.ssh:write_remote_file(self.tmp_path, content)
+                    self:sh(self.validate_command, "refusing a config that fails sshd -t")
+                    self:sh(self.install_command, "failed to write " .. self.params.path)
+                    if self.reload_command ~= nil then
+                        self:sh(self.reload_command, "failed to reload sshd")
+                    end
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.cleanup = function(self)
+                self.ssh:cmdq(self.remove_tmp_command)
+            end
+
+            return module
+        })
+        .set_name("sshd_config")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_sshd_config_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(sshd_config(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("options", lua.create_table()?)?;
+        assert!(sshd_config(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        let options = lua.create_table()?;
+        options.set("Match", "User backup")?;
+        params.set("options", options)?;
+        assert!(sshd_config(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        let options = lua.create_table()?;
+        options.set("Banner", "none\nPermitRootLogin yes")?;
+        params.set("options", options)?;
+        assert!(sshd_config(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sshd_config_rewrite() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (content, diff, unchanged) = lua
+            .load(chunk! {
+                local module = komandan.modules.sshd_config({
+                    options = { PermitRootLogin = "no", PasswordAuthentication = false, Port = { 22, 2222 } },
+                    reload = true,
+                })
+                local current = "Include /etc/ssh/sshd_config.d/*.conf\n#PermitRootLogin prohibit-password\nPort 22\nPasswordAuthentication yes\nMatch User backup\n    PasswordAuthentication yes\n"
+                local content, diff = module:rewrite_config(current)
+                local again = module:rewrite_config(content)
+                return content, diff, again == content
+            })
+            .eval::<(String, Vec<String>, bool)>()?;
+        assert_eq!(
+            content,
+            "PasswordAuthentication no\nPermitRootLogin no\nPort 22\nPort 2222\nInclude /etc/ssh/sshd_config.d/*.conf\n#PermitRootLogin prohibit-password\nMatch User backup\n    PasswordAuthentication yes\n"
+        );
+        assert_eq!(
+            diff,
+            [
+                "-PasswordAuthentication yes",
+                "+PasswordAuthentication no",
+                "+PermitRootLogin no",
+                "+Port 2222"
+            ]
+        );
+        assert!(unchanged);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sshd_config_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.sshd_config({
+                    options = { X11Forwarding = false }, path = "/etc/ssh/sshd_config", reload = true, service = "ssh",
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("reload_command")?,
+            "systemctl reload 'ssh'"
+        );
+        assert!(module.get::<String>("validate_command")?.starts_with(
+            "\"$(command -v sshd || echo /usr/sbin/sshd)\" -t -f '/etc/ssh/sshd_config.komandan-"
+        ));
+        assert_eq!(module.get::<Vec<String>>("managed")?, ["x11forwarding"]);
+        Ok(())
+    }
+}
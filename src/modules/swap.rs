@@ -0,0 +1,326 @@
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Swap file created when neither `path` nor `dev` is given.
+const DEFAULT_SWAP_FILE: &str = "/swapfile";
+
+/// Where a `swappiness` setting is persisted.
+const SYSCTL_FILE: &str = "/etc/sysctl.d/99-komandan-swap.conf";
+
+/// The size of a swap file in MiB, from sizes such as `"512M"` or `"2G"`.
+fn size_mib(size: &str) -> mlua::Result<u64> {
+    let invalid = || RuntimeError(format!("Invalid size: '{size}'. Use e.g. '512M' or '2G'"));
+    let (number, factor) = match size.strip_suffix(['G', 'g']) {
+        Some(number) => (number, 1024),
+        None => (size.strip_suffix(['M', 'm']).ok_or_else(invalid)?, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .filter(|number| *number > 0)
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(invalid)
+}
+
+/// Provision swap on the swap file `path` (default `/swapfile`, created
+/// with `size`, e.g. `"2G"`) or on the partition `dev`. The swap is
+/// formatted with `mkswap`, turned on with `swapon` and added to
+/// `/etc/fstab`; a swap file of another size is recreated. A partition is
+/// only formatted when `blkid` finds nothing on it. `swappiness` (0-100) is
+/// applied with `sysctl` and persisted in `/etc/sysctl.d`. `state =
+/// "absent"` turns the swap off, removes its `/etc/fstab` line and deletes
+/// the swap file.
+pub fn swap(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let (path, is_file) = match (
+        params.get::<Option<String>>("path")?,
+        params.get::<Option<String>>("dev")?,
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(RuntimeError(
+                "'path' and 'dev' parameters are mutually exclusive".to_string(),
+            ));
+        }
+        (Some(path), None) => (path, true),
+        (None, Some(dev)) => (dev, false),
+        (None, None) => (DEFAULT_SWAP_FILE.to_string(), true),
+    };
+    if !path.starts_with('/') || path.chars().any(char::is_whitespace) {
+        return Err(RuntimeError(format!(
+            "Invalid swap path: '{path}'. Use an absolute path without spaces"
+        )));
+    }
+    params.set("path", path.as_str())?;
+    let size = match params.get::<Option<String>>("size")? {
+        Some(size) if is_file => Some(size_mib(&size)?),
+        Some(_) => {
+            return Err(RuntimeError(
+                "'size' parameter only applies to swap files, not to 'dev'".to_string(),
+            ));
+        }
+        None if is_file && state == "present" => {
+            return Err(RuntimeError(
+                "'size' parameter is required for a swap file".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let swappiness = params.get::<Option<u8>>("swappiness")?;
+    if swappiness.is_some_and(|swappiness| swappiness > 100) {
+        return Err(RuntimeError(
+            "'swappiness' parameter must be between 0 and 100".to_string(),
+        ));
+    }
+
+    let quoted = escape_shell_value(&path);
+    let create_command = size.map(|mib| {
+        format!(
+            "rm -f {quoted} && {{ fallocate -l {mib}M {quoted} || dd if=/dev/zero of={quoted} bs=1M count={mib} status=none; }} && chmod 600 {quoted} && mkswap {quoted}"
+        )
+    });
+    let size_bytes = size.map(|mib| mib.saturating_mul(1 << 20));
+    let fstab_line = format!("{path} none swap sw 0 0");
+    let fstab_filter = "$1 == p && $3 == \"swap\"";
+    let fstab_check_command = format!(
+        "awk -v p={quoted} '{fstab_filter} {{ found = 1 }} END {{ exit !found }}' /etc/fstab"
+    );
+    let fstab_add_command = format!(
+        "printf '%s\\n' {} >> /etc/fstab",
+        escape_shell_value(&fstab_line)
+    );
+    let fstab_remove_command = format!(
+        "awk -v p={quoted} '!({fstab_filter})' /etc/fstab > /etc/fstab.komandan && cat /etc/fstab.komandan > /etc/fstab && rm -f /etc/fstab.komandan"
+    );
+    let swappiness_command = swappiness.map(|swappiness| {
+        format!(
+            "sysctl -q -w vm.swappiness={swappiness} && printf 'vm.swappiness = %s\\n' {swappiness} > {SYSCTL_FILE}"
+        )
+    });
+    let swappiness_check_command =
+        format!("sysctl -n vm.swappiness && grep -h 'vm.swappiness' {SYSCTL_FILE}");
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "swap" })
+
+            module.params = $params
+            module.is_file = $is_file
+            module.size_bytes = $size_bytes
+            module.swappiness = $swappiness
+            module.size_command = "stat -c %s " .. $quoted
+            module.blkid_command = "blkid -o value -s TYPE " .. $quoted
+            module.active_command = "swapon --show=NAME --noheadings"
+            module.create_command = $create_command
+            module.mkswap_command = "mkswap " .. $quoted
+            module.swapon_command = "swapon " .. $quoted
+            module.swapoff_command = "swapoff " .. $quoted
+            module.delete_command = "rm -f " .. $quoted
+            module.fstab_check_command = $fstab_check_command
+            module.fstab_add_command = $fstab_add_command
+            module.fstab_remove_command = $fstab_remove_command
+            module.swappiness_command = $swappiness_command
+            module.swappiness_check_command = $swappiness_check_command
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("swap: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            -- What the host has: size (swap files), swap (formatted as
+            -- swap), other (another filesystem type), active, fstab and
+            -- swappiness (the current value and the persisted setting).
+            module.current = function(self)
+                self.ssh:requires({ "swapon", "mkswap" })
+                local current = {}
+                local size = self.ssh:cmdq(self.size_command)
+                if size.exit_code == 0 then
+                    current.size = tonumber(size.stdout)
+                end
+                local blkid = self.ssh:cmdq(self.blkid_command)
+                current.swap = blkid.exit_code == 0 and blkid.stdout == "swap"
+                if blkid.exit_code == 0 and blkid.stdout ~= "swap" then
+                    current.other = blkid.stdout
+                end
+                current.active = false
+                for name in string.gmatch(self:sh(self.active_command), "%S+") do
+                    current.active = current.active or name == self.params.path
+                end
+                current.fstab = self.ssh:cmdq(self.fstab_check_command).exit_code == 0
+                current.swappiness = self.ssh:cmdq(self.swappiness_check_command).stdout
+                return current
+            end
+
+            -- The steps to run, in order.
+            module.plan = function(self, current)
+                local steps = {}
+                if self.params.state == "absent" then
+                    if current.active then
+                        table.insert(steps, "swapoff")
+                    end
+                    if current.fstab then
+                        table.insert(steps, "fstab_remove")
+                    end
+                    if self.is_file and current.size ~= nil then
+                        table.insert(steps, "delete")
+                    end
+                    return steps
+                end
+                if self.is_file then
+                    if current.size ~= self.size_bytes then
+                        if current.active then
+                            table.insert(steps, "swapoff")
+                        end
+                        table.insert(steps, "create")
+                        current.active = false
+                    elseif not current.swap then
+                        table.insert(steps, "mkswap")
+                    end
+                elseif current.other ~= nil then
+                    error("swap: " .. self.params.path .. " holds a " .. current.other .. " filesystem, not swap")
+                elseif not current.swap then
+                    table.insert(steps, "mkswap")
+                end
+                if not current.active then
+                    table.insert(steps, "swapon")
+                end
+                if not current.fstab then
+                    table.insert(steps, "fstab_add")
+                end
+                if self.swappiness ~= nil then
+                    local wanted = self.swappiness .. "\nvm.swappiness = " .. self.swappiness
+                    if current.swappiness ~= wanted then
+                        table.insert(steps, "swappiness")
+                    end
+                end
+                return steps
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(#self:plan(self:current()) > 0)
+            end
+
+            module.run = function(self)
+                local steps = self:plan(self:current())
+                for _, step in ipairs(steps) do
+                    self:sh(self[step .. "_command"])
+                end
+                self.ssh:set_changed(#steps > 0)
+            end
+
+            return module
+        })
+        .set_name("swap")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_size_mib() -> mlua::Result<()> {
+        assert_eq!(size_mib("512M")?, 512);
+        assert_eq!(size_mib("2G")?, 2048);
+        for invalid in ["2", "0G", "1.5G", "2T", "G"] {
+            assert!(size_mib(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let result = swap(&lua, lua.create_table()?);
+        assert!(result.is_err_and(|e| e.to_string().contains("'size' parameter is required")));
+
+        let params = lua.create_table()?;
+        params.set("dev", "/dev/sdb2")?;
+        params.set("size", "2G")?;
+        assert!(swap(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("size", "2G")?;
+        params.set("swappiness", 150)?;
+        assert!(swap(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("state", "absent")?;
+        assert!(swap(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_commands() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.swap({ size = "1G", swappiness = 10 })
+            })
+            .eval::<Table>()?;
+        assert!(module.get::<String>("create_command")?.contains(
+            "fallocate -l 1024M '/swapfile' || dd if=/dev/zero of='/swapfile' bs=1M count=1024"
+        ));
+        assert_eq!(
+            module.get::<String>("fstab_add_command")?,
+            "printf '%s\\n' '/swapfile none swap sw 0 0' >> /etc/fstab"
+        );
+        assert_eq!(
+            module.get::<String>("fstab_check_command")?,
+            "awk -v p='/swapfile' '$1 == p && $3 == \"swap\" { found = 1 } END { exit !found }' /etc/fstab"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let plans = lua
+            .load(chunk! {
+                local gib = 1024 * 1024 * 1024
+                local function plan(params, current)
+                    local module = komandan.modules.swap(params)
+                    return table.concat(module:plan(current), ",")
+                end
+                return {
+                    plan({ size = "1G" }, { active = false, fstab = false, swap = false }),
+                    plan({ size = "1G" }, { size = gib, swap = true, active = true, fstab = true }),
+                    plan({ size = "2G" }, { size = gib, swap = true, active = true, fstab = true }),
+                    plan({ size = "1G", swappiness = 10 }, { size = gib, swap = true, active = true, fstab = true, swappiness = "60" }),
+                    plan({ dev = "/dev/sdb2" }, { active = false, fstab = false, swap = false }),
+                    plan({ state = "absent" }, { size = gib, swap = true, active = true, fstab = true }),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            plans,
+            [
+                "create,swapon,fstab_add",
+                "",
+                "swapoff,create,swapon",
+                "swappiness",
+                "mkswap,swapon,fstab_add",
+                "swapoff,fstab_remove,delete",
+            ]
+        );
+        Ok(())
+    }
+}
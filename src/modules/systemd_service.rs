@@ -48,6 +48,8 @@ pub fn systemd_service(lua: &Lua, params: Table) -> mlua::Result<Table> {
                     if enabled == "enabled" then
                         self.ssh:set_changed(true)
                     end
+                elseif self.params.action == "reload" or self.params.action == "restart" then
+                    self.ssh:set_changed(true)
                 end
             end
 
@@ -75,8 +77,10 @@ pub fn systemd_service(lua: &Lua, params: Table) -> mlua::Result<Table> {
                     end
                 elseif self.params.action == "reload" then
                     self.ssh:cmd("systemctl reload " .. self.params.name)
+                    self.ssh:set_changed(true)
                 elseif self.params.action == "restart" then
                     self.ssh:cmd("systemctl restart " .. self.params.name)
+                    self.ssh:set_changed(true)
                 elseif self.params.action == "enable" then
                     local enabled = self.ssh:cmdq("systemctl is-enabled " .. self.params.name).stdout
                     if enabled ~= "enabled" then
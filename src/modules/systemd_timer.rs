@@ -0,0 +1,268 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+
+/// Heredoc delimiter of the written unit files.
+const UNIT_EOF: &str = "KOMANDAN_SYSTEMD_TIMER_EOF";
+
+/// Directory the unit files are written to.
+const UNIT_DIR: &str = "/etc/systemd/system";
+
+/// The `.service` and `.timer` units of the timer `name`.
+fn render_units(name: &str, params: &Table) -> mlua::Result<(String, String)> {
+    let command = params.get::<Option<String>>("command")?.ok_or_else(|| {
+        RuntimeError("'command' parameter is required unless state is 'absent'".to_string())
+    })?;
+    let on_calendar = params
+        .get::<Option<String>>("on_calendar")?
+        .ok_or_else(|| {
+            RuntimeError("'on_calendar' parameter is required unless state is 'absent'".to_string())
+        })?;
+    let description = params
+        .get::<Option<String>>("description")?
+        .unwrap_or_else(|| format!("{name} (managed by komandan)"));
+    let user = params.get::<Option<String>>("user")?;
+    for (key, value) in [
+        ("command", Some(&command)),
+        ("on_calendar", Some(&on_calendar)),
+        ("description", Some(&description)),
+        ("user", user.as_ref()),
+    ] {
+        if value.is_some_and(|value| value.trim().is_empty() || value.contains('\n')) {
+            return Err(RuntimeError(format!(
+                "'{key}' parameter must be a non-empty single line"
+            )));
+        }
+    }
+    let persistent = params.get::<Option<bool>>("persistent")?.unwrap_or(false);
+
+    let mut service = format!("[Unit]\nDescription={description}\n\n[Service]\nType=oneshot\n");
+    if let Some(user) = &user {
+        let _ = writeln!(service, "User={user}");
+    }
+    let _ = writeln!(service, "ExecStart={command}");
+
+    let mut timer =
+        format!("[Unit]\nDescription={description}\n\n[Timer]\nOnCalendar={on_calendar}\n");
+    if persistent {
+        timer.push_str("Persistent=true\n");
+    }
+    timer.push_str("\n[Install]\nWantedBy=timers.target\n");
+    Ok((service, timer))
+}
+
+/// Run `command` on a schedule with a systemd service and timer pair named
+/// `name`, the systemd take on a cron job. `on_calendar` is the schedule in
+/// `OnCalendar=` syntax (e.g. `"daily"` or `"Mon..Fri 03:00"`),
+/// `persistent = true` catches up on runs missed while the host was down,
+/// and `user` is the account the command runs as (default `root`). The
+/// units are written to `/etc/systemd/system` and the timer is enabled and
+/// started; the task only reports a change when a unit file or the timer
+/// state differs. `state = "absent"` stops the timer and removes both units.
+pub fn systemd_timer(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if name.is_empty()
+        || name.starts_with(['-', '.'])
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-@".contains(c))
+    {
+        return Err(RuntimeError(format!("Invalid timer name: '{name}'")));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+    let (service, timer) = if state == "present" {
+        let (service, timer) = render_units(&name, &params)?;
+        (Some(service), Some(timer))
+    } else {
+        (None, None)
+    };
+
+    let service_path = format!("{UNIT_DIR}/{name}.service");
+    let timer_path = format!("{UNIT_DIR}/{name}.timer");
+    let timer_unit = format!("{name}.timer");
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "systemd_timer" })
+
+            module.params = $params
+            module.timer_unit = $timer_unit
+            module.units = {
+                { path = $service_path, content = $service },
+                { path = $timer_path, content = $timer },
+            }
+            module.unit_eof = $UNIT_EOF
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("systemd_timer: " .. command .. " failed: " .. result.stderr)
+                end
+            end
+
+            -- The unit files to write, and whether the timer needs to be
+            -- enabled or started (present) or stopped and removed (absent).
+            module.plan = function(self)
+                self.ssh:requires("systemctl")
+                local write = {}
+                local exists = false
+                for _, unit in ipairs(self.units) do
+                    local current = self.ssh:cmdq("cat " .. unit.path)
+                    exists = exists or current.exit_code == 0
+                    -- cat output comes back without its trailing newline.
+                    if unit.content ~= nil and (current.exit_code ~= 0 or current.stdout .. "\n" ~= unit.content) then
+                        table.insert(write, unit)
+                    end
+                end
+                if self.params.state == "absent" then
+                    return write, exists
+                end
+                local enabled = self.ssh:cmdq("systemctl is-enabled " .. self.timer_unit).stdout == "enabled"
+                local active = self.ssh:cmdq("systemctl is-active " .. self.timer_unit).stdout == "active"
+                return write, not enabled or not active
+            end
+
+            module.dry_run = function(self)
+                local write, timer = self:plan()
+                self.ssh:set_changed(#write > 0 or timer)
+            end
+
+            module.run = function(self)
+                local write, timer = self:plan()
+                if self.params.state == "absent" then
+                    if timer then
+                        self.ssh:cmdq("systemctl disable --now " .. self.timer_unit)
+                        self:sh("rm -f " .. self.units[1].path .. " " .. self.units[2].path)
+                        self:sh("systemctl daemon-reload")
+                    end
+                    self.ssh:set_changed(timer)
+                    return
+                end
+                for _, unit in ipairs(write) do
+                    self:sh("cat > " .. unit.path .. " <<'" .. self.unit_eof .. "'\n" .. unit.content .. self.unit_eof)
+                end
+                if #write > 0 then
+                    self:sh("systemctl daemon-reload")
+                end
+                if timer then
+                    self:sh("systemctl enable --now " .. self.timer_unit)
+                end
+                self.ssh:set_changed(#write > 0 or timer)
+            end
+
+            return module
+        })
+        .set_name("systemd_timer")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_systemd_timer_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(systemd_timer(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "../backup")?;
+        params.set("state", "absent")?;
+        assert!(systemd_timer(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        params.set("command", "/usr/local/bin/backup")?;
+        let result = systemd_timer(&lua, params);
+        assert!(result.is_err_and(|e| e.to_string().contains("'on_calendar' parameter")));
+
+        let params = lua.create_table()?;
+        params.set("name", "backup")?;
+        params.set("command", "/usr/local/bin/backup\nExecStartPost=/bin/id")?;
+        params.set("on_calendar", "daily")?;
+        assert!(systemd_timer(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_systemd_timer_renders_units() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("command", "/usr/local/bin/backup --full")?;
+        params.set("on_calendar", "Mon..Fri 03:00")?;
+        params.set("persistent", true)?;
+        params.set("user", "backup")?;
+        let (service, timer) = render_units("backup", &params)?;
+        assert_eq!(
+            service,
+            "[Unit]\nDescription=backup (managed by komandan)\n\n[Service]\nType=oneshot\nUser=backup\nExecStart=/usr/local/bin/backup --full\n"
+        );
+        assert_eq!(
+            timer,
+            "[Unit]\nDescription=backup (managed by komandan)\n\n[Timer]\nOnCalendar=Mon..Fri 03:00\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_systemd_timer_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let results = lua
+            .load(chunk! {
+                local function plan(params, files, enabled, active)
+                    params.name = "backup"
+                    local module = komandan.modules.systemd_timer(params)
+                    module.ssh = {
+                        requires = function() end,
+                        cmdq = function(_, command)
+                            local path = string.match(command, "^cat (.*)$")
+                            if path ~= nil then
+                                local content = files[path]
+                                return { exit_code = content and 0 or 1, stdout = content or "" }
+                            end
+                            if string.find(command, "is-enabled", 1, true) then
+                                return { exit_code = 0, stdout = enabled }
+                            end
+                            return { exit_code = 0, stdout = active }
+                        end,
+                    }
+                    local write, timer = module:plan()
+                    return { #write, timer and 1 or 0 }
+                end
+                local present = { command = "/usr/local/bin/backup", on_calendar = "daily" }
+                local units = komandan.modules.systemd_timer({ name = "backup", command = present.command, on_calendar = "daily" }).units
+                local current = {
+                    [units[1].path] = string.sub(units[1].content, 1, -2),
+                    [units[2].path] = string.sub(units[2].content, 1, -2),
+                }
+                return {
+                    plan(present, {}, "not-found", "inactive"),
+                    plan(present, current, "enabled", "active"),
+                    plan(present, current, "enabled", "inactive"),
+                    plan({ command = "/usr/local/bin/backup", on_calendar = "hourly" }, current, "enabled", "active"),
+                    plan({ state = "absent" }, current, "enabled", "active"),
+                    plan({ state = "absent" }, {}, "not-found", "inactive"),
+                }
+            })
+            .eval::<Vec<Vec<u32>>>()?;
+        assert_eq!(results, [[2, 1], [0, 0], [0, 1], [1, 0], [0, 1], [0, 0]]);
+        Ok(())
+    }
+}
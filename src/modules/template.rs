@@ -2,6 +2,25 @@ use minijinja::Environment;
 use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
 use rand::{RngExt, distr::Alphanumeric};
 
+/// Render `source` with `vars`, returning the output and its SHA-256.
+///
+/// # Errors
+///
+/// Returns an error if the template is invalid or fails to render.
+pub fn render_template(source: &str, vars: &Value) -> mlua::Result<(String, String)> {
+    let rendered = Environment::new()
+        .template_from_str(source)
+        .map_err(|e| RuntimeError(format!("Failed to add template: {e}")))?
+        .render(minijinja::Value::from_serialize(vars))
+        .map_err(|e| RuntimeError(format!("Failed to render template: {e}")))?;
+    let rendered_sha256 = super::checksum::sha256_hex(rendered.as_bytes());
+    Ok((rendered, rendered_sha256))
+}
+
+/// Render the local template `src` with the task vars and `vars`, and write
+/// it to `dst` on the host. Changed only when the SHA-256 of the rendered
+/// output differs from the remote file's, so a dry run reports the change
+/// exactly without writing anything.
 pub fn template(lua: &Lua, params: Table) -> mlua::Result<Table> {
     let Ok(src) = params.get::<String>("src") else {
         return Err(RuntimeError(String::from("'src' parameter is required")));
@@ -25,16 +44,10 @@ pub fn template(lua: &Lua, params: Table) -> mlua::Result<Table> {
     let src_content = std::fs::read_to_string(&src)
         .map_err(|e| RuntimeError(format!("Failed to read template file: {e}")))?;
 
-    let mut env = Environment::new();
-    env.add_template("template", &src_content)
+    Environment::new()
+        .template_from_str(&src_content)
         .map_err(|e| RuntimeError(format!("Failed to add template: {e}")))?;
 
-    let rendered = env
-        .get_template("template")
-        .map_err(|e| RuntimeError(format!("Failed to get template: {e}")))?
-        .render(minijinja::Value::from_serialize(vars))
-        .map_err(|e| RuntimeError(format!("Failed to render template: {e}")))?;
-
     let random_file_name: String = rand::rng()
         .sample_iter(&Alphanumeric)
         .map(char::from)
@@ -47,10 +60,33 @@ pub fn template(lua: &Lua, params: Table) -> mlua::Result<Table> {
             local module = $base_module:new({ name = "template" })
 
             module.params = $params
-            module.rendered = $rendered
+            module.source = $src_content
             module.random_file_name = $random_file_name
 
+            module.render = function(self)
+                local vars = {}
+                for key, value in pairs(self.vars or {}) do
+                    vars[key] = value
+                end
+                for key, value in pairs(self.params.vars or {}) do
+                    vars[key] = value
+                end
+                self.rendered, self.rendered_sha256 = self.render_template(self.source, vars)
+            end
+
+            module.is_changed = function(self)
+                self:render()
+                return self:remote_sha256(self.params.dst) ~= self.rendered_sha256
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:is_changed())
+            end
+
             module.run = function(self)
+                if not self:is_changed() then
+                    return
+                end
                 local tmpdir = self.ssh:get_tmpdir()
                 local tmpfile = tmpdir .. "/." .. self.random_file_name
                 self.ssh:write_remote_file(tmpfile, self.rendered)
@@ -159,4 +195,30 @@ mod tests {
         assert!(result.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn test_template_render_layers_vars() -> mlua::Result<()> {
+        let mut temp_file = NamedTempFile::new().map_err(mlua::Error::external)?;
+        write!(temp_file, "{{{{ name }}}}:{{{{ port }}}}").map_err(mlua::Error::external)?;
+        let path = temp_file
+            .path()
+            .to_str()
+            .ok_or_else(|| mlua::Error::external("invalid path"))?;
+        let lua = create_lua()?;
+        let (rendered, sha256) = lua
+            .load(chunk! {
+                local module = komandan.modules.template({
+                    src = $path,
+                    dst = "/remote/file",
+                    vars = { port = 8080 },
+                })
+                module.vars = { name = "web", port = 80 }
+                module:render()
+                return module.rendered, module.rendered_sha256
+            })
+            .eval::<(String, String)>()?;
+        assert_eq!(rendered, "web:8080");
+        assert_eq!(sha256, crate::modules::checksum::sha256_hex(b"web:8080"));
+        Ok(())
+    }
 }
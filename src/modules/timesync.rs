@@ -0,0 +1,393 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, chunk};
+use rand::{RngExt, distr::Alphanumeric};
+
+use crate::local::escape_shell_value;
+
+/// Seconds to wait for the clock to synchronize when `sync_timeout` is not
+/// given.
+const DEFAULT_SYNC_TIMEOUT: u32 = 60;
+
+/// Drop-in written for `systemd-timesyncd`.
+const TIMESYNCD_DROP_IN: &str = "/etc/systemd/timesyncd.conf.d/komandan.conf";
+
+/// Configure time synchronization with `provider` `chrony`,
+/// `timesyncd` (`systemd-timesyncd`) or `auto` (default: chrony when
+/// `chronyd` is installed, otherwise timesyncd). `servers` and `pools`
+/// (names or lists) replace the `server` and `pool` lines of the chrony
+/// config (`config_file`, by default `/etc/chrony/chrony.conf` or
+/// `/etc/chrony.conf`, whichever exists; `iburst = false` drops the
+/// `iburst` option), or become `NTP=` of a timesyncd drop-in. Without
+/// them the sources are left alone. The service is enabled, started, and
+/// restarted when its config changes. With `max_offset` (seconds) the task
+/// then waits up to `sync_timeout` seconds (default 60) for the clock to be
+/// synchronized within that offset, and fails otherwise. Sets `provider`,
+/// `diff` and, when checked, `synchronized` and `offset` in the task
+/// result.
+pub fn timesync(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let provider = params
+        .get::<Option<String>>("provider")?
+        .unwrap_or_else(|| "auto".to_string());
+    if !["auto", "chrony", "timesyncd"].contains(&provider.as_str()) {
+        return Err(RuntimeError(format!(
+            "Invalid provider: {provider}. Valid providers are: auto, chrony and timesyncd."
+        )));
+    }
+    params.set("provider", provider.as_str())?;
+    let servers = super::name_list(&params, "servers", ".-:")?;
+    let pools = super::name_list(&params, "pools", ".-:")?;
+    let iburst = if params.get::<Option<bool>>("iburst")?.unwrap_or(true) {
+        " iburst"
+    } else {
+        ""
+    };
+    let max_offset = params.get::<Option<f64>>("max_offset")?;
+    if max_offset.is_some_and(|offset| offset.is_nan() || offset < 0.0) {
+        return Err(RuntimeError(
+            "'max_offset' parameter must be a positive number of seconds".to_string(),
+        ));
+    }
+    let sync_timeout = params
+        .get::<Option<u32>>("sync_timeout")?
+        .unwrap_or(DEFAULT_SYNC_TIMEOUT);
+    params.set("sync_timeout", sync_timeout)?;
+
+    let manage_sources = !servers.is_empty() || !pools.is_empty();
+    let mut chrony_sources = Vec::new();
+    for server in &servers {
+        chrony_sources.push(format!("server {server}{iburst}"));
+    }
+    for pool in &pools {
+        chrony_sources.push(format!("pool {pool}{iburst}"));
+    }
+    let mut timesyncd_content = String::from("# Managed by komandan\n[Time]\n");
+    let _ = writeln!(
+        timesyncd_content,
+        "NTP={}",
+        servers
+            .iter()
+            .chain(&pools)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let config_file = params.get::<Option<String>>("config_file")?;
+    let chrony_config_command = config_file.as_deref().map_or_else(
+        || {
+            "for f in /etc/chrony/chrony.conf /etc/chrony.conf; do [ -f \"$f\" ] && echo \"$f\" && exit 0; done; exit 1"
+                .to_string()
+        },
+        |path| format!("echo {}", escape_shell_value(path)),
+    );
+    let random_file_name: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(10)
+        .collect();
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "timesync" })
+
+            module.params = $params
+            module.manage_sources = $manage_sources
+            module.chrony_sources = $chrony_sources
+            module.timesyncd_content = $timesyncd_content
+            module.timesyncd_drop_in = $TIMESYNCD_DROP_IN
+            module.max_offset = $max_offset
+            module.chrony_config_command = $chrony_config_command
+            module.tmp_suffix = ".komandan-" .. $random_file_name
+            module.detect_command = "command -v chronyd >/dev/null || [ -x /usr/sbin/chronyd ]"
+            module.services = { chrony = "chronyd", timesyncd = "systemd-timesyncd" }
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("timesync: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            module.quote = function(value)
+                return "'" .. string.gsub(value, "'", "'\\''") .. "'"
+            end
+
+            module.provider = function(self)
+                if self.params.provider ~= "auto" then
+                    return self.params.provider
+                end
+                if self.ssh:cmdq(self.detect_command).exit_code == 0 then
+                    return "chrony"
+                end
+                return "timesyncd"
+            end
+
+            -- The chrony config with its server and pool lines replaced by
+            -- the wanted sources, placed where the first source was.
+            -- Returns the new content and the diff lines.
+            module.rewrite_chrony = function(self, content)
+                local wanted = {}
+                for _, line in ipairs(self.chrony_sources) do
+                    wanted[line] = true
+                end
+                local lines = {}
+                local removed = {}
+                local diff = {}
+                local inserted = false
+                for line in string.gmatch(content, "([^\n]*)\n") do
+                    local keyword = string.match(line, "^%s*(%a+)%s")
+                    if keyword == "server" or keyword == "pool" then
+                        if not inserted then
+                            for _, source in ipairs(self.chrony_sources) do
+                                table.insert(lines, source)
+                            end
+                            inserted = true
+                        end
+                        removed[line] = true
+                        if not wanted[line] then
+                            table.insert(diff, "-" .. line)
+                        end
+                    else
+                        table.insert(lines, line)
+                    end
+                end
+                if not inserted then
+                    for _, source in ipairs(self.chrony_sources) do
+                        table.insert(lines, source)
+                    end
+                end
+                for _, source in ipairs(self.chrony_sources) do
+                    if not removed[source] then
+                        table.insert(diff, "+" .. source)
+                    end
+                end
+                return table.concat(lines, "\n") .. "\n", diff
+            end
+
+            -- The config file of the provider with its current and wanted
+            -- content, or nil when the sources are not managed.
+            module.config = function(self, provider)
+                if not self.manage_sources then
+                    return nil
+                end
+                local path = self.timesyncd_drop_in
+                if provider == "chrony" then
+                    path = string.match(self:sh(self.chrony_config_command), "[^\n]+")
+                end
+                local result = self.ssh:cmdq("cat " .. self.quote(path) .. " && printf .")
+                local current = nil
+                if result.exit_code == 0 then
+                    current = string.sub(result.stdout, 1, -2)
+                end
+                if provider == "timesyncd" then
+                    local diff = {}
+                    if current ~= self.timesyncd_content then
+                        diff = { "+NTP=" .. string.match(self.timesyncd_content, "NTP=([^\n]*)") }
+                    end
+                    return { path = path, current = current, content = self.timesyncd_content, diff = diff }
+                end
+                if current == nil then
+                    error("timesync: cannot read " .. path .. ": " .. result.stderr)
+                end
+                if current ~= "" and string.sub(current, -1) ~= "\n" then
+                    current = current .. "\n"
+                end
+                local content, diff = self:rewrite_chrony(current)
+                return { path = path, current = current, content = content, diff = diff }
+            end
+
+            module.service_state = function(self, provider)
+                local service = self.services[provider]
+                local enabled = self.ssh:cmdq("systemctl is-enabled " .. service).exit_code == 0
+                local active = self.ssh:cmdq("systemctl is-active " .. service).exit_code == 0
+                return enabled and active
+            end
+
+            -- Parse the offset in seconds and whether the clock is
+            -- synchronized from chronyc tracking output.
+            module.parse_chrony_tracking = function(output)
+                local leap = string.match(output, "Leap status%s*:%s*([^\n]*)")
+                local seconds, direction = string.match(output, "System time%s*:%s*([%d%.]+) seconds (%a+)")
+                local offset = tonumber(seconds)
+                if offset ~= nil and direction == "slow" then
+                    offset = -offset
+                end
+                return leap ~= nil and leap ~= "Not synchronised", offset
+            end
+
+            -- Parse the offset in seconds from timedatectl timesync-status
+            -- output, e.g. "Offset: -1.234ms".
+            module.parse_timesync_offset = function(output)
+                local number, unit = string.match(output, "Offset:%s*([%+%-]?[%d%.]+)(%a+)")
+                local units = { ns = 1e-9, us = 1e-6, ["µs"] = 1e-6, ms = 1e-3, s = 1, min = 60 }
+                if number == nil then
+                    number, unit = string.match(output, "Offset:%s*([%+%-]?[%d%.]+)(µs)")
+                end
+                if number == nil or units[unit] == nil then
+                    return nil
+                end
+                return tonumber(number) * units[unit]
+            end
+
+            module.sync_status = function(self, provider)
+                if provider == "chrony" then
+                    local result = self.ssh:cmdq("chronyc tracking")
+                    if result.exit_code ~= 0 then
+                        return false, nil
+                    end
+                    return self.parse_chrony_tracking(result.stdout)
+                end
+                local synchronized = self.ssh:cmdq("timedatectl show -p NTPSynchronized --value").stdout == "yes"
+                local result = self.ssh:cmdq("timedatectl timesync-status")
+                return synchronized, self.parse_timesync_offset(result.stdout)
+            end
+
+            -- Wait until the clock is synchronized within max_offset, up to
+            -- sync_timeout seconds.
+            module.check_sync = function(self, provider)
+                local waited = 0
+                while true do
+                    local synchronized, offset = self:sync_status(provider)
+                    local within = synchronized and offset ~= nil and math.abs(offset) <= self.max_offset
+                    if within or waited >= self.params.sync_timeout then
+                        self:set_result("synchronized", synchronized)
+                        self:set_result("offset", offset)
+                        if not within then
+                            error("timesync: clock not synchronized within " .. self.max_offset .. " seconds (synchronized: " .. tostring(synchronized) .. ", offset: " .. tostring(offset) .. ")")
+                        end
+                        return
+                    end
+                    self.ssh:cmdq("sleep 2")
+                    waited = waited + 2
+                end
+            end
+
+            module.dry_run = function(self)
+                local provider = self:provider()
+                self:set_result("provider", provider)
+                local config = self:config(provider)
+                local changed = not self:service_state(provider)
+                if config ~= nil then
+                    self:set_result("diff", config.diff)
+                    changed = changed or config.current ~= config.content
+                end
+                self.ssh:set_changed(changed)
+            end
+
+            module.run = function(self)
+                local provider = self:provider()
+                self:set_result("provider", provider)
+                local service = self.services[provider]
+                local config = self:config(provider)
+                local config_changed = config ~= nil and config.current ~= config.content
+                if config ~= nil then
+                    self:set_result("diff", config.diff)
+                end
+                if config_changed then
+                    local path = self.quote(config.path)
+                    local tmp = self.quote(config.path .. self.tmp_suffix)
+                    self:sh("mkdir -p \"$(dirname " .. path .. ")\"")
+                    self.ssh:write_remote_file(config.path .. self.tmp_suffix, config.content)
+                    self:sh("{ [ ! -e " .. path .. " ] || chmod --reference=" .. path .. " " .. tmp .. "; } && mv -f " .. tmp .. " " .. path)
+                end
+                local service_changed = not self:service_state(provider)
+                if service_changed then
+                    if provider == "timesyncd" then
+                        self.ssh:cmdq("timedatectl set-ntp true")
+                    end
+                    self:sh("systemctl enable --now " .. service)
+                end
+                if config_changed then
+                    self:sh("systemctl restart " .. service)
+                end
+                if self.max_offset ~= nil then
+                    self:check_sync(provider)
+                end
+                self.ssh:set_changed(config_changed or service_changed)
+            end
+
+            return module
+        })
+        .set_name("timesync")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_timesync_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let params = lua.create_table()?;
+        params.set("provider", "ntpd")?;
+        assert!(timesync(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("servers", "ntp.example.com; reboot")?;
+        assert!(timesync(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("max_offset", -1)?;
+        assert!(timesync(&lua, params).is_err());
+
+        assert!(timesync(&lua, lua.create_table()?).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timesync_rewrite_chrony() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (content, diff, timesyncd) = lua
+            .load(chunk! {
+                local module = komandan.modules.timesync({ servers = "ntp1.example.com", pools = { "pool.ntp.org" } })
+                local current = "# chrony\npool 2.debian.pool.ntp.org iburst\nserver ntp1.example.com iburst\ndriftfile /var/lib/chrony/chrony.drift\n"
+                local content, diff = module:rewrite_chrony(current)
+                return content, diff, module.timesyncd_content
+            })
+            .eval::<(String, Vec<String>, String)>()?;
+        assert_eq!(
+            content,
+            "# chrony\nserver ntp1.example.com iburst\npool pool.ntp.org iburst\ndriftfile /var/lib/chrony/chrony.drift\n"
+        );
+        assert_eq!(
+            diff,
+            [
+                "-pool 2.debian.pool.ntp.org iburst",
+                "+pool pool.ntp.org iburst"
+            ]
+        );
+        assert_eq!(
+            timesyncd,
+            "# Managed by komandan\n[Time]\nNTP=ntp1.example.com pool.ntp.org\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_timesync_parse_status() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (synchronized, offset, unsynchronized, timesync_offset) = lua
+            .load(chunk! {
+                local module = komandan.modules.timesync({})
+                local synchronized, offset = module.parse_chrony_tracking("Reference ID    : C0A80001 (ntp1)\nLeap status     : Normal\nSystem time     : 0.000250000 seconds slow of NTP time\n")
+                local unsynchronized = module.parse_chrony_tracking("Leap status     : Not synchronised\n")
+                return synchronized, offset, unsynchronized, module.parse_timesync_offset("  Server: 192.0.2.1\n  Offset: +1.500ms\n")
+            })
+            .eval::<(bool, f64, bool, f64)>()?;
+        assert!(synchronized);
+        assert!((offset + 0.000_25).abs() < 1e-12);
+        assert!(!unsynchronized);
+        assert!((timesync_offset - 0.0015).abs() < 1e-12);
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 use mlua::{ExternalResult, Lua, Table, chunk};
 
+/// Upload the local file or directory `src` to `dst` on the host. The
+/// transfer is skipped, and the task reported unchanged, when the SHA-256 of
+/// `src` matches the remote copy; a dry run compares the checksums only.
 pub fn upload(lua: &Lua, params: Table) -> mlua::Result<Table> {
     let base_module = super::base_module(lua)?;
     let module = lua
@@ -8,9 +11,23 @@ pub fn upload(lua: &Lua, params: Table) -> mlua::Result<Table> {
 
             module.params = $params
 
+            module.is_changed = function(self)
+                local wanted = self.local_sha256(self.params.src)
+                if wanted == nil then
+                    error("src does not exist: " .. self.params.src)
+                end
+                return self.checksums_differ(wanted, self:remote_sha256(self.params.dst))
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:is_changed())
+            end
+
             module.run = function(self)
-                self.ssh:upload(self.params.src, self.params.dst)
-                self.ssh:set_changed(true)
+                if self:is_changed() then
+                    self.ssh:upload(self.params.src, self.params.dst)
+                    self.ssh:set_changed(true)
+                end
             end
 
             return module
@@ -39,4 +56,29 @@ mod tests {
         assert!(result.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn test_upload_unchanged_when_identical() -> mlua::Result<()> {
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let src = dir.path().join("src.conf");
+        std::fs::write(&src, "listen 80\n").map_err(mlua::Error::external)?;
+        let src = src.to_string_lossy().to_string();
+        let dst = dir.path().join("dst.conf").to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = || -> mlua::Result<bool> {
+            let params = lua.create_table()?;
+            params.set("src", src.as_str())?;
+            params.set("dst", dst.as_str())?;
+            let task = lua.create_table()?;
+            task.set(1, upload(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))?
+                .get::<bool>("changed")
+        };
+        assert!(run()?);
+        assert!(!run()?);
+        Ok(())
+    }
 }
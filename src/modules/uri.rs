@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, LuaSerdeExt, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Seconds a request may take when `timeout` is not given.
+const DEFAULT_TIMEOUT: u32 = 30;
+
+/// The accepted status codes: `status_code` as a number or a list, 200
+/// when not given.
+fn status_codes(lua: &Lua, value: Value) -> mlua::Result<Vec<u16>> {
+    let codes = match value {
+        Value::Nil => vec![200],
+        Value::Integer(_) | Value::Number(_) | Value::String(_) => vec![lua.unpack(value)?],
+        Value::Table(codes) => codes
+            .sequence_values::<u16>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(
+                "'status_code' parameter must be a number or a list of numbers".to_string(),
+            ));
+        }
+    };
+    if codes.is_empty() || codes.iter().any(|code| !(100..=599).contains(code)) {
+        return Err(RuntimeError(format!(
+            "Invalid status_code: {codes:?}. Use HTTP status codes"
+        )));
+    }
+    Ok(codes)
+}
+
+/// Send an HTTP request with `curl` from the host itself, e.g. a health
+/// check or an API registration that must run where the service lives.
+/// `method` defaults to `GET`; `headers` is a table of header names to
+/// values. The request `body` is a string, or a table sent as JSON with
+/// `Content-Type: application/json`. The response status must be one of
+/// `status_code` (a number or a list, default 200) or the task fails.
+/// `timeout` is in seconds (default 30), redirects are followed unless
+/// `follow_redirects = false`, and `validate_certs = false` accepts any
+/// TLS certificate. Sets `status`, `content` (the response body) and, when
+/// the body is JSON, `json` in the task result. Only `GET` and `HEAD`
+/// requests are sent in a dry run; other methods report a change, so use
+/// `changed_when` to refine it.
+pub fn uri(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let url = params
+        .get::<Option<String>>("url")?
+        .ok_or_else(|| RuntimeError("'url' parameter is required".to_string()))?;
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(RuntimeError(format!(
+            "Invalid url: '{url}'. Use an http:// or https:// URL"
+        )));
+    }
+    let method = params
+        .get::<Option<String>>("method")?
+        .unwrap_or_else(|| "GET".to_string())
+        .to_uppercase();
+    if method.is_empty() || !method.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(RuntimeError(format!("Invalid method: '{method}'")));
+    }
+    params.set("method", method.as_str())?;
+    let mut headers = params
+        .get::<Option<BTreeMap<String, String>>>("headers")?
+        .unwrap_or_default();
+    let body = match params.get::<Value>("body")? {
+        Value::Nil => None,
+        Value::String(body) => Some(body.to_str()?.to_string()),
+        body @ Value::Table(_) => {
+            let body = lua.from_value::<serde_json::Value>(body)?;
+            if !headers
+                .keys()
+                .any(|name| name.eq_ignore_ascii_case("content-type"))
+            {
+                headers.insert("Content-Type".to_string(), "application/json".to_string());
+            }
+            Some(body.to_string())
+        }
+        _ => {
+            return Err(RuntimeError(
+                "'body' parameter must be a string or a table".to_string(),
+            ));
+        }
+    };
+    let status_codes = status_codes(lua, params.get::<Value>("status_code")?)?;
+    let timeout = params
+        .get::<Option<u32>>("timeout")?
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let follow_redirects = params
+        .get::<Option<bool>>("follow_redirects")?
+        .unwrap_or(true);
+    let validate_certs = params
+        .get::<Option<bool>>("validate_certs")?
+        .unwrap_or(true);
+
+    let mut command = String::new();
+    if let Some(body) = &body {
+        let _ = write!(command, "printf '%s' {} | ", escape_shell_value(body));
+    }
+    // HEAD needs -I, so curl does not wait for a body.
+    let method_option = if method == "HEAD" {
+        "-I".to_string()
+    } else {
+        format!("-X {method}")
+    };
+    let _ = write!(
+        command,
+        "curl -sS {method_option} --max-time {timeout} -w '\\n%{{http_code}}'"
+    );
+    if follow_redirects {
+        command.push_str(" -L");
+    }
+    if !validate_certs {
+        command.push_str(" -k");
+    }
+    for (name, value) in &headers {
+        let _ = write!(
+            command,
+            " -H {}",
+            escape_shell_value(&format!("{name}: {value}"))
+        );
+    }
+    if body.is_some() {
+        command.push_str(" --data-binary @-");
+    }
+    let _ = write!(command, " {}", escape_shell_value(&url));
+    let sends_changes = method != "GET" && method != "HEAD";
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "uri" })
+
+            module.params = $params
+            module.command = $command
+            module.status_codes = $status_codes
+            module.sends_changes = $sends_changes
+
+            -- Split the curl output into the body and the status code, which
+            -- curl prints on a line of its own after the body.
+            module.parse_response = function(output)
+                local body, status = string.match(output, "^(.*)\n(%d+)$")
+                if body == nil then
+                    body, status = "", string.match(output, "^(%d+)$")
+                end
+                return body, tonumber(status)
+            end
+
+            module.request = function(self)
+                self.ssh:requires("curl")
+                local result = self.ssh:cmdq(self.command)
+                if result.exit_code ~= 0 then
+                    error("uri: " .. self.params.method .. " " .. self.params.url .. " failed: " .. result.stderr)
+                end
+                local body, status = self.parse_response(result.stdout)
+                self:set_result("status", status)
+                self:set_result("content", body)
+                self:set_result("json", self.decode_json(body))
+                for _, code in ipairs(self.status_codes) do
+                    if status == code then
+                        return
+                    end
+                end
+                error("uri: " .. self.params.method .. " " .. self.params.url .. " returned status " .. tostring(status) .. ", expected " .. table.concat(self.status_codes, " or "))
+            end
+
+            module.dry_run = function(self)
+                if not self.sends_changes then
+                    self:request()
+                end
+                self.ssh:set_changed(self.sends_changes)
+            end
+
+            module.run = function(self)
+                self:request()
+                self.ssh:set_changed(self.sends_changes)
+            end
+
+            return module
+        })
+        .set_name("uri")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_status_codes() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert_eq!(status_codes(&lua, Value::Nil)?, [200]);
+        assert_eq!(status_codes(&lua, Value::Integer(204))?, [204]);
+        let codes = lua.create_sequence_from([200, 201])?;
+        assert_eq!(status_codes(&lua, Value::Table(codes))?, [200, 201]);
+        assert!(status_codes(&lua, Value::Integer(42)).is_err());
+        assert!(status_codes(&lua, Value::Boolean(true)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_uri_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(uri(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("url", "ftp://example.com")?;
+        assert!(uri(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("url", "http://localhost:8080/health")?;
+        params.set("method", "GET; id")?;
+        assert!(uri(&lua, params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_uri_command_and_response() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let (command, body, status, empty_status) = lua
+            .load(chunk! {
+                local module = komandan.modules.uri({
+                    url = "http://localhost:8500/v1/agent/service/register", method = "put",
+                    headers = { ["X-Consul-Token"] = "secret" }, body = { name = "web" },
+                    follow_redirects = false,
+                })
+                local body, status = module.parse_response("{\"ok\": true}\n\n200")
+                local _, empty_status = module.parse_response("204")
+                return module.command, body, status, empty_status
+            })
+            .eval::<(String, String, u16, u16)>()?;
+        assert_eq!(
+            command,
+            "printf '%s' '{\"name\":\"web\"}' | curl -sS -X PUT --max-time 30 -w '\\n%{http_code}' -H 'Content-Type: application/json' -H 'X-Consul-Token: secret' --data-binary @- 'http://localhost:8500/v1/agent/service/register'"
+        );
+        assert_eq!(body, "{\"ok\": true}\n");
+        assert_eq!(status, 200);
+        assert_eq!(empty_status, 204);
+        Ok(())
+    }
+}
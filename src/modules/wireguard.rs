@@ -0,0 +1,456 @@
+use std::fmt::Write as _;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Whether `key` looks like a WireGuard key: 32 bytes in base64.
+fn is_key(key: &str) -> bool {
+    key.len() == 44
+        && key.ends_with('=')
+        && key
+            .bytes()
+            .take(43)
+            .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/')
+}
+
+/// The `key` field of `table` as a comma separated list: a string or a
+/// list of strings.
+fn address_list(table: &Table, key: &str) -> mlua::Result<Option<String>> {
+    let values = match table.get::<Value>(key)? {
+        Value::Nil => return Ok(None),
+        Value::String(value) => vec![value.to_str()?.to_string()],
+        Value::Table(values) => values
+            .sequence_values::<String>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(format!(
+                "'{key}' must be a string or a list of strings"
+            )));
+        }
+    };
+    if values.is_empty()
+        || values.iter().any(|value| {
+            value.is_empty()
+                || !value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".:/-".contains(c))
+        })
+    {
+        return Err(RuntimeError(format!("Invalid '{key}': {values:?}")));
+    }
+    Ok(Some(values.join(", ")))
+}
+
+/// Render the `[Peer]` sections of `peers` and return them with the
+/// public keys of the peers.
+fn render_peers(peers: Option<&Table>) -> mlua::Result<(String, Vec<String>)> {
+    let mut content = String::new();
+    let mut public_keys = Vec::new();
+    let Some(peers) = peers else {
+        return Ok((content, public_keys));
+    };
+    for peer in peers.sequence_values::<Table>() {
+        let peer = peer?;
+        let public_key = peer
+            .get::<Option<String>>("public_key")?
+            .ok_or_else(|| RuntimeError("every peer needs a 'public_key'".to_string()))?;
+        if !is_key(&public_key) {
+            return Err(RuntimeError(format!(
+                "Invalid peer public_key: '{public_key}'"
+            )));
+        }
+        let _ = write!(content, "\n[Peer]\nPublicKey = {public_key}\n");
+        if let Some(preshared_key) = peer.get::<Option<String>>("preshared_key")? {
+            if !is_key(&preshared_key) {
+                return Err(RuntimeError(format!(
+                    "Invalid preshared_key of peer {public_key}"
+                )));
+            }
+            let _ = writeln!(content, "PresharedKey = {preshared_key}");
+        }
+        let allowed_ips = address_list(&peer, "allowed_ips")?
+            .ok_or_else(|| RuntimeError(format!("peer {public_key} needs 'allowed_ips'")))?;
+        let _ = writeln!(content, "AllowedIPs = {allowed_ips}");
+        if let Some(endpoint) = peer.get::<Option<String>>("endpoint")? {
+            if endpoint.is_empty() || endpoint.contains(char::is_whitespace) {
+                return Err(RuntimeError(format!("Invalid endpoint: '{endpoint}'")));
+            }
+            let _ = writeln!(content, "Endpoint = {endpoint}");
+        }
+        if let Some(keepalive) = peer.get::<Option<u16>>("persistent_keepalive")? {
+            let _ = writeln!(content, "PersistentKeepalive = {keepalive}");
+        }
+        public_keys.push(public_key);
+    }
+    Ok((content, public_keys))
+}
+
+/// Manage the WireGuard interface `name` (e.g. `wg0`) with `wg-quick`. The
+/// config `/etc/wireguard/<name>.conf` is rendered from `address` (a CIDR
+/// or a list), `listen_port`, `dns`, `mtu` and `peers`, a list of
+/// `{ public_key = "...", allowed_ips = "10.0.0.2/32", endpoint =
+/// "host:51820", persistent_keepalive = 25, preshared_key = "..." }`
+/// tables. Without `private_key`, a key is generated once on the host in
+/// `/etc/wireguard/<name>.key` and loaded by the config, so it never leaves
+/// the host. `state` is `up` (default), `down` or `absent` (the interface
+/// is brought down and its config and key removed); `enabled` starts the
+/// interface at boot with `wg-quick@<name>`. A running interface gets peer
+/// changes with `wg syncconf` and is restarted when the `[Interface]`
+/// section changes; peers or a listen port differing from `wg show` are
+/// reported as changes too. Sets `public_key` in the task result.
+pub fn wireguard(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let name = params
+        .get::<Option<String>>("name")?
+        .ok_or_else(|| RuntimeError("'name' parameter is required".to_string()))?;
+    if name.is_empty()
+        || name.len() > 15
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c))
+    {
+        return Err(RuntimeError(format!(
+            "Invalid interface name: '{name}'. Use up to 15 letters, digits, '_' and '-'"
+        )));
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "up".to_string());
+    if !["up", "down", "absent"].contains(&state.as_str()) {
+        return Err(RuntimeError(
+            "'state' parameter must be 'up', 'down' or 'absent'".to_string(),
+        ));
+    }
+    params.set("state", state.as_str())?;
+
+    let conf_path = format!("/etc/wireguard/{name}.conf");
+    let key_path = format!("/etc/wireguard/{name}.key");
+    let private_key = params.get::<Option<String>>("private_key")?;
+    if private_key.as_deref().is_some_and(|key| !is_key(key)) {
+        return Err(RuntimeError("Invalid private_key".to_string()));
+    }
+    let listen_port = params.get::<Option<u16>>("listen_port")?;
+
+    let mut interface = String::from("# Managed by komandan\n[Interface]\n");
+    if let Some(address) = address_list(&params, "address")? {
+        let _ = writeln!(interface, "Address = {address}");
+    }
+    if let Some(port) = listen_port {
+        let _ = writeln!(interface, "ListenPort = {port}");
+    }
+    match &private_key {
+        Some(key) => {
+            let _ = writeln!(interface, "PrivateKey = {key}");
+        }
+        None => {
+            let _ = writeln!(interface, "PostUp = wg set %i private-key {key_path}");
+        }
+    }
+    if let Some(dns) = address_list(&params, "dns")? {
+        let _ = writeln!(interface, "DNS = {dns}");
+    }
+    if let Some(mtu) = params.get::<Option<u16>>("mtu")? {
+        let _ = writeln!(interface, "MTU = {mtu}");
+    }
+    let (peers, mut public_keys) = render_peers(params.get::<Option<Table>>("peers")?.as_ref())?;
+    public_keys.sort();
+    let content = format!("{interface}{peers}");
+
+    let generate_key = private_key.is_none();
+    let quoted_name = escape_shell_value(&name);
+    let public_key_command = private_key.as_deref().map_or_else(
+        || format!("wg pubkey < {key_path}"),
+        |key| format!("printf '%s' {} | wg pubkey", escape_shell_value(key)),
+    );
+    let sync_command = format!(
+        "bash -c 'wg syncconf \"$1\" <(wg-quick strip \"$1\"){}' sh {quoted_name}",
+        if generate_key {
+            format!(" && wg set \"$1\" private-key {key_path}")
+        } else {
+            String::new()
+        }
+    );
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "wireguard" })
+
+            module.params = $params
+            module.content = $content
+            module.interface = $interface
+            module.public_keys = $public_keys
+            module.listen_port = $listen_port
+            module.generate_key = $generate_key
+            module.conf_path = $conf_path
+            module.read_command = "cat " .. $conf_path .. " && printf ."
+            module.key_exists_command = "[ -f " .. $key_path .. " ]"
+            module.genkey_command = "mkdir -p /etc/wireguard && (umask 077 && wg genkey > " .. $key_path .. ")"
+            module.public_key_command = $public_key_command
+            module.is_up_command = "ip link show dev " .. $quoted_name
+            module.peers_command = "wg show " .. $quoted_name .. " peers"
+            module.listen_port_command = "wg show " .. $quoted_name .. " listen-port"
+            module.up_command = "wg-quick up " .. $quoted_name
+            module.down_command = "wg-quick down " .. $quoted_name
+            module.sync_command = $sync_command
+            module.remove_command = "rm -f " .. $conf_path .. " " .. $key_path
+            module.is_enabled_command = "systemctl is-enabled wg-quick@" .. $quoted_name
+            module.enable_command = "systemctl enable wg-quick@" .. $quoted_name
+            module.disable_command = "systemctl disable wg-quick@" .. $quoted_name
+
+            module.sh = function(self, command)
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("wireguard: " .. command .. " failed: " .. result.stderr)
+                end
+                return result.stdout
+            end
+
+            module.current_config = function(self)
+                local result = self.ssh:cmdq(self.read_command)
+                if result.exit_code ~= 0 then
+                    return nil
+                end
+                return string.sub(result.stdout, 1, -2)
+            end
+
+            module.is_up = function(self)
+                return self.ssh:cmdq(self.is_up_command).exit_code == 0
+            end
+
+            -- Whether the running interface has other peers or another
+            -- listen port than the config.
+            module.runtime_differs = function(self)
+                local peers = {}
+                for key in string.gmatch(self:sh(self.peers_command), "%S+") do
+                    table.insert(peers, key)
+                end
+                table.sort(peers)
+                if table.concat(peers, " ") ~= table.concat(self.public_keys, " ") then
+                    return true
+                end
+                if self.listen_port ~= nil then
+                    local port = string.match(self:sh(self.listen_port_command), "%d+")
+                    return tonumber(port) ~= self.listen_port
+                end
+                return false
+            end
+
+            -- Whether the interface section of the current config differs,
+            -- which syncconf cannot apply.
+            module.interface_changed = function(self, current)
+                local interface = string.match(current, "^(.-\n)\n%[Peer%]") or current
+                return interface ~= self.interface
+            end
+
+            -- The changes to make, as a table of flags.
+            module.plan = function(self)
+                self.ssh:requires({ "wg", "wg-quick" })
+                local current = self:current_config()
+                local up = self:is_up()
+                local plan = {}
+                if self.params.state == "absent" then
+                    plan.down = up
+                    plan.remove = current ~= nil or self.ssh:cmdq(self.key_exists_command).exit_code == 0
+                    return plan
+                end
+                plan.genkey = self.generate_key and self.ssh:cmdq(self.key_exists_command).exit_code ~= 0
+                plan.write = current ~= self.content
+                if self.params.enabled ~= nil then
+                    local enabled = self.ssh:cmdq(self.is_enabled_command).exit_code == 0
+                    plan.enable = self.params.enabled and not enabled
+                    plan.disable = not self.params.enabled and enabled
+                end
+                if self.params.state == "down" then
+                    plan.down = up
+                elseif not up then
+                    plan.up = true
+                elseif current == nil or self:interface_changed(current) or plan.genkey then
+                    plan.restart = true
+                elseif plan.write or self:runtime_differs() then
+                    plan.sync = true
+                end
+                return plan
+            end
+
+            module.any = function(plan)
+                for _, value in pairs(plan) do
+                    if value then
+                        return true
+                    end
+                end
+                return false
+            end
+
+            module.set_public_key = function(self)
+                if not self.generate_key or self.ssh:cmdq(self.key_exists_command).exit_code == 0 then
+                    self:set_result("public_key", string.match(self:sh(self.public_key_command), "%S+"))
+                end
+            end
+
+            module.dry_run = function(self)
+                local plan = self:plan()
+                if self.params.state ~= "absent" then
+                    self:set_public_key()
+                end
+                self.ssh:set_changed(self.any(plan))
+            end
+
+            module.run = function(self)
+                local plan = self:plan()
+                if plan.down or plan.restart then
+                    self:sh(self.down_command)
+                end
+                if plan.remove then
+                    self:sh(self.remove_command)
+                end
+                if plan.genkey then
+                    self:sh(self.genkey_command)
+                end
+                if plan.write then
+                    self:sh("mkdir -p /etc/wireguard && chmod 700 /etc/wireguard")
+                    self.ssh:write_remote_file(self.conf_path, self.content)
+                    self:sh("chmod 600 " .. self.conf_path)
+                end
+                if plan.enable then
+                    self:sh(self.enable_command)
+                end
+                if plan.disable then
+                    self:sh(self.disable_command)
+                end
+                if plan.up or plan.restart then
+                    self:sh(self.up_command)
+                end
+                if plan.sync then
+                    self:sh(self.sync_command)
+                end
+                if self.params.state ~= "absent" then
+                    self:set_public_key()
+                end
+                self.ssh:set_changed(self.any(plan))
+            end
+
+            return module
+        })
+        .set_name("wireguard")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    const PEER_KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    #[test]
+    fn test_wireguard_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(wireguard(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "wg0; reboot")?;
+        assert!(wireguard(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "wg0")?;
+        params.set("private_key", "secret")?;
+        assert!(wireguard(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("name", "wg0")?;
+        let peer = lua.create_table()?;
+        peer.set("public_key", PEER_KEY)?;
+        params.set("peers", lua.create_sequence_from([peer])?)?;
+        assert!(
+            wireguard(&lua, params).is_err_and(|e| e.to_string().contains("needs 'allowed_ips'"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wireguard_renders_config() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let peer_key = PEER_KEY;
+        let module = lua
+            .load(chunk! {
+                return komandan.modules.wireguard({
+                    name = "wg0", address = "10.8.0.1/24", listen_port = 51820,
+                    peers = {
+                        { public_key = $peer_key, allowed_ips = { "10.8.0.2/32", "fd00::2/128" }, endpoint = "peer.example.com:51820", persistent_keepalive = 25 },
+                    },
+                })
+            })
+            .eval::<Table>()?;
+        assert_eq!(
+            module.get::<String>("content")?,
+            format!(
+                "# Managed by komandan\n[Interface]\nAddress = 10.8.0.1/24\nListenPort = 51820\nPostUp = wg set %i private-key /etc/wireguard/wg0.key\n\n[Peer]\nPublicKey = {PEER_KEY}\nAllowedIPs = 10.8.0.2/32, fd00::2/128\nEndpoint = peer.example.com:51820\nPersistentKeepalive = 25\n"
+            )
+        );
+        assert_eq!(
+            module.get::<String>("sync_command")?,
+            "bash -c 'wg syncconf \"$1\" <(wg-quick strip \"$1\") && wg set \"$1\" private-key /etc/wireguard/wg0.key' sh 'wg0'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wireguard_plan() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let peer_key = PEER_KEY;
+        let plans = lua
+            .load(chunk! {
+                local function plan(current, up, peers)
+                    local module = komandan.modules.wireguard({
+                        name = "wg0", address = "10.8.0.1/24",
+                        peers = { { public_key = $peer_key, allowed_ips = "10.8.0.2/32" } },
+                    })
+                    if current == "same" then
+                        current = module.content
+                    elseif current == "peers" then
+                        current = module.interface .. "\n[Peer]\nPublicKey = other\n"
+                    end
+                    module.ssh = {
+                        requires = function() end,
+                        cmdq = function(_, command)
+                            if command == module.read_command then
+                                return { exit_code = current and 0 or 1, stdout = (current or "") .. ".", stderr = "" }
+                            elseif command == module.is_up_command then
+                                return { exit_code = up and 0 or 1, stdout = "", stderr = "" }
+                            elseif command == module.peers_command then
+                                return { exit_code = 0, stdout = peers, stderr = "" }
+                            end
+                            return { exit_code = 0, stdout = "", stderr = "" }
+                        end,
+                    }
+                    local result = {}
+                    for name, value in pairs(module:plan()) do
+                        if value then
+                            table.insert(result, name)
+                        end
+                    end
+                    table.sort(result)
+                    return table.concat(result, ",")
+                end
+                return {
+                    plan(nil, false, ""),
+                    plan("same", true, $peer_key),
+                    plan("same", true, ""),
+                    plan("peers", true, "other"),
+                    plan("# old\n[Interface]\nAddress = 10.9.0.1/24\n", true, $peer_key),
+                }
+            })
+            .eval::<Vec<String>>()?;
+        assert_eq!(
+            plans,
+            ["up,write", "", "sync", "sync,write", "restart,write"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+
+use mlua::{Error::RuntimeError, ExternalResult, Lua, Table, Value, chunk};
+
+use crate::local::escape_shell_value;
+
+/// Python program applying the edit described by the JSON in `argv[1]` with
+/// `xml.etree.ElementTree`. Prints `changed=<0|1> matches=<n>`; with
+/// `check` set the file is left alone. The edited document is parsed again
+/// before it replaces the file, keeping the mode and owner of the file.
+const XML_SCRIPT: &str = r#"import copy, json, os, sys, tempfile
+import xml.etree.ElementTree as ET
+
+def local_name(tag):
+    return tag.rsplit("}", 1)[-1] if isinstance(tag, str) else tag
+
+def find(root, xpath, namespaces):
+    if xpath.startswith("//"):
+        return root.findall("." + xpath, namespaces)
+    if xpath.startswith("/"):
+        first, _, rest = xpath[1:].partition("/")
+        name = local_name(first.split(":")[-1])
+        if first != "*" and name != local_name(root.tag):
+            return []
+        return root.findall("./" + rest, namespaces) if rest else [root]
+    return root.findall(xpath, namespaces)
+
+def same(a, b):
+    return (a.tag == b.tag and a.attrib == b.attrib
+            and (a.text or "").strip() == (b.text or "").strip()
+            and len(a) == len(b) and all(same(x, y) for x, y in zip(a, b)))
+
+def append(parent, child):
+    if len(parent) > 0:
+        last = parent[-1]
+        child.tail = last.tail
+        last.tail = parent[-2].tail if len(parent) > 1 else parent.text
+    parent.append(child)
+
+try:
+    spec = json.loads(sys.argv[1])
+    path = spec["path"]
+    for prefix, uri in spec["namespaces"].items():
+        ET.register_namespace(prefix, uri)
+    with open(path, "rb") as f:
+        original = f.read()
+    builder = ET.TreeBuilder(insert_comments=True, insert_pis=True)
+    root = ET.fromstring(original, ET.XMLParser(target=builder))
+    matches = find(root, spec["xpath"], spec["namespaces"])
+    parents = {child: parent for parent in root.iter() for child in parent}
+    attribute = spec.get("attribute")
+    value = spec.get("value")
+    changed = False
+    if spec["state"] == "absent":
+        for element in matches:
+            if attribute is not None:
+                if attribute in element.attrib:
+                    del element.attrib[attribute]
+                    changed = True
+            elif element is root:
+                raise ValueError("cannot remove the root element")
+            else:
+                parents[element].remove(element)
+                changed = True
+    else:
+        if not matches:
+            raise ValueError("xpath " + spec["xpath"] + " matches no element")
+        children = [ET.fromstring(fragment) for fragment in spec["add_children"]]
+        for element in matches:
+            if attribute is not None and value is not None:
+                if element.get(attribute) != value:
+                    element.set(attribute, value)
+                    changed = True
+            elif value is not None and (element.text or "") != value:
+                element.text = value
+                changed = True
+            for child in children:
+                if not any(same(child, existing) for existing in element):
+                    append(element, copy.deepcopy(child))
+                    changed = True
+    if changed and not spec["check"]:
+        declaration = original.lstrip().startswith(b"<?xml")
+        edited = ET.tostring(root, encoding="utf-8", xml_declaration=declaration)
+        ET.fromstring(edited)
+        fd, tmp = tempfile.mkstemp(dir=os.path.dirname(os.path.abspath(path)), prefix=".komandan-")
+        with os.fdopen(fd, "wb") as f:
+            f.write(edited if edited.endswith(b"\n") else edited + b"\n")
+        stat = os.stat(path)
+        os.chmod(tmp, stat.st_mode & 0o7777)
+        try:
+            os.chown(tmp, stat.st_uid, stat.st_gid)
+        except OSError:
+            pass
+        os.replace(tmp, path)
+    print("changed=%d matches=%d" % (changed, len(matches)))
+except Exception as e:
+    sys.stderr.write("%s\n" % e)
+    sys.exit(1)
+"#;
+
+/// Edit the XML file `path` on the host, e.g. a Tomcat `server.xml`, on
+/// the elements matched by `xpath`. `xpath` is the subset of `XPath` that
+/// Python's `ElementTree` supports, evaluated from the root element (e.g.
+/// `"./Service/Connector[@port='8080']"` or `".//Connector"`); a path
+/// starting at the root element such as `"/Server/Service"` works too.
+/// `namespaces` maps prefixes used in `xpath` to namespace URIs. `attribute`
+/// with `value` sets an attribute, `value` alone sets the text, and
+/// `add_children` (an XML fragment or a list of them) appends children an
+/// element does not have yet. `state = "absent"` removes the matched
+/// elements, or only `attribute` from them. The file is rewritten only when
+/// it changes, and the edited document is parsed again first. Needs
+/// `python3` on the host; sets `matches` (the number of matched elements)
+/// in the task result.
+pub fn xml(lua: &Lua, params: Table) -> mlua::Result<Table> {
+    let base_module = super::base_module(lua)?;
+    let mut required = Vec::with_capacity(2);
+    for name in ["path", "xpath"] {
+        required.push(
+            params
+                .get::<Option<String>>(name)?
+                .ok_or_else(|| RuntimeError(format!("'{name}' parameter is required")))?,
+        );
+    }
+    let state = params
+        .get::<Option<String>>("state")?
+        .unwrap_or_else(|| "present".to_string());
+    if state != "present" && state != "absent" {
+        return Err(RuntimeError(
+            "'state' parameter must be 'present' or 'absent'".to_string(),
+        ));
+    }
+    let attribute = params.get::<Option<String>>("attribute")?;
+    let value = params.get::<Option<String>>("value")?;
+    let add_children = match params.get::<Value>("add_children")? {
+        Value::Nil => Vec::new(),
+        Value::String(fragment) => vec![fragment.to_str()?.to_string()],
+        Value::Table(fragments) => fragments
+            .sequence_values::<String>()
+            .collect::<mlua::Result<_>>()?,
+        _ => {
+            return Err(RuntimeError(
+                "'add_children' parameter must be a string or a list of strings".to_string(),
+            ));
+        }
+    };
+    if state == "present" && value.is_none() && add_children.is_empty() {
+        return Err(RuntimeError(
+            "'value' or 'add_children' parameter is required unless state is 'absent'".to_string(),
+        ));
+    }
+    if state == "absent" && (value.is_some() || !add_children.is_empty()) {
+        return Err(RuntimeError(
+            "'value' and 'add_children' parameters do not apply when state is 'absent'".to_string(),
+        ));
+    }
+    let namespaces = params
+        .get::<Option<BTreeMap<String, String>>>("namespaces")?
+        .unwrap_or_default();
+
+    let command = |check: bool| {
+        let spec = serde_json::json!({
+            "path": required[0],
+            "xpath": required[1],
+            "namespaces": namespaces,
+            "state": state,
+            "attribute": attribute,
+            "value": value,
+            "add_children": add_children,
+            "check": check,
+        });
+        format!(
+            "python3 -c {} {}",
+            escape_shell_value(XML_SCRIPT),
+            escape_shell_value(&spec.to_string())
+        )
+    };
+    let check_command = command(true);
+    let edit_command = command(false);
+
+    let module = lua
+        .load(chunk! {
+            local module = $base_module:new({ name = "xml" })
+
+            module.params = $params
+            module.check_command = $check_command
+            module.edit_command = $edit_command
+
+            -- Run the edit program; returns whether the file changed.
+            module.edit = function(self, command)
+                self.ssh:requires("python3")
+                local result = self.ssh:cmdq(command)
+                if result.exit_code ~= 0 then
+                    error("xml: " .. self.params.path .. ": " .. result.stderr)
+                end
+                local changed, matches = string.match(result.stdout, "changed=(%d) matches=(%d+)")
+                if changed == nil then
+                    error("xml: unexpected output: " .. result.stdout)
+                end
+                self:set_result("matches", tonumber(matches))
+                return changed == "1"
+            end
+
+            module.dry_run = function(self)
+                self.ssh:set_changed(self:edit(self.check_command))
+            end
+
+            module.run = function(self)
+                self.ssh:set_changed(self:edit(self.edit_command))
+            end
+
+            return module
+        })
+        .set_name("xml")
+        .eval::<Table>()
+        .into_lua_err()?;
+
+    Ok(module)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use crate::create_lua;
+
+    use super::*;
+
+    #[test]
+    fn test_xml_validates_params() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        assert!(xml(&lua, lua.create_table()?).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/tomcat/server.xml")?;
+        params.set("xpath", ".//Connector")?;
+        assert!(xml(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/tomcat/server.xml")?;
+        params.set("xpath", ".//Connector")?;
+        params.set("state", "absent")?;
+        params.set("value", "x")?;
+        assert!(xml(&lua, params).is_err());
+
+        let params = lua.create_table()?;
+        params.set("path", "/etc/tomcat/server.xml")?;
+        params.set("xpath", ".//Connector")?;
+        params.set("attribute", "port")?;
+        params.set("value", 8443)?;
+        assert!(xml(&lua, params).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_xml_edits_file() -> mlua::Result<()> {
+        if std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+        let path = dir.path().join("server.xml");
+        std::fs::write(
+            &path,
+            "<?xml version=\"1.0\"?>\n<Server>\n  <!-- connectors -->\n  <Service name=\"Catalina\">\n    <Connector port=\"8080\"/>\n  </Service>\n</Server>\n",
+        )
+        .map_err(mlua::Error::external)?;
+        let path = path.to_string_lossy().to_string();
+
+        let lua = create_lua()?;
+        let run = |params: Table| -> mlua::Result<Table> {
+            params.set("path", path.as_str())?;
+            let task = lua.create_table()?;
+            task.set(1, xml(&lua, params)?)?;
+            let host = lua.create_table()?;
+            host.set("address", "localhost")?;
+            crate::komando::komando(&lua, (mlua::Value::Table(task), mlua::Value::Table(host)))
+        };
+        let set_port = || -> mlua::Result<Table> {
+            let params = lua.create_table()?;
+            params.set("xpath", "/Server/Service/Connector")?;
+            params.set("attribute", "port")?;
+            params.set("value", "8443")?;
+            run(params)
+        };
+
+        let result = set_port()?;
+        assert!(result.get::<bool>("changed")?);
+        assert_eq!(result.get::<i64>("matches")?, 1);
+        assert!(!set_port()?.get::<bool>("changed")?);
+
+        let params = lua.create_table()?;
+        params.set("xpath", "./Service")?;
+        params.set("add_children", "<Valve className=\"AccessLogValve\"/>")?;
+        assert!(run(params)?.get::<bool>("changed")?);
+
+        let content = std::fs::read_to_string(&path).map_err(mlua::Error::external)?;
+        assert!(content.contains("<!-- connectors -->"));
+        assert!(content.contains("<Connector port=\"8443\" />"));
+        assert!(content.contains("<Valve className=\"AccessLogValve\" />"));
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::io::Write;
+
+use mlua::{Error::RuntimeError, Function, Lua, MultiValue, Table};
+
+/// How a parallel worker's output reaches stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Print each line as it is produced, prefixed with `[host]`.
+    #[default]
+    Prefix,
+    /// Buffer the prefixed lines and print them in one block when the task
+    /// finishes.
+    Block,
+}
+
+impl OutputMode {
+    /// Read the `output` option (`"prefix"` or `"block"`) of a parallel run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for any other value.
+    pub fn from_lua_opts(opts: Option<&Table>) -> mlua::Result<Self> {
+        let mode = opts
+            .map(|opts| opts.get::<Option<String>>("output"))
+            .transpose()?
+            .flatten();
+        match mode.as_deref() {
+            None | Some("prefix") => Ok(Self::Prefix),
+            Some("block") => Ok(Self::Block),
+            Some(other) => Err(RuntimeError(format!(
+                "Invalid output mode '{other}'. Valid modes are: prefix, block."
+            ))),
+        }
+    }
+}
+
+/// Output routing installed on a worker thread for the duration of one item.
+struct Capture {
+    mode: OutputMode,
+    prefix: Option<String>,
+    buffer: Vec<String>,
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with this thread's output routed through `mode`, flushing a
+/// [`OutputMode::Block`] buffer in one piece once `f` returns.
+pub fn captured<R>(mode: OutputMode, f: impl FnOnce() -> R) -> R {
+    let capture = Capture {
+        mode,
+        prefix: None,
+        buffer: Vec::new(),
+    };
+    let previous = CAPTURE.with(|cell| cell.replace(Some(capture)));
+    let result = f();
+    let finished = CAPTURE.with(|cell| cell.replace(previous));
+
+    if let Some(finished) = finished
+        && !finished.buffer.is_empty()
+    {
+        let mut stdout = std::io::stdout().lock();
+        for line in &finished.buffer {
+            let _ = writeln!(stdout, "{line}");
+        }
+    }
+    result
+}
+
+/// Name the host whose output is being captured on this thread. Has no
+/// effect outside [`captured`].
+pub fn set_prefix(prefix: &str) {
+    CAPTURE.with(|cell| {
+        if let Some(capture) = cell.borrow_mut().as_mut() {
+            capture.prefix = Some(prefix.to_string());
+        }
+    });
+}
+
+/// Print `text` line by line, honouring the capture active on this thread.
+pub fn emit(text: &str) {
+    let handled = CAPTURE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let Some(capture) = cell.as_mut() else {
+            return false;
+        };
+        let lines = if text.is_empty() {
+            vec![""]
+        } else {
+            text.lines().collect()
+        };
+        for line in lines {
+            let line = capture
+                .prefix
+                .as_ref()
+                .map_or_else(|| line.to_string(), |prefix| format!("[{prefix}] {line}"));
+            match capture.mode {
+                OutputMode::Prefix => println!("{line}"),
+                OutputMode::Block => capture.buffer.push(line),
+            }
+        }
+        true
+    });
+    if !handled {
+        println!("{text}");
+    }
+}
+
+/// Print `text` labelled with `prefix`, unless a capture already labels this
+/// thread's output with its host.
+pub fn emit_prefixed(prefix: &str, text: &str) {
+    let labelled = CAPTURE.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .is_some_and(|capture| capture.prefix.is_some())
+    });
+    if labelled {
+        emit(text);
+    } else {
+        emit(&format!("[{prefix}] {text}"));
+    }
+}
+
+/// Replacement for Lua's global `print` that routes through [`emit`], so
+/// module and script output is prefixed in parallel runs.
+///
+/// # Errors
+///
+/// Returns an error if a value cannot be converted with `tostring`.
+pub fn lua_print(lua: &Lua, values: MultiValue) -> mlua::Result<()> {
+    let tostring = lua.globals().get::<Function>("tostring")?;
+    let parts = values
+        .into_iter()
+        .map(|value| tostring.call::<String>(value))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    emit(&parts.join("\t"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+
+    fn buffered_lines(f: impl FnOnce()) -> Vec<String> {
+        let capture = Capture {
+            mode: OutputMode::Block,
+            prefix: None,
+            buffer: Vec::new(),
+        };
+        let previous = CAPTURE.with(|cell| cell.replace(Some(capture)));
+        f();
+        CAPTURE
+            .with(|cell| cell.replace(previous))
+            .map(|capture| capture.buffer)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_emit_prefixes_each_line() {
+        let lines = buffered_lines(|| {
+            emit("before prefix");
+            set_prefix("web1");
+            emit("one\ntwo");
+            emit_prefixed("web1", "tail line");
+        });
+        assert_eq!(
+            lines,
+            vec![
+                "before prefix",
+                "[web1] one",
+                "[web1] two",
+                "[web1] tail line"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_prefix_outside_capture_is_ignored() {
+        set_prefix("web1");
+        assert!(CAPTURE.with(|cell| cell.borrow().is_none()));
+    }
+
+    #[test]
+    fn test_lua_print_is_captured() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let mut result = Ok(());
+        let lines = buffered_lines(|| {
+            set_prefix("db1");
+            result = lua.load("print('ready', 42, nil)").exec();
+        });
+        result?;
+        assert_eq!(lines, vec!["[db1] ready\t42\tnil"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_mode_from_lua_opts() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        assert_eq!(OutputMode::from_lua_opts(None)?, OutputMode::Prefix);
+        opts.set("output", "block")?;
+        assert_eq!(OutputMode::from_lua_opts(Some(&opts))?, OutputMode::Block);
+        opts.set("output", "json")?;
+        assert!(OutputMode::from_lua_opts(Some(&opts)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    cell::RefCell,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+static PROFILE: OnceLock<Mutex<Vec<ProfileRecord>>> = OnceLock::new();
+
+thread_local! {
+    /// Timings of the tasks running on this thread, innermost last.
+    static ACTIVE: RefCell<Vec<Timings>> = const { RefCell::new(Vec::new()) };
+}
+
+fn get_profile() -> &'static Mutex<Vec<ProfileRecord>> {
+    PROFILE.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// What a timed operation spent its time on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Opening or checking out the connection to the host.
+    Connect,
+    /// Uploads, downloads and remote file writes.
+    Transfer,
+    /// A command run on the host.
+    Command,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Timings {
+    connect: Duration,
+    transfer: Duration,
+    commands: Vec<(String, Duration)>,
+}
+
+#[derive(Debug, Clone)]
+struct ProfileRecord {
+    task: String,
+    host: String,
+    total: Duration,
+    connect: Duration,
+    transfer: Duration,
+    commands: Vec<(String, Duration)>,
+}
+
+impl ProfileRecord {
+    fn command_time(&self) -> Duration {
+        self.commands.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+
+    /// Time not accounted for by connecting, transfers or commands: module
+    /// Lua code, result handling and reporting.
+    fn lua_time(&self) -> Duration {
+        self.total
+            .saturating_sub(self.connect + self.transfer + self.command_time())
+    }
+}
+
+/// Profile of one task run; the breakdown is recorded when it is dropped.
+#[derive(Debug)]
+pub struct TaskProfile {
+    task: String,
+    host: String,
+    started: Instant,
+}
+
+impl TaskProfile {
+    /// Start profiling a task on the current thread, if `--profile` is set.
+    #[must_use]
+    pub fn begin(task: &str, host: &str) -> Option<Self> {
+        crate::args::global_flags()
+            .profile
+            .then(|| Self::start(task, host))
+    }
+
+    fn start(task: &str, host: &str) -> Self {
+        ACTIVE.with(|active| active.borrow_mut().push(Timings::default()));
+        Self {
+            task: task.to_string(),
+            host: host.to_string(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for TaskProfile {
+    fn drop(&mut self) {
+        let timings = ACTIVE
+            .with(|active| active.borrow_mut().pop())
+            .unwrap_or_default();
+        get_profile()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(ProfileRecord {
+                task: std::mem::take(&mut self.task),
+                host: std::mem::take(&mut self.host),
+                total: self.started.elapsed(),
+                connect: timings.connect,
+                transfer: timings.transfer,
+                commands: timings.commands,
+            });
+    }
+}
+
+/// Run `f`, adding its duration to the task profiled on this thread, if any.
+#[must_use]
+pub fn time<T>(phase: Phase, label: &str, f: impl FnOnce() -> T) -> T {
+    if ACTIVE.with(|active| active.borrow().is_empty()) {
+        return f();
+    }
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+    ACTIVE.with(|active| {
+        if let Some(timings) = active.borrow_mut().last_mut() {
+            match phase {
+                Phase::Connect => timings.connect += elapsed,
+                Phase::Transfer => timings.transfer += elapsed,
+                Phase::Command => timings.commands.push((label.to_string(), elapsed)),
+            }
+        }
+    });
+    result
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+/// First line of `command`, cut to `width` characters.
+fn command_label(command: &str, width: usize) -> String {
+    let line = command.lines().next().unwrap_or_default();
+    if line.chars().count() > width {
+        let cut = line
+            .chars()
+            .take(width.saturating_sub(3))
+            .collect::<String>();
+        format!("{cut}...")
+    } else {
+        line.to_string()
+    }
+}
+
+pub fn generate_profile() {
+    let profile = get_profile()
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone();
+    if profile.is_empty() {
+        return;
+    }
+    let width = 80;
+    let col_width = 9;
+    let col1_width = width - 5 * col_width;
+    println!();
+    println!("{:=^width$}", " Komando Profile ");
+    println!(
+        "{:<col1_width$}{:>col_width$}{:>col_width$}{:>col_width$}{:>col_width$}{:>col_width$}",
+        "Task on Host", "Total", "Connect", "Transfer", "Commands", "Lua"
+    );
+    println!("{:-<width$}", "");
+    let mut last_task = String::new();
+    let mut totals = [Duration::ZERO; 5];
+    for record in &profile {
+        if last_task != record.task {
+            println!(
+                "{}",
+                format!("* {}", record.task)
+                    .chars()
+                    .take(width)
+                    .collect::<String>()
+            );
+        }
+        let columns = [
+            record.total,
+            record.connect,
+            record.transfer,
+            record.command_time(),
+            record.lua_time(),
+        ];
+        let host = format!("  - {}", record.host)
+            .chars()
+            .take(col1_width - 1)
+            .collect::<String>();
+        print!("{host:<col1_width$}");
+        for (total, column) in totals.iter_mut().zip(columns) {
+            *total += column;
+            print!("{:>col_width$}", seconds(column));
+        }
+        println!();
+        last_task.clone_from(&record.task);
+    }
+    println!("{:-<width$}", "");
+    print!("{:<col1_width$}", "Total");
+    for total in totals {
+        print!("{:>col_width$}", seconds(total));
+    }
+    println!();
+
+    let mut commands = profile
+        .iter()
+        .flat_map(|record| {
+            record
+                .commands
+                .iter()
+                .map(move |(command, elapsed)| (*elapsed, command, &record.host))
+        })
+        .collect::<Vec<_>>();
+    if commands.is_empty() {
+        return;
+    }
+    commands.sort_by_key(|(elapsed, _, _)| std::cmp::Reverse(*elapsed));
+    println!();
+    println!("Slowest commands:");
+    for (elapsed, command, host) in commands.into_iter().take(10) {
+        let label = command_label(command, width - col_width - 4);
+        println!("{:>col_width$}  {label}", seconds(elapsed));
+        println!("{:>col_width$}  on {host}", "");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_profile_records_phases() {
+        {
+            let _profile = TaskProfile::start("profiled task", "host1");
+            let () = time(Phase::Connect, "", || {
+                std::thread::sleep(Duration::from_millis(5));
+            });
+            let () = time(Phase::Command, "echo hi", || {
+                std::thread::sleep(Duration::from_millis(5));
+            });
+            let () = time(Phase::Transfer, "/tmp/file", || {});
+        }
+        assert!(ACTIVE.with(|active| active.borrow().is_empty()));
+
+        let profile = get_profile()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone();
+        let record = profile.iter().find(|record| record.task == "profiled task");
+        assert!(record.is_some_and(|record| {
+            record.host == "host1"
+                && record.connect >= Duration::from_millis(5)
+                && record.commands.len() == 1
+                && record.commands[0].0 == "echo hi"
+                && record.total >= record.connect + record.command_time()
+        }));
+    }
+
+    #[test]
+    fn test_time_without_profile_is_not_recorded() {
+        assert_eq!(time(Phase::Command, "true", || 42), 42);
+        assert!(ACTIVE.with(|active| active.borrow().is_empty()));
+    }
+
+    #[test]
+    fn test_command_label() {
+        assert_eq!(command_label("echo hi\necho there", 20), "echo hi");
+        assert_eq!(command_label("abcdefghij", 8), "abcde...");
+    }
+}
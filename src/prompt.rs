@@ -0,0 +1,281 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+
+/// One value asked for by `komandan.prompt_vars` or `komandan.prompt`.
+struct VarPrompt {
+    name: String,
+    prompt: String,
+    secret: bool,
+    confirm: bool,
+    default: Value,
+}
+
+impl VarPrompt {
+    fn from_table(spec: &Table) -> mlua::Result<Self> {
+        let name = spec
+            .get::<Option<String>>("name")?
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| RuntimeError("prompt_vars: every entry needs a name".to_string()))?;
+        Ok(Self {
+            prompt: spec
+                .get::<Option<String>>("prompt")?
+                .unwrap_or_else(|| name.clone()),
+            secret: spec.get::<Option<bool>>("secret")?.unwrap_or(false),
+            confirm: spec.get::<Option<bool>>("confirm")?.unwrap_or(false),
+            default: spec.get("default")?,
+            name,
+        })
+    }
+
+    /// The prompt text, showing the default unless the value is secret.
+    fn label(&self) -> String {
+        let default = match &self.default {
+            Value::String(default) => Some(default.to_string_lossy()),
+            Value::Integer(default) => Some(default.to_string()),
+            Value::Number(default) => Some(default.to_string()),
+            Value::Boolean(default) => Some(default.to_string()),
+            _ => None,
+        };
+        default.filter(|_| !self.secret).map_or_else(
+            || format!("{}: ", self.prompt),
+            |default| format!("{} [{default}]: ", self.prompt),
+        )
+    }
+}
+
+/// Reads one answer; `hidden` suppresses the terminal echo.
+type ReadAnswer<'a> = dyn FnMut(&str, bool) -> io::Result<String> + 'a;
+
+/// Ask for `spec`'s value: an empty answer takes the default, and a secret
+/// with `confirm` must be typed twice.
+fn ask(lua: &Lua, spec: &VarPrompt, read: &mut ReadAnswer) -> mlua::Result<Value> {
+    loop {
+        let answer = read(&spec.label(), spec.secret)
+            .map_err(|e| RuntimeError(format!("failed to read '{}': {e}", spec.name)))?;
+        if answer.is_empty() && !spec.default.is_nil() {
+            return Ok(spec.default.clone());
+        }
+        if spec.confirm {
+            let again = read(&format!("confirm {}", spec.label()), spec.secret)
+                .map_err(|e| RuntimeError(format!("failed to read '{}': {e}", spec.name)))?;
+            if again != answer {
+                eprintln!("Values do not match, try again.");
+                continue;
+            }
+        }
+        return lua.create_string(answer).map(Value::String);
+    }
+}
+
+fn read_terminal(label: &str, hidden: bool) -> io::Result<String> {
+    if hidden {
+        return rpassword::prompt_password(label);
+    }
+    let mut stdout = io::stdout();
+    stdout.write_all(label.as_bytes())?;
+    stdout.flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Prompt on the terminal for every entry of `specs`, e.g.
+/// `{ { name = "deploy_tag", default = "latest" }, { name = "db_password", secret = true } }`,
+/// and return the answers keyed by name.
+///
+/// Entries take a `prompt` text, a `default` used for an empty answer,
+/// `secret` to hide the input and `confirm` to ask twice. Without a
+/// terminal on stdin, defaults are used and a value without one is an error.
+///
+/// # Errors
+///
+/// Returns an error if an entry is invalid or an answer cannot be read.
+pub fn prompt_vars(lua: &Lua, specs: &Table) -> mlua::Result<Table> {
+    let interactive = io::stdin().is_terminal();
+    let mut read = read_terminal;
+    collect_answers(lua, specs, interactive, &mut read)
+}
+
+fn collect_answers(
+    lua: &Lua,
+    specs: &Table,
+    interactive: bool,
+    read: &mut ReadAnswer,
+) -> mlua::Result<Table> {
+    let answers = lua.create_table()?;
+    for spec in specs.sequence_values::<Table>() {
+        let spec = VarPrompt::from_table(&spec?)?;
+        let value = answer(lua, &spec, interactive, read, "prompt_vars")?;
+        answers.set(spec.name, value)?;
+    }
+    Ok(answers)
+}
+
+/// Asks for `spec` on a terminal, or falls back to its default without
+/// one. `caller` names the Lua function in errors.
+fn answer(
+    lua: &Lua,
+    spec: &VarPrompt,
+    interactive: bool,
+    read: &mut ReadAnswer,
+    caller: &str,
+) -> mlua::Result<Value> {
+    if interactive {
+        ask(lua, spec, read)
+    } else if spec.default.is_nil() {
+        Err(RuntimeError(format!(
+            "{caller}: cannot ask for '{}' without a terminal and it has no default",
+            spec.name
+        )))
+    } else {
+        Ok(spec.default.clone())
+    }
+}
+
+/// Ask for a single value on the terminal: `komandan.prompt(message,
+/// options)` and, with `secret`, `komandan.prompt_secret(message, options)`,
+/// whose input is not echoed. `message` is shown followed by `": "`.
+///
+/// Options are `default`, returned for an empty answer and when stdin is
+/// not a terminal, and `confirm` to ask twice.
+///
+/// # Errors
+///
+/// Returns an error if the answer cannot be read, or if there is no
+/// terminal and no default.
+pub fn prompt(
+    lua: &Lua,
+    message: String,
+    secret: bool,
+    options: Option<&Table>,
+) -> mlua::Result<Value> {
+    let interactive = io::stdin().is_terminal();
+    let mut read = read_terminal;
+    prompt_with(lua, message, secret, options, interactive, &mut read)
+}
+
+fn prompt_with(
+    lua: &Lua,
+    message: String,
+    secret: bool,
+    options: Option<&Table>,
+    interactive: bool,
+    read: &mut ReadAnswer,
+) -> mlua::Result<Value> {
+    let (default, confirm) = match options {
+        Some(options) => (
+            options.get::<Value>("default")?,
+            options.get::<Option<bool>>("confirm")?.unwrap_or(false),
+        ),
+        None => (Value::Nil, false),
+    };
+    let spec = VarPrompt {
+        name: message.clone(),
+        prompt: message,
+        secret,
+        confirm,
+        default,
+    };
+    let caller = if secret { "prompt_secret" } else { "prompt" };
+    answer(lua, &spec, interactive, read, caller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::chunk;
+
+    fn specs(lua: &Lua) -> mlua::Result<Table> {
+        lua.load(chunk! {
+            return {
+                { name = "deploy_tag", prompt = "Tag to deploy", default = "latest" },
+                { name = "db_password", secret = true, confirm = true },
+            }
+        })
+        .eval::<Table>()
+    }
+
+    #[test]
+    fn test_collect_answers_interactive() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let mut answers = vec!["", "s3cret", "typo", "s3cret", "s3cret"].into_iter();
+        let mut labels = Vec::new();
+        let mut read = |label: &str, hidden: bool| -> io::Result<String> {
+            labels.push((label.to_string(), hidden));
+            Ok(answers.next().unwrap_or_default().to_string())
+        };
+        let result = collect_answers(&lua, &specs(&lua)?, true, &mut read)?;
+        assert_eq!(result.get::<String>("deploy_tag")?, "latest");
+        assert_eq!(result.get::<String>("db_password")?, "s3cret");
+        assert_eq!(labels[0], ("Tag to deploy [latest]: ".to_string(), false));
+        assert_eq!(labels[1], ("db_password: ".to_string(), true));
+        assert_eq!(labels[2], ("confirm db_password: ".to_string(), true));
+        assert_eq!(labels.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_answers_without_terminal() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let mut read = |_: &str, _: bool| -> io::Result<String> {
+            Err(io::Error::other("must not be called"))
+        };
+        let error = collect_answers(&lua, &specs(&lua)?, false, &mut read)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("cannot ask for 'db_password' without a terminal"));
+
+        let defaults = lua
+            .load(chunk! { return { { name = "region", default = "eu-west-1" } } })
+            .eval::<Table>()?;
+        let result = collect_answers(&lua, &defaults, false, &mut read)?;
+        assert_eq!(result.get::<String>("region")?, "eu-west-1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_prompt_vars_requires_name() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let specs = lua
+            .load(chunk! { return { { prompt = "Nameless" } } })
+            .eval::<Table>()?;
+        let mut read = |_: &str, _: bool| -> io::Result<String> { Ok(String::new()) };
+        assert!(collect_answers(&lua, &specs, true, &mut read).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prompt_single_value() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let mut labels = Vec::new();
+        let mut read = |label: &str, hidden: bool| -> io::Result<String> {
+            labels.push((label.to_string(), hidden));
+            Ok("123456".to_string())
+        };
+        let otp = prompt_with(&lua, "OTP code".to_string(), true, None, true, &mut read)?;
+        assert_eq!(otp.to_string()?, "123456");
+        assert_eq!(labels, [("OTP code: ".to_string(), true)]);
+
+        let options = lua
+            .load(chunk! { return { default = "main" } })
+            .eval::<Table>()?;
+        let branch = prompt_with(
+            &lua,
+            "Branch".to_string(),
+            false,
+            Some(&options),
+            false,
+            &mut read,
+        )?;
+        assert_eq!(branch.to_string()?, "main");
+
+        let error = prompt_with(&lua, "OTP code".to_string(), true, None, false, &mut read)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("prompt_secret: cannot ask for 'OTP code' without a terminal"));
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use anyhow::{Context, Result};
+use mlua::{Error::RuntimeError, Lua, Table};
+
+use crate::util::host_display;
+
+/// A command run while recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecordedCommand {
+    name: String,
+    cmd: String,
+    elevate: bool,
+    host: String,
+}
+
+/// Commands recorded so far; `None` while not recording.
+static RECORDING: OnceLock<Mutex<Option<Vec<RecordedCommand>>>> = OnceLock::new();
+
+fn recording() -> &'static Mutex<Option<Vec<RecordedCommand>>> {
+    RECORDING.get_or_init(|| Mutex::new(None))
+}
+
+/// Start recording the commands run by tasks, discarding any earlier
+/// recording.
+pub fn start() {
+    *recording().lock().unwrap_or_else(PoisonError::into_inner) = Some(Vec::new());
+}
+
+/// Stop recording and return the draft of the commands recorded.
+pub fn stop() -> String {
+    let commands = recording()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .unwrap_or_default();
+    draft(&commands)
+}
+
+/// Stop recording and write the draft to `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn save(path: &str) -> Result<()> {
+    fs::write(path, stop()).with_context(|| format!("Failed to write the recording to {path}"))
+}
+
+/// Record the command `task` runs on `host` with `module`, when recording
+/// and the module takes a `cmd` parameter.
+///
+/// # Errors
+///
+/// Returns an error if the task or module fields have the wrong type.
+pub fn record_task(task: &Table, host: &Table, module: &Table) -> mlua::Result<()> {
+    let mut recording = recording().lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(commands) = recording.as_mut() else {
+        return Ok(());
+    };
+    let Some(cmd) = module
+        .get::<Option<Table>>("params")?
+        .map(|params| params.get::<Option<String>>("cmd"))
+        .transpose()?
+        .flatten()
+    else {
+        return Ok(());
+    };
+    commands.push(RecordedCommand {
+        name: crate::util::task_display(task),
+        cmd,
+        elevate: task.get::<Option<bool>>("elevate")?.unwrap_or(false),
+        host: host_display(host),
+    });
+    Ok(())
+}
+
+/// `value` as a Lua string literal.
+fn lua_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(literal, "\\{:03}", u32::from(c));
+            }
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A Lua script running `commands` as `cmd` tasks on a placeholder host list.
+fn draft(commands: &[RecordedCommand]) -> String {
+    let hosts = commands
+        .iter()
+        .map(|command| command.host.as_str())
+        .collect::<BTreeSet<_>>();
+    let mut script = String::from("-- Draft recorded by komandan; review every task before use.\n");
+    if !hosts.is_empty() {
+        let _ = writeln!(
+            script,
+            "-- Recorded on: {}",
+            hosts.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    script.push_str(
+        "local hosts = {\n  { address = \"HOST_ADDRESS\" }, -- TODO: set the target hosts\n}\n\n",
+    );
+    script.push_str("local tasks = {\n");
+    for command in commands {
+        let elevate = if command.elevate {
+            ", elevate = true"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            script,
+            "  {{ name = {}{elevate}, komandan.modules.cmd({{ cmd = {} }}) }},",
+            lua_string(&command.name),
+            lua_string(&command.cmd)
+        );
+    }
+    script.push_str("}\n\n");
+    script.push_str(
+        "for _, host in ipairs(hosts) do\n  for _, task in ipairs(tasks) do\n    komandan.komando(task, host)\n  end\nend\n",
+    );
+    script
+}
+
+/// The `komandan.record` table: `start()`, `stop()` returning the draft, and
+/// `save(path)` writing it.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn record_table(lua: &Lua) -> mlua::Result<Table> {
+    let record = lua.create_table()?;
+    record.set(
+        "start",
+        lua.create_function(|_, ()| {
+            start();
+            Ok(())
+        })?,
+    )?;
+    record.set("stop", lua.create_function(|_, ()| Ok(stop()))?)?;
+    record.set(
+        "save",
+        lua.create_function(|_, path: String| {
+            save(&path).map_err(|e| RuntimeError(e.to_string()))
+        })?,
+    )?;
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lua_string() {
+        assert_eq!(lua_string("echo \"hi\"\n"), "\"echo \\\"hi\\\"\\n\"");
+        assert_eq!(lua_string("a\\b\u{1}"), "\"a\\\\b\\001\"");
+    }
+
+    #[test]
+    fn test_draft_is_valid_lua() -> mlua::Result<()> {
+        let commands = vec![
+            RecordedCommand {
+                name: "uptime".to_string(),
+                cmd: "uptime".to_string(),
+                elevate: false,
+                host: "web1 (10.0.0.1)".to_string(),
+            },
+            RecordedCommand {
+                name: "restart".to_string(),
+                cmd: "systemctl restart \"nginx\"".to_string(),
+                elevate: true,
+                host: "web1 (10.0.0.1)".to_string(),
+            },
+        ];
+        let script = draft(&commands);
+        assert!(script.contains("-- Recorded on: web1 (10.0.0.1)\n"));
+
+        let lua = crate::create_lua()?;
+        let chunk = script.replace("komandan.komando(task, host)", "count = count + 1");
+        let tasks = lua
+            .load(format!("count = 0\n{chunk}\nreturn tasks"))
+            .eval::<Table>()?;
+        assert_eq!(tasks.raw_len(), 2);
+        let restart = tasks.get::<Table>(2)?;
+        assert!(restart.get::<bool>("elevate")?);
+        assert_eq!(
+            restart
+                .get::<Table>(1)?
+                .get::<Table>("params")?
+                .get::<String>("cmd")?,
+            "systemctl restart \"nginx\""
+        );
+        assert_eq!(lua.globals().get::<i64>("count")?, 2);
+        Ok(())
+    }
+}
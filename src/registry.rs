@@ -0,0 +1,566 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::args::GetArgs;
+use crate::util::http_get;
+
+/// Lockfile, relative to the project root, recording fetched packages.
+pub const LOCK_FILE: &str = "komandan.lock";
+
+/// Project directories a package can install into.
+const INSTALL_DIRS: [&str; 2] = ["modules", "roles"];
+
+const TARBALL_EXTENSIONS: [&str; 5] = [".tar.gz", ".tgz", ".tar.bz2", ".tar.xz", ".tar"];
+
+/// `komandan.lock`: every package fetched by `komandan get`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockFile {
+    #[serde(default)]
+    pub packages: Vec<LockedPackage>,
+}
+
+/// One package of `komandan.lock`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    /// Git URL or tarball URL/path the package was fetched from.
+    pub source: String,
+    /// Branch, tag or commit asked for, for git sources.
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    /// Commit of a git source, or `sha256:<hex>` of a tarball.
+    pub version: String,
+    /// Files and directories installed, relative to the project root.
+    pub installed: Vec<String>,
+}
+
+impl LockFile {
+    /// Read the lockfile of `project_dir`; a missing file is an empty lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(project_dir: &Path) -> Result<Self> {
+        let path = project_dir.join(LOCK_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Write the lockfile of `project_dir`, packages sorted by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&mut self, project_dir: &Path) -> Result<()> {
+        self.packages.sort_by(|a, b| a.name.cmp(&b.name));
+        let path = project_dir.join(LOCK_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn find(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|package| package.name == name)
+    }
+}
+
+/// How a package source is fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceKind {
+    Git,
+    Tarball,
+}
+
+/// Whether `source` is a git repository or a tarball, from its form.
+fn source_kind(source: &str) -> Result<SourceKind> {
+    let path = source.split(['?', '#']).next().unwrap_or(source);
+    if TARBALL_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+        Ok(SourceKind::Tarball)
+    } else if path.ends_with(".git")
+        || ["git@", "git://", "ssh://", "git+"]
+            .iter()
+            .any(|prefix| source.starts_with(prefix))
+        || Path::new(source).join(".git").exists()
+    {
+        Ok(SourceKind::Git)
+    } else {
+        bail!(
+            "Cannot tell whether '{source}' is a git repository or a tarball: use a URL ending in .git or a .tar.gz/.tgz/.tar file"
+        )
+    }
+}
+
+/// Package name derived from `source`: its last path segment without the
+/// `.git` or tarball extension.
+fn default_name(source: &str) -> String {
+    let path = source
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(source)
+        .trim_end_matches('/');
+    let last = path.rsplit(['/', ':']).next().unwrap_or(path);
+    let last = last.strip_suffix(".git").unwrap_or(last);
+    TARBALL_EXTENSIONS
+        .iter()
+        .find_map(|ext| last.strip_suffix(ext))
+        .unwrap_or(last)
+        .to_string()
+}
+
+/// Scratch directory removed when dropped.
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    fn new() -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let dir = std::env::temp_dir().join(format!("komandan-get-{}-{nanos}", std::process::id()));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run(command: &mut Command, what: &str) -> Result<String> {
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run {what}"))?;
+    if !output.status.success() {
+        bail!(
+            "{what} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Clone `source` into `work` at `git_ref`, returning the tree and commit.
+fn fetch_git(source: &str, git_ref: Option<&str>, work: &Path) -> Result<(PathBuf, String)> {
+    let url = source.strip_prefix("git+").unwrap_or(source);
+    // Neither may be taken for an option such as `--upload-pack=<command>`.
+    if url.starts_with('-') {
+        bail!("Invalid git URL '{url}'");
+    }
+    if let Some(git_ref) = git_ref.filter(|git_ref| git_ref.starts_with('-')) {
+        bail!("Invalid git ref '{git_ref}'");
+    }
+    let tree = work.join("tree");
+    run(
+        Command::new("git")
+            .args(["clone", "--quiet", "--", url])
+            .arg(&tree),
+        "git clone",
+    )?;
+    if let Some(git_ref) = git_ref {
+        // `--` would make `checkout` read the ref as a path.
+        run(
+            Command::new("git").arg("-C").arg(&tree).args([
+                "checkout",
+                "--quiet",
+                "--end-of-options",
+                git_ref,
+            ]),
+            "git checkout",
+        )?;
+    }
+    let commit = run(
+        Command::new("git")
+            .arg("-C")
+            .arg(&tree)
+            .args(["rev-parse", "HEAD"]),
+        "git rev-parse",
+    )?;
+    fs::remove_dir_all(tree.join(".git")).context("Failed to remove the .git directory")?;
+    Ok((tree, commit))
+}
+
+/// Download or read the tarball `source` and unpack it into `work`,
+/// returning the tree and the archive's `sha256:<hex>`. An archive holding
+/// a single top-level directory other than `modules/` or `roles/`, as
+/// release tarballs do, is unpacked from inside it.
+fn fetch_tarball(source: &str, work: &Path) -> Result<(PathBuf, String)> {
+    let archive = if source.starts_with("http://") || source.starts_with("https://") {
+        let response = http_get(source).map_err(|e| anyhow::anyhow!(e))?;
+        if !response.success {
+            bail!("Failed to download {source}: HTTP {}", response.status);
+        }
+        response.body
+    } else {
+        fs::read(source).with_context(|| format!("Failed to read {source}"))?
+    };
+    let version = format!("sha256:{:x}", Sha256::digest(&archive));
+
+    let archive_path = work.join("archive");
+    fs::write(&archive_path, &archive)?;
+    let tree = work.join("tree");
+    fs::create_dir_all(&tree)?;
+    run(
+        Command::new("tar")
+            .arg("-xf")
+            .arg(&archive_path)
+            .arg("-C")
+            .arg(&tree),
+        "tar",
+    )?;
+
+    let entries = fs::read_dir(&tree)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    match entries.as_slice() {
+        [single] if single.is_dir() && !INSTALL_DIRS.iter().any(|dir| single.ends_with(dir)) => {
+            Ok((single.clone(), version))
+        }
+        _ => Ok((tree, version)),
+    }
+}
+
+/// Where the files of `tree` go in the project: the entries of its
+/// `modules/` and `roles/` directories into the project's, or the whole
+/// tree as `roles/<name>` when it has neither. Paths are relative.
+fn install_plan(tree: &Path, name: &str) -> Result<Vec<(PathBuf, String)>> {
+    let mut plan = Vec::new();
+    for dir in INSTALL_DIRS {
+        let source_dir = tree.join(dir);
+        if !source_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&source_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            plan.push((entry.path(), format!("{dir}/{file_name}")));
+        }
+    }
+    if plan.is_empty() {
+        plan.push((tree.to_path_buf(), format!("roles/{name}")));
+    }
+    plan.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(plan)
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(from)
+        .with_context(|| format!("Failed to read {}", from.display()))?
+        .file_type();
+    if file_type.is_symlink() {
+        bail!("Refusing to install the symlink {}", from.display());
+    }
+    if file_type.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to).with_context(|| format!("Failed to copy to {}", to.display()))?;
+    }
+    Ok(())
+}
+
+/// `path` of the lockfile, inside the project; guards removals against a
+/// tampered lockfile.
+fn project_path(project_dir: &Path, path: &str) -> Result<PathBuf> {
+    if path.is_empty()
+        || !Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("Refusing to touch '{path}' outside the project");
+    }
+    Ok(project_dir.join(path))
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Fetch `source` into the project and return its lock entry. `previous`
+/// is the package's current entry, whose files are replaced. With
+/// `expected_version`, the fetched version must match it.
+///
+/// # Errors
+///
+/// Returns an error if fetching fails, the version does not match, or an
+/// installed path belongs to something else.
+pub fn get_package(
+    project_dir: &Path,
+    name: &str,
+    source: &str,
+    git_ref: Option<&str>,
+    expected_version: Option<&str>,
+    previous: Option<&LockedPackage>,
+) -> Result<LockedPackage> {
+    let work = WorkDir::new()?;
+    let (tree, version) = match source_kind(source)? {
+        SourceKind::Git => fetch_git(source, expected_version.or(git_ref), &work.0)?,
+        SourceKind::Tarball => fetch_tarball(source, &work.0)?,
+    };
+    if let Some(expected) = expected_version
+        && expected != version
+    {
+        bail!("Package '{name}' from {source} is {version}, but {LOCK_FILE} expects {expected}");
+    }
+
+    let plan = install_plan(&tree, name)?;
+    let owned = previous
+        .map(|package| package.installed.as_slice())
+        .unwrap_or_default();
+    for (_, target) in &plan {
+        if project_path(project_dir, target)?.exists() && !owned.contains(target) {
+            bail!(
+                "'{target}' already exists in the project and does not belong to package '{name}'"
+            );
+        }
+    }
+    for path in owned {
+        remove_path(&project_path(project_dir, path)?)?;
+    }
+    for (from, target) in &plan {
+        copy_recursive(from, &project_path(project_dir, target)?)?;
+    }
+
+    Ok(LockedPackage {
+        name: name.to_string(),
+        source: source.to_string(),
+        git_ref: git_ref.map(ToString::to_string),
+        version,
+        installed: plan.into_iter().map(|(_, target)| target).collect(),
+    })
+}
+
+/// Handles the get command
+///
+/// With a source, fetches it and records it in `komandan.lock`. Without
+/// one, installs every package of `komandan.lock` at its locked version.
+///
+/// # Errors
+///
+/// Returns an error if a package cannot be fetched or installed, or the
+/// lockfile cannot be read or written.
+pub fn handle_get_command(args: &GetArgs) -> Result<()> {
+    let project_dir = Path::new(&args.project);
+    let mut lock = LockFile::load(project_dir)?;
+
+    let Some(source) = &args.source else {
+        if lock.packages.is_empty() {
+            println!("No packages in {LOCK_FILE}");
+        }
+        for package in &lock.packages {
+            get_package(
+                project_dir,
+                &package.name,
+                &package.source,
+                package.git_ref.as_deref(),
+                Some(&package.version),
+                Some(package),
+            )?;
+            println!("Installed {} {}", package.name, package.version);
+        }
+        return Ok(());
+    };
+
+    let name = args.name.clone().unwrap_or_else(|| default_name(source));
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        bail!("Invalid package name '{name}'; pass --name");
+    }
+    let package = get_package(
+        project_dir,
+        &name,
+        source,
+        args.git_ref.as_deref(),
+        None,
+        lock.find(&name),
+    )?;
+    println!(
+        "Installed {} {} into {}",
+        package.name,
+        package.version,
+        package.installed.join(", ")
+    );
+    lock.packages.retain(|locked| locked.name != name);
+    lock.packages.push(package);
+    lock.save(project_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_kind_and_default_name() -> Result<()> {
+        assert_eq!(
+            source_kind("https://github.com/acme/nginx-role.git")?,
+            SourceKind::Git
+        );
+        assert_eq!(
+            source_kind("git@github.com:acme/nginx.git")?,
+            SourceKind::Git
+        );
+        assert_eq!(
+            source_kind("https://example.com/pkgs/nginx-1.2.tar.gz")?,
+            SourceKind::Tarball
+        );
+        assert!(source_kind("https://example.com/nginx").is_err());
+
+        assert_eq!(
+            default_name("https://github.com/acme/nginx-role.git"),
+            "nginx-role"
+        );
+        assert_eq!(default_name("git@github.com:acme/nginx.git"), "nginx");
+        assert_eq!(default_name("./dist/tools-1.2.tgz"), "tools-1.2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_path_rejects_escapes() {
+        let project = Path::new("/project");
+        assert!(project_path(project, "roles/nginx").is_ok());
+        assert!(project_path(project, "../etc").is_err());
+        assert!(project_path(project, "/etc/passwd").is_err());
+        assert!(project_path(project, "").is_err());
+    }
+
+    fn tarball(dir: &Path, files: &[(&str, &str)]) -> Result<String> {
+        let src = dir.join("src");
+        for (path, content) in files {
+            let path = src.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+        }
+        let archive = dir.join("pkg.tar.gz");
+        run(
+            Command::new("tar")
+                .arg("-czf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(&src)
+                .arg("."),
+            "tar",
+        )?;
+        Ok(archive.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn test_get_package_from_tarball() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let project = dir.path().join("project");
+        fs::create_dir_all(&project)?;
+        let archive = tarball(
+            dir.path(),
+            &[
+                ("modules/nginx_site.lua", "-- Manage an nginx site\n"),
+                ("roles/web/init.lua", "return {}\n"),
+            ],
+        )?;
+
+        let package = get_package(&project, "web", &archive, None, None, None)?;
+        assert_eq!(package.installed, ["modules/nginx_site.lua", "roles/web"]);
+        assert!(package.version.starts_with("sha256:"));
+        assert!(project.join("modules/nginx_site.lua").is_file());
+        assert!(project.join("roles/web/init.lua").is_file());
+
+        // Reinstalling replaces the package's own files.
+        let again = get_package(
+            &project,
+            "web",
+            &archive,
+            None,
+            Some(&package.version),
+            Some(&package),
+        )?;
+        assert_eq!(again, package);
+
+        // Another package may not overwrite them, and a version mismatch fails.
+        assert!(get_package(&project, "other", &archive, None, None, None).is_err());
+        assert!(
+            get_package(
+                &project,
+                "web",
+                &archive,
+                None,
+                Some("sha256:0"),
+                Some(&package)
+            )
+            .is_err()
+        );
+
+        let mut lock = LockFile {
+            packages: vec![package],
+        };
+        lock.save(&project)?;
+        assert_eq!(LockFile::load(&project)?, lock);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_git_rejects_options() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("pwned");
+        let source = format!("--upload-pack=touch {}", marker.display());
+        assert!(fetch_git(&source, None, dir.path()).is_err());
+        assert!(
+            fetch_git(
+                "https://example.com/acme/nginx.git",
+                Some("--orphan=x"),
+                dir.path()
+            )
+            .is_err()
+        );
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_recursive_rejects_symlinks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("pkg");
+        fs::create_dir_all(&from)?;
+        fs::write(from.join("init.lua"), "return {}\n")?;
+        copy_recursive(&from, &dir.path().join("copy"))?;
+        assert!(dir.path().join("copy/init.lua").is_file());
+
+        std::os::unix::fs::symlink("/etc/passwd", from.join("passwd"))?;
+        assert!(copy_recursive(&from, &dir.path().join("again")).is_err());
+        assert!(!dir.path().join("again/passwd").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_package_installs_plain_tree_as_role() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let project = dir.path().join("project");
+        fs::create_dir_all(&project)?;
+        let archive = tarball(dir.path(), &[("init.lua", "return {}\n")])?;
+
+        let package = get_package(&project, "base", &archive, None, None, None)?;
+        assert_eq!(package.installed, ["roles/base"]);
+        assert!(project.join("roles/base/init.lua").is_file());
+        Ok(())
+    }
+}
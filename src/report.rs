@@ -1,10 +1,16 @@
 use std::{
     collections::HashMap,
-    sync::{Mutex, OnceLock},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 static REPORT: OnceLock<Mutex<Vec<ReportRecord>>> = OnceLock::new();
 
+/// Whether a task reported a change, with or without `--no-report`.
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
 fn get_report() -> &'static Mutex<Vec<ReportRecord>> {
     REPORT.get_or_init(|| Mutex::new(Vec::new()))
 }
@@ -18,6 +24,17 @@ pub fn insert_record(task: String, host: String, status: TaskStatus) {
         .push(record);
 }
 
+/// Note that a task changed, or in a dry run would change, a host.
+pub fn mark_changed() {
+    CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Whether any task changed, or in a dry run would change, a host.
+#[must_use]
+pub fn changes_made() -> bool {
+    CHANGED.load(Ordering::Relaxed)
+}
+
 #[cfg(test)]
 pub fn clear_report() {
     let report = get_report();
@@ -49,6 +66,9 @@ pub fn generate_report() {
     counters.insert(TaskStatus::OK, 0);
     counters.insert(TaskStatus::Changed, 0);
     counters.insert(TaskStatus::Failed, 0);
+    counters.insert(TaskStatus::Skipped, 0);
+    counters.insert(TaskStatus::Unreachable, 0);
+    counters.insert(TaskStatus::RolledBack, 0);
     let mut last_task = String::new();
     for record in &*report {
         if last_task != record.task {
@@ -68,12 +88,57 @@ pub fn generate_report() {
         }
     }
     println!("{:-<width$}", "");
+    let rolled_back = match counters[&TaskStatus::RolledBack] {
+        0 => String::new(),
+        count => format!(", Rolled back: {count}"),
+    };
     println!(
-        "OK: {}, Changed: {}, Failed: {}",
+        "OK: {}, Changed: {}, Failed: {}, Skipped: {}, Unreachable: {}{rolled_back}",
         counters[&TaskStatus::OK],
         counters[&TaskStatus::Changed],
-        counters[&TaskStatus::Failed]
+        counters[&TaskStatus::Failed],
+        counters[&TaskStatus::Skipped],
+        counters[&TaskStatus::Unreachable]
     );
+    if crate::args::global_flags().dry_run {
+        println!("{:-^width$}", " Per-host summary ");
+        for (host, counts) in host_summaries(&report) {
+            println!(
+                "{host:<col1_width$} OK: {}, Changed: {}, Failed: {}",
+                counts.ok, counts.changed, counts.failed
+            );
+        }
+    }
+}
+
+/// Task counts of one host; unreachable counts as failed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct HostCounts {
+    ok: usize,
+    changed: usize,
+    failed: usize,
+}
+
+/// Task counts per host, in the order the hosts first appear.
+fn host_summaries(report: &[ReportRecord]) -> Vec<(String, HostCounts)> {
+    let mut hosts: Vec<(String, HostCounts)> = Vec::new();
+    for record in report {
+        let index = hosts
+            .iter()
+            .position(|(host, _)| *host == record.host)
+            .unwrap_or_else(|| {
+                hosts.push((record.host.clone(), HostCounts::default()));
+                hosts.len() - 1
+            });
+        let counts = &mut hosts[index].1;
+        match record.status {
+            TaskStatus::OK => counts.ok += 1,
+            TaskStatus::Changed | TaskStatus::RolledBack => counts.changed += 1,
+            TaskStatus::Failed | TaskStatus::Unreachable => counts.failed += 1,
+            TaskStatus::Skipped => {}
+        }
+    }
+    hosts
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +153,11 @@ pub enum TaskStatus {
     OK,
     Changed,
     Failed,
+    Skipped,
+    Unreachable,
+    /// A changed task whose `undo` module ran after a later task in its
+    /// block failed.
+    RolledBack,
 }
 
 impl std::fmt::Display for TaskStatus {
@@ -96,6 +166,9 @@ impl std::fmt::Display for TaskStatus {
             Self::OK => write!(f, "OK"),
             Self::Changed => write!(f, "Changed"),
             Self::Failed => write!(f, "Failed"),
+            Self::Skipped => write!(f, "Skipped"),
+            Self::Unreachable => write!(f, "Unreachable"),
+            Self::RolledBack => write!(f, "Rolled back"),
         }
     }
 }
@@ -134,4 +207,44 @@ mod tests {
         assert_eq!(report[2].host, "host1");
         assert_eq!(report[2].status, TaskStatus::Failed);
     }
+
+    #[test]
+    fn test_host_summaries() {
+        let record = |host: &str, status| ReportRecord {
+            task: "task".to_string(),
+            host: host.to_string(),
+            status,
+        };
+        let report = [
+            record("web", TaskStatus::OK),
+            record("db", TaskStatus::Changed),
+            record("web", TaskStatus::Changed),
+            record("web", TaskStatus::Skipped),
+            record("db", TaskStatus::Unreachable),
+        ];
+        let counts = |ok, changed, failed| HostCounts {
+            ok,
+            changed,
+            failed,
+        };
+        assert_eq!(
+            host_summaries(&report),
+            [
+                ("web".to_string(), counts(1, 1, 0)),
+                ("db".to_string(), counts(0, 1, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mark_changed() {
+        mark_changed();
+        assert!(changes_made());
+    }
+
+    #[test]
+    fn test_task_status_display() {
+        assert_eq!(TaskStatus::Skipped.to_string(), "Skipped");
+        assert_eq!(TaskStatus::RolledBack.to_string(), "Rolled back");
+    }
 }
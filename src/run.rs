@@ -0,0 +1,204 @@
+use anyhow::{Context, Result, bail};
+use mlua::{Lua, LuaSerdeExt, Table, Value, chunk};
+use regex::Regex;
+use std::fs;
+
+use crate::args::Flags;
+
+/// Hosts of `inventory` matching `pattern`, in inventory order.
+///
+/// `pattern` is a comma-separated list of items. `all` or `*` selects every
+/// host, `~regex` hosts whose name or address matches, and any other item
+/// hosts with that name or address, or carrying it as a tag (group).
+///
+/// # Errors
+///
+/// Returns an error if a regex is invalid or no host matches.
+pub fn select_hosts(
+    inventory: &[serde_json::Value],
+    pattern: &str,
+) -> Result<Vec<serde_json::Value>> {
+    let mut selectors = Vec::new();
+    for item in pattern
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        selectors.push(match item.strip_prefix('~') {
+            Some(regex) => HostSelector::Regex(
+                Regex::new(regex).with_context(|| format!("Invalid host pattern '{item}'"))?,
+            ),
+            None if item == "all" || item == "*" => HostSelector::All,
+            None => HostSelector::Exact(item.to_string()),
+        });
+    }
+
+    let selected = inventory
+        .iter()
+        .filter(|host| selectors.iter().any(|selector| selector.matches(host)))
+        .cloned()
+        .collect::<Vec<_>>();
+    if selected.is_empty() {
+        bail!("No host in the inventory matches '{pattern}'");
+    }
+    Ok(selected)
+}
+
+enum HostSelector {
+    All,
+    Regex(Regex),
+    Exact(String),
+}
+
+impl HostSelector {
+    fn matches(&self, host: &serde_json::Value) -> bool {
+        let field = |name: &str| host.get(name).and_then(serde_json::Value::as_str);
+        match self {
+            Self::All => true,
+            Self::Regex(regex) => [field("name"), field("address")]
+                .into_iter()
+                .flatten()
+                .any(|value| regex.is_match(value)),
+            Self::Exact(item) => {
+                field("name") == Some(item.as_str())
+                    || field("address") == Some(item.as_str())
+                    || host
+                        .get("tags")
+                        .and_then(serde_json::Value::as_array)
+                        .is_some_and(|tags| {
+                            tags.iter().any(|tag| tag.as_str() == Some(item.as_str()))
+                        })
+            }
+        }
+    }
+}
+
+/// Tasks returned by a task file: a single task, or a list of tasks, told
+/// apart like `komando_parallel_hosts` does.
+pub(crate) fn task_list(loaded: Table) -> mlua::Result<Vec<Value>> {
+    let loaded = Value::Table(loaded);
+    match crate::komando::task_list(&loaded)? {
+        Some(tasks) => tasks.sequence_values::<Value>().collect(),
+        None => Ok(vec![loaded]),
+    }
+}
+
+/// Run the tasks returned by `task_file` on `hosts`, one task at a time on
+/// every host in parallel, then print the report. A task failing on some
+/// host stops the run once it finished on the other hosts.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be loaded or a task fails.
+pub fn run_task_file(
+    lua: &Lua,
+    flags: &Flags,
+    task_file: &str,
+    hosts: &[serde_json::Value],
+) -> Result<()> {
+    let script = fs::read_to_string(task_file)
+        .with_context(|| format!("Failed to read the task file ({task_file})"))?;
+    let loaded = lua.load(&script).set_name(task_file).eval::<Table>()?;
+    let tasks = lua.create_sequence_from(task_list(loaded)?)?;
+    let hosts = lua.to_value(hosts)?;
+
+    let result = lua
+        .load(chunk! {
+            local hosts = $hosts
+            for _, task in ipairs($tasks) do
+                local results = komandan.komando_parallel_hosts(task, hosts)
+                local failed = {}
+                for key, result in pairs(results) do
+                    if result.failed then
+                        table.insert(failed, hosts[key].name or hosts[key].address)
+                    end
+                end
+                if #failed > 0 then
+                    error("task failed on " .. table.concat(failed, ", "), 0)
+                end
+            end
+        })
+        .set_name(task_file)
+        .exec();
+    crate::hooks::run_complete(lua, result.as_ref().err().map(ToString::to_string));
+    result?;
+
+    crate::print_summaries(flags);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn inventory() -> Vec<serde_json::Value> {
+        vec![
+            json!({ "name": "web1", "address": "10.0.0.1", "tags": ["web", "prod"] }),
+            json!({ "name": "web2", "address": "10.0.0.2", "tags": ["web"] }),
+            json!({ "name": "db1", "address": "10.0.0.3", "tags": ["db", "prod"] }),
+            json!({ "address": "10.0.0.4" }),
+        ]
+    }
+
+    fn names(hosts: &[serde_json::Value]) -> Vec<&str> {
+        hosts
+            .iter()
+            .map(|host| {
+                host.get("name")
+                    .or_else(|| host.get("address"))
+                    .and_then(serde_json::Value::as_str)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_hosts() -> Result<()> {
+        let inventory = inventory();
+        assert_eq!(names(&select_hosts(&inventory, "web")?), ["web1", "web2"]);
+        assert_eq!(
+            names(&select_hosts(&inventory, "db1, 10.0.0.4")?),
+            ["db1", "10.0.0.4"]
+        );
+        assert_eq!(
+            names(&select_hosts(&inventory, "~^web|^db")?),
+            ["web1", "web2", "db1"]
+        );
+        assert_eq!(
+            names(&select_hosts(&inventory, "prod,db")?),
+            ["web1", "db1"]
+        );
+        assert_eq!(select_hosts(&inventory, "all")?.len(), 4);
+        assert!(select_hosts(&inventory, "cache").is_err());
+        assert!(select_hosts(&inventory, "~(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_list() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let single = lua
+            .load(chunk! { return { name = "one", komandan.modules.cmd({ cmd = "true" }) } })
+            .eval::<Table>()?;
+        assert_eq!(task_list(single)?.len(), 1);
+        let list = lua
+            .load(chunk! {
+                return {
+                    { komandan.modules.cmd({ cmd = "true" }) },
+                    { komandan.modules.cmd({ cmd = "false" }) },
+                }
+            })
+            .eval::<Table>()?;
+        assert_eq!(task_list(list)?.len(), 2);
+        let shorthand = lua.load(chunk! { return { "uptime" } }).eval::<Table>()?;
+        assert_eq!(task_list(shorthand)?.len(), 1);
+        // Read the same way as by `komando_parallel_hosts`.
+        let shorthands = lua
+            .load(chunk! { return { { "uptime" }, { "df" } } })
+            .eval::<Table>()?;
+        assert_eq!(task_list(shorthands.clone())?.len(), 1);
+        assert!(crate::komando::task_list(&Value::Table(shorthands))?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, OnceLock};
+
+use secrecy::{ExposeSecret, SecretString};
+use ssh2::Session;
+
+use crate::ssh::SSHAuthMethod;
+
+/// What a connected session was opened with. Tasks reuse a session only when
+/// every part matches, so a different user, credential, known_hosts file or
+/// pinned host key always gets its own connection.
+///
+/// Only the public parts are hashed. The password or key passphrase is kept
+/// as a [`SecretString`] and compared in constant time, so it never ends up
+/// in a hash that could be attacked offline.
+#[derive(Debug, Clone)]
+pub struct SessionKey {
+    address: String,
+    port: u16,
+    user: String,
+    /// Private key file, or `None` for password authentication.
+    private_key: Option<String>,
+    /// Password, or passphrase of `private_key`.
+    secret: Option<SecretString>,
+    known_hosts_file: Option<String>,
+    host_key_fingerprints: Vec<String>,
+}
+
+impl SessionKey {
+    #[must_use]
+    pub fn new(
+        address: &str,
+        port: u16,
+        user: &str,
+        auth_method: &SSHAuthMethod,
+        known_hosts_file: Option<&str>,
+        host_key_fingerprints: &[String],
+    ) -> Self {
+        let (private_key, secret) = match auth_method {
+            SSHAuthMethod::Password(password) => (None, Some(password.clone())),
+            SSHAuthMethod::PublicKey {
+                private_key,
+                passphrase,
+            } => (Some(private_key.clone()), passphrase.clone()),
+        };
+        Self {
+            address: address.to_string(),
+            port,
+            user: user.to_string(),
+            private_key,
+            secret,
+            known_hosts_file: known_hosts_file.map(ToString::to_string),
+            host_key_fingerprints: host_key_fingerprints.to_vec(),
+        }
+    }
+}
+
+impl PartialEq for SessionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+            && self.port == other.port
+            && self.user == other.user
+            && self.private_key == other.private_key
+            && self.known_hosts_file == other.known_hosts_file
+            && self.host_key_fingerprints == other.host_key_fingerprints
+            && secrets_match(self.secret.as_ref(), other.secret.as_ref())
+    }
+}
+
+impl Eq for SessionKey {}
+
+impl Hash for SessionKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address.hash(state);
+        self.port.hash(state);
+        self.user.hash(state);
+        self.private_key.hash(state);
+        self.known_hosts_file.hash(state);
+        self.host_key_fingerprints.hash(state);
+    }
+}
+
+/// Whether both secrets are unset or equal, comparing every byte whatever
+/// the first difference so the time taken does not reveal it.
+fn secrets_match(left: Option<&SecretString>, right: Option<&SecretString>) -> bool {
+    match (left, right) {
+        (None, None) => true,
+        (Some(left), Some(right)) => {
+            let left = left.expose_secret().as_bytes();
+            let right = right.expose_secret().as_bytes();
+            let diff = left
+                .iter()
+                .zip(right)
+                .fold(0_u8, |diff, (l, r)| diff | (l ^ r));
+            left.len() == right.len() && std::hint::black_box(diff) == 0
+        }
+        _ => false,
+    }
+}
+
+/// Authenticated sessions not in use by any task, by connection parameters.
+/// A session is checked out by one task at a time, since libssh2 serializes
+/// every channel of a session.
+static IDLE: OnceLock<Mutex<HashMap<SessionKey, Vec<Session>>>> = OnceLock::new();
+
+fn idle() -> std::sync::MutexGuard<'static, HashMap<SessionKey, Vec<Session>>> {
+    IDLE.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// How long the liveness probe of an idle session may take, in milliseconds.
+const ALIVE_CHECK_TIMEOUT_MS: u32 = 5_000;
+
+/// Take an idle session for `key`, skipping sessions the server has closed.
+pub fn checkout(key: &SessionKey) -> Option<Session> {
+    loop {
+        let session = idle().get_mut(key)?.pop()?;
+        if is_alive(&session) {
+            return Some(session);
+        }
+    }
+}
+
+/// Open and close a channel, the cheapest request the server must answer.
+fn is_alive(session: &Session) -> bool {
+    session.set_timeout(ALIVE_CHECK_TIMEOUT_MS);
+    let alive = session
+        .channel_session()
+        .and_then(|mut channel| channel.close())
+        .is_ok();
+    session.set_timeout(0);
+    alive
+}
+
+/// Return a session after its task finished so the next task on the same
+/// host skips the handshake and authentication.
+pub fn checkin(key: SessionKey, session: Session) {
+    // Clear the previous task's deadline.
+    session.set_timeout(0);
+    idle().entry(key).or_default().push(session);
+}
+
+/// Disconnect every idle session; returns how many were closed. Backs
+/// `komandan.reset_connections()`, e.g. after changing a user's groups.
+pub fn clear() -> usize {
+    let sessions = std::mem::take(&mut *idle());
+    let mut closed = 0;
+    for session in sessions.into_values().flatten() {
+        let _ = session.disconnect(None, "komandan: connection reset", None);
+        closed += 1;
+    }
+    closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_key_separates_credentials() {
+        let key = |auth: &SSHAuthMethod, user: &str| {
+            SessionKey::new("web1", 22, user, auth, Some("/root/.ssh/known_hosts"), &[])
+        };
+        let password = SSHAuthMethod::password("secret");
+        let other_password = SSHAuthMethod::password("other");
+        let public_key = SSHAuthMethod::public_key("/root/.ssh/id_ed25519", None);
+
+        assert_eq!(key(&password, "deploy"), key(&password, "deploy"));
+        assert_ne!(key(&password, "deploy"), key(&password, "root"));
+        assert_ne!(key(&password, "deploy"), key(&other_password, "deploy"));
+        assert_ne!(key(&password, "deploy"), key(&public_key, "deploy"));
+
+        let passphrase = SSHAuthMethod::public_key("/root/.ssh/id_ed25519", Some("pass".into()));
+        assert_ne!(key(&public_key, "deploy"), key(&passphrase, "deploy"));
+        assert_eq!(key(&passphrase, "deploy"), key(&passphrase, "deploy"));
+
+        let pinned = SessionKey::new(
+            "web1",
+            22,
+            "deploy",
+            &password,
+            Some("/root/.ssh/known_hosts"),
+            &["SHA256:abc".to_string()],
+        );
+        assert_ne!(key(&password, "deploy"), pinned);
+    }
+
+    #[test]
+    fn test_session_key_keeps_secret_out_of_debug() {
+        let auth = SSHAuthMethod::password("hunter2");
+        let key = SessionKey::new("web1", 22, "deploy", &auth, None, &[]);
+        assert!(!format!("{key:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn test_secrets_match() {
+        let secret = |value: &str| SecretString::new(value.into());
+        assert!(secrets_match(None, None));
+        assert!(secrets_match(Some(&secret("abc")), Some(&secret("abc"))));
+        assert!(!secrets_match(Some(&secret("abc")), Some(&secret("abd"))));
+        assert!(!secrets_match(Some(&secret("abc")), Some(&secret("abcd"))));
+        assert!(!secrets_match(Some(&secret("abc")), None));
+    }
+
+    #[test]
+    fn test_checkout_without_idle_session() {
+        let auth = SSHAuthMethod::public_key("/nonexistent/id_ed25519", None);
+        let key = SessionKey::new("pool-test.invalid", 22, "nobody", &auth, None, &[]);
+        assert!(checkout(&key).is_none());
+    }
+}
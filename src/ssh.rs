@@ -1,16 +1,23 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
+    ffi::OsStr,
     fs,
     io::{self, Read, Write},
     net::TcpStream,
-    path::Path,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use anyhow::{Error, Result};
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD_NO_PAD};
 use mlua::{Error::RuntimeError, UserData, Value};
-use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+use ssh2::{CheckResult, HashType, KnownHostFileKind, Session, Sftp};
 
+use crate::connection::ConnectionError;
 use crate::executor::{CommandExecutor, SessionResult};
+use crate::local::escape_shell_value;
+use crate::profile::{self, Phase};
+use crate::session_pool::{self, SessionKey};
 use secrecy::{ExposeSecret, SecretString};
 
 /// Authentication method for an SSH connection.
@@ -44,6 +51,15 @@ impl SSHAuthMethod {
             passphrase: passphrase.map(|p| SecretString::new(p.into_boxed_str())),
         }
     }
+
+    /// Returns the private key files this method offers to the server.
+    #[must_use]
+    pub fn key_files(&self) -> Vec<String> {
+        match self {
+            Self::Password(_) => Vec::new(),
+            Self::PublicKey { private_key, .. } => vec![private_key.clone()],
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -87,24 +103,35 @@ impl std::fmt::Display for ElevationMethod {
 pub struct SSHSession {
     pub session: Session,
     pub known_hosts_file: Option<String>,
+    /// Pinned `SHA256:...` host key fingerprints; when set, the server key
+    /// must match one of them and known_hosts is not consulted.
+    pub host_key_fingerprints: Vec<String>,
     env: HashMap<String, String>,
+    /// Variables removed from the environment before each command.
+    env_unset: BTreeSet<String>,
     pub elevation: Elevation,
     stdout: Option<String>,
     stderr: Option<String>,
     exit_code: Option<i32>,
     changed: Option<bool>,
+    deadline: Option<Instant>,
+    /// Set when the session came from or may go back to the session pool.
+    pool_key: Option<SessionKey>,
 }
 
 impl std::fmt::Debug for SSHSession {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SSHSession")
             .field("known_hosts_file", &self.known_hosts_file)
+            .field("host_key_fingerprints", &self.host_key_fingerprints)
             .field("env", &self.env)
+            .field("env_unset", &self.env_unset)
             .field("elevation", &self.elevation)
             .field("stdout", &self.stdout)
             .field("stderr", &self.stderr)
             .field("exit_code", &self.exit_code)
             .field("changed", &self.changed)
+            .field("deadline", &self.deadline)
             .finish_non_exhaustive()
     }
 }
@@ -119,7 +146,9 @@ impl SSHSession {
         Ok(Self {
             session: Session::new()?,
             known_hosts_file: None,
+            host_key_fingerprints: Vec::new(),
             env: HashMap::new(),
+            env_unset: BTreeSet::new(),
             elevation: Elevation {
                 method: ElevationMethod::None,
                 as_user: None,
@@ -128,6 +157,8 @@ impl SSHSession {
             stderr: Some(String::new()),
             exit_code: Some(0),
             changed: Some(false),
+            deadline: None,
+            pool_key: None,
         })
     }
 
@@ -148,65 +179,211 @@ impl SSHSession {
         self.session.set_tcp_stream(tcp);
         self.session.handshake()?;
 
-        if let Some(file) = &self.known_hosts_file {
+        if !self.host_key_fingerprints.is_empty() {
+            self.verify_host_key_fingerprint(address, port)?;
+        } else if let Some(file) = &self.known_hosts_file {
+            let host_key_failure = |message: String| ConnectionError::HostKeyVerification {
+                message,
+                host: address.to_string(),
+                port,
+                known_hosts_file: file.clone(),
+            };
             let host_key = self
                 .session
                 .host_key()
-                .ok_or_else(|| anyhow::anyhow!("Host key is None"))?;
+                .ok_or_else(|| host_key_failure("server did not present a host key".to_string()))?;
             let mut known_hosts = self.session.known_hosts()?;
-            match known_hosts.read_file(Path::new(file.as_str()), KnownHostFileKind::OpenSSH) {
-                Ok(_) => {}
-                Err(_) => {
-                    return Err(Error::msg(format!(
-                        "SSH host key verification failed. Please add the host key to the known_hosts file: {file}"
-                    )));
-                }
+            if let Err(e) =
+                known_hosts.read_file(Path::new(file.as_str()), KnownHostFileKind::OpenSSH)
+            {
+                return Err(host_key_failure(format!("cannot read known_hosts file ({e})")).into());
             }
 
             let known_hosts_check_result = known_hosts.check(address, host_key.0);
             match known_hosts_check_result {
                 CheckResult::Match => {}
                 _ => {
-                    return Err(Error::msg(format!(
-                        "SSH host key verification failed ({known_hosts_check_result:?}). Please check the known_hosts file: {file}"
-                    )));
+                    return Err(host_key_failure(format!(
+                        "{known_hosts_check_result:?} in known_hosts"
+                    ))
+                    .into());
                 }
             }
         }
 
-        match auth_method {
-            SSHAuthMethod::Password(password) => {
-                self.session
-                    .userauth_password(username, password.expose_secret())?;
-            }
+        let tried_keys = auth_method.key_files();
+        let auth_failure = |message: String| ConnectionError::Authentication {
+            message,
+            host: format!("{address}:{port}"),
+            user: username.to_string(),
+            tried_keys: tried_keys.clone(),
+        };
+
+        let auth_result = match auth_method {
+            SSHAuthMethod::Password(password) => self
+                .session
+                .userauth_password(username, password.expose_secret()),
             SSHAuthMethod::PublicKey {
                 private_key,
                 passphrase,
-            } => {
-                self.session.userauth_pubkey_file(
-                    username,
-                    None,
-                    Path::new(&private_key),
-                    passphrase
-                        .as_ref()
-                        .map(secrecy::ExposeSecret::expose_secret),
-                )?;
-            }
+            } => self.session.userauth_pubkey_file(
+                username,
+                None,
+                Path::new(&private_key),
+                passphrase
+                    .as_ref()
+                    .map(secrecy::ExposeSecret::expose_secret),
+            ),
+        };
+        if let Err(e) = auth_result {
+            return Err(auth_failure(e.to_string()).into());
         }
 
         if !self.session.authenticated() {
-            return Err(Error::msg("SSH authentication failed."));
+            return Err(auth_failure("server rejected the credentials".to_string()).into());
         }
 
         Ok(())
     }
 
+    /// Check the server's host key against the pinned fingerprints.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConnectionError::HostKeyFingerprint`] if none matches.
+    fn verify_host_key_fingerprint(&self, address: &str, port: u16) -> Result<()> {
+        let actual = self
+            .session
+            .host_key_hash(HashType::Sha256)
+            .map(|hash| format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)));
+        if let Some(actual) = &actual
+            && self
+                .host_key_fingerprints
+                .iter()
+                .any(|pinned| fingerprint_matches(pinned, actual))
+        {
+            return Ok(());
+        }
+        Err(ConnectionError::HostKeyFingerprint {
+            host: address.to_string(),
+            port,
+            actual: actual.unwrap_or_else(|| "no host key".to_string()),
+            expected: self.host_key_fingerprints.clone(),
+        }
+        .into())
+    }
+
+    /// Arm libssh2's blocking timeout with the time left before the task
+    /// deadline, so the next channel/SFTP/SCP operation cannot outlive it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deadline has already passed.
+    fn arm_deadline(&self) -> Result<()> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                anyhow::bail!("task timeout exceeded");
+            }
+            self.session.set_timeout(timeout_millis(remaining));
+        }
+        Ok(())
+    }
+
+    /// Connect like [`SSHSession::connect`], reusing an idle pooled session
+    /// opened with the same parameters when there is one. Pass the session
+    /// back with [`SSHSession::release`] once the task is done.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`SSHSession::connect`].
+    pub fn connect_pooled(
+        &mut self,
+        address: &str,
+        port: u16,
+        username: &str,
+        auth_method: SSHAuthMethod,
+    ) -> Result<()> {
+        let key = SessionKey::new(
+            address,
+            port,
+            username,
+            &auth_method,
+            self.known_hosts_file.as_deref(),
+            &self.host_key_fingerprints,
+        );
+        if let Some(session) = session_pool::checkout(&key) {
+            self.session = session;
+        } else {
+            self.connect(address, port, username, auth_method)?;
+        }
+        self.pool_key = Some(key);
+        Ok(())
+    }
+
+    /// Hand a pooled session back for reuse by later tasks on the same host.
+    /// Sessions not opened with [`SSHSession::connect_pooled`] are dropped.
+    pub fn release(self) {
+        if let Some(key) = self.pool_key {
+            session_pool::checkin(key, self.session);
+        }
+    }
+
+    /// Tear down the transport, e.g. after the task deadline passed mid-run.
+    pub fn abort(&self, reason: &str) {
+        let _ = self.session.disconnect(None, reason, None);
+    }
+
+    /// Drain a command's stdout and stderr, then wait for it to exit.
+    ///
+    /// The libssh2 timeout only bounds each blocking call, so a command
+    /// that keeps printing would never trip it; the deadline is checked
+    /// again after every chunk read, and the session is torn down once it
+    /// has passed so the remote command does not keep running.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the task deadline passes.
+    fn finish_command(&self, mut channel: ssh2::Channel) -> Result<(String, String, i32)> {
+        let output = read_until_deadline(&mut channel, self.deadline).and_then(|stdout| {
+            let stderr = read_until_deadline(&mut channel.stderr(), self.deadline)?;
+            Ok((stdout, stderr))
+        });
+        let (stdout, stderr) = match output {
+            Ok(output) => output,
+            Err(e) => {
+                self.abort_if_expired();
+                return Err(e);
+            }
+        };
+        self.arm_deadline()?;
+        channel.wait_close()?;
+        let exit_code = channel.exit_status()?;
+
+        Ok((stdout, stderr, exit_code))
+    }
+
+    /// Abort the session if the task deadline has passed, e.g. after a read
+    /// gave up on a command that is still running.
+    fn abort_if_expired(&self) {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.abort("task timeout");
+        }
+    }
+
     fn execute_command(&self, command: &str) -> Result<ssh2::Channel> {
+        self.arm_deadline()?;
         let mut channel = self.session.channel_session()?;
         let mut command = command.to_string();
         for (key, value) in &self.env {
             command = format!("export {key}={value}\n") + &command;
         }
+        for key in &self.env_unset {
+            command = format!("unset {key}\n") + &command;
+        }
         channel.exec(command.as_str())?;
         Ok(channel)
     }
@@ -214,15 +391,7 @@ impl SSHSession {
 
 impl CommandExecutor for SSHSession {
     fn cmd(&mut self, command: &str) -> Result<(String, String, i32)> {
-        let mut channel = self.execute_command(command)?;
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-
-        channel.read_to_string(&mut stdout)?;
-        channel.stderr().read_to_string(&mut stderr)?;
-        stdout = stdout.trim_end_matches('\n').to_string();
-        channel.wait_close()?;
-        let exit_code = channel.exit_status()?;
+        let (stdout, stderr, exit_code) = self.cmdq(command)?;
 
         if let Some(stdout_buf) = self.stdout.as_mut() {
             stdout_buf.push_str(&stdout);
@@ -236,17 +405,25 @@ impl CommandExecutor for SSHSession {
     }
 
     fn cmdq(&self, command: &str) -> Result<(String, String, i32)> {
-        let mut channel = self.execute_command(command)?;
-        let mut stdout = String::new();
-        let mut stderr = String::new();
+        profile::time(Phase::Command, command, || {
+            let channel = self.execute_command(command)?;
+            let (stdout, stderr, exit_code) = self.finish_command(channel)?;
 
-        channel.read_to_string(&mut stdout)?;
-        channel.stderr().read_to_string(&mut stderr)?;
-        stdout = stdout.trim_end_matches('\n').to_string();
-        channel.wait_close()?;
-        let exit_code = channel.exit_status()?;
+            Ok((stdout.trim_end_matches('\n').to_string(), stderr, exit_code))
+        })
+    }
 
-        Ok((stdout, stderr, exit_code))
+    fn cmd_stream(&self, command: &str, on_line: &mut dyn FnMut(&str)) -> Result<i32> {
+        profile::time(Phase::Command, command, || {
+            let mut channel = self.execute_command(command)?;
+            if let Err(e) = stream_lines_until_deadline(&mut channel, self.deadline, on_line) {
+                self.abort_if_expired();
+                return Err(e);
+            }
+            self.arm_deadline()?;
+            channel.wait_close()?;
+            Ok(channel.exit_status()?)
+        })
     }
 
     fn prepare_command(&self, command: &str) -> String {
@@ -264,78 +441,92 @@ impl CommandExecutor for SSHSession {
     }
 
     fn set_env(&mut self, key: &str, value: &str) {
+        self.env_unset.remove(key);
         *self
             .env
             .entry(key.to_string())
             .or_insert_with(|| value.to_string()) = value.to_string();
     }
 
+    fn unset_env(&mut self, key: &str) {
+        self.env.remove(key);
+        self.env_unset.insert(key.to_string());
+    }
+
+    fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
     fn get_remote_env(&self, var: &str) -> Result<String> {
-        let mut channel = self.execute_command(format!("echo ${var}").as_str())?;
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout)?;
-        stdout = stdout.trim_end_matches('\n').to_string();
-        channel.wait_close()?;
+        let channel = self.execute_command(format!("echo ${var}").as_str())?;
+        let (stdout, _, _) = self.finish_command(channel)?;
 
-        Ok(stdout)
+        Ok(stdout.trim_end_matches('\n').to_string())
     }
 
     fn get_tmpdir(&self) -> Result<String> {
-        let mut channel = self.execute_command("tmpdir=`for dir in \"$HOME/.komandan/tmp\" \"/tmp/komandan\"; do if [ -d \"$dir\" ] || mkdir -p \"$dir\" 2>/dev/null; then echo \"$dir\"; break; fi; done`; [ -z \"$tmpdir\" ] && { exit 1; } || echo \"$tmpdir\"")?;
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout)?;
-        stdout = stdout.trim_end_matches('\n').to_string();
-        channel.wait_close()?;
+        let channel = self.execute_command("tmpdir=`for dir in \"$HOME/.komandan/tmp\" \"/tmp/komandan\"; do if [ -d \"$dir\" ] || mkdir -p \"$dir\" 2>/dev/null; then echo \"$dir\"; break; fi; done`; [ -z \"$tmpdir\" ] && { exit 1; } || echo \"$tmpdir\"")?;
+        let (stdout, _, _) = self.finish_command(channel)?;
 
-        Ok(stdout)
+        Ok(stdout.trim_end_matches('\n').to_string())
     }
 
     fn upload(&self, local_path: &Path, remote_path: &Path) -> Result<()> {
-        let sftp = self.session.sftp()?;
+        profile::time(Phase::Transfer, "", || {
+            self.arm_deadline()?;
+            let sftp = self.session.sftp()?;
 
-        if local_path.is_dir() {
-            upload_directory(&sftp, local_path, remote_path)?;
-        } else {
-            upload_file(&sftp, local_path, remote_path)?;
-        }
+            if local_path.is_dir() {
+                upload_directory(&sftp, local_path, remote_path)?;
+            } else {
+                upload_file(&sftp, local_path, remote_path)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn download(&self, remote_path: &Path, local_path: &Path) -> Result<()> {
-        let sftp = self.session.sftp()?;
-        let stat = sftp.stat(remote_path)?;
+        profile::time(Phase::Transfer, "", || {
+            self.arm_deadline()?;
+            let sftp = self.session.sftp()?;
+            let stat = sftp.stat(remote_path)?;
 
-        if stat.is_dir() {
-            download_directory(&sftp, remote_path, local_path)?;
-        } else {
-            download_file(&sftp, remote_path, local_path)?;
-        }
+            if stat.is_dir() {
+                download_directory(&sftp, remote_path, local_path)?;
+            } else {
+                download_file(&sftp, remote_path, local_path)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn write_remote_file(&self, remote_path: &Path, content: &[u8]) -> Result<()> {
-        let content_length = content.len() as u64;
-        let mut remote_file = self
-            .session
-            .scp_send(remote_path, 0o644, content_length, None)?;
-        remote_file.write_all(content)?;
-        remote_file.send_eof()?;
-        remote_file.wait_eof()?;
-        remote_file.close()?;
-        remote_file.wait_close()?;
+        profile::time(Phase::Transfer, "", || {
+            self.arm_deadline()?;
+            let content_length = content.len() as u64;
+            let mut remote_file =
+                self.session
+                    .scp_send(remote_path, 0o644, content_length, None)?;
+            remote_file.write_all(content)?;
+            remote_file.send_eof()?;
+            remote_file.wait_eof()?;
+            remote_file.close()?;
+            remote_file.wait_close()?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn chmod(&self, remote_path: &Path, mode: &str) -> Result<()> {
-        let mut channel =
-            self.execute_command(&format!("chmod {} {}", mode, remote_path.to_string_lossy()))?;
-        let mut stderr = String::new();
-        channel.stderr().read_to_string(&mut stderr)?;
-        channel.wait_close()?;
-        let exit_code = channel.exit_status()?;
+        u32::from_str_radix(mode, 8)
+            .map_err(|e| anyhow::Error::new(e).context(format!("Invalid chmod mode: {mode}")))?;
+        let channel = self.execute_command(&format!(
+            "chmod {mode} {}",
+            escape_shell_value(&remote_path.to_string_lossy())
+        ))?;
+        let (_, stderr, exit_code) = self.finish_command(channel)?;
 
         if exit_code == 0 {
             Ok(())
@@ -364,6 +555,31 @@ impl CommandExecutor for SSHSession {
     }
 }
 
+/// Whether a pinned fingerprint, written like `ssh-keygen -l` prints it
+/// (`SHA256:` prefix optional, trailing `=` padding ignored), names `actual`.
+fn fingerprint_matches(pinned: &str, actual: &str) -> bool {
+    let normalize = |fingerprint: &str| {
+        let fingerprint = fingerprint.trim();
+        fingerprint
+            .strip_prefix("SHA256:")
+            .unwrap_or(fingerprint)
+            .trim_end_matches('=')
+            .to_string()
+    };
+    normalize(pinned) == normalize(actual)
+}
+
+/// Append `name` to a path on the target. Targets are POSIX hosts, so the
+/// separator is always `/`, independent of the control machine.
+fn remote_join(base: &Path, name: &OsStr) -> PathBuf {
+    let mut joined = base.as_os_str().to_owned();
+    if !joined.is_empty() && !joined.to_string_lossy().ends_with('/') {
+        joined.push("/");
+    }
+    joined.push(name);
+    PathBuf::from(joined)
+}
+
 fn upload_file(sftp: &Sftp, local_path: &Path, remote_path: &Path) -> io::Result<()> {
     let mut local_file = fs::File::open(local_path)?;
     let mut remote_file = sftp.create(remote_path)?;
@@ -382,7 +598,7 @@ fn upload_directory(sftp: &Sftp, local_path: &Path, remote_path: &Path) -> io::R
         let entry = entry?;
         let entry_path = entry.path();
         let entry_name = entry.file_name();
-        let remote_entry_path = remote_path.join(entry_name);
+        let remote_entry_path = remote_join(remote_path, &entry_name);
 
         if entry_path.is_dir() {
             upload_directory(sftp, &entry_path, &remote_entry_path)?;
@@ -396,6 +612,9 @@ fn upload_directory(sftp: &Sftp, local_path: &Path, remote_path: &Path) -> io::R
 
 fn download_file(sftp: &Sftp, remote_path: &Path, local_path: &Path) -> io::Result<()> {
     let mut remote_file = sftp.open(remote_path)?;
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
     let mut local_file = fs::File::create(local_path)?;
 
     io::copy(&mut remote_file, &mut local_file)?;
@@ -413,7 +632,7 @@ fn download_directory(sftp: &Sftp, remote_path: &Path, local_path: &Path) -> io:
             .0
             .file_name()
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid filename"))?;
-        let remote_entry_path = remote_path.join(entry_name);
+        let remote_entry_path = remote_join(remote_path, entry_name);
         let local_entry_path = local_path.join(entry_name);
 
         if entry_name == "." || entry_name == ".." {
@@ -495,6 +714,10 @@ impl UserData for SSHSession {
             Ok(())
         });
 
+        methods.add_function("with_lock", crate::lock::with_lock::<Self>);
+        methods.add_function("interpreter", crate::interpreter::lua_interpreter::<Self>);
+        methods.add_function("run_script", crate::interpreter::lua_run_script::<Self>);
+
         methods.add_method_mut(
             "write_remote_file",
             |_, this, (remote_path, content): (String, String)| {
@@ -580,10 +803,148 @@ impl UserData for SSHSession {
     }
 }
 
+/// `remaining` as a libssh2 timeout: whole milliseconds rounded up, and at
+/// least 1, since 0 means no timeout at all.
+fn timeout_millis(remaining: Duration) -> u32 {
+    u32::try_from(remaining.as_micros().div_ceil(1000))
+        .unwrap_or(u32::MAX)
+        .max(1)
+}
+
+/// Read `reader` to the end in chunks, failing with "task timeout
+/// exceeded" once `deadline` passes even while data keeps arriving.
+fn read_until_deadline(reader: &mut impl Read, deadline: Option<Instant>) -> Result<String> {
+    let mut output = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        output.extend_from_slice(&chunk[..read]);
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            anyhow::bail!("task timeout exceeded");
+        }
+    }
+    Ok(String::from_utf8(output)?)
+}
+
+/// Feed `reader` to `on_line` line by line (without the trailing newline),
+/// with the same deadline handling as [`read_until_deadline`].
+fn stream_lines_until_deadline(
+    reader: &mut impl Read,
+    deadline: Option<Instant>,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let mut pending = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        pending.extend_from_slice(&chunk[..read]);
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            on_line(std::str::from_utf8(&line[..end])?);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            anyhow::bail!("task timeout exceeded");
+        }
+    }
+    if !pending.is_empty() {
+        on_line(std::str::from_utf8(&pending)?);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A command that never stops printing.
+    struct Endless;
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf.fill(b'y');
+            if let Some(last) = buf.last_mut() {
+                *last = b'\n';
+            }
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_read_until_deadline_stops_endless_output() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result = read_until_deadline(&mut Endless, Some(deadline));
+        assert!(
+            result.is_err_and(|e| e.to_string() == "task timeout exceeded"),
+            "endless output must hit the deadline"
+        );
+
+        let mut lines = 0;
+        let result = stream_lines_until_deadline(&mut Endless, Some(deadline), &mut |_| lines += 1);
+        assert!(result.is_err());
+        assert!(lines > 0);
+    }
+
+    #[test]
+    fn test_read_until_deadline_reads_to_end() -> Result<()> {
+        let output = read_until_deadline(&mut &b"one\ntwo\n"[..], None)?;
+        assert_eq!(output, "one\ntwo\n");
+
+        let mut lines = Vec::new();
+        stream_lines_until_deadline(&mut &b"one\ntwo"[..], None, &mut |line| {
+            lines.push(line.to_string());
+        })?;
+        assert_eq!(lines, ["one", "two"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout_millis_rounds_up() {
+        assert_eq!(timeout_millis(Duration::from_micros(1)), 1);
+        assert_eq!(timeout_millis(Duration::from_micros(999)), 1);
+        assert_eq!(timeout_millis(Duration::from_micros(1001)), 2);
+        assert_eq!(timeout_millis(Duration::from_millis(1500)), 1500);
+        assert_eq!(timeout_millis(Duration::from_secs(u64::MAX)), u32::MAX);
+    }
+
+    #[test]
+    fn test_fingerprint_matches() {
+        let actual = "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s";
+        assert!(fingerprint_matches(actual, actual));
+        assert!(fingerprint_matches(
+            "uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s=",
+            actual
+        ));
+        assert!(!fingerprint_matches(
+            "SHA256:unIvztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s",
+            actual
+        ));
+        assert!(!fingerprint_matches("", actual));
+    }
+
+    #[test]
+    fn test_remote_join() {
+        let name = OsStr::new("app.conf");
+        assert_eq!(
+            remote_join(Path::new("/etc/app"), name),
+            PathBuf::from("/etc/app/app.conf")
+        );
+        assert_eq!(
+            remote_join(Path::new("/etc/app/"), name),
+            PathBuf::from("/etc/app/app.conf")
+        );
+        assert_eq!(remote_join(Path::new(""), name), PathBuf::from("app.conf"));
+    }
+
     #[test]
     fn test_prepare_command() -> anyhow::Result<()> {
         let mut session = SSHSession::new()?;
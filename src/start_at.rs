@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Hosts that reached the `--start-at-task` task, by display name.
+static STARTED_HOSTS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+fn started_hosts() -> &'static Mutex<HashSet<String>> {
+    STARTED_HOSTS.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+/// Whether `task_display` on `host` comes before the task named `start_at`,
+/// and so is skipped by `--start-at-task`.
+///
+/// Each host skips its tasks until it reaches one named `start_at`; that task
+/// and every later one run.
+pub fn before_start_task(start_at: &str, task_display: &str, host: &str) -> bool {
+    let mut started = started_hosts()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if started.contains(host) {
+        return false;
+    }
+    if task_display == start_at {
+        started.insert(host.to_string());
+        return false;
+    }
+    true
+}
+
+/// Warn when no host reached the task named `start_at`, which usually means
+/// a typo in the name, as every task was skipped.
+pub fn warn_if_not_reached(start_at: &str) {
+    let started = started_hosts()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if started.is_empty() {
+        eprintln!("Warning: no task named '{start_at}' was found; every task was skipped.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_before_start_task_is_tracked_per_host() {
+        let host_a = "test_start_at_a";
+        let host_b = "test_start_at_b";
+        assert!(before_start_task("migrate", "install", host_a));
+        assert!(before_start_task("migrate", "install", host_b));
+        assert!(!before_start_task("migrate", "migrate", host_a));
+        assert!(!before_start_task("migrate", "restart", host_a));
+        assert!(!before_start_task("migrate", "install", host_a));
+        assert!(before_start_task("migrate", "restart", host_b));
+        assert!(!before_start_task("migrate", "migrate", host_b));
+    }
+}
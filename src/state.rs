@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use mlua::{DeserializeOptions, Lua, LuaSerdeExt, Table, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::report::TaskStatus;
+use crate::util::{host_display, task_display};
+
+/// A task run on a host during this run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TaskRun {
+    host: String,
+    task: String,
+    signature: String,
+    status: TaskStatus,
+}
+
+/// The last applied state of a task on a host, as kept in the state file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskState {
+    pub signature: String,
+    pub status: String,
+    /// Seconds since the Unix epoch.
+    pub applied_at: u64,
+}
+
+/// The state file: the last applied state of every task, per host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateFile {
+    pub hosts: BTreeMap<String, BTreeMap<String, TaskState>>,
+}
+
+/// Why a task on a host has drifted from the last applied state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskDrift {
+    pub task: String,
+    pub reason: &'static str,
+}
+
+/// The drift of one host: the tasks checked and the ones that drifted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostDrift {
+    pub host: String,
+    pub tasks: usize,
+    pub drifted: Vec<TaskDrift>,
+}
+
+#[derive(Debug, Default)]
+struct Runs {
+    runs: Vec<TaskRun>,
+    /// How often a task name was seen per host, to tell repeated names apart.
+    seen: HashMap<(String, String), usize>,
+}
+
+static RUNS: OnceLock<Mutex<Runs>> = OnceLock::new();
+
+fn runs() -> &'static Mutex<Runs> {
+    RUNS.get_or_init(|| Mutex::new(Runs::default()))
+}
+
+/// Parameter names, matched as substrings of the lowercased key, whose
+/// values are left out of signatures: the state file is likely committed,
+/// and an unsalted hash of a weak secret can be guessed offline.
+const SECRET_PARAM_MARKERS: [&str; 6] = [
+    "password",
+    "passphrase",
+    "secret",
+    "token",
+    "private_key",
+    "api_key",
+];
+
+/// Stands in for a secret parameter in a signature, so setting or removing
+/// one still changes it.
+const REDACTED: &str = "<redacted>";
+
+/// The idempotency signature of `task`: a SHA-256 of its name, module name
+/// and module parameters.
+///
+/// Parameters named like secrets (see [`SECRET_PARAM_MARKERS`]), at any
+/// depth, count only by their presence unless they hold a vault-encrypted
+/// value, whose ciphertext is hashed as is. Functions and userdata values are
+/// ignored. Changing such a value alone is therefore not reported as drift.
+///
+/// # Errors
+///
+/// Returns an error if the module or its parameters cannot be read.
+pub fn signature(lua: &Lua, task: &Table) -> mlua::Result<String> {
+    let module = task.get::<Table>(1)?;
+    let options = DeserializeOptions::new().deny_unsupported_types(false);
+    let mut params =
+        lua.from_value_with::<serde_json::Value>(module.get::<Value>("params")?, options)?;
+    redact_secrets(&mut params);
+    let definition = json!({
+        "task": task.get::<Option<String>>("name")?,
+        "module": module.get::<Option<String>>("name")?,
+        "params": params,
+    });
+    Ok(format!("{:x}", Sha256::digest(definition.to_string())))
+}
+
+/// Replace the plaintext values of secret-named keys in `value` with
+/// [`REDACTED`].
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields {
+                let key = key.to_lowercase();
+                if !SECRET_PARAM_MARKERS
+                    .iter()
+                    .any(|marker| key.contains(marker))
+                {
+                    redact_secrets(field);
+                } else if !field.as_str().is_some_and(crate::vault::is_encrypted) {
+                    *field = serde_json::Value::String(REDACTED.to_string());
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Record the outcome of `task` on `host` when `--state-file` is set.
+/// Skipped tasks are not recorded.
+///
+/// # Errors
+///
+/// Returns an error if the signature of the task cannot be computed.
+pub fn record_task(lua: &Lua, task: &Table, host: &Table, status: &TaskStatus) -> mlua::Result<()> {
+    if crate::args::global_flags().state_file.is_none()
+        || matches!(status, TaskStatus::Skipped | TaskStatus::Unreachable)
+    {
+        return Ok(());
+    }
+    let signature = signature(lua, task)?;
+    let (host, name) = (host_display(host), task_display(task));
+    let mut runs = runs().lock().unwrap_or_else(PoisonError::into_inner);
+    let seen = runs.seen.entry((host.clone(), name.clone())).or_default();
+    *seen += 1;
+    let task = match *seen {
+        1 => name,
+        count => format!("{name} #{count}"),
+    };
+    runs.runs.push(TaskRun {
+        host,
+        task,
+        signature,
+        status: status.clone(),
+    });
+    Ok(())
+}
+
+/// Take the tasks recorded so far, starting afresh.
+fn take_runs() -> Vec<TaskRun> {
+    std::mem::take(&mut *runs().lock().unwrap_or_else(PoisonError::into_inner)).runs
+}
+
+/// Read the state file at `path`; an empty state when it does not exist.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed.
+pub fn load(path: &str) -> Result<StateFile> {
+    if !Path::new(path).exists() {
+        return Ok(StateFile::default());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the state file {path}"))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid state file {path}"))
+}
+
+/// Merge the tasks run so far into the state file at `path`. Tasks not run
+/// this time, e.g. because of `--tags`, keep their last applied state.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or written.
+pub fn save(path: &str) -> Result<()> {
+    let mut state = load(path)?;
+    let applied_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    for run in take_runs() {
+        state.hosts.entry(run.host).or_default().insert(
+            run.task,
+            TaskState {
+                signature: run.signature,
+                status: run.status.to_string().to_lowercase(),
+                applied_at,
+            },
+        );
+    }
+    let content = serde_json::to_string_pretty(&state)?;
+    fs::write(path, content + "\n")
+        .with_context(|| format!("Failed to write the state file {path}"))
+}
+
+/// Compare the planned `runs` of a dry run against the last applied `state`,
+/// per host in the order the hosts were first run.
+fn compare(state: &StateFile, runs: &[TaskRun]) -> Vec<HostDrift> {
+    let mut hosts: Vec<HostDrift> = Vec::new();
+    for run in runs {
+        let index = hosts
+            .iter()
+            .position(|host| host.host == run.host)
+            .unwrap_or_else(|| {
+                hosts.push(HostDrift {
+                    host: run.host.clone(),
+                    tasks: 0,
+                    drifted: Vec::new(),
+                });
+                hosts.len() - 1
+            });
+        let applied = state
+            .hosts
+            .get(&run.host)
+            .and_then(|tasks| tasks.get(&run.task));
+        let reason = match applied {
+            _ if run.status == TaskStatus::Failed => Some("could not be checked"),
+            None => Some("never applied"),
+            Some(applied) if applied.signature != run.signature => Some("definition changed"),
+            Some(applied) if applied.status == "failed" => Some("failed on the last run"),
+            Some(_) if run.status == TaskStatus::Changed => Some("would change"),
+            Some(_) => None,
+        };
+        let host = &mut hosts[index];
+        host.tasks += 1;
+        if let Some(reason) = reason {
+            host.drifted.push(TaskDrift {
+                task: run.task.clone(),
+                reason,
+            });
+        }
+    }
+    hosts
+}
+
+/// Compare the dry run against the state file at `path` and print which
+/// hosts have drifted.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read.
+pub fn print_drift(path: &str) -> Result<()> {
+    let hosts = compare(&load(path)?, &take_runs());
+    let width = 80;
+    println!();
+    println!("{:=^width$}", " Drift Report ");
+    for host in &hosts {
+        if host.drifted.is_empty() {
+            println!("{}: in sync ({} tasks)", host.host, host.tasks);
+            continue;
+        }
+        println!(
+            "{}: drifted ({} of {} tasks)",
+            host.host,
+            host.drifted.len(),
+            host.tasks
+        );
+        for task in &host.drifted {
+            println!("  - {}: {}", task.task, task.reason);
+        }
+    }
+    println!("{:-<width$}", "");
+    let drifted = hosts.iter().filter(|host| !host.drifted.is_empty()).count();
+    println!("Drifted hosts: {drifted} of {}", hosts.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::chunk;
+
+    use super::*;
+
+    fn run(host: &str, task: &str, signature: &str, status: TaskStatus) -> TaskRun {
+        TaskRun {
+            host: host.to_string(),
+            task: task.to_string(),
+            signature: signature.to_string(),
+            status,
+        }
+    }
+
+    fn applied(signature: &str, status: &str) -> TaskState {
+        TaskState {
+            signature: signature.to_string(),
+            status: status.to_string(),
+            applied_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_signature() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let signatures = lua
+            .load(chunk! {
+                local function task(cmd)
+                    return {
+                        name = "Run",
+                        { name = "cmd", params = { cmd = cmd, callback = function() end } },
+                    }
+                end
+                return { task("true"), task("true"), task("false") }
+            })
+            .eval::<Vec<Table>>()?
+            .iter()
+            .map(|task| signature(&lua, task))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        assert_eq!(signatures[0], signatures[1]);
+        assert_ne!(signatures[0], signatures[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_leaves_out_secrets() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let signatures = lua
+            .load(chunk! {
+                local function task(user)
+                    return {
+                        name = "User",
+                        { name = "postgresql_user", params = user },
+                    }
+                end
+                return {
+                    task({ name = "app", password = "hunter2" }),
+                    task({ name = "app", password = "correct horse" }),
+                    task({ name = "app" }),
+                    task({ name = "app", login = { API_Key = "a" } }),
+                    task({ name = "app", login = { API_Key = "b" } }),
+                    task({ name = "app", password = "$KOMANDAN_VAULT;1;a" }),
+                    task({ name = "app", password = "$KOMANDAN_VAULT;1;b" }),
+                }
+            })
+            .eval::<Vec<Table>>()?
+            .iter()
+            .map(|task| signature(&lua, task))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        assert_eq!(signatures[0], signatures[1]);
+        assert_ne!(signatures[0], signatures[2]);
+        assert_eq!(signatures[3], signatures[4]);
+        assert_ne!(signatures[5], signatures[6]);
+
+        let mut params = json!({ "user": "app", "db": { "login_password": "hunter2" } });
+        redact_secrets(&mut params);
+        assert!(!params.to_string().contains("hunter2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut state = StateFile::default();
+        state.hosts.insert(
+            "web".to_string(),
+            BTreeMap::from([
+                ("a".to_string(), applied("1", "changed")),
+                ("b".to_string(), applied("2", "ok")),
+                ("c".to_string(), applied("3", "ok")),
+                ("d".to_string(), applied("4", "failed")),
+            ]),
+        );
+        state.hosts.insert(
+            "db".to_string(),
+            BTreeMap::from([("a".to_string(), applied("1", "ok"))]),
+        );
+
+        let runs = [
+            run("web", "a", "1", TaskStatus::OK),
+            run("web", "b", "2", TaskStatus::Changed),
+            run("web", "c", "9", TaskStatus::OK),
+            run("web", "d", "4", TaskStatus::OK),
+            run("web", "e", "5", TaskStatus::OK),
+            run("db", "a", "1", TaskStatus::OK),
+            run("cache", "a", "1", TaskStatus::Failed),
+        ];
+        let hosts = compare(&state, &runs);
+        let reasons = |host: &HostDrift| {
+            host.drifted
+                .iter()
+                .map(|drift| format!("{}: {}", drift.task, drift.reason))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[0].tasks, 5);
+        assert_eq!(
+            reasons(&hosts[0]),
+            [
+                "b: would change",
+                "c: definition changed",
+                "d: failed on the last run",
+                "e: never applied"
+            ]
+        );
+        assert!(hosts[1].drifted.is_empty());
+        assert_eq!(reasons(&hosts[2]), ["a: could not be checked"]);
+    }
+
+    #[test]
+    fn test_load_missing_and_invalid() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        let path = path.to_string_lossy();
+        assert_eq!(load(&path)?, StateFile::default());
+
+        fs::write(path.as_ref(), "not json")?;
+        assert!(load(&path).is_err());
+        Ok(())
+    }
+}
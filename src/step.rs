@@ -0,0 +1,115 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use mlua::Error::RuntimeError;
+
+/// Set once the user answered "continue" to a `--step` question.
+static CONTINUE_ALL: AtomicBool = AtomicBool::new(false);
+
+/// Serializes `--step` questions asked from parallel workers.
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// An answer to a `--step` question.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepChoice {
+    Run,
+    Skip,
+    ContinueAll,
+}
+
+impl StepChoice {
+    fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => Some(Self::Run),
+            "n" | "no" => Some(Self::Skip),
+            "c" | "continue" => Some(Self::ContinueAll),
+            _ => None,
+        }
+    }
+}
+
+/// Reads one line of input; `None` at the end of input.
+type ReadLine<'a> = dyn FnMut(&str) -> io::Result<Option<String>> + 'a;
+
+/// Ask whether to run `task_display` on `host_display` under `--step`.
+/// Returns `false` when the task should be skipped. Once the user answers
+/// "continue", every later task runs without asking.
+///
+/// # Errors
+///
+/// Returns an error if stdin is closed or cannot be read.
+pub fn confirm_step(task_display: &str, host_display: &str) -> mlua::Result<bool> {
+    let _guard = PROMPT_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if CONTINUE_ALL.load(Ordering::SeqCst) {
+        return Ok(true);
+    }
+    let mut read = read_stdin;
+    match ask(task_display, host_display, &mut read)? {
+        StepChoice::Run => Ok(true),
+        StepChoice::Skip => Ok(false),
+        StepChoice::ContinueAll => {
+            CONTINUE_ALL.store(true, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+}
+
+fn ask(task_display: &str, host_display: &str, read: &mut ReadLine) -> mlua::Result<StepChoice> {
+    let question =
+        format!(">> Run task '{task_display}' on host '{host_display}'? [y]es/[n]o/[c]ontinue: ");
+    loop {
+        let answer = read(&question)
+            .map_err(|e| RuntimeError(format!("--step: failed to read the answer: {e}")))?
+            .ok_or_else(|| RuntimeError("--step: stdin closed before an answer".to_string()))?;
+        if let Some(choice) = StepChoice::parse(&answer) {
+            return Ok(choice);
+        }
+    }
+}
+
+fn read_stdin(question: &str) -> io::Result<Option<String>> {
+    let mut stdout = io::stdout();
+    stdout.write_all(question.as_bytes())?;
+    stdout.flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask_repeats_until_a_valid_answer() -> mlua::Result<()> {
+        let mut answers = vec!["maybe\n", "", " Y \n"].into_iter();
+        let mut questions = Vec::new();
+        let mut read = |question: &str| -> io::Result<Option<String>> {
+            questions.push(question.to_string());
+            Ok(answers.next().map(ToString::to_string))
+        };
+        assert_eq!(ask("deploy", "web1", &mut read)?, StepChoice::Run);
+        assert_eq!(questions.len(), 3);
+        assert!(questions[0].contains("Run task 'deploy' on host 'web1'?"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ask_choices() -> mlua::Result<()> {
+        for (answer, choice) in [
+            ("n", StepChoice::Skip),
+            ("no", StepChoice::Skip),
+            ("c", StepChoice::ContinueAll),
+            ("continue", StepChoice::ContinueAll),
+        ] {
+            let mut read = |_: &str| -> io::Result<Option<String>> { Ok(Some(answer.to_string())) };
+            assert_eq!(ask("deploy", "web1", &mut read)?, choice);
+        }
+        let mut closed = |_: &str| -> io::Result<Option<String>> { Ok(None) };
+        assert!(ask("deploy", "web1", &mut closed).is_err());
+        Ok(())
+    }
+}
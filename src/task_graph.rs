@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+/// Execution plan of a task graph: tasks are referred to by their position
+/// in the input of [`plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskGraph {
+    /// Groups of tasks that may run at once; every task's dependencies are in
+    /// an earlier wave.
+    pub waves: Vec<Vec<usize>>,
+    /// Direct dependencies of every task.
+    pub dependencies: Vec<Vec<usize>>,
+}
+
+/// Order tasks given as `(id, depends_on)` into waves, placing every task in
+/// the first wave after all of its dependencies. Tasks without an id cannot
+/// be depended on.
+///
+/// # Errors
+///
+/// Returns a message if an id is used twice, a dependency names an unknown
+/// task, or the dependencies form a cycle.
+pub fn plan(tasks: &[(Option<String>, Vec<String>)]) -> Result<TaskGraph, String> {
+    let label = |index: usize| {
+        tasks[index]
+            .0
+            .clone()
+            .unwrap_or_else(|| format!("#{}", index + 1))
+    };
+
+    let mut ids = HashMap::new();
+    for (index, (id, _)) in tasks.iter().enumerate() {
+        if let Some(id) = id
+            && ids.insert(id.as_str(), index).is_some()
+        {
+            return Err(format!("duplicate task name '{id}'"));
+        }
+    }
+
+    let mut dependencies = Vec::with_capacity(tasks.len());
+    for (index, (_, depends_on)) in tasks.iter().enumerate() {
+        let mut resolved = Vec::with_capacity(depends_on.len());
+        for dependency in depends_on {
+            let target = ids.get(dependency.as_str()).ok_or_else(|| {
+                format!(
+                    "task '{}' depends on unknown task '{dependency}'",
+                    label(index)
+                )
+            })?;
+            resolved.push(*target);
+        }
+        dependencies.push(resolved);
+    }
+
+    let mut wave_of: Vec<Option<usize>> = vec![None; tasks.len()];
+    let mut waves: Vec<Vec<usize>> = Vec::new();
+    let mut placed = 0;
+    while placed < tasks.len() {
+        let wave = (0..tasks.len())
+            .filter(|&index| {
+                wave_of[index].is_none()
+                    && dependencies[index]
+                        .iter()
+                        .all(|&dependency| wave_of[dependency].is_some())
+            })
+            .collect::<Vec<_>>();
+        if wave.is_empty() {
+            let cycle = (0..tasks.len())
+                .filter(|&index| wave_of[index].is_none())
+                .map(label)
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "dependency cycle between tasks: {}",
+                cycle.join(", ")
+            ));
+        }
+        for &index in &wave {
+            wave_of[index] = Some(waves.len());
+        }
+        placed += wave.len();
+        waves.push(wave);
+    }
+
+    Ok(TaskGraph {
+        waves,
+        dependencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, depends_on: &[&str]) -> (Option<String>, Vec<String>) {
+        (
+            Some(id.to_string()),
+            depends_on.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn test_plan_waves() -> Result<(), String> {
+        let graph = plan(&[
+            task("deploy", &["build", "migrate"]),
+            task("build", &[]),
+            task("migrate", &["backup"]),
+            task("backup", &[]),
+            (None, vec!["deploy".to_string()]),
+        ])?;
+        assert_eq!(graph.waves, vec![vec![1, 3], vec![2], vec![0], vec![4]]);
+        assert_eq!(graph.dependencies[0], vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        assert_eq!(
+            plan(&[task("a", &[]), task("a", &[])]),
+            Err("duplicate task name 'a'".to_string())
+        );
+        assert_eq!(
+            plan(&[task("a", &["missing"])]),
+            Err("task 'a' depends on unknown task 'missing'".to_string())
+        );
+        assert_eq!(
+            plan(&[task("a", &["b"]), task("b", &["a"]), task("c", &[])]),
+            Err("dependency cycle between tasks: a, b".to_string())
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::util::dprint;
+use crate::util::http::http_get;
 use crate::validator::validate_host;
-use http_klien::create_client_from_url;
 use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, Table, Value};
 use std::{fs::File, io::Read};
 
@@ -19,6 +19,8 @@ pub fn parse_hosts_json_file(lua: &Lua, path: Value) -> mlua::Result<Table> {
         return Err(RuntimeError(String::from("Failed to read JSON file")));
     };
 
+    let content = crate::vault::reveal(&content)
+        .map_err(|e| RuntimeError(format!("Failed to decrypt JSON file '{path}': {e:#}")))?;
     let hosts = parse_hosts_json(lua, &content)
         .map_err(|_| RuntimeError(format!("Failed to parse JSON file from '{path}'")))?;
 
@@ -40,24 +42,15 @@ pub fn parse_hosts_json_url(lua: &Lua, url: Value) -> mlua::Result<Table> {
     };
     let url = url_lua_str.to_str()?.to_owned();
 
-    let (client, path) = create_client_from_url(&url)
-        .map_err(|e| RuntimeError(format!("Failed to create client: {e}")))?;
-
-    let content = match client.get(&path) {
-        Ok(response) => {
-            if !response.is_success() {
-                return Err(RuntimeError(format!(
-                    "HTTP request failed with status: {}",
-                    response.status_code
-                )));
-            }
-            String::from_utf8(response.body)
-                .map_err(|e| RuntimeError(format!("Response body is not valid UTF-8: {e}")))?
-        }
-        Err(e) => {
-            return Err(RuntimeError(format!("Failed to fetch URL: {e:?}")));
-        }
-    };
+    let response = http_get(&url).map_err(RuntimeError)?;
+    if !response.success {
+        return Err(RuntimeError(format!(
+            "HTTP request failed with status: {}",
+            response.status
+        )));
+    }
+    let content = String::from_utf8(response.body)
+        .map_err(|e| RuntimeError(format!("Response body is not valid UTF-8: {e}")))?;
 
     let Ok(hosts) = parse_hosts_json(lua, &content) else {
         return Err(RuntimeError(format!("Failed to parse JSON from '{url}'")));
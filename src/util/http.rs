@@ -0,0 +1,184 @@
+//! HTTP client used by the URL-based helpers and `komandan.http`.
+//!
+//! The default `openssl-http` feature uses `http-klien`, which links
+//! OpenSSL, for plain GETs and `ureq` over OpenSSL for `komandan.http`. The
+//! `rustls` feature switches both to `ureq` with a pure-Rust TLS stack
+//! trusting the bundled webpki roots; `rustls-native-certs` makes it trust
+//! the system's CA store instead.
+
+#[cfg(not(any(feature = "openssl-http", feature = "rustls")))]
+compile_error!("enable either the `openssl-http` or the `rustls` feature for the HTTP client");
+
+/// Status line and body of an HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// Numeric status code as sent by the server, e.g. `"404"`.
+    pub status: String,
+    pub success: bool,
+    pub body: Vec<u8>,
+}
+
+/// Fetch `url` with a GET request.
+///
+/// Non-2xx responses are returned, not treated as errors; check
+/// [`HttpResponse::success`].
+///
+/// # Errors
+///
+/// Returns a message if the URL is invalid or the request cannot be made.
+#[cfg(not(feature = "rustls"))]
+pub fn http_get(url: &str) -> Result<HttpResponse, String> {
+    let (client, path) = http_klien::create_client_from_url(url)
+        .map_err(|e| format!("Failed to create client: {e}"))?;
+    let response = client
+        .get(&path)
+        .map_err(|e| format!("Failed to fetch URL: {e:?}"))?;
+    Ok(HttpResponse {
+        status: response.status_code.to_string(),
+        success: response.is_success(),
+        body: response.body,
+    })
+}
+
+/// Fetch `url` with a GET request.
+///
+/// Non-2xx responses are returned, not treated as errors; check
+/// [`HttpResponse::success`].
+///
+/// # Errors
+///
+/// Returns a message if the URL is invalid or the request cannot be made.
+#[cfg(feature = "rustls")]
+pub fn http_get(url: &str) -> Result<HttpResponse, String> {
+    use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+
+    let root_certs = if cfg!(feature = "rustls-native-certs") {
+        RootCerts::PlatformVerifier
+    } else {
+        RootCerts::WebPki
+    };
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .tls_config(
+            TlsConfig::builder()
+                .provider(TlsProvider::Rustls)
+                .root_certs(root_certs)
+                .build(),
+        )
+        .build()
+        .into();
+
+    let mut response = agent
+        .get(url)
+        .call()
+        .map_err(|e| format!("Failed to fetch URL: {e}"))?;
+    let status = response.status();
+    let body = response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|e| format!("Failed to read response body: {e}"))?;
+    Ok(HttpResponse {
+        status: status.as_u16().to_string(),
+        success: status.is_success(),
+        body,
+    })
+}
+
+/// A request sent by [`http_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Option<std::time::Duration>,
+    /// Proxy URL, e.g. `"http://proxy.internal:3128"`.
+    pub proxy: Option<String>,
+    pub follow_redirects: bool,
+    pub validate_certs: bool,
+}
+
+/// Status, headers and body of the response to an [`HttpRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpReply {
+    pub status: u16,
+    /// Header names as sent by the server, in order; repeated headers keep
+    /// one entry each.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Send `request` with `ureq`: over OpenSSL (`native-tls`) trusting the
+/// system's CA store in the default build, or over rustls with the roots
+/// chosen by the `rustls` features.
+///
+/// Non-2xx responses are returned, not treated as errors.
+///
+/// # Errors
+///
+/// Returns a message if the request is invalid or cannot be made.
+pub fn http_request(request: &HttpRequest) -> Result<HttpReply, String> {
+    use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+
+    let (provider, root_certs) = if cfg!(not(feature = "rustls")) {
+        (TlsProvider::NativeTls, RootCerts::PlatformVerifier)
+    } else if cfg!(feature = "rustls-native-certs") {
+        (TlsProvider::Rustls, RootCerts::PlatformVerifier)
+    } else {
+        (TlsProvider::Rustls, RootCerts::WebPki)
+    };
+    let mut config = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .max_redirects(if request.follow_redirects { 10 } else { 0 })
+        .timeout_global(request.timeout)
+        .tls_config(
+            TlsConfig::builder()
+                .provider(provider)
+                .root_certs(root_certs)
+                .disable_verification(!request.validate_certs)
+                .build(),
+        );
+    if let Some(proxy) = &request.proxy {
+        let proxy = ureq::Proxy::new(proxy).map_err(|e| format!("Invalid proxy: {e}"))?;
+        config = config.proxy(Some(proxy));
+    }
+    let agent: ureq::Agent = config.build().into();
+
+    let mut builder = ureq::http::Request::builder()
+        .method(request.method.as_str())
+        .uri(request.url.as_str());
+    for (name, value) in &request.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let result = match &request.body {
+        Some(body) => builder
+            .body(body.as_slice())
+            .map_err(|e| format!("Invalid request: {e}"))
+            .and_then(|request| agent.run(request).map_err(|e| e.to_string())),
+        None => builder
+            .body(())
+            .map_err(|e| format!("Invalid request: {e}"))
+            .and_then(|request| agent.run(request).map_err(|e| e.to_string())),
+    };
+    let mut response = result.map_err(|e| format!("Request failed: {e}"))?;
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let status = response.status().as_u16();
+    let body = response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|e| format!("Failed to read response body: {e}"))?;
+    Ok(HttpReply {
+        status,
+        headers,
+        body,
+    })
+}
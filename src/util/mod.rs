@@ -3,7 +3,13 @@ mod dprint;
 mod filter;
 mod host_info;
 mod hosts_json;
+mod http;
 mod regex_helpers;
+mod retry;
+mod tail;
+mod time;
+mod units;
+mod uuid;
 
 #[cfg(test)]
 mod tests;
@@ -19,4 +25,10 @@ pub use dprint::dprint;
 pub use filter::filter_hosts;
 pub use host_info::host_info;
 pub use hosts_json::{parse_hosts_json_file, parse_hosts_json_url};
+pub use http::{HttpReply, HttpRequest, http_get, http_request};
 pub use regex_helpers::regex_is_match;
+pub use retry::{RetryPolicy, retry};
+pub use tail::tail;
+pub use time::{iso8601, lua_now, lua_sleep, lua_stopwatch, monotonic};
+pub use units::{duration_param, lua_parse_duration, lua_parse_size};
+pub use uuid::{uuid_v4, uuid_v7};
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use mlua::{Error::RuntimeError, Function, Lua, MultiValue, Table, Value};
+
+use super::duration_param;
+
+/// How often and how patiently `komandan.retry` calls its function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of calls, including the first.
+    pub attempts: u32,
+    /// Wait after the first failed attempt.
+    pub delay: Duration,
+    /// Factor applied to the wait after every further failure.
+    pub backoff: f64,
+    /// Upper bound of the wait, if any.
+    pub max_delay: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_secs(1),
+            backoff: 1.0,
+            max_delay: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Read `attempts`, `delay`, `backoff` and `max_delay` from `opts`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `attempts` is below 1, `backoff` is below 1, or a
+    /// duration is invalid.
+    pub fn from_lua_opts(opts: Option<&Table>) -> mlua::Result<Self> {
+        let mut policy = Self::default();
+        let Some(opts) = opts else {
+            return Ok(policy);
+        };
+        if let Some(attempts) = opts.get::<Option<u32>>("attempts")? {
+            if attempts == 0 {
+                return Err(RuntimeError("attempts must be at least 1".to_string()));
+            }
+            policy.attempts = attempts;
+        }
+        if let Some(delay) = duration_param(opts.get::<Value>("delay")?, "delay")? {
+            policy.delay = delay;
+        }
+        if let Some(backoff) = opts.get::<Option<f64>>("backoff")? {
+            if !backoff.is_finite() || backoff < 1.0 {
+                return Err(RuntimeError(
+                    "backoff must be a number of at least 1".to_string(),
+                ));
+            }
+            policy.backoff = backoff;
+        }
+        policy.max_delay = duration_param(opts.get::<Value>("max_delay")?, "max_delay")?;
+        Ok(policy)
+    }
+
+    /// Wait after the `failed`-th failed attempt (counting from 1).
+    #[must_use]
+    pub fn delay_after(&self, failed: u32) -> Duration {
+        let exponent = i32::try_from(failed.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay =
+            Duration::try_from_secs_f64(self.delay.as_secs_f64() * self.backoff.powi(exponent))
+                .unwrap_or(Duration::MAX);
+        self.max_delay
+            .map_or(delay, |max_delay| delay.min(max_delay))
+    }
+}
+
+/// Lua binding: `komandan.retry(fn, opts)` calls `fn(attempt)` until it
+/// returns without raising and, if `opts.check` is given, until
+/// `opts.check(...)` is truthy for its results. Returns the results of the
+/// successful call.
+///
+/// # Errors
+///
+/// Returns an error if the options are invalid, `opts.check` raises, or no
+/// attempt succeeded.
+pub fn retry(_: &Lua, (func, opts): (Function, Option<Table>)) -> mlua::Result<MultiValue> {
+    let policy = RetryPolicy::from_lua_opts(opts.as_ref())?;
+    let check = opts
+        .map(|opts| opts.get::<Option<Function>>("check"))
+        .transpose()?
+        .flatten();
+
+    let mut attempt = 1;
+    loop {
+        let failure = match func.call::<MultiValue>(attempt) {
+            Ok(values) => match &check {
+                Some(check) if !check.call::<bool>(values.clone())? => {
+                    "condition not met".to_string()
+                }
+                _ => return Ok(values),
+            },
+            Err(e) => e.to_string(),
+        };
+        if attempt >= policy.attempts {
+            return Err(RuntimeError(format!(
+                "komandan.retry gave up after {attempt} attempt(s): {failure}"
+            )));
+        }
+        let delay = policy.delay_after(attempt);
+        tracing::debug!(
+            "komandan.retry: attempt {attempt} failed ({failure}), retrying in {}s",
+            delay.as_secs_f64()
+        );
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
+}
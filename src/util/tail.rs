@@ -0,0 +1,163 @@
+use crate::connection::create_connection;
+use crate::local::escape_shell_value;
+use crate::util::{duration_param, host_display};
+use crate::validator::validate_host;
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+
+/// Exit code of coreutils `timeout` when it stops the command.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Streams the tail of a systemd journal unit or a log file to the console.
+///
+/// `opts` takes either `unit` (systemd unit, read with `journalctl`) or `path`
+/// (file, read with `tail`), plus `lines` (default 10) and `follow_for`
+/// (seconds or a duration string such as `"2m"` to keep following new
+/// output; omit to print and return). Each
+/// line is printed as it arrives, prefixed with the host.
+///
+/// # Returns
+/// * `mlua::Result<Table>` - `{ exit_code = ..., lines = <lines printed> }`
+///
+/// # Errors
+/// Returns an error if the host or options are invalid, the connection cannot
+/// be established, or the remote command cannot be run.
+pub fn tail(lua: &Lua, (host, opts): (Value, Table)) -> mlua::Result<Table> {
+    let host_table = if host.is_nil() {
+        let table = lua.create_table()?;
+        table.set("address", "localhost")?;
+        table
+    } else {
+        validate_host(lua, host)?
+    };
+    let host_display = host_display(&host_table);
+
+    let command = build_tail_command(&opts)?;
+    let connection = create_connection(lua, &Value::Table(host_table))?;
+
+    let mut count = 0_u64;
+    let exit_code = connection
+        .cmd_stream(&command, &mut |line: &str| {
+            crate::output::emit_prefixed(&host_display, line);
+            count += 1;
+        })
+        .map_err(|e| RuntimeError(format!("tail on host '{host_display}' failed: {e}")))?;
+
+    let result = lua.create_table()?;
+    result.set(
+        "exit_code",
+        if exit_code == TIMEOUT_EXIT_CODE {
+            0
+        } else {
+            exit_code
+        },
+    )?;
+    result.set("lines", count)?;
+    Ok(result)
+}
+
+/// Build the remote shell command for the `komandan.tail` options.
+///
+/// # Errors
+/// Returns an error unless exactly one of `unit` / `path` is set, or if
+/// `lines` / `follow_for` are not positive.
+fn build_tail_command(opts: &Table) -> mlua::Result<String> {
+    let unit = opts.get::<Option<String>>("unit")?;
+    let path = opts.get::<Option<String>>("path")?;
+    let lines = opts.get::<Option<u32>>("lines")?.unwrap_or(10);
+    let follow_for = duration_param(opts.get::<Value>("follow_for")?, "follow_for")?;
+    if follow_for.is_some_and(|duration| duration.is_zero()) {
+        return Err(RuntimeError(
+            "'follow_for' must be a positive duration".to_string(),
+        ));
+    }
+
+    let follow = follow_for.is_some();
+    let command = match (unit, path) {
+        (Some(unit), None) => format!(
+            "journalctl --no-pager -u {} -n {lines}{}",
+            escape_shell_value(&unit),
+            if follow { " -f" } else { "" }
+        ),
+        (None, Some(path)) => format!(
+            "tail -n {lines}{} {}",
+            if follow { " -F" } else { "" },
+            escape_shell_value(&path)
+        ),
+        _ => {
+            return Err(RuntimeError(
+                "exactly one of 'unit' or 'path' is required".to_string(),
+            ));
+        }
+    };
+
+    Ok(match follow_for {
+        Some(duration) => format!("timeout {} {command} 2>&1", duration.as_secs_f64()),
+        None => format!("{command} 2>&1"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_lua;
+
+    #[test]
+    fn test_build_tail_command_unit() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        opts.set("unit", "nginx")?;
+        opts.set("lines", 100)?;
+        opts.set("follow_for", 30)?;
+        assert_eq!(
+            build_tail_command(&opts)?,
+            "timeout 30 journalctl --no-pager -u 'nginx' -n 100 -f 2>&1"
+        );
+        opts.set("follow_for", "1m30s")?;
+        assert_eq!(
+            build_tail_command(&opts)?,
+            "timeout 90 journalctl --no-pager -u 'nginx' -n 100 -f 2>&1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_tail_command_path() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        opts.set("path", "/var/log/app.log")?;
+        assert_eq!(
+            build_tail_command(&opts)?,
+            "tail -n 10 '/var/log/app.log' 2>&1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_tail_command_requires_one_source() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let opts = lua.create_table()?;
+        assert!(build_tail_command(&opts).is_err());
+        opts.set("unit", "nginx")?;
+        opts.set("path", "/var/log/app.log")?;
+        assert!(build_tail_command(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail_local_file() -> anyhow::Result<()> {
+        let lua = create_lua()?;
+        let mut log = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut log, b"one\ntwo\nthree\n")?;
+
+        let opts = lua.create_table()?;
+        opts.set("path", log.path().display().to_string())?;
+        opts.set("lines", 2)?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+
+        let result = tail(&lua, (Value::Table(host), opts))?;
+        assert_eq!(result.get::<i32>("exit_code")?, 0);
+        assert_eq!(result.get::<u64>("lines")?, 2);
+        Ok(())
+    }
+}
@@ -3,8 +3,8 @@ use tempfile::NamedTempFile;
 use crate::create_lua;
 
 use super::*;
-use mlua::{Table, Value};
-use std::{fs::write, io::Write};
+use mlua::{Table, Value, chunk};
+use std::{fs::write, io::Write, time::Duration};
 
 #[test]
 fn test_dprint_verbose() -> mlua::Result<()> {
@@ -1153,3 +1153,170 @@ MEM_AVAILABLE_KB=524288";
         Some("High-End Server Processor".to_string())
     );
 }
+
+#[test]
+fn test_parse_duration_values() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let parse = |value: Value| lua_parse_duration(&lua, value);
+
+    assert!((parse(Value::Integer(90))? - 90.0).abs() < f64::EPSILON);
+    assert!((parse(Value::String(lua.create_string("5m")?))? - 300.0).abs() < f64::EPSILON);
+    assert!((parse(Value::String(lua.create_string("1h30m")?))? - 5400.0).abs() < f64::EPSILON);
+    assert!((parse(Value::String(lua.create_string("250ms")?))? - 0.25).abs() < f64::EPSILON);
+    assert!((parse(Value::String(lua.create_string("2 d")?))? - 172_800.0).abs() < f64::EPSILON);
+
+    let error = parse(Value::String(lua.create_string("5 parsecs")?))
+        .err()
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    assert!(error.contains("invalid duration '5 parsecs'"));
+    assert!(error.contains("ms, s, m, h or d"));
+    assert!(parse(Value::Integer(-1)).is_err());
+    assert!(parse(Value::Boolean(true)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_size_values() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let parse = |value: Value| lua_parse_size(&lua, value);
+
+    assert_eq!(parse(Value::Integer(512))?, 512);
+    assert_eq!(
+        parse(Value::String(lua.create_string("100MB")?))?,
+        100_000_000
+    );
+    assert_eq!(
+        parse(Value::String(lua.create_string("1.5GiB")?))?,
+        1_610_612_736
+    );
+    assert_eq!(parse(Value::String(lua.create_string("4 kib")?))?, 4096);
+
+    let error = parse(Value::String(lua.create_string("10 bananas")?))
+        .err()
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    assert!(error.contains("invalid size '10 bananas'"));
+    assert!(error.contains("KiB, MiB, GiB, TiB"));
+    assert!(parse(Value::Integer(-1)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_retry_until_success() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let (value, attempts) = lua
+        .load(chunk! {
+            local calls = 0
+            local value = komandan.retry(function(attempt)
+                calls = attempt
+                if attempt < 3 then
+                    error("not ready")
+                end
+                return "ready"
+            end, { attempts = 5, delay = 0 })
+            return value, calls
+        })
+        .eval::<(String, u32)>()?;
+    assert_eq!(value, "ready");
+    assert_eq!(attempts, 3);
+    Ok(())
+}
+
+#[test]
+fn test_retry_gives_up() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let result = lua
+        .load(chunk! {
+            return komandan.retry(function(attempt)
+                return attempt
+            end, {
+                attempts = 2,
+                delay = 0,
+                check = function(attempt) return attempt > 5 end,
+            })
+        })
+        .exec();
+    let error = result.err().map(|e| e.to_string()).unwrap_or_default();
+    assert!(error.contains("gave up after 2 attempt(s): condition not met"));
+    Ok(())
+}
+
+#[test]
+fn test_retry_policy_delays() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let opts = lua.create_table()?;
+    opts.set("delay", 1)?;
+    opts.set("backoff", 2)?;
+    opts.set("max_delay", "5s")?;
+    let policy = RetryPolicy::from_lua_opts(Some(&opts))?;
+    assert_eq!(policy.delay_after(1), Duration::from_secs(1));
+    assert_eq!(policy.delay_after(3), Duration::from_secs(4));
+    assert_eq!(policy.delay_after(4), Duration::from_secs(5));
+
+    opts.set("backoff", 0.5)?;
+    assert!(RetryPolicy::from_lua_opts(Some(&opts)).is_err());
+    opts.set("backoff", 1)?;
+    opts.set("attempts", 0)?;
+    assert!(RetryPolicy::from_lua_opts(Some(&opts)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_uuid_format() -> mlua::Result<()> {
+    let uuid = uuid_v4();
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(uuid.as_bytes()[14], b'4');
+    assert!(matches!(uuid.as_bytes()[19], b'8' | b'9' | b'a' | b'b'));
+    assert_ne!(uuid, uuid_v4());
+
+    let first = uuid_v7();
+    std::thread::sleep(Duration::from_millis(2));
+    let second = uuid_v7();
+    assert_eq!(first.as_bytes()[14], b'7');
+    assert!(first[..13] < second[..13]);
+
+    let lua = create_lua()?;
+    let (v4, v7) = lua
+        .load(chunk! {
+            return komandan.uuid(), komandan.uuid_v7()
+        })
+        .eval::<(String, String)>()?;
+    assert_eq!(v4.as_bytes()[14], b'4');
+    assert_eq!(v7.as_bytes()[14], b'7');
+    Ok(())
+}
+
+#[test]
+fn test_iso8601() {
+    assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(iso8601(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    assert_eq!(iso8601(1_792_122_900_123), "2026-10-16T03:55:00.123Z");
+    assert_eq!(iso8601(-1), "1969-12-31T23:59:59.999Z");
+}
+
+#[test]
+fn test_sleep_now_and_stopwatch() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let (elapsed, measured, iso, epoch_ms) = lua
+        .load(chunk! {
+            local now = komandan.now()
+            local started = komandan.monotonic()
+            local stopwatch = komandan.stopwatch()
+            komandan.sleep("20ms")
+            komandan.sleep(0.01)
+            return komandan.monotonic() - started, stopwatch:elapsed(), now.iso8601, now.epoch_ms
+        })
+        .eval::<(f64, f64, String, i64)>()?;
+    assert!(elapsed >= 0.03);
+    assert!(measured >= 0.03);
+    assert_eq!(iso, iso8601(epoch_ms));
+
+    let result = lua
+        .load(chunk! {
+            komandan.sleep("soon")
+        })
+        .exec();
+    assert!(result.is_err());
+    Ok(())
+}
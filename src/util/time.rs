@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use mlua::{Error::RuntimeError, Lua, MultiValue, Table, Value};
+
+use super::duration_param;
+
+/// Origin of `komandan.monotonic()`: its first call.
+static MONOTONIC_START: OnceLock<Instant> = OnceLock::new();
+
+/// Year, month and day of the date `days` after 1970-01-01 in the proleptic
+/// Gregorian calendar (Howard Hinnant's `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// UTC ISO 8601 timestamp with milliseconds, e.g.
+/// `2026-10-16T03:55:00.123Z`, of `epoch_ms` milliseconds since the Unix
+/// epoch.
+#[must_use]
+pub fn iso8601(epoch_ms: i64) -> String {
+    let seconds = epoch_ms.div_euclid(1000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60,
+        epoch_ms.rem_euclid(1000)
+    )
+}
+
+/// Lua binding: `komandan.now()` returns `{ epoch, epoch_ms, iso8601 }` for
+/// the current time: seconds (with a fraction) and milliseconds since the
+/// Unix epoch, and the UTC ISO 8601 timestamp.
+///
+/// # Errors
+///
+/// Returns an error if the system clock is before the Unix epoch.
+pub fn lua_now(lua: &Lua, (): ()) -> mlua::Result<Table> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError(format!("System clock is before the Unix epoch: {e}")))?;
+    let epoch_ms = i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX);
+    let now = lua.create_table()?;
+    now.set("epoch", elapsed.as_secs_f64())?;
+    now.set("epoch_ms", epoch_ms)?;
+    now.set("iso8601", iso8601(epoch_ms))?;
+    Ok(now)
+}
+
+/// Lua binding: `komandan.sleep(seconds)` pauses the script. Accepts a
+/// number of seconds or a duration string such as `"500ms"` or `"2m"`.
+///
+/// # Errors
+///
+/// Returns an error if the duration is missing or invalid.
+pub fn lua_sleep(_: &Lua, value: Value) -> mlua::Result<()> {
+    let duration = duration_param(value, "seconds")?
+        .ok_or_else(|| RuntimeError("'seconds' is required".to_string()))?;
+    std::thread::sleep(duration);
+    Ok(())
+}
+
+/// Seconds on a clock that never goes backwards, counted from the first
+/// call. Only differences between readings are meaningful.
+#[must_use]
+pub fn monotonic() -> f64 {
+    MONOTONIC_START
+        .get_or_init(Instant::now)
+        .elapsed()
+        .as_secs_f64()
+}
+
+/// Lua binding: `komandan.stopwatch()` returns a table whose `elapsed()`
+/// gives the seconds since the stopwatch was created, on the monotonic
+/// clock.
+///
+/// # Errors
+///
+/// Returns an error if the table or its function cannot be created.
+pub fn lua_stopwatch(lua: &Lua, (): ()) -> mlua::Result<Table> {
+    let started = Instant::now();
+    let stopwatch = lua.create_table()?;
+    stopwatch.set(
+        "elapsed",
+        lua.create_function(move |_, _: MultiValue| Ok(started.elapsed().as_secs_f64()))?,
+    )?;
+    Ok(stopwatch)
+}
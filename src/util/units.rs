@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use mlua::{Error::RuntimeError, Lua, Value};
+
+const DURATION_FORMATS: &str = "a number of seconds or <number><unit> with unit ms, s, m, h or d, e.g. \"90s\", \"5m\", \"1h30m\"";
+
+const SIZE_FORMATS: &str = "a number of bytes or <number><unit> with unit B, KB, MB, GB, TB (powers of 1000) or KiB, MiB, GiB, TiB (powers of 1024), e.g. \"100MB\", \"1.5GiB\"";
+
+/// Parse a human-friendly duration such as `"90"`, `"5m"`, `"1h30m"` or
+/// `"250ms"`. A bare number is read as seconds.
+///
+/// # Errors
+///
+/// Returns a message listing the accepted formats if `text` is not a valid
+/// duration.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{text}': expected {DURATION_FORMATS}");
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).map_err(|_| invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let value = number.parse::<f64>().map_err(|_| invalid())?;
+        let scale = match unit.trim().to_ascii_lowercase().as_str() {
+            "ms" => 0.001,
+            "s" | "sec" | "secs" => 1.0,
+            "m" | "min" | "mins" => 60.0,
+            "h" | "hr" | "hrs" => 3600.0,
+            "d" => 86400.0,
+            _ => return Err(invalid()),
+        };
+        total += Duration::try_from_secs_f64(value * scale).map_err(|_| invalid())?;
+        rest = tail;
+    }
+    Ok(total)
+}
+
+/// Parse a human-friendly size such as `"512"`, `"100MB"` or `"1.5GiB"` into
+/// bytes. A bare number is read as bytes.
+///
+/// # Errors
+///
+/// Returns a message listing the accepted formats if `text` is not a valid
+/// size.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size '{text}': expected {SIZE_FORMATS}");
+    let text = text.trim();
+    let number_len = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(number_len);
+
+    let value = number.parse::<f64>().map_err(|_| invalid())?;
+    let scale: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(invalid()),
+    };
+
+    let bytes = (value * scale).round();
+    if !bytes.is_finite() || bytes > u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+/// Read an optional duration parameter that may be given as a number of
+/// seconds or as a duration string.
+///
+/// # Errors
+///
+/// Returns an error naming `name` if the value is neither a non-negative
+/// number nor a valid duration string.
+pub fn duration_param(value: Value, name: &str) -> mlua::Result<Option<Duration>> {
+    let parsed = match value {
+        Value::Nil => return Ok(None),
+        Value::Integer(seconds) => parse_duration(&seconds.to_string()),
+        Value::Number(seconds) => parse_duration(&seconds.to_string()),
+        Value::String(text) => parse_duration(&text.to_str()?),
+        other => Err(format!(
+            "expected {DURATION_FORMATS}, got {}",
+            other.type_name()
+        )),
+    };
+    parsed
+        .map(Some)
+        .map_err(|e| RuntimeError(format!("'{name}': {e}")))
+}
+
+/// Lua binding: `komandan.parse_duration("5m")` returns the duration in
+/// seconds.
+///
+/// # Errors
+///
+/// Returns an error listing the accepted formats for invalid input.
+pub fn lua_parse_duration(_: &Lua, value: Value) -> mlua::Result<f64> {
+    duration_param(value, "duration")?
+        .map(|duration| duration.as_secs_f64())
+        .ok_or_else(|| RuntimeError(format!("'duration': expected {DURATION_FORMATS}")))
+}
+
+/// Lua binding: `komandan.parse_size("100MB")` returns the size in bytes.
+///
+/// # Errors
+///
+/// Returns an error listing the accepted formats for invalid input.
+pub fn lua_parse_size(_: &Lua, value: Value) -> mlua::Result<u64> {
+    let parsed = match value {
+        Value::Integer(bytes) => u64::try_from(bytes)
+            .map_err(|_| format!("invalid size '{bytes}': expected {SIZE_FORMATS}")),
+        Value::Number(bytes) => parse_size(&bytes.to_string()),
+        Value::String(text) => parse_size(&text.to_str()?),
+        other => Err(format!(
+            "expected {SIZE_FORMATS}, got {}",
+            other.type_name()
+        )),
+    };
+    parsed.map_err(|e| RuntimeError(format!("'size': {e}")))
+}
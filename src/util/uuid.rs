@@ -0,0 +1,35 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formats 16 bytes as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, setting the
+/// given version and the RFC 9562 variant bits.
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = crate::modules::to_hex(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A random (version 4) UUID.
+#[must_use]
+pub fn uuid_v4() -> String {
+    format_uuid(rand::random(), 4)
+}
+
+/// A version 7 UUID: the Unix time in milliseconds followed by random bits,
+/// so identifiers sort by creation time.
+#[must_use]
+pub fn uuid_v7() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[10..]);
+    format_uuid(bytes, 7)
+}
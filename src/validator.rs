@@ -18,7 +18,18 @@ pub fn validate_host(lua: &Lua, host: Value) -> mlua::Result<Table> {
         validate_port(lua, &port)?;
     }
 
-    Ok(host_table)
+    let vars = host_table.get::<Value>("vars")?;
+    if !vars.is_nil() && !vars.is_table() {
+        return Err(RuntimeError("Host vars must be a table.".to_string()));
+    }
+
+    if !is_string_list(&host_table.get::<Value>("env_unset")?) {
+        return Err(RuntimeError(
+            "Host env_unset must be a list of strings.".to_string(),
+        ));
+    }
+
+    crate::vault::reveal_table(lua, &host_table)
 }
 
 fn validate_port(_: &Lua, port: &Value) -> mlua::Result<Integer> {
@@ -44,9 +55,61 @@ pub fn validate_task(lua: &Lua, task: Value) -> mlua::Result<Table> {
 
     validate_module(lua, task_table.get::<Value>(1)?).into_lua_err()?;
 
+    for predicate in ["changed_when", "failed_when"] {
+        let value = task_table.get::<Value>(predicate)?;
+        if !value.is_nil() && !value.is_function() {
+            return Err(RuntimeError(format!(
+                "Task {predicate} must be a function."
+            )));
+        }
+    }
+
+    for field in ["creates", "unless"] {
+        let value = task_table.get::<Value>(field)?;
+        if !value.is_nil() && !value.is_string() {
+            return Err(RuntimeError(format!("Task {field} must be a string.")));
+        }
+    }
+
+    let vars = task_table.get::<Value>("vars")?;
+    if !vars.is_nil() && !vars.is_table() {
+        return Err(RuntimeError("Task vars must be a table.".to_string()));
+    }
+
+    let undo = task_table.get::<Value>("undo")?;
+    if !undo.is_nil() {
+        let undo = validate_module(lua, undo)
+            .map_err(|_| RuntimeError("Task undo must be a module.".to_string()))?;
+        task_table.set("undo", undo)?;
+    }
+
+    let env_inherit = task_table.get::<Value>("env_inherit")?;
+    if !env_inherit.is_nil() && !env_inherit.is_boolean() {
+        return Err(RuntimeError(
+            "Task env_inherit must be a boolean.".to_string(),
+        ));
+    }
+
+    for field in ["tags", "depends_on", "env_unset"] {
+        if !is_string_list(&task_table.get::<Value>(field)?) {
+            return Err(RuntimeError(format!(
+                "Task {field} must be a list of strings."
+            )));
+        }
+    }
+
     Ok(task_table)
 }
 
+/// Whether `value` is nil or a list of strings.
+fn is_string_list(value: &Value) -> bool {
+    value.is_nil()
+        || value.as_table().is_some_and(|list| {
+            list.sequence_values::<Value>()
+                .all(|item| item.is_ok_and(|item| item.is_string()))
+        })
+}
+
 pub fn validate_module(lua: &Lua, module: Value) -> mlua::Result<Table> {
     if module.is_string() {
         let module = lua
@@ -250,6 +313,131 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_validate_task_predicate_not_function() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        let module = lua.create_table()?;
+        module.set("name", "cmd")?;
+        task.set(1, module)?;
+        task.set("changed_when", true)?;
+
+        let result = super::validate_task(&lua, mlua::Value::Table(task));
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!(
+                e.to_string(),
+                "runtime error: Task changed_when must be a function."
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_task_tags_not_list() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        let module = lua.create_table()?;
+        module.set("name", "cmd")?;
+        task.set(1, module)?;
+        task.set("tags", "deploy")?;
+
+        let result = super::validate_task(&lua, mlua::Value::Table(task));
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!(
+                e.to_string(),
+                "runtime error: Task tags must be a list of strings."
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_env_unset() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let host = lua.create_table()?;
+        host.set("address", "localhost")?;
+        host.set("env_unset", "DEBIAN_FRONTEND")?;
+        assert!(super::validate_host(&lua, mlua::Value::Table(host.clone())).is_err());
+        host.set("env_unset", vec!["DEBIAN_FRONTEND"])?;
+        assert!(super::validate_host(&lua, mlua::Value::Table(host)).is_ok());
+
+        let task = lua.create_table()?;
+        task.set(1, "echo ok")?;
+        task.set("env_unset", vec!["DEBIAN_FRONTEND"])?;
+        task.set("env_inherit", "no")?;
+        assert!(super::validate_task(&lua, mlua::Value::Table(task.clone())).is_err());
+        task.set("env_inherit", false)?;
+        assert!(super::validate_task(&lua, mlua::Value::Table(task)).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_task_creates_not_string() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        let module = lua.create_table()?;
+        module.set("name", "cmd")?;
+        task.set(1, module)?;
+        task.set("creates", lua.create_sequence_from(["/etc/app.conf"])?)?;
+
+        let result = super::validate_task(&lua, mlua::Value::Table(task));
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!(
+                e.to_string(),
+                "runtime error: Task creates must be a string."
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_vars_not_table() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let host = lua.create_table()?;
+        host.set("address", "127.0.0.1")?;
+        host.set("vars", "env=prod")?;
+        let result = super::validate_host(&lua, mlua::Value::Table(host));
+        assert!(
+            result.is_err_and(|e| e.to_string() == "runtime error: Host vars must be a table.")
+        );
+
+        let task = lua.create_table()?;
+        let module = lua.create_table()?;
+        module.set("name", "cmd")?;
+        task.set(1, module)?;
+        task.set("vars", 1)?;
+        let result = super::validate_task(&lua, mlua::Value::Table(task));
+        assert!(
+            result.is_err_and(|e| e.to_string() == "runtime error: Task vars must be a table.")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_task_undo() -> mlua::Result<()> {
+        let lua = create_lua()?;
+        let task = lua.create_table()?;
+        let module = lua.create_table()?;
+        module.set("name", "cmd")?;
+        task.set(1, module)?;
+        task.set("undo", "rm -f /tmp/komandan-undo")?;
+        let task = super::validate_task(&lua, mlua::Value::Table(task))?;
+        assert_eq!(
+            task.get::<mlua::Table>("undo")?.get::<String>("name")?,
+            "cmd"
+        );
+
+        task.set("undo", true)?;
+        let result = super::validate_task(&lua, mlua::Value::Table(task));
+        assert!(
+            result.is_err_and(|e| e.to_string() == "runtime error: Task undo must be a module.")
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_validate_module_valid_string() -> mlua::Result<()> {
         let lua = create_lua()?;
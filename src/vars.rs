@@ -0,0 +1,117 @@
+use mlua::{Error::RuntimeError, Lua, LuaSerdeExt, Table, Value};
+
+use crate::defaults::Defaults;
+
+/// Variables of a task on `host`, every layer overriding the keys set by the
+/// ones before it:
+///
+/// 1. `komandan.defaults:set_vars()`
+/// 2. `komandan.defaults:set_group_vars()` of every group the host is in,
+///    i.e. its `tags`, in the order listed
+/// 3. the host's `vars`
+/// 4. the task's `vars`
+///
+/// Layers are merged key by key; a nested table replaces the one below it.
+///
+/// # Errors
+///
+/// Returns an error if a layer is not a table or the defaults cannot be read.
+pub fn resolve_vars(lua: &Lua, host: &Table, task: Option<&Table>) -> mlua::Result<Table> {
+    let defaults = Defaults::global();
+    let resolved = lua.create_table()?;
+
+    let default_vars = defaults
+        .vars
+        .read()
+        .map_err(|_| RuntimeError("Failed to acquire read lock on vars".to_string()))?
+        .clone();
+    merge_layer(&resolved, lua.to_value(&default_vars)?, "default")?;
+
+    let groups = host.get::<Option<Vec<String>>>("tags")?.unwrap_or_default();
+    let group_vars = defaults
+        .group_vars
+        .read()
+        .map_err(|_| RuntimeError("Failed to acquire read lock on group_vars".to_string()))?
+        .clone();
+    for group in &groups {
+        if let Some(vars) = group_vars.get(group) {
+            merge_layer(&resolved, lua.to_value(vars)?, "group")?;
+        }
+    }
+
+    merge_layer(&resolved, host.get::<Value>("vars")?, "host")?;
+    if let Some(task) = task {
+        merge_layer(&resolved, task.get::<Value>("vars")?, "task")?;
+    }
+    Ok(resolved)
+}
+
+/// Copy the keys of `layer` into `target`.
+fn merge_layer(target: &Table, layer: Value, name: &str) -> mlua::Result<()> {
+    match layer {
+        Value::Nil => Ok(()),
+        Value::Table(layer) => {
+            for pair in layer.pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                target.raw_set(key, value)?;
+            }
+            Ok(())
+        }
+        other => Err(RuntimeError(format!(
+            "{name} vars must be a table, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::chunk;
+
+    #[test]
+    fn test_resolve_vars_precedence() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let resolved = lua
+            .load(chunk! {
+                komandan.defaults:set_vars({ port = 80, env = "prod", tier = "default" })
+                komandan.defaults:set_group_vars("test_vars_web", { port = 8080, tier = "web" })
+                komandan.defaults:set_group_vars("test_vars_canary", { tier = "canary" })
+                local host = {
+                    address = "10.0.0.5",
+                    tags = { "test_vars_web", "test_vars_canary" },
+                    vars = { env = "staging" },
+                }
+                local task = { vars = { port = 9090 } }
+                local with_task = komandan.vars(host, task)
+                local without_task = komandan.vars(host)
+                komandan.defaults:set_vars(nil)
+                komandan.defaults:set_group_vars("test_vars_web", nil)
+                komandan.defaults:set_group_vars("test_vars_canary", nil)
+                return { with_task, without_task }
+            })
+            .eval::<Table>()?;
+
+        let with_task = resolved.get::<Table>(1)?;
+        assert_eq!(with_task.get::<i64>("port")?, 9090);
+        assert_eq!(with_task.get::<String>("env")?, "staging");
+        assert_eq!(with_task.get::<String>("tier")?, "canary");
+        let without_task = resolved.get::<Table>(2)?;
+        assert_eq!(without_task.get::<i64>("port")?, 8080);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_vars_rejects_non_table() -> mlua::Result<()> {
+        let lua = crate::create_lua()?;
+        let host = lua.create_table()?;
+        host.set("address", "10.0.0.5")?;
+        host.set("vars", "env=prod")?;
+        let error = resolve_vars(&lua, &host, None)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("host vars must be a table, got string"));
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use mlua::{Error::RuntimeError, Lua, Table, Value};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::args::{VaultArgs, VaultCommands};
+
+/// Marks a vault-encrypted value. The rest is the base64 of salt, nonce and
+/// AES-256-GCM ciphertext, the key derived from the password with Argon2id.
+pub const VAULT_PREFIX: &str = "$KOMANDAN_VAULT;1;";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// How deep `reveal_table` descends into nested tables.
+const MAX_REVEAL_DEPTH: usize = 16;
+
+/// Host fields holding credentials, which stay encrypted in host tables.
+const SECRET_FIELDS: [&str; 2] = ["password", "private_key_pass"];
+
+/// Values already decrypted this run, by ciphertext; key derivation is
+/// deliberately slow and hosts are validated once per task.
+static REVEALED: OnceLock<Mutex<HashMap<String, SecretString>>> = OnceLock::new();
+
+/// Whether `value` is a vault-encrypted value or file content.
+#[must_use]
+pub fn is_encrypted(value: &str) -> bool {
+    value.trim_start().starts_with(VAULT_PREFIX)
+}
+
+fn derive_key(password: &SecretString, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0_u8; 32];
+    Argon2::default()
+        .hash_password_into(password.expose_secret().as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive the vault key: {e}"))?;
+    Ok(key)
+}
+
+/// Encrypt `plaintext` with `password` into a single-line vault value.
+///
+/// # Errors
+///
+/// Returns an error if the key cannot be derived or encryption fails.
+pub fn encrypt(plaintext: &[u8], password: &SecretString) -> Result<String> {
+    let salt = rand::random::<[u8; SALT_LEN]>();
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let cipher = Aes256Gcm::new_from_slice(&derive_key(password, &salt)?)
+        .map_err(|e| anyhow!("Invalid vault key: {e}"))?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt vault value"))?;
+    let mut payload = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    payload.extend_from_slice(&salt);
+    payload.extend_from_slice(&nonce);
+    payload.extend_from_slice(&ciphertext);
+    Ok(format!("{VAULT_PREFIX}{}", STANDARD.encode(payload)))
+}
+
+/// Decrypt a vault value produced by [`encrypt`].
+///
+/// # Errors
+///
+/// Returns an error if `value` is not a vault value, is corrupted, or was
+/// encrypted with another password.
+pub fn decrypt(value: &str, password: &SecretString) -> Result<Vec<u8>> {
+    let Some(encoded) = value.trim().strip_prefix(VAULT_PREFIX) else {
+        bail!("Not a vault-encrypted value");
+    };
+    let payload = STANDARD
+        .decode(encoded)
+        .context("Vault value is not valid base64")?;
+    if payload.len() < SALT_LEN + NONCE_LEN {
+        bail!("Vault value is truncated");
+    }
+    let (salt, rest) = payload.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new_from_slice(&derive_key(password, salt)?)
+        .map_err(|e| anyhow!("Invalid vault key: {e}"))?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt vault value: wrong password or corrupted data"))
+}
+
+/// The vault password from `password_file`, else the file named by
+/// `$KOMANDAN_VAULT_PASSWORD_FILE`, else `$KOMANDAN_VAULT_PASSWORD`.
+///
+/// # Errors
+///
+/// Returns an error if no source is set, the file cannot be read, or the
+/// password is empty.
+pub fn vault_password(password_file: Option<&str>) -> Result<SecretString> {
+    let file = password_file
+        .map(ToString::to_string)
+        .or_else(|| std::env::var("KOMANDAN_VAULT_PASSWORD_FILE").ok());
+    let password = match file {
+        Some(file) => fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read vault password file '{file}'"))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        None => std::env::var("KOMANDAN_VAULT_PASSWORD").map_err(|_| {
+            anyhow!(
+                "No vault password: pass --vault-password-file or set KOMANDAN_VAULT_PASSWORD_FILE or KOMANDAN_VAULT_PASSWORD"
+            )
+        })?,
+    };
+    if password.is_empty() {
+        bail!("The vault password is empty");
+    }
+    Ok(SecretString::new(password.into_boxed_str()))
+}
+
+/// `value` as a secret, decrypted first if it is vault-encrypted. Uses the
+/// password given by `--vault-password-file` or the environment.
+///
+/// # Errors
+///
+/// Returns an error if the value cannot be decrypted or is not UTF-8.
+pub fn reveal_secret(value: &str) -> Result<SecretString> {
+    if !is_encrypted(value) {
+        return Ok(SecretString::new(value.into()));
+    }
+    let revealed = REVEALED.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(secret) = revealed
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(value)
+    {
+        return Ok(secret.clone());
+    }
+    let password = vault_password(crate::args::global_flags().vault_password_file.as_deref())?;
+    let secret = String::from_utf8(decrypt(value, &password)?)
+        .map(|plaintext| SecretString::new(plaintext.into_boxed_str()))
+        .context("Decrypted vault value is not valid UTF-8")?;
+    revealed
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(value.to_string(), secret.clone());
+    Ok(secret)
+}
+
+/// `value` decrypted if it is vault-encrypted, else unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the value cannot be decrypted or is not UTF-8.
+pub fn reveal(value: &str) -> Result<String> {
+    reveal_secret(value).map(|secret| secret.expose_secret().to_string())
+}
+
+/// The vault-encrypted `field` of `table` as a secret, if set. Credentials
+/// are read this way where they are used, so their plaintext never enters a
+/// Lua table.
+///
+/// # Errors
+///
+/// Returns an error if the field is not a string or cannot be decrypted.
+pub fn secret_field(table: &Table, field: &str) -> mlua::Result<Option<SecretString>> {
+    table
+        .get::<Option<String>>(field)?
+        .map(|value| {
+            reveal_secret(&value)
+                .map_err(|e| RuntimeError(format!("Failed to decrypt '{field}': {e:#}")))
+        })
+        .transpose()
+}
+
+/// A copy of `table` with every vault-encrypted string, including those in
+/// nested tables such as `env` and `vars`, replaced by its plaintext. The
+/// `SECRET_FIELDS` are copied still encrypted: they are decrypted with
+/// [`secret_field`] where they are used. `table` itself is left untouched.
+///
+/// # Errors
+///
+/// Returns an error if a value cannot be decrypted.
+pub fn reveal_table(lua: &Lua, table: &Table) -> mlua::Result<Table> {
+    reveal_nested(lua, table, 0, SECRET_FIELDS.as_slice())
+}
+
+fn reveal_nested(lua: &Lua, table: &Table, depth: usize, keep: &[&str]) -> mlua::Result<Table> {
+    let copy = lua.create_table()?;
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let kept = matches!(&key, Value::String(name) if keep.iter().any(|field| name == field));
+        let value = match value {
+            Value::String(text) if !kept && is_encrypted(&text.to_string_lossy()) => {
+                let plaintext = reveal(&text.to_str()?).map_err(|e| {
+                    RuntimeError(format!("Failed to decrypt '{}': {e:#}", key_name(&key)))
+                })?;
+                Value::String(lua.create_string(plaintext)?)
+            }
+            Value::Table(nested) if depth < MAX_REVEAL_DEPTH => {
+                Value::Table(reveal_nested(lua, &nested, depth + 1, &[])?)
+            }
+            value => value,
+        };
+        copy.raw_set(key, value)?;
+    }
+    if let Some(metatable) = table.metatable() {
+        copy.set_metatable(Some(metatable))?;
+    }
+    Ok(copy)
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.to_string_lossy(),
+        Value::Integer(index) => index.to_string(),
+        other => other.type_name().to_string(),
+    }
+}
+
+/// Builds the `komandan.vault` table: `decrypt(value)`, `is_encrypted(value)`
+/// and `read_file(path)`, which returns a file's content decrypted if needed.
+///
+/// # Errors
+///
+/// Returns an error if the table or its functions cannot be created.
+pub fn vault_table(lua: &Lua) -> mlua::Result<Table> {
+    let vault = lua.create_table()?;
+    vault.set(
+        "decrypt",
+        lua.create_function(|_, value: String| {
+            reveal(&value).map_err(|e| RuntimeError(format!("{e:#}")))
+        })?,
+    )?;
+    vault.set(
+        "is_encrypted",
+        lua.create_function(|_, value: String| Ok(is_encrypted(&value)))?,
+    )?;
+    vault.set(
+        "read_file",
+        lua.create_function(|_, path: String| {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{path}'"))
+                .and_then(|content| reveal(&content))
+                .map_err(|e| RuntimeError(format!("{e:#}")))
+        })?,
+    )?;
+    Ok(vault)
+}
+
+/// Handles the vault command
+///
+/// # Errors
+///
+/// Returns an error if the password is unavailable or a file cannot be
+/// read, decrypted or written.
+pub fn handle_vault_command(args: &VaultArgs) -> Result<()> {
+    let password = vault_password(args.vault_password_file.as_deref())?;
+    match &args.command {
+        VaultCommands::Encrypt(encrypt_args) => match (&encrypt_args.string, &encrypt_args.file) {
+            (Some(value), _) => println!("{}", encrypt(value.as_bytes(), &password)?),
+            (None, Some(file)) => encrypt_file(Path::new(file), &password)?,
+            (None, None) => bail!("Nothing to encrypt: pass a file or --string"),
+        },
+        VaultCommands::Decrypt(decrypt_args) => {
+            let path = Path::new(&decrypt_args.file);
+            if decrypt_args.stdout {
+                let plaintext = decrypt(&read_encrypted(path)?, &password)?;
+                std::io::stdout().write_all(&plaintext)?;
+            } else {
+                decrypt_file(path, &password)?;
+            }
+        }
+        VaultCommands::Edit(edit_args) => edit_file(Path::new(&edit_args.file), &password)?,
+    }
+    Ok(())
+}
+
+fn read_encrypted(path: &Path) -> Result<String> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !is_encrypted(&content) {
+        bail!("{} is not vault-encrypted", path.display());
+    }
+    Ok(content)
+}
+
+/// Encrypt the file at `path` in place.
+///
+/// # Errors
+///
+/// Returns an error if the file is already encrypted or cannot be rewritten.
+pub fn encrypt_file(path: &Path, password: &SecretString) -> Result<()> {
+    let content = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if is_encrypted(&String::from_utf8_lossy(&content)) {
+        bail!("{} is already vault-encrypted", path.display());
+    }
+    fs::write(path, encrypt(&content, password)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Decrypt the file at `path` in place.
+///
+/// # Errors
+///
+/// Returns an error if the file is not encrypted, the password is wrong, or
+/// it cannot be rewritten.
+pub fn decrypt_file(path: &Path, password: &SecretString) -> Result<()> {
+    let plaintext = decrypt(&read_encrypted(path)?, password)?;
+    fs::write(path, plaintext).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Decrypt `path` into a private temporary file, open it in the user's
+/// editor and encrypt the result back when it changed.
+fn edit_file(path: &Path, password: &SecretString) -> Result<()> {
+    let plaintext = if path.exists() {
+        decrypt(&read_encrypted(path)?, password)?
+    } else {
+        Vec::new()
+    };
+
+    let file_name = path
+        .file_name()
+        .map_or_else(|| "vault".into(), |name| name.to_string_lossy());
+    let tmp_path =
+        std::env::temp_dir().join(format!("komandan-vault-{}-{file_name}", std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(&plaintext))
+        .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&tmp_path)
+        .status();
+    let edited = fs::read(&tmp_path);
+    let _ = fs::remove_file(&tmp_path);
+
+    let status = status.with_context(|| format!("Failed to run editor '{editor}'"))?;
+    if !status.success() {
+        bail!(
+            "Editor '{editor}' exited with {status}; {} was not changed",
+            path.display()
+        );
+    }
+    let edited = edited.with_context(|| format!("Failed to read {}", tmp_path.display()))?;
+    if edited == plaintext {
+        eprintln!("{} unchanged", path.display());
+        return Ok(());
+    }
+    fs::write(path, encrypt(&edited, password)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password(value: &str) -> SecretString {
+        SecretString::new(value.to_string().into_boxed_str())
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() -> Result<()> {
+        let encrypted = encrypt(b"s3cret", &password("hunter2"))?;
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("s3cret"));
+        assert_ne!(encrypted, encrypt(b"s3cret", &password("hunter2"))?);
+        assert_eq!(decrypt(&encrypted, &password("hunter2"))?, b"s3cret");
+        assert!(decrypt(&encrypted, &password("wrong")).is_err());
+
+        let mut tampered = encrypted.into_bytes();
+        if let Some(last) = tampered.iter_mut().rev().nth(2) {
+            *last = if *last == b'A' { b'B' } else { b'A' };
+        }
+        assert!(decrypt(&String::from_utf8(tampered)?, &password("hunter2")).is_err());
+        assert!(decrypt("plain", &password("hunter2")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hosts.json");
+        fs::write(&path, "[{\"address\": \"10.0.0.5\"}]")?;
+
+        encrypt_file(&path, &password("hunter2"))?;
+        assert!(is_encrypted(&fs::read_to_string(&path)?));
+        assert!(encrypt_file(&path, &password("hunter2")).is_err());
+
+        decrypt_file(&path, &password("hunter2"))?;
+        assert_eq!(fs::read_to_string(&path)?, "[{\"address\": \"10.0.0.5\"}]");
+        assert!(decrypt_file(&path, &password("hunter2")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vault_password_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("vault-pass");
+        fs::write(&path, "hunter2\n")?;
+        let secret = vault_password(path.to_str())?;
+        assert_eq!(secret.expose_secret(), "hunter2");
+
+        fs::write(&path, "\n")?;
+        assert!(vault_password(path.to_str()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reveal_table_copies_host() -> mlua::Result<()> {
+        let encrypted = encrypt(b"s3cret", &password("hunter2")).map_err(mlua::Error::external)?;
+        let lua = Lua::new();
+        let host = lua.create_table()?;
+        host.set("address", "10.0.0.5")?;
+        host.set("password", encrypted.as_str())?;
+        host.set("vars", lua.create_table_from([("tier", "web")])?)?;
+
+        let revealed = reveal_table(&lua, &host)?;
+        assert_ne!(revealed, host);
+        assert_eq!(revealed.get::<String>("address")?, "10.0.0.5");
+        assert_eq!(revealed.get::<String>("password")?, encrypted);
+        assert_eq!(revealed.get::<Table>("vars")?.get::<String>("tier")?, "web");
+        assert_eq!(reveal("plain").map_err(mlua::Error::external)?, "plain");
+        Ok(())
+    }
+}
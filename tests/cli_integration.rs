@@ -1,5 +1,5 @@
 use clap::Parser;
-use komandan::args::{Args, Commands, ProjectCommands};
+use komandan::args::{Args, Commands, ModulesCommands, OutputFormat, ProjectCommands};
 
 #[test]
 fn test_args_parsing_version_flag() {
@@ -133,3 +133,54 @@ fn test_args_parsing_chunk_and_interactive() {
     assert_eq!(args.chunk, Some("print('test')".to_string()));
     assert!(args.flags.interactive);
 }
+
+#[test]
+fn test_args_parsing_modules_list() {
+    let args = Args::parse_from([
+        "komandan", "modules", "list", "--filter", "apt", "--format", "json",
+    ]);
+
+    if let Some(Commands::Modules(modules_args)) = args.command {
+        let ModulesCommands::List(list_args) = modules_args.command;
+        assert_eq!(list_args.filter.as_deref(), Some("apt"));
+        assert_eq!(list_args.format, OutputFormat::Json);
+        assert_eq!(list_args.project, ".");
+    } else {
+        panic!("Expected Modules command");
+    }
+}
+
+#[test]
+fn test_args_parsing_tags() {
+    let args = Args::parse_from([
+        "komandan",
+        "--tags",
+        "deploy,web",
+        "--skip-tags",
+        "db",
+        "main.lua",
+    ]);
+    assert_eq!(args.flags.tags, vec!["deploy", "web"]);
+    assert_eq!(args.flags.skip_tags, vec!["db"]);
+}
+
+#[test]
+fn test_args_parsing_forks() {
+    let args = Args::parse_from(["komandan", "--forks", "5", "main.lua"]);
+    assert_eq!(args.flags.forks, Some(5));
+    assert!(Args::try_parse_from(["komandan", "--forks", "0", "main.lua"]).is_err());
+}
+
+#[test]
+fn test_args_parsing_doctor() {
+    let args = Args::parse_from(["komandan", "doctor", "web1", "-p", "2222", "-u", "deploy"]);
+
+    if let Some(Commands::Doctor(doctor_args)) = args.command {
+        assert_eq!(doctor_args.host, "web1");
+        assert_eq!(doctor_args.port, 2222);
+        assert_eq!(doctor_args.user.as_deref(), Some("deploy"));
+        assert!(doctor_args.private_key_file.is_none());
+    } else {
+        panic!("Expected Doctor command");
+    }
+}
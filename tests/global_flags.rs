@@ -0,0 +1,26 @@
+use komandan::args::{Flags, global_flags, set_global_flags};
+use komandan::create_lua;
+use mlua::chunk;
+
+#[test]
+fn test_set_global_flags_without_args() -> anyhow::Result<()> {
+    let flags = Flags {
+        dry_run: true,
+        ..Flags::default()
+    };
+    set_global_flags(flags.clone()).map_err(anyhow::Error::msg)?;
+    assert_eq!(global_flags(), flags);
+
+    let lua = create_lua()?;
+    let result = lua
+        .load(chunk! {
+            return komandan.komando({
+                name = "would fail outside dry-run",
+                komandan.modules.cmd({ cmd = "false" }),
+            }, { address = "localhost" })
+        })
+        .exec();
+    set_global_flags(Flags::default()).map_err(anyhow::Error::msg)?;
+    result?;
+    Ok(())
+}
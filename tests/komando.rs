@@ -1,5 +1,8 @@
-use komandan::create_lua;
+use komandan::connection::get_auth_config;
+use komandan::ssh::SSHAuthMethod;
+use komandan::{create_lua, vault};
 use mlua::{Integer, Table, chunk};
+use secrecy::{ExposeSecret, SecretString};
 use std::io::Write;
 use tempfile::NamedTempFile;
 
@@ -309,3 +312,182 @@ fn test_komando_apt() -> mlua::Result<()> {
     assert_eq!(result_table.get::<Integer>("exit_code")?, 0);
     Ok(())
 }
+
+#[test]
+fn test_komando_changed_when_failed_when() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result_table = lua
+        .load(chunk! {
+            local host = { address = "localhost", connection = "local" }
+
+            local task = {
+                komandan.modules.cmd({
+                    cmd = "echo unchanged; exit 3"
+                }),
+                changed_when = function(result)
+                    return result.stdout ~= "unchanged"
+                end,
+                failed_when = function(result)
+                    return result.exit_code ~= 3
+                end,
+            }
+
+            return komandan.komando(task, host)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(result_table.get::<Integer>("exit_code")?, 3);
+    assert!(!result_table.get::<bool>("changed")?);
+
+    let result = lua
+        .load(chunk! {
+            local task = {
+                komandan.modules.cmd({ cmd = "echo ok" }),
+                failed_when = function(result)
+                    return result.stdout == "ok"
+                end,
+            }
+
+            return komandan.komando(task, { address = "localhost", connection = "local" })
+        })
+        .eval::<Table>();
+
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_komando_task_timeout() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let started = std::time::Instant::now();
+    let result = lua
+        .load(chunk! {
+            local task = {
+                komandan.modules.cmd({ cmd = "sleep 5" }),
+                timeout = 0.2,
+            }
+
+            return komandan.komando(task, { address = "localhost", connection = "local" })
+        })
+        .eval::<Table>();
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    match result {
+        Err(e) => assert!(e.to_string().contains("timed out"), "{e}"),
+        Ok(_) => return Err(mlua::Error::external("task should have timed out")),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_local() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result_table = lua
+        .load(chunk! {
+            return komandan.local({
+                name = "Build artifact",
+                komandan.modules.cmd({ cmd = "echo built" }),
+            })
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(result_table.get::<Integer>("exit_code")?, 0);
+    assert_eq!(result_table.get::<String>("stdout")?.trim(), "built");
+    Ok(())
+}
+
+#[test]
+#[allow(unsafe_code)]
+fn test_komando_vault_encrypted_host() -> mlua::Result<()> {
+    let vault_password = SecretString::new("hunter2".into());
+    unsafe { std::env::set_var("KOMANDAN_VAULT_PASSWORD", "hunter2") };
+    let password = vault::encrypt(b"s3cret", &vault_password).map_err(mlua::Error::external)?;
+    let token = vault::encrypt(b"t0ken", &vault_password).map_err(mlua::Error::external)?;
+
+    let lua = create_lua()?;
+    let (result, host, task) = lua
+        .load(chunk! {
+            local host = {
+                address = "localhost",
+                connection = "local",
+                user = "deploy",
+                password = $password,
+                env = { TOKEN = $token },
+            }
+            local task = { komandan.modules.cmd({ cmd = "printf %s $TOKEN" }) }
+            return komandan.komando(task, host), host, task
+        })
+        .eval::<(Table, Table, Table)>()?;
+
+    assert_eq!(result.get::<String>("stdout")?, "t0ken");
+    assert_eq!(host.get::<String>("password")?, password);
+    assert_eq!(host.get::<Table>("env")?.get::<String>("TOKEN")?, token);
+
+    let (user, auth) = get_auth_config(&host, &task, None)?;
+    assert_eq!(user, "deploy");
+    match auth {
+        SSHAuthMethod::Password(secret) => assert_eq!(secret.expose_secret(), "s3cret"),
+        SSHAuthMethod::PublicKey { .. } => {
+            return Err(mlua::Error::external("expected password authentication"));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_module_implementations_by_interpreter() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result_table = lua
+        .load(chunk! {
+            local module = komandan.KomandanModule:new({ name = "probe" })
+            module.implementations = {
+                { interpreter = "komandan-no-such-interpreter", run = function(self, interpreter)
+                    error("unavailable implementation selected")
+                end },
+                { interpreter = "sh", run = function(self, interpreter)
+                    self.ssh:run_script(interpreter, "echo via " .. interpreter.name)
+                end },
+            }
+
+            return komandan.local({ name = "Probe interpreter", module })
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(result_table.get::<String>("stdout")?.trim(), "via sh");
+    Ok(())
+}
+
+#[test]
+fn test_komando_delegate_to_localhost() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result_table = lua
+        .load(chunk! {
+            local host = { name = "web1", address = "192.0.2.10", user = "deploy" }
+
+            local task = {
+                name = "Drain from load balancer",
+                {
+                    name = "probe",
+                    run = function(self)
+                        self.ssh:cmd("echo draining " .. self.host.address)
+                    end,
+                },
+                delegate_to = "localhost",
+            }
+
+            return komandan.komando(task, host)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(result_table.get::<Integer>("exit_code")?, 0);
+    assert_eq!(
+        result_table.get::<String>("stdout")?.trim(),
+        "draining 192.0.2.10"
+    );
+    Ok(())
+}
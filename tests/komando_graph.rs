@@ -0,0 +1,80 @@
+use komandan::create_lua;
+use mlua::{Table, chunk};
+
+#[test]
+fn test_komando_graph_respects_dependencies() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let artifact = dir.path().join("artifact").to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local tasks = {
+                {
+                    name = "deploy",
+                    depends_on = { "build" },
+                    komandan.modules.cmd({ cmd = "cat " .. $artifact }),
+                },
+                {
+                    name = "build",
+                    komandan.modules.cmd({ cmd = "sleep 0.2 && echo built > " .. $artifact }),
+                },
+                {
+                    name = "lint",
+                    komandan.modules.cmd({ cmd = "echo lint" }),
+                },
+            }
+
+            return komandan.komando_graph({ address = "localhost" }, tasks)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.get::<Table>(1)?.get::<String>("stdout")?, "built");
+    assert_eq!(results.get::<Table>(3)?.get::<String>("stdout")?, "lint");
+    Ok(())
+}
+
+#[test]
+fn test_komando_graph_skips_dependents_of_failed_task() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let dependent = dir.path().join("dependent");
+    let unrelated = dir.path().join("unrelated");
+    let dependent_path = dependent.to_string_lossy().to_string();
+    let unrelated_path = unrelated.to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let result = lua
+        .load(chunk! {
+            return komandan.komando_graph({ address = "localhost" }, {
+                migrate = { komandan.modules.cmd({ cmd = "false" }) },
+                restart = {
+                    depends_on = { "migrate" },
+                    komandan.modules.cmd({ cmd = "touch " .. $dependent_path }),
+                },
+                cleanup = { komandan.modules.cmd({ cmd = "touch " .. $unrelated_path }) },
+            })
+        })
+        .eval::<Table>();
+
+    assert!(result.is_err());
+    assert!(!dependent.exists());
+    assert!(unrelated.exists());
+    Ok(())
+}
+
+#[test]
+fn test_komando_graph_rejects_cycles() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let result = lua
+        .load(chunk! {
+            return komandan.komando_graph({ address = "localhost" }, {
+                { name = "a", depends_on = { "b" }, komandan.modules.cmd({ cmd = "true" }) },
+                { name = "b", depends_on = { "a" }, komandan.modules.cmd({ cmd = "true" }) },
+            })
+        })
+        .eval::<Table>();
+
+    let error = result.err().map(|e| e.to_string()).unwrap_or_default();
+    assert!(error.contains("dependency cycle between tasks: a, b"));
+    Ok(())
+}
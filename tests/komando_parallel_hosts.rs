@@ -52,3 +52,445 @@ fn test_komando_parallel_hosts() -> mlua::Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_komando_parallel_hosts_serial() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+                { name = "local3", address = "localhost" },
+            }
+
+            local task = {
+                name = "Echo 1",
+                komandan.modules.cmd({
+                    cmd = "echo 1",
+                }),
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts, { serial = 2 })
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 3);
+    for pair in results.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert_eq!(table.get::<Integer>("exit_code")?, 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_serial_stops_after_failed_batch() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let log = tempfile::NamedTempFile::new().map_err(mlua::Error::external)?;
+    let log_path = log.path().display().to_string();
+
+    let result = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+            }
+
+            local task = {
+                name = "Always fail",
+                komandan.modules.cmd({
+                    cmd = "echo run >> " .. $log_path .. "; exit 1",
+                }),
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts, { serial = "50%" })
+        })
+        .eval::<Table>();
+
+    assert!(result.is_err());
+    let runs = std::fs::read_to_string(&log_path).map_err(mlua::Error::external)?;
+    assert_eq!(runs.lines().count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_predicate_with_upvalue() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost", connection = "local" },
+                { name = "local2", address = "localhost", connection = "local" },
+            }
+            local marker = "ready"
+
+            local task = {
+                name = "Echo marker",
+                komandan.modules.cmd({ cmd = "echo ready" }),
+                changed_when = function(result)
+                    return result.stdout:find(marker) ~= nil
+                end,
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts)
+        })
+        .eval::<Table>();
+
+    assert!(result.is_err_and(|e| {
+        e.to_string()
+            .contains("changed_when cannot use local variables")
+    }));
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost", connection = "local" },
+                { name = "local2", address = "localhost", connection = "local" },
+            }
+
+            local task = {
+                name = "Echo marker",
+                komandan.modules.cmd({ cmd = "echo ready" }),
+                changed_when = function(result)
+                    return result.stdout:find("ready") == nil
+                end,
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 2);
+    for pair in results.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert!(!table.get::<bool>("changed")?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_per_host_errors() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+            }
+
+            local task = {
+                name = "Always fail",
+                komandan.modules.cmd({
+                    cmd = "exit 3",
+                }),
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 2);
+    for pair in results.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert!(table.get::<bool>("failed")?);
+        assert!(!table.get::<String>("error")?.is_empty());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_fail_fast() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let result = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+            }
+
+            local task = {
+                name = "Always fail",
+                komandan.modules.cmd({
+                    cmd = "exit 3",
+                }),
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts, { fail_fast = true })
+        })
+        .eval::<Table>();
+
+    let error = result.err().map(|e| e.to_string()).unwrap_or_default();
+    assert!(error.contains("Parallel run aborted: 1 of 1 host(s) failed"));
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_run_once() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let log = tempfile::NamedTempFile::new().map_err(mlua::Error::external)?;
+    let log_path = log.path().display().to_string();
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+                { name = "local3", address = "localhost" },
+            }
+
+            local task = {
+                name = "Migrate database",
+                komandan.modules.cmd({
+                    cmd = "echo migrated >> " .. $log_path .. "; echo done",
+                }),
+                run_once = true,
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 3);
+    for pair in results.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert_eq!(table.get::<String>("stdout")?.trim(), "done");
+    }
+    let runs = std::fs::read_to_string(&log_path).map_err(mlua::Error::external)?;
+    assert_eq!(runs.lines().count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_block_output() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+            }
+
+            local task = {
+                name = "Echo host",
+                komandan.modules.cmd({
+                    cmd = "echo ready",
+                }),
+            }
+
+            return komandan.komando_parallel_hosts(task, hosts, { output = "block" })
+        })
+        .eval::<Table>()?;
+
+    for index in 1..=2 {
+        let result = results.get::<Table>(index)?;
+        assert_eq!(result.get::<String>("stdout")?.trim(), "ready");
+    }
+
+    let invalid = lua
+        .load(chunk! {
+            return komandan.komando_parallel_hosts({ komandan.modules.cmd({ cmd = "true" }) }, {}, { output = "json" })
+        })
+        .eval::<Table>();
+    assert!(invalid.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_check_host_alive() -> mlua::Result<()> {
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map_err(mlua::Error::external)?
+        .port();
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                alive = { address = "localhost" },
+                dead = { address = "127.0.0.1", port = $closed_port, connection = "ssh" },
+            }
+            local task = {
+                name = "Echo alive",
+                komandan.modules.cmd({ cmd = "echo alive", changed_by_default = false }),
+            }
+            return komandan.komando_parallel_hosts(task, hosts, {
+                check_host_alive = true,
+                alive_timeout = 1,
+            })
+        })
+        .eval::<Table>()?;
+
+    let alive = results.get::<Table>("alive")?;
+    assert_eq!(alive.get::<String>("stdout")?, "alive");
+    let dead = results.get::<Table>("dead")?;
+    assert!(dead.get::<bool>("unreachable")?);
+    assert!(dead.get::<bool>("failed")?);
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_linear_strategy() -> mlua::Result<()> {
+    let lua = create_lua()?;
+    let log = tempfile::NamedTempFile::new().map_err(mlua::Error::external)?;
+    let log_path = log.path().display().to_string();
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+            }
+
+            local function step(name)
+                return {
+                    name = name,
+                    komandan.modules.cmd({
+                        cmd = "echo " .. name .. " >> " .. $log_path .. "; echo " .. name,
+                    }),
+                }
+            end
+
+            return komandan.komando_parallel_hosts({ step("first"), step("second") }, hosts)
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 2);
+    for pair in results.pairs::<Value, Table>() {
+        let (_, host_results) = pair?;
+        assert_eq!(host_results.len()?, 2);
+        assert_eq!(
+            host_results
+                .get::<Table>(2)?
+                .get::<String>("stdout")?
+                .trim(),
+            "second"
+        );
+    }
+    let runs = std::fs::read_to_string(&log_path).map_err(mlua::Error::external)?;
+    assert_eq!(
+        runs.lines().collect::<Vec<_>>(),
+        ["first", "first", "second", "second"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_free_strategy() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                web = { name = "local1", address = "localhost" },
+                db = { name = "local2", address = "localhost" },
+            }
+
+            local tasks = {
+                { name = "Say hello", komandan.modules.cmd({ cmd = "echo hello" }) },
+                { name = "Say done", komandan.modules.cmd({ cmd = "echo done" }) },
+            }
+
+            return komandan.komando_parallel_hosts(tasks, hosts, { strategy = "free" })
+        })
+        .eval::<Table>()?;
+
+    for host in ["web", "db"] {
+        let host_results = results.get::<Table>(host)?;
+        assert_eq!(host_results.len()?, 2);
+        assert_eq!(
+            host_results
+                .get::<Table>(1)?
+                .get::<String>("stdout")?
+                .trim(),
+            "hello"
+        );
+        assert_eq!(
+            host_results
+                .get::<Table>(2)?
+                .get::<String>("stdout")?
+                .trim(),
+            "done"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_task_list_stops_host_at_failure() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+            }
+
+            local tasks = {
+                { name = "Fail", komandan.modules.cmd({ cmd = "exit 3" }) },
+                { name = "Never runs", komandan.modules.cmd({ cmd = "echo unreachable" }) },
+            }
+
+            return komandan.komando_parallel_hosts(tasks, hosts)[1]
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.len()?, 1);
+    assert!(results.get::<Table>(1)?.get::<bool>("failed")?);
+
+    let result = lua
+        .load(chunk! {
+            local tasks = {
+                { name = "Echo", komandan.modules.cmd({ cmd = "echo hi" }) },
+            }
+            return komandan.komando_parallel_hosts(tasks, { { name = "local1", address = "localhost" } }, { strategy = "random" })
+        })
+        .eval::<Table>();
+    assert!(result.is_err_and(|e| e.to_string().contains("strategy must be")));
+    Ok(())
+}
+
+#[test]
+fn test_komando_parallel_hosts_keeps_result_fields() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let (answers, skipped) = lua
+        .load(chunk! {
+            local hosts = {
+                { name = "local1", address = "localhost" },
+                { name = "local2", address = "localhost" },
+            }
+
+            local probe = komandan.KomandanModule:new({ name = "probe" })
+            probe.run = function(self)
+                self.ssh:cmd("echo probed")
+                self:set_result("answer", { value = 42, labels = { "a", "b" } })
+            end
+
+            local answers = komandan.komando_parallel_hosts({ name = "Probe", probe }, hosts)
+            local skipped = komandan.komando_parallel_hosts({
+                name = "Already done",
+                komandan.modules.cmd({ cmd = "echo never" }),
+                creates = "/",
+            }, hosts)
+            return answers, skipped
+        })
+        .eval::<(Table, Table)>()?;
+
+    assert_eq!(answers.len()?, 2);
+    for pair in answers.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert_eq!(table.get::<String>("stdout")?.trim(), "probed");
+        let answer = table.get::<Table>("answer")?;
+        assert_eq!(answer.get::<Integer>("value")?, 42);
+        assert_eq!(answer.get::<Table>("labels")?.get::<String>(2)?, "b");
+    }
+    for pair in skipped.pairs::<Value, Table>() {
+        let (_, table) = pair?;
+        assert!(table.get::<bool>("skipped")?);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use komandan::create_lua;
+use mlua::{Table, chunk};
+
+#[test]
+fn test_module_defaults_fill_unset_params() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            komandan.defaults:set_module_params("cmd", { cmd = "echo from defaults" })
+
+            local from_defaults = komandan.local({
+                name = "Defaulted command",
+                komandan.modules.cmd({}),
+            })
+            local from_task = komandan.local({
+                name = "Explicit command",
+                komandan.modules.cmd({ cmd = "echo from task" }),
+            })
+
+            komandan.defaults:set_module_params("cmd", nil)
+            return { from_defaults.stdout, from_task.stdout }
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.get::<String>(1)?.trim(), "from defaults");
+    assert_eq!(results.get::<String>(2)?.trim(), "from task");
+    Ok(())
+}
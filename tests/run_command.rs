@@ -0,0 +1,156 @@
+use komandan::args::Flags;
+use komandan::create_lua;
+use komandan::run::{run_task_file, select_hosts};
+use serde_json::json;
+
+#[test]
+fn test_run_task_file_on_selected_hosts() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let marker = dir.path().join("marker");
+    let task_file = dir.path().join("touch.lua");
+    std::fs::write(
+        &task_file,
+        format!(
+            "return {{\n  {{ name = 'touch', komandan.modules.cmd({{ cmd = 'echo ran >> {}' }}) }},\n  {{ name = 'again', komandan.modules.cmd({{ cmd = 'echo ran >> {}' }}) }},\n}}\n",
+            marker.display(),
+            marker.display()
+        ),
+    )?;
+
+    let inventory = vec![
+        json!({ "name": "local", "address": "localhost", "tags": ["control"] }),
+        json!({ "name": "web1", "address": "10.0.0.1", "tags": ["web"] }),
+    ];
+    let hosts = select_hosts(&inventory, "control")?;
+    assert_eq!(hosts.len(), 1);
+
+    let lua = create_lua()?;
+    let flags = Flags {
+        no_report: true,
+        ..Flags::default()
+    };
+    run_task_file(&lua, &flags, &task_file.to_string_lossy(), &hosts)?;
+    assert_eq!(std::fs::read_to_string(&marker)?, "ran\nran\n");
+    Ok(())
+}
+
+#[test]
+fn test_run_task_file_fails_when_a_task_fails() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let task_file = dir.path().join("fail.lua");
+    std::fs::write(
+        &task_file,
+        "return { komandan.modules.cmd({ cmd = 'false' }) }\n",
+    )?;
+
+    let lua = create_lua()?;
+    let flags = Flags {
+        no_report: true,
+        ..Flags::default()
+    };
+    let hosts = [json!({ "address": "localhost" })];
+    assert!(run_task_file(&lua, &flags, &task_file.to_string_lossy(), &hosts).is_err());
+    Ok(())
+}
+
+/// A project running tasks on localhost, with a task file echoing `ran` and
+/// one failing.
+fn local_project(dir: &std::path::Path) -> anyhow::Result<()> {
+    std::fs::write(
+        dir.join("komandan.json"),
+        r#"{ "name": "local", "version": "0.1.0", "main": "main.lua", "defaults": { "hosts": "hosts.lua" } }"#,
+    )?;
+    std::fs::write(
+        dir.join("hosts.lua"),
+        "return { { name = 'local', address = 'localhost' } }\n",
+    )?;
+    std::fs::write(
+        dir.join("echo.lua"),
+        "return { name = 'echo', komandan.modules.cmd({ cmd = 'echo ran' }) }\n",
+    )?;
+    std::fs::write(
+        dir.join("fail.lua"),
+        "return { name = 'fail', komandan.modules.cmd({ cmd = 'false' }) }\n",
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_run_command_writes_state_file() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    local_project(dir.path())?;
+    let state_file = dir.path().join("state.json");
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_komandan"))
+        .current_dir(dir.path())
+        .args([
+            "run",
+            "echo.lua",
+            "--hosts",
+            "all",
+            "--no-report",
+            "--state-file",
+        ])
+        .arg(&state_file)
+        .status()?;
+    assert!(status.success());
+    let state: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&state_file)?)?;
+    assert!(state["hosts"]["local (localhost)"]["echo"]["signature"].is_string());
+    Ok(())
+}
+
+#[test]
+fn test_drift_after_failed_task() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    local_project(dir.path())?;
+    let state_file = dir.path().join("state.json");
+    let komandan = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_komandan"))
+            .current_dir(dir.path())
+            .args([
+                "run",
+                "fail.lua",
+                "--hosts",
+                "all",
+                "--no-report",
+                "--state-file",
+            ])
+            .arg(&state_file)
+            .args(extra)
+            .output()
+    };
+
+    assert!(!komandan(&[])?.status.success());
+    let state: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&state_file)?)?;
+    assert_eq!(
+        state["hosts"]["local (localhost)"]["fail"]["status"],
+        "failed"
+    );
+
+    let drift = String::from_utf8(komandan(&["--drift"])?.stdout)?;
+    assert!(drift.contains("fail: failed on the last run"), "{drift}");
+    Ok(())
+}
+
+#[test]
+fn test_run_command_writes_recording() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    local_project(dir.path())?;
+    let record = dir.path().join("recorded.lua");
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_komandan"))
+        .current_dir(dir.path())
+        .args([
+            "run",
+            "echo.lua",
+            "--hosts",
+            "all",
+            "--no-report",
+            "--record",
+        ])
+        .arg(&record)
+        .status()?;
+    assert!(status.success());
+    assert!(std::fs::read_to_string(&record)?.contains("echo ran"));
+    Ok(())
+}
@@ -4,6 +4,7 @@ use komandan::ssh::{SSHAuthMethod, SSHSession};
 use std::fs;
 use std::io::Write;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use tempfile::{NamedTempFile, TempDir};
 
 fn ssh_port() -> u16 {
@@ -173,6 +174,32 @@ fn test_cmdq_method_interface() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_cmdq_stops_chatty_command_at_deadline() -> Result<()> {
+    if std::env::var("KOMANDAN_SSH_TEST").is_err() {
+        eprintln!("Skipping SSH integration test - set KOMANDAN_SSH_TEST=1 to enable");
+        return Ok(());
+    }
+    let mut session = create_ssh_session()?;
+    let started = Instant::now();
+    session.set_deadline(Some(started + Duration::from_secs(1)));
+
+    let result = session.cmdq("while true; do echo tick; done");
+    assert!(
+        result.is_err(),
+        "a command printing past its timeout must fail"
+    );
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let mut lines = 0;
+    let mut session = create_ssh_session()?;
+    session.set_deadline(Some(Instant::now() + Duration::from_secs(1)));
+    let result = session.cmd_stream("while true; do echo tick; done", &mut |_| lines += 1);
+    assert!(result.is_err());
+    assert!(lines > 0);
+    Ok(())
+}
+
 #[test]
 fn test_upload_directory_utility() -> Result<()> {
     if std::env::var("KOMANDAN_SSH_TEST").is_err() {
@@ -0,0 +1,41 @@
+use komandan::create_lua;
+use mlua::{Table, chunk};
+
+#[test]
+fn test_ssh_session_reused_across_tasks() -> mlua::Result<()> {
+    if std::env::var("KOMANDAN_SSH_TEST").is_err() {
+        eprintln!("Skipping SSH integration test - set KOMANDAN_SSH_TEST=1 to enable");
+        return Ok(());
+    }
+    let lua = create_lua()?;
+
+    let connections = lua
+        .load(chunk! {
+            local hosts = {
+                server1 = {
+                    address = "localhost",
+                    connection = "ssh",
+                    user = "usertest",
+                    host_key_check = false,
+                    private_key_file = os.getenv("HOME") .. "/.ssh/id_ed25519",
+                },
+            }
+            local task = {
+                name = "Show SSH connection",
+                komandan.modules.cmd({ cmd = "echo $SSH_CONNECTION", changed_by_default = false }),
+            }
+
+            local first = komandan.komando_parallel_hosts(task, hosts).server1.stdout
+            local second = komandan.komando_parallel_hosts(task, hosts).server1.stdout
+            komandan.reset_connections()
+            local third = komandan.komando(task, hosts.server1).stdout
+            return { first, second, third }
+        })
+        .eval::<Table>()?;
+
+    let first = connections.get::<String>(1)?;
+    assert!(!first.is_empty());
+    assert_eq!(connections.get::<String>(2)?, first);
+    assert_ne!(connections.get::<String>(3)?, first);
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use komandan::create_lua;
+use mlua::{Table, chunk};
+
+#[test]
+fn test_block_rolls_back_applied_tasks_in_reverse() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let log = dir.path().join("log");
+    let log_path = log.to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let error = lua
+        .load(chunk! {
+            local ok, err = pcall(komandan.komando_block, {
+                {
+                    name = "first",
+                    komandan.modules.cmd({ cmd = "echo do-first >> " .. $log_path }),
+                    undo = komandan.modules.cmd({ cmd = "echo undo-first >> " .. $log_path }),
+                },
+                {
+                    name = "unchanged",
+                    komandan.modules.cmd({ cmd = "true", changed_by_default = false }),
+                    undo = "echo undo-unchanged >> " .. $log_path,
+                },
+                {
+                    name = "second",
+                    komandan.modules.cmd({ cmd = "echo do-second >> " .. $log_path }),
+                    undo = "echo undo-second >> " .. $log_path,
+                },
+                {
+                    name = "broken",
+                    komandan.modules.cmd({ cmd = "false" }),
+                },
+                {
+                    name = "never",
+                    komandan.modules.cmd({ cmd = "echo never >> " .. $log_path }),
+                },
+            })
+            assert(not ok)
+            return tostring(err)
+        })
+        .eval::<String>()?;
+
+    assert!(error.contains("Task 'broken' failed"));
+    assert!(error.contains("rolled back 2 of 2 applied task(s)"));
+    let log = std::fs::read_to_string(&log).map_err(mlua::Error::external)?;
+    assert_eq!(log, "do-first\ndo-second\nundo-second\nundo-first\n");
+    Ok(())
+}
+
+#[test]
+fn test_block_returns_results_when_every_task_succeeds() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            return komandan.komando_block({
+                {
+                    komandan.modules.cmd({ cmd = "echo one" }),
+                    undo = "echo undo-one",
+                },
+                { komandan.modules.cmd({ cmd = "echo two" }) },
+            })
+        })
+        .eval::<Table>()?;
+
+    assert_eq!(results.raw_len(), 2);
+    assert_eq!(
+        results.get::<Table>(2)?.get::<String>("stdout")?.trim(),
+        "two"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_block_rescues_failure_and_always_runs() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let log = dir.path().join("log");
+    let log_path = log.to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let outcome = lua
+        .load(chunk! {
+            return komandan.block({
+                tasks = {
+                    { name = "deploy", komandan.modules.cmd({ cmd = "echo deploy >> " .. $log_path }) },
+                    { name = "broken", komandan.modules.cmd({ cmd = "false" }) },
+                    { name = "never", komandan.modules.cmd({ cmd = "echo never >> " .. $log_path }) },
+                },
+                rescue = {
+                    { name = "recover", komandan.modules.cmd({ cmd = "echo rescue >> " .. $log_path }) },
+                },
+                always = {
+                    { name = "cleanup", komandan.modules.cmd({ cmd = "echo always >> " .. $log_path }) },
+                },
+            })
+        })
+        .eval::<Table>()?;
+
+    assert!(outcome.get::<bool>("rescued")?);
+    assert!(
+        outcome
+            .get::<String>("error")?
+            .contains("Failed to run task")
+    );
+    assert_eq!(outcome.get::<Table>("results")?.raw_len(), 1);
+    assert_eq!(outcome.get::<Table>("rescue_results")?.raw_len(), 1);
+    assert_eq!(outcome.get::<Table>("always_results")?.raw_len(), 1);
+    let log = std::fs::read_to_string(&log).map_err(mlua::Error::external)?;
+    assert_eq!(log, "deploy\nrescue\nalways\n");
+    Ok(())
+}
+
+#[test]
+fn test_block_without_rescue_fails_after_always() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let log = dir.path().join("log");
+    let log_path = log.to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let failed = lua
+        .load(chunk! {
+            local ok = pcall(komandan.block, {
+                tasks = { { komandan.modules.cmd({ cmd = "false" }) } },
+                always = { { komandan.modules.cmd({ cmd = "echo always >> " .. $log_path }) } },
+            })
+            return not ok
+        })
+        .eval::<bool>()?;
+
+    assert!(failed);
+    let log = std::fs::read_to_string(&log).map_err(mlua::Error::external)?;
+    assert_eq!(log, "always\n");
+    Ok(())
+}
+
+#[test]
+fn test_sequence_stops_at_first_failure() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let outcome = lua
+        .load(chunk! {
+            return komandan.komando_sequence({
+                { name = "one", komandan.modules.cmd({ cmd = "echo one" }) },
+                { name = "broken", komandan.modules.cmd({ cmd = "false" }) },
+                { name = "never", komandan.modules.cmd({ cmd = "echo never" }) },
+            })
+        })
+        .eval::<Table>()?;
+
+    assert!(!outcome.get::<bool>("ok")?);
+    assert_eq!(outcome.get::<i64>("failed_task")?, 2);
+    assert_eq!(outcome.get::<i64>("skipped")?, 1);
+    assert!(!outcome.get::<String>("error")?.is_empty());
+    let results = outcome.get::<Table>("results")?;
+    assert_eq!(results.raw_len(), 1);
+    assert_eq!(
+        results.get::<Table>(1)?.get::<String>("stdout")?.trim(),
+        "one"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sequence_returns_every_result() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let outcome = lua
+        .load(chunk! {
+            return komandan.komando_sequence({
+                { komandan.modules.cmd({ cmd = "echo one" }) },
+                { komandan.modules.cmd({ cmd = "echo two" }) },
+            }, { address = "localhost" })
+        })
+        .eval::<Table>()?;
+
+    assert!(outcome.get::<bool>("ok")?);
+    assert!(outcome.get::<Option<String>>("error")?.is_none());
+    assert_eq!(outcome.get::<Table>("results")?.raw_len(), 2);
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use komandan::create_lua;
+use mlua::{Table, chunk};
+
+#[test]
+fn test_creates_skips_task_when_path_exists() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let marker = dir.path().join("marker");
+    let marker_path = marker.to_string_lossy().to_string();
+    let existing = dir.path().to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let result = lua
+        .load(chunk! {
+            return komandan.komando({
+                creates = $existing,
+                komandan.modules.cmd({ cmd = "touch " .. $marker_path }),
+            })
+        })
+        .eval::<Table>()?;
+
+    assert!(result.get::<bool>("skipped")?);
+    assert!(!result.get::<bool>("changed")?);
+    assert!(!marker.exists());
+    Ok(())
+}
+
+#[test]
+fn test_creates_runs_task_when_path_is_missing() -> mlua::Result<()> {
+    let dir = tempfile::tempdir().map_err(mlua::Error::external)?;
+    let marker = dir.path().join("marker");
+    let marker_path = marker.to_string_lossy().to_string();
+    let lua = create_lua()?;
+
+    let result = lua
+        .load(chunk! {
+            return komandan.komando({
+                creates = $marker_path,
+                komandan.modules.cmd({ cmd = "touch " .. $marker_path }),
+            })
+        })
+        .eval::<Table>()?;
+
+    assert!(result.get::<Option<bool>>("skipped")?.is_none());
+    assert!(marker.exists());
+    Ok(())
+}
+
+#[test]
+fn test_unless_skips_task_when_command_succeeds() -> mlua::Result<()> {
+    let lua = create_lua()?;
+
+    let results = lua
+        .load(chunk! {
+            local skipped = komandan.komando({
+                unless = "test 1 -eq 1 && true",
+                komandan.modules.cmd({ cmd = "false" }),
+            })
+            local run = komandan.komando({
+                unless = "false",
+                komandan.modules.cmd({ cmd = "echo ran" }),
+            })
+            return { skipped, run }
+        })
+        .eval::<Table>()?;
+
+    assert!(results.get::<Table>(1)?.get::<bool>("skipped")?);
+    assert_eq!(results.get::<Table>(2)?.get::<String>("stdout")?, "ran");
+    Ok(())
+}